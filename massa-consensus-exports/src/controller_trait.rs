@@ -114,6 +114,50 @@ pub trait ConsensusController: Send + Sync {
     /// * `header`: the header of the block to register
     fn register_block_header(&self, block_id: BlockId, header: SecureShare<BlockHeader, BlockId>);
 
+    /// Register several block headers in the graph at once, in the given order.
+    /// Controllers that don't batch the registrations register them one by one.
+    ///
+    /// # Arguments
+    /// * `headers`: the ids of the blocks to register with their headers
+    fn register_block_headers(&self, headers: Vec<(BlockId, SecureShare<BlockHeader, BlockId>)>) {
+        for (block_id, header) in headers {
+            self.register_block_header(block_id, header);
+        }
+    }
+
+    /// Register a block header in the graph, failing with `ConsensusError::Unavailable`
+    /// if consensus can't take it right now, in which case the header isn't registered.
+    /// Controllers that can't fail register it with `register_block_header`.
+    ///
+    /// # Arguments
+    /// * `block_id`: the id of the block to register
+    /// * `header`: the header of the block to register
+    fn try_register_block_header(
+        &self,
+        block_id: BlockId,
+        header: SecureShare<BlockHeader, BlockId>,
+    ) -> Result<(), ConsensusError> {
+        self.register_block_header(block_id, header);
+        Ok(())
+    }
+
+    /// Register several block headers in the graph at once, in the given order, failing with
+    /// `ConsensusError::Unavailable` if consensus can't take them right now.
+    /// Controllers that don't batch the registrations register them one by one,
+    /// and may have registered the first ones when they fail.
+    ///
+    /// # Arguments
+    /// * `headers`: the ids of the blocks to register with their headers
+    fn try_register_block_headers(
+        &self,
+        headers: Vec<(BlockId, SecureShare<BlockHeader, BlockId>)>,
+    ) -> Result<(), ConsensusError> {
+        for (block_id, header) in headers {
+            self.try_register_block_header(block_id, header)?;
+        }
+        Ok(())
+    }
+
     /// Mark a block as invalid in the graph
     ///
     /// # Arguments
@@ -45,6 +45,8 @@ pub enum ConsensusError {
     ProtocolError(#[from] ProtocolError),
     /// Invalid transition {0}
     InvalidTransition(String),
+    /// consensus unavailable: {0}
+    Unavailable(String),
 }
 
 /// Internal error
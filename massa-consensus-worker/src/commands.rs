@@ -8,5 +8,6 @@ use massa_storage::Storage;
 pub enum ConsensusCommand {
     RegisterBlock(BlockId, Slot, Storage, bool),
     RegisterBlockHeader(BlockId, SecureShare<BlockHeader, BlockId>),
+    RegisterBlockHeaders(Vec<(BlockId, SecureShare<BlockHeader, BlockId>)>),
     MarkInvalidBlock(BlockId, SecureShare<BlockHeader, BlockId>),
 }
@@ -306,6 +306,80 @@ impl ConsensusController for ConsensusControllerImpl {
         }
     }
 
+    fn register_block_headers(&self, headers: Vec<(BlockId, SecureShare<BlockHeader, BlockId>)>) {
+        if self.broadcast_enabled {
+            for (block_id, header) in &headers {
+                if let Err(err) = self.broadcasts.block_header_sender.send(header.clone()) {
+                    trace!(
+                        "error, failed to broadcast block header with block id {}: {}",
+                        block_id,
+                        err
+                    );
+                }
+            }
+        }
+
+        if let Err(err) = self
+            .command_sender
+            .try_send(ConsensusCommand::RegisterBlockHeaders(headers))
+        {
+            warn!("error trying to register block headers: {}", err);
+        }
+    }
+
+    fn try_register_block_header(
+        &self,
+        block_id: BlockId,
+        header: SecureShare<BlockHeader, BlockId>,
+    ) -> Result<(), ConsensusError> {
+        let broadcast_header = self.broadcast_enabled.then(|| header.clone());
+        self.command_sender
+            .try_send(ConsensusCommand::RegisterBlockHeader(block_id, header))
+            .map_err(|err| {
+                ConsensusError::Unavailable(format!(
+                    "cannot register block header {}: {}",
+                    block_id, err
+                ))
+            })?;
+        // only broadcast once registered, so that the retried headers are broadcast once
+        if let Some(header) = broadcast_header {
+            if let Err(err) = self.broadcasts.block_header_sender.send(header) {
+                trace!(
+                    "error, failed to broadcast block header with block id {}: {}",
+                    block_id,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn try_register_block_headers(
+        &self,
+        headers: Vec<(BlockId, SecureShare<BlockHeader, BlockId>)>,
+    ) -> Result<(), ConsensusError> {
+        let broadcast_headers = self.broadcast_enabled.then(|| headers.clone());
+        let count = headers.len();
+        self.command_sender
+            .try_send(ConsensusCommand::RegisterBlockHeaders(headers))
+            .map_err(|err| {
+                ConsensusError::Unavailable(format!(
+                    "cannot register {} block headers: {}",
+                    count, err
+                ))
+            })?;
+        for (block_id, header) in broadcast_headers.into_iter().flatten() {
+            if let Err(err) = self.broadcasts.block_header_sender.send(header) {
+                trace!(
+                    "error, failed to broadcast block header with block id {}: {}",
+                    block_id,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
     fn mark_invalid_block(&self, block_id: BlockId, header: SecureShare<BlockHeader, BlockId>) {
         if let Err(err) = self
             .command_sender
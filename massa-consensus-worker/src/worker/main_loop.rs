@@ -33,6 +33,22 @@ impl ConsensusWorker {
                 write_shared_state.register_block_header(block_id, header, self.previous_slot)?;
                 write_shared_state.block_db_changed()
             }
+            ConsensusCommand::RegisterBlockHeaders(headers) => {
+                // an invalid header doesn't prevent the registration of the next ones
+                let mut result = Ok(());
+                for (block_id, header) in headers {
+                    let registered = write_shared_state.register_block_header(
+                        block_id,
+                        header,
+                        self.previous_slot,
+                    );
+                    if result.is_ok() {
+                        result = registered;
+                    }
+                }
+                write_shared_state.block_db_changed()?;
+                result
+            }
             ConsensusCommand::RegisterBlock(block_id, slot, block_storage, created) => {
                 write_shared_state.register_block(
                     block_id,
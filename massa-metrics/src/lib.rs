@@ -146,6 +146,9 @@ pub struct MassaMetrics {
     block_cache_checked_headers_size: IntGauge,
     block_cache_blocks_known_by_peer: IntGauge,
 
+    // block retrieval
+    block_retrieval_in_flight_requests: IntGauge,
+
     // Operation cache
     operation_cache_checked_operations: IntGauge,
     operation_cache_checked_operations_prefix: IntGauge,
@@ -325,6 +328,13 @@ impl MassaMetrics {
         )
         .unwrap();
 
+        // block retrieval
+        let block_retrieval_in_flight_requests = IntGauge::new(
+            "block_retrieval_in_flight_requests",
+            "number of blocks currently asked to peers",
+        )
+        .unwrap();
+
         // operation cache
         let operation_cache_checked_operations = IntGauge::new(
             "operation_cache_checked_operations",
@@ -418,6 +428,7 @@ impl MassaMetrics {
                 let _ = prometheus::register(Box::new(active_out_connections.clone()));
                 let _ = prometheus::register(Box::new(block_cache_blocks_known_by_peer.clone()));
                 let _ = prometheus::register(Box::new(block_cache_checked_headers_size.clone()));
+                let _ = prometheus::register(Box::new(block_retrieval_in_flight_requests.clone()));
                 let _ = prometheus::register(Box::new(operation_cache_checked_operations.clone()));
                 let _ = prometheus::register(Box::new(active_in_connections.clone()));
                 let _ = prometheus::register(Box::new(operation_cache_ops_know_by_peer.clone()));
@@ -495,6 +506,7 @@ impl MassaMetrics {
                 operations_final_counter,
                 block_cache_checked_headers_size,
                 block_cache_blocks_known_by_peer,
+                block_retrieval_in_flight_requests,
                 operation_cache_checked_operations,
                 operation_cache_checked_operations_prefix,
                 operation_cache_ops_know_by_peer,
@@ -579,6 +591,11 @@ impl MassaMetrics {
             .set(blocks_known_by_peer as i64);
     }
 
+    pub fn set_block_retrieval_in_flight_requests(&self, in_flight_requests: usize) {
+        self.block_retrieval_in_flight_requests
+            .set(in_flight_requests as i64);
+    }
+
     pub fn set_operations_cache_metrics(
         &self,
         checked_operations: usize,
@@ -4,7 +4,8 @@
 // use crate::endorsement::{EndorsementId, EndorsementSerializer, EndorsementSerializerLW};
 // use crate::prehash::PreHashed;
 use crate::secure_share::{
-    Id, SecureShare, SecureShareContent, SecureShareDeserializer, SecureShareSerializer,
+    AddressDerivation, Id, SecureShare, SecureShareContent, SecureShareDeserializer,
+    SecureShareSerializer,
 };
 use crate::{
     // endorsement::{Endorsement, EndorsementDeserializerLW, SecureShareEndorsement},
@@ -102,6 +103,7 @@ impl SecureShareContent for Block {
         _content_serializer: Option<&dyn Serializer<Self>>,
         _signature_deserializer: &massa_signature::SignatureDeserializer,
         _creator_public_key_deserializer: &massa_signature::PublicKeyDeserializer,
+        _address_derivation: Option<&dyn AddressDerivation>,
         content_deserializer: &DC,
         buffer: &'a [u8],
     ) -> IResult<&'a [u8], SecureShare<Self, U>, E> {
@@ -34,6 +34,8 @@ pub const SIGNATURE_DESER_SIZE: usize = 64 + 1;
 pub const MAX_ADVERTISE_LENGTH: u32 = 10000;
 /// Maximum message length in bytes
 pub const MAX_MESSAGE_SIZE: u32 = 1048576000;
+/// Version of the peer-to-peer message format, to bump on each incompatible change
+pub const PROTOCOL_VERSION: u32 = 1;
 /// Max number of operations per message
 pub const MAX_OPERATIONS_PER_MESSAGE: u32 = 1024;
 /// Length of the handshake random signature
@@ -4,7 +4,8 @@ use crate::address::AddressSerializer;
 use crate::datastore::{Datastore, DatastoreDeserializer, DatastoreSerializer};
 use crate::prehash::{PreHashSet, PreHashed};
 use crate::secure_share::{
-    Id, SecureShare, SecureShareContent, SecureShareDeserializer, SecureShareSerializer,
+    AddressDerivation, Id, SecureShare, SecureShareContent, SecureShareDeserializer,
+    SecureShareSerializer,
 };
 use crate::{
     address::{Address, AddressDeserializer},
@@ -32,6 +33,7 @@ use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DeserializeFromStr, SerializeDisplay};
 use std::convert::TryInto;
 use std::fmt::Formatter;
+use std::sync::Arc;
 use std::{ops::Bound::Included, ops::RangeInclusive, str::FromStr};
 use transition::Versioned;
 
@@ -1390,6 +1392,17 @@ impl OperationsDeserializer {
             )),
         }
     }
+
+    /// Derive the addresses of the creators of the operations with `address_derivation`
+    pub fn with_address_derivation(
+        mut self,
+        address_derivation: Arc<dyn AddressDerivation>,
+    ) -> Self {
+        self.signed_op_deserializer = self
+            .signed_op_deserializer
+            .with_address_derivation(address_derivation);
+        self
+    }
 }
 
 impl Deserializer<Vec<SecureShareOperation>> for OperationsDeserializer {
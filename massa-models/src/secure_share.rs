@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::Arc};
 
 use crate::{address::Address, error::ModelsError};
 use massa_hash::Hash;
@@ -132,6 +132,7 @@ where
         content_serializer: Option<&dyn Serializer<Self>>,
         signature_deserializer: &SignatureDeserializer,
         creator_public_key_deserializer: &PublicKeyDeserializer,
+        address_derivation: Option<&dyn AddressDerivation>,
         content_deserializer: &Deser,
         buffer: &'a [u8],
     ) -> IResult<&'a [u8], SecureShare<Self, ID>, E> {
@@ -162,7 +163,10 @@ where
             // Avoid getting the rest of the data in the serialized data
             serialized_data[..serialized_data.len() - rest.len()].to_vec()
         };
-        let creator_address = Address::from_public_key(&creator_public_key);
+        let creator_address = match address_derivation {
+            Some(address_derivation) => address_derivation.derive_address(&creator_public_key),
+            None => Address::from_public_key(&creator_public_key),
+        };
         let hash = Self::compute_hash(&content, &content_serialized, &creator_public_key);
 
         Ok((
@@ -292,6 +296,13 @@ where
     }
 }
 
+/// Derivation of the address of a creator from its public key, that a `SecureShareDeserializer`
+/// can be given to reuse the addresses it already derived
+pub trait AddressDerivation: Send + Sync {
+    /// Address of the creator with this public key
+    fn derive_address(&self, public_key: &PublicKey) -> Address;
+}
+
 /// Deserializer for SecureShare structure
 pub struct SecureShareDeserializer<T, Deser>
 where
@@ -300,6 +311,7 @@ where
 {
     signature_deserializer: SignatureDeserializer,
     public_key_deserializer: PublicKeyDeserializer,
+    address_derivation: Option<Arc<dyn AddressDerivation>>,
     content_deserializer: Deser,
     marker_t: std::marker::PhantomData<T>,
 }
@@ -317,11 +329,21 @@ where
         Self {
             signature_deserializer: SignatureDeserializer::new(),
             public_key_deserializer: PublicKeyDeserializer::new(),
+            address_derivation: None,
             content_deserializer,
             marker_t: std::marker::PhantomData,
         }
     }
 
+    /// Derive the addresses of the creators with `address_derivation` instead of hashing their public key each time
+    pub fn with_address_derivation(
+        mut self,
+        address_derivation: Arc<dyn AddressDerivation>,
+    ) -> Self {
+        self.address_derivation = Some(address_derivation);
+        self
+    }
+
     /// This method is used deserialize data that has been serialized in a lightweight form.
     /// The buffer doesn't have the whole content serialized and so
     /// this serialized data isn't coherent with the full structure and can't be used to calculate id and signature.
@@ -348,6 +370,7 @@ where
             Some(content_serializer),
             &self.signature_deserializer,
             &self.public_key_deserializer,
+            self.address_derivation.as_deref(),
             &self.content_deserializer,
             buffer,
         )
@@ -394,6 +417,7 @@ where
             None,
             &self.signature_deserializer,
             &self.public_key_deserializer,
+            self.address_derivation.as_deref(),
             &self.content_deserializer,
             buffer,
         )
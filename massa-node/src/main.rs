@@ -40,6 +40,7 @@ use massa_factory_worker::start_factory;
 use massa_final_state::{FinalState, FinalStateConfig, FinalStateController};
 use massa_grpc::config::{GrpcConfig, ServiceName};
 use massa_grpc::server::{MassaPrivateGrpc, MassaPublicGrpc};
+use massa_hash::Hash;
 use massa_ledger_exports::LedgerConfig;
 use massa_ledger_worker::FinalLedger;
 use massa_logging::massa_trace;
@@ -70,8 +71,8 @@ use massa_models::config::constants::{
     MAX_SIZE_CHANNEL_NETWORK_TO_PEER_HANDLER, MIP_STORE_STATS_BLOCK_CONSIDERED,
     OPERATION_VALIDITY_PERIODS, PERIODS_PER_CYCLE, POS_MISS_RATE_DEACTIVATION_THRESHOLD,
     POS_SAVED_CYCLES, PROTOCOL_CONTROLLER_CHANNEL_SIZE, PROTOCOL_EVENT_CHANNEL_SIZE,
-    ROLL_COUNT_TO_SLASH_ON_DENUNCIATION, ROLL_PRICE, SELECTOR_DRAW_CACHE_SIZE, T0, THREAD_COUNT,
-    VERSION,
+    PROTOCOL_VERSION, ROLL_COUNT_TO_SLASH_ON_DENUNCIATION, ROLL_PRICE, SELECTOR_DRAW_CACHE_SIZE,
+    T0, THREAD_COUNT, VERSION,
 };
 use massa_models::config::{
     BASE_OPERATION_GAS_COST, KEEP_EXECUTED_HISTORY_EXTRA_PERIODS,
@@ -84,7 +85,7 @@ use massa_pool_exports::{PoolBroadcasts, PoolChannels, PoolConfig, PoolManager};
 use massa_pool_worker::start_pool_controller;
 use massa_pos_exports::{PoSConfig, SelectorConfig, SelectorManager};
 use massa_pos_worker::start_selector_worker;
-use massa_protocol_exports::{ProtocolConfig, ProtocolManager, TransportType};
+use massa_protocol_exports::{DefaultBanPolicy, ProtocolConfig, ProtocolManager, TransportType};
 use massa_protocol_worker::{create_protocol_controller, start_protocol_controller};
 use massa_signature::KeyPair;
 use massa_storage::Storage;
@@ -593,15 +594,48 @@ async fn launch(
     listeners.insert(SETTINGS.protocol.bind, TransportType::Tcp);
     let protocol_config = ProtocolConfig {
         thread_count: THREAD_COUNT,
+        // not the hash of the genesis blocks, which also depend on the last start period
+        genesis_hash: Hash::compute_from(
+            &[
+                GENESIS_TIMESTAMP.as_millis().to_be_bytes().to_vec(),
+                GENESIS_KEY.get_public_key().to_bytes(),
+            ]
+            .concat(),
+        ),
         ask_block_timeout: SETTINGS.protocol.ask_block_timeout,
+        max_block_ask_retries: SETTINGS.protocol.max_block_ask_retries,
+        block_ask_fanout: SETTINGS.protocol.block_ask_fanout,
+        max_concurrent_block_downloads: SETTINGS.protocol.max_concurrent_block_downloads,
+        block_download_latency_buckets: SETTINGS.protocol.block_download_latency_buckets.clone(),
+        block_header_batch_window: SETTINGS.protocol.block_header_batch_window,
+        consensus_unavailable_policy: SETTINGS.protocol.consensus_unavailable_policy,
+        consensus_retry_buffer_size: SETTINGS.protocol.consensus_retry_buffer_size,
+        consensus_retry_interval: SETTINGS.protocol.consensus_retry_interval,
+        max_wishlist_size: SETTINGS.protocol.max_wishlist_size,
+        withholding_window: SETTINGS.protocol.withholding_window,
+        withholding_min_asks: SETTINGS.protocol.withholding_min_asks,
+        withholding_max_percent: SETTINGS.protocol.withholding_max_percent,
+        attack_relay_grace: SETTINGS.protocol.attack_relay_grace,
+        max_future_slots: SETTINGS.protocol.max_future_slots,
+        future_slot_tolerance: SETTINGS.protocol.future_slot_tolerance,
+        max_past_slots: SETTINGS.protocol.max_past_slots,
+        duplicate_header_window: SETTINGS.protocol.duplicate_header_window,
+        max_duplicate_headers: SETTINGS.protocol.max_duplicate_headers,
+        max_first_header_times: SETTINGS.protocol.max_first_header_times,
+        first_header_time_retention: SETTINGS.protocol.first_header_time_retention,
         max_known_blocks_size: SETTINGS.protocol.max_known_blocks_size,
         max_node_known_blocks_size: SETTINGS.protocol.max_node_known_blocks_size,
         max_block_propagation_time: SETTINGS.protocol.max_block_propagation_time,
         max_node_wanted_blocks_size: SETTINGS.protocol.max_node_wanted_blocks_size,
+        max_served_op_ids_cache_size: SETTINGS.protocol.max_served_op_ids_cache_size,
+        served_op_ids_cache_ttl: SETTINGS.protocol.served_op_ids_cache_ttl,
+        block_info_chunk_size: SETTINGS.protocol.block_info_chunk_size,
+        block_serve_concurrency: SETTINGS.protocol.block_serve_concurrency,
         max_known_ops_size: SETTINGS.protocol.max_known_ops_size,
         max_node_known_ops_size: SETTINGS.protocol.max_node_known_ops_size,
         max_known_endorsements_size: SETTINGS.protocol.max_known_endorsements_size,
         max_node_known_endorsements_size: SETTINGS.protocol.max_node_known_endorsements_size,
+        max_known_endorsements_age: SETTINGS.protocol.max_known_endorsements_age,
         max_simultaneous_ask_blocks_per_node: SETTINGS
             .protocol
             .max_simultaneous_ask_blocks_per_node,
@@ -611,7 +645,16 @@ async fn launch(
             .protocol
             .operation_announcement_buffer_capacity,
         operation_batch_proc_period: SETTINGS.protocol.operation_batch_proc_period,
+        operation_batch_window: SETTINGS.protocol.operation_batch_window,
+        operation_batch_max_size: SETTINGS.protocol.operation_batch_max_size,
         operation_announcement_interval: SETTINGS.protocol.operation_announcement_interval,
+        min_propagation_fee: SETTINGS.protocol.min_propagation_fee,
+        pool_backpressure_enabled: SETTINGS.protocol.pool_backpressure_enabled,
+        operation_propagation_fanout: SETTINGS.protocol.operation_propagation_fanout,
+        rng_seed: None,
+        mempool_sync_peers: SETTINGS.protocol.mempool_sync_peers,
+        passive_mode: SETTINGS.protocol.passive_mode,
+        handler_worker_threads: SETTINGS.protocol.handler_worker_threads,
         max_operations_per_message: SETTINGS.protocol.max_operations_per_message,
         max_serialized_operations_size_per_block: MAX_BLOCK_SIZE as usize,
         max_operations_per_block: MAX_OPERATIONS_PER_BLOCK,
@@ -623,15 +666,23 @@ async fn launch(
         max_message_size: MAX_MESSAGE_SIZE as usize,
         max_ops_kept_for_propagation: SETTINGS.protocol.max_ops_kept_for_propagation,
         max_operations_propagation_time: SETTINGS.protocol.max_operations_propagation_time,
+        expired_operations_tolerance: SETTINGS.protocol.expired_operations_tolerance,
+        max_expired_operation_messages: SETTINGS.protocol.max_expired_operation_messages,
         max_endorsements_propagation_time: SETTINGS.protocol.max_endorsements_propagation_time,
+        endorsement_announce_enabled: SETTINGS.protocol.endorsement_announce_enabled,
+        ask_endorsement_timeout: SETTINGS.protocol.ask_endorsement_timeout,
         last_start_period: final_state.read().get_last_start_period(),
         max_endorsements_per_message: MAX_ENDORSEMENTS_PER_MESSAGE as u64,
         max_denunciations_in_block_header: MAX_DENUNCIATIONS_PER_BLOCK_HEADER,
-        initial_peers: SETTINGS.protocol.initial_peers_file.clone(),
+        initial_peers: SETTINGS.protocol.initial_peers_files.clone(),
+        inline_initial_peers: SETTINGS.protocol.inline_initial_peers.clone(),
+        dns_seeds: SETTINGS.protocol.dns_seeds.clone(),
+        dns_seeds_refresh_interval: SETTINGS.protocol.dns_seeds_refresh_interval,
         listeners,
         keypair_file: SETTINGS.protocol.keypair_file.clone(),
         max_blocks_kept_for_propagation: SETTINGS.protocol.max_blocks_kept_for_propagation,
         block_propagation_tick: SETTINGS.protocol.block_propagation_tick,
+        compact_blocks_enabled: SETTINGS.protocol.compact_blocks_enabled,
         asked_operations_buffer_capacity: SETTINGS.protocol.asked_operations_buffer_capacity,
         thread_tester_count: SETTINGS.protocol.thread_tester_count,
         max_operation_storage_time: MAX_OPERATION_STORAGE_TIME,
@@ -666,21 +717,63 @@ async fn launch(
             as u128,
         try_connection_timer: SETTINGS.protocol.try_connection_timer,
         unban_everyone_timer: SETTINGS.protocol.unban_everyone_timer,
+        ban_durations: SETTINGS.protocol.ban_durations.clone(),
+        offense_decay_period: SETTINGS.protocol.offense_decay_period,
+        ban_list_path: SETTINGS.protocol.ban_list_path.clone(),
+        ip_ban_enabled: SETTINGS.protocol.ip_ban_enabled,
+        reputation_ban_threshold: SETTINGS.protocol.reputation_ban_threshold,
+        quarantine_duration: SETTINGS.protocol.quarantine_duration,
+        first_offense_policy: SETTINGS.protocol.first_offense_policy,
+        first_offense_window: SETTINGS.protocol.first_offense_window,
+        peer_events_channel_capacity: SETTINGS.protocol.peer_events_channel_capacity,
+        ban_whitelist: SETTINGS.protocol.ban_whitelist.clone(),
+        ban_policy: Arc::new(DefaultBanPolicy),
+        max_block_messages_per_sec: SETTINGS.protocol.max_block_messages_per_sec,
+        max_operation_messages_per_sec: SETTINGS.protocol.max_operation_messages_per_sec,
+        max_endorsement_messages_per_sec: SETTINGS.protocol.max_endorsement_messages_per_sec,
+        max_peer_management_messages_per_sec: SETTINGS
+            .protocol
+            .max_peer_management_messages_per_sec,
+        max_message_size_by_category: SETTINGS.protocol.max_message_size_by_category.clone(),
+        per_peer_inbound_queue_depth: SETTINGS.protocol.per_peer_inbound_queue_depth,
+        max_banned_peers: SETTINGS.protocol.max_banned_peers,
+        max_banned_subnets: SETTINGS.protocol.max_banned_subnets,
         max_in_connections: SETTINGS.protocol.max_in_connections,
+        max_connections_per_ip: SETTINGS.protocol.max_connections_per_ip,
+        max_total_connections: SETTINGS.protocol.max_total_connections,
+        target_out_connections: SETTINGS.protocol.target_out_connections,
+        subnet_diversity_enabled: SETTINGS.protocol.subnet_diversity_enabled,
+        outbound_only_peers: SETTINGS.protocol.outbound_only_peers.clone(),
         timeout_connection: SETTINGS.protocol.timeout_connection,
+        handshake_timeout: SETTINGS.protocol.handshake_timeout,
+        idle_ping_interval: SETTINGS.protocol.idle_ping_interval,
+        ping_timeout: SETTINGS.protocol.ping_timeout,
         message_timeout: SETTINGS.protocol.message_timeout,
+        shutdown_timeout: SETTINGS.protocol.shutdown_timeout,
         tester_timeout: SETTINGS.protocol.tester_timeout,
         routable_ip: SETTINGS
             .protocol
             .routable_ip
             .or(SETTINGS.network.routable_ip),
+        enable_ipv6: SETTINGS.protocol.enable_ipv6,
         debug: false,
         peers_categories: SETTINGS.protocol.peers_categories.clone(),
         default_category_info: SETTINGS.protocol.default_category_info,
         version: *VERSION,
+        protocol_version: PROTOCOL_VERSION,
+        min_peer_version: SETTINGS.protocol.min_peer_version,
         try_connection_timer_same_peer: SETTINGS.protocol.try_connection_timer_same_peer,
+        connect_backoff_base: SETTINGS.protocol.connect_backoff_base,
+        connect_backoff_max: SETTINGS.protocol.connect_backoff_max,
         test_oldest_peer_cooldown: SETTINGS.protocol.test_oldest_peer_cooldown,
+        peer_expiry: SETTINGS.protocol.peer_expiry,
         rate_limit: SETTINGS.protocol.rate_limit,
+        per_peer_bandwidth_limit: SETTINGS.protocol.per_peer_bandwidth_limit,
+        per_peer_bandwidth_burst: SETTINGS.protocol.per_peer_bandwidth_burst,
+        compression: SETTINGS.protocol.compression,
+        frame_checksum: SETTINGS.protocol.frame_checksum,
+        max_corrupted_frames: SETTINGS.protocol.max_corrupted_frames,
+        corrupted_frames_window: SETTINGS.protocol.corrupted_frames_window,
     };
 
     let (protocol_controller, protocol_channels) =
@@ -1,13 +1,19 @@
 // Copyright (c) 2022 MASSA LABS <info@massa.net>
 
 //! Build here the default node settings from the configuration file toml
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use massa_bootstrap::IpType;
-use massa_models::{config::build_massa_settings, node::NodeId};
-use massa_protocol_exports::PeerCategoryInfo;
+use massa_models::{amount::Amount, config::build_massa_settings, node::NodeId};
+use massa_protocol_exports::{
+    BanReason, CompressionMode, ConsensusUnavailablePolicy, FirstOffensePolicy, MessageCategory,
+    PeerCategoryInfo, PeerId,
+};
 use massa_time::MassaTime;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::net::{IpAddr, SocketAddr};
 
 lazy_static::lazy_static! {
@@ -184,23 +190,92 @@ pub struct MetricsSettings {
     pub tick_delay: MassaTime,
 }
 
+/// A single path or a list of paths
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrManyPaths {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+/// Read a list of paths given either as a list or as a single path
+fn deserialize_one_or_many_paths<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match OneOrManyPaths::deserialize(deserializer)? {
+        OneOrManyPaths::One(path) => vec![path],
+        OneOrManyPaths::Many(paths) => paths,
+    })
+}
+
 /// Protocol Configuration, read from toml user configuration file
 #[derive(Debug, Deserialize, Clone)]
 pub struct ProtocolSettings {
     /// after `ask_block_timeout` milliseconds we try to ask a block to another node
     pub ask_block_timeout: MassaTime,
+    /// number of asks for a block that may time out before we stop asking it until another node announces it (0 for no limit)
+    pub max_block_ask_retries: usize,
+    /// number of nodes that announced a block we ask it to at the same time, the first answer wins
+    pub block_ask_fanout: usize,
+    /// maximum number of blocks asked to nodes at the same time, the other wanted blocks wait for a free slot (0 for no limit)
+    pub max_concurrent_block_downloads: usize,
+    /// upper bounds in milliseconds of the buckets of the block download latency histogram
+    pub block_download_latency_buckets: Vec<MassaTime>,
+    /// headers received within this window are registered in consensus together, 0 to register each header on arrival
+    pub block_header_batch_window: MassaTime,
+    /// treatment of the headers that consensus can't take
+    pub consensus_unavailable_policy: ConsensusUnavailablePolicy,
+    /// max number of headers kept for consensus with the retry policy, the oldest ones are dropped beyond it
+    pub consensus_retry_buffer_size: usize,
+    /// interval between two registrations of the headers kept for consensus with the retry policy
+    pub consensus_retry_interval: MassaTime,
+    /// max number of blocks wanted by consensus that are tracked, bigger wishlists are rejected
+    pub max_wishlist_size: usize,
+    /// window over which the asks to a node for the blocks it announced are counted to detect data withholding
+    pub withholding_window: MassaTime,
+    /// number of asks to a node within the window before its share of unanswered asks is checked
+    pub withholding_min_asks: u32,
+    /// share in percents of unanswered asks for blocks it announced above which a node is penalized (0 to disable)
+    pub withholding_max_percent: u32,
+    /// only ban the peers that relayed an attack block more than once or after we detected the attack, the other ones are penalized
+    pub attack_relay_grace: bool,
+    /// number of slots after the current one in which the headers received from the peers may be, 0 to disable the check
+    pub max_future_slots: u64,
+    /// clock skew tolerated when computing the current slot for `max_future_slots`
+    pub future_slot_tolerance: MassaTime,
+    /// number of slots before the latest final slot of their thread under which the headers received from the peers are dropped, 0 to disable the check
+    pub max_past_slots: u64,
+    /// window after the receipt of a header in which the same header sent again by the same peer is dropped, 0 to disable
+    pub duplicate_header_window: MassaTime,
+    /// number of times a peer may send a header again within `duplicate_header_window` before losing reputation
+    pub max_duplicate_headers: u32,
+    /// number of blocks whose first header receipt time is kept, 0 to disable
+    pub max_first_header_times: usize,
+    /// time during which the first header receipt time of a block is kept
+    pub first_header_time_retention: MassaTime,
     /// Max known blocks we keep during their propagation
     pub max_blocks_kept_for_propagation: usize,
     /// Time during which a block is expected to propagate
     pub max_block_propagation_time: MassaTime,
     /// Block propagation tick interval, useful for propagating blocks quickly to newly connected peers.
     pub block_propagation_tick: MassaTime,
+    /// relay the blocks as their header and the ids of their operations, so that the peers rebuild them from their mempool
+    pub compact_blocks_enabled: bool,
     /// max known blocks our node keeps in its knowledge cache
     pub max_known_blocks_size: usize,
     /// max cache size for which blocks a foreign node knows about
     pub max_node_known_blocks_size: usize,
     /// max wanted blocks per node kept in memory
     pub max_node_wanted_blocks_size: usize,
+    /// max number of blocks whose operation ids we keep to answer repeated requests of peers
+    pub max_served_op_ids_cache_size: usize,
+    /// time during which cached operation ids of a block are used to answer peers
+    pub served_op_ids_cache_ttl: MassaTime,
+    /// max number of operation ids in a block info reply frame, the longer operation lists of a block are sent in several frames. 0 to send them in one frame
+    pub block_info_chunk_size: usize,
+    /// number of threads answering the block data requests of the peers, 0 to answer them from the block retrieval thread
+    pub block_serve_concurrency: usize,
     /// max known operations current node kept in memory
     pub max_known_ops_size: usize,
     /// size of the buffer of asked operations
@@ -211,6 +286,8 @@ pub struct ProtocolSettings {
     pub max_known_endorsements_size: usize,
     /// max known endorsements of foreign nodes we keep in memory (by node)
     pub max_node_known_endorsements_size: usize,
+    /// time after which an endorsement known by our node is processed again when received
+    pub max_known_endorsements_age: MassaTime,
     /// we ask for the same block `max_simultaneous_ask_blocks_per_node` times at the same time
     pub max_simultaneous_ask_blocks_per_node: usize,
     /// Max wait time for sending a Network or Node event.
@@ -223,24 +300,61 @@ pub struct ProtocolSettings {
     pub operation_announcement_buffer_capacity: usize,
     /// Start processing batches in the buffer each `operation_batch_proc_period` in millisecond
     pub operation_batch_proc_period: MassaTime,
+    /// Time during which the operations asked by a peer are gathered before being sent in one message, 0 to send them immediately
+    pub operation_batch_window: MassaTime,
+    /// Number of gathered operations for which a batch is sent without waiting for the end of `operation_batch_window`
+    pub operation_batch_max_size: usize,
     /// Interval at which operations are announced in batches.
     pub operation_announcement_interval: MassaTime,
+    /// operations with a lower fee are not relayed to other peers
+    pub min_propagation_fee: Amount,
+    /// stop asking for, checking and relaying the operations announced or sent by the peers while the operation pool is full
+    pub pool_backpressure_enabled: bool,
+    /// number of random peers each operation is announced to, 0 to announce it to all peers
+    pub operation_propagation_fanout: usize,
+    /// number of connected peers asked for the ids of all the operations of their pool, to fill ours when the node starts, 0 to disable
+    pub mempool_sync_peers: usize,
+    /// validate the received blocks, operations and endorsements without relaying anything, for observer nodes
+    pub passive_mode: bool,
+    /// Nb threads deserializing the received operation messages and checking their signatures
+    pub handler_worker_threads: usize,
     /// Maximum of operations sent in one message.
     pub max_operations_per_message: u64,
     /// MAx number of operations kept for propagation
     pub max_ops_kept_for_propagation: usize,
     /// Time threshold after which operation are not propagated
     pub max_operations_propagation_time: MassaTime,
+    /// number of periods after their expire period during which the operations received from the peers are still propagated
+    pub expired_operations_tolerance: u64,
+    /// number of messages in a row carrying expired operations a peer may send before losing reputation
+    pub max_expired_operation_messages: u32,
     /// Time threshold after which operation are not propagated
     pub max_endorsements_propagation_time: MassaTime,
-    /// Path for initial peers
-    pub initial_peers_file: PathBuf,
+    /// announce the ids of the endorsements to propagate, and let the peers ask for the ones they miss, instead of sending them in full
+    pub endorsement_announce_enabled: bool,
+    /// time after which a peer that didn't send the endorsements we asked it is penalized
+    pub ask_endorsement_timeout: MassaTime,
+    /// Paths of the initial peers files, merged. The missing ones are skipped as long as one of them can be read.
+    /// The former `initial_peers_file` key with a single path is still accepted
+    #[serde(
+        alias = "initial_peers_file",
+        deserialize_with = "deserialize_one_or_many_paths"
+    )]
+    pub initial_peers_files: Vec<PathBuf>,
+    /// Initial peers given with one of their listeners, merged with the ones of the files
+    pub inline_initial_peers: Vec<(PeerId, SocketAddr)>,
+    /// `host:port` DNS names resolving to the addresses of nodes to bootstrap our peer list from
+    pub dns_seeds: Vec<String>,
+    /// Interval between two resolutions of the DNS seeds
+    pub dns_seeds_refresh_interval: MassaTime,
     /// Keypair
     pub keypair_file: PathBuf,
     /// Ip we are bind to listen to
     pub bind: SocketAddr,
     /// Ip seen by others. If none the bind ip is used
     pub routable_ip: Option<IpAddr>,
+    /// Connect to and test the IPv6 addresses of the peers, only their IPv4 addresses are used if false
+    pub enable_ipv6: bool,
     /// Time threshold to have a connection to a node
     pub connect_timeout: MassaTime,
     /// Number of tester threads
@@ -251,24 +365,98 @@ pub struct ProtocolSettings {
     pub try_connection_timer: MassaTime,
     /// try connection timer for the same peer
     pub try_connection_timer_same_peer: MassaTime,
-    /// periodically unban every peer
+    /// Delay before trying again a peer after a failed connection, doubled at each new failure
+    pub connect_backoff_base: MassaTime,
+    /// Maximum delay between two connection attempts to a failing peer
+    pub connect_backoff_max: MassaTime,
+    /// ban duration for the reasons without an entry in `ban_durations`
     pub unban_everyone_timer: MassaTime,
+    /// ban duration for each ban reason
+    pub ban_durations: HashMap<BanReason, MassaTime>,
+    /// period without offense after which the offense count of a peer is decremented
+    pub offense_decay_period: MassaTime,
+    /// path to the file where banned peers are persisted
+    pub ban_list_path: Option<PathBuf>,
+    /// also ban the IP address of banned peers
+    pub ip_ban_enabled: bool,
+    /// reputation below which a peer committing minor offenses is banned
+    pub reputation_ban_threshold: i32,
+    /// time after which a quarantined peer that committed no further offense is trusted again
+    pub quarantine_duration: MassaTime,
+    /// whether a peer is banned or only disconnected on its first non-critical offense
+    pub first_offense_policy: FirstOffensePolicy,
+    /// time during which a peer disconnected for a first offense is banned if it offends again
+    pub first_offense_window: MassaTime,
+    /// number of peer events kept for each subscriber before the oldest ones are dropped
+    pub peer_events_channel_capacity: usize,
+    /// peers that are never banned nor quarantined, e.g. the other nodes of an operator's cluster
+    pub ban_whitelist: HashSet<PeerId>,
+    /// maximum number of block messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_block_messages_per_sec: u64,
+    /// maximum number of operation messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_operation_messages_per_sec: u64,
+    /// maximum number of endorsement messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_endorsement_messages_per_sec: u64,
+    /// maximum number of peer management messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_peer_management_messages_per_sec: u64,
+    /// maximum size in bytes of the messages of each category, a peer sending a bigger one is banned (`max_message_size` for the missing categories)
+    pub max_message_size_by_category: HashMap<MessageCategory, usize>,
+    /// max number of messages of a peer waiting in the channel of a handler, the reads of the peer pause beyond it (0 for no limit)
+    pub per_peer_inbound_queue_depth: usize,
+    /// maximum number of banned peers kept in memory, the oldest bans are forgotten beyond it (0 for no limit)
+    pub max_banned_peers: usize,
+    /// maximum number of banned IP addresses and subnets kept in memory, the oldest bans are forgotten beyond it (0 for no limit)
+    pub max_banned_subnets: usize,
     /// Timeout connection
     pub timeout_connection: MassaTime,
+    /// Max duration of a handshake, the connection is closed if it isn't finished in time
+    pub handshake_timeout: MassaTime,
+    /// A connection receiving nothing for this long gets a ping (0 to disable the keepalive)
+    pub idle_ping_interval: MassaTime,
+    /// A pinged peer sending nothing back for this long is disconnected
+    pub ping_timeout: MassaTime,
     /// Message timeout
     pub message_timeout: MassaTime,
+    /// Max duration of the connection draining on shutdown, the connections are closed abruptly if 0
+    pub shutdown_timeout: MassaTime,
     /// Timeout for the tester operations
     pub tester_timeout: MassaTime,
     /// Nb in connections
     pub max_in_connections: usize,
+    /// Nb max live inbound connections from a single IP address, loopback excluded (0 for no limit)
+    pub max_connections_per_ip: usize,
+    /// Nb max live connections, inbound and outbound (0 for no limit)
+    pub max_total_connections: usize,
+    /// Nb outbound connections kept by dialing known peers, over all the categories (0 for only the category targets)
+    pub target_out_connections: usize,
+    /// Dial the peers of the subnets we aren't connected to first
+    pub subnet_diversity_enabled: bool,
+    /// Peers always redialed when not connected, on top of the outbound targets
+    pub outbound_only_peers: Vec<(PeerId, SocketAddr)>,
     /// Peers limits per category
     pub peers_categories: HashMap<String, PeerCategoryInfo>,
     /// Limits for default category
     pub default_category_info: PeerCategoryInfo,
     /// Cooldown before testing again an old peer
     pub test_oldest_peer_cooldown: MassaTime,
+    /// time after which the known peers we didn't hear of are forgotten, unless connected, banned or quarantined (0 to keep them forever)
+    pub peer_expiry: MassaTime,
     /// Rate limitation to apply to the data stream (per second)
     pub rate_limit: u64,
+    /// Outbound bandwidth allowed towards each peer (bytes per second), no limit if absent
+    pub per_peer_bandwidth_limit: Option<u64>,
+    /// Number of bytes that can be sent to a peer at once before the bandwidth limit applies
+    pub per_peer_bandwidth_burst: u64,
+    /// Compression of the messages sent to the peers supporting it
+    pub compression: CompressionMode,
+    /// Checksum the frames sent to the peers supporting it, to drop the corrupted ones without banning the peer
+    pub frame_checksum: bool,
+    /// Number of corrupted frames a peer may send within `corrupted_frames_window` before being disconnected, no limit if 0
+    pub max_corrupted_frames: u64,
+    /// Window over which the corrupted frames of a peer are counted
+    pub corrupted_frames_window: MassaTime,
+    /// Minimum message format version of the peers we stay connected to
+    pub min_peer_version: u32,
 }
 
 /// gRPC settings
@@ -381,3 +569,26 @@ pub struct VersioningSettings {
 fn test_load_node_config() {
     let _ = *SETTINGS;
 }
+
+#[cfg(test)]
+#[test]
+fn test_initial_peers_files_accept_a_single_path() {
+    use serde::de::{
+        value::{Error, SeqDeserializer},
+        IntoDeserializer,
+    };
+
+    let single: Result<_, Error> =
+        deserialize_one_or_many_paths("base_config/initial_peers.json".into_deserializer());
+    assert_eq!(
+        single.unwrap(),
+        vec![PathBuf::from("base_config/initial_peers.json")]
+    );
+    let many: Result<_, Error> = deserialize_one_or_many_paths(SeqDeserializer::new(
+        vec!["first.json", "second.json"].into_iter(),
+    ));
+    assert_eq!(
+        many.unwrap(),
+        vec![PathBuf::from("first.json"), PathBuf::from("second.json")]
+    );
+}
@@ -46,12 +46,18 @@ pub trait PoolController: Send + Sync {
     /// Get the number of operations in the pool
     fn get_operation_count(&self) -> usize;
 
+    /// Check if the operation pool is full, new operations would then only replace the ones paying the least
+    fn is_operation_pool_full(&self) -> bool;
+
     /// Check if the pool contains a list of endorsements. Returns one boolean per item.
     fn contains_endorsements(&self, endorsements: &[EndorsementId]) -> Vec<bool>;
 
     /// Check if the pool contains a list of operations. Returns one boolean per item.
     fn contains_operations(&self, operations: &[OperationId]) -> Vec<bool>;
 
+    /// Get the ids of all the operations in the pool
+    fn get_operation_ids(&self) -> Vec<OperationId>;
+
     /// Get the number of denunciations in the pool
     fn get_denunciation_count(&self) -> usize;
 
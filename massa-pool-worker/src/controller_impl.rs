@@ -35,7 +35,7 @@ pub enum Command {
 #[derive(Clone)]
 pub struct PoolControllerImpl {
     /// Config
-    pub(crate) _config: PoolConfig,
+    pub(crate) config: PoolConfig,
     /// Shared reference to the operation pool
     pub(crate) operation_pool: Arc<RwLock<OperationPool>>,
     /// Shared reference to the endorsement pool
@@ -204,6 +204,11 @@ impl PoolController for PoolControllerImpl {
         self.operation_pool.read().len()
     }
 
+    /// Check if the operation pool is full, new operations would then only replace the ones paying the least
+    fn is_operation_pool_full(&self) -> bool {
+        self.operation_pool.read().len() >= self.config.max_operation_pool_size
+    }
+
     /// Check if the pool contains a list of endorsements. Returns one boolean per item.
     fn contains_endorsements(&self, endorsements: &[EndorsementId]) -> Vec<bool> {
         let lck = self.endorsement_pool.read();
@@ -216,6 +221,11 @@ impl PoolController for PoolControllerImpl {
         operations.iter().map(|id| lck.contains(id)).collect()
     }
 
+    /// Get the ids of all the operations in the pool
+    fn get_operation_ids(&self) -> Vec<OperationId> {
+        self.operation_pool.read().get_operation_ids()
+    }
+
     /// Get the number of denunciations in the pool
     fn get_denunciation_count(&self) -> usize {
         self.denunciation_pool.read().len()
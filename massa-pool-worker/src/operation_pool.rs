@@ -365,6 +365,11 @@ impl OperationPool {
         self.sorted_ops.len()
     }
 
+    /// Ids of the operations in the pool
+    pub fn get_operation_ids(&self) -> Vec<OperationId> {
+        self.sorted_ops.iter().map(|op_info| op_info.id).collect()
+    }
+
     /// Checks whether an element is stored in the pool.
     pub fn contains(&self, id: &OperationId) -> bool {
         self.storage.get_op_refs().contains(id)
@@ -208,7 +208,7 @@ pub fn start_pool_controller(
     )));
     let denunciation_pool = Arc::new(RwLock::new(DenunciationPool::init(config, channels)));
     let controller = PoolControllerImpl {
-        _config: config,
+        config,
         operation_pool: operation_pool.clone(),
         endorsement_pool: endorsement_pool.clone(),
         denunciation_pool: denunciation_pool.clone(),
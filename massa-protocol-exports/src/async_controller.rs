@@ -0,0 +1,69 @@
+//! Async wrapper of the protocol controller.
+//!
+//! The methods of `ProtocolController` may block the calling thread until a protocol thread
+//! accepts the command, which must not happen in the tasks of an async runtime.
+//! `AsyncProtocolController` runs them on the blocking threads of the tokio runtime, and bounds
+//! each of them by a timeout so that a stalled protocol thread can't hold the caller's task.
+//!
+//! The calls are cancel-safe: a command is sent whole or not at all, and when the future of a call
+//! is dropped its command may still be delivered, at the latest when its timeout elapses.
+
+use std::time::Duration;
+
+use massa_models::{
+    block_header::SecuredHeader,
+    block_id::BlockId,
+    prehash::{PreHashMap, PreHashSet},
+};
+
+use crate::{ProtocolController, ProtocolError};
+
+/// Async variants of the `ProtocolController` methods waiting on the protocol threads
+#[derive(Clone)]
+pub struct AsyncProtocolController {
+    controller: Box<dyn ProtocolController>,
+    timeout: Duration,
+}
+
+impl AsyncProtocolController {
+    /// Wrap `controller`, the calls fail with `ProtocolError::Timeout` after `timeout`
+    pub fn new(controller: Box<dyn ProtocolController>, timeout: Duration) -> Self {
+        AsyncProtocolController {
+            controller,
+            timeout,
+        }
+    }
+
+    /// The wrapped sync controller, for the calls that don't wait on the protocol threads
+    pub fn controller(&self) -> &dyn ProtocolController {
+        self.controller.as_ref()
+    }
+
+    /// Update the block wish list, see `ProtocolController::send_wishlist_delta`
+    pub async fn send_wishlist_delta(
+        &self,
+        new: PreHashMap<BlockId, Option<SecuredHeader>>,
+        remove: PreHashSet<BlockId>,
+    ) -> Result<(), ProtocolError> {
+        let controller = self.controller.clone();
+        let timeout = self.timeout;
+        run_blocking(move || controller.send_wishlist_delta_timeout(new, remove, timeout)).await
+    }
+
+    /// Notify to protocol an attack attempt, see `ProtocolController::notify_block_attack`
+    pub async fn notify_block_attack(&self, block_id: BlockId) -> Result<(), ProtocolError> {
+        let controller = self.controller.clone();
+        let timeout = self.timeout;
+        run_blocking(move || controller.notify_block_attack_timeout(block_id, timeout)).await
+    }
+}
+
+/// Run a controller call bounded by its own timeout on a blocking thread
+async fn run_blocking<F>(call: F) -> Result<(), ProtocolError>
+where
+    F: FnOnce() -> Result<(), ProtocolError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(call).await.map_err(|err| {
+        ProtocolError::GeneralProtocolError(format!("protocol controller call failed: {}", err))
+    })?
+}
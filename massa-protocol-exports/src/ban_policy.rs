@@ -0,0 +1,62 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use std::fmt::Debug;
+
+use crate::{BanReason, PeerId};
+
+/// Severity of an offense committed by a peer
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum BanSeverity {
+    /// may be caused by a faulty or lagging node, lowers the reputation of the peer and quarantines it,
+    /// the ban is escalated by one step on the next offense or once the reputation is too low
+    Minor,
+    /// cannot happen with an honest node, escalates the ban by two steps
+    Major,
+    /// obvious attack, the peer is banned permanently
+    Critical,
+}
+
+/// Offense submitted to the `BanPolicy`
+#[derive(Clone, Copy, Debug)]
+pub struct BanContext {
+    /// what the peer did
+    pub reason: BanReason,
+    /// severity of the offense, `None` for the bans that don't escalate
+    pub severity: Option<BanSeverity>,
+}
+
+/// Sanction decided by the `BanPolicy` for an offense
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BanDecision {
+    /// sanction the peer as the protocol asked (ban, quarantine or first offense disconnection)
+    Ban,
+    /// only close the connection to the peer, it may reconnect
+    Disconnect,
+    /// keep the peer connected
+    Ignore,
+}
+
+/// Decides how the peers are sanctioned for their offenses, for the deployments needing a
+/// different ban logic than the default one. The bans asked by the node operator are always applied.
+pub trait BanPolicy: Debug + Send + Sync {
+    /// The peer sent a block, operation or endorsement with an invalid signature
+    fn on_invalid_signature(&self, peer_id: &PeerId, ctx: &BanContext) -> BanDecision {
+        self.on_offense(peer_id, ctx)
+    }
+
+    /// The peer sent too many messages
+    fn on_rate_limit(&self, peer_id: &PeerId, ctx: &BanContext) -> BanDecision {
+        self.on_offense(peer_id, ctx)
+    }
+
+    /// Any other offense
+    fn on_offense(&self, _peer_id: &PeerId, _ctx: &BanContext) -> BanDecision {
+        BanDecision::Ban
+    }
+}
+
+/// Sanctions every offense as the protocol asks
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultBanPolicy;
+
+impl BanPolicy for DefaultBanPolicy {}
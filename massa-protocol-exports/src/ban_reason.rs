@@ -0,0 +1,28 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+
+/// Reason why a peer was banned
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanReason {
+    /// invalid block signature
+    InvalidBlockSignature,
+    /// invalid operation signature
+    InvalidOperationSignature,
+    /// invalid endorsement signature
+    InvalidEndorsementSignature,
+    /// operation list not matching the block header
+    InvalidOperationList,
+    /// propagation of an attack attempt
+    AttackPropagation,
+    /// protocol violation
+    ProtocolViolation,
+    /// rate limit exceeded
+    RateLimitExceeded,
+    /// announced blocks whose data it didn't serve
+    WithholdingData,
+    /// banned manually by the node operator
+    Manual,
+}
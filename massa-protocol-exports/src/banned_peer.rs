@@ -0,0 +1,18 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use massa_time::MassaTime;
+
+use crate::{BanReason, PeerId};
+
+/// Current ban of a peer, as listed by `ProtocolController::get_banned_peers`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BannedPeerInfo {
+    /// banned peer
+    pub peer_id: PeerId,
+    /// reason of the ban
+    pub reason: BanReason,
+    /// time of the ban
+    pub banned_at: MassaTime,
+    /// time left before the ban is lifted, `None` if it is permanent
+    pub remaining: Option<MassaTime>,
+}
@@ -2,15 +2,24 @@
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use crate::error::ProtocolError;
+use crate::BannedPeerInfo;
 use crate::BootstrapPeers;
+use crate::InflightRequest;
 
+use crate::PeerEventReceiver;
 use crate::PeerId;
+use crate::PeerStats;
+use crate::ProtocolConfigUpdate;
+use crate::ProtocolMetrics;
+use crate::RejectionReason;
 use massa_models::prehash::{PreHashMap, PreHashSet};
 use massa_models::stats::NetworkStats;
 use massa_models::{block_header::SecuredHeader, block_id::BlockId};
 use massa_storage::Storage;
+use massa_time::MassaTime;
 use peernet::peer::PeerConnectionType;
 
 #[cfg(feature = "test-exports")]
@@ -35,6 +44,29 @@ pub trait ProtocolController: Send + Sync {
     /// * `block_id`: ID of the block
     fn notify_block_attack(&self, block_id: BlockId) -> Result<(), ProtocolError>;
 
+    /// Notify to protocol an attack attempt, waiting at most `timeout` for room in the command queue
+    /// instead of failing right away when it is full.
+    ///
+    /// # Arguments
+    /// * `block_id`: ID of the block
+    /// * `timeout`: how long to wait for the block handler to accept the command
+    fn notify_block_attack_timeout(
+        &self,
+        block_id: BlockId,
+        timeout: Duration,
+    ) -> Result<(), ProtocolError>;
+
+    /// Notify to protocol that consensus rejected a header, to penalize the peers that sent it to us.
+    ///
+    /// # Arguments
+    /// * `block_id`: ID of the block
+    /// * `reason`: why the header was rejected, nobody is penalized for a benign one
+    fn notify_header_rejected(
+        &self,
+        block_id: BlockId,
+        reason: RejectionReason,
+    ) -> Result<(), ProtocolError>;
+
     /// Update the block wish list
     ///
     /// # Arguments
@@ -46,6 +78,20 @@ pub trait ProtocolController: Send + Sync {
         remove: PreHashSet<BlockId>,
     ) -> Result<(), ProtocolError>;
 
+    /// Update the block wish list, waiting at most `timeout` for the block handler to accept the delta
+    /// instead of blocking until it does. The delta is either sent whole or not at all.
+    ///
+    /// # Arguments
+    /// * `new`: new blocks to add to the wish list
+    /// * `remove`: blocks to remove from the wish list
+    /// * `timeout`: how long to wait for the block handler to accept the delta
+    fn send_wishlist_delta_timeout(
+        &self,
+        new: PreHashMap<BlockId, Option<SecuredHeader>>,
+        remove: PreHashSet<BlockId>,
+        timeout: Duration,
+    ) -> Result<(), ProtocolError>;
+
     /// Propagate a batch of operation (from pool).
     /// note: Full `OperationId` is replaced by a `OperationPrefixId` later by the worker.
     ///
@@ -72,6 +118,12 @@ pub trait ProtocolController: Send + Sync {
         ProtocolError,
     >;
 
+    /// Get the block download latency histogram and the count of block ask timeouts
+    fn get_metrics(&self) -> ProtocolMetrics;
+
+    /// Get the protocol metrics in the Prometheus text exposition format
+    fn gather_metrics(&self) -> String;
+
     /// Get a list of peers to be sent to someone that bootstrap to us
     fn get_bootstrap_peers(&self) -> Result<BootstrapPeers, ProtocolError>;
 
@@ -81,6 +133,40 @@ pub trait ProtocolController: Send + Sync {
     /// Unban a list of Peer Id
     fn unban_peers(&self, peer_ids: Vec<PeerId>) -> Result<(), ProtocolError>;
 
+    /// Get the peers currently banned with the reason and remaining time of their ban
+    fn get_banned_peers(&self) -> Vec<BannedPeerInfo>;
+
+    /// Lift the ban of a peer right away, fails if the peer isn't banned
+    fn unban_peer(&self, peer_id: &PeerId) -> Result<(), ProtocolError>;
+
+    /// Close the connection to a peer without banning it: its state is left unchanged,
+    /// so that a trusted peer may reconnect. If `graceful`, the peer is told we disconnect first.
+    fn disconnect_peer(&self, peer_id: &PeerId, graceful: bool) -> Result<(), ProtocolError>;
+
+    /// Subscribe to the bans, unbans, connections and disconnections of peers.
+    /// The channel is bounded: a slow subscriber loses its oldest events.
+    fn subscribe_peer_events(&self) -> PeerEventReceiver;
+
+    /// Get the number of peer events dropped because a subscriber was too slow
+    fn get_dropped_peer_events_count(&self) -> u64;
+
+    /// Get the bytes exchanged with each connected peer since its connection
+    fn get_peer_stats(&self) -> HashMap<PeerId, PeerStats>;
+
+    /// Get the bytes exchanged with all the peers since the start of the node
+    fn get_lifetime_peer_stats(&self) -> PeerStats;
+
+    /// Get the block data requests sent to the peers and not answered yet, oldest first
+    fn get_inflight_block_requests(&self) -> Result<Vec<InflightRequest>, ProtocolError>;
+
+    /// Get the time at which the header of each recent block was first received,
+    /// see `max_first_header_times` and `first_header_time_retention`
+    fn get_first_header_times(&self) -> Result<PreHashMap<BlockId, MassaTime>, ProtocolError>;
+
+    /// Change settings while the node runs, the protocol threads apply them on their next iteration.
+    /// Fails without changing anything if a setting can't be changed at runtime.
+    fn update_config(&self, update: ProtocolConfigUpdate) -> Result<(), ProtocolError>;
+
     /// Returns a boxed clone of self.
     /// Useful to allow cloning `Box<dyn ProtocolController>`.
     fn clone_box(&self) -> Box<dyn ProtocolController>;
@@ -1,10 +1,10 @@
 // Copyright (c) 2022 MASSA LABS <info@massa.net>
 
 use displaydoc::Display;
-use massa_models::error::ModelsError;
+use massa_models::{error::ModelsError, slot::Slot};
 use massa_pos_exports::PosError;
 use massa_versioning::versioning_factory::FactoryError;
-use std::net::IpAddr;
+use std::{net::IpAddr, path::PathBuf};
 use thiserror::Error;
 
 /// protocol error
@@ -65,6 +65,52 @@ pub enum ProtocolError {
     FactoryError(#[from] FactoryError),
     /// PoS error: {0}
     PosError(#[from] PosError),
+    /// Invalid configuration update: {0}
+    ConfigUpdateError(String),
+    /// Invalid configuration: {0}
+    ConfigError(#[from] ConfigError),
+    /// Block slot {slot} is more than {max_future_slots} slots after the current slot {current}
+    FutureBlock {
+        /// slot of the received block
+        slot: Slot,
+        /// current slot, counting the tolerated clock skew
+        current: Slot,
+        /// `max_future_slots`
+        max_future_slots: u64,
+    },
+    /// Block wishlist full: {wanted} blocks wanted, at most {max} are tracked
+    WishlistFull {
+        /// size the wishlist would have had
+        wanted: usize,
+        /// `max_wishlist_size`
+        max: usize,
+    },
+    /// Timed out: {0}
+    Timeout(String),
+}
+
+/// Invalid protocol configuration, found by `ProtocolConfig::validate`
+#[non_exhaustive]
+#[derive(Display, Error, Debug)]
+pub enum ConfigError {
+    /// thread_count must not be zero
+    ZeroThreadCount,
+    /// could not read any initial peers file, {path:?}: {error}
+    UnreadableInitialPeers {
+        /// path of the first initial peers file
+        path: PathBuf,
+        /// error met while opening it
+        error: std::io::Error,
+    },
+    /// {0} must not be zero
+    ZeroTimer(&'static str),
+    /// {shorter} must not be longer than {longer}
+    TimerOrder {
+        /// name of the timer expected to be the shortest
+        shorter: &'static str,
+        /// name of the timer expected to be the longest
+        longer: &'static str,
+    },
 }
 
 #[derive(Debug)]
@@ -0,0 +1,19 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use massa_models::block_id::BlockId;
+use massa_time::MassaTime;
+
+use crate::PeerId;
+
+/// Block data request waiting for its answer, as listed by `ProtocolController::get_inflight_block_requests`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InflightRequest {
+    /// asked block
+    pub block_id: BlockId,
+    /// peer asked for the block
+    pub peer_id: PeerId,
+    /// time of the request
+    pub asked_at: MassaTime,
+    /// number of earlier asks for the block that timed out or were cancelled
+    pub retries: usize,
+}
@@ -1,18 +1,43 @@
+#[cfg(feature = "async-controller")]
+mod async_controller;
+mod ban_policy;
+mod ban_reason;
+mod banned_peer;
 mod bootstrap_peers;
 mod controller_trait;
 mod error;
+mod inflight_request;
+mod peer_event;
 mod peer_id;
+mod peer_stats;
+mod prometheus;
+mod protocol_metrics;
+mod rejection_reason;
 mod settings;
 
+#[cfg(feature = "async-controller")]
+pub use async_controller::AsyncProtocolController;
+pub use ban_policy::{BanContext, BanDecision, BanPolicy, BanSeverity, DefaultBanPolicy};
+pub use ban_reason::BanReason;
+pub use banned_peer::BannedPeerInfo;
 pub use bootstrap_peers::{
     BootstrapPeers, BootstrapPeersDeserializer, BootstrapPeersSerializer, PeerData,
 };
 pub use controller_trait::{ProtocolController, ProtocolManager};
-pub use error::ProtocolError;
+pub use error::{ConfigError, ProtocolError};
+pub use inflight_request::InflightRequest;
+pub use peer_event::{PeerEvent, PeerEventBroadcast, PeerEventReceiver};
 pub use peer_id::{PeerId, PeerIdDeserializer, PeerIdSerializer};
+pub use peer_stats::PeerStats;
 pub use peernet::peer::PeerConnectionType;
 pub use peernet::transports::TransportType;
-pub use settings::{PeerCategoryInfo, ProtocolConfig};
+pub use prometheus::ProtocolMetricsState;
+pub use protocol_metrics::{MessageCounters, MessageKind, PeerStateCounts, ProtocolMetrics};
+pub use rejection_reason::RejectionReason;
+pub use settings::{
+    CompressionMode, ConsensusUnavailablePolicy, FirstOffensePolicy, MessageCategory,
+    PeerCategoryInfo, ProtocolConfig, ProtocolConfigUpdate,
+};
 
 #[cfg(any(test, feature = "test-exports"))]
 pub mod test_exports;
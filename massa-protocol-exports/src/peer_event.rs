@@ -0,0 +1,106 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use massa_time::MassaTime;
+use tokio::sync::broadcast;
+
+use crate::{BanReason, PeerId};
+
+/// Change in the status of a peer, published to the subscribers of peer events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// the peer was banned
+    Banned {
+        peer_id: PeerId,
+        ip: Option<IpAddr>,
+        reason: BanReason,
+        timestamp: MassaTime,
+    },
+    /// the ban of the peer was lifted
+    Unbanned {
+        peer_id: PeerId,
+        ip: Option<IpAddr>,
+        timestamp: MassaTime,
+    },
+    /// a connection with the peer was established
+    Connected {
+        peer_id: PeerId,
+        ip: Option<IpAddr>,
+        timestamp: MassaTime,
+    },
+    /// the connection with the peer was closed
+    Disconnected {
+        peer_id: PeerId,
+        ip: Option<IpAddr>,
+        timestamp: MassaTime,
+    },
+}
+
+impl PeerEvent {
+    /// Get the peer concerned by the event
+    pub fn peer_id(&self) -> &PeerId {
+        match self {
+            PeerEvent::Banned { peer_id, .. }
+            | PeerEvent::Unbanned { peer_id, .. }
+            | PeerEvent::Connected { peer_id, .. }
+            | PeerEvent::Disconnected { peer_id, .. } => peer_id,
+        }
+    }
+}
+
+/// Receiving end of a peer events subscription
+pub type PeerEventReceiver = broadcast::Receiver<PeerEvent>;
+
+/// Bounded broadcast of the peer events.
+///
+/// Publishing never blocks: when a subscriber lags behind, its oldest events are overwritten
+/// and counted as dropped.
+#[derive(Clone)]
+pub struct PeerEventBroadcast {
+    sender: broadcast::Sender<PeerEvent>,
+    capacity: usize,
+    dropped: Arc<AtomicU64>,
+}
+
+impl PeerEventBroadcast {
+    /// Create a broadcast keeping at most `capacity` events per subscriber
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        PeerEventBroadcast {
+            sender: broadcast::channel(capacity).0,
+            capacity,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Whether someone subscribed to the events, to avoid building them for nothing
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Publish an event to all the subscribers
+    pub fn send(&self, event: PeerEvent) {
+        if !self.has_subscribers() {
+            return;
+        }
+        if self.sender.len() >= self.capacity {
+            // the oldest event of the slowest subscriber is overwritten
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        // only fails if every subscriber left in the meantime
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to the events published from now on
+    pub fn subscribe(&self) -> PeerEventReceiver {
+        self.sender.subscribe()
+    }
+
+    /// Number of events dropped because a subscriber was too slow to consume them
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use std::collections::HashMap;
+
+use crate::MessageCategory;
+
+/// Bytes exchanged with a peer, by message category, as returned by `ProtocolController::get_peer_stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// bytes sent to the peer, compression and message type id included
+    pub bytes_sent: HashMap<MessageCategory, u64>,
+    /// bytes received from the peer, compression and message type id included
+    pub bytes_received: HashMap<MessageCategory, u64>,
+    /// frames received from the peer and dropped for failing their checksum
+    pub corrupted_frames: u64,
+}
+
+impl PeerStats {
+    /// Count bytes sent in a message of the given category
+    pub fn record_sent(&mut self, category: MessageCategory, bytes: u64) {
+        let count = self.bytes_sent.entry(category).or_default();
+        *count = count.saturating_add(bytes);
+    }
+
+    /// Count bytes received in a message of the given category
+    pub fn record_received(&mut self, category: MessageCategory, bytes: u64) {
+        let count = self.bytes_received.entry(category).or_default();
+        *count = count.saturating_add(bytes);
+    }
+
+    /// Count a frame received corrupted
+    pub fn record_corrupted(&mut self) {
+        self.corrupted_frames = self.corrupted_frames.saturating_add(1);
+    }
+
+    /// Total number of bytes sent, all categories included
+    pub fn total_bytes_sent(&self) -> u64 {
+        self.bytes_sent.values().sum()
+    }
+
+    /// Total number of bytes received, all categories included
+    pub fn total_bytes_received(&self) -> u64 {
+        self.bytes_received.values().sum()
+    }
+}
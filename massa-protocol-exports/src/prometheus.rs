@@ -0,0 +1,397 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+//! Prometheus text exposition of the protocol metrics, as returned by
+//! `ProtocolController::gather_metrics` for the node to serve it.
+
+use std::{collections::HashMap, fmt::Write};
+
+use massa_time::MassaTime;
+
+use crate::{BanReason, MessageCategory, MessageKind, PeerStats, ProtocolMetrics};
+
+/// State of the protocol sampled when the metrics are gathered, rendered next to `ProtocolMetrics`
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolMetricsState {
+    /// number of connections initiated by the peers, `None` if unknown
+    pub in_connections: Option<u64>,
+    /// number of connections we initiated, `None` if unknown
+    pub out_connections: Option<u64>,
+    /// number of peers currently banned, by reason
+    pub banned_peers: HashMap<BanReason, u64>,
+    /// bytes exchanged with all the peers since the start of the node
+    pub traffic: PeerStats,
+}
+
+fn ban_reason_label(reason: BanReason) -> &'static str {
+    match reason {
+        BanReason::InvalidBlockSignature => "invalid_block_signature",
+        BanReason::InvalidOperationSignature => "invalid_operation_signature",
+        BanReason::InvalidEndorsementSignature => "invalid_endorsement_signature",
+        BanReason::InvalidOperationList => "invalid_operation_list",
+        BanReason::AttackPropagation => "attack_propagation",
+        BanReason::ProtocolViolation => "protocol_violation",
+        BanReason::RateLimitExceeded => "rate_limit_exceeded",
+        BanReason::WithholdingData => "withholding_data",
+        BanReason::Manual => "manual",
+    }
+}
+
+fn category_label(category: MessageCategory) -> &'static str {
+    match category {
+        MessageCategory::Block => "block",
+        MessageCategory::Endorsement => "endorsement",
+        MessageCategory::Operation => "operation",
+        MessageCategory::PeerManagement => "peer_management",
+    }
+}
+
+fn seconds(time: MassaTime) -> f64 {
+    time.as_millis() as f64 / 1000.0
+}
+
+/// Writes metric families in the Prometheus text format
+struct TextEncoder {
+    output: String,
+}
+
+impl TextEncoder {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        // writing to a String can't fail
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, value))
+                .collect();
+            let _ = write!(self.output, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.output, " {}", value);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.family(name, "counter", help);
+        self.sample(name, &[], value);
+    }
+}
+
+impl ProtocolMetrics {
+    /// Render the metrics and the sampled `state` in the Prometheus text exposition format
+    pub fn encode(&self, state: &ProtocolMetricsState) -> String {
+        let mut encoder = TextEncoder {
+            output: String::new(),
+        };
+
+        if state.in_connections.is_some() || state.out_connections.is_some() {
+            encoder.family(
+                "massa_protocol_active_connections",
+                "gauge",
+                "Number of active connections to peers",
+            );
+            for (direction, count) in [("in", state.in_connections), ("out", state.out_connections)]
+            {
+                if let Some(count) = count {
+                    encoder.sample(
+                        "massa_protocol_active_connections",
+                        &[("direction", direction)],
+                        count,
+                    );
+                }
+            }
+        }
+
+        encoder.family(
+            "massa_protocol_banned_peers",
+            "gauge",
+            "Number of peers currently banned, by reason",
+        );
+        let mut banned_peers: Vec<(&'static str, u64)> = state
+            .banned_peers
+            .iter()
+            .map(|(reason, count)| (ban_reason_label(*reason), *count))
+            .collect();
+        banned_peers.sort_unstable();
+        for (reason, count) in banned_peers {
+            encoder.sample("massa_protocol_banned_peers", &[("reason", reason)], count);
+        }
+        encoder.family(
+            "massa_protocol_peers",
+            "gauge",
+            "Number of known peers, by state",
+        );
+        for (peer_state, count) in self.peers_by_state.labeled() {
+            encoder.sample("massa_protocol_peers", &[("state", peer_state)], count);
+        }
+
+        for (name, help, bytes) in [
+            (
+                "massa_protocol_received_bytes_total",
+                "Bytes received from the peers, by message category",
+                &state.traffic.bytes_received,
+            ),
+            (
+                "massa_protocol_sent_bytes_total",
+                "Bytes sent to the peers, by message category",
+                &state.traffic.bytes_sent,
+            ),
+        ] {
+            encoder.family(name, "counter", help);
+            let mut bytes: Vec<(&'static str, u64)> = bytes
+                .iter()
+                .map(|(category, count)| (category_label(*category), *count))
+                .collect();
+            bytes.sort_unstable();
+            for (category, count) in bytes {
+                encoder.sample(name, &[("category", category)], count);
+            }
+        }
+        encoder.counter(
+            "massa_protocol_corrupted_frames_total",
+            "Frames received from the peers and dropped for failing their checksum",
+            state.traffic.corrupted_frames,
+        );
+
+        encoder.family(
+            "massa_protocol_block_download_seconds",
+            "histogram",
+            "Time between a block data request and its valid response",
+        );
+        let mut cumulative_count: u64 = 0;
+        for (bound, count) in self
+            .block_download_buckets
+            .iter()
+            .zip(&self.block_download_counts)
+        {
+            cumulative_count += *count;
+            let bound = seconds(*bound).to_string();
+            encoder.sample(
+                "massa_protocol_block_download_seconds_bucket",
+                &[("le", bound.as_str())],
+                cumulative_count,
+            );
+        }
+        encoder.sample(
+            "massa_protocol_block_download_seconds_bucket",
+            &[("le", "+Inf")],
+            self.block_downloads,
+        );
+        encoder.sample(
+            "massa_protocol_block_download_seconds_sum",
+            &[],
+            seconds(self.block_download_total_time),
+        );
+        encoder.sample(
+            "massa_protocol_block_download_seconds_count",
+            &[],
+            self.block_downloads,
+        );
+        encoder.counter(
+            "massa_protocol_block_ask_timeouts_total",
+            "Block data requests that weren't answered in time",
+            self.block_ask_timeouts,
+        );
+        encoder.family(
+            "massa_protocol_wishlist_blocks",
+            "gauge",
+            "Number of blocks wanted by consensus and tracked by the block handler",
+        );
+        encoder.sample("massa_protocol_wishlist_blocks", &[], self.wishlist_size);
+
+        encoder.counter(
+            "massa_protocol_operations_received_total",
+            "Operations received from the peers",
+            self.operations_received,
+        );
+        encoder.counter(
+            "massa_protocol_operations_duplicates_total",
+            "Received operations that had already been checked",
+            self.operations_duplicates,
+        );
+        encoder.counter(
+            "massa_protocol_operations_invalid_total",
+            "Received operations rejected as invalid",
+            self.operations_invalid,
+        );
+        encoder.counter(
+            "massa_protocol_operations_below_fee_total",
+            "Received operations paying less than the minimum propagation fee",
+            self.operations_below_fee,
+        );
+        encoder.counter(
+            "massa_protocol_operations_sent_to_pool_total",
+            "Received operations sent to the pool",
+            self.operations_sent_to_pool,
+        );
+        encoder.family(
+            "massa_protocol_operation_ingestion_rate",
+            "gauge",
+            "Moving average of the number of operations sent to the pool per second",
+        );
+        encoder.sample(
+            "massa_protocol_operation_ingestion_rate",
+            &[],
+            self.get_operation_ingestion_rate(MassaTime::now()),
+        );
+
+        encoder.counter(
+            "massa_protocol_bandwidth_delayed_sends_total",
+            "Messages whose sending was delayed by the per peer bandwidth limit",
+            self.bandwidth_delayed_sends,
+        );
+        encoder.family(
+            "massa_protocol_bandwidth_limited_peers",
+            "gauge",
+            "Peers whose last messages were all delayed by the per peer bandwidth limit",
+        );
+        encoder.sample(
+            "massa_protocol_bandwidth_limited_peers",
+            &[],
+            self.bandwidth_limited_peers.len(),
+        );
+
+        for (name, help, received) in [
+            (
+                "massa_protocol_received_messages_total",
+                "Messages received from the peers, by kind",
+                true,
+            ),
+            (
+                "massa_protocol_sent_messages_total",
+                "Messages sent to the peers, by kind",
+                false,
+            ),
+        ] {
+            encoder.family(name, "counter", help);
+            for kind in MessageKind::ALL {
+                let count = if received {
+                    self.message_counters.received(kind)
+                } else {
+                    self.message_counters.sent(kind)
+                };
+                encoder.sample(name, &[("kind", kind.label())], count);
+            }
+        }
+        encoder.family(
+            "massa_protocol_dropped_messages_total",
+            "counter",
+            "Messages received from the peers and dropped because the channel of their handler was full, by category",
+        );
+        for category in MessageCategory::ALL {
+            encoder.sample(
+                "massa_protocol_dropped_messages_total",
+                &[("category", category_label(category))],
+                self.message_counters.dropped(category),
+            );
+        }
+        encoder.family(
+            "massa_protocol_paused_reads_total",
+            "counter",
+            "Reads of a peer paused because too many of its messages were waiting for their handler, by category",
+        );
+        for category in MessageCategory::ALL {
+            encoder.sample(
+                "massa_protocol_paused_reads_total",
+                &[("category", category_label(category))],
+                self.message_counters.paused(category),
+            );
+        }
+
+        encoder.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use massa_time::MassaTime;
+
+    use super::ProtocolMetricsState;
+    use crate::{BanReason, MessageCategory, MessageKind, PeerStats, ProtocolMetrics};
+
+    #[test]
+    fn test_encode_protocol_metrics() {
+        let mut metrics = ProtocolMetrics::new(vec![
+            MassaTime::from_millis(100),
+            MassaTime::from_millis(500),
+        ]);
+        let asked_at = MassaTime::from_millis(1000);
+        for latency in [50, 300, 2000] {
+            metrics.record_block_download(asked_at, MassaTime::from_millis(1000 + latency));
+        }
+        metrics.record_block_ask_timeout();
+        metrics.record_operations_received(10, 3);
+        metrics.record_invalid_operations(2);
+        metrics.peers_by_state.trusted = 7;
+        metrics.peers_by_state.quarantined = 1;
+        metrics.message_counters.record_received(MessageKind::Ping);
+        metrics
+            .message_counters
+            .record_sent(MessageKind::BlockDataRequest);
+        let mut traffic = PeerStats::default();
+        traffic.record_received(MessageCategory::Block, 1500);
+        traffic.record_sent(MessageCategory::PeerManagement, 42);
+        traffic.record_corrupted();
+        metrics
+            .message_counters
+            .record_dropped(MessageCategory::Operation, MassaTime::from_millis(1000));
+        metrics
+            .message_counters
+            .record_paused(MessageCategory::Block);
+        let state = ProtocolMetricsState {
+            in_connections: Some(3),
+            out_connections: Some(5),
+            banned_peers: HashMap::from([
+                (BanReason::RateLimitExceeded, 2),
+                (BanReason::Manual, 1),
+            ]),
+            traffic,
+        };
+
+        let output = metrics.encode(&state);
+        let lines: Vec<&str> = output.lines().collect();
+        for expected in [
+            "# TYPE massa_protocol_active_connections gauge",
+            "massa_protocol_active_connections{direction=\"in\"} 3",
+            "massa_protocol_active_connections{direction=\"out\"} 5",
+            "massa_protocol_banned_peers{reason=\"manual\"} 1",
+            "massa_protocol_banned_peers{reason=\"rate_limit_exceeded\"} 2",
+            "# TYPE massa_protocol_peers gauge",
+            "massa_protocol_peers{state=\"trusted\"} 7",
+            "massa_protocol_peers{state=\"banned\"} 0",
+            "massa_protocol_peers{state=\"quarantined\"} 1",
+            "# TYPE massa_protocol_received_bytes_total counter",
+            "massa_protocol_received_bytes_total{category=\"block\"} 1500",
+            "massa_protocol_sent_bytes_total{category=\"peer_management\"} 42",
+            "massa_protocol_corrupted_frames_total 1",
+            "# TYPE massa_protocol_block_download_seconds histogram",
+            "massa_protocol_block_download_seconds_bucket{le=\"0.1\"} 1",
+            "massa_protocol_block_download_seconds_bucket{le=\"0.5\"} 2",
+            "massa_protocol_block_download_seconds_bucket{le=\"+Inf\"} 3",
+            "massa_protocol_block_download_seconds_sum 2.35",
+            "massa_protocol_block_download_seconds_count 3",
+            "massa_protocol_block_ask_timeouts_total 1",
+            "massa_protocol_wishlist_blocks 0",
+            "massa_protocol_operations_received_total 10",
+            "massa_protocol_operations_duplicates_total 3",
+            "massa_protocol_operations_invalid_total 2",
+            "massa_protocol_bandwidth_limited_peers 0",
+            "massa_protocol_received_messages_total{kind=\"ping\"} 1",
+            "massa_protocol_received_messages_total{kind=\"pong\"} 0",
+            "massa_protocol_sent_messages_total{kind=\"block_data_request\"} 1",
+            "massa_protocol_dropped_messages_total{category=\"operation\"} 1",
+            "massa_protocol_dropped_messages_total{category=\"block\"} 0",
+            "massa_protocol_paused_reads_total{category=\"block\"} 1",
+        ] {
+            assert!(lines.contains(&expected), "missing line {}", expected);
+        }
+
+        // the connection gauges are left out when unknown
+        let output = metrics.encode(&ProtocolMetricsState::default());
+        assert!(!output.contains("massa_protocol_active_connections"));
+    }
+}
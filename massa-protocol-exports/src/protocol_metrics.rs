@@ -0,0 +1,405 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use massa_time::MassaTime;
+
+use crate::{MessageCategory, PeerId};
+
+/// Time constant, in seconds, of the moving average of the operation ingestion rate
+const OPERATION_INGESTION_RATE_PERIOD_S: f64 = 10.0;
+
+/// Minimum time between two warnings about the messages dropped from the same full channel
+const DROPPED_MESSAGES_WARNING_WINDOW: MassaTime = MassaTime::from_millis(60_000);
+
+/// Variants of the messages exchanged with the peers, counted in `MessageCounters`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    BlockHeader,
+    BlockDataRequest,
+    BlockDataResponse,
+    CompactBlock,
+    Endorsements,
+    EndorsementsAnnouncement,
+    AskEndorsements,
+    OperationsAnnouncement,
+    AskForOperations,
+    Operations,
+    AskKnownOperationIds,
+    KnownOperationIds,
+    NewPeerConnected,
+    ListPeers,
+    Disconnect,
+    Ping,
+    Pong,
+}
+
+impl MessageKind {
+    pub const ALL: [MessageKind; 17] = [
+        MessageKind::BlockHeader,
+        MessageKind::BlockDataRequest,
+        MessageKind::BlockDataResponse,
+        MessageKind::CompactBlock,
+        MessageKind::Endorsements,
+        MessageKind::EndorsementsAnnouncement,
+        MessageKind::AskEndorsements,
+        MessageKind::OperationsAnnouncement,
+        MessageKind::AskForOperations,
+        MessageKind::Operations,
+        MessageKind::AskKnownOperationIds,
+        MessageKind::KnownOperationIds,
+        MessageKind::NewPeerConnected,
+        MessageKind::ListPeers,
+        MessageKind::Disconnect,
+        MessageKind::Ping,
+        MessageKind::Pong,
+    ];
+
+    /// Name of the kind in the metric labels
+    pub fn label(self) -> &'static str {
+        match self {
+            MessageKind::BlockHeader => "block_header",
+            MessageKind::BlockDataRequest => "block_data_request",
+            MessageKind::BlockDataResponse => "block_data_response",
+            MessageKind::CompactBlock => "compact_block",
+            MessageKind::Endorsements => "endorsements",
+            MessageKind::EndorsementsAnnouncement => "endorsements_announcement",
+            MessageKind::AskEndorsements => "ask_endorsements",
+            MessageKind::OperationsAnnouncement => "operations_announcement",
+            MessageKind::AskForOperations => "ask_for_operations",
+            MessageKind::Operations => "operations",
+            MessageKind::AskKnownOperationIds => "ask_known_operation_ids",
+            MessageKind::KnownOperationIds => "known_operation_ids",
+            MessageKind::NewPeerConnected => "new_peer_connected",
+            MessageKind::ListPeers => "list_peers",
+            MessageKind::Disconnect => "disconnect",
+            MessageKind::Ping => "ping",
+            MessageKind::Pong => "pong",
+        }
+    }
+}
+
+/// Number of messages of each kind received from and sent to the peers, of the received
+/// messages dropped because the channel of their handler was full, and of the reads of the peers
+/// paused because too many of their messages were waiting for their handler.
+/// Atomics, so that the network threads count the messages without taking any lock.
+#[derive(Debug, Default)]
+pub struct MessageCounters {
+    received: [AtomicU64; MessageKind::ALL.len()],
+    sent: [AtomicU64; MessageKind::ALL.len()],
+    dropped: [AtomicU64; MessageCategory::ALL.len()],
+    paused: [AtomicU64; MessageCategory::ALL.len()],
+    /// time in milliseconds of the last warning about each channel, 0 before the first one
+    last_drop_warnings: [AtomicU64; MessageCategory::ALL.len()],
+}
+
+impl MessageCounters {
+    /// Count a message received from a peer
+    pub fn record_received(&self, kind: MessageKind) {
+        self.received[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a message sent to a peer
+    pub fn record_sent(&self, kind: MessageKind) {
+        self.sent[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of messages of this kind received since the start of the node
+    pub fn received(&self, kind: MessageKind) -> u64 {
+        self.received[kind as usize].load(Ordering::Relaxed)
+    }
+
+    /// Number of messages of this kind sent since the start of the node
+    pub fn sent(&self, kind: MessageKind) -> u64 {
+        self.sent[kind as usize].load(Ordering::Relaxed)
+    }
+
+    /// Count a received message dropped at time `now` because the channel of its handler was full.
+    /// Returns whether it is the first drop of this channel within the warning window
+    pub fn record_dropped(&self, category: MessageCategory, now: MassaTime) -> bool {
+        self.dropped[category as usize].fetch_add(1, Ordering::Relaxed);
+        let last_warning = &self.last_drop_warnings[category as usize];
+        let last_warning_at = last_warning.load(Ordering::Relaxed);
+        if last_warning_at != 0
+            && now.saturating_sub(MassaTime::from_millis(last_warning_at))
+                < DROPPED_MESSAGES_WARNING_WINDOW
+        {
+            return false;
+        }
+        // a single thread wins the warning when several of them drop messages at the same time
+        last_warning
+            .compare_exchange(
+                last_warning_at,
+                now.as_millis().max(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Number of received messages of this category dropped since the start of the node
+    pub fn dropped(&self, category: MessageCategory) -> u64 {
+        self.dropped[category as usize].load(Ordering::Relaxed)
+    }
+
+    /// Count the reads of a peer paused because it had too many messages of this category waiting
+    pub fn record_paused(&self, category: MessageCategory) {
+        self.paused[category as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of reads paused for messages of this category since the start of the node
+    pub fn paused(&self, category: MessageCategory) -> u64 {
+        self.paused[category as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl PartialEq for MessageCounters {
+    fn eq(&self, other: &Self) -> bool {
+        MessageKind::ALL.iter().all(|kind| {
+            self.received(*kind) == other.received(*kind) && self.sent(*kind) == other.sent(*kind)
+        }) && MessageCategory::ALL.iter().all(|category| {
+            self.dropped(*category) == other.dropped(*category)
+                && self.paused(*category) == other.paused(*category)
+        })
+    }
+}
+
+/// Number of known peers in each state, tallied by the peer database as the peers change state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerStateCounts {
+    pub trusted: u64,
+    pub banned: u64,
+    pub quarantined: u64,
+    pub in_handshake: u64,
+    pub handshake_failed: u64,
+    pub wrong_network: u64,
+}
+
+impl PeerStateCounts {
+    /// Count of each state with its name in the metric labels
+    pub fn labeled(&self) -> [(&'static str, u64); 6] {
+        [
+            ("trusted", self.trusted),
+            ("banned", self.banned),
+            ("quarantined", self.quarantined),
+            ("in_handshake", self.in_handshake),
+            ("handshake_failed", self.handshake_failed),
+            ("wrong_network", self.wrong_network),
+        ]
+    }
+}
+
+/// Metrics gathered by the protocol, as returned by `ProtocolController::get_metrics`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolMetrics {
+    /// upper bounds of the buckets of the block download latency histogram, sorted
+    pub block_download_buckets: Vec<MassaTime>,
+    /// number of block downloads in each bucket,
+    /// the extra last one counts the downloads slower than all the bounds
+    pub block_download_counts: Vec<u64>,
+    /// total number of block downloads
+    pub block_downloads: u64,
+    /// sum of the durations of all the block downloads
+    pub block_download_total_time: MassaTime,
+    /// number of block asks that timed out
+    pub block_ask_timeouts: u64,
+    /// number of operations received from peers
+    pub operations_received: u64,
+    /// number of received operations that had already been checked
+    pub operations_duplicates: u64,
+    /// number of received operations rejected as invalid (too big or with an invalid signature)
+    pub operations_invalid: u64,
+    /// number of received operations paying less than `min_propagation_fee`
+    pub operations_below_fee: u64,
+    /// number of received operations sent to the pool
+    pub operations_sent_to_pool: u64,
+    /// exponential moving average of the number of operations sent to the pool per second,
+    /// as of `operation_ingestion_rate_updated_at`
+    pub operation_ingestion_rate: f64,
+    /// last time `operation_ingestion_rate` was updated
+    pub operation_ingestion_rate_updated_at: Option<MassaTime>,
+    /// number of messages whose sending was delayed by the per peer bandwidth limit
+    pub bandwidth_delayed_sends: u64,
+    /// peers whose last messages were all delayed by the per peer bandwidth limit
+    pub bandwidth_limited_peers: HashSet<PeerId>,
+    /// messages received and sent by kind and dropped on full channels, live counters shared by all the clones of the metrics
+    pub message_counters: Arc<MessageCounters>,
+    /// number of known peers in each state, copied from the peer database when the metrics are read
+    pub peers_by_state: PeerStateCounts,
+    /// number of blocks in the wishlist of the block handler
+    pub wishlist_size: u64,
+}
+
+impl ProtocolMetrics {
+    /// Creates empty metrics with the given histogram bucket bounds
+    pub fn new(mut block_download_buckets: Vec<MassaTime>) -> Self {
+        block_download_buckets.sort_unstable();
+        block_download_buckets.dedup();
+        ProtocolMetrics {
+            block_download_counts: vec![0; block_download_buckets.len() + 1],
+            block_download_buckets,
+            block_downloads: 0,
+            block_download_total_time: MassaTime::from_millis(0),
+            block_ask_timeouts: 0,
+            operations_received: 0,
+            operations_duplicates: 0,
+            operations_invalid: 0,
+            operations_below_fee: 0,
+            operations_sent_to_pool: 0,
+            operation_ingestion_rate: 0.0,
+            operation_ingestion_rate_updated_at: None,
+            bandwidth_delayed_sends: 0,
+            bandwidth_limited_peers: HashSet::new(),
+            message_counters: Default::default(),
+            peers_by_state: PeerStateCounts::default(),
+            wishlist_size: 0,
+        }
+    }
+
+    /// Record a block data request answered with a valid response
+    ///
+    /// # Arguments
+    /// * `asked_at`: time at which the request was sent
+    /// * `received_at`: time at which the response was received
+    pub fn record_block_download(&mut self, asked_at: MassaTime, received_at: MassaTime) {
+        let latency = received_at.saturating_sub(asked_at);
+        let bucket = self
+            .block_download_buckets
+            .partition_point(|bound| *bound < latency);
+        self.block_download_counts[bucket] += 1;
+        self.block_downloads += 1;
+        self.block_download_total_time = self.block_download_total_time.saturating_add(latency);
+    }
+
+    /// Record a block data request that wasn't answered in time
+    pub fn record_block_ask_timeout(&mut self) {
+        self.block_ask_timeouts += 1;
+    }
+
+    /// Record operations received from a peer, `duplicates` of them being already known
+    pub fn record_operations_received(&mut self, count: u64, duplicates: u64) {
+        self.operations_received += count;
+        self.operations_duplicates += duplicates;
+    }
+
+    /// Record received operations rejected because of an invalid signature
+    pub fn record_invalid_operations(&mut self, count: u64) {
+        self.operations_invalid += count;
+    }
+
+    /// Record received operations paying less than `min_propagation_fee`
+    pub fn record_operations_below_fee(&mut self, count: u64) {
+        self.operations_below_fee += count;
+    }
+
+    /// Record received operations sent to the pool at time `now`, and update the ingestion rate
+    pub fn record_operations_sent_to_pool(&mut self, count: u64, now: MassaTime) {
+        self.operations_sent_to_pool += count;
+        let decayed_rate = self.get_operation_ingestion_rate(now);
+        self.operation_ingestion_rate =
+            decayed_rate + count as f64 / OPERATION_INGESTION_RATE_PERIOD_S;
+        self.operation_ingestion_rate_updated_at = Some(now);
+    }
+
+    /// Record a message whose sending was delayed by the per peer bandwidth limit
+    pub fn record_bandwidth_delayed_send(&mut self) {
+        self.bandwidth_delayed_sends += 1;
+    }
+
+    /// Mark a peer as constantly hitting the per peer bandwidth limit, or not anymore
+    pub fn set_bandwidth_limited(&mut self, peer_id: &PeerId, limited: bool) {
+        if limited {
+            self.bandwidth_limited_peers.insert(*peer_id);
+        } else {
+            self.bandwidth_limited_peers.remove(peer_id);
+        }
+    }
+
+    /// Get the moving average of the number of operations sent to the pool per second at time `now`
+    pub fn get_operation_ingestion_rate(&self, now: MassaTime) -> f64 {
+        match self.operation_ingestion_rate_updated_at {
+            Some(updated_at) => {
+                let elapsed_s = now.saturating_sub(updated_at).as_millis() as f64 / 1000.0;
+                self.operation_ingestion_rate
+                    * (-elapsed_s / OPERATION_INGESTION_RATE_PERIOD_S).exp()
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use massa_time::MassaTime;
+
+    use super::{MessageCounters, ProtocolMetrics};
+    use crate::MessageCategory;
+
+    #[test]
+    fn test_block_download_histogram() {
+        let mut metrics = ProtocolMetrics::new(vec![
+            MassaTime::from_millis(500),
+            MassaTime::from_millis(100),
+        ]);
+        let asked_at = MassaTime::from_millis(1000);
+        for latency in [50, 100, 300, 2000] {
+            metrics.record_block_download(asked_at, MassaTime::from_millis(1000 + latency));
+        }
+        metrics.record_block_ask_timeout();
+
+        assert_eq!(
+            metrics.block_download_buckets,
+            vec![MassaTime::from_millis(100), MassaTime::from_millis(500)]
+        );
+        assert_eq!(metrics.block_download_counts, vec![2, 1, 1]);
+        assert_eq!(metrics.block_downloads, 4);
+        assert_eq!(
+            metrics.block_download_total_time,
+            MassaTime::from_millis(2450)
+        );
+        assert_eq!(metrics.block_ask_timeouts, 1);
+    }
+
+    #[test]
+    fn test_operation_ingestion_rate() {
+        let mut metrics = ProtocolMetrics::new(vec![]);
+        assert_eq!(
+            metrics.get_operation_ingestion_rate(MassaTime::from_millis(1000)),
+            0.0
+        );
+        metrics.record_operations_sent_to_pool(100, MassaTime::from_millis(1000));
+        assert_eq!(metrics.operations_sent_to_pool, 100);
+        assert_eq!(
+            metrics.get_operation_ingestion_rate(MassaTime::from_millis(1000)),
+            10.0
+        );
+        // the rate decays when no operations are received
+        let later_rate = metrics.get_operation_ingestion_rate(MassaTime::from_millis(11000));
+        assert!((later_rate - 10.0 / std::f64::consts::E).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_dropped_messages_warning_window() {
+        let counters = MessageCounters::default();
+        let start = MassaTime::from_millis(1000);
+        assert!(counters.record_dropped(MessageCategory::Operation, start));
+        assert!(!counters.record_dropped(
+            MessageCategory::Operation,
+            start.saturating_add(MassaTime::from_millis(1000))
+        ));
+        // each channel has its own window
+        assert!(counters.record_dropped(MessageCategory::Endorsement, start));
+        assert!(counters.record_dropped(
+            MessageCategory::Operation,
+            start.saturating_add(MassaTime::from_millis(60_000))
+        ));
+        assert_eq!(counters.dropped(MessageCategory::Operation), 3);
+        assert_eq!(counters.dropped(MessageCategory::Endorsement), 1);
+    }
+}
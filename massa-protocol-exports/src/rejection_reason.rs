@@ -0,0 +1,25 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+
+/// Reason why consensus rejected a block header
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// header already known
+    AlreadyKnown,
+    /// parents not consistent with the graph
+    InvalidParents,
+    /// slot too old or too far in the future
+    InvalidSlot,
+    /// header invalid for another reason
+    Invalid,
+}
+
+impl RejectionReason {
+    /// Whether the peers that sent us the header did nothing wrong
+    pub fn is_benign(&self) -> bool {
+        matches!(self, RejectionReason::AlreadyKnown)
+    }
+}
@@ -1,12 +1,15 @@
 // Copyright (c) 2022 MASSA LABS <info@massa.net>
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::{IpAddr, SocketAddr},
     path::PathBuf,
+    sync::Arc,
 };
 
-use massa_models::version::Version;
+use crate::{BanPolicy, BanReason, ConfigError, DefaultBanPolicy, PeerId, ProtocolError};
+use massa_hash::Hash;
+use massa_models::{amount::Amount, version::Version};
 use massa_time::MassaTime;
 use peernet::transports::TransportType;
 use serde::Deserialize;
@@ -19,6 +22,67 @@ pub struct PeerCategoryInfo {
     pub max_in_connections_per_ip: usize,
 }
 
+/// Categories of the messages exchanged with the peers
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageCategory {
+    /// block headers, infos and asks
+    Block,
+    /// endorsements and their announcements
+    Endorsement,
+    /// operations and their announcements
+    Operation,
+    /// peer lists and connection announcements
+    PeerManagement,
+}
+
+impl MessageCategory {
+    pub const ALL: [MessageCategory; 4] = [
+        MessageCategory::Block,
+        MessageCategory::Endorsement,
+        MessageCategory::Operation,
+        MessageCategory::PeerManagement,
+    ];
+}
+
+/// Compression applied to the messages sent to the peers supporting it
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum CompressionMode {
+    /// messages are sent uncompressed
+    Off,
+    /// messages of at least `min_size` bytes are compressed with zstd at the given `level`
+    Zstd {
+        /// zstd compression level
+        level: i32,
+        /// minimum size, in bytes, of a serialized message for it to be compressed
+        min_size: usize,
+    },
+}
+
+/// Treatment of the first non-critical protocol offense of a peer
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FirstOffensePolicy {
+    /// the peer is banned on its first offense
+    #[default]
+    BanImmediately,
+    /// the peer is only disconnected on its first offense, and banned if it offends again within `first_offense_window`
+    DisconnectThenBan,
+}
+
+/// Treatment of the block headers that consensus can't take because it is shutting down or overloaded
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusUnavailablePolicy {
+    /// the headers are dropped, consensus asks for their blocks again if it needs them
+    #[default]
+    Drop,
+    /// the headers are kept in a buffer of `consensus_retry_buffer_size` headers and registered again
+    /// every `consensus_retry_interval`
+    Retry,
+}
+
 /// Dynamic protocol configuration mix in static settings and constants configurations.
 #[derive(Debug, Deserialize, Clone)]
 pub struct ProtocolConfig {
@@ -26,22 +90,78 @@ pub struct ProtocolConfig {
     pub keypair_file: PathBuf,
     /// listeners from where we can receive messages
     pub listeners: HashMap<SocketAddr, TransportType>,
-    /// initial peers path
-    pub initial_peers: PathBuf,
+    /// paths of the initial peers files, merged. The missing ones are skipped as long as one of them can be read
+    pub initial_peers: Vec<PathBuf>,
+    /// initial peers given with one of their listeners, merged with the ones of the files
+    pub inline_initial_peers: Vec<(PeerId, SocketAddr)>,
+    /// `host:port` DNS names resolving to the addresses of nodes to bootstrap our peer list from
+    pub dns_seeds: Vec<String>,
+    /// interval between two resolutions of the DNS seeds
+    pub dns_seeds_refresh_interval: MassaTime,
     /// after `ask_block_timeout` milliseconds we try to ask a block to another node
     pub ask_block_timeout: MassaTime,
+    /// number of asks for a block that may time out before we stop asking it until another node announces it (0 for no limit)
+    pub max_block_ask_retries: usize,
+    /// number of nodes that announced a block we ask it to at the same time, the first answer wins
+    pub block_ask_fanout: usize,
+    /// maximum number of blocks asked to nodes at the same time, the other wanted blocks wait for a free slot (0 for no limit)
+    pub max_concurrent_block_downloads: usize,
+    /// upper bounds in milliseconds of the buckets of the block download latency histogram
+    pub block_download_latency_buckets: Vec<MassaTime>,
+    /// headers received within this window are registered in consensus together, 0 to register each header on arrival
+    pub block_header_batch_window: MassaTime,
+    /// treatment of the headers that consensus can't take
+    pub consensus_unavailable_policy: ConsensusUnavailablePolicy,
+    /// max number of headers kept for consensus with the `Retry` policy, the oldest ones are dropped beyond it
+    pub consensus_retry_buffer_size: usize,
+    /// interval between two registrations of the headers kept for consensus with the `Retry` policy
+    pub consensus_retry_interval: MassaTime,
+    /// max number of blocks wanted by consensus that are tracked, bigger wishlists are rejected
+    pub max_wishlist_size: usize,
+    /// window over which the asks to a node for the blocks it announced are counted to detect data withholding
+    pub withholding_window: MassaTime,
+    /// number of asks to a node within the window before its share of unanswered asks is checked
+    pub withholding_min_asks: u32,
+    /// share in percents of unanswered asks for blocks it announced above which a node is penalized (0 to disable)
+    pub withholding_max_percent: u32,
+    /// only ban the peers that relayed an attack block more than once or after we detected the attack, the other ones are penalized
+    pub attack_relay_grace: bool,
+    /// number of slots after the current one in which the headers received from the peers may be, 0 to disable the check
+    pub max_future_slots: u64,
+    /// clock skew tolerated when computing the current slot for `max_future_slots`
+    pub future_slot_tolerance: MassaTime,
+    /// number of slots before the latest final slot of their thread under which the headers received from the peers are dropped, 0 to disable the check
+    pub max_past_slots: u64,
+    /// window after the receipt of a header in which the same header sent again by the same peer is dropped, 0 to disable
+    pub duplicate_header_window: MassaTime,
+    /// number of times a peer may send a header again within `duplicate_header_window` before losing reputation
+    pub max_duplicate_headers: u32,
+    /// number of blocks whose first header receipt time is kept, 0 to disable
+    pub max_first_header_times: usize,
+    /// time during which the first header receipt time of a block is kept
+    pub first_header_time_retention: MassaTime,
     /// Max known blocks we keep during their propagation
     pub max_blocks_kept_for_propagation: usize,
     /// Time during which a block is expected to propagate
     pub max_block_propagation_time: MassaTime,
     /// Block propagation tick interval, useful for propagating blocks quickly to newly connected peers.
     pub block_propagation_tick: MassaTime,
+    /// relay the blocks as their header and the ids of their operations, so that the peers rebuild them from their mempool
+    pub compact_blocks_enabled: bool,
     /// max known blocks of current nodes we keep in memory
     pub max_known_blocks_size: usize,
     /// max known blocks of foreign nodes we keep in memory (by node)
     pub max_node_known_blocks_size: usize,
     /// max wanted blocks per node kept in memory
     pub max_node_wanted_blocks_size: usize,
+    /// max number of blocks whose operation ids we keep to answer repeated requests of peers
+    pub max_served_op_ids_cache_size: usize,
+    /// time during which cached operation ids of a block are used to answer peers
+    pub served_op_ids_cache_ttl: MassaTime,
+    /// max number of operation ids in a block info reply frame, the longer operation lists of a block are sent in several frames. 0 to send them in one frame
+    pub block_info_chunk_size: usize,
+    /// number of threads answering the block data requests of the peers, 0 to answer them from the block retrieval thread
+    pub block_serve_concurrency: usize,
     /// max known operations current node kept in memory
     pub max_known_ops_size: usize,
     /// max known operations of foreign nodes we keep in memory (by node)
@@ -50,6 +170,8 @@ pub struct ProtocolConfig {
     pub max_known_endorsements_size: usize,
     /// max known endorsements of foreign nodes we keep in memory (by node)
     pub max_node_known_endorsements_size: usize,
+    /// time after which an endorsement known by our node is processed again when received
+    pub max_known_endorsements_age: MassaTime,
     /// we ask for the same block `max_simultaneous_ask_blocks_per_node` times at the same time
     pub max_simultaneous_ask_blocks_per_node: usize,
     /// Max wait time for sending a Network or Node event.
@@ -62,15 +184,34 @@ pub struct ProtocolConfig {
     pub operation_announcement_buffer_capacity: usize,
     /// Start processing batches in the buffer each `operation_batch_proc_period` in millisecond
     pub operation_batch_proc_period: MassaTime,
+    /// Time during which the operations asked by a peer are gathered before being sent in one message, 0 to send them immediately
+    pub operation_batch_window: MassaTime,
+    /// Number of gathered operations for which a batch is sent without waiting for the end of `operation_batch_window`
+    pub operation_batch_max_size: usize,
     /// Maximum number of asked operations in the memory buffer.
     pub asked_operations_buffer_capacity: usize,
     /// Interval at which operations are announced in batches.
     pub operation_announcement_interval: MassaTime,
+    /// operations with a lower fee are not relayed to other peers
+    pub min_propagation_fee: Amount,
+    /// stop asking for, checking and relaying the operations announced or sent by the peers while the operation pool is full
+    pub pool_backpressure_enabled: bool,
+    /// number of random peers each operation is announced to, 0 to announce it to all peers
+    pub operation_propagation_fanout: usize,
+    /// seed of the random peer selections, so that tests can reproduce them. `None` seeds them from the OS entropy
+    pub rng_seed: Option<u64>,
+    /// number of connected peers asked for the ids of all the operations of their pool, to fill ours when the node starts, 0 to disable
+    pub mempool_sync_peers: usize,
+    /// the node validates what it receives but relays nothing and only asks the peers for the wishlisted blocks
+    pub passive_mode: bool,
+    /// number of threads deserializing the received operation messages and checking their signatures, the messages of a peer stay in order
+    pub handler_worker_threads: usize,
     /// Maximum time we keep an operation in the storage
     pub max_operation_storage_time: MassaTime,
     /// Maximum of operations sent in one message.
     pub max_operations_per_message: u64,
     /// Maximum of operations sent in one block.
+    /// This is also the hard ceiling of the operation id lists received for a block.
     pub max_operations_per_block: u32,
     /// Maximum size in bytes of all serialized operations size in a block
     pub max_serialized_operations_size_per_block: usize,
@@ -86,8 +227,16 @@ pub struct ProtocolConfig {
     pub max_ops_kept_for_propagation: usize,
     /// max time we propagate operations
     pub max_operations_propagation_time: MassaTime,
+    /// number of periods after their expire period during which the operations received from the peers are still propagated
+    pub expired_operations_tolerance: u64,
+    /// number of messages in a row carrying expired operations a peer may send before losing reputation
+    pub max_expired_operation_messages: u32,
     /// max time we propagate endorsements
     pub max_endorsements_propagation_time: MassaTime,
+    /// announce the ids of the endorsements to propagate, and let the peers ask for the ones they miss, instead of sending them in full
+    pub endorsement_announce_enabled: bool,
+    /// time after which a peer that didn't send the endorsements we asked it is penalized
+    pub ask_endorsement_timeout: MassaTime,
     /// Max message size
     pub max_message_size: usize,
     /// number of thread tester
@@ -122,6 +271,9 @@ pub struct ProtocolConfig {
     pub endorsement_count: u32,
     /// running threads count
     pub thread_count: u8,
+    /// identifies the genesis of our network, exchanged in the handshake with `thread_count`:
+    /// the peers announcing another network are disconnected
+    pub genesis_hash: Hash,
     /// Maximum size of an value user datastore
     pub max_size_value_datastore: u64,
     /// Maximum size of a function name
@@ -148,20 +300,85 @@ pub struct ProtocolConfig {
     pub try_connection_timer: MassaTime,
     /// try connection timer same peer
     pub try_connection_timer_same_peer: MassaTime,
-    /// periodically unban every peer
+    /// delay before trying again a peer after a failed connection, doubled at each new failure
+    pub connect_backoff_base: MassaTime,
+    /// maximum delay between two connection attempts to a failing peer
+    pub connect_backoff_max: MassaTime,
+    /// ban duration for the reasons without an entry in `ban_durations`
     pub unban_everyone_timer: MassaTime,
+    /// ban duration for each ban reason
+    pub ban_durations: HashMap<BanReason, MassaTime>,
+    /// period without offense after which the offense count of a peer is decremented
+    pub offense_decay_period: MassaTime,
+    /// file where the banned peers are persisted across restarts
+    pub ban_list_path: Option<PathBuf>,
+    /// also ban the IP address of banned peers
+    pub ip_ban_enabled: bool,
+    /// reputation below which a peer committing minor offenses is banned
+    pub reputation_ban_threshold: i32,
+    /// time after which a quarantined peer that committed no further offense is trusted again
+    pub quarantine_duration: MassaTime,
+    /// whether a peer is banned or only disconnected on its first non-critical offense
+    pub first_offense_policy: FirstOffensePolicy,
+    /// time during which a peer disconnected for a first offense is banned if it offends again
+    pub first_offense_window: MassaTime,
+    /// number of peer events kept for each subscriber before the oldest ones are dropped
+    pub peer_events_channel_capacity: usize,
+    /// peers that are never banned nor quarantined, e.g. the other nodes of an operator's cluster
+    pub ban_whitelist: HashSet<PeerId>,
+    /// decides how the peers are sanctioned for their offenses, not read from the settings file
+    #[serde(skip, default = "default_ban_policy")]
+    pub ban_policy: Arc<dyn BanPolicy>,
+    /// maximum number of block messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_block_messages_per_sec: u64,
+    /// maximum number of operation messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_operation_messages_per_sec: u64,
+    /// maximum number of endorsement messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_endorsement_messages_per_sec: u64,
+    /// maximum number of peer management messages per second accepted from a peer, the excess is dropped and a peer sending twice as many is banned (0 for no limit)
+    pub max_peer_management_messages_per_sec: u64,
+    /// maximum size in bytes of the messages of each category, a peer sending a bigger one is banned (`max_message_size` for the missing categories)
+    pub max_message_size_by_category: HashMap<MessageCategory, usize>,
+    /// max number of messages of a peer waiting in the channel of a handler, the reads of the peer pause beyond it
+    /// and it is disconnected if they aren't processed within `message_timeout` (0 for no limit)
+    pub per_peer_inbound_queue_depth: usize,
+    /// maximum number of banned peers kept in memory, the oldest bans are forgotten beyond it (0 for no limit)
+    pub max_banned_peers: usize,
+    /// maximum number of banned IP addresses and subnets kept in memory, the oldest bans are forgotten beyond it (0 for no limit)
+    pub max_banned_subnets: usize,
     /// Max in connections
     pub max_in_connections: usize,
+    /// max number of live inbound connections from a single IP address, loopback excluded (0 for no limit)
+    pub max_connections_per_ip: usize,
+    /// max number of live connections, inbound and outbound (0 for no limit). Once reached, the node stops dialing,
+    /// and an inbound peer is only accepted in place of a connected inbound peer with a lower reputation
+    pub max_total_connections: usize,
+    /// number of outbound connections the node dials known peers to keep, over all the peer categories (0 for only the targets of the categories)
+    pub target_out_connections: usize,
+    /// dial the peers of the subnets we aren't connected to first, to resist eclipse attacks
+    pub subnet_diversity_enabled: bool,
+    /// peers always redialed when not connected, on top of `target_out_connections` and the category targets
+    pub outbound_only_peers: Vec<(PeerId, SocketAddr)>,
     /// Timeout connection
     pub timeout_connection: MassaTime,
+    /// max duration of a handshake, the connection is closed if it isn't finished in time
+    pub handshake_timeout: MassaTime,
+    /// a connection receiving nothing for this long gets a ping (0 to disable the keepalive)
+    pub idle_ping_interval: MassaTime,
+    /// a pinged peer sending nothing back for this long is disconnected
+    pub ping_timeout: MassaTime,
     /// Timeout message
     pub message_timeout: MassaTime,
+    /// max duration of the connection draining on shutdown, the connections are closed abruptly if 0
+    pub shutdown_timeout: MassaTime,
     /// Timeout for the tester operations
     pub tester_timeout: MassaTime,
     /// Number of bytes per second that can be read/write in a connection (should be a 10 multiplier)
     pub read_write_limit_bytes_per_second: u128,
     /// Optional routable ip
     pub routable_ip: Option<IpAddr>,
+    /// connect to and test the IPv6 addresses of the peers, only their IPv4 addresses are used if false
+    pub enable_ipv6: bool,
     /// debug prints
     pub debug: bool,
     /// Peers categories infos
@@ -170,8 +387,189 @@ pub struct ProtocolConfig {
     pub default_category_info: PeerCategoryInfo,
     /// Version
     pub version: Version,
+    /// version of the message format we speak, exchanged in the handshake
+    pub protocol_version: u32,
+    /// peers speaking an older message format are disconnected after the handshake,
+    /// the ones not advertising any version are considered at version 0
+    pub min_peer_version: u32,
     /// Cooldown before testing again an old peer
     pub test_oldest_peer_cooldown: MassaTime,
+    /// time after which the known peers we didn't hear of are forgotten, unless connected, banned or quarantined (0 to keep them forever)
+    pub peer_expiry: MassaTime,
     /// Rate limit to apply on the data stream
     pub rate_limit: u64,
+    /// Outbound bandwidth allowed towards each peer in bytes per second, no limit if None
+    pub per_peer_bandwidth_limit: Option<u64>,
+    /// Number of bytes that can be sent to a peer at once before the bandwidth limit applies
+    pub per_peer_bandwidth_burst: u64,
+    /// Compression of the messages sent to the peers that advertise its support in the handshake
+    pub compression: CompressionMode,
+    /// checksum the frames sent to the peers supporting it, so that the corrupted ones are dropped instead of failing to deserialize
+    pub frame_checksum: bool,
+    /// number of corrupted frames a peer may send within `corrupted_frames_window` before being disconnected, no limit if 0
+    pub max_corrupted_frames: u64,
+    /// window over which the corrupted frames of a peer are counted
+    pub corrupted_frames_window: MassaTime,
+}
+
+fn default_ban_policy() -> Arc<dyn BanPolicy> {
+    Arc::new(DefaultBanPolicy)
+}
+
+impl ProtocolConfig {
+    /// Ban duration of a peer banned for the given reason
+    pub fn get_ban_duration(&self, reason: &BanReason) -> MassaTime {
+        self.ban_durations
+            .get(reason)
+            .copied()
+            .unwrap_or(self.unban_everyone_timer)
+    }
+
+    /// Check the settings that would make the worker panic or misbehave, before starting it
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.thread_count == 0 {
+            return Err(ConfigError::ZeroThreadCount);
+        }
+        // the missing initial peers files are skipped, unless none of them can be read
+        let mut unreadable_initial_peers = None;
+        for path in &self.initial_peers {
+            match std::fs::File::open(path) {
+                Ok(_) => {
+                    unreadable_initial_peers = None;
+                    break;
+                }
+                Err(error) => {
+                    unreadable_initial_peers.get_or_insert((path, error));
+                }
+            }
+        }
+        if let Some((path, error)) = unreadable_initial_peers {
+            return Err(ConfigError::UnreadableInitialPeers {
+                path: path.clone(),
+                error,
+            });
+        }
+        // periods of the tickers of the worker threads
+        for (name, timer) in [
+            ("t0", self.t0),
+            ("ask_block_timeout", self.ask_block_timeout),
+            ("ask_endorsement_timeout", self.ask_endorsement_timeout),
+            ("block_propagation_tick", self.block_propagation_tick),
+            (
+                "operation_batch_proc_period",
+                self.operation_batch_proc_period,
+            ),
+            ("served_op_ids_cache_ttl", self.served_op_ids_cache_ttl),
+            ("try_connection_timer", self.try_connection_timer),
+            ("unban_everyone_timer", self.unban_everyone_timer),
+        ] {
+            if timer.as_millis() == 0 {
+                return Err(ConfigError::ZeroTimer(name));
+            }
+        }
+        if self.consensus_unavailable_policy == ConsensusUnavailablePolicy::Retry
+            && self.consensus_retry_interval.as_millis() == 0
+        {
+            return Err(ConfigError::ZeroTimer("consensus_retry_interval"));
+        }
+        // a zero ping timeout would close every idle connection as soon as it is pinged
+        if self.idle_ping_interval.as_millis() > 0 && self.ping_timeout.as_millis() == 0 {
+            return Err(ConfigError::ZeroTimer("ping_timeout"));
+        }
+        for (shorter, shorter_timer, longer, longer_timer) in [
+            (
+                "connect_backoff_base",
+                self.connect_backoff_base,
+                "connect_backoff_max",
+                self.connect_backoff_max,
+            ),
+            (
+                "block_propagation_tick",
+                self.block_propagation_tick,
+                "max_block_propagation_time",
+                self.max_block_propagation_time,
+            ),
+            (
+                "operation_announcement_interval",
+                self.operation_announcement_interval,
+                "max_operations_propagation_time",
+                self.max_operations_propagation_time,
+            ),
+        ] {
+            if shorter_timer > longer_timer {
+                return Err(ConfigError::TimerOrder { shorter, longer });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Partial update of the settings that can be changed while the node runs,
+/// applied by `ProtocolController::update_config`. The settings left to `None` are kept.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ProtocolConfigUpdate {
+    pub unban_everyone_timer: Option<MassaTime>,
+    pub ban_durations: Option<HashMap<BanReason, MassaTime>>,
+    pub ban_whitelist: Option<HashSet<PeerId>>,
+    pub max_block_messages_per_sec: Option<u64>,
+    pub max_operation_messages_per_sec: Option<u64>,
+    pub max_endorsement_messages_per_sec: Option<u64>,
+    pub max_peer_management_messages_per_sec: Option<u64>,
+    pub block_ask_fanout: Option<usize>,
+    pub operation_propagation_fanout: Option<usize>,
+    /// the other settings found in a deserialized update, they can't be changed while the node runs
+    #[serde(flatten)]
+    pub fixed_settings: HashMap<String, serde_json::Value>,
+}
+
+impl ProtocolConfigUpdate {
+    /// Check that the update can be applied while the node runs
+    pub fn check(&self) -> Result<(), ProtocolError> {
+        if let Some(name) = self.fixed_settings.keys().min() {
+            return Err(ProtocolError::ConfigUpdateError(format!(
+                "{} can't be changed while the node runs",
+                name
+            )));
+        }
+        if self
+            .unban_everyone_timer
+            .is_some_and(|timer| timer.as_millis() == 0)
+        {
+            return Err(ProtocolError::ConfigUpdateError(
+                "unban_everyone_timer must not be zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Change the settings of `config` that are set in the update
+    pub fn apply(&self, config: &mut ProtocolConfig) {
+        if let Some(unban_everyone_timer) = self.unban_everyone_timer {
+            config.unban_everyone_timer = unban_everyone_timer;
+        }
+        if let Some(ban_durations) = &self.ban_durations {
+            config.ban_durations = ban_durations.clone();
+        }
+        if let Some(ban_whitelist) = &self.ban_whitelist {
+            config.ban_whitelist = ban_whitelist.clone();
+        }
+        if let Some(limit) = self.max_block_messages_per_sec {
+            config.max_block_messages_per_sec = limit;
+        }
+        if let Some(limit) = self.max_operation_messages_per_sec {
+            config.max_operation_messages_per_sec = limit;
+        }
+        if let Some(limit) = self.max_endorsement_messages_per_sec {
+            config.max_endorsement_messages_per_sec = limit;
+        }
+        if let Some(limit) = self.max_peer_management_messages_per_sec {
+            config.max_peer_management_messages_per_sec = limit;
+        }
+        if let Some(fanout) = self.block_ask_fanout {
+            config.block_ask_fanout = fanout;
+        }
+        if let Some(fanout) = self.operation_propagation_fanout {
+            config.operation_propagation_fanout = fanout;
+        }
+    }
 }
@@ -1,7 +1,17 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
-use crate::{settings::PeerCategoryInfo, ProtocolConfig};
-use massa_models::config::{ENDORSEMENT_COUNT, MAX_MESSAGE_SIZE};
+use crate::{
+    settings::{CompressionMode, ConsensusUnavailablePolicy, FirstOffensePolicy, PeerCategoryInfo},
+    DefaultBanPolicy, ProtocolConfig,
+};
+use massa_hash::Hash;
+use massa_models::{
+    amount::Amount,
+    config::{ENDORSEMENT_COUNT, MAX_MESSAGE_SIZE},
+};
 use massa_time::MassaTime;
 use tempfile::NamedTempFile;
 
@@ -15,27 +25,66 @@ impl Default for ProtocolConfig {
                 .path()
                 .to_path_buf(),
             ask_block_timeout: MassaTime::from_millis(10000),
+            max_block_ask_retries: 20,
+            block_ask_fanout: 1,
+            max_concurrent_block_downloads: 0,
+            block_download_latency_buckets: [100, 250, 500, 1000, 2500, 5000, 10000]
+                .into_iter()
+                .map(MassaTime::from_millis)
+                .collect(),
+            block_header_batch_window: MassaTime::from_millis(0),
+            consensus_unavailable_policy: ConsensusUnavailablePolicy::Drop,
+            consensus_retry_buffer_size: 1000,
+            consensus_retry_interval: MassaTime::from_millis(1000),
+            max_wishlist_size: 10000,
+            withholding_window: MassaTime::from_millis(60000),
+            withholding_min_asks: 5,
+            withholding_max_percent: 0,
+            attack_relay_grace: false,
+            max_future_slots: 0,
+            future_slot_tolerance: MassaTime::from_millis(2000),
+            max_past_slots: 0,
+            duplicate_header_window: MassaTime::from_millis(0),
+            max_duplicate_headers: 3,
+            max_first_header_times: 100,
+            first_header_time_retention: MassaTime::from_millis(60000),
             max_blocks_kept_for_propagation: 300,
             max_block_propagation_time: MassaTime::from_millis(40000),
             block_propagation_tick: MassaTime::from_millis(1000),
+            compact_blocks_enabled: false,
             max_known_blocks_size: 100,
             max_node_known_blocks_size: 100,
             max_node_wanted_blocks_size: 100,
+            max_served_op_ids_cache_size: 100,
+            served_op_ids_cache_ttl: MassaTime::from_millis(2000),
+            block_info_chunk_size: 0,
+            block_serve_concurrency: 0,
             max_simultaneous_ask_blocks_per_node: 10,
             max_send_wait: MassaTime::from_millis(100),
             max_known_ops_size: 1000,
             max_node_known_ops_size: 1000,
             max_known_endorsements_size: 1000,
             max_node_known_endorsements_size: 1000,
+            max_known_endorsements_age: MassaTime::from_millis(60000),
             operation_batch_buffer_capacity: 1000,
             operation_announcement_buffer_capacity: 1000,
             max_operation_storage_time: MassaTime::from_millis(60000),
             operation_batch_proc_period: MassaTime::from_millis(200),
+            operation_batch_window: MassaTime::from_millis(0),
+            operation_batch_max_size: 1024,
             asked_operations_buffer_capacity: 10000,
             operation_announcement_interval: MassaTime::from_millis(150),
+            min_propagation_fee: Amount::zero(),
+            pool_backpressure_enabled: false,
+            operation_propagation_fanout: 0,
+            rng_seed: None,
+            mempool_sync_peers: 0,
+            passive_mode: false,
+            handler_worker_threads: 1,
             max_operations_per_message: 1024,
             max_operations_per_block: 5000,
             thread_count: 32,
+            genesis_hash: Hash::compute_from(b"genesis"),
             max_serialized_operations_size_per_block: 1024,
             controller_channel_size: 1024,
             event_channel_size: 1024,
@@ -43,11 +92,18 @@ impl Default for ProtocolConfig {
             t0: MassaTime::from_millis(16000),
             max_ops_kept_for_propagation: 10000,
             max_operations_propagation_time: MassaTime::from_millis(30000),
+            expired_operations_tolerance: 2,
+            max_expired_operation_messages: 3,
             max_endorsements_propagation_time: MassaTime::from_millis(60000),
-            initial_peers: NamedTempFile::new()
+            endorsement_announce_enabled: false,
+            ask_endorsement_timeout: MassaTime::from_millis(1000),
+            initial_peers: vec![NamedTempFile::new()
                 .expect("cannot create temp file")
                 .path()
-                .to_path_buf(),
+                .to_path_buf()],
+            inline_initial_peers: Vec::new(),
+            dns_seeds: Vec::new(),
+            dns_seeds_refresh_interval: MassaTime::from_millis(60 * 60 * 1000),
             listeners: HashMap::default(),
             thread_tester_count: 2,
             max_size_channel_commands_connectivity: 1000,
@@ -76,14 +132,43 @@ impl Default for ProtocolConfig {
             max_size_listeners_per_peer: 100,
             max_size_peers_announcement: 100,
             message_timeout: MassaTime::from_millis(10000),
+            shutdown_timeout: MassaTime::from_millis(0),
             tester_timeout: MassaTime::from_millis(500),
             last_start_period: 0,
             read_write_limit_bytes_per_second: 1024 * 1000,
             timeout_connection: MassaTime::from_millis(1000),
+            handshake_timeout: MassaTime::from_millis(10000),
+            idle_ping_interval: MassaTime::from_millis(60000),
+            ping_timeout: MassaTime::from_millis(10000),
             try_connection_timer: MassaTime::from_millis(5000),
             unban_everyone_timer: MassaTime::from_millis(ONE_DAY_MS),
+            ban_durations: HashMap::default(),
+            offense_decay_period: MassaTime::from_millis(60 * 60 * 1000),
+            ban_list_path: None,
+            ip_ban_enabled: false,
+            reputation_ban_threshold: -100,
+            quarantine_duration: MassaTime::from_millis(10 * 60 * 1000),
+            first_offense_policy: FirstOffensePolicy::BanImmediately,
+            first_offense_window: MassaTime::from_millis(60 * 60 * 1000),
+            peer_events_channel_capacity: 1000,
+            ban_whitelist: HashSet::default(),
+            ban_policy: Arc::new(DefaultBanPolicy),
+            max_block_messages_per_sec: 500,
+            max_operation_messages_per_sec: 1000,
+            max_endorsement_messages_per_sec: 1000,
+            max_peer_management_messages_per_sec: 50,
+            max_message_size_by_category: HashMap::default(),
+            per_peer_inbound_queue_depth: 0,
+            max_banned_peers: 10000,
+            max_banned_subnets: 10000,
             routable_ip: None,
+            enable_ipv6: true,
             max_in_connections: 10,
+            max_connections_per_ip: 0,
+            max_total_connections: 0,
+            target_out_connections: 0,
+            subnet_diversity_enabled: false,
+            outbound_only_peers: Vec::new(),
             debug: true,
             peers_categories: HashMap::default(),
             default_category_info: PeerCategoryInfo {
@@ -93,9 +178,20 @@ impl Default for ProtocolConfig {
                 max_in_connections_per_ip: 0,
             },
             version: "TEST.23.2".parse().unwrap(),
+            protocol_version: 1,
+            min_peer_version: 0,
             try_connection_timer_same_peer: MassaTime::from_millis(1000),
+            connect_backoff_base: MassaTime::from_millis(1000),
+            connect_backoff_max: MassaTime::from_millis(60000),
             test_oldest_peer_cooldown: MassaTime::from_millis(720000),
+            peer_expiry: MassaTime::from_millis(0),
             rate_limit: 1024 * 1024 * 2,
+            per_peer_bandwidth_limit: None,
+            per_peer_bandwidth_burst: 1024 * 1024,
+            compression: CompressionMode::Off,
+            frame_checksum: false,
+            max_corrupted_frames: 10,
+            corrupted_frames_window: MassaTime::from_millis(60_000),
         }
     }
 }
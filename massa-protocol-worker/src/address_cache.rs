@@ -0,0 +1,119 @@
+//! Cache of the addresses derived from the public keys of the creators.
+//!
+//! Deserializing an operation or an endorsement derives the address of its creator by hashing
+//! its public key. The operations and endorsements received mostly come from a small set of
+//! creators, so the recently derived addresses are kept and reused by the deserializers of the
+//! operation workers and of the endorsement retrieval thread.
+
+use massa_models::{address::Address, secure_share::AddressDerivation};
+use massa_signature::PublicKey;
+use parking_lot::Mutex;
+use schnellru::{ByLength, LruMap};
+
+/// Maximum number of creators whose address is kept
+pub(crate) const MAX_CACHED_ADDRESSES: u32 = 4096;
+
+pub(crate) struct AddressCache {
+    /// addresses of the recently seen creators, the least recently used one is dropped when full
+    addresses: Mutex<LruMap<PublicKey, Address>>,
+    /// number of addresses derived, to check the cache hits in the tests
+    #[cfg(test)]
+    derivations: std::sync::atomic::AtomicUsize,
+}
+
+impl AddressCache {
+    pub(crate) fn new(max_addresses: u32) -> Self {
+        AddressCache {
+            addresses: Mutex::new(LruMap::new(ByLength::new(max_addresses))),
+            #[cfg(test)]
+            derivations: Default::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn derivations(&self) -> usize {
+        self.derivations.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl AddressDerivation for AddressCache {
+    fn derive_address(&self, public_key: &PublicKey) -> Address {
+        if let Some(address) = self.addresses.lock().get(public_key) {
+            return *address;
+        }
+        // hashed without holding the lock, the other threads keep reading the cache meanwhile
+        let address = Address::from_public_key(public_key);
+        #[cfg(test)]
+        self.derivations
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.addresses.lock().insert(*public_key, address);
+        address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use massa_models::{
+        address::Address,
+        amount::Amount,
+        operation::{
+            Operation, OperationSerializer, OperationType, OperationsDeserializer,
+            OperationsSerializer, SecureShareOperation,
+        },
+        secure_share::{AddressDerivation, SecureShareContent},
+    };
+    use massa_serialization::{DeserializeError, Deserializer, Serializer};
+    use massa_signature::KeyPair;
+
+    use super::AddressCache;
+
+    #[test]
+    fn test_operations_of_a_creator_derive_its_address_once() {
+        let creators: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate(0).unwrap()).collect();
+        let operations: Vec<SecureShareOperation> = (0..30)
+            .map(|index| {
+                let keypair = &creators[index % creators.len()];
+                let content = Operation {
+                    fee: Amount::zero(),
+                    op: OperationType::Transaction {
+                        recipient_address: Address::from_public_key(&keypair.get_public_key()),
+                        amount: Amount::default(),
+                    },
+                    expire_period: index as u64,
+                };
+                Operation::new_verifiable(content, OperationSerializer::new(), keypair).unwrap()
+            })
+            .collect();
+        let mut buffer = Vec::new();
+        OperationsSerializer::new()
+            .serialize(&operations, &mut buffer)
+            .unwrap();
+
+        let address_cache = Arc::new(AddressCache::new(2));
+        let deserializer = OperationsDeserializer::new(100, 1000, 1000, 1000, 10, 255, 1000)
+            .with_address_derivation(address_cache.clone());
+        let (_, deserialized) = deserializer
+            .deserialize::<DeserializeError>(&buffer)
+            .unwrap();
+        for (deserialized, operation) in deserialized.iter().zip(&operations) {
+            assert_eq!(
+                deserialized.content_creator_address,
+                operation.content_creator_address
+            );
+        }
+        // the creators take turns and the cache only holds two of them
+        assert_eq!(address_cache.derivations(), 30);
+
+        let address_cache = Arc::new(AddressCache::new(16));
+        let deserializer = OperationsDeserializer::new(100, 1000, 1000, 1000, 10, 255, 1000)
+            .with_address_derivation(address_cache.clone());
+        deserializer
+            .deserialize::<DeserializeError>(&buffer)
+            .unwrap();
+        assert_eq!(address_cache.derivations(), creators.len());
+        address_cache.derive_address(&creators[0].get_public_key());
+        assert_eq!(address_cache.derivations(), creators.len());
+    }
+}
@@ -0,0 +1,499 @@
+//! Per-peer limit of the outbound bandwidth.
+//!
+//! Each peer gets a token bucket holding up to `per_peer_bandwidth_burst` bytes and refilled at
+//! `per_peer_bandwidth_limit` bytes per second. A message takes its size from the bucket, which can
+//! go in debt: the message is then delayed until the bucket is back to zero, so that the large
+//! messages are paced instead of dropped. The delayed messages are sent by a `PacedSender`
+//! thread, the handlers sending them are never blocked.
+//! The limit protects our uplink, peers hitting it did nothing wrong and are never banned.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{unbounded, RecvTimeoutError, Sender};
+use massa_protocol_exports::{PeerId, ProtocolError};
+use parking_lot::Mutex;
+use tracing::{debug, warn};
+
+use crate::handlers::block_handler::SharedProtocolMetrics;
+
+/// Number of consecutive delayed messages after which a peer is reported as bandwidth limited
+const LIMITED_PEER_STREAK: u32 = 10;
+/// Interval between two cleanups of the buckets of idle peers
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+/// Number of delayed messages that may wait for a peer, the next ones are refused to their senders
+pub const MAX_WAITING_MESSAGES_PER_PEER: usize = 256;
+
+struct TokenBucket {
+    /// available bytes, negative while messages wait for the bucket to refill
+    tokens: f64,
+    updated_at: Instant,
+    /// number of consecutive messages delayed by the limit
+    delayed_streak: u32,
+}
+
+struct TokenBuckets {
+    buckets: HashMap<PeerId, TokenBucket>,
+    last_prune: Instant,
+}
+
+/// Bandwidth limiter shared by all the senders
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    /// bytes per second allowed towards each peer
+    rate: u64,
+    /// capacity of the bucket of each peer in bytes
+    burst: u64,
+    buckets: Arc<Mutex<TokenBuckets>>,
+    protocol_metrics: SharedProtocolMetrics,
+}
+
+impl BandwidthLimiter {
+    pub fn new(rate: u64, burst: u64, protocol_metrics: SharedProtocolMetrics) -> Self {
+        BandwidthLimiter {
+            rate: rate.max(1),
+            burst,
+            buckets: Arc::new(Mutex::new(TokenBuckets {
+                buckets: HashMap::new(),
+                last_prune: Instant::now(),
+            })),
+            protocol_metrics,
+        }
+    }
+
+    /// Reserve the bandwidth needed to send `size` bytes to a peer at time `now`,
+    /// returns how long the sending must be delayed
+    pub fn reserve_at(&self, peer_id: &PeerId, size: usize, now: Instant) -> Duration {
+        let rate = self.rate as f64;
+        let burst = self.burst as f64;
+        let mut no_longer_limited = Vec::new();
+        let mut buckets = self.buckets.lock();
+        if now.saturating_duration_since(buckets.last_prune) >= PRUNE_INTERVAL {
+            // a bucket that refilled completely behaves like a new one
+            buckets.buckets.retain(|id, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated_at);
+                let keep = bucket.tokens + elapsed.as_secs_f64() * rate < burst;
+                if !keep && bucket.delayed_streak >= LIMITED_PEER_STREAK {
+                    no_longer_limited.push(*id);
+                }
+                keep
+            });
+            buckets.last_prune = now;
+        }
+        let bucket = buckets
+            .buckets
+            .entry(*peer_id)
+            .or_insert_with(|| TokenBucket {
+                tokens: burst,
+                updated_at: now,
+                delayed_streak: 0,
+            });
+        if now > bucket.updated_at {
+            let elapsed = now.duration_since(bucket.updated_at);
+            bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst);
+            bucket.updated_at = now;
+        }
+        bucket.tokens -= size as f64;
+
+        let delay = if bucket.tokens >= 0.0 {
+            if bucket.delayed_streak >= LIMITED_PEER_STREAK {
+                no_longer_limited.push(*peer_id);
+            }
+            bucket.delayed_streak = 0;
+            Duration::ZERO
+        } else {
+            bucket.delayed_streak = bucket.delayed_streak.saturating_add(1);
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        };
+        let limited = bucket.delayed_streak == LIMITED_PEER_STREAK;
+        drop(buckets);
+
+        if delay.is_zero() && no_longer_limited.is_empty() {
+            return delay;
+        }
+        let mut protocol_metrics = self.protocol_metrics.write();
+        for id in &no_longer_limited {
+            protocol_metrics.set_bandwidth_limited(id, false);
+        }
+        if !delay.is_zero() {
+            protocol_metrics.record_bandwidth_delayed_send();
+        }
+        if limited {
+            debug!(
+                "Peer {} constantly hits the bandwidth limit of {} bytes per second",
+                peer_id, self.rate
+            );
+            protocol_metrics.set_bandwidth_limited(peer_id, true);
+        }
+        delay
+    }
+
+    /// Reserve the bandwidth needed to send `size` bytes to a peer now,
+    /// returns how long the sending must be delayed
+    pub fn reserve(&self, peer_id: &PeerId, size: usize) -> Duration {
+        self.reserve_at(peer_id, size, Instant::now())
+    }
+
+    /// Forget the bucket of a disconnected peer
+    pub fn remove_peer(&self, peer_id: &PeerId) {
+        if self.buckets.lock().buckets.remove(peer_id).is_some() {
+            self.protocol_metrics
+                .write()
+                .set_bandwidth_limited(peer_id, false);
+        }
+    }
+}
+
+/// Command handled by the thread of a `PacedSender`
+enum PacedCommand {
+    /// Send a serialized message to a peer at a given time, the generation of the peer queue it belongs to
+    /// and whether it is high priority
+    Send(Instant, PeerId, u64, Vec<u8>, bool),
+    /// Drop the messages waiting for a peer
+    RemovePeer(PeerId),
+}
+
+/// Messages waiting for a peer
+struct PeerQueue {
+    count: usize,
+    /// when the last one is sent
+    last_send_at: Instant,
+    /// tells the messages of this queue apart from those of a removed queue of the same peer
+    generation: u64,
+}
+
+#[derive(Default)]
+struct PeerQueues {
+    queues: HashMap<PeerId, PeerQueue>,
+    next_generation: u64,
+}
+
+/// Sends the messages delayed by the bandwidth limit from a thread, once their time has come.
+/// The messages to a peer stay in order: while some of them wait, the next ones wait behind them.
+#[derive(Clone)]
+pub struct PacedSender {
+    sender: Sender<PacedCommand>,
+    waiting: Arc<Mutex<PeerQueues>>,
+    /// number of messages that may wait for a peer, the next ones are refused
+    max_waiting: usize,
+}
+
+impl PacedSender {
+    /// Start the thread sending the delayed messages with `send`, keeping at most `max_waiting` messages
+    /// waiting for each peer. It stops once all the senders are dropped
+    pub fn new<F>(max_waiting: usize, send: F) -> Self
+    where
+        F: Fn(&PeerId, Vec<u8>, bool) + Send + 'static,
+    {
+        let (sender, receiver) = unbounded::<PacedCommand>();
+        let waiting: Arc<Mutex<PeerQueues>> = Default::default();
+        let thread_waiting = waiting.clone();
+        std::thread::Builder::new()
+            .name("protocol-paced-sender".to_string())
+            .spawn(move || {
+                // ordered by sending time then by arrival, so that the messages sent at the same time keep their order
+                let mut queue: BTreeMap<(Instant, u64), (PeerId, u64, Vec<u8>, bool)> =
+                    BTreeMap::new();
+                let mut next_seq = 0u64;
+                loop {
+                    let received = match queue.keys().next() {
+                        Some((send_at, _)) => receiver.recv_deadline(*send_at),
+                        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    };
+                    match received {
+                        Ok(PacedCommand::Send(
+                            send_at,
+                            peer_id,
+                            generation,
+                            data,
+                            high_priority,
+                        )) => {
+                            queue.insert(
+                                (send_at, next_seq),
+                                (peer_id, generation, data, high_priority),
+                            );
+                            next_seq += 1;
+                        }
+                        Ok(PacedCommand::RemovePeer(peer_id)) => {
+                            queue.retain(|_, (id, ..)| *id != peer_id);
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    let now = Instant::now();
+                    while let Some(entry) = queue.first_entry() {
+                        if entry.key().0 > now {
+                            break;
+                        }
+                        let (peer_id, generation, data, high_priority) = entry.remove();
+                        send(&peer_id, data, high_priority);
+                        // only forgotten once sent, so that the next messages of the peer don't overtake it
+                        let mut waiting = thread_waiting.lock();
+                        if let Some(peer_queue) = waiting
+                            .queues
+                            .get_mut(&peer_id)
+                            .filter(|peer_queue| peer_queue.generation == generation)
+                        {
+                            peer_queue.count -= 1;
+                            if peer_queue.count == 0 {
+                                waiting.queues.remove(&peer_id);
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("OS failed to start paced sender thread");
+        PacedSender {
+            sender,
+            waiting,
+            max_waiting,
+        }
+    }
+
+    /// Queue the serialized message `data` for `peer_id` if it must be delayed by `delay`
+    /// or if messages of the peer are already waiting, returns it back if it can be sent right away.
+    /// Fails if too many messages are already waiting for the peer.
+    pub fn delay(
+        &self,
+        peer_id: &PeerId,
+        data: Vec<u8>,
+        high_priority: bool,
+        delay: Duration,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        self.delay_at(peer_id, data, high_priority, delay, Instant::now())
+    }
+
+    /// Same as `delay`, at time `now`
+    pub fn delay_at(
+        &self,
+        peer_id: &PeerId,
+        data: Vec<u8>,
+        high_priority: bool,
+        delay: Duration,
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let mut waiting = self.waiting.lock();
+        let PeerQueues {
+            queues,
+            next_generation,
+        } = &mut *waiting;
+        let (waiting_count, last_send_at) = queues.get(peer_id).map_or((0, None), |peer_queue| {
+            (peer_queue.count, Some(peer_queue.last_send_at))
+        });
+        if delay.is_zero() && last_send_at.is_none() {
+            return Ok(Some(data));
+        }
+        if waiting_count >= self.max_waiting {
+            return Err(ProtocolError::SendError(format!(
+                "Too many messages waiting for the bandwidth of peer {}",
+                peer_id
+            )));
+        }
+        let send_at = last_send_at.map_or(now + delay, |last| last.max(now + delay));
+        let peer_queue = queues.entry(*peer_id).or_insert_with(|| {
+            *next_generation += 1;
+            PeerQueue {
+                count: 0,
+                last_send_at: send_at,
+                generation: *next_generation,
+            }
+        });
+        peer_queue.count += 1;
+        peer_queue.last_send_at = send_at;
+        // sent under the lock so that the messages of a peer reach the thread in order
+        let command = PacedCommand::Send(
+            send_at,
+            *peer_id,
+            peer_queue.generation,
+            data,
+            high_priority,
+        );
+        if self.sender.send(command).is_err() {
+            warn!(
+                "The paced sender stopped, dropping message to peer {}",
+                peer_id
+            );
+        }
+        Ok(None)
+    }
+
+    /// Drop the messages waiting for a disconnected peer
+    pub fn remove_peer(&self, peer_id: &PeerId) {
+        let mut waiting = self.waiting.lock();
+        if waiting.queues.remove(peer_id).is_some() {
+            // under the lock so that the messages of a new queue of the peer come after the removal
+            let _ = self.sender.send(PacedCommand::RemovePeer(*peer_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use massa_protocol_exports::{PeerId, ProtocolMetrics};
+    use massa_signature::KeyPair;
+    use parking_lot::RwLock;
+
+    use super::{BandwidthLimiter, PacedSender, LIMITED_PEER_STREAK};
+
+    #[test]
+    fn test_large_message_is_paced() {
+        let protocol_metrics = Arc::new(RwLock::new(ProtocolMetrics::new(vec![])));
+        let bandwidth_limiter = BandwidthLimiter::new(1_000, 500, protocol_metrics.clone());
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let other_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+
+        // the burst goes through right away
+        assert_eq!(
+            bandwidth_limiter.reserve_at(&peer_id, 500, now),
+            Duration::ZERO
+        );
+        // 2500 bytes at 1000 bytes per second once the burst is spent
+        assert_eq!(
+            bandwidth_limiter.reserve_at(&peer_id, 2_500, now),
+            Duration::from_millis(2_500)
+        );
+        // the next message waits for the previous one
+        assert_eq!(
+            bandwidth_limiter.reserve_at(&peer_id, 1_000, now + Duration::from_millis(1_500)),
+            Duration::from_millis(2_000)
+        );
+        // other peers have their own bucket
+        assert_eq!(
+            bandwidth_limiter.reserve_at(&other_peer_id, 500, now),
+            Duration::ZERO
+        );
+        // the bucket refills up to the burst
+        assert_eq!(
+            bandwidth_limiter.reserve_at(&peer_id, 500, now + Duration::from_secs(60)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            bandwidth_limiter.reserve_at(&peer_id, 250, now + Duration::from_secs(60)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(protocol_metrics.read().bandwidth_delayed_sends, 3);
+        assert!(protocol_metrics.read().bandwidth_limited_peers.is_empty());
+    }
+
+    #[test]
+    fn test_delayed_messages_are_sent_in_order_without_blocking() {
+        let (sent_sender, sent_receiver) = crossbeam::channel::unbounded();
+        let paced_sender = PacedSender::new(10, move |peer_id, data, _| {
+            sent_sender.send((*peer_id, data, Instant::now())).unwrap();
+        });
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let other_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+
+        assert_eq!(
+            paced_sender
+                .delay_at(&peer_id, vec![1], false, Duration::from_millis(200), now)
+                .unwrap(),
+            None
+        );
+        // not delayed but queued behind the previous message of the peer
+        assert_eq!(
+            paced_sender
+                .delay_at(&peer_id, vec![2], false, Duration::ZERO, now)
+                .unwrap(),
+            None
+        );
+        // the other peers are not held back
+        assert_eq!(
+            paced_sender
+                .delay_at(&other_peer_id, vec![3], false, Duration::ZERO, now)
+                .unwrap(),
+            Some(vec![3])
+        );
+
+        for expected in [vec![1], vec![2]] {
+            let (sent_to, data, sent_at) =
+                sent_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(sent_to, peer_id);
+            assert_eq!(data, expected);
+            assert!(sent_at >= now + Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_waiting_messages_are_bounded_and_dropped_on_disconnect() {
+        let (sent_sender, sent_receiver) = crossbeam::channel::unbounded();
+        let paced_sender = PacedSender::new(2, move |peer_id, data, _| {
+            sent_sender.send((*peer_id, data)).unwrap();
+        });
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let other_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+        let delay = Duration::from_millis(200);
+
+        for data in [vec![1], vec![2]] {
+            assert_eq!(
+                paced_sender
+                    .delay_at(&peer_id, data, false, delay, now)
+                    .unwrap(),
+                None
+            );
+        }
+        // the queue of the peer is full, not those of the other peers
+        assert!(paced_sender
+            .delay_at(&peer_id, vec![3], false, delay, now)
+            .is_err());
+        assert_eq!(
+            paced_sender
+                .delay_at(&other_peer_id, vec![4], false, delay, now)
+                .unwrap(),
+            None
+        );
+
+        // the messages waiting for a disconnected peer are dropped
+        paced_sender.remove_peer(&peer_id);
+        assert_eq!(
+            paced_sender
+                .delay_at(&peer_id, vec![5], false, Duration::ZERO, now)
+                .unwrap(),
+            Some(vec![5])
+        );
+        assert_eq!(
+            sent_receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (other_peer_id, vec![4])
+        );
+        assert!(sent_receiver
+            .recv_timeout(Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_constantly_limited_peer_in_metrics() {
+        let protocol_metrics = Arc::new(RwLock::new(ProtocolMetrics::new(vec![])));
+        let bandwidth_limiter = BandwidthLimiter::new(1_000, 0, protocol_metrics.clone());
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+
+        for _ in 0..LIMITED_PEER_STREAK {
+            assert!(!protocol_metrics
+                .read()
+                .bandwidth_limited_peers
+                .contains(&peer_id));
+            assert!(!bandwidth_limiter.reserve_at(&peer_id, 100, now).is_zero());
+        }
+        assert!(protocol_metrics
+            .read()
+            .bandwidth_limited_peers
+            .contains(&peer_id));
+
+        // the peer is not reported anymore once its messages go through
+        assert!(bandwidth_limiter
+            .reserve_at(&peer_id, 0, now + Duration::from_secs(60))
+            .is_zero());
+        assert!(protocol_metrics.read().bandwidth_limited_peers.is_empty());
+    }
+}
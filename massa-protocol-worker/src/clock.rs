@@ -0,0 +1,198 @@
+//! Source of time of the protocol timers.
+//!
+//! The timers of the protocol threads read the time and tick through a `ProtocolClock`. The node
+//! follows the system time, while the tests can use a manual clock that only moves when they
+//! advance it, so that the timers fire instantly and at the exact expected time.
+
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{at, tick, Receiver};
+use massa_time::MassaTime;
+
+#[cfg(test)]
+pub use manual::ManualClock;
+
+#[derive(Clone, Default)]
+pub enum ProtocolClock {
+    /// system time and real tickers
+    #[default]
+    System,
+    /// time moved by the tests
+    #[cfg(test)]
+    Manual(ManualClock),
+}
+
+impl ProtocolClock {
+    /// Current time
+    pub fn now(&self) -> MassaTime {
+        match self {
+            ProtocolClock::System => MassaTime::now(),
+            #[cfg(test)]
+            ProtocolClock::Manual(clock) => clock.now(),
+        }
+    }
+
+    /// Receiver of a message every `period`, as `crossbeam::channel::tick`
+    pub fn tick(&self, period: Duration) -> Receiver<Instant> {
+        match self {
+            ProtocolClock::System => tick(period),
+            #[cfg(test)]
+            ProtocolClock::Manual(clock) => clock.tick(period),
+        }
+    }
+
+    /// Receiver of a single message once the time reaches `deadline`, as `crossbeam::channel::at`
+    pub fn at(&self, deadline: MassaTime) -> Receiver<Instant> {
+        match self {
+            ProtocolClock::System => {
+                at(Instant::now() + deadline.saturating_sub(MassaTime::now()).to_duration())
+            }
+            #[cfg(test)]
+            ProtocolClock::Manual(clock) => clock.at(deadline),
+        }
+    }
+}
+
+#[cfg(test)]
+mod manual {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+    use massa_time::MassaTime;
+    use parking_lot::Mutex;
+
+    struct ManualTicker {
+        sender: Sender<Instant>,
+        /// the ticker fires once if `None`
+        period: Option<MassaTime>,
+        next: MassaTime,
+    }
+
+    struct ManualClockState {
+        now: MassaTime,
+        tickers: Vec<ManualTicker>,
+    }
+
+    /// Clock whose time only moves with `advance`, shared by all its clones
+    #[derive(Clone)]
+    pub struct ManualClock(Arc<Mutex<ManualClockState>>);
+
+    impl ManualClock {
+        pub fn new(start: MassaTime) -> Self {
+            ManualClock(Arc::new(Mutex::new(ManualClockState {
+                now: start,
+                tickers: Vec::new(),
+            })))
+        }
+
+        pub fn now(&self) -> MassaTime {
+            self.0.lock().now
+        }
+
+        pub(crate) fn tick(&self, period: Duration) -> Receiver<Instant> {
+            // like the crossbeam tickers, a late receiver gets a single message
+            let (sender, receiver) = bounded(1);
+            let period = MassaTime::from_millis((period.as_millis() as u64).max(1));
+            let mut state = self.0.lock();
+            let next = state.now.saturating_add(period);
+            state.tickers.push(ManualTicker {
+                sender,
+                period: Some(period),
+                next,
+            });
+            receiver
+        }
+
+        pub(crate) fn at(&self, deadline: MassaTime) -> Receiver<Instant> {
+            let (sender, receiver) = bounded(1);
+            let mut state = self.0.lock();
+            if deadline <= state.now {
+                // the deadline already passed: fire right away
+                let _ = sender.try_send(Instant::now());
+            } else {
+                state.tickers.push(ManualTicker {
+                    sender,
+                    period: None,
+                    next: deadline,
+                });
+            }
+            receiver
+        }
+
+        /// Move the time forward by `duration` and fire the tickers that are due
+        pub fn advance(&self, duration: MassaTime) {
+            let mut state = self.0.lock();
+            let now = state.now.saturating_add(duration);
+            state.now = now;
+            state.tickers.retain_mut(|ticker| {
+                if ticker.next > now {
+                    return true;
+                }
+                let Some(period) = ticker.period else {
+                    let _ = ticker.sender.try_send(Instant::now());
+                    return false;
+                };
+                while ticker.next <= now {
+                    ticker.next = ticker.next.saturating_add(period);
+                }
+                !matches!(
+                    ticker.sender.try_send(Instant::now()),
+                    Err(TrySendError::Disconnected(_))
+                )
+            });
+        }
+    }
+
+    mod tests {
+        use std::time::Duration;
+
+        use massa_time::MassaTime;
+
+        use super::ManualClock;
+
+        #[test]
+        fn test_manual_clock_fires_due_tickers() {
+            let clock = ManualClock::new(MassaTime::from_millis(1000));
+            let fast = clock.tick(Duration::from_millis(100));
+            let slow = clock.tick(Duration::from_millis(1000));
+
+            clock.advance(MassaTime::from_millis(99));
+            assert!(fast.try_recv().is_err());
+            clock.advance(MassaTime::from_millis(1));
+            assert!(fast.try_recv().is_ok());
+            assert!(slow.try_recv().is_err());
+
+            // several missed periods give a single tick
+            clock.advance(MassaTime::from_millis(900));
+            assert!(fast.try_recv().is_ok());
+            assert!(fast.try_recv().is_err());
+            assert!(slow.try_recv().is_ok());
+            assert_eq!(clock.now(), MassaTime::from_millis(2000));
+
+            // dropped receivers are forgotten
+            drop(fast);
+            clock.advance(MassaTime::from_millis(100));
+            assert_eq!(clock.0.lock().tickers.len(), 1);
+        }
+
+        #[test]
+        fn test_manual_clock_fires_deadlines_once() {
+            let clock = ManualClock::new(MassaTime::from_millis(1000));
+            let deadline = clock.at(MassaTime::from_millis(1500));
+
+            clock.advance(MassaTime::from_millis(499));
+            assert!(deadline.try_recv().is_err());
+            clock.advance(MassaTime::from_millis(1));
+            assert!(deadline.try_recv().is_ok());
+            clock.advance(MassaTime::from_millis(1000));
+            assert!(deadline.try_recv().is_err());
+
+            // a deadline that already passed fires right away
+            assert!(clock.at(MassaTime::from_millis(1000)).try_recv().is_ok());
+            assert_eq!(clock.0.lock().tickers.len(), 0);
+        }
+    }
+}
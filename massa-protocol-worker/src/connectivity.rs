@@ -7,8 +7,11 @@ use massa_metrics::MassaMetrics;
 use massa_models::stats::NetworkStats;
 use massa_pool_exports::PoolController;
 use massa_pos_exports::SelectorController;
-use massa_protocol_exports::{PeerCategoryInfo, PeerId, ProtocolConfig, ProtocolError};
+use massa_protocol_exports::{
+    PeerCategoryInfo, PeerEvent, PeerEventBroadcast, PeerId, ProtocolConfig, ProtocolError,
+};
 use massa_storage::Storage;
+use massa_time::MassaTime;
 use massa_versioning::versioning::MipStore;
 use parking_lot::RwLock;
 use peernet::peer::PeerConnectionType;
@@ -18,16 +21,20 @@ use std::{collections::HashMap, net::IpAddr};
 use std::{thread::JoinHandle, time::Duration};
 use tracing::{debug, warn};
 
+use crate::address_cache::{AddressCache, MAX_CACHED_ADDRESSES};
+use crate::clock::ProtocolClock;
 use crate::handlers::peer_handler::models::ConnectionMetadata;
 use crate::{
-    handlers::peer_handler::models::{InitialPeers, PeerState, SharedPeerDB},
-    ip::to_canonical,
+    handlers::peer_handler::models::{InitialPeers, PeerState, SharedPeerDB, SharedPeerTraffic},
+    ip::{is_ip_enabled, spread_over_subnets, subnet_of, to_canonical},
     worker::ProtocolChannels,
 };
 use crate::{handlers::peer_handler::PeerManagementHandler, messages::MessagesHandler};
 use crate::{
     handlers::{
-        block_handler::{cache::BlockCache, BlockHandler},
+        block_handler::{
+            cache::BlockCache, commands_retrieval::BlockHandlerRetrievalCommand, BlockHandler,
+        },
         endorsement_handler::{cache::EndorsementCache, EndorsementHandler},
         operation_handler::{cache::OperationCache, OperationHandler},
         peer_handler::models::PeerMessageTuple,
@@ -88,6 +95,7 @@ pub(crate) fn start_connectivity_thread(
         let sender_blocks_retrieval_ext = protocol_channels.block_handler_retrieval.0.clone();
         let sender_blocks_propagation_ext = protocol_channels.block_handler_propagation.0.clone();
         let sender_operations_propagation_ext = protocol_channels.operation_handler_propagation.0.clone();
+        let clock = protocol_channels.clock.clone();
         move || {
             for (addr, transport) in &config.listeners {
                 network_controller
@@ -110,7 +118,8 @@ pub(crate) fn start_connectivity_thread(
             )));
             let endorsement_cache = Arc::new(RwLock::new(EndorsementCache::new(
                 config.max_known_endorsements_size.try_into().unwrap(),
-                (total_in_slots + total_out_slots).try_into().unwrap()
+                (total_in_slots + total_out_slots).try_into().unwrap(),
+                config.max_known_endorsements_age.to_duration(),
             )));
 
             let block_cache = Arc::new(RwLock::new(BlockCache::new(
@@ -130,14 +139,19 @@ pub(crate) fn start_connectivity_thread(
                 peer_categories.iter().map(|(key, value)|(key.clone(), (value.0.clone(), value.1.target_out_connections))).collect(),
                 config.default_category_info.target_out_connections,
                 &config,
+                protocol_channels.peer_events.clone(),
+                clock.clone(),
                 massa_metrics.clone(),
             );
 
+            // addresses of the creators, shared by the deserializers of operations and endorsements
+            let address_cache = Arc::new(AddressCache::new(MAX_CACHED_ADDRESSES));
             let mut operation_handler = OperationHandler::new(
                 pool_controller.clone(),
                 storage.clone_without_refs(),
                 config.clone(),
                 operation_cache.clone(),
+                address_cache.clone(),
                 network_controller.get_active_connections(),
                 channel_operations.1,
                 protocol_channels.operation_handler_retrieval.0.clone(),
@@ -145,12 +159,15 @@ pub(crate) fn start_connectivity_thread(
                 sender_operations_propagation_ext.clone(),
                 protocol_channels.operation_handler_propagation.1.clone(),
                 peer_management_handler.sender.command_sender.clone(),
+                clock.clone(),
                 massa_metrics.clone(),
+                protocol_channels.protocol_metrics.clone(),
             );
             let mut endorsement_handler = EndorsementHandler::new(
                 pool_controller.clone(),
                 selector_controller.clone(),
                 endorsement_cache.clone(),
+                address_cache,
                 storage.clone_without_refs(),
                 config.clone(),
                 network_controller.get_active_connections(),
@@ -175,18 +192,23 @@ pub(crate) fn start_connectivity_thread(
                 sender_operations_propagation_ext,
                 sender_endorsements_propagation_ext,
                 peer_management_handler.sender.command_sender.clone(),
+                peer_db.clone(),
                 config.clone(),
                 endorsement_cache,
                 operation_cache,
                 block_cache,
                 storage.clone_without_refs(),
                 mip_store,
+                clock.clone(),
                 massa_metrics.clone(),
+                protocol_channels.protocol_metrics.clone(),
             );
 
             let tick_metrics = tick(massa_metrics.tick_delay);
             let tick_try_connect = tick(config.try_connection_timer.to_duration());
-            let tick_unban_everyone = tick(config.unban_everyone_timer.to_duration());
+            let outbound_only_peers: HashMap<PeerId, SocketAddr> = config.outbound_only_peers.iter().copied().collect();
+            // connections seen at the last check, to notify the subscribers of peer events
+            let mut known_connections: HashMap<PeerId, SocketAddr> = HashMap::new();
 
             //Try to connect to peers
             loop {
@@ -197,12 +219,17 @@ pub(crate) fn start_connectivity_thread(
                         match msg {
                             Ok(ConnectivityCommand::Stop) => {
                                 debug!("Stopping protocol");
-                                drop(network_controller);
-                                debug!("Stopped network controller");
+                                // flush the batched operations and endorsements before telling the peers we leave
                                 operation_handler.stop();
                                 debug!("Stopped operation handler");
                                 endorsement_handler.stop();
                                 debug!("Stopped endorsement handler");
+                                if config.shutdown_timeout.as_millis() > 0 {
+                                    peer_management_handler.drain("node shutting down", config.shutdown_timeout.to_duration());
+                                    debug!("Drained connections");
+                                }
+                                drop(network_controller);
+                                debug!("Stopped network controller");
                                 block_handler.stop();
                                 debug!("Stopped block handler");
                                 peer_management_handler.stop();
@@ -250,18 +277,48 @@ pub(crate) fn start_connectivity_thread(
                         let active_conn = network_controller.get_active_connections();
                         let peers_connected = active_conn.get_peers_connected();
                         let peers_connection_queue = active_conn.get_peer_ids_out_connection_queue();
+                        let disconnected_peers = notify_connection_changes(&protocol_channels.peer_events, &protocol_channels.peer_traffic, &mut known_connections, &peers_connected);
+                        if !disconnected_peers.is_empty() {
+                            // the blocks asked to these peers can be asked to others without waiting for the asks to time out
+                            if let Err(err) = protocol_channels.block_handler_retrieval.0.try_send(BlockHandlerRetrievalCommand::PeersDisconnected(disconnected_peers)) {
+                                warn!("could not notify the block handler of disconnections: {}", err);
+                            }
+                        }
+
+                        // Redial the outbound-only peers as soon as they aren't connected, they don't take any slot
+                        for (peer_id, addr) in outbound_only_peers.iter() {
+                            if peers_connected.contains_key(peer_id) || peers_connection_queue.contains(addr) {
+                                continue;
+                            }
+                            let _ = try_connect_peer(*addr, &mut network_controller, &peer_db, &config, &clock);
+                        }
 
                         let mut connection_slots = HashMap::new();
                         connection_slots.insert("default", config.default_category_info.target_out_connections);
                         for (category, infos) in peer_categories.iter() {
                             connection_slots.insert(category, infos.1.target_out_connections);
                         }
+                        // Connections we can still initiate before reaching the total target, unlimited if it is 0
+                        let mut out_slots = if config.target_out_connections == 0 {
+                            usize::MAX
+                        } else {
+                            let out_connections = peers_connected.iter().filter(|(peer_id, (_, connection_type, _))| *connection_type == PeerConnectionType::OUT && !outbound_only_peers.contains_key(peer_id)).count();
+                            let queued_connections = peers_connection_queue.iter().filter(|addr| !outbound_only_peers.values().any(|outbound_only_addr| outbound_only_addr == *addr)).count();
+                            config.target_out_connections.saturating_sub(out_connections + queued_connections)
+                        };
+                        // nor beyond the total number of connections
+                        if config.max_total_connections > 0 {
+                            out_slots = out_slots.min(config.max_total_connections.saturating_sub(peers_connected.len() + peers_connection_queue.len()));
+                        }
 
                         // Get all the addresses we can connect to, without any filter or prioritization done yet
                         let mut addresses_can_connect  = Vec::new();
                         {
                             let peer_db_read = peer_db.read();
                             for (peer_id, peer_info) in peer_db_read.get_peers() {
+                                if outbound_only_peers.contains_key(peer_id) {
+                                    continue;
+                                }
 
                                 // If peer already connected, decrement the slots for the given category, or default category if none
                                 if let Some(peer) = peers_connected.get(peer_id) {
@@ -286,7 +343,7 @@ pub(crate) fn start_connectivity_thread(
                                             continue;
                                         }
 
-                                        if let Some((addr, _)) = last_announce.listeners.iter().next() {
+                                        if let Some((addr, _)) = last_announce.listeners.iter().find(|(addr, _)| is_ip_enabled(addr, config.enable_ipv6)) {
                                             let canonical_ip = to_canonical(addr.ip());
                                             let mut allowed_local_ips = false;
                                             // Check if the peer is in a category and we didn't reached out target yet
@@ -313,12 +370,16 @@ pub(crate) fn start_connectivity_thread(
 
                                             // check if the peer last connect attempt has not been too recent
                                             if let ConnectionMetadata { last_try_connect: Some(lt), .. } = connection_metadata {
-                                                let last_try_connect = lt.estimate_instant().expect("Time went backward");
-                                                if last_try_connect.elapsed() < config.try_connection_timer_same_peer.to_duration() {
+                                                if clock.now().saturating_sub(lt) < config.try_connection_timer_same_peer {
                                                     continue;
                                                 }
                                             }
 
+                                            // skip the peers in backoff after failed connections
+                                            if connection_metadata.next_try_connect.is_some_and(|next| next > clock.now()) {
+                                                continue;
+                                            }
+
                                             if config.listeners.iter().any(|(local_addr, _transport)| addr == local_addr) {
                                                 continue;
                                             }
@@ -338,10 +399,18 @@ pub(crate) fn start_connectivity_thread(
 
                         // Sort addresses using the metadata
                         addresses_can_connect.sort_by(|a, b| a.1.cmp(&b.1));
+                        // Prefer the subnets we aren't connected to yet, so that a single operator can't take all our connections
+                        if config.subnet_diversity_enabled {
+                            let used_subnets = peers_connected.values().map(|(addr, _, _)| addr.ip()).chain(peers_connection_queue.iter().map(|addr| addr.ip())).map(subnet_of).collect();
+                            addresses_can_connect = spread_over_subnets(addresses_can_connect, |(addr, _, _)| *addr, &used_subnets);
+                        }
 
                         // Connect to the given addresses, trying to fill all the slots available
                         let mut addresses_connected = vec![];
                         for (addr, _, category) in addresses_can_connect.iter() {
+                            if out_slots == 0 {
+                                break;
+                            }
                             if addresses_connected.contains(addr) {
                                 continue;
                             }
@@ -353,8 +422,9 @@ pub(crate) fn start_connectivity_thread(
                                     for (name, slots) in connection_slots.iter_mut() {
                                         if name == *cat && *slots > 0 {
                                             // In case the connection succeeds, we take a place in a slot
-                                            if try_connect_peer(*addr, &mut network_controller, &peer_db, &config).is_ok() {
+                                            if try_connect_peer(*addr, &mut network_controller, &peer_db, &config, &clock).is_ok() {
                                                 *slots = slots.saturating_sub(1);
+                                                out_slots = out_slots.saturating_sub(1);
                                                 addresses_connected.push(*addr);
                                             }
                                         }
@@ -364,10 +434,11 @@ pub(crate) fn start_connectivity_thread(
                                 // Default category
                                 None if connection_slots["default"] > 0 => {
                                     // In case the connection succeeds, we take a place in a slot
-                                    if try_connect_peer(*addr, &mut network_controller, &peer_db, &config).is_err() {
+                                    if try_connect_peer(*addr, &mut network_controller, &peer_db, &config, &clock).is_ok() {
                                         if let Some(v) = connection_slots.get_mut("default") {
                                             *v = v.saturating_sub(1);
                                         }
+                                        out_slots = out_slots.saturating_sub(1);
                                         addresses_connected.push(*addr);
                                     }
                                 }
@@ -380,15 +451,6 @@ pub(crate) fn start_connectivity_thread(
                             }
                         }
                     }
-                    recv(tick_unban_everyone) -> _ => {
-                        debug!("Periodic unban of every peer");
-                        let mut peer_db_write = peer_db.write();
-                        for (peer_id, peer_status) in peer_db_write.get_peers().clone() {
-                            if peer_status.state == PeerState::Banned {
-                                peer_db_write.unban_peer(&peer_id);
-                            }
-                        }
-                    }
                 }
             }
         }
@@ -398,22 +460,60 @@ pub(crate) fn start_connectivity_thread(
     Ok((protocol_channels.connectivity_thread.0, handle))
 }
 
+/// Publish the connections and disconnections that happened since the last check,
+/// and reset the traffic counters of the disconnected peers, which are returned
+fn notify_connection_changes(
+    peer_events: &PeerEventBroadcast,
+    peer_traffic: &SharedPeerTraffic,
+    known_connections: &mut HashMap<PeerId, SocketAddr>,
+    peers_connected: &HashMap<PeerId, (SocketAddr, PeerConnectionType, Option<String>)>,
+) -> Vec<PeerId> {
+    let timestamp = MassaTime::now();
+    let mut disconnected_peers = Vec::new();
+    known_connections.retain(|peer_id, addr| {
+        if peers_connected.contains_key(peer_id) {
+            return true;
+        }
+        disconnected_peers.push(*peer_id);
+        peer_traffic.write().remove_peer(peer_id);
+        peer_events.send(PeerEvent::Disconnected {
+            peer_id: *peer_id,
+            ip: Some(to_canonical(addr.ip())),
+            timestamp,
+        });
+        false
+    });
+    for (peer_id, (addr, _, _)) in peers_connected {
+        if !known_connections.contains_key(peer_id) {
+            known_connections.insert(*peer_id, *addr);
+            peer_events.send(PeerEvent::Connected {
+                peer_id: *peer_id,
+                ip: Some(to_canonical(addr.ip())),
+                timestamp,
+            });
+        }
+    }
+    disconnected_peers
+}
+
 // Attempt to connect to peer
 fn try_connect_peer(
     addr: SocketAddr,
     network_controller: &mut Box<dyn NetworkController>,
     peer_db: &SharedPeerDB,
     config: &ProtocolConfig,
+    clock: &ProtocolClock,
 ) -> Result<(), ProtocolError> {
     debug!("Trying to connect to addr {}", addr);
 
     let conn_res = network_controller.try_connect(addr, config.timeout_connection.to_duration());
     {
+        let now = clock.now();
         let mut peer_db_write = peer_db.write();
-        peer_db_write.set_try_connect_success_or_insert(&addr);
+        peer_db_write.set_try_connect_success_or_insert(&addr, now);
         if let Err(ref err) = conn_res {
             debug!("Failed to connect to peer {:?}: {:?}", addr, err);
-            peer_db_write.set_try_connect_failure_or_insert(&addr);
+            peer_db_write.set_try_connect_failure_or_insert(&addr, now);
         }
     }
     conn_res
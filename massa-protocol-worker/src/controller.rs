@@ -1,5 +1,6 @@
 use std::{collections::HashMap, net::SocketAddr, time::Duration};
 
+use crossbeam::channel::SendTimeoutError;
 use massa_channel::{sender::MassaSender, MassaChannel};
 use massa_models::{
     block_header::SecuredHeader,
@@ -7,8 +8,13 @@ use massa_models::{
     prehash::{PreHashMap, PreHashSet},
     stats::NetworkStats,
 };
-use massa_protocol_exports::{BootstrapPeers, PeerId, ProtocolController, ProtocolError};
+use massa_protocol_exports::{
+    BanReason, BannedPeerInfo, BootstrapPeers, InflightRequest, PeerEvent, PeerEventBroadcast,
+    PeerEventReceiver, PeerId, PeerStats, ProtocolConfigUpdate, ProtocolController, ProtocolError,
+    ProtocolMetrics, ProtocolMetricsState, RejectionReason,
+};
 use massa_storage::Storage;
+use massa_time::MassaTime;
 use peernet::peer::PeerConnectionType;
 
 use crate::{
@@ -16,11 +22,11 @@ use crate::{
     handlers::{
         block_handler::{
             commands_propagation::BlockHandlerPropagationCommand,
-            commands_retrieval::BlockHandlerRetrievalCommand,
+            commands_retrieval::BlockHandlerRetrievalCommand, SharedProtocolMetrics,
         },
         endorsement_handler::commands_propagation::EndorsementHandlerPropagationCommand,
         operation_handler::commands_propagation::OperationHandlerPropagationCommand,
-        peer_handler::models::PeerManagementCmd,
+        peer_handler::models::{PeerManagementCmd, SharedPeerDB, SharedPeerTraffic},
     },
 };
 
@@ -37,6 +43,12 @@ pub struct ProtocolControllerImpl {
     pub sender_endorsement_handler: Option<MassaSender<EndorsementHandlerPropagationCommand>>,
     pub sender_connectivity_thread: Option<MassaSender<ConnectivityCommand>>,
     pub sender_peer_management_thread: Option<MassaSender<PeerManagementCmd>>,
+    pub peer_events: PeerEventBroadcast,
+    pub peer_db: SharedPeerDB,
+    pub protocol_metrics: SharedProtocolMetrics,
+    pub peer_traffic: SharedPeerTraffic,
+    /// wishlist deltas that would make the block handler track more blocks are rejected
+    pub max_wishlist_size: usize,
 }
 
 impl ProtocolControllerImpl {
@@ -47,6 +59,11 @@ impl ProtocolControllerImpl {
         sender_endorsement_handler: MassaSender<EndorsementHandlerPropagationCommand>,
         sender_connectivity_thread: MassaSender<ConnectivityCommand>,
         sender_peer_management_thread: MassaSender<PeerManagementCmd>,
+        peer_events: PeerEventBroadcast,
+        peer_db: SharedPeerDB,
+        protocol_metrics: SharedProtocolMetrics,
+        peer_traffic: SharedPeerTraffic,
+        max_wishlist_size: usize,
     ) -> Self {
         ProtocolControllerImpl {
             sender_block_retrieval_handler: Some(sender_block_retrieval_handler),
@@ -55,6 +72,36 @@ impl ProtocolControllerImpl {
             sender_endorsement_handler: Some(sender_endorsement_handler),
             sender_connectivity_thread: Some(sender_connectivity_thread),
             sender_peer_management_thread: Some(sender_peer_management_thread),
+            peer_events,
+            peer_db,
+            protocol_metrics,
+            peer_traffic,
+            max_wishlist_size,
+        }
+    }
+
+    /// reject the wishlist deltas that would make the block handler track too many blocks
+    fn check_wishlist_delta(&self, added: usize, removed: usize) -> Result<(), ProtocolError> {
+        let wishlist_size = self.protocol_metrics.read().wishlist_size as usize;
+        let wanted = (wishlist_size + added).saturating_sub(removed);
+        if wanted > self.max_wishlist_size {
+            return Err(ProtocolError::WishlistFull {
+                wanted,
+                max: self.max_wishlist_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// error of a command that the receiving thread didn't accept in time
+fn send_timeout_error<T>(err: SendTimeoutError<T>, command: &str) -> ProtocolError {
+    match err {
+        SendTimeoutError::Timeout(_) => {
+            ProtocolError::Timeout(format!("{} command not accepted in time", command))
+        }
+        SendTimeoutError::Disconnected(_) => {
+            ProtocolError::ChannelError(format!("{} command send error", command))
         }
     }
 }
@@ -93,12 +140,44 @@ impl ProtocolController for ProtocolControllerImpl {
             })
     }
 
-    /// update the block wish list
+    fn notify_block_attack_timeout(
+        &self,
+        block_id: BlockId,
+        timeout: Duration,
+    ) -> Result<(), ProtocolError> {
+        self.sender_block_handler
+            .as_ref()
+            .unwrap()
+            .send_timeout(
+                BlockHandlerPropagationCommand::AttackBlockDetected(block_id),
+                timeout,
+            )
+            .map_err(|err| send_timeout_error(err, "notify_block_attack"))
+    }
+
+    fn notify_header_rejected(
+        &self,
+        block_id: BlockId,
+        reason: RejectionReason,
+    ) -> Result<(), ProtocolError> {
+        self.sender_block_handler
+            .as_ref()
+            .unwrap()
+            .try_send(BlockHandlerPropagationCommand::HeaderRejected(
+                block_id, reason,
+            ))
+            .map_err(|_| {
+                ProtocolError::ChannelError("notify_header_rejected command send error".into())
+            })
+    }
+
+    /// update the block wish list, the whole delta is rejected if it would make the wishlist too big
     fn send_wishlist_delta(
         &self,
         new: PreHashMap<BlockId, Option<SecuredHeader>>,
         remove: PreHashSet<BlockId>,
     ) -> Result<(), ProtocolError> {
+        self.check_wishlist_delta(new.len(), remove.len())?;
         self.sender_block_retrieval_handler
             .as_ref()
             .unwrap()
@@ -108,6 +187,23 @@ impl ProtocolController for ProtocolControllerImpl {
             })
     }
 
+    fn send_wishlist_delta_timeout(
+        &self,
+        new: PreHashMap<BlockId, Option<SecuredHeader>>,
+        remove: PreHashSet<BlockId>,
+        timeout: Duration,
+    ) -> Result<(), ProtocolError> {
+        self.check_wishlist_delta(new.len(), remove.len())?;
+        self.sender_block_retrieval_handler
+            .as_ref()
+            .unwrap()
+            .send_timeout(
+                BlockHandlerRetrievalCommand::WishlistDelta { new, remove },
+                timeout,
+            )
+            .map_err(|err| send_timeout_error(err, "send_wishlist_delta"))
+    }
+
     /// Propagate a batch of operation ids (from pool).
     ///
     /// note: Full `OperationId` is replaced by a `OperationPrefixId` later by the worker.
@@ -160,7 +256,7 @@ impl ProtocolController for ProtocolControllerImpl {
         self.sender_peer_management_thread
             .as_ref()
             .unwrap()
-            .try_send(PeerManagementCmd::Ban(peer_ids))
+            .try_send(PeerManagementCmd::Ban(peer_ids, BanReason::Manual))
             .map_err(|_| ProtocolError::ChannelError("ban_peers command send error".into()))
     }
 
@@ -172,6 +268,106 @@ impl ProtocolController for ProtocolControllerImpl {
             .map_err(|_| ProtocolError::ChannelError("unban_peers command send error".into()))
     }
 
+    fn get_banned_peers(&self) -> Vec<BannedPeerInfo> {
+        self.peer_db.read().get_banned_peers(MassaTime::now())
+    }
+
+    fn unban_peer(&self, peer_id: &PeerId) -> Result<(), ProtocolError> {
+        let mut peer_db = self.peer_db.write();
+        if peer_db.get_ban_reason(peer_id).is_none() {
+            return Err(ProtocolError::GeneralProtocolError(format!(
+                "peer {} is not banned",
+                peer_id
+            )));
+        }
+        peer_db.unban_peer(peer_id);
+        drop(peer_db);
+        self.peer_events.send(PeerEvent::Unbanned {
+            peer_id: *peer_id,
+            ip: None,
+            timestamp: MassaTime::now(),
+        });
+        Ok(())
+    }
+
+    fn disconnect_peer(&self, peer_id: &PeerId, graceful: bool) -> Result<(), ProtocolError> {
+        self.sender_peer_management_thread
+            .as_ref()
+            .unwrap()
+            .try_send(PeerManagementCmd::Disconnect {
+                peer_id: *peer_id,
+                graceful,
+            })
+            .map_err(|_| ProtocolError::ChannelError("disconnect_peer command send error".into()))
+    }
+
+    fn get_metrics(&self) -> ProtocolMetrics {
+        let mut metrics = self.protocol_metrics.read().clone();
+        metrics.peers_by_state = self.peer_db.read().get_peer_state_counts();
+        metrics
+    }
+
+    fn gather_metrics(&self) -> String {
+        // the connection counts are left out if the connectivity thread doesn't answer
+        let stats = self.get_stats().ok().map(|(stats, _)| stats);
+        let mut banned_peers = HashMap::new();
+        for banned_peer in self.peer_db.read().get_banned_peers(MassaTime::now()) {
+            *banned_peers.entry(banned_peer.reason).or_default() += 1;
+        }
+        let state = ProtocolMetricsState {
+            in_connections: stats.as_ref().map(|stats| stats.in_connection_count),
+            out_connections: stats.as_ref().map(|stats| stats.out_connection_count),
+            banned_peers,
+            traffic: self.peer_traffic.read().lifetime.clone(),
+        };
+        self.get_metrics().encode(&state)
+    }
+
+    fn subscribe_peer_events(&self) -> PeerEventReceiver {
+        self.peer_events.subscribe()
+    }
+
+    fn get_dropped_peer_events_count(&self) -> u64 {
+        self.peer_events.dropped_count()
+    }
+
+    fn get_peer_stats(&self) -> HashMap<PeerId, PeerStats> {
+        self.peer_traffic.read().peers.clone()
+    }
+
+    fn get_lifetime_peer_stats(&self) -> PeerStats {
+        self.peer_traffic.read().lifetime.clone()
+    }
+
+    fn get_inflight_block_requests(&self) -> Result<Vec<InflightRequest>, ProtocolError> {
+        let (sender, receiver) =
+            MassaChannel::new("get_inflight_block_requests".to_string(), Some(1));
+        self.sender_block_retrieval_handler
+            .as_ref()
+            .unwrap()
+            .try_send(BlockHandlerRetrievalCommand::GetInflightRequests { responder: sender })
+            .map_err(|_| {
+                ProtocolError::ChannelError("get_inflight_block_requests command send error".into())
+            })?;
+        receiver.recv_timeout(Duration::from_secs(10)).map_err(|_| {
+            ProtocolError::ChannelError("get_inflight_block_requests command receive error".into())
+        })
+    }
+
+    fn get_first_header_times(&self) -> Result<PreHashMap<BlockId, MassaTime>, ProtocolError> {
+        let (sender, receiver) = MassaChannel::new("get_first_header_times".to_string(), Some(1));
+        self.sender_block_retrieval_handler
+            .as_ref()
+            .unwrap()
+            .try_send(BlockHandlerRetrievalCommand::GetFirstHeaderTimes { responder: sender })
+            .map_err(|_| {
+                ProtocolError::ChannelError("get_first_header_times command send error".into())
+            })?;
+        receiver.recv_timeout(Duration::from_secs(10)).map_err(|_| {
+            ProtocolError::ChannelError("get_first_header_times command receive error".into())
+        })
+    }
+
     fn get_bootstrap_peers(&self) -> Result<BootstrapPeers, ProtocolError> {
         let (sender, receiver) = MassaChannel::new("get_bootstrap_peers".to_string(), Some(1));
         self.sender_peer_management_thread
@@ -186,6 +382,35 @@ impl ProtocolController for ProtocolControllerImpl {
         })
     }
 
+    fn update_config(&self, update: ProtocolConfigUpdate) -> Result<(), ProtocolError> {
+        update.check()?;
+        if update.block_ask_fanout.is_some() {
+            self.sender_block_retrieval_handler
+                .as_ref()
+                .unwrap()
+                .try_send(BlockHandlerRetrievalCommand::UpdateConfig(update.clone()))
+                .map_err(|_| {
+                    ProtocolError::ChannelError("update_config command send error".into())
+                })?;
+        }
+        if update.operation_propagation_fanout.is_some() {
+            self.sender_operation_handler
+                .as_ref()
+                .unwrap()
+                .try_send(OperationHandlerPropagationCommand::UpdateConfig(
+                    update.clone(),
+                ))
+                .map_err(|_| {
+                    ProtocolError::ChannelError("update_config command send error".into())
+                })?;
+        }
+        self.sender_peer_management_thread
+            .as_ref()
+            .unwrap()
+            .try_send(PeerManagementCmd::UpdateConfig(update))
+            .map_err(|_| ProtocolError::ChannelError("update_config command send error".into()))
+    }
+
     fn clone_box(&self) -> Box<dyn ProtocolController> {
         Box::new(self.clone())
     }
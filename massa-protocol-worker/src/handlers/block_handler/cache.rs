@@ -1,7 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use massa_models::{block_header::SecuredHeader, block_id::BlockId};
@@ -17,6 +17,12 @@ pub struct BlockCache {
     pub blocks_known_by_peer: HashMap<PeerId, LruMap<BlockId, (bool, Instant)>>,
     /// max number of blocks known in peer knowledge cache
     pub max_known_blocks_by_peer: u32,
+    /// peers that sent us the header of each block, with the number of times they sent it
+    pub header_sources: LruMap<BlockId, HashMap<PeerId, u32>>,
+    /// blocks detected as attack attempts, whose later relays are banned
+    pub attack_blocks: LruMap<BlockId, ()>,
+    /// headers recently accepted from each peer, with the time of their receipt and the number of times they were sent again since
+    pub recent_headers_by_peer: HashMap<PeerId, LruMap<BlockId, (Instant, u32)>>,
 }
 
 impl BlockCache {
@@ -43,6 +49,46 @@ impl BlockCache {
             known_blocks.insert(*block_id, (known, now));
         }
     }
+
+    /// Remember that a peer sent us the header of a block
+    pub fn insert_header_source(&mut self, block_id: BlockId, from_peer_id: PeerId) {
+        match self.header_sources.get(&block_id) {
+            Some(sources) => {
+                *sources.entry(from_peer_id).or_default() += 1;
+            }
+            None => {
+                self.header_sources
+                    .insert(block_id, HashMap::from([(from_peer_id, 1)]));
+            }
+        }
+    }
+
+    /// Remember that a header sent by a peer was accepted, its later copies from that peer are counted as duplicates
+    pub fn insert_recent_header(&mut self, from_peer_id: PeerId, block_id: BlockId) {
+        self.recent_headers_by_peer
+            .entry(from_peer_id)
+            .or_insert_with(|| LruMap::new(ByLength::new(self.max_known_blocks_by_peer)))
+            .insert(block_id, (Instant::now(), 0));
+    }
+
+    /// Count a header sent again by a peer within `window` of its acceptance.
+    /// Returns the number of copies received so far, or none if the header isn't a duplicate.
+    pub fn count_duplicate_header(
+        &mut self,
+        from_peer_id: &PeerId,
+        block_id: &BlockId,
+        window: Duration,
+    ) -> Option<u32> {
+        let (received_at, duplicates) = self
+            .recent_headers_by_peer
+            .get_mut(from_peer_id)?
+            .get(block_id)?;
+        if received_at.elapsed() > window {
+            return None;
+        }
+        *duplicates += 1;
+        Some(*duplicates)
+    }
 }
 
 impl BlockCache {
@@ -51,6 +97,9 @@ impl BlockCache {
             checked_headers: LruMap::new(ByLength::new(max_known_blocks)),
             blocks_known_by_peer: HashMap::new(),
             max_known_blocks_by_peer,
+            header_sources: LruMap::new(ByLength::new(max_known_blocks)),
+            attack_blocks: LruMap::new(ByLength::new(max_known_blocks)),
+            recent_headers_by_peer: HashMap::new(),
         }
     }
 
@@ -58,6 +107,8 @@ impl BlockCache {
         // Remove disconnected peers from cache
         self.blocks_known_by_peer
             .retain(|peer_id, _| peers_connected.contains(peer_id));
+        self.recent_headers_by_peer
+            .retain(|peer_id, _| peers_connected.contains(peer_id));
 
         // Add new connected peers to cache
         for peer_id in peers_connected {
@@ -1,4 +1,5 @@
 use massa_models::block_id::BlockId;
+use massa_protocol_exports::RejectionReason;
 use massa_storage::Storage;
 
 /// Commands that the block handler can process
@@ -14,4 +15,6 @@ pub enum BlockHandlerPropagationCommand {
     },
     /// A block, or it's header, amounted to an attempted attack.
     AttackBlockDetected(BlockId),
+    /// Consensus rejected the header of a block.
+    HeaderRejected(BlockId, RejectionReason),
 }
@@ -1,8 +1,11 @@
+use massa_channel::sender::MassaSender;
 use massa_models::{
     block_header::SecuredHeader,
     block_id::BlockId,
     prehash::{PreHashMap, PreHashSet},
 };
+use massa_protocol_exports::{InflightRequest, PeerId, ProtocolConfigUpdate};
+use massa_time::MassaTime;
 
 #[derive(Clone)]
 pub enum BlockHandlerRetrievalCommand {
@@ -14,4 +17,16 @@ pub enum BlockHandlerRetrievalCommand {
         /// remove from wish list
         remove: PreHashSet<BlockId>,
     },
+    /// Settings changed at runtime
+    UpdateConfig(ProtocolConfigUpdate),
+    /// The connections to these peers were closed, their pending asks are sent to other peers
+    PeersDisconnected(Vec<PeerId>),
+    /// Send the block data requests not answered yet to the responder
+    GetInflightRequests {
+        responder: MassaSender<Vec<InflightRequest>>,
+    },
+    /// Send the time at which the header of each recent block was first received to the responder
+    GetFirstHeaderTimes {
+        responder: MassaSender<PreHashMap<BlockId, MassaTime>>,
+    },
 }
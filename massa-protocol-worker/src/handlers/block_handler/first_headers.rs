@@ -0,0 +1,98 @@
+//! Time at which the header of each block was first received.
+//!
+//! Together with the download latency of the blocks, it gives the time taken to acquire a block
+//! since we first heard of it. The times of the last `max_first_header_times` blocks are kept,
+//! for `first_header_time_retention` at most.
+
+use massa_models::{block_id::BlockId, prehash::PreHashMap};
+use massa_protocol_exports::ProtocolConfig;
+use massa_time::MassaTime;
+use schnellru::{ByLength, LruMap};
+
+pub(crate) struct FirstHeaderTimes {
+    /// first receipt time of each block header, oldest first as the times are never updated
+    times: LruMap<BlockId, MassaTime>,
+    max_count: usize,
+    retention: MassaTime,
+}
+
+impl FirstHeaderTimes {
+    pub(crate) fn new(config: &ProtocolConfig) -> Self {
+        FirstHeaderTimes {
+            times: LruMap::new(ByLength::new(
+                config.max_first_header_times.try_into().unwrap_or(u32::MAX),
+            )),
+            max_count: config.max_first_header_times,
+            retention: config.first_header_time_retention,
+        }
+    }
+
+    /// A header of `block_id` was received at `received_at`, only the first receipt is kept
+    pub(crate) fn record(&mut self, block_id: BlockId, received_at: MassaTime) {
+        if self.max_count == 0 || self.times.peek(&block_id).is_some() {
+            return;
+        }
+        self.prune(received_at);
+        self.times.insert(block_id, received_at);
+    }
+
+    /// First receipt times of the headers received within the retention time
+    pub(crate) fn snapshot(&mut self, now: MassaTime) -> PreHashMap<BlockId, MassaTime> {
+        self.prune(now);
+        self.times
+            .iter()
+            .map(|(block_id, received_at)| (*block_id, *received_at))
+            .collect()
+    }
+
+    /// Forget the times older than the retention time
+    fn prune(&mut self, now: MassaTime) {
+        while self.times.peek_oldest().map_or(false, |(_, received_at)| {
+            now.saturating_sub(*received_at) > self.retention
+        }) {
+            self.times.pop_oldest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use massa_hash::Hash;
+    use massa_models::block_id::BlockId;
+    use massa_protocol_exports::ProtocolConfig;
+    use massa_time::MassaTime;
+
+    use super::FirstHeaderTimes;
+
+    #[test]
+    fn test_first_header_times_bounded_by_count_and_age() {
+        let mut times = FirstHeaderTimes::new(&ProtocolConfig {
+            max_first_header_times: 2,
+            first_header_time_retention: MassaTime::from_millis(1000),
+            ..Default::default()
+        });
+        let block_ids: Vec<BlockId> = (0..3u8)
+            .map(|index| BlockId::generate_from_hash(Hash::compute_from(&[index])))
+            .collect();
+        let start = MassaTime::from_millis(10_000);
+
+        times.record(block_ids[0], start);
+        times.record(
+            block_ids[1],
+            start.saturating_add(MassaTime::from_millis(100)),
+        );
+        times.record(
+            block_ids[2],
+            start.saturating_add(MassaTime::from_millis(200)),
+        );
+        // the oldest block was dropped for the newest one
+        let snapshot = times.snapshot(start.saturating_add(MassaTime::from_millis(200)));
+        assert_eq!(snapshot.len(), 2);
+        assert!(!snapshot.contains_key(&block_ids[0]));
+
+        // the times expire after the retention time
+        let snapshot = times.snapshot(start.saturating_add(MassaTime::from_millis(1150)));
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot.contains_key(&block_ids[2]));
+    }
+}
@@ -7,8 +7,10 @@ use massa_models::{
     },
     secure_share::{SecureShareDeserializer, SecureShareSerializer},
 };
+use massa_protocol_exports::MessageKind;
 use massa_serialization::{
-    Deserializer, SerializeError, Serializer, U64VarIntDeserializer, U64VarIntSerializer,
+    DeserializeError, Deserializer, SerializeError, Serializer, U64VarIntDeserializer,
+    U64VarIntSerializer,
 };
 use nom::{
     error::{context, ContextError, ParseError},
@@ -38,9 +40,18 @@ pub enum BlockInfoReply {
     Header(SecuredHeader),
     /// List of operation IDs within the block
     OperationIds(Vec<OperationId>),
+    /// Part of the list of operation IDs within the block, for the lists longer than `block_info_chunk_size`
+    OperationIdsChunk {
+        /// Length of the whole list
+        total: u64,
+        /// Position of the first ID of the chunk in the whole list
+        offset: u64,
+        /// IDs of the chunk
+        operation_ids: Vec<OperationId>,
+    },
     /// Requested full operations of the block
     Operations(Vec<SecureShareOperation>),
-    /// Block not found
+    /// Block not found, or none of the asked operations are available anymore
     NotFound,
 }
 
@@ -64,6 +75,14 @@ pub enum BlockMessage {
         /// Block info reply.
         block_info: BlockInfoReply,
     },
+    /// Block header along with the ids of its operations, for the receiver
+    /// to rebuild the block from the operations it already has.
+    CompactBlock {
+        /// Header of the block.
+        header: SecuredHeader,
+        /// IDs of the operations of the block, ordered and can contain duplicates.
+        operation_ids: Vec<OperationId>,
+    },
 }
 
 #[derive(IntoPrimitive, Debug, Eq, PartialEq, TryFromPrimitive)]
@@ -72,6 +91,7 @@ pub enum MessageTypeId {
     Header,
     DataRequest,
     DataResponse,
+    CompactBlock,
 }
 
 impl From<&BlockMessage> for MessageTypeId {
@@ -80,6 +100,56 @@ impl From<&BlockMessage> for MessageTypeId {
             BlockMessage::Header(_) => MessageTypeId::Header,
             BlockMessage::DataRequest { .. } => MessageTypeId::DataRequest,
             BlockMessage::DataResponse { .. } => MessageTypeId::DataResponse,
+            BlockMessage::CompactBlock { .. } => MessageTypeId::CompactBlock,
+        }
+    }
+}
+
+impl From<MessageTypeId> for MessageKind {
+    fn from(id: MessageTypeId) -> Self {
+        match id {
+            MessageTypeId::Header => MessageKind::BlockHeader,
+            MessageTypeId::DataRequest => MessageKind::BlockDataRequest,
+            MessageTypeId::DataResponse => MessageKind::BlockDataResponse,
+            MessageTypeId::CompactBlock => MessageKind::CompactBlock,
+        }
+    }
+}
+
+impl BlockMessage {
+    /// Kind of the message, as counted in the protocol metrics
+    pub(crate) fn kind(&self) -> MessageKind {
+        MessageTypeId::from(self).into()
+    }
+
+    /// Kind of a serialized block message from its leading type id, `None` if invalid
+    pub(crate) fn kind_of(raw_id: u64) -> Option<MessageKind> {
+        MessageTypeId::try_from(raw_id).ok().map(MessageKind::from)
+    }
+
+    /// Whether a serialized block message is of a type, or asks or replies with a block info,
+    /// unknown to this version. Such messages come from newer nodes and are skipped rather than
+    /// handled as malformed.
+    pub(crate) fn has_unknown_type(buffer: &[u8]) -> bool {
+        let id_deserializer = U64VarIntDeserializer::new(Included(0), Included(u64::MAX));
+        let Ok((rest, raw_id)) = id_deserializer.deserialize::<DeserializeError>(buffer) else {
+            return false;
+        };
+        match MessageTypeId::try_from(raw_id) {
+            Err(_) => true,
+            Ok(MessageTypeId::DataRequest | MessageTypeId::DataResponse) => {
+                let Ok((rest, _)) =
+                    BlockIdDeserializer::new().deserialize::<DeserializeError>(rest)
+                else {
+                    return false;
+                };
+                id_deserializer
+                    .deserialize::<DeserializeError>(rest)
+                    .map_or(false, |(_, raw_info_id)| {
+                        BlockInfoType::try_from(raw_info_id).is_err()
+                    })
+            }
+            Ok(_) => false,
         }
     }
 }
@@ -91,6 +161,7 @@ pub enum BlockInfoType {
     OperationIds = 1,
     Operations = 2,
     NotFound = 3,
+    OperationIdsChunk = 4,
 }
 
 #[derive(Default, Clone)]
@@ -177,6 +248,22 @@ impl Serializer<BlockMessage> for BlockMessageSerializer {
                                 .serialize(operation_id, buffer)?;
                         }
                     }
+                    BlockInfoReply::OperationIdsChunk {
+                        total,
+                        offset,
+                        operation_ids,
+                    } => {
+                        self.id_serializer
+                            .serialize(&(BlockInfoType::OperationIdsChunk as u64), buffer)?;
+                        self.length_serializer.serialize(total, buffer)?;
+                        self.length_serializer.serialize(offset, buffer)?;
+                        self.length_serializer
+                            .serialize(&(operation_ids.len() as u64), buffer)?;
+                        for operation_id in operation_ids {
+                            self.operation_id_serializer
+                                .serialize(operation_id, buffer)?;
+                        }
+                    }
                     BlockInfoReply::Operations(operations) => {
                         self.id_serializer
                             .serialize(&(BlockInfoType::Operations as u64), buffer)?;
@@ -192,6 +279,18 @@ impl Serializer<BlockMessage> for BlockMessageSerializer {
                     }
                 }
             }
+            BlockMessage::CompactBlock {
+                header,
+                operation_ids,
+            } => {
+                self.secure_share_serializer.serialize(header, buffer)?;
+                self.length_serializer
+                    .serialize(&(operation_ids.len() as u64), buffer)?;
+                for operation_id in operation_ids {
+                    self.operation_id_serializer
+                        .serialize(operation_id, buffer)?;
+                }
+            }
         }
         Ok(())
     }
@@ -203,6 +302,7 @@ pub struct BlockMessageDeserializer {
     block_id_deserializer: BlockIdDeserializer,
     operation_ids_deserializer: OperationIdsDeserializer,
     operations_deserializer: OperationsDeserializer,
+    chunk_position_deserializer: U64VarIntDeserializer,
 }
 
 pub struct BlockMessageDeserializerArgs {
@@ -242,6 +342,10 @@ impl BlockMessageDeserializer {
                 args.max_op_datastore_key_length,
                 args.max_op_datastore_value_length,
             ),
+            chunk_position_deserializer: U64VarIntDeserializer::new(
+                Included(0),
+                Included(args.max_operations_per_block as u64),
+            ),
         }
     }
 }
@@ -292,7 +396,7 @@ impl Deserializer<BlockMessage> for BlockMessageDeserializer {
                                     .map(|(rest, operation_ids)| {
                                         (rest, AskForBlockInfo::Operations(operation_ids))
                                     }),
-                                BlockInfoType::NotFound => {
+                                BlockInfoType::NotFound | BlockInfoType::OperationIdsChunk => {
                                     Err(nom::Err::Error(ParseError::from_error_kind(
                                         buffer,
                                         nom::error::ErrorKind::Digit,
@@ -341,6 +445,25 @@ impl Deserializer<BlockMessage> for BlockMessageDeserializer {
                                         (rest, BlockInfoReply::Operations(operations))
                                     }),
                                 BlockInfoType::NotFound => Ok((rest, BlockInfoReply::NotFound)),
+                                BlockInfoType::OperationIdsChunk => tuple((
+                                    context("Failed total deserialization", |input| {
+                                        self.chunk_position_deserializer.deserialize(input)
+                                    }),
+                                    context("Failed offset deserialization", |input| {
+                                        self.chunk_position_deserializer.deserialize(input)
+                                    }),
+                                    context("Failed OperationIds deserialization", |input| {
+                                        self.operation_ids_deserializer.deserialize(input)
+                                    }),
+                                ))
+                                .map(|(total, offset, operation_ids)| {
+                                    BlockInfoReply::OperationIdsChunk {
+                                        total,
+                                        offset,
+                                        operation_ids,
+                                    }
+                                })
+                                .parse(rest),
                             }
                         }),
                     )),
@@ -350,6 +473,22 @@ impl Deserializer<BlockMessage> for BlockMessageDeserializer {
                     block_info,
                 })
                 .parse(buffer),
+                MessageTypeId::CompactBlock => context(
+                    "Failed CompactBlock deserialization",
+                    tuple((
+                        context("Failed BlockHeader deserialization", |input| {
+                            self.block_header_deserializer.deserialize(input)
+                        }),
+                        context("Failed OperationIds deserialization", |input| {
+                            self.operation_ids_deserializer.deserialize(input)
+                        }),
+                    )),
+                )
+                .map(|(header, operation_ids)| BlockMessage::CompactBlock {
+                    header,
+                    operation_ids,
+                })
+                .parse(buffer),
             }
         })
         .parse(buffer)
@@ -444,6 +583,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unknown_types_are_told_apart_from_malformed_messages() {
+        let block_id =
+            BlockId::from_str("B12DvrcQkzF1Wi8BVoNfc4n93CD3E2qhCNe7nVhnEQGWHZ24fEmg").unwrap();
+        let serializer = super::BlockMessageSerializer::new();
+        let mut known = Vec::new();
+        serializer
+            .serialize(
+                &super::BlockMessage::DataRequest {
+                    block_id,
+                    block_info: super::AskForBlockInfo::OperationIds,
+                },
+                &mut known,
+            )
+            .unwrap();
+        assert!(!super::BlockMessage::has_unknown_type(&known));
+
+        // unknown block info asked
+        let mut unknown_info = known.clone();
+        *unknown_info.last_mut().unwrap() = 99;
+        assert!(super::BlockMessage::has_unknown_type(&unknown_info));
+
+        // unknown message type
+        assert!(super::BlockMessage::has_unknown_type(&[99, 1, 2, 3]));
+
+        // truncated request
+        assert!(!super::BlockMessage::has_unknown_type(&known[..4]));
+    }
+
     #[test]
     fn test_high_limit_message() {
         let message = super::BlockMessage::DataRequest {
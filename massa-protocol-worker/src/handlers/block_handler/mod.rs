@@ -1,15 +1,16 @@
-use std::thread::JoinHandle;
+use std::{sync::Arc, thread::JoinHandle};
 
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
 use massa_consensus_exports::ConsensusController;
 use massa_metrics::MassaMetrics;
 use massa_pool_exports::PoolController;
 use massa_pos_exports::SelectorController;
-use massa_protocol_exports::ProtocolConfig;
+use massa_protocol_exports::{ProtocolConfig, ProtocolMetrics};
 use massa_storage::Storage;
 use massa_versioning::versioning::MipStore;
+use parking_lot::RwLock;
 
-use crate::wrap_network::ActiveConnectionsTrait;
+use crate::{clock::ProtocolClock, wrap_network::ActiveConnectionsTrait};
 
 use self::{
     cache::SharedBlockCache, commands_propagation::BlockHandlerPropagationCommand,
@@ -20,9 +21,14 @@ use self::{
 pub mod cache;
 pub mod commands_propagation;
 pub mod commands_retrieval;
+mod first_headers;
 pub mod messages;
 mod propagation;
 mod retrieval;
+pub(crate) mod selections;
+mod serving;
+mod spans;
+mod withholding;
 
 pub(crate) use messages::{BlockMessage, BlockMessageSerializer};
 
@@ -38,9 +44,12 @@ use super::{
     operation_handler::{
         cache::SharedOperationCache, commands_propagation::OperationHandlerPropagationCommand,
     },
-    peer_handler::models::{PeerManagementCmd, PeerMessageTuple},
+    peer_handler::models::{PeerManagementCmd, PeerMessageTuple, SharedPeerDB},
 };
 
+/// Metrics filled by the block retrieval thread and read by the protocol controller
+pub type SharedProtocolMetrics = Arc<RwLock<ProtocolMetrics>>;
+
 pub struct BlockHandler {
     pub block_retrieval_thread: Option<(MassaSender<BlockHandlerRetrievalCommand>, JoinHandle<()>)>,
     pub block_propagation_thread:
@@ -62,13 +71,16 @@ impl BlockHandler {
         sender_propagations_ops: MassaSender<OperationHandlerPropagationCommand>,
         sender_propagations_endorsements: MassaSender<EndorsementHandlerPropagationCommand>,
         peer_cmd_sender: MassaSender<PeerManagementCmd>,
+        peer_db: SharedPeerDB,
         config: ProtocolConfig,
         endorsement_cache: SharedEndorsementCache,
         operation_cache: SharedOperationCache,
         cache: SharedBlockCache,
         storage: Storage,
         mip_store: MipStore,
+        clock: ProtocolClock,
         massa_metrics: MassaMetrics,
+        protocol_metrics: SharedProtocolMetrics,
     ) -> Self {
         let block_retrieval_thread = start_retrieval_thread(
             active_connections.clone(),
@@ -81,18 +93,22 @@ impl BlockHandler {
             sender_propagations_ops,
             sender_propagations_endorsements,
             peer_cmd_sender.clone(),
+            peer_db.clone(),
             config.clone(),
             endorsement_cache,
             operation_cache,
             cache.clone(),
             storage.clone_without_refs(),
             mip_store,
+            clock,
             massa_metrics,
+            protocol_metrics,
         );
         let block_propagation_thread = start_propagation_thread(
             active_connections,
             internal_receiver,
             peer_cmd_sender,
+            peer_db,
             config,
             cache,
         );
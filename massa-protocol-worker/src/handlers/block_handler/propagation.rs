@@ -5,7 +5,8 @@
 //! It also manages peer banning for invalid blocks detected by consensus.
 //!
 //! The block propagation system works in the following way:
-//! * a node announces the headers of blocks to its neighbor nodes
+//! * a node announces the headers of blocks to its neighbor nodes,
+//!   along with the ids of their operations when `compact_blocks_enabled` is set
 //! * the neighbor nodes that need that block then ask our Retrieval process for it
 //!
 //! Here we need to announce block headers to other nodes that haven't sene them,
@@ -16,7 +17,13 @@ use super::{
     BlockMessageSerializer,
 };
 use crate::{
-    handlers::{block_handler::BlockMessage, peer_handler::models::PeerManagementCmd},
+    handlers::{
+        block_handler::BlockMessage,
+        peer_handler::models::{
+            BanSeverity, PeerManagementCmd, SharedPeerDB, REPUTATION_EARLY_ATTACK_RELAY,
+            REPUTATION_REJECTED_HEADER,
+        },
+    },
     messages::MessagesSerializer,
     wrap_network::ActiveConnectionsTrait,
 };
@@ -24,10 +31,12 @@ use crossbeam::channel::RecvTimeoutError;
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
 use massa_models::block_header::SecuredHeader;
 use massa_models::block_id::BlockId;
-use massa_protocol_exports::PeerId;
+use massa_models::operation::OperationId;
+use massa_protocol_exports::{BanReason, PeerId};
 use massa_protocol_exports::{ProtocolConfig, ProtocolError};
 use massa_storage::Storage;
 use schnellru::{ByLength, LruMap};
+use std::collections::HashSet;
 use std::thread::JoinHandle;
 use std::time::Instant;
 use tracing::{debug, info, warn};
@@ -40,6 +49,8 @@ struct BlockPropagationData {
     pub _storage: Storage,
     /// Clone of the block header to avoid locking storage during propagation
     pub header: SecuredHeader,
+    /// Clone of the operation ids of the block, sent along with the header in compact blocks
+    pub operation_ids: Vec<OperationId>,
 }
 
 pub struct PropagationThread {
@@ -55,6 +66,8 @@ pub struct PropagationThread {
     active_connections: Box<dyn ActiveConnectionsTrait>,
     /// Channel to send commands to the peer management system (for banning peers)
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
+    /// Shared access to the peer database (to skip quarantined peers)
+    peer_db: SharedPeerDB,
     /// Serializer for block-related messages
     block_serializer: MessagesSerializer,
 }
@@ -73,21 +86,23 @@ impl PropagationThread {
                         BlockHandlerPropagationCommand::IntegratedBlock { block_id, storage } => {
                             debug!("received IntegratedBlock({})", block_id);
 
-                            // get the block header
-                            let header = match storage
-                                .read_blocks()
-                                .get(&block_id)
-                                .map(|block| block.content.header.clone())
-                            {
-                                Some(h) => h,
-                                None => {
-                                    warn!(
-                                        "claimed block {} absent from storage on propagation",
-                                        block_id
-                                    );
-                                    continue;
-                                }
-                            };
+                            // get the block header and operation ids
+                            let (header, operation_ids) =
+                                match storage.read_blocks().get(&block_id).map(|block| {
+                                    (
+                                        block.content.header.clone(),
+                                        block.content.operations.clone(),
+                                    )
+                                }) {
+                                    Some(h) => h,
+                                    None => {
+                                        warn!(
+                                            "claimed block {} absent from storage on propagation",
+                                            block_id
+                                        );
+                                        continue;
+                                    }
+                                };
 
                             // Add the block and its dependencies to the propagation LRU
                             // to ensure they are stored for the time of the propagation.
@@ -97,6 +112,7 @@ impl PropagationThread {
                                     time_added: Instant::now(),
                                     _storage: storage,
                                     header,
+                                    operation_ids,
                                 },
                             );
 
@@ -110,19 +126,18 @@ impl PropagationThread {
                         }
                         BlockHandlerPropagationCommand::AttackBlockDetected(block_id) => {
                             debug!("received AttackBlockDetected({})", block_id);
-                            let peers_to_ban: Vec<PeerId> = self
-                                .cache
-                                .read()
-                                .blocks_known_by_peer
-                                .iter()
-                                .filter_map(|(peer_id, knowledge)| {
-                                    match knowledge.peek(&block_id) {
-                                        Some((true, _)) => Some(*peer_id),
-                                        _ => None,
-                                    }
-                                })
-                                .collect();
+                            let (peers_to_ban, peers_to_penalize) =
+                                self.attack_block_relayers(&block_id);
                             self.ban_peers(&peers_to_ban);
+                            for peer_id in peers_to_penalize {
+                                self.adjust_reputation(peer_id, REPUTATION_EARLY_ATTACK_RELAY);
+                            }
+                        }
+                        BlockHandlerPropagationCommand::HeaderRejected(block_id, reason) => {
+                            debug!("received HeaderRejected({}, {})", block_id, reason);
+                            if !reason.is_benign() {
+                                self.penalize_header_sources(&block_id);
+                            }
                         }
                         BlockHandlerPropagationCommand::Stop => {
                             info!("Stop block propagation thread");
@@ -165,13 +180,29 @@ impl PropagationThread {
             }
         }
 
+        // a passive node relays nothing
+        if self.config.passive_mode {
+            return;
+        }
+
         // update caches based on currently connected peers
         let peers_connected = self.active_connections.get_peer_ids_connected();
+        let quarantined_peers = self.peer_db.read().get_quarantined_peers();
         let mut cache_lock = self.cache.write();
         cache_lock.update_cache(&peers_connected);
         'peer_loop: for (peer_id, known_by_peer) in cache_lock.blocks_known_by_peer.iter_mut() {
-            for (block_id, BlockPropagationData { header, .. }) in
-                self.stored_for_propagation.iter()
+            // do not relay blocks to quarantined peers
+            if quarantined_peers.contains(peer_id) {
+                continue;
+            }
+            for (
+                block_id,
+                BlockPropagationData {
+                    header,
+                    operation_ids,
+                    ..
+                },
+            ) in self.stored_for_propagation.iter()
             {
                 // if the peer already knows about the block, do not propagate it
                 if let Some((true, _)) = known_by_peer.peek(block_id) {
@@ -180,10 +211,18 @@ impl PropagationThread {
 
                 // try to propagate
                 debug!("announcing header {} to peer {}", block_id, peer_id);
+                let message = if self.config.compact_blocks_enabled {
+                    BlockMessage::CompactBlock {
+                        header: header.clone(),
+                        operation_ids: operation_ids.clone(),
+                    }
+                } else {
+                    BlockMessage::Header(header.clone())
+                };
                 match self.active_connections.send_to_peer(
                     peer_id,
                     &self.block_serializer,
-                    BlockMessage::Header(header.clone()).into(),
+                    message.into(),
                     true,
                 ) {
                     Ok(()) => {
@@ -202,22 +241,88 @@ impl PropagationThread {
         }
     }
 
-    /// try to ban a list of peers
+    /// Peers that relayed an attack block, split between the ones to ban and the ones to penalize.
+    /// With `attack_relay_grace`, the peers that sent its header only once before we detected the
+    /// attack may be honest nodes that relayed it before knowing, they are only penalized
+    fn attack_block_relayers(&mut self, block_id: &BlockId) -> (Vec<PeerId>, Vec<PeerId>) {
+        let mut cache = self.cache.write();
+        let mut relayers: HashSet<PeerId> = cache
+            .blocks_known_by_peer
+            .iter()
+            .filter_map(|(peer_id, knowledge)| match knowledge.peek(block_id) {
+                Some((true, _)) => Some(*peer_id),
+                _ => None,
+            })
+            .collect();
+        if !self.config.attack_relay_grace {
+            return (relayers.into_iter().collect(), Vec::new());
+        }
+        // the relays received from now on are banned by the retrieval thread
+        let already_detected = cache.attack_blocks.peek(block_id).is_some();
+        cache.attack_blocks.insert(*block_id, ());
+        let sources = cache
+            .header_sources
+            .peek(block_id)
+            .cloned()
+            .unwrap_or_default();
+        relayers.extend(sources.keys().copied());
+        let (peers_to_ban, peers_to_penalize): (Vec<PeerId>, Vec<PeerId>) = relayers
+            .into_iter()
+            .partition(|peer_id| sources.get(peer_id).map_or(false, |relays| *relays > 1));
+        // the early relayers were already penalized when the attack was first detected
+        if already_detected {
+            return (peers_to_ban, Vec::new());
+        }
+        (peers_to_ban, peers_to_penalize)
+    }
+
+    /// try to ban a list of peers that propagated an attack block
     fn ban_peers(&mut self, peer_ids: &[PeerId]) {
         if let Err(err) = self
             .peer_cmd_sender
-            .try_send(PeerManagementCmd::Ban(peer_ids.to_vec()))
+            .try_send(PeerManagementCmd::BanWithSeverity(
+                peer_ids.to_vec(),
+                BanReason::AttackPropagation,
+                BanSeverity::Critical,
+            ))
             .map_err(|err| ProtocolError::SendError(err.to_string()))
         {
             warn!("could not send Ban command to peer manager: {}", err);
         }
     }
+
+    /// lower the reputation of the peers that sent us a header rejected by consensus
+    fn penalize_header_sources(&mut self, block_id: &BlockId) {
+        let sources = self
+            .cache
+            .write()
+            .header_sources
+            .remove(block_id)
+            .unwrap_or_default();
+        for peer_id in sources.into_keys() {
+            self.adjust_reputation(peer_id, REPUTATION_REJECTED_HEADER);
+        }
+    }
+
+    fn adjust_reputation(&mut self, peer_id: PeerId, delta: i32) {
+        if let Err(err) = self
+            .peer_cmd_sender
+            .try_send(PeerManagementCmd::AdjustReputation(peer_id, delta))
+            .map_err(|err| ProtocolError::SendError(err.to_string()))
+        {
+            warn!(
+                "could not send AdjustReputation command to peer manager: {}",
+                err
+            );
+        }
+    }
 }
 
 pub fn start_propagation_thread(
     active_connections: Box<dyn ActiveConnectionsTrait>,
     receiver: MassaReceiver<BlockHandlerPropagationCommand>,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
+    peer_db: SharedPeerDB,
     config: ProtocolConfig,
     cache: SharedBlockCache,
 ) -> JoinHandle<()> {
@@ -237,6 +342,7 @@ pub fn start_propagation_thread(
                 config,
                 cache,
                 peer_cmd_sender,
+                peer_db,
                 active_connections,
                 block_serializer,
             };
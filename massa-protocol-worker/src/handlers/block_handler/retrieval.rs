@@ -1,10 +1,11 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     thread::JoinHandle,
     time::Instant,
 };
 
 use crate::{
+    clock::ProtocolClock,
     handlers::{
         endorsement_handler::{
             cache::SharedEndorsementCache,
@@ -14,13 +15,16 @@ use crate::{
         operation_handler::{
             cache::SharedOperationCache, commands_propagation::OperationHandlerPropagationCommand,
         },
-        peer_handler::models::{PeerManagementCmd, PeerMessageTuple},
+        peer_handler::models::{
+            BanSeverity, PeerManagementCmd, PeerMessageTuple, SharedPeerDB,
+            REPUTATION_BLOCK_SERVED, REPUTATION_DUPLICATE_HEADER, REPUTATION_STALE_HEADER,
+        },
     },
     messages::{Message, MessagesSerializer},
     wrap_network::ActiveConnectionsTrait,
 };
 use crossbeam::{
-    channel::{at, tick},
+    channel::{at, never, tick},
     select,
 };
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
@@ -31,36 +35,42 @@ use massa_models::{
     block::{Block, BlockSerializer},
     block_header::SecuredHeader,
     block_id::BlockId,
-    endorsement::EndorsementId,
     operation::{
         compute_operations_hash, OperationId, OperationIdSerializer, SecureShareOperation,
     },
     prehash::{PreHashMap, PreHashSet},
     secure_share::SecureShare,
-    timeslots::get_block_slot_timestamp,
+    slot::Slot,
+    timeslots::{get_block_slot_timestamp, get_latest_block_slot_at_timestamp},
 };
 use massa_pool_exports::PoolController;
 use massa_pos_exports::SelectorController;
-use massa_protocol_exports::PeerId;
+use massa_protocol_exports::{BanReason, ConsensusUnavailablePolicy, InflightRequest, PeerId};
 use massa_protocol_exports::{ProtocolConfig, ProtocolError};
 use massa_serialization::{DeserializeError, Deserializer, Serializer};
 use massa_storage::Storage;
-use massa_time::TimeError;
+use massa_time::MassaTime;
 use massa_versioning::versioning::MipStore;
 use rand::thread_rng;
-use rand::{seq::SliceRandom, Rng};
+use rand::Rng;
+use schnellru::{ByLength, LruMap};
 use tracing::{debug, info, warn};
 
 use super::{
-    super::operation_handler::note_operations_from_peer,
+    super::operation_handler::{classify_operations_offense, note_operations_from_peer},
     cache::SharedBlockCache,
     commands_propagation::BlockHandlerPropagationCommand,
     commands_retrieval::BlockHandlerRetrievalCommand,
+    first_headers::FirstHeaderTimes,
     messages::{
         AskForBlockInfo, BlockInfoReply, BlockMessage, BlockMessageDeserializer,
         BlockMessageDeserializerArgs,
     },
-    BlockMessageSerializer,
+    selections::SelectionCache,
+    serving::{BlockInfoServer, BlockServeWorkers},
+    spans::BlockSpans,
+    withholding::WithholdingTracker,
+    BlockMessageSerializer, SharedProtocolMetrics,
 };
 
 /// Info about a block we've seen
@@ -73,21 +83,54 @@ pub(crate) struct BlockInfo {
     /// Operations and endorsements contained in the block,
     /// if we've received them already, and none otherwise.
     pub(crate) storage: Storage,
+    /// Peers that announced the header of the block, asked first
+    pub(crate) announced_by: HashSet<PeerId>,
+    /// Peers that didn't answer an ask for this block in time or disconnected before answering, asked last
+    pub(crate) failed_peers: HashSet<PeerId>,
+    /// Number of asks for this block that timed out or were cancelled by the disconnection of the peer,
+    /// we stop asking after `max_block_ask_retries`
+    pub(crate) failed_asks: usize,
+    /// Rank of the block in the order the blocks were added to the wishlist
+    pub(crate) wishlist_rank: u64,
 }
 
 impl BlockInfo {
-    fn new(header: Option<SecuredHeader>, storage: Storage) -> Self {
+    fn new(header: Option<SecuredHeader>, storage: Storage, wishlist_rank: u64) -> Self {
         BlockInfo {
             header,
             operation_ids: None,
             storage,
+            announced_by: HashSet::new(),
+            failed_peers: HashSet::new(),
+            failed_asks: 0,
+            wishlist_rank,
+        }
+    }
+
+    /// Priority of the block when asking it: blocks at the earliest slots first,
+    /// in wishlist order within a slot. Blocks whose header is unknown come last.
+    fn ask_priority(&self) -> (bool, Option<Slot>, u64) {
+        match &self.header {
+            Some(header) => (false, Some(header.content.slot), self.wishlist_rank),
+            None => (true, None, self.wishlist_rank),
         }
     }
 }
 
+/// Operation list of a block being received in several chunks
+struct PartialOperationList {
+    /// Peer sending the chunks
+    peer_id: PeerId,
+    /// Length of the whole list
+    total: u64,
+    /// IDs received so far, in list order
+    operation_ids: Vec<OperationId>,
+}
+
 pub struct RetrievalThread {
     active_connections: Box<dyn ActiveConnectionsTrait>,
-    selector_controller: Box<dyn SelectorController>,
+    /// draws used to check the endorsements of the headers
+    selections: SelectionCache,
     consensus_controller: Box<dyn ConsensusController>,
     pool_controller: Box<dyn PoolController>,
     receiver_network: MassaReceiver<PeerMessageTuple>,
@@ -95,19 +138,48 @@ pub struct RetrievalThread {
     receiver: MassaReceiver<BlockHandlerRetrievalCommand>,
     block_message_serializer: MessagesSerializer,
     block_wishlist: PreHashMap<BlockId, BlockInfo>,
-    asked_blocks: HashMap<PeerId, PreHashMap<BlockId, Instant>>,
+    asked_blocks: HashMap<PeerId, PreHashMap<BlockId, MassaTime>>,
+    /// blocks with an outstanding ask and the time of the ask,
+    /// not asked again until the ask times out or completes
+    in_flight_blocks: PreHashMap<BlockId, MassaTime>,
+    /// rank given to the next block added to the wishlist
+    next_wishlist_rank: u64,
+    /// answers the block data requests of the peers
+    block_server: BlockInfoServer,
+    /// threads answering the block data requests with `block_server`, none to answer them here
+    block_serve_workers: Option<BlockServeWorkers>,
+    /// operation ids of the blocks received as compact blocks and not wanted yet, with the peer that sent them
+    compact_blocks: LruMap<BlockId, (PeerId, Vec<OperationId>)>,
+    /// operation lists of the wanted blocks being received in chunks
+    operation_id_chunks: PreHashMap<BlockId, PartialOperationList>,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
+    peer_db: SharedPeerDB,
     sender_propagation_ops: MassaSender<OperationHandlerPropagationCommand>,
     sender_propagation_endorsements: MassaSender<EndorsementHandlerPropagationCommand>,
     endorsement_cache: SharedEndorsementCache,
     operation_cache: SharedOperationCache,
-    next_timer_ask_block: Instant,
+    next_timer_ask_block: MassaTime,
+    /// time of the block asks and of their timeouts
+    clock: ProtocolClock,
     cache: SharedBlockCache,
     config: ProtocolConfig,
     storage: Storage,
     mip_store: MipStore,
     massa_metrics: MassaMetrics,
+    protocol_metrics: SharedProtocolMetrics,
     operation_id_serializer: OperationIdSerializer,
+    /// tracing spans of the retrieval of each block
+    block_spans: BlockSpans,
+    /// asks answered and unanswered by the peers for the blocks they announced
+    withholding: WithholdingTracker,
+    /// time at which the header of each recent block was first received
+    first_header_times: FirstHeaderTimes,
+    /// new headers waiting to be registered in consensus together, see `block_header_batch_window`
+    header_batch: Vec<(BlockId, SecuredHeader)>,
+    /// time at which the pending headers are registered
+    header_batch_deadline: Option<Instant>,
+    /// headers consensus couldn't take, registered again every `consensus_retry_interval`
+    consensus_retry_buffer: VecDeque<(BlockId, SecuredHeader)>,
 }
 
 impl RetrievalThread {
@@ -128,6 +200,13 @@ impl RetrievalThread {
             });
 
         let tick_update_metrics = tick(self.massa_metrics.tick_delay);
+        let tick_prune_served_op_ids = tick(self.config.served_op_ids_cache_ttl.to_duration());
+        let tick_consensus_retry = match self.config.consensus_unavailable_policy {
+            ConsensusUnavailablePolicy::Drop => never(),
+            ConsensusUnavailablePolicy::Retry => {
+                tick(self.config.consensus_retry_interval.to_duration())
+            }
+        };
         loop {
             select! {
                 recv(self.receiver_network) -> msg => {
@@ -137,8 +216,17 @@ impl RetrievalThread {
                             let (rest, message) = match block_message_deserializer
                                 .deserialize::<DeserializeError>(&message) {
                                 Ok((rest, message)) => (rest, message),
+                                Err(_) if BlockMessage::has_unknown_type(&message) => {
+                                    debug!("Skipping block message of unknown type from peer {}", peer_id);
+                                    continue;
+                                }
                                 Err(err) => {
-                                    warn!("Error in deserializing block message: {:?}", err);
+                                    warn!("Error in deserializing block message from peer {}: {:?}", peer_id, err);
+                                    // malformed messages, like operation id lists longer than `max_operations_per_block`,
+                                    // are rejected by the deserializer before allocating them
+                                    if let Err(err) = self.ban_peers(&[peer_id], BanReason::ProtocolViolation, BanSeverity::Major) {
+                                        warn!("Error while banning peer {} err: {:?}", peer_id, err);
+                                    }
                                     continue;
                                 }
                             };
@@ -148,7 +236,10 @@ impl RetrievalThread {
                             }
                             match message {
                                 BlockMessage::DataRequest{block_id, block_info} => {
-                                    self.on_ask_for_block_info_received(peer_id, block_id, block_info);
+                                    match &self.block_serve_workers {
+                                        Some(workers) => workers.submit(peer_id, block_id, block_info),
+                                        None => self.block_server.serve(peer_id, block_id, block_info),
+                                    }
                                 }
                                 BlockMessage::DataResponse{block_id, block_info} => {
                                    self.on_block_info_received(peer_id, block_id, block_info);
@@ -158,6 +249,10 @@ impl RetrievalThread {
                                     self.on_block_header_received(peer_id, header);
                                     self.update_block_retrieval();
                                 }
+                                BlockMessage::CompactBlock{header, operation_ids} => {
+                                    self.on_compact_block_received(peer_id, header, operation_ids);
+                                    self.update_block_retrieval();
+                                }
                             }
                         },
                         Err(_) => {
@@ -173,25 +268,69 @@ impl RetrievalThread {
                             match command {
                                 BlockHandlerRetrievalCommand::WishlistDelta { new, remove } => {
                                     massa_trace!("protocol.protocol_worker.process_command.wishlist_delta.begin", { "new": new, "remove": remove });
-                                    for (block_id, header) in new.into_iter() {
-                                        self.block_wishlist.insert(
-                                            block_id,
-                                            BlockInfo::new(header, self.storage.clone_without_refs()),
-                                        );
-                                    }
                                     // Cleanup the knowledge that we asked this list of blocks to nodes.
                                     self.remove_asked_blocks(&remove);
 
-                                    // Remove from the wishlist.
+                                    // Remove from the wishlist first, to make room for the new blocks.
                                     for block_id in remove.iter() {
                                         self.block_wishlist.remove(block_id);
+                                        self.block_spans.forget(block_id);
+                                        self.compact_blocks.remove(block_id);
+                                        self.operation_id_chunks.remove(block_id);
                                     }
 
+                                    // The blocks beyond `max_wishlist_size` aren't tracked
+                                    let mut dropped = 0;
+                                    for (block_id, header) in new.into_iter() {
+                                        if remove.contains(&block_id) {
+                                            continue;
+                                        }
+                                        // a block already wanted keeps its retrieval progress and its pending asks
+                                        if let Some(info) = self.block_wishlist.get_mut(&block_id) {
+                                            if info.header.is_none() {
+                                                info.header = header;
+                                            }
+                                            continue;
+                                        }
+                                        if self.block_wishlist.len() >= self.config.max_wishlist_size {
+                                            dropped += 1;
+                                            continue;
+                                        }
+                                        let _span = self.block_spans.wishlisted(&block_id);
+                                        self.block_wishlist.insert(
+                                            block_id,
+                                            BlockInfo::new(header, self.storage.clone_without_refs(), self.next_wishlist_rank),
+                                        );
+                                        self.next_wishlist_rank += 1;
+                                    }
+                                    if dropped > 0 {
+                                        warn!("block wishlist full: {} wanted blocks dropped", dropped);
+                                    }
+                                    self.protocol_metrics.write().wishlist_size = self.block_wishlist.len() as u64;
+
+                                    // the blocks already relayed as compact blocks only miss their operations
+                                    self.rebuild_compact_blocks();
+
                                     // update block asking process
                                     self.update_block_retrieval();
                                 },
+                                BlockHandlerRetrievalCommand::UpdateConfig(update) => {
+                                    update.apply(&mut self.config);
+                                },
+                                BlockHandlerRetrievalCommand::PeersDisconnected(peer_ids) => {
+                                    debug!("peers {:?} disconnected, re-asking their blocks", peer_ids);
+                                    self.update_block_retrieval();
+                                },
+                                BlockHandlerRetrievalCommand::GetInflightRequests { responder } => {
+                                    responder.try_send(self.get_inflight_requests()).unwrap_or_else(|_| warn!("Failed to send in flight block requests to responder"));
+                                },
+                                BlockHandlerRetrievalCommand::GetFirstHeaderTimes { responder } => {
+                                    responder.try_send(self.first_header_times.snapshot(MassaTime::now())).unwrap_or_else(|_| warn!("Failed to send first header times to responder"));
+                                },
                                 BlockHandlerRetrievalCommand::Stop => {
                                     info!("Stop block retrieval thread from command receiver (Stop)");
+                                    self.register_header_batch();
+                                    self.retry_consensus_registrations();
                                     return;
                                 }
                             }
@@ -217,6 +356,8 @@ impl RetrievalThread {
                             count,
                         );
                     }
+                    self.massa_metrics
+                        .set_block_retrieval_in_flight_requests(self.in_flight_blocks.len());
 
                     {
                         let ope_read = self.operation_cache.read();
@@ -228,157 +369,19 @@ impl RetrievalThread {
                         );
                     }
                 }
-                recv(at(self.next_timer_ask_block)) -> _ => {
+                recv(tick_prune_served_op_ids) -> _ => {
+                    self.block_server.prune_served_op_ids_cache();
+                }
+                recv(tick_consensus_retry) -> _ => {
+                    self.retry_consensus_registrations();
+                }
+                recv(self.clock.at(self.next_timer_ask_block)) -> _ => {
                     self.update_block_retrieval();
                 }
-            }
-        }
-    }
-
-    /// A remote node asked the local node for block data
-    ///
-    /// We send the block's operation ids if the foreign node asked for `AskForBlockInfo::Info`
-    /// or a subset of the full operations of the block if it asked for `AskForBlockInfo::Operations`.
-    fn on_ask_for_block_info_received(
-        &mut self,
-        from_peer_id: PeerId,
-        block_id: BlockId,
-        info_requested: AskForBlockInfo,
-    ) {
-        debug!(
-            "peer {} asked for block info on block {}: {:?}",
-            &from_peer_id, block_id, &info_requested
-        );
-
-        // updates on the remote peer's knowledge on blocks, operations and endorsements
-        // only applied if the response is successfully sent to the peer
-        let mut block_knowledge_updates = PreHashSet::default();
-        let mut operation_knowledge_updates = PreHashSet::default();
-        let mut endorsement_knowledge_updates = PreHashSet::default();
-
-        // retrieve block data from storage
-        let stored_header_op_ids = self.storage.read_blocks().get(&block_id).map(|block| {
-            (
-                block.content.header.clone(),
-                block.content.operations.clone(),
-            )
-        });
-
-        let block_info_response = match (stored_header_op_ids, info_requested) {
-            (None, _) => BlockInfoReply::NotFound,
-
-            (Some((header, _)), AskForBlockInfo::Header) => {
-                // the peer asked for a block header
-
-                // once sent, the peer will know about that block,
-                // no need to announce this header to that peer anymore
-                block_knowledge_updates.insert(block_id);
-
-                // once sent, the peer will know about the endorsements in that block,
-                // no need to announce those endorsements to that peer anymore
-                endorsement_knowledge_updates.extend(
-                    header
-                        .content
-                        .endorsements
-                        .iter()
-                        .map(|e| e.id)
-                        .collect::<PreHashSet<EndorsementId>>(),
-                );
-
-                BlockInfoReply::Header(header)
-            }
-            (Some((_, block_op_ids)), AskForBlockInfo::OperationIds) => {
-                // the peer asked for the operation IDs of the block
-
-                // once sent, the peer will know about those operations,
-                // no need to announce their IDs to that peer anymore
-                operation_knowledge_updates.extend(block_op_ids.iter().cloned());
-
-                BlockInfoReply::OperationIds(block_op_ids)
-            }
-            (Some((_, block_op_ids)), AskForBlockInfo::Operations(mut asked_ops)) => {
-                // the peer asked for a list of full operations from the block
-
-                // retain only ops that belong to the block
-                {
-                    let block_op_ids_set: PreHashSet<OperationId> =
-                        block_op_ids.iter().copied().collect();
-                    asked_ops.retain(|id| block_op_ids_set.contains(id));
+                recv(self.header_batch_deadline.map_or_else(never, at)) -> _ => {
+                    self.register_header_batch();
                 }
-
-                // Send the operations that are available in storage
-                let returned_ops: Vec<_> = {
-                    let op_storage_lock = self.storage.read_operations();
-                    asked_ops
-                        .into_iter()
-                        .filter_map(|id| op_storage_lock.get(&id))
-                        .cloned()
-                        .collect()
-                };
-
-                // mark the peer as knowing about those operations,
-                // no need to announce their IDs to them anymore
-                operation_knowledge_updates.extend(
-                    returned_ops
-                        .iter()
-                        .map(|op| op.id)
-                        .collect::<PreHashSet<OperationId>>(),
-                );
-
-                BlockInfoReply::Operations(returned_ops)
-            }
-        };
-
-        debug!(
-            "sending reply for block {} info to {}",
-            block_id, from_peer_id
-        );
-
-        // send response to peer
-        if let Err(err) = self.active_connections.send_to_peer(
-            &from_peer_id,
-            &self.block_message_serializer,
-            BlockMessage::DataResponse {
-                block_id,
-                block_info: block_info_response,
             }
-            .into(),
-            true,
-        ) {
-            warn!(
-                "Error while sending reply for block {} to {}: {:?}",
-                block_id, from_peer_id, err
-            );
-            return;
-        }
-
-        // here we know that the response was successfully sent to the peer
-        // so we can update our vision of the peer's knowledge on blocks, operations and endorsements
-        if !block_knowledge_updates.is_empty() {
-            self.cache.write().insert_peer_known_block(
-                &from_peer_id,
-                &block_knowledge_updates.into_iter().collect::<Vec<_>>(),
-                true,
-            );
-        }
-        if !operation_knowledge_updates.is_empty() {
-            self.operation_cache.write().insert_peer_known_ops(
-                &from_peer_id,
-                &operation_knowledge_updates
-                    .into_iter()
-                    .map(|op_id| op_id.prefix())
-                    .collect::<Vec<_>>(),
-            );
-        }
-        if !endorsement_knowledge_updates.is_empty() {
-            self.endorsement_cache
-                .write()
-                .insert_peer_known_endorsements(
-                    &from_peer_id,
-                    &endorsement_knowledge_updates
-                        .into_iter()
-                        .collect::<Vec<_>>(),
-                );
         }
     }
 
@@ -389,6 +392,7 @@ impl RetrievalThread {
         block_id: BlockId,
         block_info: BlockInfoReply,
     ) {
+        let _span = self.block_spans.data_response(&block_id, &from_peer_id);
         match block_info {
             BlockInfoReply::Header(header) => {
                 // Verify and send it consensus
@@ -401,6 +405,20 @@ impl RetrievalThread {
                 // the block_header.
                 self.on_block_operation_list_received(from_peer_id, block_id, operation_list);
             }
+            BlockInfoReply::OperationIdsChunk {
+                total,
+                offset,
+                operation_ids,
+            } => {
+                // Reassemble the operation list, which is then handled as if it was received at once.
+                self.on_block_operation_ids_chunk_received(
+                    from_peer_id,
+                    block_id,
+                    total,
+                    offset,
+                    operation_ids,
+                );
+            }
             BlockInfoReply::Operations(operations) => {
                 // Send operations to pool,
                 // before performing the below checks,
@@ -408,29 +426,140 @@ impl RetrievalThread {
                 self.on_block_full_operations_received(from_peer_id, block_id, operations);
             }
             BlockInfoReply::NotFound => {
-                // The peer doesn't know about the block. Mark it as such.
-                self.cache
-                    .write()
-                    .insert_peer_known_block(&from_peer_id, &[block_id], false);
+                self.on_block_not_found(from_peer_id, block_id);
             }
         }
     }
 
+    /// The peer declined to send the data of a block, for example because it pruned it.
+    /// Instead of waiting for the ask to time out, the block is asked to another peer right away.
+    /// Declining a block it announced counts as withholding it.
+    fn on_block_not_found(&mut self, from_peer_id: PeerId, block_id: BlockId) {
+        // The peer doesn't know about the block. Mark it as such.
+        self.cache
+            .write()
+            .insert_peer_known_block(&from_peer_id, &[block_id], false);
+
+        let was_asked = self
+            .asked_blocks
+            .get_mut(&from_peer_id)
+            .map_or(false, |asked_blocks| {
+                asked_blocks.remove(&block_id).is_some()
+            });
+        if !was_asked {
+            return;
+        }
+        debug!(
+            "peer {} doesn't have block {}, asking another peer",
+            from_peer_id, block_id
+        );
+
+        let announced = self
+            .cache
+            .read()
+            .header_sources
+            .peek(&block_id)
+            .map_or(false, |sources| sources.contains_key(&from_peer_id));
+        if announced
+            && self
+                .withholding
+                .record_withheld(&from_peer_id, self.clock.now())
+        {
+            warn!("peer {} announced blocks it didn't serve", from_peer_id);
+            if let Err(err) = self.ban_peers(
+                &[from_peer_id],
+                BanReason::WithholdingData,
+                BanSeverity::Minor,
+            ) {
+                warn!("Error while banning peer {} err: {:?}", from_peer_id, err);
+            }
+        }
+
+        // the next asks go to other peers first
+        if let Some(info) = self.block_wishlist.get_mut(&block_id) {
+            info.failed_peers.insert(from_peer_id);
+            info.failed_asks += 1;
+        }
+        self.update_block_retrieval();
+    }
+
     /// On block header received from a node.
     fn on_block_header_received(&mut self, from_peer_id: PeerId, header: SecuredHeader) {
         debug!("received header {} from {}", header.id, from_peer_id);
 
+        let received_at = MassaTime::now();
         let block_id = header.id;
+        let _span = self.block_spans.header_received(&block_id, &from_peer_id);
+
+        if self.is_stale_header(&header) {
+            debug!(
+                "dropping header {} of slot {} from {}: too old",
+                block_id, header.content.slot, from_peer_id
+            );
+            if let Err(err) = self.adjust_reputation(from_peer_id, REPUTATION_STALE_HEADER) {
+                warn!("Error while penalizing peer {}: {}", from_peer_id, err);
+            }
+            return;
+        }
+
+        if self.config.attack_relay_grace
+            && self.cache.read().attack_blocks.peek(&block_id).is_some()
+        {
+            warn!(
+                "peer {} relayed block {} after it was detected as an attack",
+                from_peer_id, block_id
+            );
+            if let Err(err) = self.ban_peers(
+                &[from_peer_id],
+                BanReason::AttackPropagation,
+                BanSeverity::Critical,
+            ) {
+                warn!("Error while banning peer {} err: {:?}", &from_peer_id, err);
+            }
+            return;
+        }
+
+        if let Some(duplicates) = self.count_duplicate_header(&from_peer_id, &block_id) {
+            debug!(
+                "dropping header {} from {}: already sent {} times",
+                block_id, from_peer_id, duplicates
+            );
+            if duplicates > self.config.max_duplicate_headers {
+                if let Err(err) = self.adjust_reputation(from_peer_id, REPUTATION_DUPLICATE_HEADER)
+                {
+                    warn!("Error while penalizing peer {}: {}", from_peer_id, err);
+                }
+            }
+            return;
+        }
 
         // Check header and update knowledge info
         let is_new = match self.note_header_from_peer(&header, &from_peer_id) {
-            Ok(is_new) => is_new,
+            Ok(is_new) => {
+                self.first_header_times.record(block_id, received_at);
+                let mut cache_write = self.cache.write();
+                cache_write.insert_header_source(block_id, from_peer_id);
+                if self.config.duplicate_header_window.as_millis() > 0 {
+                    cache_write.insert_recent_header(from_peer_id, block_id);
+                }
+                is_new
+            }
             Err(err) => {
                 warn!(
                     "peer {} sent us critically incorrect header: {}",
                     &from_peer_id, err
                 );
-                if let Err(err) = self.ban_peers(&[from_peer_id]) {
+                let (reason, severity) = match err {
+                    ProtocolError::WrongSignature => {
+                        (BanReason::InvalidBlockSignature, BanSeverity::Major)
+                    }
+                    // may come from a node whose clock is wrong, it is banned if it keeps on
+                    ProtocolError::FutureBlock { .. } => {
+                        (BanReason::ProtocolViolation, BanSeverity::Minor)
+                    }
+                    _ => (BanReason::ProtocolViolation, BanSeverity::Major),
+                };
+                if let Err(err) = self.ban_peers(&[from_peer_id], reason, severity) {
                     warn!("Error while banning peer {} err: {:?}", &from_peer_id, err);
                 }
                 return;
@@ -440,6 +569,18 @@ impl RetrievalThread {
         if let Some(info) = self.block_wishlist.get_mut(&block_id) {
             // We are actively trying to get this block
 
+            if info.announced_by.insert(from_peer_id)
+                && self.config.max_block_ask_retries > 0
+                && info.failed_asks >= self.config.max_block_ask_retries
+            {
+                // a new peer has the block, give it a chance
+                debug!(
+                    "peer {} announced block {}, retrying to retrieve it",
+                    from_peer_id, block_id
+                );
+                info.failed_asks = 0;
+            }
+
             if info.header.is_none() {
                 // we were looking for the missing header
 
@@ -450,12 +591,150 @@ impl RetrievalThread {
                 // This is done so that update_block_retrieval can prioritize asking the rest of the block data
                 // to that same peer that just gave us the header, and not exclude the peer
                 // because we still believe we are actively asking it for stuff.
+                self.record_block_ask_served(&block_id);
                 self.remove_asked_blocks(&[block_id].into_iter().collect())
             }
         } else if is_new {
             // if not in wishlist, and if the header is new, we send it to consensus
-            self.consensus_controller
-                .register_block_header(block_id, header);
+            self.register_block_header(block_id, header);
+        }
+    }
+
+    /// On compact block received from a node: the header goes through the usual checks,
+    /// and the operation ids are kept to rebuild the block once consensus wants it.
+    fn on_compact_block_received(
+        &mut self,
+        from_peer_id: PeerId,
+        header: SecuredHeader,
+        operation_ids: Vec<OperationId>,
+    ) {
+        let block_id = header.id;
+        self.on_block_header_received(from_peer_id, header);
+        if self.cache.read().checked_headers.peek(&block_id).is_none() {
+            // the header was rejected
+            return;
+        }
+        self.compact_blocks
+            .insert(block_id, (from_peer_id, operation_ids));
+        self.rebuild_compact_blocks();
+    }
+
+    /// Fill the operation list of the wanted blocks received as compact blocks,
+    /// so that only the operations missing from our storage are asked.
+    /// A compact operation list that doesn't match the header of its block is handled like an invalid
+    /// operation list answer, and the block is then retrieved through the usual data requests.
+    fn rebuild_compact_blocks(&mut self) {
+        let wanted: Vec<BlockId> = self
+            .compact_blocks
+            .iter()
+            .map(|(block_id, _)| *block_id)
+            .filter(|block_id| {
+                self.block_wishlist.get(block_id).map_or(false, |info| {
+                    info.header.is_some() && info.operation_ids.is_none()
+                })
+            })
+            .collect();
+        for block_id in wanted {
+            if let Some((from_peer_id, operation_ids)) = self.compact_blocks.remove(&block_id) {
+                self.on_block_operation_list_received(from_peer_id, block_id, operation_ids);
+            }
+        }
+    }
+
+    /// Number of copies of an accepted header sent again by a peer within `duplicate_header_window`,
+    /// or none if the header isn't a duplicate
+    fn count_duplicate_header(&self, from_peer_id: &PeerId, block_id: &BlockId) -> Option<u32> {
+        let window = self.config.duplicate_header_window;
+        if window.as_millis() == 0 {
+            return None;
+        }
+        self.cache
+            .write()
+            .count_duplicate_header(from_peer_id, block_id, window.to_duration())
+    }
+
+    /// Send a new header to consensus, along with the other headers received
+    /// within `block_header_batch_window` if it is set
+    fn register_block_header(&mut self, block_id: BlockId, header: SecuredHeader) {
+        if self.config.block_header_batch_window.as_millis() == 0 {
+            match self.config.consensus_unavailable_policy {
+                ConsensusUnavailablePolicy::Drop => self
+                    .consensus_controller
+                    .register_block_header(block_id, header),
+                ConsensusUnavailablePolicy::Retry => {
+                    if let Err(err) = self
+                        .consensus_controller
+                        .try_register_block_header(block_id, header.clone())
+                    {
+                        warn!("header {} kept for consensus: {}", block_id, err);
+                        self.keep_for_consensus_retry(vec![(block_id, header)]);
+                    }
+                }
+            }
+            return;
+        }
+        self.header_batch.push((block_id, header));
+        if self.header_batch_deadline.is_none() {
+            self.header_batch_deadline =
+                Some(Instant::now() + self.config.block_header_batch_window.to_duration());
+        }
+    }
+
+    /// Register the pending headers in consensus, by slot
+    fn register_header_batch(&mut self) {
+        self.header_batch_deadline = None;
+        if self.header_batch.is_empty() {
+            return;
+        }
+        let mut headers = std::mem::take(&mut self.header_batch);
+        // the sort is stable, headers of the same slot keep their arrival order
+        headers.sort_by_key(|(_, header)| header.content.slot);
+        match self.config.consensus_unavailable_policy {
+            ConsensusUnavailablePolicy::Drop => {
+                self.consensus_controller.register_block_headers(headers)
+            }
+            ConsensusUnavailablePolicy::Retry => {
+                if let Err(err) = self
+                    .consensus_controller
+                    .try_register_block_headers(headers.clone())
+                {
+                    warn!("{} headers kept for consensus: {}", headers.len(), err);
+                    self.keep_for_consensus_retry(headers);
+                }
+            }
+        }
+    }
+
+    /// Keep headers consensus couldn't take to register them again,
+    /// the oldest ones are dropped beyond `consensus_retry_buffer_size`
+    fn keep_for_consensus_retry(&mut self, headers: Vec<(BlockId, SecuredHeader)>) {
+        self.consensus_retry_buffer.extend(headers);
+        let excess = self
+            .consensus_retry_buffer
+            .len()
+            .saturating_sub(self.config.consensus_retry_buffer_size);
+        if excess > 0 {
+            self.consensus_retry_buffer.drain(..excess);
+            warn!("consensus retry buffer full: {} headers dropped", excess);
+        }
+    }
+
+    /// Register again in consensus the headers it couldn't take, they are kept if it still can't
+    fn retry_consensus_registrations(&mut self) {
+        if self.consensus_retry_buffer.is_empty() {
+            return;
+        }
+        let headers: Vec<_> = self.consensus_retry_buffer.iter().cloned().collect();
+        match self
+            .consensus_controller
+            .try_register_block_headers(headers)
+        {
+            Ok(()) => self.consensus_retry_buffer.clear(),
+            Err(err) => debug!(
+                "{} headers still kept for consensus: {}",
+                self.consensus_retry_buffer.len(),
+                err
+            ),
         }
     }
 
@@ -494,6 +773,53 @@ impl RetrievalThread {
         Ok(())
     }
 
+    /// Whether a header is more than `max_past_slots` before the latest final slot of its thread.
+    /// The final periods are the ones consensus gives to the pool.
+    fn is_stale_header(&self, header: &SecuredHeader) -> bool {
+        if self.config.max_past_slots == 0 {
+            return false;
+        }
+        let slot = header.content.slot;
+        let Some(final_period) = self
+            .pool_controller
+            .get_final_cs_periods()
+            .get(slot.thread as usize)
+            .copied()
+        else {
+            return false;
+        };
+        Slot::new(final_period, slot.thread)
+            .slots_since(&slot, self.config.thread_count)
+            .map_or(false, |slots_behind| {
+                slots_behind > self.config.max_past_slots
+            })
+    }
+
+    /// Check that a header slot isn't more than `max_future_slots` after the current slot
+    fn check_future_slot(&self, slot: Slot) -> Result<(), ProtocolError> {
+        if self.config.max_future_slots == 0 {
+            return Ok(());
+        }
+        let current = get_latest_block_slot_at_timestamp(
+            self.config.thread_count,
+            self.config.t0,
+            self.config.genesis_timestamp,
+            MassaTime::now().saturating_add(self.config.future_slot_tolerance),
+        )?
+        .unwrap_or_else(Slot::min);
+        match slot.slots_since(&current, self.config.thread_count) {
+            Ok(slots_ahead) if slots_ahead > self.config.max_future_slots => {
+                Err(ProtocolError::FutureBlock {
+                    slot,
+                    current,
+                    max_future_slots: self.config.max_future_slots,
+                })
+            }
+            // not after the current slot
+            _ => Ok(()),
+        }
+    }
+
     /// Performs validity checks on a block header,
     /// and if valid update the node's view of its surrounding peers.
     ///
@@ -503,6 +829,7 @@ impl RetrievalThread {
     ///
     /// Checks performed on Header:
     /// - Not genesis
+    /// - Not too far in the future
     /// - Compatible version
     /// - Can compute a `BlockId`
     /// - Valid signature
@@ -520,6 +847,9 @@ impl RetrievalThread {
             return Err(ProtocolError::InvalidBlock("block is genesis".to_string()));
         }
 
+        // refuse blocks too far in the future, their endorsements and signature aren't checked
+        self.check_future_slot(header.content.slot)?;
+
         // Check that our node supports the block version
         self.check_network_version_compatibility(header)?;
 
@@ -577,7 +907,7 @@ impl RetrievalThread {
             header.content.endorsements.clone(),
             from_peer_id,
             &self.endorsement_cache,
-            self.selector_controller.as_ref(),
+            &mut self.selections,
             &self.storage,
             &self.config,
             &self.sender_propagation_endorsements,
@@ -591,10 +921,8 @@ impl RetrievalThread {
 
         // check header signature
         if let Err(err) = header.verify_signature() {
-            return Err(ProtocolError::InvalidBlock(format!(
-                "invalid header signature: {}",
-                err
-            )));
+            debug!("invalid header signature: {}", err);
+            return Err(ProtocolError::WrongSignature);
         };
 
         // check endorsement integrity within the context of the header
@@ -654,9 +982,48 @@ impl RetrievalThread {
     }
 
     /// send a ban peer command to the peer handler
-    fn ban_peers(&mut self, peer_ids: &[PeerId]) -> Result<(), ProtocolError> {
+    fn ban_peers(
+        &mut self,
+        peer_ids: &[PeerId],
+        reason: BanReason,
+        severity: BanSeverity,
+    ) -> Result<(), ProtocolError> {
+        self.peer_cmd_sender
+            .try_send(PeerManagementCmd::BanWithSeverity(
+                peer_ids.to_vec(),
+                reason,
+                severity,
+            ))
+            .map_err(|err| ProtocolError::SendError(err.to_string()))
+    }
+
+    /// Snapshot of the asks waiting for an answer, oldest first
+    fn get_inflight_requests(&self) -> Vec<InflightRequest> {
+        let mut requests: Vec<InflightRequest> = self
+            .asked_blocks
+            .iter()
+            .flat_map(|(peer_id, blocks)| {
+                blocks
+                    .iter()
+                    .map(move |(block_id, asked_at)| InflightRequest {
+                        block_id: *block_id,
+                        peer_id: *peer_id,
+                        asked_at: *asked_at,
+                        retries: self
+                            .block_wishlist
+                            .get(block_id)
+                            .map_or(0, |info| info.failed_asks),
+                    })
+            })
+            .collect();
+        requests.sort_by_key(|request| request.asked_at);
+        requests
+    }
+
+    /// send a reputation change command to the peer handler
+    fn adjust_reputation(&mut self, peer_id: PeerId, delta: i32) -> Result<(), ProtocolError> {
         self.peer_cmd_sender
-            .try_send(PeerManagementCmd::Ban(peer_ids.to_vec()))
+            .try_send(PeerManagementCmd::AdjustReputation(peer_id, delta))
             .map_err(|err| ProtocolError::SendError(err.to_string()))
     }
 
@@ -667,12 +1034,26 @@ impl RetrievalThread {
                 asked_blocks.remove(remove_h);
             }
         }
+        for remove_h in remove_hashes {
+            self.in_flight_blocks.remove(remove_h);
+        }
+    }
+
+    /// Record the download latency of a block ask answered with a valid response
+    fn record_block_ask_served(&mut self, block_id: &BlockId) {
+        if let Some(asked_at) = self.in_flight_blocks.get(block_id) {
+            self.protocol_metrics
+                .write()
+                .record_block_download(*asked_at, self.clock.now());
+        }
     }
 
     /// Mark a block as invalid
     fn mark_block_as_invalid(&mut self, block_id: &BlockId) {
         // stop retrieving the block
+        self.block_spans.forget(block_id);
         if let Some(wishlist_info) = self.block_wishlist.remove(block_id) {
+            self.protocol_metrics.write().wishlist_size = self.block_wishlist.len() as u64;
             if let Some(header) = wishlist_info.header {
                 // notify consensus that the block is invalid
                 self.consensus_controller
@@ -680,7 +1061,7 @@ impl RetrievalThread {
             }
         }
 
-        // ban all peers that know about this block
+        // ban all peers that know about this block, they may only have relayed it
         let mut peers_to_ban = Vec::new();
         {
             let cache_read = self.cache.read();
@@ -691,7 +1072,11 @@ impl RetrievalThread {
             }
         }
         if !peers_to_ban.is_empty() {
-            if let Err(err) = self.ban_peers(&peers_to_ban) {
+            if let Err(err) = self.ban_peers(
+                &peers_to_ban,
+                BanReason::ProtocolViolation,
+                BanSeverity::Minor,
+            ) {
                 warn!(
                     "Error while banning peers {:?} err: {:?}",
                     peers_to_ban, err
@@ -719,7 +1104,25 @@ impl RetrievalThread {
             "received operation list for block {} from {}",
             block_id, &from_peer_id
         );
-        // Note that the length of the operation list was checked at deserialization to not overflow the max per block.
+        // `max_operations_per_block` is the hard ceiling of the operations of a block, the length of the list
+        // is checked against it at deserialization too. The headers only commit to the operations of the block
+        // through their merkle root: there is no declared count to check against.
+        if operation_ids.len() > self.config.max_operations_per_block as usize {
+            warn!(
+                "Peer id {} sent us {} operation ids for block id {}, more than the max per block",
+                from_peer_id,
+                operation_ids.len(),
+                block_id
+            );
+            if let Err(err) = self.ban_peers(
+                &[from_peer_id],
+                BanReason::InvalidOperationList,
+                BanSeverity::Major,
+            ) {
+                warn!("Error while banning peer {} err: {:?}", from_peer_id, err);
+            }
+            return;
+        }
 
         // All operation ids sent into a set to deduplicate and search quickly for presence
         let operation_ids_set: PreHashSet<OperationId> = operation_ids.iter().cloned().collect();
@@ -762,7 +1165,11 @@ impl RetrievalThread {
             != computed_operations_hash
         {
             warn!("Peer id {} sent us a operation list for block id {} but the hash in the header doesn't match.", from_peer_id, block_id);
-            if let Err(err) = self.ban_peers(&[from_peer_id]) {
+            if let Err(err) = self.ban_peers(
+                &[from_peer_id],
+                BanReason::InvalidOperationList,
+                BanSeverity::Major,
+            ) {
                 warn!("Error while banning peer {} err: {:?}", from_peer_id, err);
             }
             return;
@@ -776,10 +1183,108 @@ impl RetrievalThread {
         // Save the received operation ID list to the wishlist
         wishlist_info.operation_ids = Some(operation_ids);
 
-        // free up all the nodes that we asked for that operation list
+        // free up all the nodes that we asked for that operation list,
+        // the lists sent unasked, like the ones of compact blocks, don't count as served asks
+        if self
+            .asked_blocks
+            .get(&from_peer_id)
+            .map_or(false, |asked| asked.contains_key(&block_id))
+        {
+            self.withholding
+                .record_served(&from_peer_id, self.clock.now());
+        }
+        self.record_block_ask_served(&block_id);
         self.remove_asked_blocks(&[block_id].into_iter().collect());
     }
 
+    /// We received a chunk of the list of operations of a block.
+    ///
+    /// The chunks of a peer are appended in the order they are received, they must follow each other
+    /// without overlap and agree on the length of the list. The peer is banned otherwise.
+    fn on_block_operation_ids_chunk_received(
+        &mut self,
+        from_peer_id: PeerId,
+        block_id: BlockId,
+        total: u64,
+        offset: u64,
+        operation_ids: Vec<OperationId>,
+    ) {
+        if !self.block_wishlist.get(&block_id).map_or(false, |info| {
+            info.header.is_some() && info.operation_ids.is_none()
+        }) {
+            debug!("peer {} sent us a chunk of the operation IDs of block id {} but we were not looking for it", from_peer_id, block_id);
+            self.cache
+                .write()
+                .insert_peer_known_block(&from_peer_id, &[block_id], true);
+            return;
+        }
+
+        // the first chunk starts a reassembly, replacing the one of a peer we don't wait for anymore
+        if offset == 0 {
+            let replace = match self.operation_id_chunks.get(&block_id) {
+                Some(partial) => {
+                    partial.peer_id == from_peer_id
+                        || !self
+                            .asked_blocks
+                            .get(&partial.peer_id)
+                            .map_or(false, |asked| asked.contains_key(&block_id))
+                }
+                None => true,
+            };
+            if replace {
+                self.operation_id_chunks.insert(
+                    block_id,
+                    PartialOperationList {
+                        peer_id: from_peer_id,
+                        total,
+                        operation_ids: Vec::with_capacity(total as usize),
+                    },
+                );
+            }
+        }
+        let Some(partial) = self
+            .operation_id_chunks
+            .get_mut(&block_id)
+            .filter(|partial| partial.peer_id == from_peer_id)
+        else {
+            // the list is reassembled from the chunks of another peer
+            debug!(
+                "ignoring chunk of the operation IDs of block id {} from peer {}",
+                block_id, from_peer_id
+            );
+            return;
+        };
+
+        if operation_ids.is_empty()
+            || partial.total != total
+            || partial.operation_ids.len() as u64 != offset
+            || offset.saturating_add(operation_ids.len() as u64) > total
+        {
+            warn!(
+                "Peer id {} sent us an inconsistent chunk of the operation IDs of block id {}",
+                from_peer_id, block_id
+            );
+            self.operation_id_chunks.remove(&block_id);
+            if let Err(err) = self.ban_peers(
+                &[from_peer_id],
+                BanReason::InvalidOperationList,
+                BanSeverity::Major,
+            ) {
+                warn!("Error while banning peer {} err: {:?}", from_peer_id, err);
+            }
+            return;
+        }
+
+        partial.operation_ids.extend(operation_ids);
+        if partial.operation_ids.len() as u64 == total {
+            let partial = self
+                .operation_id_chunks
+                .remove(&block_id)
+                .expect("partial operation list presence should have been checked above");
+            self.on_block_operation_list_received(from_peer_id, block_id, partial.operation_ids);
+        }
+    }
+
     /// Return the sum of all operation's serialized sizes in the id list
     fn get_total_operations_size(storage: &Storage, operation_ids: &[OperationId]) -> usize {
         let op_read_lock = storage.read_operations();
@@ -880,14 +1385,17 @@ impl RetrievalThread {
             &self.config,
             operations.values().cloned().collect(),
             &from_peer_id,
+            &PreHashSet::default(),
             &mut self.sender_propagation_ops,
             &mut self.pool_controller,
+            &self.protocol_metrics,
         ) {
             warn!(
                 "Peer id {} sent us operations for block id {} but they failed validity checks: {}",
                 from_peer_id, block_id, err
             );
-            if let Err(err) = self.ban_peers(&[from_peer_id]) {
+            let (reason, severity) = classify_operations_offense(&err);
+            if let Err(err) = self.ban_peers(&[from_peer_id], reason, severity) {
                 warn!("Error while banning peer {} err: {:?}", from_peer_id, err);
             }
             return;
@@ -899,7 +1407,24 @@ impl RetrievalThread {
             .store_operations(operations.into_values().collect());
 
         if wishlist_info.storage.get_op_refs().len() == block_ops_set.len() {
+            // reward the sender if it served the block we asked it for in time
+            let served_in_time = self
+                .asked_blocks
+                .get(&from_peer_id)
+                .and_then(|asked| asked.get(&block_id))
+                .map_or(false, |ask_time| {
+                    self.clock.now().saturating_sub(*ask_time) <= self.config.ask_block_timeout
+                });
+            if served_in_time {
+                if let Err(err) = self.adjust_reputation(from_peer_id, REPUTATION_BLOCK_SERVED) {
+                    warn!("Error while rewarding peer {} err: {:?}", from_peer_id, err);
+                }
+            }
+
             // if we gathered all the ops, we should delete the asked history and mark the sender as knowing the block
+            self.withholding
+                .record_served(&from_peer_id, self.clock.now());
+            self.record_block_ask_served(&block_id);
             self.remove_asked_blocks(&[block_id].into_iter().collect());
 
             // Mark the sender as knowing this block
@@ -922,45 +1447,53 @@ impl RetrievalThread {
 
     /// function that updates the global state of block retrieval
     pub(crate) fn update_block_retrieval(&mut self) {
-        let ask_block_timeout = self.config.ask_block_timeout.to_duration();
+        let ask_block_timeout = self.config.ask_block_timeout;
 
         // Init timer for next tick
-        let now = Instant::now();
-        let mut next_tick = now
-            .checked_add(self.config.ask_block_timeout.into())
-            .ok_or(TimeError::TimeOverflowError)
-            .expect("could not compute next block retrieval timer tick");
+        let now = self.clock.now();
+        let mut next_tick = now.saturating_add(ask_block_timeout);
 
         if self.asked_blocks.is_empty() && self.block_wishlist.is_empty() {
+            self.in_flight_blocks.clear();
             return;
         }
 
         // Get connected peer list
         let connected_peers = self.active_connections.get_peer_ids_connected();
+        let quarantined_peers = self.peer_db.read().get_quarantined_peers();
 
         // Update cache
         self.cache.write().update_cache(&connected_peers);
 
         // Cleanup asked_blocks from all disconnected peers and blocks that are not in the wishlist anymore.
+        // The pending asks of the disconnected peers are cancelled and count as failed.
         self.asked_blocks.retain(|peer_id, asked_blocks| {
             if !connected_peers.contains(peer_id) {
+                for block_id in asked_blocks.keys() {
+                    if let Some(info) = self.block_wishlist.get_mut(block_id) {
+                        debug!(
+                            "ask for block {} cancelled: peer {} disconnected",
+                            block_id, peer_id
+                        );
+                        info.failed_peers.insert(*peer_id);
+                        info.failed_asks += 1;
+                    }
+                }
                 return false;
             }
             asked_blocks.retain(|block_id, _| self.block_wishlist.contains_key(block_id));
             !asked_blocks.is_empty()
         });
 
-        // list of blocks that need to be asked
-        let mut to_ask: PreHashSet<BlockId> = self.block_wishlist.keys().copied().collect();
         // the number of things already being asked to those peers
         let mut peer_loads: HashMap<PeerId, usize> = Default::default();
+        // peers that didn't answer too many asks for the blocks they announced
+        let mut withholding_peers = Vec::new();
         for (peer_id, asked_blocks) in &mut self.asked_blocks {
             // init the list of items to remove from asked_blocks
             let mut to_remove_from_asked_blocks = Vec::new();
             for (block_id, ask_time) in asked_blocks.iter() {
-                let expiry = ask_time
-                    .checked_add(ask_block_timeout)
-                    .expect("could not compute block ask expiry");
+                let expiry = ask_time.saturating_add(ask_block_timeout);
                 if expiry <= now {
                     // the block has been asked for the block data a long time agp and did not respond
 
@@ -969,13 +1502,36 @@ impl RetrievalThread {
                         .write()
                         .insert_peer_known_block(peer_id, &[*block_id], false);
 
+                    self.protocol_metrics.write().record_block_ask_timeout();
+
+                    // the peer announced the block but didn't serve it
+                    let announced = self
+                        .cache
+                        .read()
+                        .header_sources
+                        .peek(block_id)
+                        .map_or(false, |sources| sources.contains_key(peer_id));
+                    if announced && self.withholding.record_withheld(peer_id, now) {
+                        withholding_peers.push(*peer_id);
+                    }
+
+                    // the next asks go to other peers first
+                    if let Some(info) = self.block_wishlist.get_mut(block_id) {
+                        info.failed_peers.insert(*peer_id);
+                        info.failed_asks += 1;
+                        if info.failed_asks == self.config.max_block_ask_retries {
+                            warn!(
+                                "giving up retrieving block {}: {} asks timed out",
+                                block_id, info.failed_asks
+                            );
+                        }
+                    }
+
                     // We mark the block for removal from the asked_blocks list.
                     // This prevents us from re-detecting the timeout many times.
                     to_remove_from_asked_blocks.push(*block_id);
                 } else {
-                    // this block was recently asked to this peer: no need to ask for the block for now
-
-                    to_ask.remove(block_id);
+                    // this block was recently asked to this peer: it stays in flight
 
                     // mark this peer as loaded with an angoing ask
                     peer_loads
@@ -992,15 +1548,64 @@ impl RetrievalThread {
                 asked_blocks.remove(&remove_id);
             }
         }
+        if !withholding_peers.is_empty() {
+            warn!(
+                "peers {:?} announced blocks they didn't serve",
+                withholding_peers
+            );
+            if let Err(err) = self.ban_peers(
+                &withholding_peers,
+                BanReason::WithholdingData,
+                BanSeverity::Minor,
+            ) {
+                warn!(
+                    "Error while banning peers {:?} err: {:?}",
+                    withholding_peers, err
+                );
+            }
+        }
 
-        // for each block to ask, choose a peer to ask it from and perform the ask
-        let mut to_ask = to_ask.into_iter().collect::<Vec<_>>();
-        to_ask.shuffle(&mut thread_rng()); // shuffle ask order
-        for block_id in to_ask {
-            // prioritize peers by (max knowledge, min knowledge age, min load, max random)
+        // a block is in flight as long as one of the peers we asked it to may still answer
+        self.in_flight_blocks.retain(|block_id, _| {
+            self.asked_blocks
+                .values()
+                .any(|asked_blocks| asked_blocks.contains_key(block_id))
+        });
+
+        // list of blocks that need to be asked: no duplicate ask for the blocks in flight
+        let mut to_ask: Vec<_> = self
+            .block_wishlist
+            .iter()
+            .filter(|(block_id, _)| !self.in_flight_blocks.contains_key(block_id))
+            .map(|(block_id, info)| (info.ask_priority(), *block_id))
+            .collect();
+
+        // for each block to ask, by priority, choose a peer to ask it from and perform the ask
+        to_ask.sort_unstable();
+        for (_, block_id) in to_ask {
+            if self.config.max_concurrent_block_downloads > 0
+                && self.in_flight_blocks.len() >= self.config.max_concurrent_block_downloads
+            {
+                // the other blocks wait for an ask to complete or time out
+                break;
+            }
+            let Some(wishlist_info) = self.block_wishlist.get(&block_id) else {
+                continue;
+            };
+            if self.config.max_block_ask_retries > 0
+                && wishlist_info.failed_asks >= self.config.max_block_ask_retries
+            {
+                // retries exhausted until another peer announces the block
+                continue;
+            }
+            // prioritize peers by (announced and not timed out, max knowledge, min knowledge age, min load, max random)
             let mut peer_scores: Vec<_> = connected_peers
                 .iter()
                 .filter_map(|peer_id| {
+                    // do not ask blocks from quarantined peers
+                    if quarantined_peers.contains(peer_id) {
+                        return None;
+                    }
                     // Get the peer load. Look for the minimum score for asking.
                     let peer_load = peer_loads.get(peer_id).copied().unwrap_or_default();
                     if peer_load >= self.config.max_simultaneous_ask_blocks_per_node {
@@ -1014,11 +1619,20 @@ impl RetrievalThread {
                         .blocks_known_by_peer
                         .get(peer_id)
                         .and_then(|blocks_known| blocks_known.peek(&block_id).copied());
+                    // rotate through the peers that announced the block, the ones that timed out come last
+                    let rotation_rank = if wishlist_info.failed_peers.contains(peer_id) {
+                        1i8
+                    } else if wishlist_info.announced_by.contains(peer_id) {
+                        -1i8
+                    } else {
+                        0i8
+                    };
                     match peer_knowledge_of_block {
                         Some((false, info_t)) => {
                             // we think that the peer doesn't know the block
                             Some((
-                                1i8,                                                               // worst knowledge
+                                rotation_rank,
+                                1i8, // worst knowledge
                                 Some(-(now.saturating_duration_since(info_t).as_millis() as i64)), // the older the info the better
                                 peer_load,                 // the lower the load the better
                                 thread_rng().gen::<u64>(), // random tie breaker,
@@ -1028,6 +1642,7 @@ impl RetrievalThread {
                         None => {
                             // we don't know if the peer knows the block
                             Some((
+                                rotation_rank,
                                 0i8,                       // medium knowledge
                                 None,                      // N/A
                                 peer_load,                 // the lower the load the better
@@ -1038,7 +1653,8 @@ impl RetrievalThread {
                         Some((true, info_t)) => {
                             // we think that the peer knows the block
                             Some((
-                                -1i8,                                                           // best knowledge
+                                rotation_rank,
+                                -1i8, // best knowledge
                                 Some(now.saturating_duration_since(info_t).as_millis() as i64), // the newer the info the better
                                 peer_load,                 // the lower the load the better
                                 thread_rng().gen::<u64>(), // random tie breaker,
@@ -1076,8 +1692,18 @@ impl RetrievalThread {
                 _ => panic!("invalid wishlist state"),
             };
 
-            // try to ask peers from best to worst
-            for (_, _, _, _, peer_id) in peer_scores {
+            // try to ask peers from best to worst, the block is asked to up to `block_ask_fanout` peers that announced it.
+            // The first valid response moves the retrieval forward and frees all the asked peers,
+            // the late responses of the others are ignored without penalizing them.
+            let fanout = self.config.block_ask_fanout.max(1);
+            let mut asked_count = 0;
+            for (rotation_rank, knowledge, _, _, _, peer_id) in peer_scores {
+                let announced = rotation_rank < 0 || (rotation_rank == 0 && knowledge < 0);
+                if asked_count > 0 && !announced {
+                    // peers are sorted: no other peer announced the block
+                    break;
+                }
+                let _span = self.block_spans.data_request(&block_id, &peer_id);
                 debug!(
                     "Sending ask for block {} data to {}: {:?}",
                     block_id, peer_id, &request
@@ -1110,8 +1736,13 @@ impl RetrievalThread {
                         .and_modify(|v| *v += 1)
                         .or_insert(1);
 
-                    // No need to look for other peers.
-                    break;
+                    self.in_flight_blocks.entry(block_id).or_insert(now);
+
+                    asked_count += 1;
+                    if asked_count >= fanout {
+                        // No need to look for other peers.
+                        break;
+                    }
                 }
             }
         }
@@ -1243,6 +1874,7 @@ impl RetrievalThread {
         block_storage.store_block(signed_block);
 
         // Send to consensus
+        let _span = self.block_spans.registered(block_id);
         self.consensus_controller
             .register_block(*block_id, slot, block_storage, false);
 
@@ -1263,28 +1895,60 @@ pub fn start_retrieval_thread(
     sender_propagation_ops: MassaSender<OperationHandlerPropagationCommand>,
     sender_propagation_endorsements: MassaSender<EndorsementHandlerPropagationCommand>,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
+    peer_db: SharedPeerDB,
     config: ProtocolConfig,
     endorsement_cache: SharedEndorsementCache,
     operation_cache: SharedOperationCache,
     cache: SharedBlockCache,
     storage: Storage,
     mip_store: MipStore,
+    clock: ProtocolClock,
     massa_metrics: MassaMetrics,
+    protocol_metrics: SharedProtocolMetrics,
 ) -> JoinHandle<()> {
     let block_message_serializer =
         MessagesSerializer::new().with_block_message_serializer(BlockMessageSerializer::new());
     std::thread::Builder::new()
         .name("protocol-block-handler-retrieval".to_string())
         .spawn(move || {
+            let block_server = BlockInfoServer::new(
+                active_connections.clone(),
+                storage.clone(),
+                cache.clone(),
+                operation_cache.clone(),
+                endorsement_cache.clone(),
+                &config,
+            );
+            let block_serve_workers = (config.block_serve_concurrency > 0).then(|| {
+                BlockServeWorkers::start(
+                    block_server.clone(),
+                    config.block_serve_concurrency,
+                    config.max_size_channel_network_to_block_handler,
+                )
+            });
             let mut retrieval_thread = RetrievalThread {
                 active_connections,
-                selector_controller,
+                selections: SelectionCache::new(selector_controller, &config),
                 consensus_controller,
                 pool_controller,
-                next_timer_ask_block: Instant::now() + config.ask_block_timeout.to_duration(),
+                next_timer_ask_block: clock.now().saturating_add(config.ask_block_timeout),
+                clock,
                 block_wishlist: PreHashMap::default(),
                 asked_blocks: HashMap::default(),
+                in_flight_blocks: PreHashMap::default(),
+                next_wishlist_rank: 0,
+                block_server,
+                block_serve_workers,
+                compact_blocks: LruMap::new(ByLength::new(config.max_known_blocks_size as u32)),
+                operation_id_chunks: PreHashMap::default(),
+                block_spans: BlockSpans::new(config.max_known_blocks_size as u32),
+                withholding: WithholdingTracker::new(&config),
+                first_header_times: FirstHeaderTimes::new(&config),
+                header_batch: Vec::new(),
+                header_batch_deadline: None,
+                consensus_retry_buffer: VecDeque::new(),
                 peer_cmd_sender,
+                peer_db,
                 sender_propagation_ops,
                 sender_propagation_endorsements,
                 receiver_network,
@@ -1298,6 +1962,7 @@ pub fn start_retrieval_thread(
                 storage,
                 mip_store,
                 massa_metrics,
+                protocol_metrics,
                 operation_id_serializer: OperationIdSerializer::new(),
             };
             retrieval_thread.run();
@@ -0,0 +1,144 @@
+//! Cache of the PoS draws used to check the endorsements received.
+//!
+//! The endorsements of a header and of an endorsement batch mostly target the same slot, and
+//! each of them needs the draw of its slot. The draws are kept per slot, so the selector is asked
+//! once per slot. They are dropped once the slot is older than `max_endorsements_propagation_time`
+//! as the endorsements of such slots aren't propagated anymore, or when the cache is full.
+
+use massa_models::{slot::Slot, timeslots::get_block_slot_timestamp};
+use massa_pos_exports::{PosResult, Selection, SelectorController};
+use massa_protocol_exports::ProtocolConfig;
+use massa_time::MassaTime;
+use schnellru::{ByLength, LruMap};
+
+/// Maximum number of slots whose draws are kept
+const MAX_CACHED_SELECTIONS: u32 = 256;
+
+pub(crate) struct SelectionCache {
+    selector_controller: Box<dyn SelectorController>,
+    /// draws of the recently checked slots, keyed by slot so that each thread has its own entries
+    selections: LruMap<Slot, Selection>,
+    thread_count: u8,
+    t0: MassaTime,
+    genesis_timestamp: MassaTime,
+    max_age: MassaTime,
+}
+
+impl SelectionCache {
+    pub(crate) fn new(
+        selector_controller: Box<dyn SelectorController>,
+        config: &ProtocolConfig,
+    ) -> Self {
+        SelectionCache {
+            selector_controller,
+            selections: LruMap::new(ByLength::new(MAX_CACHED_SELECTIONS)),
+            thread_count: config.thread_count,
+            t0: config.t0,
+            genesis_timestamp: config.genesis_timestamp,
+            max_age: config.max_endorsements_propagation_time,
+        }
+    }
+
+    /// Draws of a slot, only asked to the selector if they aren't cached
+    pub(crate) fn get_selection(&mut self, slot: Slot) -> PosResult<Selection> {
+        if let Some(selection) = self.selections.get(&slot) {
+            return Ok(selection.clone());
+        }
+        self.forget_old_slots(MassaTime::now());
+        let selection = self.selector_controller.get_selection(slot)?;
+        self.selections.insert(slot, selection.clone());
+        Ok(selection)
+    }
+
+    /// Drop the draws of the slots that are too old at time `now`
+    fn forget_old_slots(&mut self, now: MassaTime) {
+        let oldest_timestamp = now.saturating_sub(self.max_age);
+        let old_slots: Vec<Slot> = self
+            .selections
+            .iter()
+            .map(|(slot, _)| *slot)
+            .filter(|slot| {
+                get_block_slot_timestamp(self.thread_count, self.t0, self.genesis_timestamp, *slot)
+                    .map_or(true, |timestamp| timestamp < oldest_timestamp)
+            })
+            .collect();
+        for slot in old_slots {
+            self.selections.remove(&slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use massa_models::{address::Address, slot::Slot};
+    use massa_pos_exports::{MockSelectorController, Selection};
+    use massa_protocol_exports::ProtocolConfig;
+    use massa_signature::KeyPair;
+    use massa_time::MassaTime;
+    use mockall::predicate::eq;
+
+    use super::SelectionCache;
+
+    fn selection() -> Selection {
+        let address = Address::from_public_key(&KeyPair::generate(0).unwrap().get_public_key());
+        Selection {
+            endorsements: vec![address; 16],
+            producer: address,
+        }
+    }
+
+    #[test]
+    fn test_selection_asked_once_per_slot() {
+        let config = ProtocolConfig::default();
+        let slot = Slot::new(1, 0);
+        // same period in another thread, with its own draws
+        let other_thread_slot = Slot::new(1, 1);
+        let (selection, other_thread_selection) = (selection(), selection());
+        let mut selector_controller = MockSelectorController::new();
+        selector_controller
+            .expect_get_selection()
+            .with(eq(slot))
+            .times(1)
+            .return_const(Ok(selection.clone()));
+        selector_controller
+            .expect_get_selection()
+            .with(eq(other_thread_slot))
+            .times(1)
+            .return_const(Ok(other_thread_selection.clone()));
+        let mut selections = SelectionCache::new(Box::new(selector_controller), &config);
+
+        assert_eq!(selections.get_selection(slot).unwrap(), selection);
+        assert_eq!(selections.get_selection(slot).unwrap(), selection);
+        assert_eq!(
+            selections.get_selection(other_thread_slot).unwrap(),
+            other_thread_selection
+        );
+        assert_eq!(
+            selections.get_selection(other_thread_slot).unwrap(),
+            other_thread_selection
+        );
+    }
+
+    #[test]
+    fn test_old_slots_are_forgotten() {
+        let genesis_timestamp = MassaTime::now();
+        let config = ProtocolConfig {
+            genesis_timestamp,
+            t0: MassaTime::from_millis(1000),
+            max_endorsements_propagation_time: MassaTime::from_millis(5000),
+            ..Default::default()
+        };
+        let mut selector_controller = MockSelectorController::new();
+        selector_controller
+            .expect_get_selection()
+            .return_const(Ok(selection()));
+        let mut selections = SelectionCache::new(Box::new(selector_controller), &config);
+        selections.get_selection(Slot::new(1, 0)).unwrap();
+        selections.get_selection(Slot::new(10, 0)).unwrap();
+
+        selections
+            .forget_old_slots(genesis_timestamp.saturating_add(MassaTime::from_millis(10_000)));
+        assert!(selections.selections.peek(&Slot::new(1, 0)).is_none());
+        assert!(selections.selections.peek(&Slot::new(10, 0)).is_some());
+    }
+}
@@ -0,0 +1,378 @@
+//! Serving the block data asked by the peers.
+//!
+//! The block data requests of the peers are answered from storage by `block_serve_concurrency`
+//! threads, so that several peers are served at once while no more than that many threads read
+//! the storage. The requests received while all the threads are busy wait in a bounded queue, and
+//! the ones that don't fit in it are dropped: the peers ask another node or again later.
+//! With `block_serve_concurrency` at 0 the retrieval thread answers the requests itself.
+
+use std::{
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{bounded, Sender, TrySendError};
+use massa_models::{
+    block_id::BlockId, endorsement::EndorsementId, operation::OperationId, prehash::PreHashSet,
+};
+use massa_protocol_exports::{PeerId, ProtocolConfig};
+use massa_storage::Storage;
+use parking_lot::Mutex;
+use schnellru::{ByLength, LruMap};
+use tracing::{debug, warn};
+
+use crate::{
+    handlers::{
+        endorsement_handler::cache::SharedEndorsementCache,
+        operation_handler::cache::SharedOperationCache,
+    },
+    messages::MessagesSerializer,
+    wrap_network::ActiveConnectionsTrait,
+};
+
+use super::{
+    cache::SharedBlockCache,
+    messages::{AskForBlockInfo, BlockInfoReply, BlockMessage},
+    BlockMessageSerializer,
+};
+
+/// Answers the block data requests of the peers, shared by the serving threads
+#[derive(Clone)]
+pub(crate) struct BlockInfoServer {
+    active_connections: Box<dyn ActiveConnectionsTrait>,
+    block_message_serializer: MessagesSerializer,
+    storage: Storage,
+    cache: SharedBlockCache,
+    operation_cache: SharedOperationCache,
+    endorsement_cache: SharedEndorsementCache,
+    /// operation ids of the blocks recently served to peers, with the time they were read from storage
+    served_op_ids_cache: Arc<Mutex<LruMap<BlockId, (Vec<OperationId>, Instant)>>>,
+    served_op_ids_cache_ttl: Duration,
+    block_info_chunk_size: usize,
+}
+
+impl BlockInfoServer {
+    pub(crate) fn new(
+        active_connections: Box<dyn ActiveConnectionsTrait>,
+        storage: Storage,
+        cache: SharedBlockCache,
+        operation_cache: SharedOperationCache,
+        endorsement_cache: SharedEndorsementCache,
+        config: &ProtocolConfig,
+    ) -> Self {
+        BlockInfoServer {
+            active_connections,
+            block_message_serializer: MessagesSerializer::new()
+                .with_block_message_serializer(BlockMessageSerializer::new()),
+            storage,
+            cache,
+            operation_cache,
+            endorsement_cache,
+            served_op_ids_cache: Arc::new(Mutex::new(LruMap::new(ByLength::new(
+                config.max_served_op_ids_cache_size as u32,
+            )))),
+            served_op_ids_cache_ttl: config.served_op_ids_cache_ttl.to_duration(),
+            block_info_chunk_size: config.block_info_chunk_size,
+        }
+    }
+
+    /// A remote node asked the local node for block data
+    ///
+    /// We send the block's operation ids if the foreign node asked for `AskForBlockInfo::Info`
+    /// or a subset of the full operations of the block if it asked for `AskForBlockInfo::Operations`.
+    pub(crate) fn serve(
+        &self,
+        from_peer_id: PeerId,
+        block_id: BlockId,
+        info_requested: AskForBlockInfo,
+    ) {
+        debug!(
+            "peer {} asked for block info on block {}: {:?}",
+            &from_peer_id, block_id, &info_requested
+        );
+
+        // updates on the remote peer's knowledge on blocks, operations and endorsements
+        // only applied if the response is successfully sent to the peer
+        let mut block_knowledge_updates = PreHashSet::default();
+        let mut operation_knowledge_updates = PreHashSet::default();
+        let mut endorsement_knowledge_updates = PreHashSet::default();
+
+        // repeated operation ids requests are answered from the cache, without reading the storage
+        let cached_op_ids = match info_requested {
+            AskForBlockInfo::OperationIds => self.get_served_op_ids(&block_id),
+            _ => None,
+        };
+
+        let block_info_response = if let Some(block_op_ids) = cached_op_ids {
+            operation_knowledge_updates.extend(block_op_ids.iter().cloned());
+
+            BlockInfoReply::OperationIds(block_op_ids)
+        } else {
+            // retrieve block data from storage
+            let stored_header_op_ids = self.storage.read_blocks().get(&block_id).map(|block| {
+                (
+                    block.content.header.clone(),
+                    block.content.operations.clone(),
+                )
+            });
+
+            match (stored_header_op_ids, info_requested) {
+                (None, _) => BlockInfoReply::NotFound,
+
+                (Some((header, _)), AskForBlockInfo::Header) => {
+                    // the peer asked for a block header
+
+                    // once sent, the peer will know about that block,
+                    // no need to announce this header to that peer anymore
+                    block_knowledge_updates.insert(block_id);
+
+                    // once sent, the peer will know about the endorsements in that block,
+                    // no need to announce those endorsements to that peer anymore
+                    endorsement_knowledge_updates.extend(
+                        header
+                            .content
+                            .endorsements
+                            .iter()
+                            .map(|e| e.id)
+                            .collect::<PreHashSet<EndorsementId>>(),
+                    );
+
+                    BlockInfoReply::Header(header)
+                }
+                (Some((_, block_op_ids)), AskForBlockInfo::OperationIds) => {
+                    // the peer asked for the operation IDs of the block
+
+                    // once sent, the peer will know about those operations,
+                    // no need to announce their IDs to that peer anymore
+                    operation_knowledge_updates.extend(block_op_ids.iter().cloned());
+
+                    self.served_op_ids_cache
+                        .lock()
+                        .insert(block_id, (block_op_ids.clone(), Instant::now()));
+
+                    BlockInfoReply::OperationIds(block_op_ids)
+                }
+                (Some((_, block_op_ids)), AskForBlockInfo::Operations(mut asked_ops)) => {
+                    // the peer asked for a list of full operations from the block
+
+                    // retain only ops that belong to the block
+                    {
+                        let block_op_ids_set: PreHashSet<OperationId> =
+                            block_op_ids.iter().copied().collect();
+                        asked_ops.retain(|id| block_op_ids_set.contains(id));
+                    }
+                    let asked_ops_count = asked_ops.len();
+
+                    // Send the operations that are available in storage
+                    let returned_ops: Vec<_> = {
+                        let op_storage_lock = self.storage.read_operations();
+                        asked_ops
+                            .into_iter()
+                            .filter_map(|id| op_storage_lock.get(&id))
+                            .cloned()
+                            .collect()
+                    };
+
+                    if returned_ops.is_empty() && asked_ops_count > 0 {
+                        // the operations were pruned, the peer can ask another node right away
+                        BlockInfoReply::NotFound
+                    } else {
+                        // mark the peer as knowing about those operations,
+                        // no need to announce their IDs to them anymore
+                        operation_knowledge_updates.extend(
+                            returned_ops
+                                .iter()
+                                .map(|op| op.id)
+                                .collect::<PreHashSet<OperationId>>(),
+                        );
+
+                        BlockInfoReply::Operations(returned_ops)
+                    }
+                }
+            }
+        };
+
+        debug!(
+            "sending reply for block {} info to {}",
+            block_id, from_peer_id
+        );
+
+        // send response to peer
+        for block_info in self.split_block_info_reply(block_info_response) {
+            if let Err(err) = self.active_connections.send_to_peer(
+                &from_peer_id,
+                &self.block_message_serializer,
+                BlockMessage::DataResponse {
+                    block_id,
+                    block_info,
+                }
+                .into(),
+                true,
+            ) {
+                warn!(
+                    "Error while sending reply for block {} to {}: {:?}",
+                    block_id, from_peer_id, err
+                );
+                return;
+            }
+        }
+
+        // here we know that the response was successfully sent to the peer
+        // so we can update our vision of the peer's knowledge on blocks, operations and endorsements
+        if !block_knowledge_updates.is_empty() {
+            self.cache.write().insert_peer_known_block(
+                &from_peer_id,
+                &block_knowledge_updates.into_iter().collect::<Vec<_>>(),
+                true,
+            );
+        }
+        if !operation_knowledge_updates.is_empty() {
+            self.operation_cache.write().insert_peer_known_ops(
+                &from_peer_id,
+                &operation_knowledge_updates
+                    .into_iter()
+                    .map(|op_id| op_id.prefix())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        if !endorsement_knowledge_updates.is_empty() {
+            self.endorsement_cache
+                .write()
+                .insert_peer_known_endorsements(
+                    &from_peer_id,
+                    &endorsement_knowledge_updates
+                        .into_iter()
+                        .collect::<Vec<_>>(),
+                );
+        }
+    }
+
+    /// Split the operation ids replies longer than `block_info_chunk_size` in several frames
+    fn split_block_info_reply(&self, reply: BlockInfoReply) -> Vec<BlockInfoReply> {
+        let chunk_size = self.block_info_chunk_size;
+        match reply {
+            BlockInfoReply::OperationIds(operation_ids)
+                if chunk_size > 0 && operation_ids.len() > chunk_size =>
+            {
+                let total = operation_ids.len() as u64;
+                operation_ids
+                    .chunks(chunk_size)
+                    .enumerate()
+                    .map(|(index, chunk)| BlockInfoReply::OperationIdsChunk {
+                        total,
+                        offset: (index * chunk_size) as u64,
+                        operation_ids: chunk.to_vec(),
+                    })
+                    .collect()
+            }
+            reply => vec![reply],
+        }
+    }
+
+    /// Get the cached operation ids of a block, if they were read from storage recently
+    fn get_served_op_ids(&self, block_id: &BlockId) -> Option<Vec<OperationId>> {
+        let mut served_op_ids_cache = self.served_op_ids_cache.lock();
+        match served_op_ids_cache.peek(block_id) {
+            Some((op_ids, cached_at)) if cached_at.elapsed() < self.served_op_ids_cache_ttl => {
+                Some(op_ids.clone())
+            }
+            Some(_) => {
+                served_op_ids_cache.remove(block_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Remove the expired cached operation ids and the ones of the blocks pruned from storage
+    pub(crate) fn prune_served_op_ids_cache(&self) {
+        let mut served_op_ids_cache = self.served_op_ids_cache.lock();
+        let to_remove: Vec<BlockId> = {
+            let blocks = self.storage.read_blocks();
+            served_op_ids_cache
+                .iter()
+                .filter(|(block_id, (_, cached_at))| {
+                    cached_at.elapsed() >= self.served_op_ids_cache_ttl
+                        || !blocks.contains(block_id)
+                })
+                .map(|(block_id, _)| *block_id)
+                .collect()
+        };
+        for block_id in to_remove {
+            served_op_ids_cache.remove(&block_id);
+        }
+    }
+}
+
+/// Block data request of a peer waiting for a serving thread
+type BlockInfoRequest = (PeerId, BlockId, AskForBlockInfo);
+
+/// Threads answering the block data requests, stopped when dropped
+pub(crate) struct BlockServeWorkers {
+    sender: Option<Sender<BlockInfoRequest>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BlockServeWorkers {
+    /// Start `concurrency` threads answering the requests with `server`,
+    /// at most `queue_size` requests wait for a free thread
+    pub(crate) fn start(server: BlockInfoServer, concurrency: usize, queue_size: usize) -> Self {
+        let (sender, receiver) = bounded::<BlockInfoRequest>(queue_size);
+        let threads = (0..concurrency)
+            .map(|index| {
+                let server = server.clone();
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("protocol-block-handler-server-{}", index))
+                    .spawn(move || {
+                        while let Ok((peer_id, block_id, info_requested)) = receiver.recv() {
+                            server.serve(peer_id, block_id, info_requested);
+                        }
+                    })
+                    .expect("OS failed to start block server thread")
+            })
+            .collect();
+        BlockServeWorkers {
+            sender: Some(sender),
+            threads,
+        }
+    }
+
+    /// Queue a request until a thread is free, it is dropped if the queue is full
+    pub(crate) fn submit(
+        &self,
+        peer_id: PeerId,
+        block_id: BlockId,
+        info_requested: AskForBlockInfo,
+    ) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.try_send((peer_id, block_id, info_requested)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!(
+                    "dropping block info request of peer {} for block {}: too many requests waiting",
+                    peer_id, block_id
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!(
+                    "block server threads stopped, dropping request of peer {}",
+                    peer_id
+                );
+            }
+        }
+    }
+}
+
+impl Drop for BlockServeWorkers {
+    fn drop(&mut self) {
+        // the threads stop once the queue is empty and disconnected
+        self.sender.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Tracing spans following the retrieval of each block.
+//!
+//! Each block gets a root `block_lifecycle` span, opened when its header is received or when it
+//! enters the wishlist, and closed once it is registered in consensus or leaves the wishlist.
+//! Each step of the retrieval runs in a child span of it, all carrying the id of the block, so
+//! that a trace viewer shows the whole retrieval of a block together.
+//! Nothing is kept when the spans are disabled.
+
+use massa_models::block_id::BlockId;
+use massa_protocol_exports::PeerId;
+use schnellru::{ByLength, LruMap};
+use tracing::{debug_span, span::EnteredSpan, Span};
+
+pub(crate) struct BlockSpans {
+    /// lifecycle spans of the blocks being retrieved, the oldest ones are closed first
+    lifecycles: LruMap<BlockId, Span>,
+}
+
+impl BlockSpans {
+    /// Follow up to `max_blocks` blocks at a time
+    pub(crate) fn new(max_blocks: u32) -> Self {
+        BlockSpans {
+            lifecycles: LruMap::new(ByLength::new(max_blocks)),
+        }
+    }
+
+    /// Lifecycle span of a block, opened if needed
+    fn lifecycle(&mut self, block_id: &BlockId) -> Span {
+        if let Some(span) = self.lifecycles.get(block_id) {
+            return span.clone();
+        }
+        let span = debug_span!(parent: None, "block_lifecycle", block_id = %block_id);
+        if !span.is_disabled() {
+            self.lifecycles.insert(*block_id, span.clone());
+        }
+        span
+    }
+
+    /// Span of the processing of a header received from a peer
+    pub(crate) fn header_received(&mut self, block_id: &BlockId, peer_id: &PeerId) -> EnteredSpan {
+        let lifecycle = self.lifecycle(block_id);
+        debug_span!(
+            parent: &lifecycle,
+            "block_header_received",
+            block_id = %block_id,
+            peer_id = %peer_id
+        )
+        .entered()
+    }
+
+    /// Span of the addition of a block to the wishlist
+    pub(crate) fn wishlisted(&mut self, block_id: &BlockId) -> EnteredSpan {
+        let lifecycle = self.lifecycle(block_id);
+        debug_span!(parent: &lifecycle, "block_wishlisted", block_id = %block_id).entered()
+    }
+
+    /// Span of the sending of a data request for a block to a peer
+    pub(crate) fn data_request(&mut self, block_id: &BlockId, peer_id: &PeerId) -> EnteredSpan {
+        let lifecycle = self.lifecycle(block_id);
+        debug_span!(
+            parent: &lifecycle,
+            "block_data_request",
+            block_id = %block_id,
+            peer_id = %peer_id
+        )
+        .entered()
+    }
+
+    /// Span of the processing of a data response about a block received from a peer
+    pub(crate) fn data_response(&mut self, block_id: &BlockId, peer_id: &PeerId) -> EnteredSpan {
+        let lifecycle = self.lifecycle(block_id);
+        debug_span!(
+            parent: &lifecycle,
+            "block_data_response",
+            block_id = %block_id,
+            peer_id = %peer_id
+        )
+        .entered()
+    }
+
+    /// Span of the registration of a fully gathered block in consensus, which ends its lifecycle
+    pub(crate) fn registered(&mut self, block_id: &BlockId) -> EnteredSpan {
+        let lifecycle = self.lifecycle(block_id);
+        self.lifecycles.remove(block_id);
+        debug_span!(parent: &lifecycle, "block_registered", block_id = %block_id).entered()
+    }
+
+    /// Close the lifecycle span of a block that isn't retrieved anymore
+    pub(crate) fn forget(&mut self, block_id: &BlockId) {
+        self.lifecycles.remove(block_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use massa_hash::Hash;
+    use massa_models::block_id::BlockId;
+    use massa_protocol_exports::PeerId;
+    use massa_signature::KeyPair;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        registry::LookupSpan,
+        Layer, Registry,
+    };
+
+    use super::BlockSpans;
+
+    /// Span opened: name, name of the parent, fields
+    type CapturedSpan = (String, Option<String>, Vec<(String, String)>);
+
+    #[derive(Clone, Default)]
+    struct CaptureLayer {
+        spans: Arc<Mutex<Vec<CapturedSpan>>>,
+    }
+
+    struct FieldVisitor(Vec<(String, String)>);
+
+    impl Visit for FieldVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CaptureLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut visitor = FieldVisitor(Vec::new());
+            attrs.record(&mut visitor);
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.name().to_string());
+            self.spans.lock().unwrap().push((
+                attrs.metadata().name().to_string(),
+                parent,
+                visitor.0,
+            ));
+        }
+    }
+
+    #[test]
+    fn test_block_lifecycle_spans() {
+        let capture = CaptureLayer::default();
+        let subscriber = Registry::default().with(capture.clone());
+        let block_id = BlockId::generate_from_hash(Hash::compute_from(b"block"));
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut spans = BlockSpans::new(10);
+            drop(spans.header_received(&block_id, &peer_id));
+            drop(spans.wishlisted(&block_id));
+            drop(spans.data_request(&block_id, &peer_id));
+            drop(spans.data_response(&block_id, &peer_id));
+            drop(spans.registered(&block_id));
+            // a new retrieval of the block starts a new lifecycle
+            drop(spans.wishlisted(&block_id));
+            spans.forget(&block_id);
+        });
+
+        let spans = capture.spans.lock().unwrap();
+        let names: Vec<&str> = spans.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "block_lifecycle",
+                "block_header_received",
+                "block_wishlisted",
+                "block_data_request",
+                "block_data_response",
+                "block_registered",
+                "block_lifecycle",
+                "block_wishlisted",
+            ]
+        );
+        for (name, parent, fields) in spans.iter() {
+            assert!(
+                fields.contains(&("block_id".to_string(), block_id.to_string())),
+                "span {} without the block id",
+                name
+            );
+            if name == "block_lifecycle" {
+                assert_eq!(*parent, None);
+            } else {
+                assert_eq!(parent.as_deref(), Some("block_lifecycle"));
+            }
+            let has_peer_id = fields.contains(&("peer_id".to_string(), peer_id.to_string()));
+            assert_eq!(
+                has_peer_id,
+                matches!(
+                    name.as_str(),
+                    "block_header_received" | "block_data_request" | "block_data_response"
+                ),
+                "unexpected peer id field in span {}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Detection of the peers announcing headers whose data they don't serve.
+//!
+//! A peer announcing headers and never answering the asks for their data takes our download slots
+//! until the asks time out. The answered and unanswered asks to each peer for the blocks it
+//! announced are counted over `withholding_window`, and the peer is penalized once the share of
+//! unanswered ones goes above `withholding_max_percent`.
+
+use massa_protocol_exports::{PeerId, ProtocolConfig};
+use massa_time::MassaTime;
+use schnellru::{ByLength, LruMap};
+
+/// Maximum number of peers whose asks are counted
+const MAX_TRACKED_PEERS: u32 = 1000;
+
+/// Asks to a peer since the start of its window
+struct ServedAsks {
+    start: MassaTime,
+    served: u32,
+    withheld: u32,
+}
+
+pub(crate) struct WithholdingTracker {
+    peers: LruMap<PeerId, ServedAsks>,
+    window: MassaTime,
+    min_asks: u32,
+    max_percent: u32,
+}
+
+impl WithholdingTracker {
+    pub(crate) fn new(config: &ProtocolConfig) -> Self {
+        WithholdingTracker {
+            peers: LruMap::new(ByLength::new(MAX_TRACKED_PEERS)),
+            window: config.withholding_window,
+            min_asks: config.withholding_min_asks,
+            max_percent: config.withholding_max_percent,
+        }
+    }
+
+    /// Counts of a peer, reset when its window is over
+    fn asks(&mut self, peer_id: &PeerId, now: MassaTime) -> &mut ServedAsks {
+        let window = self.window;
+        let asks = self
+            .peers
+            .get_or_insert(*peer_id, || ServedAsks {
+                start: now,
+                served: 0,
+                withheld: 0,
+            })
+            .expect("the tracked peers are limited by count");
+        if now.saturating_sub(asks.start) >= window {
+            *asks = ServedAsks {
+                start: now,
+                served: 0,
+                withheld: 0,
+            };
+        }
+        asks
+    }
+
+    /// A peer served the data of a block we asked it for
+    pub(crate) fn record_served(&mut self, peer_id: &PeerId, now: MassaTime) {
+        if self.max_percent == 0 {
+            return;
+        }
+        self.asks(peer_id, now).served += 1;
+    }
+
+    /// An ask to a peer for a block it announced timed out,
+    /// returns true if the peer withholds too much data and must be penalized
+    pub(crate) fn record_withheld(&mut self, peer_id: &PeerId, now: MassaTime) -> bool {
+        if self.max_percent == 0 {
+            return false;
+        }
+        let (min_asks, max_percent) = (self.min_asks, self.max_percent);
+        let asks = self.asks(peer_id, now);
+        asks.withheld += 1;
+        let total = asks.served + asks.withheld;
+        if total < min_asks.max(1) || asks.withheld * 100 <= max_percent * total {
+            return false;
+        }
+        // the peer is penalized once per window
+        self.peers.remove(peer_id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use massa_protocol_exports::{PeerId, ProtocolConfig};
+    use massa_signature::KeyPair;
+    use massa_time::MassaTime;
+
+    use super::WithholdingTracker;
+
+    #[test]
+    fn test_withholding_counted_over_window() {
+        let mut tracker = WithholdingTracker::new(&ProtocolConfig {
+            withholding_window: MassaTime::from_millis(1000),
+            withholding_min_asks: 4,
+            withholding_max_percent: 50,
+            ..Default::default()
+        });
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let start = MassaTime::from_millis(10_000);
+
+        // half of the asks answered is tolerated
+        tracker.record_served(&peer_id, start);
+        tracker.record_served(&peer_id, start);
+        assert!(!tracker.record_withheld(&peer_id, start));
+        assert!(!tracker.record_withheld(&peer_id, start));
+        assert!(tracker.record_withheld(&peer_id, start));
+
+        // the counts start over with the next window
+        let next_window = start.saturating_add(MassaTime::from_millis(1000));
+        tracker.record_served(&peer_id, start);
+        tracker.record_served(&peer_id, start);
+        for _ in 0..3 {
+            assert!(!tracker.record_withheld(&peer_id, next_window));
+        }
+        assert!(tracker.record_withheld(&peer_id, next_window));
+    }
+}
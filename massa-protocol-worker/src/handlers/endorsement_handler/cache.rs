@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use massa_models::endorsement::EndorsementId;
@@ -10,8 +11,10 @@ use schnellru::{ByLength, LruMap};
 
 /// Cache of endorsements
 pub struct EndorsementCache {
-    /// List of endorsements we checked recently
-    pub checked_endorsements: LruMap<EndorsementId, ()>,
+    /// List of endorsements we checked recently, with the time at which we first saw them
+    pub checked_endorsements: LruMap<EndorsementId, Instant>,
+    /// Time after which a checked endorsement is considered as unknown again
+    pub max_known_endorsements_age: Duration,
     /// List of endorsements known by peers
     pub endorsements_known_by_peer: HashMap<PeerId, LruMap<EndorsementId, ()>>,
     /// Maximum number of endorsements known by a peer
@@ -20,9 +23,14 @@ pub struct EndorsementCache {
 
 impl EndorsementCache {
     /// Create a new EndorsementCache
-    pub fn new(max_known_endorsements: u32, max_known_endorsements_by_peer: u32) -> Self {
+    pub fn new(
+        max_known_endorsements: u32,
+        max_known_endorsements_by_peer: u32,
+        max_known_endorsements_age: Duration,
+    ) -> Self {
         Self {
             checked_endorsements: LruMap::new(ByLength::new(max_known_endorsements)),
+            max_known_endorsements_age,
             endorsements_known_by_peer: HashMap::new(),
             max_known_endorsements_by_peer,
         }
@@ -43,9 +51,21 @@ impl EndorsementCache {
         }
     }
 
-    /// Mark an endorsement ID as checked by us
+    /// Mark an endorsement ID as checked by us, keeping the time we first saw it if it is still known
     pub fn insert_checked_endorsement(&mut self, enrodsement_id: EndorsementId) {
-        self.checked_endorsements.insert(enrodsement_id, ());
+        if !self.is_endorsement_checked(&enrodsement_id) {
+            self.checked_endorsements
+                .insert(enrodsement_id, Instant::now());
+        }
+    }
+
+    /// Check if we saw an endorsement less than `max_known_endorsements_age` ago
+    pub fn is_endorsement_checked(&self, endorsement_id: &EndorsementId) -> bool {
+        self.checked_endorsements
+            .peek(endorsement_id)
+            .map_or(false, |seen_at| {
+                seen_at.elapsed() < self.max_known_endorsements_age
+            })
     }
 
     /// Update caches to remove all data from disconnected peers
@@ -1,7 +1,9 @@
+use massa_hash::{HashDeserializer, HashSerializer};
 use massa_models::{
-    endorsement::{Endorsement, EndorsementDeserializer, SecureShareEndorsement},
-    secure_share::{SecureShareDeserializer, SecureShareSerializer},
+    endorsement::{Endorsement, EndorsementDeserializer, EndorsementId, SecureShareEndorsement},
+    secure_share::{AddressDerivation, Id, SecureShareDeserializer, SecureShareSerializer},
 };
+use massa_protocol_exports::MessageKind;
 use massa_serialization::{
     Deserializer, SerializeError, Serializer, U64VarIntDeserializer, U64VarIntSerializer,
 };
@@ -11,33 +13,64 @@ use nom::{
     IResult, Parser,
 };
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::ops::Bound::Included;
+use std::{ops::Bound::Included, sync::Arc};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum EndorsementMessage {
     /// Endorsements
     Endorsements(Vec<SecureShareEndorsement>),
+    /// Ids of endorsements the sender has
+    AnnounceIds(Vec<EndorsementId>),
+    /// Someone asks for endorsements
+    AskEndorsements(Vec<EndorsementId>),
 }
 
 #[derive(IntoPrimitive, Debug, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u64)]
 pub enum MessageTypeId {
     Endorsements,
+    AnnounceIds,
+    AskEndorsements,
 }
 
 impl From<&EndorsementMessage> for MessageTypeId {
     fn from(message: &EndorsementMessage) -> Self {
         match message {
             EndorsementMessage::Endorsements(_) => MessageTypeId::Endorsements,
+            EndorsementMessage::AnnounceIds(_) => MessageTypeId::AnnounceIds,
+            EndorsementMessage::AskEndorsements(_) => MessageTypeId::AskEndorsements,
         }
     }
 }
 
+impl From<MessageTypeId> for MessageKind {
+    fn from(id: MessageTypeId) -> Self {
+        match id {
+            MessageTypeId::Endorsements => MessageKind::Endorsements,
+            MessageTypeId::AnnounceIds => MessageKind::EndorsementsAnnouncement,
+            MessageTypeId::AskEndorsements => MessageKind::AskEndorsements,
+        }
+    }
+}
+
+impl EndorsementMessage {
+    /// Kind of the message, as counted in the protocol metrics
+    pub(crate) fn kind(&self) -> MessageKind {
+        MessageTypeId::from(self).into()
+    }
+
+    /// Kind of a serialized endorsement message from its leading type id, `None` if invalid
+    pub(crate) fn kind_of(raw_id: u64) -> Option<MessageKind> {
+        MessageTypeId::try_from(raw_id).ok().map(MessageKind::from)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct EndorsementMessageSerializer {
     id_serializer: U64VarIntSerializer,
     length_endorsements_serializer: U64VarIntSerializer,
     secure_share_serializer: SecureShareSerializer,
+    hash_serializer: HashSerializer,
 }
 
 impl EndorsementMessageSerializer {
@@ -46,6 +79,7 @@ impl EndorsementMessageSerializer {
             id_serializer: U64VarIntSerializer::new(),
             length_endorsements_serializer: U64VarIntSerializer::new(),
             secure_share_serializer: SecureShareSerializer::new(),
+            hash_serializer: HashSerializer::new(),
         }
     }
 }
@@ -71,6 +105,15 @@ impl Serializer<EndorsementMessage> for EndorsementMessageSerializer {
                         .serialize(endorsement, buffer)?;
                 }
             }
+            EndorsementMessage::AnnounceIds(endorsement_ids)
+            | EndorsementMessage::AskEndorsements(endorsement_ids) => {
+                self.length_endorsements_serializer
+                    .serialize(&(endorsement_ids.len() as u64), buffer)?;
+                for endorsement_id in endorsement_ids {
+                    self.hash_serializer
+                        .serialize(endorsement_id.get_hash(), buffer)?;
+                }
+            }
         }
         Ok(())
     }
@@ -86,6 +129,7 @@ pub struct EndorsementMessageDeserializer {
     id_deserializer: U64VarIntDeserializer,
     length_endorsements_deserializer: U64VarIntDeserializer,
     secure_share_deserializer: SecureShareDeserializer<Endorsement, EndorsementDeserializer>,
+    hash_deserializer: HashDeserializer,
 }
 
 impl EndorsementMessageDeserializer {
@@ -100,10 +144,41 @@ impl EndorsementMessageDeserializer {
                 args.thread_count,
                 args.endorsement_count,
             )),
+            hash_deserializer: HashDeserializer::new(),
         }
     }
 }
 
+impl EndorsementMessageDeserializer {
+    /// Derive the addresses of the creators of the received endorsements with `address_derivation`
+    pub fn with_address_derivation(
+        mut self,
+        address_derivation: Arc<dyn AddressDerivation>,
+    ) -> Self {
+        self.secure_share_deserializer = self
+            .secure_share_deserializer
+            .with_address_derivation(address_derivation);
+        self
+    }
+
+    fn deserialize_endorsement_ids<'a, E: ParseError<&'a [u8]> + ContextError<&'a [u8]>>(
+        &self,
+        buffer: &'a [u8],
+    ) -> IResult<&'a [u8], Vec<EndorsementId>, E> {
+        length_count(
+            context("Failed length deserialization", |input| {
+                self.length_endorsements_deserializer.deserialize(input)
+            }),
+            context("Failed endorsement id deserialization", |input| {
+                self.hash_deserializer
+                    .deserialize(input)
+                    .map(|(rest, hash)| (rest, EndorsementId::new(hash)))
+            }),
+        )
+        .parse(buffer)
+    }
+}
+
 impl Deserializer<EndorsementMessage> for EndorsementMessageDeserializer {
     fn deserialize<'a, E: ParseError<&'a [u8]> + ContextError<&'a [u8]>>(
         &self,
@@ -131,6 +206,20 @@ impl Deserializer<EndorsementMessage> for EndorsementMessageDeserializer {
                 )
                 .map(EndorsementMessage::Endorsements)
                 .parse(buffer),
+                MessageTypeId::AnnounceIds => {
+                    context("Failed AnnounceIds deserialization", |input| {
+                        self.deserialize_endorsement_ids(input)
+                    })
+                    .map(EndorsementMessage::AnnounceIds)
+                    .parse(buffer)
+                }
+                MessageTypeId::AskEndorsements => {
+                    context("Failed AskEndorsements deserialization", |input| {
+                        self.deserialize_endorsement_ids(input)
+                    })
+                    .map(EndorsementMessage::AskEndorsements)
+                    .parse(buffer)
+                }
             }
         })
         .parse(buffer)
@@ -141,10 +230,11 @@ impl Deserializer<EndorsementMessage> for EndorsementMessageDeserializer {
 mod tests {
     use std::str::FromStr;
 
+    use massa_hash::Hash;
     use massa_models::{
         block_id::BlockId,
-        endorsement::{Endorsement, EndorsementSerializer},
-        secure_share::SecureShareContent,
+        endorsement::{Endorsement, EndorsementId, EndorsementSerializer},
+        secure_share::{Id, SecureShareContent},
         slot::Slot,
     };
     use massa_serialization::{DeserializeError, Deserializer, Serializer};
@@ -171,6 +261,35 @@ mod tests {
         assert_eq!(deserialized_message, message);
     }
 
+    #[test]
+    fn test_endorsement_ids_messages() {
+        let endorsement_ids: Vec<EndorsementId> = ["endo_1", "endo_2"]
+            .iter()
+            .map(|data| EndorsementId::new(Hash::compute_from(data.as_bytes())))
+            .collect();
+        let serializer = super::EndorsementMessageSerializer::new();
+        let deserializer =
+            super::EndorsementMessageDeserializer::new(super::EndorsementMessageDeserializerArgs {
+                thread_count: 32,
+                max_length_endorsements: 2,
+                endorsement_count: 16,
+            });
+        for message in [
+            super::EndorsementMessage::AnnounceIds(endorsement_ids.clone()),
+            super::EndorsementMessage::AskEndorsements(endorsement_ids.clone()),
+        ] {
+            let mut buffer = Vec::new();
+            serializer
+                .serialize(&message, &mut buffer)
+                .expect("Failed to serialize message");
+            let (rest, deserialized_message) = deserializer
+                .deserialize::<DeserializeError>(&buffer)
+                .expect("Failed to deserialize message");
+            assert!(rest.is_empty());
+            assert_eq!(deserialized_message, message);
+        }
+    }
+
     #[test]
     fn test_high_limit_message() {
         let endorsement = Endorsement {
@@ -1,4 +1,4 @@
-use std::thread::JoinHandle;
+use std::{sync::Arc, thread::JoinHandle};
 
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
 use massa_metrics::MassaMetrics;
@@ -7,7 +7,7 @@ use massa_pos_exports::SelectorController;
 use massa_protocol_exports::ProtocolConfig;
 use massa_storage::Storage;
 
-use crate::wrap_network::ActiveConnectionsTrait;
+use crate::{address_cache::AddressCache, wrap_network::ActiveConnectionsTrait};
 
 use self::{
     cache::SharedEndorsementCache, commands_propagation::EndorsementHandlerPropagationCommand,
@@ -44,6 +44,7 @@ impl EndorsementHandler {
         pool_controller: Box<dyn PoolController>,
         selector_controller: Box<dyn SelectorController>,
         cache: SharedEndorsementCache,
+        address_cache: Arc<AddressCache>,
         storage: Storage,
         config: ProtocolConfig,
         active_connections: Box<dyn ActiveConnectionsTrait>,
@@ -61,11 +62,13 @@ impl EndorsementHandler {
             local_sender.clone(),
             sender_peer_cmd,
             cache.clone(),
+            address_cache,
             selector_controller,
             pool_controller,
             config.clone(),
             storage.clone_without_refs(),
             massa_metrics,
+            active_connections.clone(),
         );
 
         let endorsement_propagation_thread =
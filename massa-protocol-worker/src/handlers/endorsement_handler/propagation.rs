@@ -85,12 +85,17 @@ impl PropagationThread {
 
         // mark that we have checked those endorsements
         for endorsement in &endorsements {
-            cache_write.checked_endorsements.insert(endorsement.id, ());
+            cache_write.insert_checked_endorsement(endorsement.id);
         }
 
         // Add peers that potentially don't exist in cache and remove the ones that disconnected
         cache_write.update_cache(&peers_connected);
 
+        // a passive node relays nothing
+        if self.config.passive_mode {
+            return;
+        }
+
         // Propagate to peers
         'peer_loop: for peer_id in peers_connected {
             // write access to the cache of which endorsements are known by the peer
@@ -115,8 +120,12 @@ impl PropagationThread {
                 if let Err(err) = self.active_connections.send_to_peer(
                     &peer_id,
                     &self.endorsement_serializer,
-                    EndorsementMessage::Endorsements(chunk.iter().map(|&e| e.clone()).collect())
-                        .into(),
+                    if self.config.endorsement_announce_enabled {
+                        EndorsementMessage::AnnounceIds(chunk.iter().map(|e| e.id).collect())
+                    } else {
+                        EndorsementMessage::Endorsements(chunk.iter().map(|&e| e.clone()).collect())
+                    }
+                    .into(),
                     false,
                 ) {
                     warn!(
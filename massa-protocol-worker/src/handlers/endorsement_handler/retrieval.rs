@@ -1,17 +1,17 @@
-use std::thread::JoinHandle;
+use std::{collections::HashSet, sync::Arc, thread::JoinHandle, time::Instant};
 
 use crossbeam::{channel::tick, select};
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
 use massa_logging::massa_trace;
 use massa_metrics::MassaMetrics;
 use massa_models::{
-    endorsement::SecureShareEndorsement,
+    endorsement::{EndorsementId, SecureShareEndorsement},
     prehash::{CapacityAllocator, PreHashMap, PreHashSet},
     timeslots::get_block_slot_timestamp,
 };
 use massa_pool_exports::PoolController;
 use massa_pos_exports::SelectorController;
-use massa_protocol_exports::PeerId;
+use massa_protocol_exports::{BanReason, PeerId};
 use massa_protocol_exports::{ProtocolConfig, ProtocolError};
 use massa_serialization::{DeserializeError, Deserializer};
 use massa_storage::Storage;
@@ -19,18 +19,27 @@ use massa_time::MassaTime;
 use tracing::{debug, info, warn};
 
 use crate::{
+    address_cache::AddressCache,
     handlers::{
+        block_handler::selections::SelectionCache,
         endorsement_handler::messages::EndorsementMessage,
-        peer_handler::models::{PeerManagementCmd, PeerMessageTuple},
+        peer_handler::models::{
+            PeerManagementCmd, PeerMessageTuple, REPUTATION_UNDELIVERED_ENDORSEMENTS,
+        },
     },
+    messages::MessagesSerializer,
     sig_verifier::verify_sigs_batch,
+    wrap_network::ActiveConnectionsTrait,
 };
 
 use super::{
     cache::SharedEndorsementCache,
     commands_propagation::EndorsementHandlerPropagationCommand,
     commands_retrieval::EndorsementHandlerRetrievalCommand,
-    messages::{EndorsementMessageDeserializer, EndorsementMessageDeserializerArgs},
+    messages::{
+        EndorsementMessageDeserializer, EndorsementMessageDeserializerArgs,
+        EndorsementMessageSerializer,
+    },
 };
 
 pub struct RetrievalThread {
@@ -38,19 +47,25 @@ pub struct RetrievalThread {
     receiver_ext: MassaReceiver<EndorsementHandlerRetrievalCommand>,
     cache: SharedEndorsementCache,
     internal_sender: MassaSender<EndorsementHandlerPropagationCommand>,
-    selector_controller: Box<dyn SelectorController>,
+    /// draws used to check the endorsements received
+    selections: SelectionCache,
     pool_controller: Box<dyn PoolController>,
     config: ProtocolConfig,
     storage: Storage,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
     metrics: MassaMetrics,
     endorsement_message_deserializer: EndorsementMessageDeserializer,
+    active_connections: Box<dyn ActiveConnectionsTrait>,
+    endorsement_serializer: MessagesSerializer,
+    /// endorsements we asked for, with the peer we asked and when
+    asked_endorsements: PreHashMap<EndorsementId, (PeerId, Instant)>,
 }
 
 impl RetrievalThread {
     fn run(&mut self) {
         // regular interval ticks for metrics
         let tick_metrics = tick(self.metrics.tick_delay);
+        let tick_ask_endorsements = tick(self.config.ask_endorsement_timeout.to_duration());
 
         loop {
             select! {
@@ -92,6 +107,9 @@ impl RetrievalThread {
                     self.metrics
                         .set_endorsements_cache_metrics(cache_lock.checked_endorsements.len(), count);
                 }
+                recv(tick_ask_endorsements) -> _ => {
+                    self.penalize_undelivered_endorsements();
+                }
             }
         }
     }
@@ -118,11 +136,19 @@ impl RetrievalThread {
         match message {
             EndorsementMessage::Endorsements(endorsements) => {
                 debug!("Received endorsement message: Endorsement from {}", peer_id);
+                // the asks answered by this peer are fulfilled
+                for endorsement in &endorsements {
+                    if let Some((asked_peer_id, _)) = self.asked_endorsements.get(&endorsement.id) {
+                        if asked_peer_id == &peer_id {
+                            self.asked_endorsements.remove(&endorsement.id);
+                        }
+                    }
+                }
                 if let Err(err) = note_endorsements_from_peer(
                     endorsements,
                     &peer_id,
                     &self.cache,
-                    self.selector_controller.as_ref(),
+                    &mut self.selections,
                     &self.storage,
                     &self.config,
                     &self.internal_sender,
@@ -134,19 +160,136 @@ impl RetrievalThread {
                         loss of sync between us and the remote node. Err = {}",
                         peer_id, err
                     );
-                    if let Err(err) = self.ban_peer(&peer_id) {
+                    let reason = match err {
+                        ProtocolError::WrongSignature => BanReason::InvalidEndorsementSignature,
+                        _ => BanReason::ProtocolViolation,
+                    };
+                    if let Err(err) = self.ban_peer(&peer_id, reason) {
                         warn!("Error while banning peer {} err: {:?}", peer_id, err);
                     }
                 }
             }
+            EndorsementMessage::AnnounceIds(endorsement_ids) => {
+                debug!("Received endorsement message: AnnounceIds from {}", peer_id);
+                self.on_endorsement_ids_announced(&peer_id, endorsement_ids);
+            }
+            EndorsementMessage::AskEndorsements(endorsement_ids) => {
+                debug!(
+                    "Received endorsement message: AskEndorsements from {}",
+                    peer_id
+                );
+                self.on_asked_endorsements_received(&peer_id, endorsement_ids);
+            }
+        }
+    }
+
+    /// Ask the announcing peer for the endorsements we don't know and haven't asked yet
+    fn on_endorsement_ids_announced(
+        &mut self,
+        peer_id: &PeerId,
+        endorsement_ids: Vec<EndorsementId>,
+    ) {
+        let to_ask: Vec<EndorsementId> = {
+            let mut cache_write = self.cache.write();
+            cache_write.insert_peer_known_endorsements(peer_id, &endorsement_ids);
+            endorsement_ids
+                .into_iter()
+                .collect::<PreHashSet<_>>()
+                .into_iter()
+                .filter(|endorsement_id| {
+                    !cache_write.is_endorsement_checked(endorsement_id)
+                        && !self.asked_endorsements.contains_key(endorsement_id)
+                })
+                .collect()
+        };
+        // a passive node doesn't ask for endorsements
+        if to_ask.is_empty() || self.config.passive_mode {
+            return;
+        }
+        if let Err(err) = self.active_connections.send_to_peer(
+            peer_id,
+            &self.endorsement_serializer,
+            EndorsementMessage::AskEndorsements(to_ask.clone()).into(),
+            false,
+        ) {
+            warn!(
+                "Failed to send AskEndorsements message to peer {}: {}",
+                peer_id, err
+            );
+            return;
+        }
+        let now = Instant::now();
+        for endorsement_id in to_ask {
+            self.asked_endorsements
+                .insert(endorsement_id, (*peer_id, now));
+        }
+    }
+
+    /// Send the asked endorsements that we have to the peer
+    fn on_asked_endorsements_received(
+        &mut self,
+        peer_id: &PeerId,
+        endorsement_ids: Vec<EndorsementId>,
+    ) {
+        let endorsements: Vec<SecureShareEndorsement> = {
+            let storage_lock = self.storage.read_endorsements();
+            endorsement_ids
+                .iter()
+                .filter_map(|endorsement_id| storage_lock.get(endorsement_id).cloned())
+                .collect()
+        };
+        if endorsements.is_empty() {
+            return;
+        }
+        self.cache.write().insert_peer_known_endorsements(
+            peer_id,
+            &endorsements
+                .iter()
+                .map(|endorsement| endorsement.id)
+                .collect::<Vec<_>>(),
+        );
+        if let Err(err) = self.active_connections.send_to_peer(
+            peer_id,
+            &self.endorsement_serializer,
+            EndorsementMessage::Endorsements(endorsements).into(),
+            false,
+        ) {
+            warn!(
+                "Failed to send Endorsements message to peer {}: {}",
+                peer_id, err
+            );
+        }
+    }
+
+    /// Forget the asks that weren't answered in time and penalize the peers we asked
+    fn penalize_undelivered_endorsements(&mut self) {
+        let timeout = self.config.ask_endorsement_timeout.to_duration();
+        let mut penalized_peers = HashSet::new();
+        self.asked_endorsements.retain(|_, (peer_id, asked_at)| {
+            if asked_at.elapsed() < timeout {
+                return true;
+            }
+            penalized_peers.insert(*peer_id);
+            false
+        });
+        for peer_id in penalized_peers {
+            if let Err(err) = self
+                .peer_cmd_sender
+                .try_send(PeerManagementCmd::AdjustReputation(
+                    peer_id,
+                    REPUTATION_UNDELIVERED_ENDORSEMENTS,
+                ))
+            {
+                warn!("Error when penalizing peer {}: {}", peer_id, err);
+            }
         }
     }
 
     /// send a ban peer command to the peer handler
-    fn ban_peer(&mut self, peer_id: &PeerId) -> Result<(), ProtocolError> {
+    fn ban_peer(&mut self, peer_id: &PeerId, reason: BanReason) -> Result<(), ProtocolError> {
         massa_trace!("ban node from retrieval thread", { "peer_id": peer_id.to_string() });
         self.peer_cmd_sender
-            .try_send(PeerManagementCmd::Ban(vec![*peer_id]))
+            .try_send(PeerManagementCmd::Ban(vec![*peer_id], reason))
             .map_err(|err| ProtocolError::SendError(err.to_string()))
     }
 }
@@ -165,7 +308,7 @@ pub(crate) fn note_endorsements_from_peer(
     endorsements: Vec<SecureShareEndorsement>,
     from_peer_id: &PeerId,
     cache: &SharedEndorsementCache,
-    selector_controller: &dyn SelectorController,
+    selections: &mut SelectionCache,
     storage: &Storage,
     config: &ProtocolConfig,
     endorsement_propagation_sender: &MassaSender<EndorsementHandlerPropagationCommand>,
@@ -181,12 +324,8 @@ pub(crate) fn note_endorsements_from_peer(
             let endorsement_id = endorsement.id;
             all_endorsement_ids.insert(endorsement_id);
 
-            // only consider the endorsement as new if we have not already checked it
-            if cache_read
-                .checked_endorsements
-                .peek(&endorsement_id)
-                .is_none()
-            {
+            // only consider the endorsement as new if we have not already checked it recently
+            if !cache_read.is_endorsement_checked(&endorsement_id) {
                 new_endorsements.insert(endorsement_id, endorsement);
             }
         }
@@ -208,7 +347,7 @@ pub(crate) fn note_endorsements_from_peer(
 
     // Check PoS draws
     for endorsement in new_endorsements.values() {
-        let selection = selector_controller
+        let selection = selections
             .get_selection(endorsement.content.slot)?
             .endorsements;
         let Some(address) = selection.get(endorsement.content.index as usize) else {
@@ -288,18 +427,21 @@ pub fn start_retrieval_thread(
     internal_sender: MassaSender<EndorsementHandlerPropagationCommand>,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
     cache: SharedEndorsementCache,
+    address_cache: Arc<AddressCache>,
     selector_controller: Box<dyn SelectorController>,
     pool_controller: Box<dyn PoolController>,
     config: ProtocolConfig,
     storage: Storage,
     metrics: MassaMetrics,
+    active_connections: Box<dyn ActiveConnectionsTrait>,
 ) -> JoinHandle<()> {
     let endorsement_message_deserializer =
         EndorsementMessageDeserializer::new(EndorsementMessageDeserializerArgs {
             thread_count: config.thread_count,
             max_length_endorsements: config.max_endorsements_per_message,
             endorsement_count: config.endorsement_count,
-        });
+        })
+        .with_address_derivation(address_cache);
     std::thread::Builder::new()
         .name("protocol-endorsement-handler-retrieval".to_string())
         .spawn(move || {
@@ -309,12 +451,16 @@ pub fn start_retrieval_thread(
                 peer_cmd_sender,
                 cache,
                 internal_sender,
-                selector_controller,
+                selections: SelectionCache::new(selector_controller, &config),
                 pool_controller,
                 config,
                 storage,
                 metrics,
                 endorsement_message_deserializer,
+                active_connections,
+                endorsement_serializer: MessagesSerializer::new()
+                    .with_endorsement_message_serializer(EndorsementMessageSerializer::new()),
+                asked_endorsements: PreHashMap::default(),
             };
             retrieval_thread.run();
         })
@@ -16,6 +16,8 @@ pub struct OperationCache {
     pub checked_operations_prefix: LruMap<OperationPrefixId, ()>,
     /// List of operations known by peers
     pub ops_known_by_peer: HashMap<PeerId, LruMap<OperationPrefixId, ()>>,
+    /// List of operations announced to us by peers, that they have and we don't send them
+    pub ops_announced_by_peer: HashMap<PeerId, LruMap<OperationPrefixId, ()>>,
     /// Maximum number of operations known by a peer
     pub max_known_ops_by_peer: u32,
 }
@@ -27,6 +29,7 @@ impl OperationCache {
             checked_operations: LruMap::new(ByLength::new(max_known_ops)),
             checked_operations_prefix: LruMap::new(ByLength::new(max_known_ops)),
             ops_known_by_peer: HashMap::new(),
+            ops_announced_by_peer: HashMap::new(),
             max_known_ops_by_peer,
         }
     }
@@ -42,6 +45,18 @@ impl OperationCache {
         }
     }
 
+    /// Mark a list of operation ID prefixes as announced to us by a peer, and known by it
+    pub fn insert_peer_announced_ops(&mut self, peer_id: &PeerId, ops: &[OperationPrefixId]) {
+        self.insert_peer_known_ops(peer_id, ops);
+        let announced_ops = self
+            .ops_announced_by_peer
+            .entry(*peer_id)
+            .or_insert_with(|| LruMap::new(ByLength::new(self.max_known_ops_by_peer)));
+        for op in ops {
+            announced_ops.insert(*op, ());
+        }
+    }
+
     /// Mark an operation ID as checked by us
     pub fn insert_checked_operation(&mut self, operation_id: OperationId) {
         self.checked_operations.insert(operation_id, ());
@@ -54,6 +69,8 @@ impl OperationCache {
         // Remove disconnected peers from cache
         self.ops_known_by_peer
             .retain(|peer_id, _| peers_connected.contains(peer_id));
+        self.ops_announced_by_peer
+            .retain(|peer_id, _| peers_connected.contains(peer_id));
 
         // Add new connected peers to cache
         for peer_id in peers_connected {
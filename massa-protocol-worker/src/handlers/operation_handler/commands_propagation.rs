@@ -1,3 +1,4 @@
+use massa_protocol_exports::ProtocolConfigUpdate;
 use massa_storage::Storage;
 
 #[derive(Clone)]
@@ -5,4 +6,6 @@ pub enum OperationHandlerPropagationCommand {
     Stop,
     /// operations ids
     PropagateOperations(Storage),
+    /// Settings changed at runtime
+    UpdateConfig(ProtocolConfigUpdate),
 }
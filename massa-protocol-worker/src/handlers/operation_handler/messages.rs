@@ -1,7 +1,12 @@
-use massa_models::operation::{
-    OperationPrefixIds, OperationPrefixIdsDeserializer, OperationPrefixIdsSerializer,
-    OperationsDeserializer, OperationsSerializer, SecureShareOperation,
+use massa_models::{
+    operation::{
+        OperationId, OperationIdsDeserializer, OperationIdsSerializer, OperationPrefixIds,
+        OperationPrefixIdsDeserializer, OperationPrefixIdsSerializer, OperationsDeserializer,
+        OperationsSerializer, SecureShareOperation,
+    },
+    secure_share::AddressDerivation,
 };
+use massa_protocol_exports::MessageKind;
 use massa_serialization::{
     Deserializer, SerializeError, Serializer, U64VarIntDeserializer, U64VarIntSerializer,
 };
@@ -10,7 +15,7 @@ use nom::{
     IResult, Parser,
 };
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::ops::Bound::Included;
+use std::{ops::Bound::Included, sync::Arc};
 
 #[derive(Debug)]
 pub enum OperationMessage {
@@ -20,6 +25,10 @@ pub enum OperationMessage {
     AskForOperations(OperationPrefixIds),
     /// A list of operations
     Operations(Vec<SecureShareOperation>),
+    /// Someone ask for the ids of the operations of our pool, starting at this offset in the sorted ids
+    AskKnownIds(u64),
+    /// A page of the sorted ids of the operations of a pool, full unless it is the last one
+    KnownIds(Vec<OperationId>),
 }
 
 #[derive(IntoPrimitive, Debug, Eq, PartialEq, TryFromPrimitive)]
@@ -28,6 +37,8 @@ pub enum MessageTypeId {
     OperationsAnnouncement = 0,
     AskForOperations = 1,
     Operations = 2,
+    AskKnownIds = 3,
+    KnownIds = 4,
 }
 
 impl From<&OperationMessage> for MessageTypeId {
@@ -36,15 +47,43 @@ impl From<&OperationMessage> for MessageTypeId {
             OperationMessage::OperationsAnnouncement(_) => MessageTypeId::OperationsAnnouncement,
             OperationMessage::AskForOperations(_) => MessageTypeId::AskForOperations,
             OperationMessage::Operations(_) => MessageTypeId::Operations,
+            OperationMessage::AskKnownIds(_) => MessageTypeId::AskKnownIds,
+            OperationMessage::KnownIds(_) => MessageTypeId::KnownIds,
         }
     }
 }
 
+impl From<MessageTypeId> for MessageKind {
+    fn from(id: MessageTypeId) -> Self {
+        match id {
+            MessageTypeId::OperationsAnnouncement => MessageKind::OperationsAnnouncement,
+            MessageTypeId::AskForOperations => MessageKind::AskForOperations,
+            MessageTypeId::Operations => MessageKind::Operations,
+            MessageTypeId::AskKnownIds => MessageKind::AskKnownOperationIds,
+            MessageTypeId::KnownIds => MessageKind::KnownOperationIds,
+        }
+    }
+}
+
+impl OperationMessage {
+    /// Kind of the message, as counted in the protocol metrics
+    pub(crate) fn kind(&self) -> MessageKind {
+        MessageTypeId::from(self).into()
+    }
+
+    /// Kind of a serialized operation message from its leading type id, `None` if invalid
+    pub(crate) fn kind_of(raw_id: u64) -> Option<MessageKind> {
+        MessageTypeId::try_from(raw_id).ok().map(MessageKind::from)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct OperationMessageSerializer {
     id_serializer: U64VarIntSerializer,
     operation_prefix_ids_serializer: OperationPrefixIdsSerializer,
     operations_serializer: OperationsSerializer,
+    offset_serializer: U64VarIntSerializer,
+    operation_ids_serializer: OperationIdsSerializer,
 }
 
 impl OperationMessageSerializer {
@@ -53,6 +92,8 @@ impl OperationMessageSerializer {
             id_serializer: U64VarIntSerializer::new(),
             operation_prefix_ids_serializer: OperationPrefixIdsSerializer::new(),
             operations_serializer: OperationsSerializer::new(),
+            offset_serializer: U64VarIntSerializer::new(),
+            operation_ids_serializer: OperationIdsSerializer::new(),
         }
     }
 }
@@ -81,6 +122,13 @@ impl Serializer<OperationMessage> for OperationMessageSerializer {
             OperationMessage::Operations(operations) => {
                 self.operations_serializer.serialize(operations, buffer)?;
             }
+            OperationMessage::AskKnownIds(offset) => {
+                self.offset_serializer.serialize(offset, buffer)?;
+            }
+            OperationMessage::KnownIds(operation_ids) => {
+                self.operation_ids_serializer
+                    .serialize(operation_ids, buffer)?;
+            }
         }
         Ok(())
     }
@@ -90,11 +138,13 @@ pub struct OperationMessageDeserializer {
     id_deserializer: U64VarIntDeserializer,
     operation_prefix_ids_deserializer: OperationPrefixIdsDeserializer,
     operations_deserializer: OperationsDeserializer,
+    offset_deserializer: U64VarIntDeserializer,
+    operation_ids_deserializer: OperationIdsDeserializer,
 }
 
 /// Limits used in the deserialization of `OperationMessage`
 pub struct OperationMessageDeserializerArgs {
-    /// Maximum number of prefix ids that can be asked to propagate or sent
+    /// Maximum number of prefix ids that can be asked to propagate or sent, and of ids in a page of known ids
     pub max_operations_prefix_ids: u32,
     /// Maximum of full operations sent in one message
     pub max_operations: u32,
@@ -129,10 +179,27 @@ impl OperationMessageDeserializer {
                 args.max_op_datastore_key_length,
                 args.max_op_datastore_value_length,
             ),
+            offset_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            operation_ids_deserializer: OperationIdsDeserializer::new(
+                args.max_operations_prefix_ids,
+            ),
         }
     }
 }
 
+impl OperationMessageDeserializer {
+    /// Derive the addresses of the creators of the received operations with `address_derivation`
+    pub fn with_address_derivation(
+        mut self,
+        address_derivation: Arc<dyn AddressDerivation>,
+    ) -> Self {
+        self.operations_deserializer = self
+            .operations_deserializer
+            .with_address_derivation(address_derivation);
+        self
+    }
+}
+
 impl Deserializer<OperationMessage> for OperationMessageDeserializer {
     fn deserialize<'a, E: ParseError<&'a [u8]> + ContextError<&'a [u8]>>(
         &self,
@@ -168,6 +235,18 @@ impl Deserializer<OperationMessage> for OperationMessageDeserializer {
                     .map(OperationMessage::Operations)
                     .parse(buffer)
                 }
+                MessageTypeId::AskKnownIds => {
+                    context("Failed AskKnownIds deserialization", |input| {
+                        self.offset_deserializer.deserialize(input)
+                    })
+                    .map(OperationMessage::AskKnownIds)
+                    .parse(buffer)
+                }
+                MessageTypeId::KnownIds => context("Failed KnownIds deserialization", |input| {
+                    self.operation_ids_deserializer.deserialize(input)
+                })
+                .map(OperationMessage::KnownIds)
+                .parse(buffer),
             }
         })
         .parse(buffer)
@@ -1,17 +1,19 @@
-use std::thread::JoinHandle;
+use std::{sync::Arc, thread::JoinHandle};
 
-use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
+use massa_channel::{receiver::MassaReceiver, sender::MassaSender, MassaChannel};
 use massa_metrics::MassaMetrics;
 use massa_pool_exports::PoolController;
 use massa_protocol_exports::ProtocolConfig;
 use massa_storage::Storage;
 
-use crate::wrap_network::ActiveConnectionsTrait;
+use crate::{
+    address_cache::AddressCache, clock::ProtocolClock, wrap_network::ActiveConnectionsTrait,
+};
 
 use self::{
     cache::SharedOperationCache, commands_propagation::OperationHandlerPropagationCommand,
     commands_retrieval::OperationHandlerRetrievalCommand, propagation::start_propagation_thread,
-    retrieval::start_retrieval_thread,
+    retrieval::start_retrieval_thread, workers::OperationWorkers,
 };
 
 pub mod cache;
@@ -20,11 +22,15 @@ pub mod commands_retrieval;
 mod messages;
 mod propagation;
 mod retrieval;
+mod workers;
 
 pub(crate) use messages::{OperationMessage, OperationMessageSerializer};
-pub(crate) use retrieval::note_operations_from_peer;
+pub(crate) use retrieval::{classify_operations_offense, note_operations_from_peer};
 
-use super::peer_handler::models::{PeerManagementCmd, PeerMessageTuple};
+use super::{
+    block_handler::SharedProtocolMetrics,
+    peer_handler::models::{PeerManagementCmd, PeerMessageTuple},
+};
 
 pub struct OperationHandler {
     pub operation_retrieval_thread: Option<(
@@ -35,6 +41,8 @@ pub struct OperationHandler {
         MassaSender<OperationHandlerPropagationCommand>,
         JoinHandle<()>,
     )>,
+    /// deserialize the received messages for the retrieval thread
+    operation_workers: OperationWorkers,
 }
 
 impl OperationHandler {
@@ -44,6 +52,7 @@ impl OperationHandler {
         storage: Storage,
         config: ProtocolConfig,
         cache: SharedOperationCache,
+        address_cache: Arc<AddressCache>,
         active_connections: Box<dyn ActiveConnectionsTrait>,
         receiver_network: MassaReceiver<PeerMessageTuple>,
         sender_retrieval_ext: MassaSender<OperationHandlerRetrievalCommand>,
@@ -51,10 +60,23 @@ impl OperationHandler {
         local_sender: MassaSender<OperationHandlerPropagationCommand>,
         local_receiver: MassaReceiver<OperationHandlerPropagationCommand>,
         peer_cmd_sender: MassaSender<PeerManagementCmd>,
+        clock: ProtocolClock,
         massa_metrics: MassaMetrics,
+        protocol_metrics: SharedProtocolMetrics,
     ) -> Self {
-        let operation_retrieval_thread = start_retrieval_thread(
+        let (sender_workers, receiver_workers) = MassaChannel::new(
+            "operation_workers".to_string(),
+            Some(config.max_size_channel_network_to_operation_handler),
+        );
+        let operation_workers = OperationWorkers::start(
             receiver_network,
+            sender_workers,
+            &config,
+            cache.clone(),
+            address_cache,
+        );
+        let operation_retrieval_thread = start_retrieval_thread(
+            receiver_workers,
             pool_controller,
             storage.clone_without_refs(),
             config.clone(),
@@ -63,7 +85,9 @@ impl OperationHandler {
             receiver_retrieval_ext,
             local_sender.clone(),
             peer_cmd_sender,
+            clock,
             massa_metrics.clone(),
+            protocol_metrics,
         );
 
         let operation_propagation_thread = start_propagation_thread(
@@ -77,10 +101,12 @@ impl OperationHandler {
         Self {
             operation_retrieval_thread: Some((sender_retrieval_ext, operation_retrieval_thread)),
             operation_propagation_thread: Some((local_sender, operation_propagation_thread)),
+            operation_workers,
         }
     }
 
     pub fn stop(&mut self) {
+        self.operation_workers.stop();
         if let Some((tx, thread)) = self.operation_retrieval_thread.take() {
             let _ = tx.send(OperationHandlerRetrievalCommand::Stop);
             thread.join().unwrap();
@@ -1,4 +1,5 @@
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
 use std::{mem, thread::JoinHandle};
 
 use crossbeam::channel::RecvTimeoutError;
@@ -12,11 +13,12 @@ use massa_protocol_exports::PeerId;
 use massa_protocol_exports::ProtocolConfig;
 use massa_protocol_exports::ProtocolError;
 use massa_storage::Storage;
+use rand::{rngs::StdRng, seq::SliceRandom};
 use tracing::{debug, info, log::warn};
 
 use crate::{
     handlers::operation_handler::OperationMessage, messages::MessagesSerializer,
-    wrap_network::ActiveConnectionsTrait,
+    rng::peer_selection_rng, wrap_network::ActiveConnectionsTrait,
 };
 
 use super::{
@@ -34,6 +36,8 @@ struct PropagationThread {
     config: ProtocolConfig,
     cache: SharedOperationCache,
     operation_message_serializer: MessagesSerializer,
+    /// picks the peers an operation is announced to when `operation_propagation_fanout` is set
+    rng: StdRng,
     _massa_metrics: MassaMetrics,
 }
 
@@ -62,7 +66,25 @@ impl PropagationThread {
                             self.op_storage.extend(operations);
                             self.prune_propagation_storage();
 
-                            for op_id in new_ops {
+                            // a passive node relays nothing
+                            if self.config.passive_mode {
+                                continue;
+                            }
+
+                            // operations paying less than `min_propagation_fee` are kept but not relayed
+                            let relayed_ops: Vec<OperationId> = {
+                                let stored_ops = self.op_storage.read_operations();
+                                new_ops
+                                    .into_iter()
+                                    .filter(|op_id| {
+                                        stored_ops.get(op_id).map_or(false, |op| {
+                                            op.content.fee >= self.config.min_propagation_fee
+                                        })
+                                    })
+                                    .collect()
+                            };
+
+                            for op_id in relayed_ops {
                                 self.next_batch.insert(op_id);
                                 if self.next_batch.len()
                                     >= self.config.operation_announcement_buffer_capacity
@@ -78,6 +100,9 @@ impl PropagationThread {
                                 }
                             }
                         }
+                        OperationHandlerPropagationCommand::UpdateConfig(update) => {
+                            update.apply(&mut self.config);
+                        }
                         OperationHandlerPropagationCommand::Stop => {
                             info!("Stop operation propagation thread");
                             return;
@@ -142,7 +167,20 @@ impl PropagationThread {
         if self.next_batch.is_empty() {
             return;
         }
-        let operation_ids = mem::take(&mut self.next_batch);
+        let mut operation_ids: Vec<OperationId> =
+            mem::take(&mut self.next_batch).into_iter().collect();
+        if operation_ids.len() > self.config.max_operations_per_message as usize {
+            // the announcements don't fit in one message: the highest fees go first
+            let stored_ops = self.op_storage.read_operations();
+            operation_ids.sort_by_cached_key(|op_id| {
+                Reverse(
+                    stored_ops
+                        .get(op_id)
+                        .map(|op| op.content.fee)
+                        .unwrap_or_default(),
+                )
+            });
+        }
         massa_trace!("protocol.protocol_worker.announce_ops.begin", {
             "operation_ids": operation_ids
         });
@@ -151,44 +189,56 @@ impl PropagationThread {
             let peers_connected = self.active_connections.get_peer_ids_connected();
             cache_write.update_cache(&peers_connected);
 
-            // Propagate to peers
-            let all_keys: Vec<PeerId> = cache_write.ops_known_by_peer.keys().cloned().collect();
-            for peer_id in all_keys {
-                let ops = cache_write.ops_known_by_peer.get_mut(&peer_id).unwrap();
-                let new_ops: Vec<OperationId> = operation_ids
+            // Pick, for each operation, the peers it will be announced to
+            let fanout = self.config.operation_propagation_fanout;
+            let mut ops_to_announce: HashMap<PeerId, Vec<OperationId>> = HashMap::new();
+            for op_id in &operation_ids {
+                // the peer we received the operation from already knows it
+                let mut targets: Vec<PeerId> = cache_write
+                    .ops_known_by_peer
                     .iter()
-                    .filter(|id| ops.peek(&id.prefix()).is_none())
-                    .copied()
+                    .filter(|(_, ops)| ops.peek(&op_id.prefix()).is_none())
+                    .map(|(peer_id, _)| *peer_id)
                     .collect();
-                if !new_ops.is_empty() {
-                    for id in &new_ops {
-                        ops.insert(id.prefix(), ());
+                if fanout > 0 && targets.len() > fanout {
+                    // sorted first so that a seeded generator always gives the same selection
+                    targets.sort_unstable();
+                    targets.shuffle(&mut self.rng);
+                    targets.truncate(fanout);
+                }
+                for peer_id in targets {
+                    if let Some(ops) = cache_write.ops_known_by_peer.get_mut(&peer_id) {
+                        ops.insert(op_id.prefix(), ());
                     }
-                    debug!(
-                        "Send operations announcement of len {} to {}",
-                        new_ops.len(),
-                        peer_id
-                    );
-                    for sub_list in new_ops.chunks(self.config.max_operations_per_message as usize)
-                    {
-                        if let Err(err) = self.active_connections.send_to_peer(
-                            &peer_id,
-                            &self.operation_message_serializer,
-                            OperationMessage::OperationsAnnouncement(
-                                sub_list.iter().map(|id| id.into_prefix()).collect(),
-                            )
-                            .into(),
-                            false,
-                        ) {
-                            warn!(
-                                "Failed to send OperationsAnnouncement message to peer: {}",
-                                err
-                            );
-
-                            if let ProtocolError::PeerDisconnected(_) = err {
-                                // cache of this peer is removed in next call of cache_write.update_cache
-                                break;
-                            }
+                    ops_to_announce.entry(peer_id).or_default().push(*op_id);
+                }
+            }
+
+            // Propagate to peers
+            for (peer_id, new_ops) in ops_to_announce {
+                debug!(
+                    "Send operations announcement of len {} to {}",
+                    new_ops.len(),
+                    peer_id
+                );
+                for sub_list in new_ops.chunks(self.config.max_operations_per_message as usize) {
+                    if let Err(err) = self.active_connections.send_to_peer(
+                        &peer_id,
+                        &self.operation_message_serializer,
+                        OperationMessage::OperationsAnnouncement(
+                            sub_list.iter().map(|id| id.into_prefix()).collect(),
+                        )
+                        .into(),
+                        false,
+                    ) {
+                        warn!(
+                            "Failed to send OperationsAnnouncement message to peer: {}",
+                            err
+                        );
+
+                        if let ProtocolError::PeerDisconnected(_) = err {
+                            // cache of this peer is removed in next call of cache_write.update_cache
+                            break;
                         }
                     }
                 }
@@ -220,6 +270,7 @@ pub fn start_propagation_thread(
                         .operation_announcement_buffer_capacity
                         .saturating_add(1),
                 ),
+                rng: peer_selection_rng(config.rng_seed),
                 config,
                 cache,
                 _massa_metrics: massa_metrics,
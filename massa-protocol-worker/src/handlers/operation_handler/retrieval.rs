@@ -1,26 +1,36 @@
-use std::{collections::VecDeque, thread::JoinHandle, time::Instant};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    thread::JoinHandle,
+    time::Instant,
+};
 
-use crossbeam::{channel::tick, select};
+use crossbeam::{
+    channel::{never, tick},
+    select,
+};
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
 use massa_logging::massa_trace;
 use massa_metrics::MassaMetrics;
 use massa_models::{
-    operation::{OperationPrefixId, OperationPrefixIds, SecureShareOperation},
+    operation::{OperationId, OperationPrefixId, OperationPrefixIds, SecureShareOperation},
     prehash::{CapacityAllocator, PreHashMap, PreHashSet},
     secure_share::Id,
     slot::Slot,
-    timeslots::get_block_slot_timestamp,
+    timeslots::get_latest_block_slot_at_timestamp,
 };
 use massa_pool_exports::PoolController;
-use massa_protocol_exports::PeerId;
+use massa_protocol_exports::{BanReason, PeerId};
 use massa_protocol_exports::{ProtocolConfig, ProtocolError};
-use massa_serialization::{DeserializeError, Deserializer};
 use massa_storage::Storage;
-use massa_time::{MassaTime, TimeError};
+use massa_time::MassaTime;
 use schnellru::{ByLength, LruMap};
 
 use crate::{
-    handlers::peer_handler::models::{PeerManagementCmd, PeerMessageTuple},
+    clock::ProtocolClock,
+    handlers::block_handler::SharedProtocolMetrics,
+    handlers::peer_handler::models::{
+        BanSeverity, PeerManagementCmd, REPUTATION_EXPIRED_OPERATIONS, REPUTATION_VALID_OPERATIONS,
+    },
     messages::MessagesSerializer,
     sig_verifier::verify_sigs_batch,
     wrap_network::ActiveConnectionsTrait,
@@ -28,19 +38,17 @@ use crate::{
 use tracing::{debug, info, warn};
 
 use super::{
-    cache::SharedOperationCache,
-    commands_propagation::OperationHandlerPropagationCommand,
-    commands_retrieval::OperationHandlerRetrievalCommand,
-    messages::{OperationMessage, OperationMessageDeserializer, OperationMessageDeserializerArgs},
-    OperationMessageSerializer,
+    cache::SharedOperationCache, commands_propagation::OperationHandlerPropagationCommand,
+    commands_retrieval::OperationHandlerRetrievalCommand, messages::OperationMessage,
+    workers::InboundOperationMessage, OperationMessageSerializer,
 };
 
 /// Structure containing a Batch of `operation_ids` we would like to ask
 /// to a `peer_id` now or later. Mainly used in protocol and translated into
 /// simple combination of a `peer_id` and `operations_prefix_ids`
 pub struct OperationBatchItem {
-    /// last updated at instant
-    pub instant: Instant,
+    /// time at which the batch is processed
+    pub instant: MassaTime,
     /// node id
     pub peer_id: PeerId,
     /// operation prefix ids
@@ -48,10 +56,10 @@ pub struct OperationBatchItem {
 }
 
 pub struct RetrievalThread {
-    receiver: MassaReceiver<PeerMessageTuple>,
+    receiver: MassaReceiver<InboundOperationMessage>,
     pool_controller: Box<dyn PoolController>,
     cache: SharedOperationCache,
-    asked_operations: LruMap<OperationPrefixId, (Instant, Vec<PeerId>)>,
+    asked_operations: LruMap<OperationPrefixId, (MassaTime, Vec<PeerId>)>,
     active_connections: Box<dyn ActiveConnectionsTrait>,
     op_batch_buffer: VecDeque<OperationBatchItem>,
     storage: Storage,
@@ -60,59 +68,70 @@ pub struct RetrievalThread {
     receiver_ext: MassaReceiver<OperationHandlerRetrievalCommand>,
     operation_message_serializer: MessagesSerializer,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
+    /// reputation earned by peers for relaying valid operations, sent to the peer handler at each tick
+    pending_reputation_rewards: HashMap<PeerId, i32>,
+    /// number of messages in a row carrying expired operations sent by each peer
+    expired_operation_messages: HashMap<PeerId, u32>,
+    /// operations waiting to be sent to each peer, with the time at which the batch was started
+    pending_operation_batches: HashMap<PeerId, (Instant, Vec<SecureShareOperation>)>,
+    /// peers asked for the ids of their pool operations, with the offset of the next page expected from them
+    mempool_syncs: HashMap<PeerId, u64>,
+    /// peers that sent all the ids of their pool operations
+    mempool_synced_peers: HashSet<PeerId>,
+    protocol_metrics: SharedProtocolMetrics,
+    /// time of the operation asks and of the batches waiting to be asked again
+    clock: ProtocolClock,
     _massa_metrics: MassaMetrics,
 }
 
 impl RetrievalThread {
     fn run(&mut self) {
-        let operation_message_deserializer =
-            OperationMessageDeserializer::new(OperationMessageDeserializerArgs {
-                max_operations_prefix_ids: self.config.max_operations_per_message as u32,
-                max_operations: self.config.max_operations_per_message as u32,
-                max_datastore_value_length: self.config.max_op_datastore_value_length,
-                max_function_name_length: self.config.max_size_function_name,
-                max_parameters_size: self.config.max_size_call_sc_parameter,
-                max_op_datastore_entry_count: self.config.max_op_datastore_entry_count,
-                max_op_datastore_key_length: self.config.max_op_datastore_key_length,
-                max_op_datastore_value_length: self.config.max_op_datastore_value_length,
-            });
         let tick_ask_operations = tick(self.config.operation_batch_proc_period.to_duration());
+        // outgoing operations are only batched when the window isn't zero
+        let tick_flush_operations = if self.config.operation_batch_window.as_millis() > 0 {
+            tick(self.config.operation_batch_window.to_duration())
+        } else {
+            never()
+        };
 
         loop {
             select! {
                 recv(self.receiver) -> msg => {
                     self.receiver.update_metrics();
                     match msg {
-                        Ok((peer_id, message)) => {
-                            let (rest, message) = match operation_message_deserializer
-                                .deserialize::<DeserializeError>(&message) {
-                                    Ok((rest, message)) => (rest, message),
-                                    Err(err) => {
-                                        warn!("Error when deserializing message from peer {}: Err = {}", peer_id, err);
-                                        continue;
-                                    }
-                                };
-                            if !rest.is_empty() {
-                                println!("Error: message not fully consumed");
-                                return;
-                            }
+                        Ok(InboundOperationMessage { peer_id, message, verified_operations }) => {
                             match message {
                                 OperationMessage::Operations(ops) => {
                                     debug!("Received operation message: Operations from {}", peer_id);
-                                    if let Err(err) = note_operations_from_peer(
+                                    if self.is_pool_full() {
+                                        debug!("Dropping {} operations from {}: the pool is full", ops.len(), peer_id);
+                                        continue;
+                                    }
+                                    match note_operations_from_peer(
                                         &self.storage,
                                         &mut self.cache,
                                         &self.config,
                                         ops,
                                         &peer_id,
+                                        &verified_operations,
                                         &mut self.internal_sender,
-                                        &mut self.pool_controller
+                                        &mut self.pool_controller,
+                                        &self.protocol_metrics,
                                     ) {
-                                        warn!("peer {} sent us critically incorrect operation, which may be an attack attempt by the remote peer or a loss of sync between us and the remote peer. Err = {}", peer_id, err);
+                                        Err(err) => {
+                                            warn!("peer {} sent us critically incorrect operation, which may be an attack attempt by the remote peer or a loss of sync between us and the remote peer. Err = {}", peer_id, err);
 
-                                        if let Err(e) = self.ban_node(&peer_id) {
-                                            warn!("Error when banning node: {}", e);
+                                            let (reason, severity) = classify_operations_offense(&err);
+                                            if let Err(e) = self.ban_node(&peer_id, reason, severity) {
+                                                warn!("Error when banning node: {}", e);
+                                            }
+                                        }
+                                        Ok(0) => {
+                                            self.expired_operation_messages.remove(&peer_id);
+                                            let reward = self.pending_reputation_rewards.entry(peer_id).or_default();
+                                            *reward = reward.saturating_add(REPUTATION_VALID_OPERATIONS);
                                         }
+                                        Ok(expired_count) => self.on_expired_operations_received(&peer_id, expired_count),
                                     }
                                 }
                                 OperationMessage::OperationsAnnouncement(announcement) => {
@@ -129,10 +148,21 @@ impl RetrievalThread {
                                         warn!("error when processing asked operations received from peer {}: Err = {}", peer_id, err);
                                     }
                                 }
+                                OperationMessage::AskKnownIds(offset) => {
+                                    debug!("Received operation message: AskKnownIds from {}", peer_id);
+                                    self.on_asked_known_ids_received(&peer_id, offset);
+                                }
+                                OperationMessage::KnownIds(operation_ids) => {
+                                    debug!("Received operation message: KnownIds from {}", peer_id);
+                                    if let Err(err) = self.on_known_ids_received(&peer_id, operation_ids) {
+                                        warn!("error when processing known ids received from peer {}: Err = {}", peer_id, err);
+                                    }
+                                }
                             }
                         }
                         Err(_) => {
                             info!("Stop operation retrieval thread");
+                            self.flush_operation_batches(true);
                             return;
                         }
                     }
@@ -143,11 +173,13 @@ impl RetrievalThread {
                         Ok(cmd) => match cmd {
                             OperationHandlerRetrievalCommand::Stop => {
                                 info!("Stop operation retrieval thread");
+                                self.flush_operation_batches(true);
                                 return;
                             }
                         },
                         Err(_) => {
                             info!("Stop operation retrieval thread");
+                            self.flush_operation_batches(true);
                             return;
                         }
                     }
@@ -156,6 +188,12 @@ impl RetrievalThread {
                     if let Err(err) = self.update_ask_operation() {
                         warn!("Error in update_ask_operation: {}", err);
                     };
+                    self.start_mempool_syncs();
+                    self.prune_expired_operation_messages();
+                    self.send_reputation_rewards();
+                }
+                recv(tick_flush_operations) -> _ => {
+                    self.flush_operation_batches(false);
                 }
             }
         }
@@ -198,7 +236,12 @@ impl RetrievalThread {
         // mark sender as knowing the ops
         self.cache
             .write()
-            .insert_peer_known_ops(peer_id, &op_batch.iter().copied().collect::<Vec<_>>());
+            .insert_peer_announced_ops(peer_id, &op_batch.iter().copied().collect::<Vec<_>>());
+
+        // don't ask for operations that the pool would drop, a passive node doesn't ask for them at all
+        if self.config.passive_mode || self.is_pool_full() {
+            return Ok(());
+        }
 
         // filter out the operations that we already know about
         {
@@ -209,7 +252,7 @@ impl RetrievalThread {
         let mut ask_set = OperationPrefixIds::with_capacity(op_batch.len());
         let mut future_set = OperationPrefixIds::with_capacity(op_batch.len());
         // exactitude isn't important, we want to have a now for that function call
-        let now = Instant::now();
+        let now = self.clock.now();
         let mut count_reask = 0;
         for op_id in op_batch {
             let opt_previous_ask = match self.asked_operations.get(&op_id) {
@@ -225,10 +268,7 @@ impl RetrievalThread {
             if let Some((previous_ask_time, previous_ask_peers)) = opt_previous_ask {
                 // Ask now if latest ask instant < now - operation_batch_proc_period
                 // otherwise add in future_set
-                if now
-                    .checked_duration_since(*previous_ask_time)
-                    .unwrap_or_default()
-                    > self.config.operation_batch_proc_period.to_duration()
+                if now.saturating_sub(*previous_ask_time) > self.config.operation_batch_proc_period
                 {
                     count_reask += 1;
                     ask_set.insert(op_id);
@@ -251,9 +291,7 @@ impl RetrievalThread {
             && !future_set.is_empty()
         {
             self.op_batch_buffer.push_back(OperationBatchItem {
-                instant: now
-                    .checked_add(self.config.operation_batch_proc_period.into())
-                    .ok_or(TimeError::TimeOverflowError)?,
+                instant: now.saturating_add(self.config.operation_batch_proc_period),
                 peer_id: *peer_id,
                 operations_prefix_ids: future_set,
             });
@@ -288,8 +326,13 @@ impl RetrievalThread {
         Ok(())
     }
 
+    /// Whether the operations received from the peers must be dropped because the pool is full
+    fn is_pool_full(&self) -> bool {
+        self.config.pool_backpressure_enabled && self.pool_controller.is_operation_pool_full()
+    }
+
     fn update_ask_operation(&mut self) -> Result<(), ProtocolError> {
-        let now = Instant::now();
+        let now = self.clock.now();
         while !self.op_batch_buffer.is_empty()
         // This unwrap is ok because we checked that it's not empty just before.
             && now >= self.op_batch_buffer.front().unwrap().instant
@@ -310,8 +353,12 @@ impl RetrievalThread {
     fn on_asked_operations_received(
         &mut self,
         peer_id: &PeerId,
-        op_pre_ids: OperationPrefixIds,
+        mut op_pre_ids: OperationPrefixIds,
     ) -> Result<(), ProtocolError> {
+        // the peer announced some of these operations to us, it already has them
+        if let Some(announced_ops) = self.cache.read().ops_announced_by_peer.get(peer_id) {
+            op_pre_ids.retain(|prefix| announced_ops.peek(prefix).is_none());
+        }
         if op_pre_ids.is_empty() {
             return Ok(());
         }
@@ -333,6 +380,139 @@ impl RetrievalThread {
                 }
             }
         }
+        if ops.is_empty() {
+            return Ok(());
+        }
+        if self.config.operation_batch_window.as_millis() == 0 {
+            self.send_operations(peer_id, ops);
+            return Ok(());
+        }
+
+        // batch the operations with the ones already waiting to be sent to this peer
+        let batch_len = {
+            let (_, batch) = self
+                .pending_operation_batches
+                .entry(*peer_id)
+                .or_insert_with(|| (Instant::now(), Vec::new()));
+            batch.extend(ops);
+            batch.len()
+        };
+        if batch_len >= self.config.operation_batch_max_size {
+            if let Some((_, batch)) = self.pending_operation_batches.remove(peer_id) {
+                self.send_operations(peer_id, batch);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send to a peer a page of the sorted ids of the operations of our pool, starting at `offset`
+    fn on_asked_known_ids_received(&mut self, peer_id: &PeerId, offset: u64) {
+        let mut operation_ids = self.pool_controller.get_operation_ids();
+        operation_ids.sort_unstable();
+        let page: Vec<OperationId> = operation_ids
+            .into_iter()
+            .skip(offset.try_into().unwrap_or(usize::MAX))
+            .take(self.config.max_operations_per_message as usize)
+            .collect();
+        debug!(
+            "Send known operation ids of len {} to {}",
+            page.len(),
+            peer_id
+        );
+        if let Err(err) = self.active_connections.send_to_peer(
+            peer_id,
+            &self.operation_message_serializer,
+            OperationMessage::KnownIds(page).into(),
+            false,
+        ) {
+            warn!("Failed to send KnownIds message to peer: {}", err);
+        }
+    }
+
+    /// Ask the operations we miss among a page of the pool of a peer we are syncing with,
+    /// then the next page if this one is full. Pages that weren't asked are ignored.
+    fn on_known_ids_received(
+        &mut self,
+        peer_id: &PeerId,
+        operation_ids: Vec<OperationId>,
+    ) -> Result<(), ProtocolError> {
+        let Some(offset) = self.mempool_syncs.get(peer_id).copied() else {
+            return Ok(());
+        };
+        let page_len = operation_ids.len() as u64;
+        if page_len < self.config.max_operations_per_message {
+            self.mempool_syncs.remove(peer_id);
+            self.mempool_synced_peers.insert(*peer_id);
+        } else {
+            let next_offset = offset.saturating_add(page_len);
+            self.mempool_syncs.insert(*peer_id, next_offset);
+            self.ask_known_ids(peer_id, next_offset);
+        }
+        self.on_operations_announcements_received(
+            operation_ids.iter().map(|id| id.into_prefix()).collect(),
+            peer_id,
+        )
+    }
+
+    /// Ask the ids of the operations of their pool to connected peers, until `mempool_sync_peers`
+    /// of them are syncing or synced. The peers disconnected during their sync are replaced.
+    fn start_mempool_syncs(&mut self) {
+        if self.config.mempool_sync_peers == 0 || self.config.passive_mode {
+            return;
+        }
+        let connected_peers = self.active_connections.get_peer_ids_connected();
+        self.mempool_syncs
+            .retain(|peer_id, _| connected_peers.contains(peer_id));
+        let mut missing_peers = self
+            .config
+            .mempool_sync_peers
+            .saturating_sub(self.mempool_syncs.len() + self.mempool_synced_peers.len());
+        for peer_id in connected_peers {
+            if missing_peers == 0 {
+                break;
+            }
+            if self.mempool_syncs.contains_key(&peer_id)
+                || self.mempool_synced_peers.contains(&peer_id)
+            {
+                continue;
+            }
+            self.mempool_syncs.insert(peer_id, 0);
+            self.ask_known_ids(&peer_id, 0);
+            missing_peers -= 1;
+        }
+    }
+
+    /// Ask a peer for the page of the ids of its pool operations starting at `offset`
+    fn ask_known_ids(&mut self, peer_id: &PeerId, offset: u64) {
+        if let Err(err) = self.active_connections.send_to_peer(
+            peer_id,
+            &self.operation_message_serializer,
+            OperationMessage::AskKnownIds(offset).into(),
+            false,
+        ) {
+            warn!("Failed to send AskKnownIds message to peer: {}", err);
+        }
+    }
+
+    /// Send the batches of operations that have been waiting for `operation_batch_window`,
+    /// or all of them if `force` is set
+    fn flush_operation_batches(&mut self, force: bool) {
+        let window = self.config.operation_batch_window.to_duration();
+        let peer_ids: Vec<PeerId> = self
+            .pending_operation_batches
+            .iter()
+            .filter(|(_, (started_at, _))| force || started_at.elapsed() >= window)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in peer_ids {
+            if let Some((_, batch)) = self.pending_operation_batches.remove(&peer_id) {
+                self.send_operations(&peer_id, batch);
+            }
+        }
+    }
+
+    /// Send full operations to a peer, in as few messages as possible
+    fn send_operations(&mut self, peer_id: &PeerId, ops: Vec<SecureShareOperation>) {
         debug!("Send full operations of len {} to {}", ops.len(), peer_id);
         for sub_list in ops.chunks(self.config.max_operations_per_message as usize) {
             if let Err(err) = self.active_connections.send_to_peer(
@@ -347,55 +527,127 @@ impl RetrievalThread {
                 }
             }
         }
-        Ok(())
+    }
+
+    /// Count a message of `peer_id` carrying expired operations, the peer loses reputation
+    /// for each such message beyond `max_expired_operation_messages` in a row
+    fn on_expired_operations_received(&mut self, peer_id: &PeerId, expired_count: usize) {
+        debug!(
+            "Dropped {} expired operations from {}",
+            expired_count, peer_id
+        );
+        let messages = self.expired_operation_messages.entry(*peer_id).or_default();
+        *messages = messages.saturating_add(1);
+        if *messages > self.config.max_expired_operation_messages {
+            let reward = self.pending_reputation_rewards.entry(*peer_id).or_default();
+            *reward = reward.saturating_add(REPUTATION_EXPIRED_OPERATIONS);
+        }
+    }
+
+    /// Forget the expired operation messages of the disconnected peers
+    fn prune_expired_operation_messages(&mut self) {
+        if self.expired_operation_messages.is_empty() {
+            return;
+        }
+        let connected_peers = self.active_connections.get_peer_ids_connected();
+        self.expired_operation_messages
+            .retain(|peer_id, _| connected_peers.contains(peer_id));
+    }
+
+    /// send the accumulated reputation rewards to the peer handler
+    fn send_reputation_rewards(&mut self) {
+        for (peer_id, reward) in self.pending_reputation_rewards.drain() {
+            if let Err(err) = self
+                .peer_cmd_sender
+                .try_send(PeerManagementCmd::AdjustReputation(peer_id, reward))
+            {
+                warn!("Error when rewarding peer {}: {}", peer_id, err);
+            }
+        }
     }
 
     /// send a ban peer command to the peer handler
-    fn ban_node(&mut self, peer_id: &PeerId) -> Result<(), ProtocolError> {
+    fn ban_node(
+        &mut self,
+        peer_id: &PeerId,
+        reason: BanReason,
+        severity: BanSeverity,
+    ) -> Result<(), ProtocolError> {
         massa_trace!("ban node from retrieval thread", { "peer_id": peer_id.to_string() });
         self.peer_cmd_sender
-            .try_send(PeerManagementCmd::Ban(vec![*peer_id]))
+            .try_send(PeerManagementCmd::BanWithSeverity(
+                vec![*peer_id],
+                reason,
+                severity,
+            ))
             .map_err(|err| ProtocolError::SendError(err.to_string()))
     }
 }
 
+/// Classify an error returned by `note_operations_from_peer` to ban the sender.
+/// Oversized operations can come from a node with another configuration, invalid signatures can't.
+pub(crate) fn classify_operations_offense(err: &ProtocolError) -> (BanReason, BanSeverity) {
+    match err {
+        ProtocolError::InvalidOperationError(_) => {
+            (BanReason::ProtocolViolation, BanSeverity::Minor)
+        }
+        ProtocolError::WrongSignature => (BanReason::InvalidOperationSignature, BanSeverity::Major),
+        _ => (BanReason::ProtocolViolation, BanSeverity::Major),
+    }
+}
+
+/// Check the operations received from a peer and send the new ones to the pool.
+/// `verified_operations` are the operations whose signatures were verified already, the other new ones are verified here.
+/// Returns the number of operations dropped because they expired more than `expired_operations_tolerance` periods ago.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn note_operations_from_peer(
     base_storage: &Storage,
     operations_cache: &mut SharedOperationCache,
     config: &ProtocolConfig,
     operations: Vec<SecureShareOperation>,
     source_peer_id: &PeerId,
+    verified_operations: &PreHashSet<OperationId>,
     ops_propagation_sender: &mut MassaSender<OperationHandlerPropagationCommand>,
     pool_controller: &mut Box<dyn PoolController>,
-) -> Result<(), ProtocolError> {
+    protocol_metrics: &SharedProtocolMetrics,
+) -> Result<usize, ProtocolError> {
     massa_trace!("protocol.protocol_worker.note_operations_from_peer", { "peer": source_peer_id, "operations": operations });
     let now = MassaTime::now();
-
+    let received_count = operations.len() as u64;
+    // none before the genesis, when no operation can be expired
+    let current_slot = get_latest_block_slot_at_timestamp(
+        config.thread_count,
+        config.t0,
+        config.genesis_timestamp,
+        now,
+    )
+    .ok()
+    .flatten();
+
+    let mut expired_count = 0;
     let mut new_operations = PreHashMap::with_capacity(operations.len());
     for operation in operations {
-        // ignore if op is too old
-        let expire_period_timestamp = get_block_slot_timestamp(
-            config.thread_count,
-            config.t0,
-            config.genesis_timestamp,
-            Slot::new(
-                operation.content.expire_period,
-                operation
-                    .content_creator_address
-                    .get_thread(config.thread_count),
-            ),
+        // ignore the op if it can't be included in a block anymore, gossiping it would waste bandwidth
+        let last_propagated_slot = Slot::new(
+            operation
+                .content
+                .expire_period
+                .saturating_add(config.expired_operations_tolerance),
+            operation
+                .content_creator_address
+                .get_thread(config.thread_count),
         );
-        match expire_period_timestamp {
-            Ok(slot_timestamp) => {
-                if slot_timestamp.saturating_add(config.max_operations_propagation_time) < now {
-                    continue;
-                }
-            }
-            Err(_) => continue,
+        if current_slot.map_or(false, |current_slot| last_propagated_slot < current_slot) {
+            expired_count += 1;
+            continue;
         }
 
         // quit if op is too big
         if operation.serialized_size() > config.max_serialized_operations_size_per_block {
+            // the whole batch is rejected
+            let mut metrics = protocol_metrics.write();
+            metrics.record_operations_received(received_count, 0);
+            metrics.record_invalid_operations(received_count);
             return Err(ProtocolError::InvalidOperationError(format!(
                 "Operation {} exceeds max block size,  maximum authorized {} bytes but found {} bytes",
                 operation.id,
@@ -416,14 +668,24 @@ pub(crate) fn note_operations_from_peer(
         let cache_read = operations_cache.read();
         new_operations.retain(|op_id, _| cache_read.checked_operations.peek(op_id).is_none());
     }
+    protocol_metrics.write().record_operations_received(
+        received_count,
+        (all_received_ids.len() - new_operations.len()) as u64,
+    );
 
     // optimized signature verification
-    verify_sigs_batch(
+    if let Err(err) = verify_sigs_batch(
         &new_operations
             .iter()
+            .filter(|(op_id, _)| !verified_operations.contains(*op_id))
             .map(|(op_id, op)| (*op_id.get_hash(), op.signature, op.content_creator_pub_key))
             .collect::<Vec<_>>(),
-    )?;
+    ) {
+        protocol_metrics
+            .write()
+            .record_invalid_operations(new_operations.len() as u64);
+        return Err(err);
+    }
 
     {
         // add to checked operations
@@ -445,6 +707,16 @@ pub(crate) fn note_operations_from_peer(
     }
 
     if !new_operations.is_empty() {
+        let below_fee_count = new_operations
+            .values()
+            .filter(|op| op.content.fee < config.min_propagation_fee)
+            .count();
+        {
+            let mut metrics = protocol_metrics.write();
+            metrics.record_operations_below_fee(below_fee_count as u64);
+            metrics.record_operations_sent_to_pool(new_operations.len() as u64, now);
+        }
+
         // Store new operations, claim locally
         let mut ops = base_storage.clone_without_refs();
         ops.store_operations(new_operations.into_values().collect());
@@ -460,12 +732,12 @@ pub(crate) fn note_operations_from_peer(
         pool_controller.add_operations(ops);
     }
 
-    Ok(())
+    Ok(expired_count)
 }
 
 #[allow(clippy::too_many_arguments)]
 pub fn start_retrieval_thread(
-    receiver: MassaReceiver<PeerMessageTuple>,
+    receiver: MassaReceiver<InboundOperationMessage>,
     pool_controller: Box<dyn PoolController>,
     storage: Storage,
     config: ProtocolConfig,
@@ -474,7 +746,9 @@ pub fn start_retrieval_thread(
     receiver_ext: MassaReceiver<OperationHandlerRetrievalCommand>,
     internal_sender: MassaSender<OperationHandlerPropagationCommand>,
     peer_cmd_sender: MassaSender<PeerManagementCmd>,
+    clock: ProtocolClock,
     massa_metrics: MassaMetrics,
+    protocol_metrics: SharedProtocolMetrics,
 ) -> JoinHandle<()> {
     std::thread::Builder::new()
         .name("protocol-operation-handler-retrieval".to_string())
@@ -498,6 +772,13 @@ pub fn start_retrieval_thread(
                     .with_operation_message_serializer(OperationMessageSerializer::new()),
                 op_batch_buffer: VecDeque::new(),
                 peer_cmd_sender,
+                pending_reputation_rewards: HashMap::new(),
+                expired_operation_messages: HashMap::new(),
+                pending_operation_batches: HashMap::new(),
+                mempool_syncs: HashMap::new(),
+                mempool_synced_peers: HashSet::new(),
+                protocol_metrics,
+                clock,
                 _massa_metrics: massa_metrics,
             };
             retrieval_thread.run();
@@ -0,0 +1,405 @@
+//! Workers deserializing the operation messages received from the peers.
+//!
+//! Deserializing the received operations and checking their signatures is the heaviest part of
+//! their processing, it is spread over `handler_worker_threads` threads ahead of the retrieval
+//! thread. The messages of a peer are always processed by the same worker, so that they reach the
+//! retrieval thread in the order in which the peer sent them.
+//!
+//! A worker checks the signatures of all the operations waiting in its channel in a single batch.
+//! Only when the batch is invalid are the messages checked one by one to find the culprits.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+    thread::JoinHandle,
+};
+
+use crossbeam::{
+    channel::{bounded, Receiver, Sender},
+    select,
+};
+use massa_channel::{receiver::MassaReceiver, sender::MassaSender, MassaChannel};
+use massa_models::{operation::OperationId, prehash::PreHashSet, secure_share::Id};
+use massa_protocol_exports::{PeerId, ProtocolConfig};
+use massa_serialization::{DeserializeError, Deserializer};
+use tracing::warn;
+
+use crate::{
+    address_cache::AddressCache, handlers::peer_handler::models::PeerMessageTuple,
+    sig_verifier::verify_sigs_batch,
+};
+
+use super::{
+    cache::SharedOperationCache,
+    messages::{OperationMessage, OperationMessageDeserializer, OperationMessageDeserializerArgs},
+};
+
+/// Operation message of a peer, deserialized by a worker
+pub(crate) struct InboundOperationMessage {
+    pub peer_id: PeerId,
+    pub message: OperationMessage,
+    /// operations of an `Operations` message whose signatures were checked by the worker and are valid
+    pub verified_operations: PreHashSet<OperationId>,
+}
+
+pub(crate) struct OperationWorkers {
+    /// dropped to stop the workers
+    stop_sender: Option<Sender<()>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl OperationWorkers {
+    /// Start the workers reading the messages of `receiver_network` and sending them deserialized to `sender_retrieval`
+    pub(crate) fn start(
+        receiver_network: MassaReceiver<PeerMessageTuple>,
+        sender_retrieval: MassaSender<InboundOperationMessage>,
+        config: &ProtocolConfig,
+        cache: SharedOperationCache,
+        address_cache: Arc<AddressCache>,
+    ) -> Self {
+        let (stop_sender, stop_receiver) = bounded(0);
+        let worker_count = config.handler_worker_threads.max(1);
+        let mut threads = Vec::with_capacity(worker_count + 1);
+        if worker_count == 1 {
+            threads.push(start_worker(
+                0,
+                receiver_network,
+                sender_retrieval,
+                stop_receiver,
+                config,
+                cache,
+                address_cache,
+            ));
+            return OperationWorkers {
+                stop_sender: Some(stop_sender),
+                threads,
+            };
+        }
+
+        let mut worker_senders = Vec::with_capacity(worker_count);
+        for index in 0..worker_count {
+            let (worker_sender, worker_receiver) = MassaChannel::new(
+                format!("operation_worker_{}", index),
+                Some(config.max_size_channel_network_to_operation_handler),
+            );
+            worker_senders.push(worker_sender);
+            threads.push(start_worker(
+                index,
+                worker_receiver,
+                sender_retrieval.clone(),
+                stop_receiver.clone(),
+                config,
+                cache.clone(),
+                address_cache.clone(),
+            ));
+        }
+        threads.push(
+            std::thread::Builder::new()
+                .name("protocol-operation-handler-dispatcher".to_string())
+                .spawn(move || loop {
+                    select! {
+                        recv(receiver_network) -> msg => {
+                            receiver_network.update_metrics();
+                            let Ok((peer_id, data)) = msg else {
+                                return;
+                            };
+                            let worker_sender = &worker_senders[worker_index(&peer_id, worker_senders.len())];
+                            if worker_sender.send((peer_id, data)).is_err() {
+                                return;
+                            }
+                        },
+                        recv(stop_receiver) -> _ => return,
+                    }
+                })
+                .expect("OS failed to start operation dispatcher thread"),
+        );
+        OperationWorkers {
+            stop_sender: Some(stop_sender),
+            threads,
+        }
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.stop_sender.take();
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// Worker processing the messages of `peer_id`, among `worker_count` ones
+fn worker_index(peer_id: &PeerId, worker_count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    peer_id.hash(&mut hasher);
+    (hasher.finish() % worker_count as u64) as usize
+}
+
+fn start_worker(
+    index: usize,
+    receiver: MassaReceiver<PeerMessageTuple>,
+    sender_retrieval: MassaSender<InboundOperationMessage>,
+    stop_receiver: Receiver<()>,
+    config: &ProtocolConfig,
+    cache: SharedOperationCache,
+    address_cache: Arc<AddressCache>,
+) -> JoinHandle<()> {
+    let deserializer = OperationMessageDeserializer::new(OperationMessageDeserializerArgs {
+        max_operations_prefix_ids: config.max_operations_per_message as u32,
+        max_operations: config.max_operations_per_message as u32,
+        max_datastore_value_length: config.max_op_datastore_value_length,
+        max_function_name_length: config.max_size_function_name,
+        max_parameters_size: config.max_size_call_sc_parameter,
+        max_op_datastore_entry_count: config.max_op_datastore_entry_count,
+        max_op_datastore_key_length: config.max_op_datastore_key_length,
+        max_op_datastore_value_length: config.max_op_datastore_value_length,
+    })
+    .with_address_derivation(address_cache);
+    let max_window_operations = config.max_operations_per_message as usize;
+    std::thread::Builder::new()
+        .name(format!("protocol-operation-handler-worker-{}", index))
+        .spawn(move || loop {
+            select! {
+                recv(receiver) -> msg => {
+                    receiver.update_metrics();
+                    let Ok(first) = msg else {
+                        return;
+                    };
+                    // the messages already waiting are verified together, up to a message worth of operations
+                    let mut window = Vec::new();
+                    let mut operation_count = 0;
+                    let mut next = Some(first);
+                    while let Some((peer_id, data)) = next.take() {
+                        match deserializer.deserialize::<DeserializeError>(&data) {
+                            Ok((rest, message)) if rest.is_empty() => {
+                                if let OperationMessage::Operations(operations) = &message {
+                                    operation_count += operations.len();
+                                }
+                                window.push((peer_id, message));
+                            }
+                            Ok(_) => warn!("Operation message from peer {} not fully consumed", peer_id),
+                            Err(err) => warn!("Error when deserializing message from peer {}: Err = {}", peer_id, err),
+                        }
+                        if operation_count < max_window_operations {
+                            next = receiver.try_recv().ok();
+                            if next.is_some() {
+                                receiver.update_metrics();
+                            }
+                        }
+                    }
+                    for message in verify_window(window, &cache) {
+                        if sender_retrieval.send(message).is_err() {
+                            return;
+                        }
+                    }
+                },
+                recv(stop_receiver) -> _ => return,
+            }
+        })
+        .expect("OS failed to start operation worker thread")
+}
+
+/// Check the signatures of the operations of a window of messages in a single batch.
+/// If the batch is invalid, the messages are checked one by one so that only the ones with an
+/// invalid signature are left unverified, for the retrieval thread to ban their senders
+fn verify_window(
+    window: Vec<(PeerId, OperationMessage)>,
+    cache: &SharedOperationCache,
+) -> Vec<InboundOperationMessage> {
+    // the operations already checked at this point may be evicted from the cache before the
+    // retrieval thread sees them, so the ones actually verified here are handed over explicitly
+    let (operation_ids, signatures): (Vec<Vec<_>>, Vec<Vec<_>>) = {
+        let cache_read = cache.read();
+        window
+            .iter()
+            .map(|(_, message)| match message {
+                OperationMessage::Operations(operations) => operations
+                    .iter()
+                    .filter(|op| cache_read.checked_operations.peek(&op.id).is_none())
+                    .map(|op| {
+                        (
+                            op.id,
+                            (*op.id.get_hash(), op.signature, op.content_creator_pub_key),
+                        )
+                    })
+                    .unzip(),
+                _ => (Vec::new(), Vec::new()),
+            })
+            .unzip()
+    };
+    let window_valid = verify_sigs_batch(&signatures.concat()).is_ok();
+    window
+        .into_iter()
+        .zip(operation_ids.into_iter().zip(signatures))
+        .map(|((peer_id, message), (operation_ids, signatures))| {
+            let verified_operations = if window_valid || verify_sigs_batch(&signatures).is_ok() {
+                operation_ids.into_iter().collect()
+            } else {
+                PreHashSet::default()
+            };
+            InboundOperationMessage {
+                peer_id,
+                message,
+                verified_operations,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use massa_channel::MassaChannel;
+    use massa_models::{
+        address::Address,
+        amount::Amount,
+        operation::{Operation, OperationSerializer, OperationType, SecureShareOperation},
+        secure_share::SecureShareContent,
+    };
+    use massa_protocol_exports::{PeerId, ProtocolConfig};
+    use massa_serialization::Serializer;
+    use massa_signature::KeyPair;
+    use parking_lot::RwLock;
+
+    use super::{verify_window, OperationWorkers};
+    use crate::{
+        address_cache::{AddressCache, MAX_CACHED_ADDRESSES},
+        handlers::operation_handler::{
+            cache::OperationCache, OperationMessage, OperationMessageSerializer,
+        },
+    };
+
+    #[test]
+    fn test_workers_keep_the_order_of_each_peer() {
+        let config = ProtocolConfig {
+            handler_worker_threads: 4,
+            ..Default::default()
+        };
+        let (sender_network, receiver_network) =
+            MassaChannel::new(String::from("test_workers_network"), None);
+        let (sender_retrieval, receiver_retrieval) =
+            MassaChannel::new(String::from("test_workers_retrieval"), None);
+        let cache = Arc::new(RwLock::new(OperationCache::new(100, 100)));
+        let mut workers = OperationWorkers::start(
+            receiver_network,
+            sender_retrieval,
+            &config,
+            cache,
+            Arc::new(AddressCache::new(MAX_CACHED_ADDRESSES)),
+        );
+
+        // each peer sends its messages numbered in order, interleaved with the ones of the others
+        let peer_ids: Vec<PeerId> = (0..8)
+            .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+            .collect();
+        let serializer = OperationMessageSerializer::new();
+        let message_count = 50;
+        for offset in 0..message_count {
+            for peer_id in &peer_ids {
+                let mut data = Vec::new();
+                serializer
+                    .serialize(&OperationMessage::AskKnownIds(offset), &mut data)
+                    .unwrap();
+                sender_network.send((*peer_id, data)).unwrap();
+            }
+        }
+
+        let mut received: HashMap<PeerId, Vec<u64>> = HashMap::new();
+        for _ in 0..message_count * peer_ids.len() as u64 {
+            let inbound = receiver_retrieval
+                .recv_timeout(Duration::from_secs(5))
+                .expect("a message wasn't processed");
+            let OperationMessage::AskKnownIds(offset) = inbound.message else {
+                panic!("unexpected message");
+            };
+            received.entry(inbound.peer_id).or_default().push(offset);
+        }
+        for peer_id in &peer_ids {
+            assert_eq!(
+                received[peer_id],
+                (0..message_count).collect::<Vec<u64>>(),
+                "messages of peer {} out of order",
+                peer_id
+            );
+        }
+        workers.stop();
+    }
+
+    fn create_operation(keypair: &KeyPair) -> SecureShareOperation {
+        let content = Operation {
+            fee: Amount::zero(),
+            op: OperationType::Transaction {
+                recipient_address: Address::from_public_key(&keypair.get_public_key()),
+                amount: Amount::default(),
+            },
+            expire_period: 1,
+        };
+        Operation::new_verifiable(content, OperationSerializer::new(), keypair).unwrap()
+    }
+
+    #[test]
+    fn test_invalid_window_is_checked_message_by_message() {
+        let keypair = KeyPair::generate(0).unwrap();
+        let mut invalid_operation = create_operation(&keypair);
+        invalid_operation.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
+        let peer_ids: Vec<PeerId> = (0..4)
+            .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+            .collect();
+        let window = vec![
+            (
+                peer_ids[0],
+                OperationMessage::Operations(vec![
+                    create_operation(&keypair),
+                    create_operation(&keypair),
+                ]),
+            ),
+            (
+                peer_ids[1],
+                OperationMessage::Operations(vec![create_operation(&keypair), invalid_operation]),
+            ),
+            (peer_ids[2], OperationMessage::AskKnownIds(0)),
+            (
+                peer_ids[3],
+                OperationMessage::Operations(vec![create_operation(&keypair)]),
+            ),
+        ];
+        let cache = Arc::new(RwLock::new(OperationCache::new(100, 100)));
+
+        // only the message with the invalid signature is left for the retrieval thread to reject
+        let verified: Vec<(PeerId, bool)> = verify_window(window, &cache)
+            .into_iter()
+            .map(|inbound| (inbound.peer_id, !inbound.verified_operations.is_empty()))
+            .collect();
+        assert_eq!(
+            verified,
+            vec![
+                (peer_ids[0], true),
+                (peer_ids[1], false),
+                (peer_ids[2], false),
+                (peer_ids[3], true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operations_already_checked_are_not_reported_verified() {
+        let keypair = KeyPair::generate(0).unwrap();
+        let mut forged_operation = create_operation(&keypair);
+        forged_operation.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let cache = Arc::new(RwLock::new(OperationCache::new(100, 100)));
+        cache.write().insert_checked_operation(forged_operation.id);
+
+        // the id is known so its signature is skipped, it must not be reported as verified
+        // in case it is evicted from the cache before the retrieval thread sees it
+        let inbound = verify_window(
+            vec![(
+                peer_id,
+                OperationMessage::Operations(vec![forged_operation]),
+            )],
+            &cache,
+        );
+        assert!(inbound[0].verified_operations.is_empty());
+    }
+}
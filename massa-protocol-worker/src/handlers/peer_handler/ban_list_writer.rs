@@ -0,0 +1,66 @@
+//! Background writes of the ban list file.
+//!
+//! The ban list is rewritten on each ban and unban, while the peer database is locked. Only its
+//! serialization is done under the lock: the content is handed to a thread that writes it to a
+//! temporary file renamed over the ban list, so that a crash never leaves a truncated list behind.
+//! When several contents are waiting, only the latest one is written.
+
+use std::path::PathBuf;
+
+use crossbeam::channel::{bounded, unbounded, Sender};
+use tracing::log::warn;
+
+/// Content to write, with the senders to notify once it is written
+type WriteRequest = (String, Option<Sender<()>>);
+
+#[derive(Clone)]
+pub struct BanListWriter {
+    sender: Sender<WriteRequest>,
+}
+
+impl BanListWriter {
+    /// Start the thread writing the ban list to `path`, it stops once all the writers are dropped
+    pub fn new(path: PathBuf) -> Self {
+        let (sender, receiver) = unbounded::<WriteRequest>();
+        std::thread::Builder::new()
+            .name("protocol-ban-list-writer".to_string())
+            .spawn(move || {
+                while let Ok((mut content, ack)) = receiver.recv() {
+                    let mut acks: Vec<_> = ack.into_iter().collect();
+                    // the contents still waiting are superseded by the latest one
+                    while let Ok((newer_content, ack)) = receiver.try_recv() {
+                        content = newer_content;
+                        acks.extend(ack);
+                    }
+                    let tmp_path = path.with_extension("tmp");
+                    let res = std::fs::write(&tmp_path, content)
+                        .and_then(|_| std::fs::rename(&tmp_path, &path));
+                    if let Err(err) = res {
+                        warn!("Could not write ban list file {:?}: {}", path, err);
+                    }
+                    for ack in acks {
+                        let _ = ack.send(());
+                    }
+                }
+            })
+            .expect("OS failed to start ban list writer thread");
+        BanListWriter { sender }
+    }
+
+    /// Queue the content to write, without waiting for it to be written
+    pub fn write(&self, content: String) {
+        if self.sender.send((content, None)).is_err() {
+            warn!("The ban list writer stopped, the ban list isn't saved");
+        }
+    }
+
+    /// Write the content and wait until it is written
+    pub fn write_and_wait(&self, content: String) {
+        let (ack_sender, ack_receiver) = bounded(1);
+        if self.sender.send((content, Some(ack_sender))).is_err() {
+            warn!("The ban list writer stopped, the ban list isn't saved");
+            return;
+        }
+        let _ = ack_receiver.recv();
+    }
+}
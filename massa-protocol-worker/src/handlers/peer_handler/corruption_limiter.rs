@@ -0,0 +1,162 @@
+//! Per-peer limit of the corrupted frames received.
+//!
+//! A frame failing its checksum is dropped without banning its sender: it was most likely corrupted
+//! on the way. But each one still costs us its checksum, so a peer sending more than
+//! `max_corrupted_frames` of them within `corrupted_frames_window` is disconnected, without being banned.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use massa_channel::sender::MassaSender;
+use massa_protocol_exports::{PeerId, ProtocolConfig};
+use parking_lot::Mutex;
+use tracing::{info, warn};
+
+use super::models::PeerManagementCmd;
+
+/// Corrupted frames counted for a peer since the start of its window
+struct CorruptionWindow {
+    start: Instant,
+    count: u64,
+}
+
+/// Corrupted frames limiter shared by all the connections
+#[derive(Clone)]
+pub struct CorruptedFramesLimiter {
+    /// number of corrupted frames a peer may send within `window`, no limit if 0
+    max_corrupted_frames: u64,
+    window: Duration,
+    windows: Arc<Mutex<HashMap<PeerId, CorruptionWindow>>>,
+    peer_cmd_sender: MassaSender<PeerManagementCmd>,
+}
+
+impl CorruptedFramesLimiter {
+    pub fn new(config: &ProtocolConfig, peer_cmd_sender: MassaSender<PeerManagementCmd>) -> Self {
+        CorruptedFramesLimiter {
+            max_corrupted_frames: config.max_corrupted_frames,
+            window: config.corrupted_frames_window.to_duration(),
+            windows: Default::default(),
+            peer_cmd_sender,
+        }
+    }
+
+    /// Count a corrupted frame received from a peer at time `now`,
+    /// returns true if the peer is disconnected for sending too many of them
+    pub fn record_at(&self, peer_id: &PeerId, now: Instant) -> bool {
+        if self.max_corrupted_frames == 0 {
+            return false;
+        }
+        let mut windows = self.windows.lock();
+        // the windows that ended are forgotten, so that only the peers sending corrupted frames are tracked
+        windows.retain(|_, window| now.saturating_duration_since(window.start) < self.window);
+        let window = windows.entry(*peer_id).or_insert(CorruptionWindow {
+            start: now,
+            count: 0,
+        });
+        window.count = window.count.saturating_add(1);
+        if window.count <= self.max_corrupted_frames {
+            return false;
+        }
+        windows.remove(peer_id);
+        drop(windows);
+        info!(
+            "Peer {} sent more than {} corrupted frames in {:?}, disconnecting it",
+            peer_id, self.max_corrupted_frames, self.window
+        );
+        if let Err(err) = self
+            .peer_cmd_sender
+            .try_send(PeerManagementCmd::Disconnect {
+                peer_id: *peer_id,
+                graceful: false,
+            })
+        {
+            warn!("Error while disconnecting peer {} err: {:?}", peer_id, err);
+        }
+        true
+    }
+
+    /// Count a corrupted frame received from a peer now,
+    /// returns true if the peer is disconnected for sending too many of them
+    pub fn record(&self, peer_id: &PeerId) -> bool {
+        self.record_at(peer_id, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use massa_channel::MassaChannel;
+    use massa_protocol_exports::{PeerId, ProtocolConfig};
+    use massa_signature::KeyPair;
+    use massa_time::MassaTime;
+
+    use crate::handlers::peer_handler::models::PeerManagementCmd;
+
+    use super::CorruptedFramesLimiter;
+
+    #[test]
+    fn test_peer_disconnected_above_the_corrupted_frames_threshold() {
+        let (sender, receiver) = MassaChannel::new("test_corruption_limiter".to_string(), None);
+        let limiter = CorruptedFramesLimiter::new(
+            &ProtocolConfig {
+                max_corrupted_frames: 3,
+                corrupted_frames_window: MassaTime::from_millis(1_000),
+                ..Default::default()
+            },
+            sender,
+        );
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let other_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+
+        // a few corrupted frames are tolerated
+        for _ in 0..3 {
+            assert!(!limiter.record_at(&peer_id, now));
+        }
+        assert!(!limiter.record_at(&other_peer_id, now));
+        assert!(receiver.try_recv().is_err());
+
+        // the count restarts with the window
+        let next_window = now + Duration::from_millis(1_000);
+        for _ in 0..3 {
+            assert!(!limiter.record_at(&peer_id, next_window));
+        }
+        assert!(receiver.try_recv().is_err());
+
+        // one more within the window disconnects the peer, without banning it
+        assert!(limiter.record_at(&peer_id, next_window));
+        match receiver.try_recv() {
+            Ok(PeerManagementCmd::Disconnect {
+                peer_id: disconnected,
+                graceful,
+            }) => {
+                assert_eq!(disconnected, peer_id);
+                assert!(!graceful);
+            }
+            _ => panic!("expected the peer to be disconnected"),
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_no_corrupted_frames_limit() {
+        let (sender, receiver) = MassaChannel::new("test_corruption_limiter".to_string(), None);
+        let limiter = CorruptedFramesLimiter::new(
+            &ProtocolConfig {
+                max_corrupted_frames: 0,
+                ..Default::default()
+            },
+            sender,
+        );
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(!limiter.record_at(&peer_id, now));
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+}
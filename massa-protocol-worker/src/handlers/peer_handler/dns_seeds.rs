@@ -0,0 +1,314 @@
+//! Discovery of peers through DNS seeds.
+//!
+//! The `host:port` names of `dns_seeds` are resolved at startup, then every `dns_seeds_refresh_interval`,
+//! in a thread of their own so that a slow resolver never delays the startup. The new addresses are
+//! added to the tested addresses of the peer database as due for a test, so the testers pick them
+//! first and add the nodes answering to the connection candidates.
+//! A seed that fails to resolve is retried with an exponential backoff.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossbeam::select;
+use massa_channel::{sender::MassaSender, MassaChannel};
+use massa_protocol_exports::ProtocolConfig;
+use massa_time::MassaTime;
+use tracing::log::{debug, warn};
+
+use super::models::{InitialPeers, SharedPeerDB};
+use crate::ip::is_ip_enabled;
+
+/// Delay before retrying a seed after its first resolution failure, doubled at each new failure
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Resolves the DNS seeds, abstracted to be mocked in the tests
+pub trait DnsResolver: Send {
+    fn resolve(&self, seed: &str) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Resolver of the operating system
+pub struct SystemDnsResolver;
+
+impl DnsResolver for SystemDnsResolver {
+    fn resolve(&self, seed: &str) -> io::Result<Vec<SocketAddr>> {
+        Ok(seed.to_socket_addrs()?.collect())
+    }
+}
+
+struct SeedState {
+    next_resolution: Instant,
+    failures: u32,
+}
+
+/// Resolves the seeds whose time has come at `now` and registers the new addresses in the peer
+/// database, returns when the next resolution is due
+fn resolve_due_seeds(
+    resolver: &dyn DnsResolver,
+    seeds: &mut HashMap<String, SeedState>,
+    peer_db: &SharedPeerDB,
+    known_addresses: &HashSet<SocketAddr>,
+    config: &ProtocolConfig,
+    now: Instant,
+) -> Option<Instant> {
+    let refresh_interval = config.dns_seeds_refresh_interval.to_duration();
+    // old enough to be the first ones picked by the testers
+    let tested_at = MassaTime::now()
+        .saturating_sub(config.test_oldest_peer_cooldown)
+        .saturating_sub(MassaTime::from_millis(1000));
+    for (seed, state) in seeds.iter_mut() {
+        if state.next_resolution > now {
+            continue;
+        }
+        match resolver.resolve(seed) {
+            Ok(addrs) => {
+                state.failures = 0;
+                state.next_resolution = now + refresh_interval;
+                let mut peer_db = peer_db.write();
+                let mut new_addrs = 0;
+                for addr in addrs {
+                    if !is_ip_enabled(&addr, config.enable_ipv6)
+                        || known_addresses.contains(&addr)
+                        || peer_db.get_tested_addresses().contains_key(&addr)
+                    {
+                        continue;
+                    }
+                    peer_db.insert_tested_address(&addr, tested_at);
+                    new_addrs += 1;
+                }
+                debug!("DNS seed {} resolved to {} new addresses", seed, new_addrs);
+            }
+            Err(err) => {
+                let backoff = FIRST_RETRY_DELAY
+                    .saturating_mul(2u32.saturating_pow(state.failures))
+                    .min(refresh_interval);
+                state.failures = state.failures.saturating_add(1);
+                state.next_resolution = now + backoff;
+                warn!(
+                    "Failed to resolve DNS seed {}: {}, retrying in {:?}",
+                    seed, err, backoff
+                );
+            }
+        }
+    }
+    seeds.values().map(|state| state.next_resolution).min()
+}
+
+/// Thread resolving the DNS seeds in the background
+pub struct DnsSeeder {
+    stop_sender: MassaSender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DnsSeeder {
+    /// Start resolving the seeds of the config, returns `None` if there isn't any
+    pub fn start(
+        config: &ProtocolConfig,
+        resolver: Box<dyn DnsResolver>,
+        peer_db: SharedPeerDB,
+        initial_peers: &InitialPeers,
+    ) -> Option<Self> {
+        if config.dns_seeds.is_empty() {
+            return None;
+        }
+        // the initial peers are already tested at startup
+        let known_addresses: HashSet<SocketAddr> = initial_peers
+            .values()
+            .flat_map(|listeners| listeners.keys().copied())
+            .collect();
+        let now = Instant::now();
+        let mut seeds: HashMap<String, SeedState> = config
+            .dns_seeds
+            .iter()
+            .map(|seed| {
+                (
+                    seed.clone(),
+                    SeedState {
+                        next_resolution: now,
+                        failures: 0,
+                    },
+                )
+            })
+            .collect();
+        let config = config.clone();
+        let (stop_sender, stop_receiver) =
+            MassaChannel::new("dns_seeder_stop".to_string(), Some(1));
+        let handle = std::thread::Builder::new()
+            .name("protocol-dns-seeder".to_string())
+            .spawn(move || loop {
+                let next_resolution = resolve_due_seeds(
+                    resolver.as_ref(),
+                    &mut seeds,
+                    &peer_db,
+                    &known_addresses,
+                    &config,
+                    Instant::now(),
+                );
+                let wait = next_resolution
+                    .map(|next| next.saturating_duration_since(Instant::now()))
+                    .unwrap_or(config.dns_seeds_refresh_interval.to_duration());
+                select! {
+                    recv(stop_receiver) -> _ => return,
+                    default(wait) => {}
+                }
+            })
+            .expect("OS failed to start DNS seeder thread");
+        Some(DnsSeeder {
+            stop_sender,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        let _ = self.stop_sender.try_send(());
+        if let Some(handle) = self.handle.take() {
+            handle.join().expect("Failed to join DNS seeder thread");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        io,
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use massa_protocol_exports::ProtocolConfig;
+    use massa_time::MassaTime;
+    use parking_lot::RwLock;
+
+    use super::{resolve_due_seeds, DnsResolver, SeedState, FIRST_RETRY_DELAY};
+    use crate::handlers::peer_handler::models::{PeerDB, SharedPeerDB};
+
+    struct MockResolver(HashMap<String, Vec<SocketAddr>>);
+
+    impl DnsResolver for MockResolver {
+        fn resolve(&self, seed: &str) -> io::Result<Vec<SocketAddr>> {
+            self.0
+                .get(seed)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown host"))
+        }
+    }
+
+    #[test]
+    fn test_dns_seed_addresses_become_candidates() {
+        let config = ProtocolConfig {
+            dns_seeds_refresh_interval: MassaTime::from_millis(60 * 60 * 1000),
+            test_oldest_peer_cooldown: MassaTime::from_millis(1000),
+            ..Default::default()
+        };
+        let seed_addrs: Vec<SocketAddr> = vec![
+            "10.0.0.1:31244".parse().unwrap(),
+            "10.0.0.2:31244".parse().unwrap(),
+            "[2001:db8::1]:31244".parse().unwrap(),
+        ];
+        let initial_peer_addr = seed_addrs[0];
+        let resolver = MockResolver(HashMap::from([(
+            "seed.massa.test:31244".to_string(),
+            seed_addrs.clone(),
+        )]));
+        let peer_db: SharedPeerDB = Arc::new(RwLock::new(PeerDB::default()));
+        let now = Instant::now();
+        let mut seeds = HashMap::from([
+            (
+                "seed.massa.test:31244".to_string(),
+                SeedState {
+                    next_resolution: now,
+                    failures: 0,
+                },
+            ),
+            (
+                "unknown.massa.test:31244".to_string(),
+                SeedState {
+                    next_resolution: now,
+                    failures: 0,
+                },
+            ),
+        ]);
+
+        let next = resolve_due_seeds(
+            &resolver,
+            &mut seeds,
+            &peer_db,
+            &HashSet::from([initial_peer_addr]),
+            &config,
+            now,
+        );
+
+        // the addresses of the initial peers are not added twice
+        let tested_addresses: HashSet<SocketAddr> = peer_db
+            .read()
+            .get_tested_addresses()
+            .keys()
+            .copied()
+            .collect();
+        assert_eq!(
+            tested_addresses,
+            seed_addrs[1..].iter().copied().collect::<HashSet<_>>()
+        );
+        // the testers pick them right away
+        let candidate = peer_db
+            .read()
+            .get_oldest_peer(
+                config.test_oldest_peer_cooldown.to_duration(),
+                &HashSet::new(),
+            )
+            .expect("no candidate to test");
+        assert!(seed_addrs[1..].contains(&candidate));
+
+        // the failing seed is retried soon, the resolved one at the next refresh
+        assert_eq!(next, Some(now + FIRST_RETRY_DELAY));
+        assert_eq!(
+            seeds["seed.massa.test:31244"].next_resolution,
+            now + config.dns_seeds_refresh_interval.to_duration()
+        );
+    }
+
+    #[test]
+    fn test_dns_seed_retry_backoff() {
+        let config = ProtocolConfig {
+            dns_seeds_refresh_interval: MassaTime::from_millis(10_000),
+            ..Default::default()
+        };
+        let resolver = MockResolver(HashMap::new());
+        let peer_db: SharedPeerDB = Arc::new(RwLock::new(PeerDB::default()));
+        let mut now = Instant::now();
+        let mut seeds = HashMap::from([(
+            "unknown.massa.test:31244".to_string(),
+            SeedState {
+                next_resolution: now,
+                failures: 0,
+            },
+        )]);
+
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            let next = resolve_due_seeds(
+                &resolver,
+                &mut seeds,
+                &peer_db,
+                &HashSet::new(),
+                &config,
+                now,
+            )
+            .unwrap();
+            delays.push(next - now);
+            now = next;
+        }
+        // doubles at each failure, up to the refresh interval
+        assert_eq!(
+            delays,
+            [1, 2, 4, 8, 10, 10].map(Duration::from_secs).to_vec()
+        );
+        assert!(peer_db.read().get_tested_addresses().is_empty());
+    }
+}
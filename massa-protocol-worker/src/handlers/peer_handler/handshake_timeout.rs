@@ -0,0 +1,125 @@
+//! Time limit of the handshakes.
+//!
+//! A peer opening a connection and never finishing the handshake would keep the connection and
+//! its handshake thread busy, the read timeouts being reset each time it sends a few bytes.
+//! Each handshake is watched by a thread shutting its connection down once `handshake_timeout`
+//! elapsed, which makes the pending reads fail and the handshake end in error.
+
+use std::{thread::JoinHandle, time::Duration};
+
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+use peernet::transports::endpoint::Endpoint;
+
+/// Connection that can be shut down while a handshake is reading from it
+pub trait HandshakeConnection: Send + 'static {
+    fn shutdown(&mut self);
+}
+
+impl HandshakeConnection for Endpoint {
+    fn shutdown(&mut self) {
+        Endpoint::shutdown(self);
+    }
+}
+
+/// Watches a handshake, its connection is shut down if it isn't finished in time
+pub struct HandshakeTimeout {
+    finished_sender: Option<Sender<()>>,
+    handle: Option<JoinHandle<bool>>,
+}
+
+impl HandshakeTimeout {
+    pub fn start<C: HandshakeConnection>(mut connection: C, timeout: Duration) -> Self {
+        let (finished_sender, finished_receiver) = bounded::<()>(1);
+        let handle = std::thread::Builder::new()
+            .name("protocol-handshake-timeout".to_string())
+            .spawn(move || match finished_receiver.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => {
+                    connection.shutdown();
+                    true
+                }
+                // the handshake is over, dropping the sender
+                _ => false,
+            })
+            .expect("OS failed to start handshake timeout thread");
+        HandshakeTimeout {
+            finished_sender: Some(finished_sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop watching the handshake, returns true if its connection was shut down for taking too long
+    pub fn finish(mut self) -> bool {
+        self.finished_sender.take();
+        self.handle
+            .take()
+            .map_or(false, |handle| handle.join().unwrap_or(false))
+    }
+}
+
+impl Drop for HandshakeTimeout {
+    fn drop(&mut self) {
+        // the handshake failed early, the thread ends without closing the connection
+        self.finished_sender.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::{HandshakeConnection, HandshakeTimeout};
+
+    #[derive(Clone, Default)]
+    struct SilentConnection {
+        closed: Arc<AtomicBool>,
+    }
+
+    impl HandshakeConnection for SilentConnection {
+        fn shutdown(&mut self) {
+            self.closed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_stalled_handshake_is_closed() {
+        let connection = SilentConnection::default();
+        let start = Instant::now();
+        let handshake_timeout =
+            HandshakeTimeout::start(connection.clone(), Duration::from_millis(100));
+
+        // the peer never sends its handshake message
+        while !connection.closed.load(Ordering::SeqCst) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "the stalled connection wasn't closed"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(handshake_timeout.finish());
+    }
+
+    #[test]
+    fn test_finished_handshake_is_kept() {
+        let connection = SilentConnection::default();
+        let handshake_timeout =
+            HandshakeTimeout::start(connection.clone(), Duration::from_secs(60));
+        assert!(!handshake_timeout.finish());
+        assert!(!connection.closed.load(Ordering::SeqCst));
+
+        // nor closed when the handshake fails early
+        let connection = SilentConnection::default();
+        drop(HandshakeTimeout::start(
+            connection.clone(),
+            Duration::from_millis(50),
+        ));
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!connection.closed.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-peer bounded queues of the incoming messages.
+//!
+//! The messages of all the peers share the channels of their handlers, so a fast peer can
+//! fill them faster than they are processed. A peer may only have `per_peer_inbound_queue_depth`
+//! messages waiting in each channel: beyond it, the connection thread of the peer waits for its
+//! messages to be processed before reading the next ones from the socket. The peer is kept slow
+//! instead of having its messages dropped, and the other peers aren't slowed down.
+//!
+//! The channels are FIFO, so the messages of a peer still waiting are the ones sent after the
+//! last message taken from the channel: the messages sent in each channel are numbered, and the
+//! number of messages taken is deduced from the length of the channel.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use massa_channel::sender::MassaSender;
+use massa_protocol_exports::{MessageCategory, MessageCounters, PeerId};
+use parking_lot::Mutex;
+use peernet::error::{PeerNetError, PeerNetResult};
+use tracing::debug;
+
+/// Interval between two checks of the queue of a paused peer
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The queues of the peers whose messages are all processed are forgotten every this many messages
+const FORGET_PROCESSED_PEERS_PERIOD: u64 = 1024;
+
+/// Messages of the peers sent in the channel of a handler
+#[derive(Default)]
+struct ChannelQueues {
+    /// number of messages sent in the channel
+    sent: u64,
+    /// numbers of the messages of each peer that may still be in the channel, oldest first
+    peers: HashMap<PeerId, VecDeque<u64>>,
+}
+
+/// Bounded queues shared by all the connections
+#[derive(Clone)]
+pub struct InboundQueues {
+    /// max number of messages of a peer waiting in the channel of a handler
+    depth: usize,
+    /// max time a connection thread waits for room in the queue of its peer
+    max_wait: Duration,
+    /// queues of the channel of each category
+    channels: Arc<[Mutex<ChannelQueues>; MessageCategory::ALL.len()]>,
+    /// counts the reads paused for full queues, no counting if `None`
+    message_counters: Option<Arc<MessageCounters>>,
+}
+
+impl InboundQueues {
+    pub fn new(
+        depth: usize,
+        max_wait: Duration,
+        message_counters: Option<Arc<MessageCounters>>,
+    ) -> Self {
+        InboundQueues {
+            depth,
+            max_wait,
+            channels: Arc::new(Default::default()),
+            message_counters,
+        }
+    }
+
+    /// Put a message of `peer_id` in the channel of `category` with `send` once the peer has room
+    /// in its queue of that channel, the connection thread of the peer waiting meanwhile.
+    /// `send` returns whether the message was put in `sender`, it may drop it.
+    /// Fails if the queue of the peer stays full for `max_wait`, which closes the connection.
+    pub fn send<T>(
+        &self,
+        peer_id: &PeerId,
+        category: MessageCategory,
+        sender: &MassaSender<T>,
+        send: impl FnOnce() -> PeerNetResult<bool>,
+    ) -> PeerNetResult<()> {
+        let mut paused_since: Option<Instant> = None;
+        loop {
+            {
+                let mut channel = self.channels[category as usize].lock();
+                // the messages numbered below `processed` were taken from the channel
+                let processed = channel.sent.saturating_sub(sender.len() as u64);
+                let queue = channel.peers.entry(*peer_id).or_default();
+                while queue.front().map_or(false, |number| *number < processed) {
+                    queue.pop_front();
+                }
+                if queue.len() < self.depth {
+                    // sent under the lock, so that the messages are numbered in the channel order
+                    if send()? {
+                        let number = channel.sent;
+                        channel.sent += 1;
+                        channel.peers.entry(*peer_id).or_default().push_back(number);
+                        if channel.sent % FORGET_PROCESSED_PEERS_PERIOD == 0 {
+                            channel.peers.retain(|_, queue| {
+                                queue.back().map_or(false, |number| *number >= processed)
+                            });
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+            let paused_at = *paused_since.get_or_insert_with(|| {
+                debug!(
+                    "Pausing the reads of peer {}: {} {:?} messages waiting",
+                    peer_id, self.depth, category
+                );
+                if let Some(message_counters) = &self.message_counters {
+                    message_counters.record_paused(category);
+                }
+                Instant::now()
+            });
+            if paused_at.elapsed() >= self.max_wait {
+                return Err(PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(format!(
+                        "{:?} messages of the peer not processed in time",
+                        category
+                    )),
+                ));
+            }
+            std::thread::sleep(PAUSE_CHECK_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Keepalive of the idle connections.
+//!
+//! A peer that stops answering without closing its socket would stay connected until the OS
+//! notices it. The connections that received nothing for `idle_ping_interval` get a `Ping`, and
+//! are closed if neither the `Pong` nor any other message comes back within `ping_timeout`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use massa_protocol_exports::{PeerId, ProtocolConfig};
+
+/// Pings to send and connections to close after a keepalive check
+#[derive(Debug, Default)]
+pub struct KeepAliveActions {
+    /// idle peers with the nonce of the ping to send them
+    pub to_ping: Vec<(PeerId, u64)>,
+    /// peers that didn't answer their ping in time
+    pub to_close: Vec<PeerId>,
+}
+
+pub struct KeepAlive {
+    idle_ping_interval: Duration,
+    ping_timeout: Duration,
+    /// time at which each connected peer was first checked, for the peers that never sent anything
+    first_seen: HashMap<PeerId, Instant>,
+    /// nonce and sending time of the pings waiting for their pong
+    pending_pings: HashMap<PeerId, (u64, Instant)>,
+}
+
+impl KeepAlive {
+    /// Returns `None` if the keepalive is disabled by a zero `idle_ping_interval`
+    pub fn new(config: &ProtocolConfig) -> Option<Self> {
+        if config.idle_ping_interval.as_millis() == 0 {
+            return None;
+        }
+        Some(KeepAlive {
+            idle_ping_interval: config.idle_ping_interval.to_duration(),
+            ping_timeout: config.ping_timeout.to_duration(),
+            first_seen: HashMap::new(),
+            pending_pings: HashMap::new(),
+        })
+    }
+
+    /// Interval between two checks, short enough to notice late pongs without much delay
+    pub fn check_interval(&self) -> Duration {
+        (self.idle_ping_interval.min(self.ping_timeout) / 2).max(Duration::from_millis(100))
+    }
+
+    /// Find the idle connections to ping and the ones to close at time `now`,
+    /// given the time of the last message received from each peer
+    pub fn check(
+        &mut self,
+        connected: &HashSet<PeerId>,
+        last_received: &HashMap<PeerId, Instant>,
+        now: Instant,
+    ) -> KeepAliveActions {
+        self.first_seen
+            .retain(|peer_id, _| connected.contains(peer_id));
+        self.pending_pings
+            .retain(|peer_id, _| connected.contains(peer_id));
+        let mut actions = KeepAliveActions::default();
+        for peer_id in connected {
+            let first_seen = *self.first_seen.entry(*peer_id).or_insert(now);
+            let last_activity = last_received
+                .get(peer_id)
+                .map_or(first_seen, |received| (*received).max(first_seen));
+            if let Some((_, sent)) = self.pending_pings.get(peer_id) {
+                // any message proves that the peer is alive
+                if last_activity > *sent {
+                    self.pending_pings.remove(peer_id);
+                } else if now.saturating_duration_since(*sent) >= self.ping_timeout {
+                    self.pending_pings.remove(peer_id);
+                    self.first_seen.remove(peer_id);
+                    actions.to_close.push(*peer_id);
+                    continue;
+                } else {
+                    continue;
+                }
+            }
+            if now.saturating_duration_since(last_activity) >= self.idle_ping_interval {
+                let nonce = rand::random();
+                self.pending_pings.insert(*peer_id, (nonce, now));
+                actions.to_ping.push((*peer_id, nonce));
+            }
+        }
+        actions
+    }
+
+    /// Record the answer of a peer to our ping received at `now`, mismatching nonces are ignored
+    pub fn pong_received(&mut self, peer_id: &PeerId, nonce: u64, now: Instant) {
+        if let Some((expected, _)) = self.pending_pings.get(peer_id) {
+            if *expected == nonce {
+                self.pending_pings.remove(peer_id);
+                // the pong may not be counted in the traffic, restart the idle period from now
+                self.first_seen.insert(*peer_id, now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        time::{Duration, Instant},
+    };
+
+    use massa_protocol_exports::{PeerId, ProtocolConfig};
+    use massa_signature::KeyPair;
+    use massa_time::MassaTime;
+
+    use super::KeepAlive;
+
+    fn keepalive() -> KeepAlive {
+        KeepAlive::new(&ProtocolConfig {
+            idle_ping_interval: MassaTime::from_millis(10_000),
+            ping_timeout: MassaTime::from_millis(5_000),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_silent_peer_is_reaped() {
+        let mut keepalive = keepalive();
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let connected = HashSet::from([peer_id]);
+        let now = Instant::now();
+
+        let actions = keepalive.check(&connected, &HashMap::new(), now);
+        assert!(actions.to_ping.is_empty() && actions.to_close.is_empty());
+
+        // idle for too long, it gets pinged once
+        let actions = keepalive.check(&connected, &HashMap::new(), now + Duration::from_secs(10));
+        assert_eq!(actions.to_ping.len(), 1);
+        assert_eq!(actions.to_ping[0].0, peer_id);
+        assert!(actions.to_close.is_empty());
+        let actions = keepalive.check(&connected, &HashMap::new(), now + Duration::from_secs(12));
+        assert!(actions.to_ping.is_empty() && actions.to_close.is_empty());
+
+        // the peer ignores the ping
+        let actions = keepalive.check(&connected, &HashMap::new(), now + Duration::from_secs(15));
+        assert!(actions.to_ping.is_empty());
+        assert_eq!(actions.to_close, vec![peer_id]);
+    }
+
+    #[test]
+    fn test_answering_peer_is_kept() {
+        let mut keepalive = keepalive();
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let busy_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let connected = HashSet::from([peer_id, busy_peer_id]);
+        let now = Instant::now();
+        keepalive.check(&connected, &HashMap::new(), now);
+
+        // the busy peer sent a message recently and isn't pinged
+        let last_received = HashMap::from([(busy_peer_id, now + Duration::from_secs(8))]);
+        let actions = keepalive.check(&connected, &last_received, now + Duration::from_secs(10));
+        assert_eq!(actions.to_ping.len(), 1);
+        let (pinged_peer_id, nonce) = actions.to_ping[0];
+        assert_eq!(pinged_peer_id, peer_id);
+
+        // a pong with another nonce isn't an answer
+        keepalive.pong_received(
+            &peer_id,
+            nonce.wrapping_add(1),
+            now + Duration::from_secs(11),
+        );
+        assert!(keepalive.pending_pings.contains_key(&peer_id));
+        keepalive.pong_received(&peer_id, nonce, now + Duration::from_secs(11));
+        let actions = keepalive.check(&connected, &last_received, now + Duration::from_secs(15));
+        assert!(actions.to_close.is_empty());
+        assert!(!actions.to_ping.iter().any(|(id, _)| *id == peer_id));
+
+        // disconnected peers are forgotten
+        keepalive.check(&HashSet::new(), &HashMap::new(), now);
+        assert!(keepalive.first_seen.is_empty() && keepalive.pending_pings.is_empty());
+    }
+}
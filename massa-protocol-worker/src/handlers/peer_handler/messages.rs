@@ -1,7 +1,9 @@
 use std::{collections::HashMap, net::SocketAddr, ops::Bound::Included};
 
-use massa_models::serialization::{IpAddrDeserializer, IpAddrSerializer};
-use massa_protocol_exports::{PeerId, PeerIdDeserializer, PeerIdSerializer};
+use massa_models::serialization::{
+    IpAddrDeserializer, IpAddrSerializer, StringDeserializer, StringSerializer,
+};
+use massa_protocol_exports::{MessageKind, PeerId, PeerIdDeserializer, PeerIdSerializer};
 use massa_serialization::{
     Deserializer, SerializeError, Serializer, U64VarIntDeserializer, U64VarIntSerializer,
 };
@@ -22,13 +24,25 @@ pub enum PeerManagementMessage {
     NewPeerConnected((PeerId, HashMap<SocketAddr, TransportType>)),
     // Receive the ip addresses sent by a peer that is already connected.
     ListPeers(Vec<(PeerId, HashMap<SocketAddr, TransportType>)>),
+    // The peer is closing the connection, with the reason why.
+    Disconnect { reason: String },
+    // Check that an idle peer is still alive, it answers with a `Pong` carrying the same nonce.
+    Ping(u64),
+    // Answer to a `Ping`.
+    Pong(u64),
 }
 
+/// Maximum length in bytes of the reason of a `Disconnect` message
+const MAX_DISCONNECT_REASON_LENGTH: u64 = 256;
+
 #[derive(IntoPrimitive, Debug, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u64)]
 pub enum MessageTypeId {
     NewPeerConnected = 0,
     ListPeers = 1,
+    Disconnect = 2,
+    Ping = 3,
+    Pong = 4,
 }
 
 impl From<&PeerManagementMessage> for MessageTypeId {
@@ -36,16 +50,51 @@ impl From<&PeerManagementMessage> for MessageTypeId {
         match message {
             PeerManagementMessage::NewPeerConnected(_) => MessageTypeId::NewPeerConnected,
             PeerManagementMessage::ListPeers(_) => MessageTypeId::ListPeers,
+            PeerManagementMessage::Disconnect { .. } => MessageTypeId::Disconnect,
+            PeerManagementMessage::Ping(_) => MessageTypeId::Ping,
+            PeerManagementMessage::Pong(_) => MessageTypeId::Pong,
+        }
+    }
+}
+
+impl From<MessageTypeId> for MessageKind {
+    fn from(id: MessageTypeId) -> Self {
+        match id {
+            MessageTypeId::NewPeerConnected => MessageKind::NewPeerConnected,
+            MessageTypeId::ListPeers => MessageKind::ListPeers,
+            MessageTypeId::Disconnect => MessageKind::Disconnect,
+            MessageTypeId::Ping => MessageKind::Ping,
+            MessageTypeId::Pong => MessageKind::Pong,
         }
     }
 }
 
-#[derive(Default, Clone)]
+impl PeerManagementMessage {
+    /// Kind of the message, as counted in the protocol metrics
+    pub(crate) fn kind(&self) -> MessageKind {
+        MessageTypeId::from(self).into()
+    }
+
+    /// Kind of a serialized peer management message from its leading type id, `None` if invalid
+    pub(crate) fn kind_of(raw_id: u64) -> Option<MessageKind> {
+        MessageTypeId::try_from(raw_id).ok().map(MessageKind::from)
+    }
+}
+
+#[derive(Clone)]
 pub struct PeerManagementMessageSerializer {
     id_serializer: U64VarIntSerializer,
     length_serializer: U64VarIntSerializer,
     ip_addr_serializer: IpAddrSerializer,
     peer_id_serializer: PeerIdSerializer,
+    reason_serializer: StringSerializer<U64VarIntSerializer, u64>,
+    nonce_serializer: U64VarIntSerializer,
+}
+
+impl Default for PeerManagementMessageSerializer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PeerManagementMessageSerializer {
@@ -55,6 +104,8 @@ impl PeerManagementMessageSerializer {
             length_serializer: U64VarIntSerializer::new(),
             ip_addr_serializer: IpAddrSerializer::new(),
             peer_id_serializer: PeerIdSerializer::new(),
+            reason_serializer: StringSerializer::new(U64VarIntSerializer::new()),
+            nonce_serializer: U64VarIntSerializer::new(),
         }
     }
 }
@@ -98,6 +149,12 @@ impl Serializer<PeerManagementMessage> for PeerManagementMessageSerializer {
                     }
                 }
             }
+            PeerManagementMessage::Disconnect { reason } => {
+                self.reason_serializer.serialize(reason, buffer)?;
+            }
+            PeerManagementMessage::Ping(nonce) | PeerManagementMessage::Pong(nonce) => {
+                self.nonce_serializer.serialize(nonce, buffer)?;
+            }
         }
         Ok(())
     }
@@ -109,6 +166,8 @@ pub struct PeerManagementMessageDeserializer {
     peers_length_deserializer: U64VarIntDeserializer,
     ip_addr_deserializer: IpAddrDeserializer,
     peer_id_deserializer: PeerIdDeserializer,
+    reason_deserializer: StringDeserializer<U64VarIntDeserializer, u64>,
+    nonce_deserializer: U64VarIntDeserializer,
 }
 
 /// Limits used in the deserialization of `OperationMessage`
@@ -133,6 +192,11 @@ impl PeerManagementMessageDeserializer {
             ),
             ip_addr_deserializer: IpAddrDeserializer::new(),
             peer_id_deserializer: PeerIdDeserializer::new(),
+            reason_deserializer: StringDeserializer::new(U64VarIntDeserializer::new(
+                Included(0),
+                Included(MAX_DISCONNECT_REASON_LENGTH),
+            )),
+            nonce_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
         }
     }
 }
@@ -208,6 +272,27 @@ impl Deserializer<PeerManagementMessage> for PeerManagementMessageDeserializer {
                     PeerManagementMessage::ListPeers(data)
                 })
                 .parse(buffer),
+                MessageTypeId::Disconnect => {
+                    context("Failed Disconnect deserialization", |buffer: &'a [u8]| {
+                        self.reason_deserializer.deserialize(buffer)
+                    })
+                    .map(|reason| PeerManagementMessage::Disconnect { reason })
+                    .parse(buffer)
+                }
+                MessageTypeId::Ping => {
+                    context("Failed Ping deserialization", |buffer: &'a [u8]| {
+                        self.nonce_deserializer.deserialize(buffer)
+                    })
+                    .map(PeerManagementMessage::Ping)
+                    .parse(buffer)
+                }
+                MessageTypeId::Pong => {
+                    context("Failed Pong deserialization", |buffer: &'a [u8]| {
+                        self.nonce_deserializer.deserialize(buffer)
+                    })
+                    .map(PeerManagementMessage::Pong)
+                    .parse(buffer)
+                }
             }
         })
         .parse(buffer)
@@ -367,4 +452,92 @@ mod tests {
             _ => panic!("Bad message deserialized"),
         }
     }
+
+    #[test]
+    fn test_list_peers_ipv6() {
+        let keypair = KeyPair::generate(0).unwrap();
+        let listeners = HashMap::from([
+            ("[2001:db8::1]:33036".parse().unwrap(), TransportType::Tcp),
+            ("82.245.123.77:33036".parse().unwrap(), TransportType::Tcp),
+        ]);
+        let message = PeerManagementMessage::ListPeers(vec![(
+            PeerId::from_public_key(keypair.get_public_key()),
+            listeners.clone(),
+        )]);
+
+        let serializer = PeerManagementMessageSerializer::new();
+        let mut buffer = vec![];
+        serializer.serialize(&message, &mut buffer).unwrap();
+        let deserializer =
+            PeerManagementMessageDeserializer::new(PeerManagementMessageDeserializerArgs {
+                max_listeners_per_peer: 1000,
+                max_peers_per_announcement: 1000,
+            });
+        let (rest, message) = deserializer
+            .deserialize::<DeserializeError>(&buffer)
+            .unwrap();
+        assert!(rest.is_empty());
+        match message {
+            PeerManagementMessage::ListPeers(peers) => {
+                assert_eq!(
+                    peers,
+                    vec![(PeerId::from_public_key(keypair.get_public_key()), listeners)]
+                );
+            }
+            _ => panic!("Bad message deserialized"),
+        }
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let message = PeerManagementMessage::Disconnect {
+            reason: "node shutting down".to_string(),
+        };
+        let serializer = PeerManagementMessageSerializer::new();
+        let mut buffer = vec![];
+        serializer.serialize(&message, &mut buffer).unwrap();
+        let deserializer =
+            PeerManagementMessageDeserializer::new(PeerManagementMessageDeserializerArgs {
+                max_listeners_per_peer: 1000,
+                max_peers_per_announcement: 1000,
+            });
+        let (rest, message) = deserializer
+            .deserialize::<DeserializeError>(&buffer)
+            .unwrap();
+        assert!(rest.is_empty());
+        match message {
+            PeerManagementMessage::Disconnect { reason } => {
+                assert_eq!(reason, "node shutting down");
+            }
+            _ => panic!("Bad message deserialized"),
+        }
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let serializer = PeerManagementMessageSerializer::new();
+        let deserializer =
+            PeerManagementMessageDeserializer::new(PeerManagementMessageDeserializerArgs {
+                max_listeners_per_peer: 1000,
+                max_peers_per_announcement: 1000,
+            });
+        for message in [
+            PeerManagementMessage::Ping(u64::MAX),
+            PeerManagementMessage::Pong(42),
+        ] {
+            let mut buffer = vec![];
+            serializer.serialize(&message, &mut buffer).unwrap();
+            let (rest, deserialized) = deserializer
+                .deserialize::<DeserializeError>(&buffer)
+                .unwrap();
+            assert!(rest.is_empty());
+            match (message, deserialized) {
+                (PeerManagementMessage::Ping(sent), PeerManagementMessage::Ping(received))
+                | (PeerManagementMessage::Pong(sent), PeerManagementMessage::Pong(received)) => {
+                    assert_eq!(sent, received);
+                }
+                _ => panic!("Bad message deserialized"),
+            }
+        }
+    }
 }
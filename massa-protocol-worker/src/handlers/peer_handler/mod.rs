@@ -1,18 +1,30 @@
 use std::net::IpAddr;
-use std::{collections::HashMap, net::SocketAddr, thread::JoinHandle, time::Duration};
+use std::ops::Bound::Included;
+use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use crossbeam::channel::tick;
+use crossbeam::channel::{at, never, tick};
 use crossbeam::select;
-use massa_channel::{receiver::MassaReceiver, sender::MassaSender};
+use massa_channel::{receiver::MassaReceiver, sender::MassaSender, MassaChannel};
 use massa_hash::Hash;
 use massa_metrics::MassaMetrics;
 use massa_models::config::SIGNATURE_DESER_SIZE;
 use massa_models::version::{VersionDeserializer, VersionSerializer};
 use massa_protocol_exports::{
-    BootstrapPeers, PeerId, PeerIdDeserializer, PeerIdSerializer, ProtocolConfig,
+    BanContext, BanDecision, BanReason, BootstrapPeers, CompressionMode, FirstOffensePolicy,
+    PeerEvent, PeerEventBroadcast, PeerId, PeerIdDeserializer, PeerIdSerializer, ProtocolConfig,
+};
+use massa_serialization::{
+    DeserializeError, Deserializer, Serializer, U32VarIntDeserializer, U32VarIntSerializer,
 };
-use massa_serialization::{DeserializeError, Deserializer, Serializer};
 use massa_signature::Signature;
+use massa_time::MassaTime;
+use parking_lot::RwLock;
 use peernet::context::Context as _;
 use peernet::messages::MessagesSerializer as _;
 use rand::{rngs::StdRng, RngCore, SeedableRng};
@@ -20,20 +32,28 @@ use rand::{rngs::StdRng, RngCore, SeedableRng};
 use peernet::{
     error::{PeerNetError, PeerNetResult},
     messages::MessagesHandler as PeerNetMessagesHandler,
-    peer::InitConnectionHandler,
+    peer::{InitConnectionHandler, PeerConnectionType},
     transports::{endpoint::Endpoint, TransportType},
 };
 use tracing::log::{debug, error, info, warn};
 
+use crate::clock::ProtocolClock;
 use crate::context::Context;
 use crate::handlers::peer_handler::models::PeerState;
-use crate::messages::{Message, MessagesHandler, MessagesSerializer};
+use crate::ip::to_canonical;
+use crate::messages::{Message, MessageFormatVersion, MessagesHandler, MessagesSerializer};
 use crate::wrap_network::ActiveConnectionsTrait;
 
-use self::models::PeerInfo;
+use self::models::{
+    BanSeverity, NegotiatedFeatures, PeerInfo, WeightingPolicy, REPUTATION_MINOR_OFFENSE,
+};
 use self::{
+    dns_seeds::{DnsSeeder, SystemDnsResolver},
+    handshake_timeout::HandshakeTimeout,
+    keepalive::KeepAlive,
     models::{
-        InitialPeers, PeerManagementChannel, PeerManagementCmd, PeerMessageTuple, SharedPeerDB,
+        InitialPeers, PeerManagementChannel, PeerManagementCmd, PeerMessageTuple,
+        SharedChecksumPeers, SharedCompressionPeers, SharedFormatVersions, SharedPeerDB,
     },
     tester::Tester,
 };
@@ -44,23 +64,41 @@ use self::{
         AnnouncementSerializer,
     },
     messages::{PeerManagementMessageDeserializer, PeerManagementMessageDeserializerArgs},
+    network_identity::{NetworkIdentity, NetworkIdentityDeserializer, NetworkIdentitySerializer},
 };
 
 /// This file contains the definition of the peer management handler
 /// This handler is here to check that announcements we receive are valid and
 /// that all the endpoints we received are active.
-mod announcement;
+pub mod announcement;
+mod ban_list_writer;
+pub mod corruption_limiter;
+mod dns_seeds;
+mod handshake_timeout;
+pub mod inbound_queues;
+mod keepalive;
 mod messages;
 pub mod models;
+mod network_identity;
+pub mod rate_limiter;
+pub mod size_limiter;
 mod tester;
 
 pub(crate) use messages::{PeerManagementMessage, PeerManagementMessageSerializer};
 
+/// Time left to the connections to send out their pending messages before closing them on shutdown
+const DRAIN_FLUSH_DELAY: Duration = Duration::from_millis(200);
+
+/// The expired peers are looked for this many times per `peer_expiry`,
+/// a peer is forgotten at most a tenth of `peer_expiry` after it expired
+const PEER_PRUNE_CHECKS_PER_EXPIRY: u32 = 10;
+
 pub struct PeerManagementHandler {
     pub peer_db: SharedPeerDB,
     pub thread_join: Option<JoinHandle<()>>,
     pub sender: PeerManagementChannel,
     testers: Vec<Tester>,
+    dns_seeder: Option<DnsSeeder>,
 }
 
 impl PeerManagementHandler {
@@ -82,9 +120,13 @@ impl PeerManagementHandler {
         target_out_connections: HashMap<String, (Vec<IpAddr>, usize)>,
         default_target_out_connections: usize,
         config: &ProtocolConfig,
+        peer_events: PeerEventBroadcast,
+        clock: ProtocolClock,
         massa_metrics: MassaMetrics,
     ) -> Self {
         let message_serializer = PeerManagementMessageSerializer::new();
+        let peer_traffic = messages_handler.peer_traffic.clone();
+        let rate_limiter = messages_handler.rate_limiter.clone();
 
         let ((test_sender, test_receiver), testers) = Tester::run(
             config,
@@ -93,6 +135,7 @@ impl PeerManagementHandler {
             messages_handler,
             target_out_connections,
             default_target_out_connections,
+            clock.clone(),
             massa_metrics,
         );
 
@@ -100,8 +143,18 @@ impl PeerManagementHandler {
         .name("protocol-peer-handler".to_string())
         .spawn({
             let peer_db = peer_db.clone();
-            let ticker = tick(Duration::from_secs(10));
-            let config = config.clone();
+            let ticker = clock.tick(Duration::from_secs(10));
+            let mut unban_ticker = clock.tick(unban_check_interval(config));
+            let prune_ticker = if config.peer_expiry.as_millis() > 0 {
+                clock.tick(config.peer_expiry.to_duration() / PEER_PRUNE_CHECKS_PER_EXPIRY)
+            } else {
+                never()
+            };
+            let mut keepalive = KeepAlive::new(config);
+            let keepalive_ticker = keepalive
+                .as_ref()
+                .map_or_else(never, |keepalive| tick(keepalive.check_interval()));
+            let mut config = config.clone();
             let message_serializer = MessagesSerializer::new()
                 .with_peer_management_message_serializer(PeerManagementMessageSerializer::new());
             let message_deserializer =
@@ -111,10 +164,71 @@ impl PeerManagementHandler {
                 });
 
             move || {
+                // graceful disconnections waiting for the goodbye message to be sent, oldest first
+                let mut pending_disconnects: VecDeque<(Instant, PeerId)> = VecDeque::new();
                 loop {
+                    let disconnect_timer = pending_disconnects
+                        .front()
+                        .map_or_else(never, |(close_at, _)| at(*close_at));
                     select! {
+                        recv(disconnect_timer) -> _ => {
+                            let now = Instant::now();
+                            while let Some((_, peer_id)) = pending_disconnects
+                                .front()
+                                .filter(|(close_at, _)| *close_at <= now)
+                                .copied()
+                            {
+                                pending_disconnects.pop_front();
+                                info!("Disconnecting peer {}", peer_id);
+                                active_connections.shutdown_connection(&peer_id);
+                            }
+                        }
+                        recv(unban_ticker) -> _ => {
+                            let unbanned_peers = peer_db.write().tick_unban(clock.now());
+                            if !unbanned_peers.is_empty() {
+                                debug!("Ban of peers {:?} expired", unbanned_peers);
+                                notify_unbans(&peer_events, &unbanned_peers);
+                            }
+                        }
+                        recv(prune_ticker) -> _ => {
+                            let connected = active_connections.get_peer_ids_connected();
+                            let pruned_peers = peer_db.write().prune_expired_peers(clock.now(), &connected);
+                            if !pruned_peers.is_empty() {
+                                debug!("Forgot {} peers we didn't hear of for too long", pruned_peers.len());
+                            }
+                        }
+                        recv(keepalive_ticker) -> _ => {
+                            let Some(keepalive) = keepalive.as_mut() else {
+                                continue;
+                            };
+                            let last_received = peer_traffic
+                                .as_ref()
+                                .map(|peer_traffic| peer_traffic.read().last_received.clone())
+                                .unwrap_or_default();
+                            let actions = keepalive.check(
+                                &active_connections.get_peer_ids_connected(),
+                                &last_received,
+                                Instant::now(),
+                            );
+                            for (peer_id, nonce) in actions.to_ping {
+                                if let Err(e) = active_connections
+                                    .send_to_peer(&peer_id, &message_serializer, PeerManagementMessage::Ping(nonce).into(), false) {
+                                    debug!("error sending Ping message to peer {}: {:?}", peer_id, e);
+                                }
+                            }
+                            for peer_id in actions.to_close {
+                                info!("Peer {} didn't answer our ping, closing the connection", peer_id);
+                                active_connections.shutdown_connection(&peer_id);
+                            }
+                        }
                         recv(ticker) -> _ => {
-                            let peers_to_send = peer_db.read().get_rand_peers_to_send(100);
+                            let unbanned_peers = peer_db.write().unban_expired_peers(clock.now());
+                            if !unbanned_peers.is_empty() {
+                                debug!("Ban of peers {:?} expired", unbanned_peers);
+                                notify_unbans(&peer_events, &unbanned_peers);
+                            }
+
+                            let peers_to_send = peer_db.read().get_weighted_peers_to_send(100, WeightingPolicy::default());
                             if peers_to_send.is_empty() {
                                 continue;
                             }
@@ -132,20 +246,90 @@ impl PeerManagementHandler {
                             receiver_cmd.update_metrics();
                             // internal command
                            match cmd {
-                             Ok(PeerManagementCmd::Ban(peer_ids)) => {
+                             Ok(PeerManagementCmd::Ban(mut peer_ids, reason)) => {
+                                remove_whitelisted_peers(&config, &mut peer_ids, reason);
+                                apply_ban_policy(&config, active_connections.as_mut(), &mut peer_ids, reason, None);
+                                disconnect_first_offenders(&config, &peer_db, active_connections.as_mut(), &mut peer_ids, reason, clock.now());
+                                notify_bans(&peer_events, active_connections.as_ref(), &peer_ids, reason);
+                                if config.ip_ban_enabled {
+                                    ban_peers_ips(&peer_db, active_connections.as_ref(), &peer_ids, clock.now());
+                                }
+                                let ips = connected_ips(active_connections.as_ref(), &peer_ids);
                                 // remove running handshake ?
                                 for peer_id in peer_ids {
                                     active_connections.shutdown_connection(&peer_id);
 
                                     // update peer_db
-                                    peer_db.write().ban_peer(&peer_id);
+                                    peer_db.write().ban_peer(&peer_id, reason, clock.now());
+                                    log_ban(&peer_db, &peer_id, ips.get(&peer_id), reason);
                                 }
                             },
-                             Ok(PeerManagementCmd::Unban(peer_ids)) => {
+                             Ok(PeerManagementCmd::BanWithSeverity(mut peer_ids, reason, severity)) => {
+                                remove_whitelisted_peers(&config, &mut peer_ids, reason);
+                                apply_ban_policy(&config, active_connections.as_mut(), &mut peer_ids, reason, Some(severity));
+                                if severity == BanSeverity::Minor {
+                                    // minor offenses cost reputation and quarantine the peer,
+                                    // it is banned on its next offense or once its reputation is too low
+                                    let mut write_peer_db = peer_db.write();
+                                    peer_ids.retain(|peer_id| {
+                                        write_peer_db.adjust_reputation(peer_id, REPUTATION_MINOR_OFFENSE)
+                                            || !write_peer_db.quarantine_peer(peer_id, clock.now())
+                                    });
+                                }
+                                if severity != BanSeverity::Critical {
+                                    disconnect_first_offenders(&config, &peer_db, active_connections.as_mut(), &mut peer_ids, reason, clock.now());
+                                }
+                                notify_bans(&peer_events, active_connections.as_ref(), &peer_ids, reason);
+                                if config.ip_ban_enabled {
+                                    ban_peers_ips(&peer_db, active_connections.as_ref(), &peer_ids, clock.now());
+                                }
+                                let ips = connected_ips(active_connections.as_ref(), &peer_ids);
                                 for peer_id in peer_ids {
-                                    peer_db.write().unban_peer(&peer_id);
+                                    active_connections.shutdown_connection(&peer_id);
+
+                                    // update peer_db
+                                    peer_db.write().ban_peer_with_severity(&peer_id, reason, severity, clock.now());
+                                    log_ban(&peer_db, &peer_id, ips.get(&peer_id), reason);
+                                }
+                            },
+                             Ok(PeerManagementCmd::AdjustReputation(peer_id, delta)) => {
+                                let mut peer_ids = Vec::new();
+                                if peer_db.write().adjust_reputation(&peer_id, delta) {
+                                    peer_ids.push(peer_id);
+                                    apply_ban_policy(&config, active_connections.as_mut(), &mut peer_ids, BanReason::ProtocolViolation, Some(BanSeverity::Minor));
+                                }
+                                if !peer_ids.is_empty() {
+                                    notify_bans(&peer_events, active_connections.as_ref(), &[peer_id], BanReason::ProtocolViolation);
+                                    if config.ip_ban_enabled {
+                                        ban_peers_ips(&peer_db, active_connections.as_ref(), &[peer_id], clock.now());
+                                    }
+                                    let ips = connected_ips(active_connections.as_ref(), &[peer_id]);
+                                    active_connections.shutdown_connection(&peer_id);
+                                    peer_db.write().ban_peer_with_severity(&peer_id, BanReason::ProtocolViolation, BanSeverity::Minor, clock.now());
+                                    log_ban(&peer_db, &peer_id, ips.get(&peer_id), BanReason::ProtocolViolation);
+                                }
+                            },
+                             Ok(PeerManagementCmd::Unban(peer_ids)) => {
+                                for peer_id in &peer_ids {
+                                    peer_db.write().unban_peer(peer_id);
                                 }
+                                notify_unbans(&peer_events, &peer_ids);
                             },
+                             Ok(PeerManagementCmd::Disconnect { peer_id, graceful }) => {
+                                if disconnect_peer(active_connections.as_mut(), &message_serializer, &peer_id, graceful) {
+                                    // closed once the message had time to be sent, without holding up the other commands
+                                    pending_disconnects.push_back((Instant::now() + DRAIN_FLUSH_DELAY, peer_id));
+                                }
+                             },
+                             Ok(PeerManagementCmd::UpdateConfig(update)) => {
+                                update.apply(&mut config);
+                                if let Some(rate_limiter) = &rate_limiter {
+                                    rate_limiter.update_limits(&config);
+                                }
+                                peer_db.write().update_ban_settings(&config);
+                                unban_ticker = clock.tick(unban_check_interval(&config));
+                                info!("Protocol settings updated: {:?}", update);
+                             },
                              Ok(PeerManagementCmd::GetBootstrapPeers { responder }) => {
                                 let mut peers = peer_db.read().get_rand_peers_to_send(100);
                                 // Add myself
@@ -159,7 +343,14 @@ impl PeerManagementHandler {
                                     warn!("error sending bootstrap peers: {:?}", err);
                                 }
                              },
+                             Ok(PeerManagementCmd::Drain { reason, flush_delay, responder }) => {
+                                drain_connections(active_connections.as_mut(), &message_serializer, reason, flush_delay);
+                                if let Err(err) = responder.try_send(()) {
+                                    warn!("error answering the drain request: {:?}", err);
+                                }
+                             },
                              Ok(PeerManagementCmd::Stop) => {
+                                peer_db.read().save_ban_list();
                                 while let Ok(_msg) = test_receiver.try_recv() {
                                     // nothing to do just clean the channel
                                 }
@@ -212,6 +403,21 @@ impl PeerManagementHandler {
                                         }
                                     }
                                 }
+                                PeerManagementMessage::Disconnect { reason } => {
+                                    info!("Peer {} is disconnecting: {}", peer_id, reason);
+                                    active_connections.shutdown_connection(&peer_id);
+                                }
+                                PeerManagementMessage::Ping(nonce) => {
+                                    if let Err(e) = active_connections
+                                        .send_to_peer(&peer_id, &message_serializer, PeerManagementMessage::Pong(nonce).into(), false) {
+                                        debug!("error sending Pong message to peer {}: {:?}", peer_id, e);
+                                    }
+                                }
+                                PeerManagementMessage::Pong(nonce) => {
+                                    if let Some(keepalive) = keepalive.as_mut() {
+                                        keepalive.pong_received(&peer_id, nonce, Instant::now());
+                                    }
+                                }
                             }
                         }
                     }
@@ -219,6 +425,13 @@ impl PeerManagementHandler {
             }
         }).expect("OS failed to start peer management thread");
 
+        let dns_seeder = DnsSeeder::start(
+            config,
+            Box::new(SystemDnsResolver),
+            peer_db.clone(),
+            &initial_peers,
+        );
+
         for (peer_id, listeners) in &initial_peers {
             let mut message = Vec::new();
             message_serializer
@@ -238,6 +451,31 @@ impl PeerManagementHandler {
                 command_sender: sender_cmd,
             },
             testers,
+            dns_seeder,
+        }
+    }
+
+    /// Tell the connected peers we leave and close the connections, waiting at most `timeout`
+    pub fn drain(&mut self, reason: &str, timeout: Duration) {
+        let (responder, response) =
+            MassaChannel::new("peer_handler_drain_responder".to_string(), Some(1));
+        if let Err(err) = self
+            .sender
+            .command_sender
+            .try_send(PeerManagementCmd::Drain {
+                reason: reason.to_string(),
+                flush_delay: timeout.min(DRAIN_FLUSH_DELAY),
+                responder,
+            })
+        {
+            warn!(
+                "error sending the drain request to the peer handler: {:?}",
+                err
+            );
+            return;
+        }
+        if response.recv_timeout(timeout).is_err() {
+            warn!("the connections weren't drained in time, closing them abruptly");
         }
     }
 
@@ -247,6 +485,10 @@ impl PeerManagementHandler {
             .send(PeerManagementCmd::Stop)
             .unwrap();
 
+        if let Some(dns_seeder) = self.dns_seeder.as_mut() {
+            dns_seeder.stop();
+        }
+
         // waiting for all threads to finish
         self.testers.iter_mut().for_each(|tester| {
             if let Some(join_handle) = tester.handler.take() {
@@ -256,14 +498,302 @@ impl PeerManagementHandler {
     }
 }
 
+/// Tell the connected peers why we leave, give their connections `flush_delay` to send out
+/// the pending messages, then close them
+fn drain_connections(
+    active_connections: &mut dyn ActiveConnectionsTrait,
+    message_serializer: &MessagesSerializer,
+    reason: String,
+    flush_delay: Duration,
+) {
+    let peer_ids = active_connections.get_peer_ids_connected();
+    let message = PeerManagementMessage::Disconnect { reason };
+    for peer_id in &peer_ids {
+        if let Err(err) = active_connections.send_to_peer(
+            peer_id,
+            message_serializer,
+            message.clone().into(),
+            true,
+        ) {
+            debug!(
+                "error sending Disconnect message to peer {}: {:?}",
+                peer_id, err
+            );
+        }
+    }
+    if !peer_ids.is_empty() {
+        std::thread::sleep(flush_delay);
+    }
+    for peer_id in &peer_ids {
+        active_connections.shutdown_connection(peer_id);
+    }
+}
+
+/// Close the connection to `peer_id` on request of the node operator, without touching its state.
+/// If `graceful`, the peer is told we disconnect and `true` is returned: the connection is left
+/// open for the caller to close it once the message had time to be sent.
+fn disconnect_peer(
+    active_connections: &mut dyn ActiveConnectionsTrait,
+    message_serializer: &MessagesSerializer,
+    peer_id: &PeerId,
+    graceful: bool,
+) -> bool {
+    if !active_connections
+        .get_peer_ids_connected()
+        .contains(peer_id)
+    {
+        debug!("Tried to disconnect peer {} which isn't connected", peer_id);
+        return false;
+    }
+    if graceful {
+        let message = PeerManagementMessage::Disconnect {
+            reason: "disconnected by the node operator".to_string(),
+        };
+        match active_connections.send_to_peer(peer_id, message_serializer, message.into(), true) {
+            Ok(()) => return true,
+            Err(err) => debug!(
+                "error sending Disconnect message to peer {}: {:?}",
+                peer_id, err
+            ),
+        }
+    }
+    info!("Disconnecting peer {}", peer_id);
+    active_connections.shutdown_connection(peer_id);
+    false
+}
+
+/// Check the bans at the pace of the shortest ban duration
+fn unban_check_interval(config: &ProtocolConfig) -> Duration {
+    config
+        .ban_durations
+        .values()
+        .copied()
+        .filter(|duration| duration.as_millis() > 0)
+        .chain(std::iter::once(config.unban_everyone_timer))
+        .min()
+        .unwrap_or(config.unban_everyone_timer)
+        .to_duration()
+}
+
+/// Keep the whitelisted peers connected and trusted whatever they did
+fn remove_whitelisted_peers(
+    config: &ProtocolConfig,
+    peer_ids: &mut Vec<PeerId>,
+    reason: BanReason,
+) {
+    peer_ids.retain(|peer_id| {
+        let whitelisted = config.ban_whitelist.contains(peer_id);
+        if whitelisted {
+            warn!("Not banning whitelisted peer {} ({})", peer_id, reason);
+        }
+        !whitelisted
+    });
+}
+
+/// Submit the offenses to the ban policy: the peers it doesn't want sanctioned are removed from `peer_ids`,
+/// the ones it only wants disconnected are disconnected. The bans asked by the node operator are always applied.
+fn apply_ban_policy(
+    config: &ProtocolConfig,
+    active_connections: &mut dyn ActiveConnectionsTrait,
+    peer_ids: &mut Vec<PeerId>,
+    reason: BanReason,
+    severity: Option<BanSeverity>,
+) {
+    if reason == BanReason::Manual {
+        return;
+    }
+    let ctx = BanContext { reason, severity };
+    peer_ids.retain(|peer_id| {
+        let decision = match reason {
+            BanReason::InvalidBlockSignature
+            | BanReason::InvalidOperationSignature
+            | BanReason::InvalidEndorsementSignature => {
+                config.ban_policy.on_invalid_signature(peer_id, &ctx)
+            }
+            BanReason::RateLimitExceeded => config.ban_policy.on_rate_limit(peer_id, &ctx),
+            _ => config.ban_policy.on_offense(peer_id, &ctx),
+        };
+        match decision {
+            BanDecision::Ban => true,
+            BanDecision::Disconnect => {
+                info!(
+                    "Disconnecting peer {} instead of banning it ({}), as decided by the ban policy",
+                    peer_id, reason
+                );
+                active_connections.shutdown_connection(peer_id);
+                false
+            }
+            BanDecision::Ignore => {
+                info!("Not banning peer {} ({}), as decided by the ban policy", peer_id, reason);
+                false
+            }
+        }
+    });
+}
+
+/// Under the `DisconnectThenBan` policy, only disconnect the peers committing their first offense,
+/// the peers left in `peer_ids` are the ones that already offended within `first_offense_window`
+fn disconnect_first_offenders(
+    config: &ProtocolConfig,
+    peer_db: &SharedPeerDB,
+    active_connections: &mut dyn ActiveConnectionsTrait,
+    peer_ids: &mut Vec<PeerId>,
+    reason: BanReason,
+    now: MassaTime,
+) {
+    if config.first_offense_policy != FirstOffensePolicy::DisconnectThenBan {
+        return;
+    }
+    let mut first_offenders = Vec::new();
+    {
+        let mut peer_db_write = peer_db.write();
+        peer_ids.retain(|peer_id| {
+            let first_offense = peer_db_write.mark_first_offense(peer_id, now);
+            if first_offense {
+                first_offenders.push(*peer_id);
+            }
+            !first_offense
+        });
+    }
+    for peer_id in first_offenders {
+        info!(
+            "Disconnecting peer {} for its first offense ({}), it is banned if it offends again",
+            peer_id, reason
+        );
+        active_connections.shutdown_connection(&peer_id);
+    }
+}
+
+/// Publish the bans of the given peers to the subscribers of peer events
+fn notify_bans(
+    peer_events: &PeerEventBroadcast,
+    active_connections: &dyn ActiveConnectionsTrait,
+    peer_ids: &[PeerId],
+    reason: BanReason,
+) {
+    if !peer_events.has_subscribers() {
+        return;
+    }
+    let peers_connected = active_connections.get_peers_connected();
+    let timestamp = MassaTime::now();
+    for peer_id in peer_ids {
+        peer_events.send(PeerEvent::Banned {
+            peer_id: *peer_id,
+            ip: peers_connected
+                .get(peer_id)
+                .map(|(addr, _, _)| to_canonical(addr.ip())),
+            reason,
+            timestamp,
+        });
+    }
+}
+
+/// Publish the unbans of the given peers to the subscribers of peer events
+fn notify_unbans(peer_events: &PeerEventBroadcast, peer_ids: &[PeerId]) {
+    let timestamp = MassaTime::now();
+    for peer_id in peer_ids {
+        peer_events.send(PeerEvent::Unbanned {
+            peer_id: *peer_id,
+            ip: None,
+            timestamp,
+        });
+    }
+}
+
+/// Ban at `now` the IP addresses from which the given peers are connected
+fn ban_peers_ips(
+    peer_db: &SharedPeerDB,
+    active_connections: &dyn ActiveConnectionsTrait,
+    peer_ids: &[PeerId],
+    now: MassaTime,
+) {
+    let peers_connected = active_connections.get_peers_connected();
+    let mut peer_db_write = peer_db.write();
+    for peer_id in peer_ids {
+        if let Some((addr, _, _)) = peers_connected.get(peer_id) {
+            peer_db_write.ban_ip(addr.ip(), now);
+        }
+    }
+}
+
+/// Addresses of the given peers that are connected
+fn connected_ips(
+    active_connections: &dyn ActiveConnectionsTrait,
+    peer_ids: &[PeerId],
+) -> HashMap<PeerId, IpAddr> {
+    active_connections
+        .get_peers_connected()
+        .into_iter()
+        .filter(|(peer_id, _)| peer_ids.contains(peer_id))
+        .map(|(peer_id, (addr, _, _))| (peer_id, to_canonical(addr.ip())))
+        .collect()
+}
+
+/// Target of the ban events, so that the log pipelines can route them separately
+const BAN_LOG_TARGET: &str = "massa_protocol::bans";
+
+/// Log a ban with structured fields once the peer database applied it,
+/// `ban_until` is left out of permanent bans
+fn log_ban(peer_db: &SharedPeerDB, peer_id: &PeerId, ip: Option<&IpAddr>, reason: BanReason) {
+    // whitelisted and unknown peers aren't banned
+    let Some((offense_count, ban_end)) = peer_db.read().get_ban_details(peer_id) else {
+        return;
+    };
+    tracing::info!(
+        target: BAN_LOG_TARGET,
+        peer_id = %peer_id,
+        ip = ip.map(tracing::field::display),
+        reason = %reason,
+        offense_count,
+        ban_until = ban_end.map(|ban_end| ban_end.as_millis()),
+        "Banned peer"
+    );
+}
+
+/// Handshake capability flag: we can receive zstd-compressed messages
+const HANDSHAKE_CAPABILITY_ZSTD: u8 = 1;
+/// Handshake capability flag: we can receive checksummed frames
+const HANDSHAKE_CAPABILITY_CHECKSUM: u8 = 2;
+/// Handshake capability flag: we can receive the frames tagged with their format version
+const HANDSHAKE_CAPABILITY_FORMAT_TAG: u8 = 4;
+/// Handshake capability flag: we can receive the messages in length-prefixed envelopes
+const HANDSHAKE_CAPABILITY_ENVELOPE: u8 = 8;
+
+/// Wire format of the messages sent to a peer advertising the capability flags `peer_flags`:
+/// the newest one it decodes, so that it skips those of our messages it doesn't know.
+/// The older peers only decode the untagged frames.
+fn negotiated_format_version(peer_flags: u8) -> MessageFormatVersion {
+    if peer_flags & HANDSHAKE_CAPABILITY_ENVELOPE != 0 {
+        MessageFormatVersion::V3
+    } else if peer_flags & HANDSHAKE_CAPABILITY_FORMAT_TAG != 0 {
+        MessageFormatVersion::V2
+    } else {
+        MessageFormatVersion::V1
+    }
+}
+
 #[derive(Clone)]
 pub struct MassaHandshake {
     pub announcement_serializer: AnnouncementSerializer,
     pub announcement_deserializer: AnnouncementDeserializer,
     pub version_serializer: VersionSerializer,
     pub version_deserializer: VersionDeserializer,
+    pub protocol_version_serializer: U32VarIntSerializer,
+    pub protocol_version_deserializer: U32VarIntDeserializer,
+    network_identity_serializer: NetworkIdentitySerializer,
+    network_identity_deserializer: NetworkIdentityDeserializer,
     pub config: ProtocolConfig,
     pub peer_db: SharedPeerDB,
+    /// peers with which both sides advertised compression support
+    pub compression_peers: SharedCompressionPeers,
+    /// peers advertising checksum support, to which we send checksummed frames if `frame_checksum` is set
+    pub checksum_peers: SharedChecksumPeers,
+    /// wire format of the messages sent to the peers advertising a tagged one
+    pub format_versions: SharedFormatVersions,
+    /// connections of the network manager, set once it is started, to limit the connections per IP
+    pub active_connections: Arc<RwLock<Option<Box<dyn ActiveConnectionsTrait>>>>,
+    /// time of the connection attempts recorded in the peer database
+    pub clock: ProtocolClock,
     peer_mngt_msg_serializer: MessagesSerializer,
     peer_id_serializer: PeerIdSerializer,
     peer_id_deserializer: PeerIdDeserializer,
@@ -273,6 +803,11 @@ impl MassaHandshake {
     pub fn new(peer_db: SharedPeerDB, config: ProtocolConfig) -> Self {
         Self {
             peer_db,
+            compression_peers: Default::default(),
+            checksum_peers: Default::default(),
+            format_versions: Default::default(),
+            active_connections: Default::default(),
+            clock: Default::default(),
             announcement_serializer: AnnouncementSerializer::new(),
             announcement_deserializer: AnnouncementDeserializer::new(
                 AnnouncementDeserializerArgs {
@@ -281,6 +816,13 @@ impl MassaHandshake {
             ),
             version_serializer: VersionSerializer::new(),
             version_deserializer: VersionDeserializer::new(),
+            protocol_version_serializer: U32VarIntSerializer::new(),
+            protocol_version_deserializer: U32VarIntDeserializer::new(
+                Included(0),
+                Included(u32::MAX),
+            ),
+            network_identity_serializer: NetworkIdentitySerializer::new(),
+            network_identity_deserializer: NetworkIdentityDeserializer::new(),
             config,
             peer_id_serializer: PeerIdSerializer::new(),
             peer_id_deserializer: PeerIdDeserializer::new(),
@@ -289,9 +831,86 @@ impl MassaHandshake {
         }
     }
 
+    /// Whether the IP of `addr` already has `max_connections_per_ip` live inbound connections.
+    /// Loopback addresses and the connections we initiate aren't limited.
+    fn is_ip_connection_limit_reached(&self, addr: &SocketAddr) -> bool {
+        let ip = to_canonical(addr.ip());
+        if self.config.max_connections_per_ip == 0 || ip.is_loopback() {
+            return false;
+        }
+        let active_connections = self.active_connections.read();
+        let Some(active_connections) = active_connections.as_ref() else {
+            return false;
+        };
+        if active_connections
+            .get_peer_ids_out_connection_queue()
+            .contains(addr)
+        {
+            return false;
+        }
+        let connections_from_ip = active_connections
+            .get_peers_connected()
+            .values()
+            .filter(|(peer_addr, connection_type, _)| {
+                *connection_type == PeerConnectionType::IN && to_canonical(peer_addr.ip()) == ip
+            })
+            .count();
+        connections_from_ip >= self.config.max_connections_per_ip
+    }
+
+    /// Whether an inbound connection from `peer_id` can be accepted with regard to `max_total_connections`.
+    /// At the limit, the connection of the inbound peer with the lowest reputation is closed to make room
+    /// if that reputation is lower than the one of `peer_id`, the connection is refused otherwise.
+    /// The connections we initiate aren't limited here, we stop dialing at the limit.
+    fn make_room_for_connection(&self, addr: &SocketAddr, peer_id: &PeerId) -> bool {
+        if self.config.max_total_connections == 0 {
+            return true;
+        }
+        let mut active_connections = self.active_connections.write();
+        let Some(active_connections) = active_connections.as_mut() else {
+            return true;
+        };
+        if active_connections
+            .get_peer_ids_out_connection_queue()
+            .contains(addr)
+        {
+            return true;
+        }
+        let peers_connected = active_connections.get_peers_connected();
+        if peers_connected.len() < self.config.max_total_connections {
+            return true;
+        }
+        let evicted = {
+            let peer_db_read = self.peer_db.read();
+            let reputation = |peer_id: &PeerId| {
+                peer_db_read
+                    .get_peers()
+                    .get(peer_id)
+                    .map_or(0, |info| info.reputation)
+            };
+            let lowest = peers_connected
+                .iter()
+                .filter(|(_, (_, connection_type, _))| *connection_type == PeerConnectionType::IN)
+                .map(|(connected_peer_id, _)| (*connected_peer_id, reputation(connected_peer_id)))
+                .min_by_key(|(_, connected_reputation)| *connected_reputation);
+            match lowest {
+                Some((evicted, evicted_reputation)) if evicted_reputation < reputation(peer_id) => {
+                    evicted
+                }
+                _ => return false,
+            }
+        };
+        info!(
+            "Closing the connection to peer {} to make room for peer {}",
+            evicted, peer_id
+        );
+        active_connections.shutdown_connection(&evicted);
+        true
+    }
+
     fn handshake_fail(&mut self, addr: &SocketAddr) {
         let mut peer_db_write = self.peer_db.write();
-        peer_db_write.set_try_connect_failure_or_insert(addr);
+        peer_db_write.set_try_connect_failure_or_insert(addr, self.clock.now());
     }
 }
 
@@ -304,6 +923,23 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
         messages_handler: MessagesHandler,
     ) -> PeerNetResult<PeerId> {
         let addr = *endpoint.get_target_addr();
+        if self.peer_db.read().is_ip_banned(&addr.ip()) {
+            debug!("Banned IP tried to connect: {}", addr);
+            return Err(PeerNetError::HandshakeError.error(
+                "Massa Handshake",
+                Some(format!("IP {} is banned", addr.ip())),
+            ));
+        }
+        if self.is_ip_connection_limit_reached(&addr) {
+            debug!("Too many connections from IP: {}", addr);
+            return Err(PeerNetError::HandshakeError.error(
+                "Massa Handshake",
+                Some(format!("Too many connections from IP {}", addr.ip())),
+            ));
+        }
+        let handshake_timeout = endpoint.try_clone().ok().map(|connection| {
+            HandshakeTimeout::start(connection, self.config.handshake_timeout.to_duration())
+        });
         let mut bytes = vec![];
         self.peer_id_serializer
             .serialize(&context.get_peer_id(), &mut bytes)
@@ -339,8 +975,38 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
                     Some(format!("Failed to serialize announcement: {}", err)),
                 )
             })?;
+        // trailing capabilities, ignored by the peers that don't know about them.
+        // The checksummed, tagged and enveloped frames are always accepted, whether we send some or not.
+        let capabilities = match self.config.compression {
+            CompressionMode::Zstd { .. } => HANDSHAKE_CAPABILITY_ZSTD,
+            CompressionMode::Off => 0,
+        } | HANDSHAKE_CAPABILITY_CHECKSUM
+            | HANDSHAKE_CAPABILITY_FORMAT_TAG
+            | HANDSHAKE_CAPABILITY_ENVELOPE;
+        bytes.push(capabilities);
+        self.protocol_version_serializer
+            .serialize(&self.config.protocol_version, &mut bytes)
+            .map_err(|err| {
+                self.handshake_fail(&addr);
+                PeerNetError::HandshakeError.error(
+                    "Massa Handshake",
+                    Some(format!("Failed to serialize protocol version: {}", err)),
+                )
+            })?;
+        self.network_identity_serializer
+            .serialize(&NetworkIdentity::from_config(&self.config), &mut bytes)
+            .map_err(|err| {
+                self.handshake_fail(&addr);
+                PeerNetError::HandshakeError.error(
+                    "Massa Handshake",
+                    Some(format!("Failed to serialize network identity: {}", err)),
+                )
+            })?;
         endpoint.send::<PeerId>(&bytes)?;
-        let received = endpoint.receive::<PeerId>()?;
+        let received = endpoint.receive::<PeerId>().map_err(|err| {
+            self.handshake_fail(&addr);
+            err
+        })?;
         if received.len() < 32 {
             self.handshake_fail(&addr);
             return Err(PeerNetError::HandshakeError.error(
@@ -358,24 +1024,32 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
                     Some(format!("Failed to deserialize peer id: {}", err)),
                 )
             })?;
+        if self
+            .peer_db
+            .read()
+            .get_peers()
+            .get(&peer_id)
+            .map_or(false, |info| info.state == PeerState::Banned)
         {
-            let peer_db_read = self.peer_db.read();
-            if let Some(info) = peer_db_read.get_peers().get(&peer_id) {
-                if info.state == PeerState::Banned {
-                    debug!("Banned peer tried to connect: {:?}", peer_id);
-                }
-            }
+            debug!("Banned peer tried to connect: {:?}", peer_id);
+            return Err(PeerNetError::HandshakeError.error(
+                "Massa Handshake",
+                Some(format!("Peer {} is banned", peer_id)),
+            ));
+        }
+        if !self.make_room_for_connection(&addr, &peer_id) {
+            debug!("Too many connections, refusing peer {}", peer_id);
+            return Err(PeerNetError::HandshakeError
+                .error("Massa Handshake", Some("Too many connections".to_string())));
         }
 
-        let res = {
+        let mut negotiated_features = None;
+        let mut wrong_network = false;
+        // run as a closure so that the failures still update the state of the peer below
+        let res = (|| -> PeerNetResult<(PeerId, Option<Announcement>)> {
             {
                 let mut peer_db_write = self.peer_db.write();
-                peer_db_write
-                    .get_peers_mut()
-                    .entry(peer_id)
-                    .and_modify(|info| {
-                        info.state = PeerState::InHandshake;
-                    });
+                peer_db_write.set_peer_state(&peer_id, PeerState::InHandshake);
             }
 
             let (received, version) = self
@@ -399,7 +1073,7 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
             )?;
             match id {
                 0 => {
-                    let (_, announcement) = self
+                    let (trailing, announcement) = self
                         .announcement_deserializer
                         .deserialize::<DeserializeError>(
                             received.get(1..).ok_or(PeerNetError::HandshakeError.error(
@@ -420,6 +1094,97 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
                         return Err(PeerNetError::HandshakeError
                             .error("Massa Handshake", Some("Invalid signature".to_string())));
                     }
+                    // peers predating the version negotiation don't send any version
+                    let (trailing_identity, peer_protocol_version) = match trailing.get(1..) {
+                        Some(data) if !data.is_empty() => self
+                            .protocol_version_deserializer
+                            .deserialize::<DeserializeError>(data)
+                            .map_err(|err| {
+                                PeerNetError::HandshakeError.error(
+                                    "Massa Handshake",
+                                    Some(format!(
+                                        "Failed to deserialize protocol version: {}",
+                                        err
+                                    )),
+                                )
+                            })?,
+                        _ => (&[][..], 0),
+                    };
+                    if peer_protocol_version < self.config.min_peer_version {
+                        info!(
+                            "Disconnecting peer {} speaking protocol version {}, below our minimum {}",
+                            peer_id, peer_protocol_version, self.config.min_peer_version
+                        );
+                        return Err(PeerNetError::HandshakeError.error(
+                            "Massa Handshake",
+                            Some(format!(
+                                "Incompatible protocol version {}, minimum is {}",
+                                peer_protocol_version, self.config.min_peer_version
+                            )),
+                        ));
+                    }
+                    // peers predating the network identity exchange don't send any
+                    if !trailing_identity.is_empty() {
+                        let (_, peer_identity) = self
+                            .network_identity_deserializer
+                            .deserialize::<DeserializeError>(trailing_identity)
+                            .map_err(|err| {
+                                PeerNetError::HandshakeError.error(
+                                    "Massa Handshake",
+                                    Some(format!(
+                                        "Failed to deserialize network identity: {}",
+                                        err
+                                    )),
+                                )
+                            })?;
+                        let our_identity = NetworkIdentity::from_config(&self.config);
+                        if peer_identity != our_identity {
+                            info!(
+                                "Disconnecting peer {} of another network: {:?}, ours is {:?}",
+                                peer_id, peer_identity, our_identity
+                            );
+                            wrong_network = true;
+                            return Err(PeerNetError::HandshakeError.error(
+                                "Massa Handshake",
+                                Some(format!("Wrong network: {:?}", peer_identity)),
+                            ));
+                        }
+                    }
+                    // peers not advertising any capability fall back to uncompressed messages
+                    let peer_supports_compression = trailing
+                        .first()
+                        .map_or(false, |flags| flags & HANDSHAKE_CAPABILITY_ZSTD != 0);
+                    let compress = matches!(self.config.compression, CompressionMode::Zstd { .. })
+                        && peer_supports_compression;
+                    if compress {
+                        self.compression_peers.write().insert(peer_id);
+                    } else {
+                        self.compression_peers.write().remove(&peer_id);
+                    }
+                    let peer_supports_checksum = trailing
+                        .first()
+                        .map_or(false, |flags| flags & HANDSHAKE_CAPABILITY_CHECKSUM != 0);
+                    let frame_checksum = self.config.frame_checksum && peer_supports_checksum;
+                    if frame_checksum {
+                        self.checksum_peers.write().insert(peer_id);
+                    } else {
+                        self.checksum_peers.write().remove(&peer_id);
+                    }
+                    let format_version =
+                        negotiated_format_version(trailing.first().copied().unwrap_or(0));
+                    if format_version == MessageFormatVersion::V1 {
+                        self.format_versions.write().remove(&peer_id);
+                    } else {
+                        self.format_versions.write().insert(peer_id, format_version);
+                    }
+                    negotiated_features = Some(NegotiatedFeatures {
+                        version,
+                        protocol_version: peer_protocol_version,
+                        capabilities: trailing.first().copied().unwrap_or(0),
+                        compression: compress,
+                        frame_checksum,
+                        format_version,
+                    });
                     let message = PeerManagementMessage::NewPeerConnected((
                         peer_id,
                         announcement.clone().listeners,
@@ -500,49 +1265,66 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
                 _ => Err(PeerNetError::HandshakeError
                     .error("Massa Handshake", Some("Invalid message id".to_string()))),
             }
+        })();
+        // a handshake finishing right as it times out has lost its connection anyway
+        let res = if handshake_timeout.map_or(false, HandshakeTimeout::finish) {
+            debug!("Handshake with {} timed out", addr);
+            res.and(Err(PeerNetError::HandshakeError.error(
+                "Massa Handshake",
+                Some("Handshake timed out".to_string()),
+            )))
+        } else {
+            res
         };
+        let now = self.clock.now();
         {
             let mut peer_db_write = self.peer_db.write();
             // if handshake failed, we set the peer state to HandshakeFailed
             match &res {
                 Ok((peer_id, Some(announcement))) => {
                     info!("Peer connected: {:?}", peer_id);
-                    peer_db_write.set_try_connect_success_or_insert(&addr);
-                    peer_db_write
-                        .get_peers_mut()
-                        .entry(*peer_id)
-                        .and_modify(|info| {
-                            info.last_announce = Some(announcement.clone());
-                            info.state = PeerState::Trusted;
-                        })
-                        .or_insert(PeerInfo {
-                            last_announce: Some(announcement.clone()),
-                            state: PeerState::Trusted,
-                        });
+                    peer_db_write.set_try_connect_success_or_insert(&addr, now);
+                    peer_db_write.set_handshake_success_or_insert(&addr, now);
+                    // reconnecting doesn't end a quarantine
+                    let state = if peer_db_write.get_quarantined_peers().contains(peer_id) {
+                        PeerState::Quarantined
+                    } else {
+                        PeerState::Trusted
+                    };
+                    peer_db_write.set_peer_announcement(peer_id, announcement.clone());
+                    peer_db_write.set_peer_features(peer_id, negotiated_features.clone());
+                    if !peer_db_write.set_peer_state(peer_id, state) {
+                        peer_db_write.insert_peer(
+                            *peer_id,
+                            PeerInfo {
+                                last_announce: Some(announcement.clone()),
+                                state: PeerState::Trusted,
+                                ban_reason: None,
+                                reputation: 0,
+                                features: negotiated_features.clone(),
+                            },
+                        );
+                    }
                 }
                 Ok((_peer_id, None)) => {
-                    peer_db_write
-                        .get_peers_mut()
-                        .entry(peer_id)
-                        .and_modify(|info| {
-                            //TODO: Add the peerdb but for now impossible as we don't have announcement and we need one to place in peerdb
-                            info.state = PeerState::HandshakeFailed;
-                        });
-                    peer_db_write.set_try_connect_failure_or_insert(&addr);
+                    //TODO: Add the peerdb but for now impossible as we don't have announcement and we need one to place in peerdb
+                    peer_db_write.set_peer_state(&peer_id, PeerState::HandshakeFailed);
+                    peer_db_write.set_try_connect_failure_or_insert(&addr, now);
                     return Err(PeerNetError::HandshakeError.error(
                         "Massa Handshake",
                         Some("Distant peer don't have slot for us.".to_string()),
                     ));
                 }
                 Err(_) => {
-                    peer_db_write.set_try_connect_failure_or_insert(&addr);
-                    peer_db_write
-                        .get_peers_mut()
-                        .entry(peer_id)
-                        .and_modify(|info| {
-                            //TODO: Add the peerdb but for now impossible as we don't have announcement and we need one to place in peerdb
-                            info.state = PeerState::HandshakeFailed;
-                        });
+                    peer_db_write.set_try_connect_failure_or_insert(&addr, now);
+                    //TODO: Add the peerdb but for now impossible as we don't have announcement and we need one to place in peerdb
+                    // a peer of another network is incompatible, not at fault: it isn't banned
+                    let state = if wrong_network {
+                        PeerState::WrongNetwork
+                    } else {
+                        PeerState::HandshakeFailed
+                    };
+                    peer_db_write.set_peer_state(&peer_id, state);
                 }
             }
         }
@@ -617,78 +1399,1557 @@ impl InitConnectionHandler<PeerId, Context, MessagesHandler> for MassaHandshake
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, ops::Deref, sync::Arc};
+    use std::{
+        collections::{HashMap, HashSet},
+        net::{IpAddr, SocketAddr},
+        ops::Deref,
+        sync::Arc,
+        time::Duration,
+    };
 
     use massa_channel::MassaChannel;
-    use massa_protocol_exports::ProtocolConfig;
-    use massa_serialization::U64VarIntDeserializer;
+    use massa_models::config::MAX_MESSAGE_SIZE;
+    use massa_protocol_exports::{CompressionMode, ProtocolConfig};
+    use massa_serialization::{DeserializeError, Deserializer, Serializer, U64VarIntDeserializer};
     use massa_signature::KeyPair;
-    use parking_lot::RwLock;
-    use peernet::{peer::InitConnectionHandler, transports::endpoint::Endpoint};
+    use parking_lot::{Mutex, RwLock};
+    use peernet::{
+        peer::{InitConnectionHandler, PeerConnectionType},
+        transports::{endpoint::Endpoint, TransportType},
+    };
+
+    use massa_protocol_exports::{BanReason, PeerEvent, PeerEventBroadcast, PeerId};
+    use massa_time::MassaTime;
+    use tracing::{
+        field::{Field, Visit},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context as LayerContext, SubscriberExt},
+        Layer, Registry,
+    };
 
-    use crate::{context::Context, messages::MessagesHandler};
+    use crate::{
+        context::Context,
+        messages::{Message, MessageFormatVersion, MessagesHandler, MessagesSerializer},
+        wrap_network::MockActiveConnectionsTrait,
+        wrap_peer_db::PeerDBTrait,
+    };
 
-    use super::models::PeerDB;
+    use super::{PeerManagementMessage, PeerManagementMessageSerializer};
+
+    use super::announcement::{
+        Announcement, AnnouncementDeserializer, AnnouncementDeserializerArgs,
+        AnnouncementSerializer,
+    };
+    use super::models::{
+        BanSeverity, NegotiatedFeatures, PeerDB, PeerInfo, PeerState, SharedPeerDB, WeightingPolicy,
+    };
 
     #[test]
-    fn test_handshake_working_behaviour() {
-        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
-        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
-        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
-        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
-        let shared_peer_db = Arc::new(RwLock::new(PeerDB::default()));
-        let mut handshake = super::MassaHandshake::new(shared_peer_db, ProtocolConfig::default());
-        let our_keypair = KeyPair::generate(0).unwrap();
-        let messages_handlers = MessagesHandler {
-            id_deserializer: U64VarIntDeserializer::new(
-                std::ops::Bound::Included(0),
-                std::ops::Bound::Included(u64::MAX),
-            ),
-            sender_blocks,
-            sender_endorsements,
-            sender_operations,
-            sender_peers,
-        };
-        let (local_sender, remote_receiver) =
-            MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
-        let (remote_sender, local_receiver) =
-            MassaChannel::new(String::from("Test_transport_remote_to_local"), None);
-        let mut endpoint = Endpoint::MockEndpoint((
-            (*local_sender.deref()).clone(),
-            (*local_receiver.deref()).clone(),
-            "127.0.0.1:0".parse().unwrap(),
-        ));
-        let context = Context { our_keypair };
-        let thread = std::thread::spawn({
-            let remote_receiver = remote_receiver.clone();
-            let remote_sender = remote_sender.clone();
-            let our_keypair = KeyPair::generate(0).unwrap();
-            let context = Context { our_keypair };
-            let mut handshake = handshake.clone();
-            let messages_handlers = messages_handlers.clone();
-            let mut endpoint = Endpoint::MockEndpoint((
-                (*remote_sender.deref()).clone(),
-                (*remote_receiver.deref()).clone(),
-                "127.0.0.1:0".parse().unwrap(),
-            ));
-            move || {
-                let res = handshake.perform_handshake(
-                    &context,
-                    &mut endpoint,
-                    &HashMap::default(),
-                    messages_handlers,
-                );
-                assert!(res.is_ok());
-            }
-        });
-        let res = handshake.perform_handshake(
-            &context,
-            &mut endpoint,
-            &HashMap::default(),
-            messages_handlers,
+    fn test_ban_escalation() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                offense_decay_period: MassaTime::from_millis(60 * 60 * 1000),
+                ..Default::default()
+            },
+            MassaTime::now(),
         );
-        assert!(res.is_ok());
-        thread.join().unwrap();
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+
+        let now = MassaTime::now();
+
+        // first minor offense: short ban
+        peer_db.ban_peer_with_severity(
+            &peer_id,
+            BanReason::ProtocolViolation,
+            BanSeverity::Minor,
+            now,
+        );
+        assert_eq!(peer_db.peers[&peer_id].state, PeerState::Banned);
+        assert_eq!(
+            peer_db.get_ban_reason(&peer_id).map(|(reason, _)| reason),
+            Some(BanReason::ProtocolViolation)
+        );
+        let first_ban_end = peer_db.offenses[&peer_id].ban_end.unwrap();
+
+        // unbanning keeps the offense count, the next ban lasts longer
+        peer_db.unban_peer(&peer_id);
+        assert_eq!(peer_db.peers[&peer_id].state, PeerState::HandshakeFailed);
+        assert!(peer_db.get_ban_reason(&peer_id).is_none());
+        peer_db.ban_peer_with_severity(
+            &peer_id,
+            BanReason::ProtocolViolation,
+            BanSeverity::Minor,
+            now,
+        );
+        assert_eq!(peer_db.offenses[&peer_id].count, 2);
+        assert!(peer_db.offenses[&peer_id].ban_end.unwrap() > first_ban_end);
+
+        // bans that didn't expire are not lifted
+        assert!(peer_db.unban_expired_peers(now).is_empty());
+        assert_eq!(peer_db.peers[&peer_id].state, PeerState::Banned);
+
+        // a major offense after that reaches the permanent step
+        peer_db.ban_peer_with_severity(
+            &peer_id,
+            BanReason::InvalidOperationList,
+            BanSeverity::Major,
+            now,
+        );
+        assert_eq!(peer_db.offenses[&peer_id].count, 4);
+        assert!(peer_db.offenses[&peer_id].ban_end.is_none());
+        assert_eq!(
+            peer_db.get_ban_reason(&peer_id).map(|(reason, _)| reason),
+            Some(BanReason::InvalidOperationList)
+        );
+    }
+
+    /// Collects the fields of the ban events
+    #[derive(Clone, Default)]
+    struct BanEventCapture(Arc<Mutex<Vec<HashMap<String, String>>>>);
+
+    struct FieldVisitor(HashMap<String, String>);
+
+    impl Visit for FieldVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for BanEventCapture {
+        fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+            if event.metadata().target() != super::BAN_LOG_TARGET {
+                return;
+            }
+            let mut visitor = FieldVisitor(HashMap::new());
+            event.record(&mut visitor);
+            self.0.lock().push(visitor.0);
+        }
+    }
+
+    #[test]
+    fn test_ban_is_logged_with_its_details() {
+        let mut peer_db = PeerDB::new(&ProtocolConfig::default(), MassaTime::now());
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        peer_db.ban_peer_with_severity(
+            &peer_id,
+            BanReason::InvalidBlockSignature,
+            BanSeverity::Major,
+            MassaTime::now(),
+        );
+        let ban_end = peer_db.offenses[&peer_id].ban_end.unwrap();
+        let peer_db: SharedPeerDB = Arc::new(RwLock::new(peer_db));
+        let ip: IpAddr = "82.245.123.77".parse().unwrap();
+
+        let capture = BanEventCapture::default();
+        tracing::subscriber::with_default(Registry::default().with(capture.clone()), || {
+            super::log_ban(
+                &peer_db,
+                &peer_id,
+                Some(&ip),
+                BanReason::InvalidBlockSignature,
+            );
+        });
+
+        let events = capture.0.lock();
+        assert_eq!(events.len(), 1);
+        let fields = &events[0];
+        assert_eq!(fields["peer_id"], peer_id.to_string());
+        assert_eq!(fields["ip"], "82.245.123.77");
+        assert_eq!(
+            fields["reason"],
+            BanReason::InvalidBlockSignature.to_string()
+        );
+        assert_eq!(fields["offense_count"], "2");
+        assert_eq!(fields["ban_until"], ban_end.as_millis().to_string());
+    }
+
+    #[test]
+    fn test_escalated_ban_lifted_at_its_end() {
+        let mut peer_db = PeerDB::new(&ProtocolConfig::default(), MassaTime::now());
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        let now = MassaTime::now();
+        peer_db.ban_peer_with_severity(
+            &peer_id,
+            BanReason::ProtocolViolation,
+            BanSeverity::Minor,
+            now,
+        );
+        let ban_end = peer_db.offenses[&peer_id].ban_end.unwrap();
+        assert!(ban_end > now);
+
+        // the ban is lifted at its end as seen by the given clock, not by the system one
+        assert!(peer_db
+            .unban_expired_peers(ban_end.saturating_sub(MassaTime::from_millis(1)))
+            .is_empty());
+        assert_eq!(peer_db.unban_expired_peers(ban_end), vec![peer_id]);
+        assert_eq!(peer_db.peers[&peer_id].state, PeerState::HandshakeFailed);
+    }
+
+    #[test]
+    fn test_tick_unban() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                ban_durations: HashMap::from([(
+                    BanReason::RateLimitExceeded,
+                    MassaTime::from_millis(60 * 1000),
+                )]),
+                unban_everyone_timer: MassaTime::from_millis(60 * 60 * 1000),
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        let [flooding_peer, manually_banned_peer, attacker] = [0; 3].map(|_| {
+            let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+            peer_db.insert_peer(
+                peer_id,
+                PeerInfo {
+                    last_announce: None,
+                    state: PeerState::Trusted,
+                    ban_reason: None,
+                    reputation: 0,
+                    features: None,
+                },
+            );
+            peer_id
+        });
+        let now = MassaTime::now();
+        peer_db.ban_peer(&flooding_peer, BanReason::RateLimitExceeded, now);
+        peer_db.ban_peer(&manually_banned_peer, BanReason::Manual, now);
+        peer_db.ban_peer_with_severity(
+            &attacker,
+            BanReason::AttackPropagation,
+            BanSeverity::Critical,
+            now,
+        );
+
+        // nobody served its ban yet
+        assert!(peer_db.tick_unban(now).is_empty());
+
+        // the ban duration depends on the reason
+        assert_eq!(
+            peer_db.tick_unban(now.saturating_add(MassaTime::from_millis(2 * 60 * 1000))),
+            vec![flooding_peer]
+        );
+        assert_eq!(peer_db.peers[&flooding_peer].state, PeerState::Trusted);
+        assert!(peer_db.get_ban_reason(&flooding_peer).is_none());
+        assert_eq!(
+            peer_db.peers[&manually_banned_peer].state,
+            PeerState::Banned
+        );
+
+        // permanent bans are never lifted
+        assert_eq!(
+            peer_db.tick_unban(now.saturating_add(MassaTime::from_millis(2 * 60 * 60 * 1000))),
+            vec![manually_banned_peer]
+        );
+        assert_eq!(
+            peer_db.peers[&manually_banned_peer].state,
+            PeerState::Trusted
+        );
+        assert_eq!(peer_db.peers[&attacker].state, PeerState::Banned);
+    }
+
+    #[test]
+    fn test_ban_list_persistence() {
+        let ban_list_file = tempfile::NamedTempFile::new().unwrap();
+        let config = ProtocolConfig {
+            ban_list_path: Some(ban_list_file.path().to_path_buf()),
+            ..Default::default()
+        };
+        // the empty temp file isn't a valid ban list
+        let mut peer_db = PeerDB::new(&config, MassaTime::now());
+        assert!(peer_db.peers.is_empty());
+
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        peer_db.ban_peer(&peer_id, BanReason::AttackPropagation, MassaTime::now());
+        peer_db.save_ban_list();
+
+        // the ban survives a restart
+        let peer_db = PeerDB::new(&config, MassaTime::now());
+        assert_eq!(peer_db.peers[&peer_id].state, PeerState::Banned);
+        assert_eq!(
+            peer_db.get_ban_reason(&peer_id).map(|(reason, _)| reason),
+            Some(BanReason::AttackPropagation)
+        );
+
+        // bans older than `unban_everyone_timer` are dropped at load
+        let peer_db = PeerDB::new(
+            &ProtocolConfig {
+                unban_everyone_timer: MassaTime::from_millis(0),
+                ..config
+            },
+            MassaTime::now(),
+        );
+        assert!(peer_db.peers.is_empty());
+    }
+
+    #[test]
+    fn test_subnet_ban() {
+        let mut peer_db = PeerDB::new(&ProtocolConfig::default(), MassaTime::now());
+        let now = MassaTime::now();
+        peer_db.ban_subnet("10.0.0.0/8".parse().unwrap(), now);
+        peer_db.ban_ip("192.168.1.1".parse().unwrap(), now);
+        assert!(peer_db.is_ip_banned(&"10.1.2.3".parse().unwrap()));
+        assert!(peer_db.is_ip_banned(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(peer_db.is_ip_banned(&"192.168.1.1".parse().unwrap()));
+        assert!(!peer_db.is_ip_banned(&"192.168.1.2".parse().unwrap()));
+
+        // loopback is never banned
+        peer_db.ban_ip("127.0.0.1".parse().unwrap(), now);
+        peer_db.ban_subnet("::1/128".parse().unwrap(), now);
+        assert!(!peer_db.is_ip_banned(&"127.0.0.1".parse().unwrap()));
+        assert!(!peer_db.is_ip_banned(&"::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ipv6_peers() {
+        let mut peer_db = PeerDB::new(&ProtocolConfig::default(), MassaTime::now());
+        let keypair = KeyPair::generate(0).unwrap();
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        let listener: SocketAddr = "[2001:db8::1]:31244".parse().unwrap();
+        let announcement = Announcement::new(
+            HashMap::from([(listener, TransportType::Tcp)]),
+            Some(listener.ip()),
+            &keypair,
+        )
+        .unwrap();
+
+        // the announcement received by the other peers
+        let mut buf = Vec::new();
+        AnnouncementSerializer::new()
+            .serialize(&announcement, &mut buf)
+            .unwrap();
+        let (rest, received_announcement) =
+            AnnouncementDeserializer::new(AnnouncementDeserializerArgs { max_listeners: 100 })
+                .deserialize::<DeserializeError>(&buf)
+                .unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(received_announcement, announcement);
+
+        // the peer is advertised with its IPv6 listener
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: Some(received_announcement),
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        assert_eq!(
+            peer_db.get_rand_peers_to_send(10),
+            vec![(peer_id, HashMap::from([(listener, TransportType::Tcp)]))]
+        );
+
+        // and can be banned by its IPv6 address
+        assert!(!peer_db.is_ip_banned(&listener.ip()));
+        let now = MassaTime::now();
+        peer_db.ban_ip(listener.ip(), now);
+        assert!(peer_db.is_ip_banned(&listener.ip()));
+        assert!(!peer_db.is_ip_banned(&"2001:db8::2".parse().unwrap()));
+        peer_db.ban_subnet("2001:db9::/64".parse().unwrap(), now);
+        assert!(peer_db.is_ip_banned(&"2001:db9::1234".parse().unwrap()));
+        assert!(!peer_db.is_ip_banned(&"2001:db9:0:1::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_peer_announces() {
+        let mut peer_db = PeerDB::new(&ProtocolConfig::default(), MassaTime::now());
+        let keypair = KeyPair::generate(0).unwrap();
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        let listener: SocketAddr = "192.168.1.1:31244".parse().unwrap();
+        let announcement = Announcement::new(
+            HashMap::from([(listener, TransportType::Tcp)]),
+            Some(listener.ip()),
+            &keypair,
+        )
+        .unwrap();
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: Some(announcement.clone()),
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        // a known peer that didn't announce itself
+        let silent_peer_id =
+            PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        peer_db.insert_peer(
+            silent_peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+
+        let read_announcement = peer_db.get_peer_announce(&peer_id).unwrap();
+        assert_eq!(read_announcement, announcement);
+        assert_eq!(
+            read_announcement.listeners,
+            HashMap::from([(listener, TransportType::Tcp)])
+        );
+        assert_eq!(peer_db.get_peer_announce(&silent_peer_id), None);
+        let unknown_peer_id =
+            PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        assert_eq!(peer_db.get_peer_announce(&unknown_peer_id), None);
+        assert_eq!(
+            peer_db.get_all_announces(),
+            HashMap::from([(peer_id, announcement)])
+        );
+    }
+
+    #[test]
+    fn test_seeded_peer_selection_is_reproducible() {
+        let peers: Vec<(PeerId, Announcement)> = (0..20)
+            .map(|index| {
+                let keypair = KeyPair::generate(0).unwrap();
+                let listener: SocketAddr =
+                    format!("82.245.123.{}:31244", index + 1).parse().unwrap();
+                let announcement = Announcement::new(
+                    HashMap::from([(listener, TransportType::Tcp)]),
+                    Some(listener.ip()),
+                    &keypair,
+                )
+                .unwrap();
+                (
+                    PeerId::from_public_key(keypair.get_public_key()),
+                    announcement,
+                )
+            })
+            .collect();
+        let selected_peers = |rng_seed: Option<u64>| {
+            let mut peer_db = PeerDB::new(
+                &ProtocolConfig {
+                    rng_seed,
+                    ..Default::default()
+                },
+                MassaTime::now(),
+            );
+            // inserted in another order each time
+            for (peer_id, announcement) in peers.iter().rev() {
+                peer_db.insert_peer(
+                    *peer_id,
+                    PeerInfo {
+                        last_announce: Some(announcement.clone()),
+                        state: PeerState::Trusted,
+                        ban_reason: None,
+                        reputation: 0,
+                        features: None,
+                    },
+                );
+            }
+            peer_db
+                .get_rand_peers_to_send(5)
+                .into_iter()
+                .map(|(peer_id, _)| peer_id)
+                .collect::<Vec<_>>()
+        };
+
+        let selection = selected_peers(Some(42));
+        assert_eq!(selection.len(), 5);
+        for _ in 0..5 {
+            assert_eq!(selected_peers(Some(42)), selection);
+        }
+        // another seed picks other peers
+        assert_ne!(selected_peers(Some(43)), selection);
+    }
+
+    #[test]
+    fn test_banned_peers_cap() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                max_banned_peers: 2,
+                max_banned_subnets: 1,
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        let peer_ids: Vec<PeerId> = (0..3)
+            .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+            .collect();
+        let start = MassaTime::now();
+        for (index, peer_id) in peer_ids.iter().enumerate() {
+            peer_db.insert_peer(
+                *peer_id,
+                PeerInfo {
+                    last_announce: None,
+                    state: PeerState::Trusted,
+                    ban_reason: None,
+                    reputation: 0,
+                    features: None,
+                },
+            );
+            // the ban timestamps are different
+            peer_db.ban_peer(
+                peer_id,
+                BanReason::ProtocolViolation,
+                start.saturating_add(MassaTime::from_millis(index as u64)),
+            );
+        }
+
+        // the oldest ban is forgotten
+        assert_eq!(peer_db.banned_count(), 2);
+        assert!(!peer_db.peers.contains_key(&peer_ids[0]));
+        assert_eq!(peer_db.peers[&peer_ids[1]].state, PeerState::Banned);
+        assert_eq!(peer_db.peers[&peer_ids[2]].state, PeerState::Banned);
+
+        // IP bans have their own cap
+        peer_db.ban_ip("192.168.1.1".parse().unwrap(), start);
+        peer_db.ban_ip(
+            "192.168.1.2".parse().unwrap(),
+            start.saturating_add(MassaTime::from_millis(1)),
+        );
+        assert!(!peer_db.is_ip_banned(&"192.168.1.1".parse().unwrap()));
+        assert!(peer_db.is_ip_banned(&"192.168.1.2".parse().unwrap()));
+        assert_eq!(peer_db.banned_count(), 2);
+    }
+
+    #[test]
+    fn test_reputation_threshold() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                reputation_ban_threshold: -50,
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+
+        // good behavior is capped so that it can't shield a peer for too long
+        assert!(!peer_db.adjust_reputation(&peer_id, 1000));
+        assert!(!peer_db.adjust_reputation(&peer_id, -150));
+        assert_eq!(peer_db.peers[&peer_id].reputation, -50);
+        assert!(peer_db.adjust_reputation(&peer_id, -1));
+
+        // banned peers don't trigger a new ban and start over once unbanned
+        peer_db.ban_peer_with_severity(
+            &peer_id,
+            BanReason::ProtocolViolation,
+            BanSeverity::Minor,
+            MassaTime::now(),
+        );
+        assert!(!peer_db.adjust_reputation(&peer_id, -1));
+        peer_db.unban_peer(&peer_id);
+        assert_eq!(peer_db.peers[&peer_id].reputation, 0);
+
+        // unknown peers are ignored
+        let unknown_peer_id =
+            PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        assert!(!peer_db.adjust_reputation(&unknown_peer_id, -1000));
+    }
+
+    #[test]
+    fn test_weighted_peers_to_send_favor_reputation() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                rng_seed: Some(42),
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        // half of the peers have a high reputation
+        let mut high_reputation_peers = HashSet::new();
+        for port in 8081..8091 {
+            let keypair = KeyPair::generate(0).unwrap();
+            let peer_id = PeerId::from_public_key(keypair.get_public_key());
+            let listeners = HashMap::from([(
+                format!("82.245.123.77:{}", port).parse().unwrap(),
+                TransportType::Tcp,
+            )]);
+            let announcement =
+                Announcement::new(listeners, Some("82.245.123.77".parse().unwrap()), &keypair)
+                    .unwrap();
+            let reputation = if port % 2 == 0 { 50 } else { 0 };
+            if reputation > 0 {
+                high_reputation_peers.insert(peer_id);
+            }
+            peer_db.insert_peer(
+                peer_id,
+                PeerInfo {
+                    last_announce: Some(announcement),
+                    state: PeerState::Trusted,
+                    ban_reason: None,
+                    reputation,
+                    features: None,
+                },
+            );
+        }
+
+        let (mut high_reputation_draws, mut low_reputation_draws) = (0, 0);
+        for _ in 0..1000 {
+            let sent_peers = peer_db.get_weighted_peers_to_send(3, WeightingPolicy::default());
+            assert_eq!(sent_peers.len(), 3);
+            for (peer_id, _) in sent_peers {
+                if high_reputation_peers.contains(&peer_id) {
+                    high_reputation_draws += 1;
+                } else {
+                    low_reputation_draws += 1;
+                }
+            }
+        }
+        // the high reputation peers are selected more often, the others aren't starved
+        assert!(high_reputation_draws > 2 * low_reputation_draws);
+        assert!(low_reputation_draws > 0);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        // quarantines expire right away
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                quarantine_duration: MassaTime::from_millis(0),
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        let mut peer_ids = Vec::new();
+        for port in [8081, 8082] {
+            let keypair = KeyPair::generate(0).unwrap();
+            let peer_id = PeerId::from_public_key(keypair.get_public_key());
+            let listeners = HashMap::from([(
+                format!("82.245.123.77:{}", port).parse().unwrap(),
+                TransportType::Tcp,
+            )]);
+            let announcement =
+                Announcement::new(listeners, Some("82.245.123.77".parse().unwrap()), &keypair)
+                    .unwrap();
+            peer_db.insert_peer(
+                peer_id,
+                PeerInfo {
+                    last_announce: Some(announcement),
+                    state: PeerState::Trusted,
+                    ban_reason: None,
+                    reputation: 0,
+                    features: None,
+                },
+            );
+            peer_ids.push(peer_id);
+        }
+        let sent_peers = |peer_db: &PeerDB| {
+            let mut sent_peers = peer_db
+                .get_rand_peers_to_send(10)
+                .into_iter()
+                .map(|(peer_id, _)| peer_id)
+                .collect::<Vec<_>>();
+            sent_peers.sort();
+            sent_peers
+        };
+        let mut all_peers = peer_ids.clone();
+        all_peers.sort();
+
+        let now = MassaTime::now();
+
+        // quarantined peers are not advertised
+        assert!(peer_db.quarantine_peer(&peer_ids[0], now));
+        assert_eq!(peer_db.peers[&peer_ids[0]].state, PeerState::Quarantined);
+        assert!(peer_db.get_quarantined_peers().contains(&peer_ids[0]));
+        assert_eq!(sent_peers(&peer_db), vec![peer_ids[1]]);
+
+        // a second offense must be escalated to a ban
+        assert!(!peer_db.quarantine_peer(&peer_ids[0], now));
+
+        // the quarantine is over
+        peer_db.unban_expired_peers(now);
+        assert_eq!(peer_db.peers[&peer_ids[0]].state, PeerState::Trusted);
+        assert!(peer_db.get_quarantined_peers().is_empty());
+        assert_eq!(sent_peers(&peer_db), all_peers);
+
+        // banning a quarantined peer ends its quarantine
+        assert!(peer_db.quarantine_peer(&peer_ids[1], now));
+        peer_db.ban_peer_with_severity(
+            &peer_ids[1],
+            BanReason::ProtocolViolation,
+            BanSeverity::Minor,
+            now,
+        );
+        assert_eq!(peer_db.peers[&peer_ids[1]].state, PeerState::Banned);
+        assert!(peer_db.get_quarantined_peers().is_empty());
+        assert!(peer_db.quarantine_peer(&peer_ids[1], now));
+        assert_eq!(peer_db.peers[&peer_ids[1]].state, PeerState::Banned);
+    }
+
+    #[test]
+    fn test_peer_state_counts() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                quarantine_duration: MassaTime::from_millis(0),
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        // the tallies must always match the states of the peers
+        let assert_counts = |peer_db: &PeerDB, trusted: u64, banned: u64, quarantined: u64| {
+            let counts = peer_db.get_peer_state_counts();
+            let count = |state: PeerState| {
+                peer_db
+                    .peers
+                    .values()
+                    .filter(|peer| peer.state == state)
+                    .count() as u64
+            };
+            assert_eq!(counts.trusted, count(PeerState::Trusted));
+            assert_eq!(counts.banned, count(PeerState::Banned));
+            assert_eq!(counts.quarantined, count(PeerState::Quarantined));
+            assert_eq!(counts.in_handshake, count(PeerState::InHandshake));
+            assert_eq!(counts.handshake_failed, count(PeerState::HandshakeFailed));
+            assert_eq!(counts.wrong_network, count(PeerState::WrongNetwork));
+            assert_eq!(
+                (counts.trusted, counts.banned, counts.quarantined),
+                (trusted, banned, quarantined)
+            );
+        };
+        let peer_ids: Vec<PeerId> = (0..3)
+            .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+            .collect();
+        for peer_id in &peer_ids {
+            peer_db.insert_peer(
+                *peer_id,
+                PeerInfo {
+                    last_announce: None,
+                    state: PeerState::Trusted,
+                    ban_reason: None,
+                    reputation: 0,
+                    features: None,
+                },
+            );
+        }
+        assert_counts(&peer_db, 3, 0, 0);
+
+        // replacing a known peer doesn't count it twice
+        peer_db.insert_peer(
+            peer_ids[0],
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::InHandshake,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        assert_counts(&peer_db, 2, 0, 0);
+        assert!(peer_db.set_peer_state(&peer_ids[0], PeerState::Trusted));
+        assert_counts(&peer_db, 3, 0, 0);
+
+        let now = MassaTime::now();
+        peer_db.ban_peer(&peer_ids[0], BanReason::Manual, now);
+        peer_db.ban_peer_with_severity(
+            &peer_ids[1],
+            BanReason::ProtocolViolation,
+            BanSeverity::Major,
+            now,
+        );
+        assert_counts(&peer_db, 1, 2, 0);
+        assert_eq!(peer_db.get_banned_peer_count(), 2);
+
+        assert!(peer_db.quarantine_peer(&peer_ids[2], now));
+        assert_counts(&peer_db, 0, 2, 1);
+        // banned peers are not quarantined
+        assert!(peer_db.quarantine_peer(&peer_ids[0], now));
+        assert_counts(&peer_db, 0, 2, 1);
+
+        // the unbanned peer must be tested again
+        peer_db.unban_peer(&peer_ids[0]);
+        assert_counts(&peer_db, 0, 1, 1);
+        assert_eq!(peer_db.get_peer_state_counts().handshake_failed, 1);
+
+        // the quarantine is over
+        peer_db.unban_expired_peers(now);
+        assert_counts(&peer_db, 1, 1, 0);
+
+        // unknown peers are left out
+        let unknown_peer_id =
+            PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        assert!(!peer_db.set_peer_state(&unknown_peer_id, PeerState::Trusted));
+        peer_db.ban_peer(&unknown_peer_id, BanReason::Manual, now);
+        assert_counts(&peer_db, 1, 1, 0);
+    }
+
+    #[test]
+    fn test_connect_backoff() {
+        let mut peer_db = PeerDB::new(
+            &ProtocolConfig {
+                connect_backoff_base: MassaTime::from_millis(1000),
+                connect_backoff_max: MassaTime::from_millis(10000),
+                ..Default::default()
+            },
+            MassaTime::now(),
+        );
+        let addr: SocketAddr = "82.245.123.77:31244".parse().unwrap();
+        let retry_interval = |peer_db: &PeerDB| {
+            let metadata = peer_db.get_connection_metadata_or_default(&addr);
+            metadata
+                .next_try_connect
+                .unwrap()
+                .saturating_sub(metadata.last_failure.unwrap())
+                .as_millis()
+        };
+
+        let now = MassaTime::now();
+
+        // the interval doubles at each failure, up to the max
+        let mut intervals = Vec::new();
+        for _ in 0..6 {
+            peer_db.set_try_connect_failure_or_insert(&addr, now);
+            intervals.push(retry_interval(&peer_db));
+        }
+        assert_eq!(intervals, vec![1000, 2000, 4000, 8000, 10000, 10000]);
+        let metadata = peer_db.get_connection_metadata_or_default(&addr);
+        assert_eq!(metadata.connect_failures, 6);
+        assert_eq!(
+            metadata.next_try_connect,
+            Some(now.saturating_add(MassaTime::from_millis(10000)))
+        );
+
+        // a successful handshake ends the backoff
+        peer_db.set_handshake_success_or_insert(&addr, now);
+        let metadata = peer_db.get_connection_metadata_or_default(&addr);
+        assert_eq!(metadata.connect_failures, 0);
+        assert!(metadata.next_try_connect.is_none());
+        peer_db.set_try_connect_failure_or_insert(&addr, now);
+        assert_eq!(retry_interval(&peer_db), 1000);
+    }
+
+    #[test]
+    fn test_peer_events_drop_oldest() {
+        let peer_events = PeerEventBroadcast::new(2);
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let event = |timestamp| PeerEvent::Unbanned {
+            peer_id,
+            ip: None,
+            timestamp: MassaTime::from_millis(timestamp),
+        };
+
+        // nothing is kept without subscribers
+        peer_events.send(event(0));
+        assert_eq!(peer_events.dropped_count(), 0);
+
+        let mut receiver = peer_events.subscribe();
+        for timestamp in 1..=4 {
+            peer_events.send(event(timestamp));
+        }
+        assert_eq!(peer_events.dropped_count(), 2);
+        // the subscriber is told it lagged, then gets the most recent events
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(receiver.try_recv().unwrap(), event(3));
+        assert_eq!(receiver.try_recv().unwrap(), event(4));
+    }
+
+    #[test]
+    fn test_drain_connections() {
+        let peer_ids: HashSet<PeerId> = (0..3)
+            .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+            .collect();
+        // (peer, whether the event is a shutdown) in the order of the calls
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut active_connections = MockActiveConnectionsTrait::new();
+        active_connections
+            .expect_get_peer_ids_connected()
+            .times(1)
+            .return_const(peer_ids.clone());
+        active_connections
+            .expect_send_to_peer()
+            .times(3)
+            .returning({
+                let calls = calls.clone();
+                move |peer_id, _, message, high_priority| {
+                    assert!(high_priority);
+                    match message {
+                        Message::PeerManagement(message) => match *message {
+                            PeerManagementMessage::Disconnect { reason } => {
+                                assert_eq!(reason, "node shutting down")
+                            }
+                            _ => panic!("unexpected peer management message"),
+                        },
+                        _ => panic!("unexpected message"),
+                    }
+                    calls.lock().push((*peer_id, false));
+                    Ok(())
+                }
+            });
+        active_connections
+            .expect_shutdown_connection()
+            .times(3)
+            .returning({
+                let calls = calls.clone();
+                move |peer_id| calls.lock().push((*peer_id, true))
+            });
+
+        super::drain_connections(
+            &mut active_connections,
+            &MessagesSerializer::new()
+                .with_peer_management_message_serializer(PeerManagementMessageSerializer::new()),
+            "node shutting down".to_string(),
+            Duration::from_millis(10),
+        );
+
+        // every peer is told we leave before any connection is closed
+        let calls = calls.lock();
+        assert!(calls[..3].iter().all(|(_, shutdown)| !shutdown));
+        assert!(calls[3..].iter().all(|(_, shutdown)| *shutdown));
+        let shut_down: HashSet<PeerId> = calls[3..].iter().map(|(peer_id, _)| *peer_id).collect();
+        assert_eq!(shut_down, peer_ids);
+    }
+
+    #[test]
+    fn test_handshake_working_behaviour() {
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let shared_peer_db = Arc::new(RwLock::new(PeerDB::default()));
+        let mut handshake = super::MassaHandshake::new(shared_peer_db, ProtocolConfig::default());
+        let our_keypair = KeyPair::generate(0).unwrap();
+        let messages_handlers = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(
+                std::ops::Bound::Included(0),
+                std::ops::Bound::Included(u64::MAX),
+            ),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let (local_sender, remote_receiver) =
+            MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
+        let (remote_sender, local_receiver) =
+            MassaChannel::new(String::from("Test_transport_remote_to_local"), None);
+        let mut endpoint = Endpoint::MockEndpoint((
+            (*local_sender.deref()).clone(),
+            (*local_receiver.deref()).clone(),
+            "127.0.0.1:0".parse().unwrap(),
+        ));
+        let context = Context { our_keypair };
+        let thread = std::thread::spawn({
+            let remote_receiver = remote_receiver.clone();
+            let remote_sender = remote_sender.clone();
+            let our_keypair = KeyPair::generate(0).unwrap();
+            let context = Context { our_keypair };
+            let mut handshake = handshake.clone();
+            let messages_handlers = messages_handlers.clone();
+            let mut endpoint = Endpoint::MockEndpoint((
+                (*remote_sender.deref()).clone(),
+                (*remote_receiver.deref()).clone(),
+                "127.0.0.1:0".parse().unwrap(),
+            ));
+            move || {
+                let res = handshake.perform_handshake(
+                    &context,
+                    &mut endpoint,
+                    &HashMap::default(),
+                    messages_handlers,
+                );
+                assert!(res.is_ok());
+            }
+        });
+        let res = handshake.perform_handshake(
+            &context,
+            &mut endpoint,
+            &HashMap::default(),
+            messages_handlers,
+        );
+        assert!(res.is_ok());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_format_version_is_the_newest_advertised() {
+        assert_eq!(
+            super::negotiated_format_version(super::HANDSHAKE_CAPABILITY_ZSTD),
+            MessageFormatVersion::V1
+        );
+        assert_eq!(
+            super::negotiated_format_version(super::HANDSHAKE_CAPABILITY_FORMAT_TAG),
+            MessageFormatVersion::V2
+        );
+        assert_eq!(
+            super::negotiated_format_version(
+                super::HANDSHAKE_CAPABILITY_FORMAT_TAG | super::HANDSHAKE_CAPABILITY_ENVELOPE
+            ),
+            MessageFormatVersion::V3
+        );
+    }
+
+    #[test]
+    fn test_handshake_records_negotiated_features() {
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let messages_handlers = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(
+                std::ops::Bound::Included(0),
+                std::ops::Bound::Included(u64::MAX),
+            ),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let compression = CompressionMode::Zstd {
+            level: 3,
+            min_size: 0,
+        };
+        let local_keypair = KeyPair::generate(0).unwrap();
+        let local_peer_id = PeerId::from_public_key(local_keypair.get_public_key());
+        let remote_keypair = KeyPair::generate(0).unwrap();
+        let remote_peer_id = PeerId::from_public_key(remote_keypair.get_public_key());
+        // both nodes compress, only the local one sends checksummed frames
+        let local_peer_db = Arc::new(RwLock::new(PeerDB::default()));
+        let mut handshake = super::MassaHandshake::new(
+            local_peer_db.clone(),
+            ProtocolConfig {
+                protocol_version: 2,
+                compression,
+                frame_checksum: true,
+                ..Default::default()
+            },
+        );
+        let remote_peer_db = Arc::new(RwLock::new(PeerDB::default()));
+        let remote_config = ProtocolConfig {
+            protocol_version: 3,
+            compression,
+            frame_checksum: false,
+            ..Default::default()
+        };
+        let remote_version = remote_config.version;
+        let (local_sender, remote_receiver) =
+            MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
+        let (remote_sender, local_receiver) =
+            MassaChannel::new(String::from("Test_transport_remote_to_local"), None);
+        let mut endpoint = Endpoint::MockEndpoint((
+            (*local_sender.deref()).clone(),
+            (*local_receiver.deref()).clone(),
+            "127.0.0.1:0".parse().unwrap(),
+        ));
+        let context = Context {
+            our_keypair: local_keypair,
+        };
+        let thread = std::thread::spawn({
+            let context = Context {
+                our_keypair: remote_keypair,
+            };
+            let mut handshake = super::MassaHandshake::new(remote_peer_db.clone(), remote_config);
+            let messages_handlers = messages_handlers.clone();
+            let mut endpoint = Endpoint::MockEndpoint((
+                (*remote_sender.deref()).clone(),
+                (*remote_receiver.deref()).clone(),
+                "127.0.0.1:0".parse().unwrap(),
+            ));
+            move || {
+                let res = handshake.perform_handshake(
+                    &context,
+                    &mut endpoint,
+                    &HashMap::default(),
+                    messages_handlers,
+                );
+                assert!(res.is_ok());
+            }
+        });
+        let res = handshake.perform_handshake(
+            &context,
+            &mut endpoint,
+            &HashMap::default(),
+            messages_handlers,
+        );
+        assert!(res.is_ok());
+        thread.join().unwrap();
+
+        assert_eq!(
+            local_peer_db.read().get_peer_features(&remote_peer_id),
+            Some(NegotiatedFeatures {
+                version: remote_version,
+                protocol_version: 3,
+                capabilities: super::HANDSHAKE_CAPABILITY_ZSTD
+                    | super::HANDSHAKE_CAPABILITY_CHECKSUM
+                    | super::HANDSHAKE_CAPABILITY_FORMAT_TAG
+                    | super::HANDSHAKE_CAPABILITY_ENVELOPE,
+                compression: true,
+                frame_checksum: true,
+                format_version: MessageFormatVersion::V3,
+            })
+        );
+        // the remote node doesn't send checksummed frames even though we support them
+        let remote_features = remote_peer_db
+            .read()
+            .get_peer_features(&local_peer_id)
+            .unwrap();
+        assert_eq!(remote_features.protocol_version, 2);
+        assert!(remote_features.compression);
+        assert!(!remote_features.frame_checksum);
+        assert_eq!(remote_features.format_version, MessageFormatVersion::V3);
+        assert_eq!(
+            handshake.format_versions.read().get(&remote_peer_id),
+            Some(&MessageFormatVersion::V3)
+        );
+        assert!(local_peer_db
+            .read()
+            .get_peer_features(&local_peer_id)
+            .is_none());
+    }
+
+    #[test]
+    fn test_handshake_rejects_incompatible_protocol_version() {
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let messages_handlers = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(
+                std::ops::Bound::Included(0),
+                std::ops::Bound::Included(u64::MAX),
+            ),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let remote_keypair = KeyPair::generate(0).unwrap();
+        let remote_peer_id = PeerId::from_public_key(remote_keypair.get_public_key());
+        let mut local_peer_db = PeerDB::default();
+        local_peer_db.insert_peer(
+            remote_peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        let local_peer_db = Arc::new(RwLock::new(local_peer_db));
+        let mut handshake = super::MassaHandshake::new(
+            local_peer_db.clone(),
+            ProtocolConfig {
+                protocol_version: 2,
+                min_peer_version: 2,
+                ..Default::default()
+            },
+        );
+        let (local_sender, remote_receiver) =
+            MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
+        let (remote_sender, local_receiver) =
+            MassaChannel::new(String::from("Test_transport_remote_to_local"), None);
+        let mut endpoint = Endpoint::MockEndpoint((
+            (*local_sender.deref()).clone(),
+            (*local_receiver.deref()).clone(),
+            "127.0.0.1:0".parse().unwrap(),
+        ));
+        let context = Context {
+            our_keypair: KeyPair::generate(0).unwrap(),
+        };
+        let thread = std::thread::spawn({
+            let context = Context {
+                our_keypair: remote_keypair,
+            };
+            let mut handshake = super::MassaHandshake::new(
+                Arc::new(RwLock::new(PeerDB::default())),
+                ProtocolConfig {
+                    protocol_version: 1,
+                    ..Default::default()
+                },
+            );
+            let messages_handlers = messages_handlers.clone();
+            let mut endpoint = Endpoint::MockEndpoint((
+                (*remote_sender.deref()).clone(),
+                (*remote_receiver.deref()).clone(),
+                "127.0.0.1:0".parse().unwrap(),
+            ));
+            move || {
+                // the outdated peer is disconnected before the end of its handshake
+                let res = handshake.perform_handshake(
+                    &context,
+                    &mut endpoint,
+                    &HashMap::default(),
+                    messages_handlers,
+                );
+                assert!(res.is_err());
+            }
+        });
+        let res = handshake.perform_handshake(
+            &context,
+            &mut endpoint,
+            &HashMap::default(),
+            messages_handlers,
+        );
+        assert!(res.is_err());
+        thread.join().unwrap();
+
+        // the outdated peer is disconnected, not banned
+        let local_peer_db = local_peer_db.read();
+        let info = local_peer_db.peers.get(&remote_peer_id).unwrap();
+        assert_eq!(info.state, PeerState::HandshakeFailed);
+        assert!(info.ban_reason.is_none());
+        assert!(local_peer_db.banned_subnets.is_empty());
+    }
+
+    #[test]
+    fn test_handshake_disconnects_peers_of_another_network() {
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let messages_handlers = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(
+                std::ops::Bound::Included(0),
+                std::ops::Bound::Included(u64::MAX),
+            ),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let local_keypair = KeyPair::generate(0).unwrap();
+        let local_peer_id = PeerId::from_public_key(local_keypair.get_public_key());
+        let remote_keypair = KeyPair::generate(0).unwrap();
+        let remote_peer_id = PeerId::from_public_key(remote_keypair.get_public_key());
+        let trusted_peer = || PeerInfo {
+            last_announce: None,
+            state: PeerState::Trusted,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
+        };
+        let mut local_peer_db = PeerDB::default();
+        local_peer_db.insert_peer(remote_peer_id, trusted_peer());
+        let local_peer_db = Arc::new(RwLock::new(local_peer_db));
+        let mut remote_peer_db = PeerDB::default();
+        remote_peer_db.insert_peer(local_peer_id, trusted_peer());
+        let remote_peer_db = Arc::new(RwLock::new(remote_peer_db));
+        // same genesis, different thread counts
+        let mut handshake = super::MassaHandshake::new(
+            local_peer_db.clone(),
+            ProtocolConfig {
+                thread_count: 32,
+                ..Default::default()
+            },
+        );
+        let (local_sender, remote_receiver) =
+            MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
+        let (remote_sender, local_receiver) =
+            MassaChannel::new(String::from("Test_transport_remote_to_local"), None);
+        let mut endpoint = Endpoint::MockEndpoint((
+            (*local_sender.deref()).clone(),
+            (*local_receiver.deref()).clone(),
+            "127.0.0.1:0".parse().unwrap(),
+        ));
+        let context = Context {
+            our_keypair: local_keypair,
+        };
+        let thread = std::thread::spawn({
+            let context = Context {
+                our_keypair: remote_keypair,
+            };
+            let mut handshake = super::MassaHandshake::new(
+                remote_peer_db.clone(),
+                ProtocolConfig {
+                    thread_count: 16,
+                    ..Default::default()
+                },
+            );
+            let messages_handlers = messages_handlers.clone();
+            let mut endpoint = Endpoint::MockEndpoint((
+                (*remote_sender.deref()).clone(),
+                (*remote_receiver.deref()).clone(),
+                "127.0.0.1:0".parse().unwrap(),
+            ));
+            move || {
+                let res = handshake.perform_handshake(
+                    &context,
+                    &mut endpoint,
+                    &HashMap::default(),
+                    messages_handlers,
+                );
+                assert!(res.is_err());
+            }
+        });
+        let res = handshake.perform_handshake(
+            &context,
+            &mut endpoint,
+            &HashMap::default(),
+            messages_handlers,
+        );
+        assert!(res.is_err());
+        thread.join().unwrap();
+
+        // both sides disconnect the other one without banning it
+        for (peer_db, peer_id) in [
+            (&local_peer_db, &remote_peer_id),
+            (&remote_peer_db, &local_peer_id),
+        ] {
+            let peer_db = peer_db.read();
+            let info = peer_db.peers.get(peer_id).unwrap();
+            assert_eq!(info.state, PeerState::WrongNetwork);
+            assert!(info.ban_reason.is_none());
+            assert_eq!(info.reputation, 0);
+            assert!(peer_db.banned_subnets.is_empty());
+            assert_eq!(peer_db.get_peer_state_counts().wrong_network, 1);
+            assert_eq!(peer_db.get_banned_peer_count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_handshake_refuses_connections_above_the_ip_limit() {
+        let max_connections_per_ip = 2;
+        let remote_addr: SocketAddr = "10.0.0.1:33036".parse().unwrap();
+        let loopback_addr: SocketAddr = "127.0.0.1:33036".parse().unwrap();
+        let handshake_with_connections = |ip: IpAddr, count: u16| {
+            let peers_connected: HashMap<_, _> = (0..count)
+                .map(|port| {
+                    (
+                        PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()),
+                        (
+                            SocketAddr::new(ip, 1000 + port),
+                            PeerConnectionType::IN,
+                            None,
+                        ),
+                    )
+                })
+                .collect();
+            let mut active_connections = MockActiveConnectionsTrait::new();
+            active_connections
+                .expect_get_peer_ids_out_connection_queue()
+                .return_const(HashSet::new());
+            active_connections
+                .expect_get_peers_connected()
+                .return_const(peers_connected);
+            let handshake = super::MassaHandshake::new(
+                Arc::new(RwLock::new(PeerDB::default())),
+                ProtocolConfig {
+                    max_connections_per_ip,
+                    ..Default::default()
+                },
+            );
+            *handshake.active_connections.write() = Some(Box::new(active_connections));
+            handshake
+        };
+
+        // the connections from the same IP are accepted up to the limit
+        for live_connections in 0..=max_connections_per_ip {
+            let handshake = handshake_with_connections(remote_addr.ip(), live_connections as u16);
+            assert_eq!(
+                handshake.is_ip_connection_limit_reached(&remote_addr),
+                live_connections == max_connections_per_ip
+            );
+        }
+        // loopback isn't limited
+        let handshake = handshake_with_connections(loopback_addr.ip(), 2);
+        assert!(!handshake.is_ip_connection_limit_reached(&loopback_addr));
+
+        // the connection above the limit is refused before anything is exchanged
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let messages_handlers = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(
+                std::ops::Bound::Included(0),
+                std::ops::Bound::Included(u64::MAX),
+            ),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let (local_sender, remote_receiver) =
+            MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
+        let (_remote_sender, local_receiver) =
+            MassaChannel::new(String::from("Test_transport_remote_to_local"), None);
+        let mut endpoint = Endpoint::MockEndpoint((
+            (*local_sender.deref()).clone(),
+            (*local_receiver.deref()).clone(),
+            remote_addr,
+        ));
+        let mut handshake =
+            handshake_with_connections(remote_addr.ip(), max_connections_per_ip as u16);
+        let context = Context {
+            our_keypair: KeyPair::generate(0).unwrap(),
+        };
+        let res = handshake.perform_handshake(
+            &context,
+            &mut endpoint,
+            &HashMap::default(),
+            messages_handlers,
+        );
+        assert!(res.is_err());
+        assert!(remote_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_handshake_makes_room_above_the_total_connection_limit() {
+        let max_total_connections = 3;
+        let remote_addr: SocketAddr = "10.0.0.1:33036".parse().unwrap();
+        let connected_peer_ids: Vec<PeerId> = (0..max_total_connections)
+            .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+            .collect();
+        // the connected peers have reputations 0, 1 and 2
+        let mut peer_db = PeerDB::default();
+        for (reputation, peer_id) in connected_peer_ids.iter().enumerate() {
+            peer_db.insert_peer(
+                *peer_id,
+                PeerInfo {
+                    last_announce: None,
+                    state: PeerState::Trusted,
+                    ban_reason: None,
+                    reputation: reputation as i32,
+                    features: None,
+                },
+            );
+        }
+        let peer_db = Arc::new(RwLock::new(peer_db));
+        let handshake_with_connections = |count: usize, expected_eviction: Option<PeerId>| {
+            let peers_connected: HashMap<_, _> = connected_peer_ids[..count]
+                .iter()
+                .enumerate()
+                .map(|(index, peer_id)| {
+                    (
+                        *peer_id,
+                        (
+                            SocketAddr::new(remote_addr.ip(), 1000 + index as u16),
+                            PeerConnectionType::IN,
+                            None,
+                        ),
+                    )
+                })
+                .collect();
+            let mut active_connections = MockActiveConnectionsTrait::new();
+            active_connections
+                .expect_get_peer_ids_out_connection_queue()
+                .return_const(HashSet::new());
+            active_connections
+                .expect_get_peers_connected()
+                .return_const(peers_connected);
+            match expected_eviction {
+                Some(evicted) => {
+                    active_connections
+                        .expect_shutdown_connection()
+                        .withf(move |peer_id| *peer_id == evicted)
+                        .times(1)
+                        .return_const(());
+                }
+                None => {
+                    active_connections.expect_shutdown_connection().never();
+                }
+            }
+            let handshake = super::MassaHandshake::new(
+                peer_db.clone(),
+                ProtocolConfig {
+                    max_total_connections,
+                    ..Default::default()
+                },
+            );
+            *handshake.active_connections.write() = Some(Box::new(active_connections));
+            handshake
+        };
+        let unknown_peer_id =
+            PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        // the connections are accepted up to the limit
+        for live_connections in 0..max_total_connections {
+            let handshake = handshake_with_connections(live_connections, None);
+            assert!(handshake.make_room_for_connection(&remote_addr, &unknown_peer_id));
+        }
+        // at the limit, a peer no better than the connected ones is refused
+        let handshake = handshake_with_connections(max_total_connections, None);
+        assert!(!handshake.make_room_for_connection(&remote_addr, &unknown_peer_id));
+        // and a peer with a better reputation replaces the worst connected one
+        let handshake =
+            handshake_with_connections(max_total_connections, Some(connected_peer_ids[0]));
+        assert!(handshake.make_room_for_connection(&remote_addr, &connected_peer_ids[1]));
     }
 
     #[test]
@@ -709,6 +2970,13 @@ mod tests {
             sender_endorsements,
             sender_operations,
             sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
         };
         let (local_sender, _) =
             MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
@@ -754,6 +3022,13 @@ mod tests {
             sender_endorsements,
             sender_operations,
             sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
         };
         let (local_sender, _) =
             MassaChannel::new(String::from("Test_transport_local_to_remote"), None);
@@ -1,22 +1,60 @@
+use ipnet::IpNet;
 use massa_channel::sender::MassaSender;
-use massa_protocol_exports::{BootstrapPeers, PeerId};
+use massa_models::version::Version;
+pub use massa_protocol_exports::BanSeverity;
+use massa_protocol_exports::{
+    BanReason, BannedPeerInfo, BootstrapPeers, MessageCategory, PeerId, PeerStateCounts, PeerStats,
+    ProtocolConfig, ProtocolConfigUpdate,
+};
 use massa_time::MassaTime;
 use parking_lot::RwLock;
 use peernet::transports::TransportType;
 use rand::seq::SliceRandom;
 use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashSet;
-use std::time::Duration;
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tracing::log::info;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tracing::log::{debug, info, warn};
 
-use crate::wrap_peer_db::PeerDBTrait;
+use crate::{
+    ip::to_canonical, messages::MessageFormatVersion, rng::SharedRng, wrap_peer_db::PeerDBTrait,
+};
 
-use super::announcement::Announcement;
+use super::{announcement::Announcement, ban_list_writer::BanListWriter};
 
 const THREE_DAYS_MS: u64 = 3 * 24 * 60 * 60 * 1_000;
 
+/// Maximum reputation of a peer, so that a long history of good behavior doesn't shield it from bans
+const MAX_REPUTATION: i32 = 100;
+/// Reputation gained by a peer serving a block we asked for in time
+pub const REPUTATION_BLOCK_SERVED: i32 = 2;
+/// Reputation gained by a peer relaying valid operations
+pub const REPUTATION_VALID_OPERATIONS: i32 = 1;
+/// Reputation lost by a peer not sending the endorsements it announced and that we asked for
+pub const REPUTATION_UNDELIVERED_ENDORSEMENTS: i32 = -5;
+/// Reputation lost by a peer committing a minor offense
+pub const REPUTATION_MINOR_OFFENSE: i32 = -25;
+/// Reputation lost by a peer sending us a header that consensus rejected
+pub const REPUTATION_REJECTED_HEADER: i32 = -10;
+/// Reputation lost by a peer sending us a header of a slot long finalized
+pub const REPUTATION_STALE_HEADER: i32 = -2;
+/// Reputation lost by a peer sending us the same header more than `max_duplicate_headers` times again
+pub const REPUTATION_DUPLICATE_HEADER: i32 = -5;
+/// Reputation lost by each message carrying expired operations sent by a peer beyond `max_expired_operation_messages` in a row
+pub const REPUTATION_EXPIRED_OPERATIONS: i32 = -2;
+/// Reputation lost by a peer that relayed an attack block once, before we detected the attack
+pub const REPUTATION_EARLY_ATTACK_RELAY: i32 = -50;
+
+/// Ban durations applied for the first offenses of a peer, past the last step the ban is permanent
+const ESCALATING_BAN_DURATIONS_MS: [u64; 3] = [60 * 1_000, 5 * 60 * 1_000, 30 * 60 * 1_000];
+
 pub type InitialPeers = HashMap<PeerId, HashMap<SocketAddr, TransportType>>;
 
 #[derive(Clone, Eq, PartialEq)]
@@ -26,6 +64,10 @@ pub struct ConnectionMetadata {
     pub last_try_connect: Option<MassaTime>,
     pub last_test_success: Option<MassaTime>,
     pub last_test_failure: Option<MassaTime>,
+    /// failed connection attempts since the last successful handshake
+    pub connect_failures: u32,
+    /// the address isn't tried again before this time after a failure
+    pub next_try_connect: Option<MassaTime>,
     random_priority: u64,
 }
 
@@ -37,6 +79,8 @@ impl Default for ConnectionMetadata {
             last_success: Default::default(),
             last_failure: Default::default(),
             last_try_connect: Default::default(),
+            connect_failures: 0,
+            next_try_connect: None,
             random_priority: thread_rng().gen(),
         }
     }
@@ -125,46 +169,487 @@ impl ConnectionMetadata {
             _ => unreachable!("connection metadata data_type not recognized: {data_type}"),
         }
     }
-    pub fn failure(&mut self) {
-        self.last_failure = Some(MassaTime::now());
+    /// Record a connection that failed at `now`, the next attempt is delayed by `base * 2^n` after
+    /// the n-th consecutive failure, up to `max`
+    pub fn failure(&mut self, backoff: Option<(MassaTime, MassaTime)>, now: MassaTime) {
+        self.last_failure = Some(now);
+        if let Some((base, max)) = backoff {
+            let delay = base
+                .saturating_mul(2u64.saturating_pow(self.connect_failures))
+                .min(max);
+            self.connect_failures = self.connect_failures.saturating_add(1);
+            self.next_try_connect = Some(now.saturating_add(delay));
+        }
     }
 
-    pub fn test_failure(&mut self) {
-        self.last_test_failure = Some(MassaTime::now());
+    pub fn test_failure(&mut self, now: MassaTime) {
+        self.last_test_failure = Some(now);
     }
 
-    pub fn test_success(&mut self) {
-        self.last_test_success = Some(MassaTime::now());
+    pub fn test_success(&mut self, now: MassaTime) {
+        self.last_test_success = Some(now);
     }
 
-    pub fn success(&mut self) {
-        self.last_success = Some(MassaTime::now());
+    pub fn success(&mut self, now: MassaTime) {
+        self.last_success = Some(now);
+        self.connect_failures = 0;
+        self.next_try_connect = None;
     }
 
-    pub fn try_connect(&mut self) {
-        self.last_try_connect = Some(MassaTime::now());
+    pub fn try_connect(&mut self, now: MassaTime) {
+        self.last_try_connect = Some(now);
     }
 }
 
 #[derive(Default, Clone)]
 pub struct PeerDB {
+    /// known peers, their state must be changed with `set_peer_state` to keep `state_counts` right
     pub peers: HashMap<PeerId, PeerInfo>,
+    /// number of peers in each state, updated at each change of state
+    state_counts: PeerStateCounts,
     /// Tested addresses used to avoid testing the same address too often. //TODO: Need to be pruned
     pub tested_addresses: HashMap<SocketAddr, MassaTime>,
     /// history of try connection to peers
     pub try_connect_history: HashMap<SocketAddr, ConnectionMetadata>,
     /// peers currently tested
     pub peers_in_test: HashSet<SocketAddr>,
+    /// offenses committed by peers, used to escalate their ban durations
+    pub offenses: HashMap<PeerId, PeerOffenses>,
+    /// period without offense after which the offense count of a peer is decremented, no decay if `None`
+    pub offense_decay_period: Option<MassaTime>,
+    /// file where the banned peers are persisted, no persistence if `None`
+    pub ban_list_path: Option<PathBuf>,
+    /// writes the ban list to `ban_list_path` out of the lock of the peer database
+    ban_list_writer: Option<BanListWriter>,
+    /// banned IP addresses and subnets with their ban time
+    pub banned_subnets: HashMap<IpNet, MassaTime>,
+    /// duration of IP and subnet bans, they never expire if `None`
+    pub ip_ban_duration: Option<MassaTime>,
+    /// reputation below which a peer is banned
+    pub reputation_ban_threshold: i32,
+    /// quarantined peers with their quarantine time
+    pub quarantined_peers: HashMap<PeerId, MassaTime>,
+    /// duration after which a quarantined peer is trusted again, quarantines never expire if `None`
+    pub quarantine_duration: Option<MassaTime>,
+    /// peers disconnected for a first offense with the time of the offense
+    pub pending_offenses: HashMap<PeerId, MassaTime>,
+    /// time during which a peer is banned if it offends again after its first offense, pending offenses never expire if `None`
+    pub first_offense_window: Option<MassaTime>,
+    /// peers that are never banned nor quarantined
+    pub ban_whitelist: HashSet<PeerId>,
+    /// ban duration for each ban reason
+    pub ban_durations: HashMap<BanReason, MassaTime>,
+    /// ban duration for the reasons without an entry in `ban_durations`, flat bans never expire if `None`
+    pub default_ban_duration: Option<MassaTime>,
+    /// maximum number of banned peers kept, the oldest bans are forgotten beyond it (no limit if 0)
+    pub max_banned_peers: usize,
+    /// maximum number of banned IP addresses and subnets kept, the oldest bans are forgotten beyond it (no limit if 0)
+    pub max_banned_subnets: usize,
+    /// base and maximum delays before trying again an address after failed connections, no backoff if `None`
+    pub connect_backoff: Option<(MassaTime, MassaTime)>,
+    /// time after which the peers we didn't hear of are forgotten, kept forever if `None`
+    pub peer_expiry: Option<MassaTime>,
+    /// picks the peers advertised to other peers
+    peer_selection_rng: SharedRng,
+}
+
+/// Ban of a peer as persisted in the ban list file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersistedBan {
+    pub reason: BanReason,
+    pub banned_at: MassaTime,
+    /// end of an escalated ban, `None` if the ban is only lifted manually or by the periodic unban of every peer
+    pub ban_end: Option<MassaTime>,
+    pub offense_count: u32,
+}
+
+impl PeerDB {
+    /// Create the peer database and reload the bans persisted in `ban_list_path` that are still running at `now`
+    pub fn new(config: &ProtocolConfig, now: MassaTime) -> Self {
+        let mut peer_db = PeerDB {
+            offense_decay_period: Some(config.offense_decay_period),
+            ban_list_path: config.ban_list_path.clone(),
+            ban_list_writer: config.ban_list_path.clone().map(BanListWriter::new),
+            ip_ban_duration: Some(config.unban_everyone_timer),
+            reputation_ban_threshold: config.reputation_ban_threshold,
+            quarantine_duration: Some(config.quarantine_duration),
+            first_offense_window: Some(config.first_offense_window),
+            ban_whitelist: config.ban_whitelist.clone(),
+            ban_durations: config.ban_durations.clone(),
+            default_ban_duration: Some(config.unban_everyone_timer),
+            max_banned_peers: config.max_banned_peers,
+            max_banned_subnets: config.max_banned_subnets,
+            connect_backoff: Some((config.connect_backoff_base, config.connect_backoff_max)),
+            peer_expiry: (config.peer_expiry.as_millis() > 0).then_some(config.peer_expiry),
+            peer_selection_rng: SharedRng::new(config.rng_seed),
+            ..Default::default()
+        };
+        peer_db.load_ban_list(config, now);
+        peer_db
+    }
+
+    /// Reload the persisted bans, skipping the ones that already expired.
+    /// A missing or corrupt file is logged and the node starts with an empty ban list.
+    fn load_ban_list(&mut self, config: &ProtocolConfig, now: MassaTime) {
+        let Some(path) = &self.ban_list_path else {
+            return;
+        };
+        if !path.exists() {
+            return;
+        }
+        let bans = match std::fs::read_to_string(path)
+            .map(|content| serde_json::from_str::<HashMap<PeerId, PersistedBan>>(&content))
+        {
+            Ok(Ok(bans)) => bans,
+            Ok(Err(err)) => {
+                warn!("Corrupt ban list file {:?}, ignoring it: {}", path, err);
+                return;
+            }
+            Err(err) => {
+                warn!("Could not read ban list file {:?}: {}", path, err);
+                return;
+            }
+        };
+        for (peer_id, ban) in bans {
+            if ban
+                .banned_at
+                .saturating_add(config.get_ban_duration(&ban.reason))
+                <= now
+                || ban.ban_end.map(|ban_end| ban_end <= now).unwrap_or(false)
+                || self.ban_whitelist.contains(&peer_id)
+            {
+                continue;
+            }
+            self.insert_peer(
+                peer_id,
+                PeerInfo {
+                    last_announce: None,
+                    state: PeerState::Banned,
+                    ban_reason: Some((ban.reason, ban.banned_at)),
+                    reputation: 0,
+                    features: None,
+                },
+            );
+            if ban.offense_count > 0 {
+                self.offenses.insert(
+                    peer_id,
+                    PeerOffenses {
+                        count: ban.offense_count,
+                        last_update: ban.banned_at,
+                        ban_end: ban.ban_end,
+                    },
+                );
+            }
+        }
+        self.evict_oldest_bans();
+        info!(
+            "Loaded {} banned peers from {:?}",
+            self.banned_count(),
+            path
+        );
+    }
+
+    /// Number of peers currently banned
+    pub fn banned_count(&self) -> usize {
+        self.state_counts.banned as usize
+    }
+
+    /// Forget a peer
+    fn remove_peer(&mut self, peer_id: &PeerId) -> Option<PeerInfo> {
+        let peer = self.peers.remove(peer_id)?;
+        decrement_count(state_count(&mut self.state_counts, &peer.state));
+        Some(peer)
+    }
+
+    /// Last time we heard of a peer: its last announcement or the last successful connection to or
+    /// test of one of its listeners
+    fn last_heard_of(&self, peer: &PeerInfo) -> Option<MassaTime> {
+        let announce = peer.last_announce.as_ref()?;
+        announce
+            .listeners
+            .keys()
+            .filter_map(|addr| self.try_connect_history.get(addr))
+            .flat_map(|metadata| [metadata.last_success, metadata.last_test_success])
+            .flatten()
+            .chain(std::iter::once(MassaTime::from_millis(announce.timestamp)))
+            .max()
+    }
+
+    /// End of the current ban of a peer, `None` if it is permanent
+    fn get_ban_end(&self, peer_id: &PeerId, peer: &PeerInfo) -> Option<MassaTime> {
+        if let Some(offenses) = self.offenses.get(peer_id) {
+            if offenses.ban_end.is_some() {
+                return offenses.ban_end;
+            }
+            if offenses.count as usize > ESCALATING_BAN_DURATIONS_MS.len() {
+                return None;
+            }
+        }
+        // bans without a known reason and date are lifted at the first check
+        let Some((reason, banned_at)) = peer.ban_reason else {
+            return Some(MassaTime::from_millis(0));
+        };
+        self.ban_durations
+            .get(&reason)
+            .copied()
+            .or(self.default_ban_duration)
+            .map(|duration| banned_at.saturating_add(duration))
+    }
+
+    /// Forget the peers banned the longest ago so that at most `max_banned_peers` remain
+    fn evict_oldest_bans(&mut self) {
+        if self.max_banned_peers == 0 {
+            return;
+        }
+        let mut banned: Vec<(MassaTime, PeerId)> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerState::Banned)
+            .map(|(peer_id, peer)| {
+                let banned_at = peer
+                    .ban_reason
+                    .map(|(_, banned_at)| banned_at)
+                    .unwrap_or_else(|| MassaTime::from_millis(0));
+                (banned_at, *peer_id)
+            })
+            .collect();
+        if banned.len() <= self.max_banned_peers {
+            return;
+        }
+        banned.sort_unstable_by_key(|(banned_at, _)| *banned_at);
+        let evicted_count = banned.len() - self.max_banned_peers;
+        for (_, peer_id) in banned.into_iter().take(evicted_count) {
+            self.remove_peer(&peer_id);
+            self.offenses.remove(&peer_id);
+            self.quarantined_peers.remove(&peer_id);
+            debug!("Evicted banned peer {:?}: too many banned peers", peer_id);
+        }
+    }
+
+    /// Forget the subnets banned the longest ago so that at most `max_banned_subnets` remain
+    fn evict_oldest_subnet_bans(&mut self) {
+        if self.max_banned_subnets == 0 || self.banned_subnets.len() <= self.max_banned_subnets {
+            return;
+        }
+        let mut banned: Vec<(MassaTime, IpNet)> = self
+            .banned_subnets
+            .iter()
+            .map(|(subnet, banned_at)| (*banned_at, *subnet))
+            .collect();
+        banned.sort_unstable_by_key(|(banned_at, _)| *banned_at);
+        let evicted_count = banned.len() - self.max_banned_subnets;
+        for (_, subnet) in banned.into_iter().take(evicted_count) {
+            self.banned_subnets.remove(&subnet);
+            debug!("Evicted banned subnet {}: too many banned subnets", subnet);
+        }
+    }
+
+    /// Write the banned peers to `ban_list_path` in the background
+    fn flush_ban_list(&self) {
+        if let (Some(writer), Some(content)) = (&self.ban_list_writer, self.serialize_ban_list()) {
+            writer.write(content);
+        }
+    }
+
+    /// Content of the ban list file, `None` if the bans aren't persisted
+    fn serialize_ban_list(&self) -> Option<String> {
+        let path = self.ban_list_path.as_ref()?;
+        let bans: HashMap<PeerId, PersistedBan> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerState::Banned)
+            .filter_map(|(peer_id, peer)| {
+                let (reason, banned_at) = peer.ban_reason?;
+                let offenses = self.offenses.get(peer_id);
+                Some((
+                    *peer_id,
+                    PersistedBan {
+                        reason,
+                        banned_at,
+                        ban_end: offenses.and_then(|offenses| offenses.ban_end),
+                        offense_count: offenses.map(|offenses| offenses.count).unwrap_or(0),
+                    },
+                ))
+            })
+            .collect();
+        match serde_json::to_string_pretty(&bans) {
+            Ok(content) => Some(content),
+            Err(err) => {
+                warn!("Could not serialize ban list file {:?}: {}", path, err);
+                None
+            }
+        }
+    }
+}
+
+/// Count of the peers in `state` among `counts`
+fn state_count<'a>(counts: &'a mut PeerStateCounts, state: &PeerState) -> &'a mut u64 {
+    match state {
+        PeerState::Banned => &mut counts.banned,
+        PeerState::InHandshake => &mut counts.in_handshake,
+        PeerState::HandshakeFailed => &mut counts.handshake_failed,
+        PeerState::Trusted => &mut counts.trusted,
+        PeerState::Quarantined => &mut counts.quarantined,
+        PeerState::WrongNetwork => &mut counts.wrong_network,
+    }
+}
+
+/// Take a peer out of a count, which must hold it
+fn decrement_count(count: &mut u64) {
+    debug_assert!(*count > 0, "peer state count underflow");
+    *count = count.saturating_sub(1);
+}
+
+/// Change the state of `peer`, moving it from a count of `counts` to another
+fn change_state(counts: &mut PeerStateCounts, peer: &mut PeerInfo, state: PeerState) {
+    decrement_count(state_count(counts, &peer.state));
+    *state_count(counts, &state) += 1;
+    peer.state = state;
 }
 
 pub type SharedPeerDB = Arc<RwLock<dyn PeerDBTrait>>;
 
+/// Peers that advertised in the handshake that they can receive compressed messages
+pub type SharedCompressionPeers = Arc<RwLock<HashSet<PeerId>>>;
+
+/// Peers that advertised in the handshake that they can receive checksummed frames
+pub type SharedChecksumPeers = Arc<RwLock<HashSet<PeerId>>>;
+
+/// Wire format negotiated in the handshake with the peers, the others are sent `V1` frames
+pub type SharedFormatVersions = Arc<RwLock<HashMap<PeerId, MessageFormatVersion>>>;
+
 pub type PeerMessageTuple = (PeerId, Vec<u8>);
 
+/// Bytes exchanged with the connected peers, and with all the peers since the start
+#[derive(Debug, Default)]
+pub struct PeerTraffic {
+    /// counters of the connected peers, reset when they disconnect
+    pub peers: HashMap<PeerId, PeerStats>,
+    /// counters of all the peers, never reset
+    pub lifetime: PeerStats,
+    /// time of the last message received from each connected peer
+    pub last_received: HashMap<PeerId, Instant>,
+}
+
+impl PeerTraffic {
+    pub fn record_sent(&mut self, peer_id: &PeerId, category: MessageCategory, bytes: u64) {
+        self.peers
+            .entry(*peer_id)
+            .or_default()
+            .record_sent(category, bytes);
+        self.lifetime.record_sent(category, bytes);
+    }
+
+    pub fn record_received(&mut self, peer_id: &PeerId, category: MessageCategory, bytes: u64) {
+        self.peers
+            .entry(*peer_id)
+            .or_default()
+            .record_received(category, bytes);
+        self.lifetime.record_received(category, bytes);
+        self.last_received.insert(*peer_id, Instant::now());
+    }
+
+    pub fn record_corrupted(&mut self, peer_id: &PeerId) {
+        self.peers.entry(*peer_id).or_default().record_corrupted();
+        self.lifetime.record_corrupted();
+    }
+
+    /// Reset the counters of a disconnected peer, its bytes stay in the lifetime counters
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+        self.last_received.remove(peer_id);
+    }
+}
+
+pub type SharedPeerTraffic = Arc<RwLock<PeerTraffic>>;
+
 #[derive(Clone, Debug)]
 pub struct PeerInfo {
     pub last_announce: Option<Announcement>,
     pub state: PeerState,
+    /// reason and time of the current ban
+    pub ban_reason: Option<(BanReason, MassaTime)>,
+    /// rewards useful behavior and penalizes minor offenses, the peer is banned when it drops below `reputation_ban_threshold`
+    pub reputation: i32,
+    /// what was negotiated during the last successful handshake with the peer
+    pub features: Option<NegotiatedFeatures>,
+}
+
+/// Result of the handshake with a peer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NegotiatedFeatures {
+    /// node version announced by the peer
+    pub version: Version,
+    /// protocol version spoken by the peer, 0 for the peers predating the version negotiation
+    pub protocol_version: u32,
+    /// capability flags advertised by the peer, 0 if it doesn't advertise any
+    pub capabilities: u8,
+    /// the messages sent to the peer are compressed
+    pub compression: bool,
+    /// the frames sent to the peer are checksummed
+    pub frame_checksum: bool,
+    /// wire format of the messages sent to the peer
+    pub format_version: MessageFormatVersion,
+}
+
+/// How `get_weighted_peers_to_send` favors the peers. The weight of a peer is
+/// `base_weight + reputation_weight * reputation + recency_weight * recency`, where the recency goes
+/// from 1 for a peer that announced itself just now to 0 for one that did `recency_window` ago or more
+#[derive(Clone, Copy, Debug)]
+pub struct WeightingPolicy {
+    /// weight of every peer, so that the new peers are still selected
+    pub base_weight: f64,
+    /// weight of each reputation point, the negative reputations count as 0
+    pub reputation_weight: f64,
+    /// weight of a peer that announced itself just now
+    pub recency_weight: f64,
+    /// age of the announcements from which the peers get no recency weight
+    pub recency_window: MassaTime,
+}
+
+impl Default for WeightingPolicy {
+    fn default() -> Self {
+        WeightingPolicy {
+            base_weight: 1.0,
+            reputation_weight: 0.1,
+            recency_weight: 1.0,
+            recency_window: MassaTime::from_millis(60 * 60 * 1000),
+        }
+    }
+}
+
+impl WeightingPolicy {
+    /// Weight of `peer` at `now` (in milliseconds), always positive
+    fn weight(&self, peer: &PeerInfo, now: u64) -> f64 {
+        let recency = match (&peer.last_announce, self.recency_window.as_millis()) {
+            (Some(announce), window) if window > 0 => {
+                let age = now.saturating_sub(announce.timestamp).min(window);
+                1.0 - age as f64 / window as f64
+            }
+            _ => 0.0,
+        };
+        let weight = self.base_weight
+            + self.reputation_weight * peer.reputation.max(0) as f64
+            + self.recency_weight * recency;
+        weight.max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Listeners of `peer` to advertise to the other peers, `None` if the peer mustn't be advertised
+fn advertised_listeners(
+    peer: &PeerInfo,
+    min_time: u64,
+) -> Option<HashMap<SocketAddr, TransportType>> {
+    // don't advertise suspicious peers
+    if peer.state == PeerState::Quarantined {
+        return None;
+    }
+    // skip old peers
+    let last_announce = peer.last_announce.as_ref()?;
+    if last_announce.timestamp < min_time || last_announce.listeners.is_empty() {
+        return None;
+    }
+    Some(last_announce.listeners.clone())
 }
 
 #[warn(dead_code)]
@@ -174,15 +659,79 @@ pub enum PeerState {
     InHandshake,
     HandshakeFailed,
     Trusted,
+    /// suspicious peer that we keep connected to but don't relay data to nor ask blocks from
+    Quarantined,
+    /// peer of another network (genesis or thread count), not banned but never connected to
+    WrongNetwork,
+}
+
+/// Number of escalation steps added to the offense count of a peer
+fn severity_weight(severity: BanSeverity) -> u32 {
+    match severity {
+        BanSeverity::Minor => 1,
+        BanSeverity::Major => 2,
+        BanSeverity::Critical => ESCALATING_BAN_DURATIONS_MS.len() as u32 + 1,
+    }
+}
+
+/// Offenses history of a peer
+#[derive(Clone, Debug)]
+pub struct PeerOffenses {
+    /// number of offenses, decremented every `offense_decay_period` without offense
+    pub count: u32,
+    /// last time the count was incremented or decayed
+    pub last_update: MassaTime,
+    /// end of the current ban, `None` if the peer isn't banned or is banned permanently
+    pub ban_end: Option<MassaTime>,
+}
+
+impl PeerOffenses {
+    /// Decrement the offense count by one for each full `decay_period` elapsed since the last update
+    fn decay(&mut self, now: MassaTime, decay_period: Option<MassaTime>) {
+        let Some(decay_period) = decay_period.filter(|period| period.as_millis() > 0) else {
+            return;
+        };
+        let Ok(steps) = now
+            .saturating_sub(self.last_update)
+            .checked_div_time(decay_period)
+        else {
+            return;
+        };
+        if steps == 0 {
+            return;
+        }
+        self.count = self
+            .count
+            .saturating_sub(steps.try_into().unwrap_or(u32::MAX));
+        self.last_update = self
+            .last_update
+            .saturating_add(decay_period.saturating_mul(steps));
+    }
 }
 
 #[derive(Clone)]
 pub enum PeerManagementCmd {
-    Ban(Vec<PeerId>),
+    Ban(Vec<PeerId>, BanReason),
+    BanWithSeverity(Vec<PeerId>, BanReason, BanSeverity),
+    AdjustReputation(PeerId, i32),
     Unban(Vec<PeerId>),
+    /// Close the connection to a peer without changing its state,
+    /// telling it we disconnect first if `graceful`
+    Disconnect {
+        peer_id: PeerId,
+        graceful: bool,
+    },
+    /// Settings changed at runtime
+    UpdateConfig(ProtocolConfigUpdate),
     GetBootstrapPeers {
         responder: MassaSender<BootstrapPeers>,
     },
+    /// Tell the connected peers we leave, then close the connections
+    Drain {
+        reason: String,
+        flush_delay: Duration,
+        responder: MassaSender<()>,
+    },
     Stop,
 }
 
@@ -192,20 +741,174 @@ pub struct PeerManagementChannel {
 }
 
 impl PeerDBTrait for PeerDB {
-    fn ban_peer(&mut self, peer_id: &PeerId) {
+    fn ban_peer(&mut self, peer_id: &PeerId, reason: BanReason, now: MassaTime) {
+        if self.ban_whitelist.contains(peer_id) {
+            warn!("Not banning whitelisted peer {:?} ({})", peer_id, reason);
+            return;
+        }
         if let Some(peer) = self.peers.get_mut(peer_id) {
-            peer.state = PeerState::Banned;
-            info!("Banned peer: {:?}", peer_id);
+            change_state(&mut self.state_counts, peer, PeerState::Banned);
+            peer.ban_reason = Some((reason, now));
+            self.quarantined_peers.remove(peer_id);
+            // a flat ban doesn't expire on its own
+            if let Some(offenses) = self.offenses.get_mut(peer_id) {
+                offenses.ban_end = None;
+            }
+            info!("Banned peer: {:?} ({})", peer_id, reason);
+            self.evict_oldest_bans();
+            self.flush_ban_list();
         } else {
             info!("Tried to ban unknown peer: {:?}", peer_id);
         };
     }
 
+    /// Ban a peer for a duration escalating with the number of offenses it recently committed
+    fn ban_peer_with_severity(
+        &mut self,
+        peer_id: &PeerId,
+        reason: BanReason,
+        severity: BanSeverity,
+        now: MassaTime,
+    ) {
+        if self.ban_whitelist.contains(peer_id) {
+            warn!(
+                "Not banning whitelisted peer {:?} ({}, {:?} offense)",
+                peer_id, reason, severity
+            );
+            return;
+        }
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            info!("Tried to ban unknown peer: {:?}", peer_id);
+            return;
+        };
+        let offenses = self.offenses.entry(*peer_id).or_insert(PeerOffenses {
+            count: 0,
+            last_update: now,
+            ban_end: None,
+        });
+        offenses.decay(now, self.offense_decay_period);
+        // the clean period before the next decay starts at the latest offense
+        offenses.last_update = now;
+        offenses.count = offenses.count.saturating_add(severity_weight(severity));
+        offenses.ban_end = ESCALATING_BAN_DURATIONS_MS
+            .get(offenses.count as usize - 1)
+            .map(|duration| now.saturating_add(MassaTime::from_millis(*duration)));
+        change_state(&mut self.state_counts, peer, PeerState::Banned);
+        peer.ban_reason = Some((reason, now));
+        self.quarantined_peers.remove(peer_id);
+        match offenses.ban_end {
+            Some(ban_end) => info!(
+                "Banned peer {:?} until {} ({}, {:?} offense, count {})",
+                peer_id,
+                ban_end.format_instant(),
+                reason,
+                severity,
+                offenses.count
+            ),
+            None => info!(
+                "Banned peer {:?} permanently ({}, {:?} offense, count {})",
+                peer_id, reason, severity, offenses.count
+            ),
+        }
+        self.evict_oldest_bans();
+        self.flush_ban_list();
+    }
+
+    /// Unban the peers whose escalated ban has expired, release the peers whose quarantine is over
+    /// and forget the fully decayed offenses
+    fn unban_expired_peers(&mut self, now: MassaTime) -> Vec<PeerId> {
+        if let Some(quarantine_duration) = self.quarantine_duration {
+            let peers = &mut self.peers;
+            let state_counts = &mut self.state_counts;
+            self.quarantined_peers.retain(|peer_id, quarantined_at| {
+                if quarantined_at.saturating_add(quarantine_duration) > now {
+                    return true;
+                }
+                if let Some(peer) = peers.get_mut(peer_id) {
+                    if peer.state == PeerState::Quarantined {
+                        change_state(state_counts, peer, PeerState::Trusted);
+                        info!("Released peer {:?} from quarantine", peer_id);
+                    }
+                }
+                false
+            });
+        }
+        if let Some(ip_ban_duration) = self.ip_ban_duration {
+            self.banned_subnets.retain(|subnet, banned_at| {
+                let keep = banned_at.saturating_add(ip_ban_duration) > now;
+                if !keep {
+                    info!("Unbanned subnet: {}", subnet);
+                }
+                keep
+            });
+        }
+        let mut expired = Vec::new();
+        for (peer_id, offenses) in self.offenses.iter_mut() {
+            offenses.decay(now, self.offense_decay_period);
+            if let Some(ban_end) = offenses.ban_end {
+                if ban_end <= now {
+                    offenses.ban_end = None;
+                    expired.push(*peer_id);
+                }
+            }
+        }
+        self.offenses
+            .retain(|_, offenses| offenses.count > 0 || offenses.ban_end.is_some());
+        let unbanned: Vec<PeerId> = expired
+            .into_iter()
+            .filter(|peer_id| {
+                self.peers
+                    .get(peer_id)
+                    .map(|peer| peer.state == PeerState::Banned)
+                    .unwrap_or(false)
+            })
+            .collect();
+        for peer_id in &unbanned {
+            self.unban_peer(peer_id);
+        }
+        unbanned
+    }
+
+    /// Escalated bans are left to `unban_expired_peers` and permanent bans are never lifted
+    fn tick_unban(&mut self, now: MassaTime) -> Vec<PeerId> {
+        let unbanned: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerState::Banned)
+            .filter(|(peer_id, _)| {
+                self.offenses
+                    .get(peer_id)
+                    .map(|offenses| offenses.ban_end.is_none())
+                    .unwrap_or(true)
+            })
+            .filter(|(peer_id, peer)| {
+                self.get_ban_end(peer_id, peer)
+                    .map(|ban_end| ban_end <= now)
+                    .unwrap_or(false)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in &unbanned {
+            self.unban_peer(peer_id);
+            // the peer stayed away during all its ban, it doesn't need to be tested again
+            self.set_peer_state(peer_id, PeerState::Trusted);
+        }
+        unbanned
+    }
+
     fn unban_peer(&mut self, peer_id: &PeerId) {
         if let Some(peer) = self.peers.get_mut(peer_id) {
             // We set the state to HandshakeFailed to force the peer to be tested again
-            peer.state = PeerState::HandshakeFailed;
+            change_state(&mut self.state_counts, peer, PeerState::HandshakeFailed);
+            peer.ban_reason = None;
+            peer.reputation = 0;
+            self.quarantined_peers.remove(peer_id);
+            // the offense count is kept so that a repeat offender is banned longer
+            if let Some(offenses) = self.offenses.get_mut(peer_id) {
+                offenses.ban_end = None;
+            }
             info!("Unbanned peer: {:?}", peer_id);
+            self.flush_ban_list();
         } else {
             info!("Tried to unban unknown peer: {:?}", peer_id);
         };
@@ -249,32 +952,235 @@ impl PeerDBTrait for PeerDB {
         let min_time = now - THREE_DAYS_MS;
 
         let mut keys = self.peers.keys().cloned().collect::<Vec<_>>();
-        let mut rng = rand::thread_rng();
-        keys.shuffle(&mut rng);
+        // sorted first so that a seeded generator always gives the same selection
+        keys.sort_unstable();
+        keys.shuffle(&mut *self.peer_selection_rng.lock());
+        // prefer peers with a higher reputation, the shuffle breaks ties randomly
+        keys.sort_by_key(|key| std::cmp::Reverse(self.peers[key].reputation));
 
-        let mut result = Vec::new();
+        keys.into_iter()
+            .filter_map(|key| {
+                advertised_listeners(&self.peers[&key], min_time).map(|listeners| (key, listeners))
+            })
+            .take(nb_peers)
+            .collect()
+    }
 
-        for key in keys {
-            if result.len() >= nb_peers {
-                break;
-            }
-            if let Some(peer) = self.peers.get(&key) {
-                // skip old peers
-                if let Some(last_announce) = &peer.last_announce {
-                    if last_announce.timestamp < min_time {
-                        continue;
-                    }
-                    let listeners: HashMap<SocketAddr, TransportType> =
-                        last_announce.listeners.clone().into_iter().collect();
-                    if listeners.is_empty() {
-                        continue;
-                    }
-                    result.push((key, listeners));
-                }
+    /// Same as `get_rand_peers_to_send`, each peer being selected with a probability
+    /// increasing with its weight in `policy`
+    fn get_weighted_peers_to_send(
+        &self,
+        nb_peers: usize,
+        policy: WeightingPolicy,
+    ) -> Vec<(PeerId, HashMap<SocketAddr, TransportType>)> {
+        let now = MassaTime::now().as_millis();
+        let min_time = now - THREE_DAYS_MS;
+
+        let mut candidates: Vec<_> = self
+            .peers
+            .iter()
+            .filter_map(|(peer_id, peer)| {
+                advertised_listeners(peer, min_time)
+                    .map(|listeners| (*peer_id, listeners, policy.weight(peer, now)))
+            })
+            .collect();
+        // sorted first so that a seeded generator always gives the same selection
+        candidates.sort_unstable_by_key(|(peer_id, _, _)| *peer_id);
+
+        // weighted sampling without replacement: each peer gets a random key `u^(1/weight)`,
+        // the peers with the highest keys are selected
+        let mut rng = self.peer_selection_rng.lock();
+        let mut keyed: Vec<_> = candidates
+            .into_iter()
+            .map(|(peer_id, listeners, weight)| {
+                let key = rng.gen::<f64>().powf(1.0 / weight);
+                (key, peer_id, listeners)
+            })
+            .collect();
+        keyed.sort_by(|(key_a, _, _), (key_b, _, _)| key_b.total_cmp(key_a));
+        keyed
+            .into_iter()
+            .take(nb_peers)
+            .map(|(_, peer_id, listeners)| (peer_id, listeners))
+            .collect()
+    }
+
+    fn ban_ip(&mut self, ip: IpAddr, now: MassaTime) {
+        self.ban_subnet(IpNet::from(to_canonical(ip)), now);
+    }
+
+    fn ban_subnet(&mut self, subnet: IpNet, now: MassaTime) {
+        // never ban ourselves or local test setups
+        if subnet.contains(&IpAddr::from([127, 0, 0, 1]))
+            || subnet.contains(&IpAddr::from(std::net::Ipv6Addr::LOCALHOST))
+        {
+            info!("Refused to ban loopback subnet: {}", subnet);
+            return;
+        }
+        self.banned_subnets.insert(subnet, now);
+        info!("Banned subnet: {}", subnet);
+        self.evict_oldest_subnet_bans();
+    }
+
+    fn is_ip_banned(&self, ip: &IpAddr) -> bool {
+        let ip = to_canonical(*ip);
+        self.banned_subnets
+            .keys()
+            .any(|subnet| subnet.contains(&ip))
+    }
+
+    fn save_ban_list(&self) {
+        if let (Some(writer), Some(content)) = (&self.ban_list_writer, self.serialize_ban_list()) {
+            writer.write_and_wait(content);
+        }
+    }
+
+    fn update_ban_settings(&mut self, config: &ProtocolConfig) {
+        self.ban_whitelist = config.ban_whitelist.clone();
+        self.ban_durations = config.ban_durations.clone();
+        self.default_ban_duration = Some(config.unban_everyone_timer);
+        self.ip_ban_duration = Some(config.unban_everyone_timer);
+        self.first_offense_window = Some(config.first_offense_window);
+    }
+
+    fn adjust_reputation(&mut self, peer_id: &PeerId, delta: i32) -> bool {
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            return false;
+        };
+        peer.reputation = peer.reputation.saturating_add(delta).min(MAX_REPUTATION);
+        if delta < 0 {
+            debug!(
+                "Reputation of peer {:?} lowered to {}",
+                peer_id, peer.reputation
+            );
+        }
+        peer.state != PeerState::Banned
+            && peer.reputation < self.reputation_ban_threshold
+            && !self.ban_whitelist.contains(peer_id)
+    }
+
+    fn quarantine_peer(&mut self, peer_id: &PeerId, now: MassaTime) -> bool {
+        if self.ban_whitelist.contains(peer_id) {
+            warn!("Not quarantining whitelisted peer {:?}", peer_id);
+            return true;
+        }
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            info!("Tried to quarantine unknown peer: {:?}", peer_id);
+            return true;
+        };
+        match peer.state {
+            PeerState::Quarantined => false,
+            PeerState::Banned => true,
+            _ => {
+                change_state(&mut self.state_counts, peer, PeerState::Quarantined);
+                self.quarantined_peers.insert(*peer_id, now);
+                info!("Quarantined peer: {:?}", peer_id);
+                true
             }
         }
+    }
+
+    fn mark_first_offense(&mut self, peer_id: &PeerId, now: MassaTime) -> bool {
+        if let Some(first_offense_window) = self.first_offense_window {
+            self.pending_offenses
+                .retain(|_, offended_at| offended_at.saturating_add(first_offense_window) > now);
+        }
+        if self.pending_offenses.remove(peer_id).is_some() {
+            return false;
+        }
+        self.pending_offenses.insert(*peer_id, now);
+        true
+    }
+
+    fn prune_expired_peers(&mut self, now: MassaTime, connected: &HashSet<PeerId>) -> Vec<PeerId> {
+        let Some(peer_expiry) = self.peer_expiry else {
+            return Vec::new();
+        };
+        let min_time = now.saturating_sub(peer_expiry);
+        let expired: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|(peer_id, peer)| {
+                !connected.contains(peer_id)
+                    && !matches!(peer.state, PeerState::Banned | PeerState::Quarantined)
+                    && self
+                        .last_heard_of(peer)
+                        .map_or(true, |last_heard_of| last_heard_of < min_time)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in &expired {
+            self.remove_peer(peer_id);
+        }
+
+        // the addresses of the remaining peers are kept, the others are forgotten once they expire
+        // so that `get_oldest_peer` doesn't keep handing out addresses of long gone peers
+        let known_listeners: HashSet<SocketAddr> = self
+            .peers
+            .values()
+            .filter_map(|peer| peer.last_announce.as_ref())
+            .flat_map(|announce| announce.listeners.keys().copied())
+            .collect();
+        let peers_in_test = &self.peers_in_test;
+        self.tested_addresses.retain(|addr, tested_at| {
+            *tested_at >= min_time || known_listeners.contains(addr) || peers_in_test.contains(addr)
+        });
+        self.try_connect_history.retain(|addr, metadata| {
+            known_listeners.contains(addr)
+                || [
+                    metadata.last_success,
+                    metadata.last_failure,
+                    metadata.last_try_connect,
+                    metadata.last_test_success,
+                    metadata.last_test_failure,
+                ]
+                .into_iter()
+                .flatten()
+                .any(|time| time >= min_time)
+        });
+        expired
+    }
 
-        result
+    fn get_quarantined_peers(&self) -> HashSet<PeerId> {
+        self.quarantined_peers.keys().copied().collect()
+    }
+
+    fn get_ban_details(&self, peer_id: &PeerId) -> Option<(u32, Option<MassaTime>)> {
+        let peer = self
+            .peers
+            .get(peer_id)
+            .filter(|peer| peer.state == PeerState::Banned)?;
+        let offense_count = self
+            .offenses
+            .get(peer_id)
+            .map(|offenses| offenses.count)
+            .unwrap_or(0);
+        Some((offense_count, self.get_ban_end(peer_id, peer)))
+    }
+
+    fn get_ban_reason(&self, peer_id: &PeerId) -> Option<(BanReason, MassaTime)> {
+        self.peers
+            .get(peer_id)
+            .filter(|peer| peer.state == PeerState::Banned)
+            .and_then(|peer| peer.ban_reason)
+    }
+
+    fn get_banned_peers(&self, now: MassaTime) -> Vec<BannedPeerInfo> {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerState::Banned)
+            .filter_map(|(peer_id, peer)| {
+                let (reason, banned_at) = peer.ban_reason?;
+                Some(BannedPeerInfo {
+                    peer_id: *peer_id,
+                    reason,
+                    banned_at,
+                    remaining: self
+                        .get_ban_end(peer_id, peer)
+                        .map(|ban_end| ban_end.saturating_sub(now)),
+                })
+            })
+            .collect()
     }
 
     fn clone_box(&self) -> Box<dyn PeerDBTrait> {
@@ -282,10 +1188,7 @@ impl PeerDBTrait for PeerDB {
     }
 
     fn get_banned_peer_count(&self) -> u64 {
-        self.peers
-            .values()
-            .filter(|peer| peer.state == PeerState::Banned)
-            .count() as u64
+        self.banned_count() as u64
     }
 
     fn get_known_peer_count(&self) -> u64 {
@@ -296,8 +1199,58 @@ impl PeerDBTrait for PeerDB {
         &self.peers
     }
 
-    fn get_peers_mut(&mut self) -> &mut HashMap<PeerId, PeerInfo> {
-        &mut self.peers
+    fn get_peer_announce(&self, peer_id: &PeerId) -> Option<Announcement> {
+        self.peers.get(peer_id)?.last_announce.clone()
+    }
+
+    fn get_peer_features(&self, peer_id: &PeerId) -> Option<NegotiatedFeatures> {
+        self.peers.get(peer_id)?.features.clone()
+    }
+
+    fn get_all_announces(&self) -> HashMap<PeerId, Announcement> {
+        self.peers
+            .iter()
+            .filter_map(|(peer_id, peer)| Some((*peer_id, peer.last_announce.clone()?)))
+            .collect()
+    }
+
+    fn set_peer_announcement(&mut self, peer_id: &PeerId, announcement: Announcement) -> bool {
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            return false;
+        };
+        peer.last_announce = Some(announcement);
+        true
+    }
+
+    fn set_peer_features(
+        &mut self,
+        peer_id: &PeerId,
+        features: Option<NegotiatedFeatures>,
+    ) -> bool {
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            return false;
+        };
+        peer.features = features;
+        true
+    }
+
+    fn set_peer_state(&mut self, peer_id: &PeerId, state: PeerState) -> bool {
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            return false;
+        };
+        change_state(&mut self.state_counts, peer, state);
+        true
+    }
+
+    fn insert_peer(&mut self, peer_id: PeerId, peer: PeerInfo) {
+        *state_count(&mut self.state_counts, &peer.state) += 1;
+        if let Some(previous) = self.peers.insert(peer_id, peer) {
+            decrement_count(state_count(&mut self.state_counts, &previous.state));
+        }
+    }
+
+    fn get_peer_state_counts(&self) -> PeerStateCounts {
+        self.state_counts
     }
 
     fn get_connection_metadata_or_default(&self, addr: &SocketAddr) -> ConnectionMetadata {
@@ -307,29 +1260,39 @@ impl PeerDBTrait for PeerDB {
             .unwrap_or(ConnectionMetadata::default())
     }
 
-    fn set_try_connect_success_or_insert(&mut self, addr: &SocketAddr) {
+    fn set_try_connect_success_or_insert(&mut self, addr: &SocketAddr, now: MassaTime) {
         self.try_connect_history
             .entry(*addr)
             .or_default()
-            .try_connect();
+            .try_connect(now);
     }
 
-    fn set_try_connect_failure_or_insert(&mut self, addr: &SocketAddr) {
-        self.try_connect_history.entry(*addr).or_default().failure();
+    fn set_try_connect_failure_or_insert(&mut self, addr: &SocketAddr, now: MassaTime) {
+        self.try_connect_history
+            .entry(*addr)
+            .or_default()
+            .failure(self.connect_backoff, now);
+    }
+
+    fn set_handshake_success_or_insert(&mut self, addr: &SocketAddr, now: MassaTime) {
+        self.try_connect_history
+            .entry(*addr)
+            .or_default()
+            .success(now);
     }
 
-    fn set_try_connect_test_success_or_insert(&mut self, addr: &SocketAddr) {
+    fn set_try_connect_test_success_or_insert(&mut self, addr: &SocketAddr, now: MassaTime) {
         self.try_connect_history
             .entry(*addr)
             .or_default()
-            .test_success();
+            .test_success(now);
     }
 
-    fn set_try_connect_test_failure_or_insert(&mut self, addr: &SocketAddr) {
+    fn set_try_connect_test_failure_or_insert(&mut self, addr: &SocketAddr, now: MassaTime) {
         self.try_connect_history
             .entry(*addr)
             .or_default()
-            .test_failure();
+            .test_failure(now);
     }
 
     fn get_peers_in_test(&self) -> &HashSet<SocketAddr> {
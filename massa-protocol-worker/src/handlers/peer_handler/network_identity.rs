@@ -0,0 +1,119 @@
+use massa_hash::{Hash, HashDeserializer, HashSerializer};
+use massa_protocol_exports::ProtocolConfig;
+use massa_serialization::{Deserializer, SerializeError, Serializer};
+use nom::{
+    error::{context, ContextError, ParseError},
+    sequence::tuple,
+    IResult, Parser,
+};
+
+/// Network a node is on, the peers of another network are incompatible with us
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkIdentity {
+    /// identifies the genesis of the network
+    pub genesis_hash: Hash,
+    /// running threads count
+    pub thread_count: u8,
+}
+
+impl NetworkIdentity {
+    pub fn from_config(config: &ProtocolConfig) -> Self {
+        NetworkIdentity {
+            genesis_hash: config.genesis_hash,
+            thread_count: config.thread_count,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NetworkIdentitySerializer {
+    hash_serializer: HashSerializer,
+}
+
+impl NetworkIdentitySerializer {
+    pub fn new() -> Self {
+        Self {
+            hash_serializer: HashSerializer::new(),
+        }
+    }
+}
+
+impl Serializer<NetworkIdentity> for NetworkIdentitySerializer {
+    fn serialize(
+        &self,
+        value: &NetworkIdentity,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError> {
+        self.hash_serializer
+            .serialize(&value.genesis_hash, buffer)?;
+        buffer.push(value.thread_count);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct NetworkIdentityDeserializer {
+    hash_deserializer: HashDeserializer,
+}
+
+impl NetworkIdentityDeserializer {
+    pub fn new() -> Self {
+        Self {
+            hash_deserializer: HashDeserializer::new(),
+        }
+    }
+}
+
+impl Deserializer<NetworkIdentity> for NetworkIdentityDeserializer {
+    fn deserialize<'a, E: ParseError<&'a [u8]> + ContextError<&'a [u8]>>(
+        &self,
+        buffer: &'a [u8],
+    ) -> IResult<&'a [u8], NetworkIdentity, E> {
+        context(
+            "Failed network identity deserialization",
+            tuple((
+                context("Failed genesis hash deserialization", |buffer| {
+                    self.hash_deserializer.deserialize(buffer)
+                }),
+                context("Failed thread count deserialization", |buffer| {
+                    nom::number::complete::be_u8(buffer)
+                }),
+            )),
+        )
+        .map(|(genesis_hash, thread_count)| NetworkIdentity {
+            genesis_hash,
+            thread_count,
+        })
+        .parse(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use massa_hash::Hash;
+    use massa_serialization::{DeserializeError, Deserializer, Serializer};
+
+    use super::{NetworkIdentity, NetworkIdentityDeserializer, NetworkIdentitySerializer};
+
+    #[test]
+    fn test_network_identity_serialization() {
+        let identity = NetworkIdentity {
+            genesis_hash: Hash::compute_from(b"genesis"),
+            thread_count: 32,
+        };
+        let mut bytes = Vec::new();
+        NetworkIdentitySerializer::new()
+            .serialize(&identity, &mut bytes)
+            .unwrap();
+        bytes.push(7);
+        let (rest, deserialized) = NetworkIdentityDeserializer::new()
+            .deserialize::<DeserializeError>(&bytes)
+            .unwrap();
+        assert_eq!(deserialized, identity);
+        assert_eq!(rest, [7]);
+        // a truncated identity is refused
+        assert!(NetworkIdentityDeserializer::new()
+            .deserialize::<DeserializeError>(&bytes[..32])
+            .is_err());
+    }
+}
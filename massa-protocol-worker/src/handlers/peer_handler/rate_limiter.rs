@@ -0,0 +1,286 @@
+//! Per-peer rate limiting of the incoming messages.
+//!
+//! Each peer gets a sliding-window counter per message category. Messages above the configured
+//! rate are dropped, and a peer sending at more than `RATE_LIMIT_BAN_FACTOR` times the rate is banned.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use massa_channel::sender::MassaSender;
+use massa_protocol_exports::{BanReason, PeerId, ProtocolConfig};
+use parking_lot::{Mutex, RwLock};
+use tracing::{debug, warn};
+
+use crate::messages::MessageTypeId;
+
+use super::models::{BanSeverity, PeerManagementCmd};
+
+/// Length of the rate counting window
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// A peer exceeding its rate limit by this factor is banned instead of only throttled
+const RATE_LIMIT_BAN_FACTOR: u64 = 2;
+/// Interval between two cleanups of the counters of inactive peers
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Sliding-window approximation: the count of the previous window is weighted
+/// by the part of it that still overlaps the last `RATE_WINDOW`.
+struct RateWindow {
+    start: Instant,
+    previous_count: u64,
+    current_count: u64,
+}
+
+impl RateWindow {
+    fn new(now: Instant) -> Self {
+        RateWindow {
+            start: now,
+            previous_count: 0,
+            current_count: 0,
+        }
+    }
+
+    /// Count a new message and return the estimated number of messages received in the last `RATE_WINDOW`
+    fn record(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= RATE_WINDOW * 2 {
+            *self = RateWindow::new(now);
+        } else if elapsed >= RATE_WINDOW {
+            self.previous_count = self.current_count;
+            self.current_count = 0;
+            self.start += RATE_WINDOW;
+        }
+        self.current_count = self.current_count.saturating_add(1);
+        let remaining = RATE_WINDOW.saturating_sub(now.saturating_duration_since(self.start));
+        let previous_weighted =
+            (self.previous_count as u128 * remaining.as_millis() / RATE_WINDOW.as_millis()) as u64;
+        self.current_count.saturating_add(previous_weighted)
+    }
+}
+
+/// Outcome of the rate check of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitVerdict {
+    /// the message can be processed
+    Accept,
+    /// the message is dropped
+    Throttle,
+    /// the message is dropped and the peer must be banned
+    Ban,
+}
+
+struct RateCounters {
+    windows: HashMap<(PeerId, MessageTypeId), RateWindow>,
+    last_prune: Instant,
+}
+
+fn limits_from_config(config: &ProtocolConfig) -> HashMap<MessageTypeId, u64> {
+    HashMap::from([
+        (MessageTypeId::Block, config.max_block_messages_per_sec),
+        (
+            MessageTypeId::Endorsement,
+            config.max_endorsement_messages_per_sec,
+        ),
+        (
+            MessageTypeId::Operation,
+            config.max_operation_messages_per_sec,
+        ),
+        (
+            MessageTypeId::PeerManagement,
+            config.max_peer_management_messages_per_sec,
+        ),
+    ])
+}
+
+/// Rate limiter shared by all the connections
+#[derive(Clone)]
+pub struct MessageRateLimiter {
+    /// maximum number of messages per second for each category, no limit if 0
+    limits: Arc<RwLock<HashMap<MessageTypeId, u64>>>,
+    counters: Arc<Mutex<RateCounters>>,
+    peer_cmd_sender: MassaSender<PeerManagementCmd>,
+}
+
+impl MessageRateLimiter {
+    pub fn new(config: &ProtocolConfig, peer_cmd_sender: MassaSender<PeerManagementCmd>) -> Self {
+        MessageRateLimiter {
+            limits: Arc::new(RwLock::new(limits_from_config(config))),
+            counters: Arc::new(Mutex::new(RateCounters {
+                windows: HashMap::new(),
+                last_prune: Instant::now(),
+            })),
+            peer_cmd_sender,
+        }
+    }
+
+    /// Apply the limits of `config` to all the connections, the counters are kept
+    pub fn update_limits(&self, config: &ProtocolConfig) {
+        *self.limits.write() = limits_from_config(config);
+    }
+
+    /// Count a message received from a peer in the given category
+    pub fn check_at(
+        &self,
+        peer_id: &PeerId,
+        category: MessageTypeId,
+        now: Instant,
+    ) -> RateLimitVerdict {
+        let limit = self
+            .limits
+            .read()
+            .get(&category)
+            .copied()
+            .unwrap_or_default();
+        if limit == 0 {
+            return RateLimitVerdict::Accept;
+        }
+        let mut counters = self.counters.lock();
+        if now.saturating_duration_since(counters.last_prune) >= PRUNE_INTERVAL {
+            counters
+                .windows
+                .retain(|_, window| now.saturating_duration_since(window.start) < RATE_WINDOW * 2);
+            counters.last_prune = now;
+        }
+        let rate = counters
+            .windows
+            .entry((*peer_id, category))
+            .or_insert_with(|| RateWindow::new(now))
+            .record(now);
+        if rate <= limit {
+            RateLimitVerdict::Accept
+        } else if rate <= limit.saturating_mul(RATE_LIMIT_BAN_FACTOR) {
+            RateLimitVerdict::Throttle
+        } else {
+            // start over so that the ban is only requested once
+            counters.windows.retain(|(id, _), _| id != peer_id);
+            RateLimitVerdict::Ban
+        }
+    }
+
+    /// Count a message received from a peer, returns true if it can be processed.
+    /// Peers flooding us are banned.
+    pub fn check(&self, peer_id: &PeerId, category: MessageTypeId) -> bool {
+        match self.check_at(peer_id, category, Instant::now()) {
+            RateLimitVerdict::Accept => true,
+            RateLimitVerdict::Throttle => {
+                debug!(
+                    "Dropping {:?} message from peer {}: rate limit exceeded",
+                    category, peer_id
+                );
+                false
+            }
+            RateLimitVerdict::Ban => {
+                warn!(
+                    "Peer {} is flooding us with {:?} messages, banning it",
+                    peer_id, category
+                );
+                if let Err(err) = self
+                    .peer_cmd_sender
+                    .try_send(PeerManagementCmd::BanWithSeverity(
+                        vec![*peer_id],
+                        BanReason::RateLimitExceeded,
+                        BanSeverity::Major,
+                    ))
+                {
+                    warn!("Error while banning peer {} err: {:?}", peer_id, err);
+                }
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use massa_channel::MassaChannel;
+    use massa_protocol_exports::{PeerId, ProtocolConfig};
+    use massa_signature::KeyPair;
+
+    use crate::messages::MessageTypeId;
+
+    use super::{MessageRateLimiter, RateLimitVerdict};
+
+    #[test]
+    fn test_throttle_before_ban() {
+        let (sender, _receiver) = MassaChannel::new("test_rate_limiter".to_string(), None);
+        let rate_limiter = MessageRateLimiter::new(
+            &ProtocolConfig {
+                max_operation_messages_per_sec: 10,
+                ..Default::default()
+            },
+            sender,
+        );
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let other_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+
+        let verdicts: Vec<_> = (0..21)
+            .map(|_| rate_limiter.check_at(&peer_id, MessageTypeId::Operation, now))
+            .collect();
+        assert!(verdicts[..10]
+            .iter()
+            .all(|verdict| *verdict == RateLimitVerdict::Accept));
+        assert!(verdicts[10..20]
+            .iter()
+            .all(|verdict| *verdict == RateLimitVerdict::Throttle));
+        assert_eq!(verdicts[20], RateLimitVerdict::Ban);
+
+        // other peers and categories have their own counters
+        assert_eq!(
+            rate_limiter.check_at(&other_peer_id, MessageTypeId::Operation, now),
+            RateLimitVerdict::Accept
+        );
+        for _ in 0..10 {
+            rate_limiter.check_at(&other_peer_id, MessageTypeId::Operation, now);
+        }
+        assert_eq!(
+            rate_limiter.check_at(&other_peer_id, MessageTypeId::Block, now),
+            RateLimitVerdict::Accept
+        );
+
+        // the rate goes down once the window slides
+        assert_eq!(
+            rate_limiter.check_at(
+                &other_peer_id,
+                MessageTypeId::Operation,
+                now + Duration::from_secs(2)
+            ),
+            RateLimitVerdict::Accept
+        );
+    }
+
+    #[test]
+    fn test_update_limits() {
+        let (sender, _receiver) = MassaChannel::new("test_rate_limiter".to_string(), None);
+        let rate_limiter = MessageRateLimiter::new(
+            &ProtocolConfig {
+                max_operation_messages_per_sec: 10,
+                ..Default::default()
+            },
+            sender,
+        );
+        // the connections use clones of the limiter
+        let connection_rate_limiter = rate_limiter.clone();
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(
+                connection_rate_limiter.check_at(&peer_id, MessageTypeId::Operation, now),
+                RateLimitVerdict::Accept
+            );
+        }
+
+        rate_limiter.update_limits(&ProtocolConfig {
+            max_operation_messages_per_sec: 2,
+            ..Default::default()
+        });
+        assert_eq!(
+            connection_rate_limiter.check_at(&peer_id, MessageTypeId::Operation, now),
+            RateLimitVerdict::Ban
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Per-category size limits of the incoming messages.
+//!
+//! A single global limit is too coarse: block replies can be large while peer management
+//! messages must stay small. A peer sending a message above the limit of its category is banned.
+
+use std::collections::HashMap;
+
+use massa_channel::sender::MassaSender;
+use massa_protocol_exports::{BanReason, MessageCategory, PeerId, ProtocolConfig};
+use tracing::warn;
+
+use crate::messages::MessageTypeId;
+
+use super::models::{BanSeverity, PeerManagementCmd};
+
+/// Size limiter shared by all the connections
+#[derive(Clone)]
+pub struct MessageSizeLimiter {
+    /// maximum size in bytes of a message of each category
+    limits: HashMap<MessageTypeId, usize>,
+    /// limit of the categories without a specific one
+    default_limit: usize,
+    peer_cmd_sender: MassaSender<PeerManagementCmd>,
+}
+
+impl MessageSizeLimiter {
+    pub fn new(config: &ProtocolConfig, peer_cmd_sender: MassaSender<PeerManagementCmd>) -> Self {
+        MessageSizeLimiter {
+            limits: config
+                .max_message_size_by_category
+                .iter()
+                .map(|(category, limit)| {
+                    let id = match category {
+                        MessageCategory::Block => MessageTypeId::Block,
+                        MessageCategory::Endorsement => MessageTypeId::Endorsement,
+                        MessageCategory::Operation => MessageTypeId::Operation,
+                        MessageCategory::PeerManagement => MessageTypeId::PeerManagement,
+                    };
+                    (id, *limit)
+                })
+                .collect(),
+            default_limit: config.max_message_size,
+            peer_cmd_sender,
+        }
+    }
+
+    /// Check the size of a message received from a peer, returns true if it can be processed.
+    /// Peers sending oversized messages are banned.
+    pub fn check(&self, peer_id: &PeerId, category: MessageTypeId, size: usize) -> bool {
+        let limit = self
+            .limits
+            .get(&category)
+            .copied()
+            .unwrap_or(self.default_limit);
+        if size <= limit {
+            return true;
+        }
+        warn!(
+            "Peer {} sent a {:?} message of {} bytes, above the limit of {}, banning it",
+            peer_id, category, size, limit
+        );
+        if let Err(err) = self
+            .peer_cmd_sender
+            .try_send(PeerManagementCmd::BanWithSeverity(
+                vec![*peer_id],
+                BanReason::ProtocolViolation,
+                BanSeverity::Major,
+            ))
+        {
+            warn!("Error while banning peer {} err: {:?}", peer_id, err);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use massa_channel::MassaChannel;
+    use massa_protocol_exports::{BanReason, MessageCategory, PeerId, ProtocolConfig};
+    use massa_signature::KeyPair;
+
+    use crate::handlers::peer_handler::models::{BanSeverity, PeerManagementCmd};
+    use crate::messages::MessageTypeId;
+
+    use super::MessageSizeLimiter;
+
+    #[test]
+    fn test_size_limit_per_category() {
+        let (sender, receiver) = MassaChannel::new("test_size_limiter".to_string(), None);
+        let size_limiter = MessageSizeLimiter::new(
+            &ProtocolConfig {
+                max_message_size: 10_000,
+                max_message_size_by_category: HashMap::from([
+                    (MessageCategory::Block, 5_000),
+                    (MessageCategory::Endorsement, 2_000),
+                    (MessageCategory::Operation, 3_000),
+                    (MessageCategory::PeerManagement, 1_000),
+                ]),
+                ..Default::default()
+            },
+            sender,
+        );
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        for (category, limit) in [
+            (MessageTypeId::Block, 5_000),
+            (MessageTypeId::Endorsement, 2_000),
+            (MessageTypeId::Operation, 3_000),
+            (MessageTypeId::PeerManagement, 1_000),
+        ] {
+            assert!(size_limiter.check(&peer_id, category, limit));
+            assert!(receiver.try_recv().is_err());
+            assert!(!size_limiter.check(&peer_id, category, limit + 1));
+            match receiver.try_recv() {
+                Ok(PeerManagementCmd::BanWithSeverity(peer_ids, reason, severity)) => {
+                    assert_eq!(peer_ids, vec![peer_id]);
+                    assert_eq!(reason, BanReason::ProtocolViolation);
+                    assert_eq!(severity, BanSeverity::Major);
+                }
+                _ => panic!(
+                    "the peer sending an oversized {:?} message isn't banned",
+                    category
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_size_limit_defaults_to_max_message_size() {
+        let (sender, receiver) = MassaChannel::new("test_size_limiter".to_string(), None);
+        let size_limiter = MessageSizeLimiter::new(
+            &ProtocolConfig {
+                max_message_size: 10_000,
+                max_message_size_by_category: HashMap::from([(
+                    MessageCategory::PeerManagement,
+                    1_000,
+                )]),
+                ..Default::default()
+            },
+            sender,
+        );
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        assert!(size_limiter.check(&peer_id, MessageTypeId::Block, 10_000));
+        assert!(!size_limiter.check(&peer_id, MessageTypeId::Block, 10_001));
+        assert!(receiver.try_recv().is_ok());
+    }
+}
@@ -2,17 +2,21 @@ use std::{
     collections::HashMap,
     io::Read,
     net::{IpAddr, SocketAddr},
+    ops::Bound::Included,
     thread::JoinHandle,
     time::Duration,
 };
 
-use crate::{ip::to_canonical, messages::MessagesHandler};
+use crate::{
+    clock::ProtocolClock,
+    ip::{is_ip_enabled, to_canonical},
+    messages::MessagesHandler,
+};
 use massa_channel::{receiver::MassaReceiver, sender::MassaSender, MassaChannel};
 use massa_metrics::MassaMetrics;
 use massa_models::version::VersionDeserializer;
 use massa_protocol_exports::{PeerConnectionType, PeerId, PeerIdDeserializer, ProtocolConfig};
-use massa_serialization::{DeserializeError, Deserializer};
-use massa_time::MassaTime;
+use massa_serialization::{DeserializeError, Deserializer, U32VarIntDeserializer};
 use peernet::{
     error::{PeerNetError, PeerNetResult},
     messages::MessagesHandler as PeerNetMessagesHandler,
@@ -23,9 +27,39 @@ use tracing::debug;
 use super::{
     announcement::{AnnouncementDeserializer, AnnouncementDeserializerArgs},
     models::PeerInfo,
+    network_identity::{NetworkIdentity, NetworkIdentityDeserializer},
     SharedPeerDB,
 };
 use crate::wrap_network::ActiveConnectionsTrait;
+
+/// Network identity in the bytes following the announcement of a handshake:
+/// the capabilities, the protocol version, then the identity, `None` for the older peers not sending it
+fn peer_network_identity(trailing: &[u8]) -> PeerNetResult<Option<NetworkIdentity>> {
+    let Some(data) = trailing.get(1..).filter(|data| !data.is_empty()) else {
+        return Ok(None);
+    };
+    let (data, _) = U32VarIntDeserializer::new(Included(0), Included(u32::MAX))
+        .deserialize::<DeserializeError>(data)
+        .map_err(|err| {
+            PeerNetError::HandshakeError.error(
+                "Tester Handshake",
+                Some(format!("Failed to deserialize protocol version: {}", err)),
+            )
+        })?;
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let (_, identity) = NetworkIdentityDeserializer::new()
+        .deserialize::<DeserializeError>(data)
+        .map_err(|err| {
+            PeerNetError::HandshakeError.error(
+                "Tester Handshake",
+                Some(format!("Failed to deserialize network identity: {}", err)),
+            )
+        })?;
+    Ok(Some(identity))
+}
+
 pub struct Tester {
     pub handler: Option<JoinHandle<()>>,
 }
@@ -39,6 +73,7 @@ impl Tester {
         messages_handler: MessagesHandler,
         target_out_connections: HashMap<String, (Vec<IpAddr>, usize)>,
         default_target_out_connections: usize,
+        clock: ProtocolClock,
         massa_metrics: MassaMetrics,
     ) -> (
         (
@@ -64,6 +99,7 @@ impl Tester {
                 messages_handler.clone(),
                 target_out_connections.clone(),
                 default_target_out_connections,
+                clock.clone(),
                 massa_metrics.clone(),
             ));
         }
@@ -80,6 +116,7 @@ impl Tester {
         peer_id_deserializer: PeerIdDeserializer,
         addr: SocketAddr,
         config: &ProtocolConfig,
+        clock: &ProtocolClock,
         massa_metrics: MassaMetrics,
     ) -> PeerNetResult<PeerId> {
         let our_version = config.version;
@@ -159,7 +196,7 @@ impl Tester {
                 )?;
                 match id {
                     0 => {
-                        let (_, announcement) = announcement_deserializer
+                        let (trailing, announcement) = announcement_deserializer
                             .deserialize::<DeserializeError>(data.get(1..).ok_or(
                                 PeerNetError::HandshakeError.error(
                                     "Massa Handshake",
@@ -182,26 +219,61 @@ impl Tester {
                                 Some(String::from("Invalid signature")),
                             ));
                         }
+                        if let Some(peer_identity) = peer_network_identity(trailing)? {
+                            if peer_identity != NetworkIdentity::from_config(config) {
+                                // incompatible, not at fault: the peer isn't banned
+                                let mut peer_db_write = peer_db.write();
+                                if !peer_db_write
+                                    .set_peer_state(&peer_id, super::PeerState::WrongNetwork)
+                                {
+                                    peer_db_write.insert_peer(
+                                        peer_id,
+                                        PeerInfo {
+                                            last_announce: None,
+                                            state: super::PeerState::WrongNetwork,
+                                            ban_reason: None,
+                                            reputation: 0,
+                                            features: None,
+                                        },
+                                    );
+                                }
+                                peer_db_write
+                                    .set_try_connect_test_failure_or_insert(&addr, clock.now());
+                                return Err(PeerNetError::HandshakeError.error(
+                                    "Tester Handshake",
+                                    Some(format!("Wrong network: {:?}", peer_identity)),
+                                ));
+                            }
+                        }
                         //TODO: Check ip we are connected match one of the announced ips
                         {
                             let mut peer_db_write = peer_db.write();
-                            peer_db_write
-                                .get_peers_mut()
-                                .entry(peer_id)
-                                .and_modify(|info| {
-                                    if let Some(last_announce) = &info.last_announce {
-                                        if last_announce.timestamp < announcement.timestamp {
-                                            info.last_announce = Some(announcement.clone());
-                                        }
-                                    } else {
-                                        info.last_announce = Some(announcement.clone());
-                                    }
-                                    info.state = super::PeerState::Trusted;
-                                })
-                                .or_insert(PeerInfo {
-                                    last_announce: Some(announcement),
-                                    state: super::PeerState::Trusted,
+                            let state = if peer_db_write.get_quarantined_peers().contains(&peer_id)
+                            {
+                                super::PeerState::Quarantined
+                            } else {
+                                super::PeerState::Trusted
+                            };
+                            let newer = peer_db_write
+                                .get_peer_announce(&peer_id)
+                                .map_or(true, |last_announce| {
+                                    last_announce.timestamp < announcement.timestamp
                                 });
+                            if newer {
+                                peer_db_write.set_peer_announcement(&peer_id, announcement.clone());
+                            }
+                            if !peer_db_write.set_peer_state(&peer_id, state) {
+                                peer_db_write.insert_peer(
+                                    peer_id,
+                                    PeerInfo {
+                                        last_announce: Some(announcement),
+                                        state: super::PeerState::Trusted,
+                                        ban_reason: None,
+                                        reputation: 0,
+                                        features: None,
+                                    },
+                                );
+                            }
                         }
                         Ok(peer_id)
                     }
@@ -229,19 +301,21 @@ impl Tester {
 
                 // if handshake failed, we set the peer state to HandshakeFailed
                 if res.is_err() {
-                    peer_db_write
-                        .get_peers_mut()
-                        .entry(peer_id)
-                        .and_modify(|info| {
-                            info.state = super::PeerState::HandshakeFailed;
-                        })
-                        .or_insert(PeerInfo {
-                            last_announce: None,
-                            state: super::PeerState::HandshakeFailed,
-                        });
-                    peer_db_write.set_try_connect_test_failure_or_insert(&addr);
+                    if !peer_db_write.set_peer_state(&peer_id, super::PeerState::HandshakeFailed) {
+                        peer_db_write.insert_peer(
+                            peer_id,
+                            PeerInfo {
+                                last_announce: None,
+                                state: super::PeerState::HandshakeFailed,
+                                ban_reason: None,
+                                reputation: 0,
+                                features: None,
+                            },
+                        );
+                    }
+                    peer_db_write.set_try_connect_test_failure_or_insert(&addr, clock.now());
                 } else {
-                    peer_db_write.set_try_connect_test_success_or_insert(&addr);
+                    peer_db_write.set_try_connect_test_success_or_insert(&addr, clock.now());
                 }
             }
 
@@ -272,6 +346,7 @@ impl Tester {
         messages_handler: MessagesHandler,
         target_out_connections: HashMap<String, (Vec<IpAddr>, usize)>,
         default_target_out_connections: usize,
+        clock: ProtocolClock,
         massa_metrics: MassaMetrics,
     ) -> Self {
         let handle = std::thread::Builder::new()
@@ -327,10 +402,13 @@ impl Tester {
                                     true
                                 }).count());
                                 {
-                                    let now = MassaTime::now();
+                                    let now = clock.now();
                                     let db = db.clone();
                                     // receive new listener to test
                                     for (addr, _) in listener.1.iter() {
+                                        if !is_ip_enabled(addr, protocol_config.enable_ipv6) {
+                                            continue;
+                                        }
                                         if !db.write().insert_peer_in_test(addr) {
                                             // if the peer is already in test, we skip it
                                             continue;
@@ -392,6 +470,7 @@ impl Tester {
                                                 PeerIdDeserializer::new(),
                                                 *addr,
                                                 &protocol_config,
+                                                &clock,
                                                 massa_metrics.clone(),
                                             );
 
@@ -421,13 +500,18 @@ impl Tester {
                                 db_write.get_peers_in_test(),
                             ) {
                                 db_write.insert_peer_in_test(&listener);
-                                db_write.insert_tested_address(&listener, MassaTime::now());
+                                db_write.insert_tested_address(&listener, clock.now());
                                 listener
                             } else {
                                 continue;
                             }
                         };
 
+                        if !is_ip_enabled(&listener, protocol_config.enable_ipv6) {
+                            db.write().remove_peer_in_test(&listener);
+                            continue;
+                        }
+
                         // we try to connect to all peer listener (For now we have only one listener)
                         let ip_canonical = to_canonical(listener.ip());
                         if active_connections.get_peers_connected().iter().any(|(_, (addr, _, _))| to_canonical(addr.ip()) == ip_canonical) {
@@ -458,6 +542,7 @@ impl Tester {
                             PeerIdDeserializer::new(),
                             listener,
                             &protocol_config,
+                            &clock,
                             massa_metrics.clone(),
                         );
                         // let res =  network_manager.try_connect(
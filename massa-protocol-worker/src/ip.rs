@@ -1,4 +1,14 @@
-use std::net::IpAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
+
+use ipnet::IpNet;
+
+/// Prefix length of the IPv4 subnets considered as run by a single operator
+const IPV4_SUBNET_PREFIX_LEN: u8 = 16;
+/// Prefix length of the IPv6 subnets considered as run by a single operator
+const IPV6_SUBNET_PREFIX_LEN: u8 = 32;
 
 // TODO: Use std one when stable
 pub(crate) fn to_canonical(ip: IpAddr) -> IpAddr {
@@ -12,3 +22,110 @@ pub(crate) fn to_canonical(ip: IpAddr) -> IpAddr {
         }
     }
 }
+
+/// Whether we can connect to an address of a peer, IPv6 ones being skipped if disabled
+pub(crate) fn is_ip_enabled(addr: &SocketAddr, enable_ipv6: bool) -> bool {
+    enable_ipv6 || to_canonical(addr.ip()).is_ipv4()
+}
+
+/// Subnet of an IP address, used to spread the connections over several operators
+pub(crate) fn subnet_of(ip: IpAddr) -> IpNet {
+    let ip = to_canonical(ip);
+    let prefix_len = if ip.is_ipv4() {
+        IPV4_SUBNET_PREFIX_LEN
+    } else {
+        IPV6_SUBNET_PREFIX_LEN
+    };
+    IpNet::new(ip, prefix_len)
+        .expect("valid subnet prefix length")
+        .trunc()
+}
+
+/// Reorder candidates so that the first ones are in distinct subnets, starting with the subnets
+/// that aren't in `used_subnets` yet. The candidates of a subnet keep their relative order, so
+/// the order is unchanged when they are all in the same subnet.
+pub(crate) fn spread_over_subnets<T>(
+    candidates: Vec<T>,
+    addr_of: impl Fn(&T) -> SocketAddr,
+    used_subnets: &HashSet<IpNet>,
+) -> Vec<T> {
+    // candidates already taken in each subnet, the used subnets start with one
+    let mut taken: HashMap<IpNet, usize> = HashMap::new();
+    let mut ranked: Vec<(usize, T)> = candidates
+        .into_iter()
+        .map(|candidate| {
+            let subnet = subnet_of(addr_of(&candidate).ip());
+            let rank = taken
+                .entry(subnet)
+                .or_insert_with(|| usize::from(used_subnets.contains(&subnet)));
+            *rank += 1;
+            (*rank, candidate)
+        })
+        .collect();
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, net::SocketAddr};
+
+    use super::{is_ip_enabled, spread_over_subnets, subnet_of};
+
+    #[test]
+    fn test_ipv6_addresses_can_be_disabled() {
+        let v4: SocketAddr = "10.0.0.1:31244".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:31244".parse().unwrap();
+        let v4_mapped: SocketAddr = "[::ffff:10.0.0.1]:31244".parse().unwrap();
+
+        assert!(is_ip_enabled(&v4, true));
+        assert!(is_ip_enabled(&v6, true));
+        assert!(is_ip_enabled(&v4, false));
+        assert!(!is_ip_enabled(&v6, false));
+        // an IPv4 address in disguise is still reachable
+        assert!(is_ip_enabled(&v4_mapped, false));
+    }
+
+    #[test]
+    fn test_candidates_spread_over_subnets() {
+        let parse = |addrs: &[&str]| -> Vec<SocketAddr> {
+            addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+        };
+        let candidates = parse(&[
+            "1.1.0.1:31244",
+            "1.1.0.2:31244",
+            "1.1.7.3:31244",
+            "2.2.0.1:31244",
+            "3.3.0.1:31244",
+        ]);
+
+        let spread = spread_over_subnets(candidates.clone(), |addr| *addr, &HashSet::new());
+        let first_subnets: HashSet<_> = spread[..3]
+            .iter()
+            .map(|addr| subnet_of(addr.ip()))
+            .collect();
+        assert_eq!(first_subnets.len(), 3);
+        assert_eq!(
+            spread,
+            parse(&[
+                "1.1.0.1:31244",
+                "2.2.0.1:31244",
+                "3.3.0.1:31244",
+                "1.1.0.2:31244",
+                "1.1.7.3:31244",
+            ])
+        );
+
+        // the subnets we are already connected to come after the other ones
+        let used_subnets = HashSet::from([subnet_of("2.2.9.9".parse().unwrap())]);
+        let spread = spread_over_subnets(candidates, |addr| *addr, &used_subnets);
+        assert_eq!(spread[..2], parse(&["1.1.0.1:31244", "3.3.0.1:31244"]));
+
+        // candidates in a single subnet keep their order
+        let same_subnet = parse(&["1.1.0.3:31244", "1.1.0.1:31244", "1.1.0.2:31244"]);
+        assert_eq!(
+            spread_over_subnets(same_subnet.clone(), |addr| *addr, &HashSet::new()),
+            same_subnet
+        );
+    }
+}
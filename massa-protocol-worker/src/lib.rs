@@ -1,3 +1,6 @@
+mod address_cache;
+mod bandwidth_limiter;
+mod clock;
 mod connectivity;
 mod context;
 mod controller;
@@ -5,6 +8,7 @@ mod handlers;
 mod ip;
 mod manager;
 mod messages;
+mod rng;
 mod sig_verifier;
 mod worker;
 mod wrap_network;
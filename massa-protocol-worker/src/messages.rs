@@ -1,8 +1,12 @@
+use std::{io::Read, sync::Arc};
+
+use crossbeam::channel::TrySendError;
 use massa_channel::sender::MassaSender;
-use massa_protocol_exports::PeerId;
+use massa_protocol_exports::{MessageCategory, MessageCounters, MessageKind, PeerId};
 use massa_serialization::{
     DeserializeError, Deserializer, Serializer, U64VarIntDeserializer, U64VarIntSerializer,
 };
+use massa_time::MassaTime;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use peernet::{
     error::{PeerNetError, PeerNetResult},
@@ -10,14 +14,19 @@ use peernet::{
         MessagesHandler as PeerNetMessagesHandler, MessagesSerializer as PeerNetMessagesSerializer,
     },
 };
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::handlers::{
     block_handler::{BlockMessage, BlockMessageSerializer},
     endorsement_handler::{EndorsementMessage, EndorsementMessageSerializer},
     operation_handler::{OperationMessage, OperationMessageSerializer},
     peer_handler::{
-        models::PeerMessageTuple, PeerManagementMessage, PeerManagementMessageSerializer,
+        corruption_limiter::CorruptedFramesLimiter,
+        inbound_queues::InboundQueues,
+        models::{PeerMessageTuple, SharedPeerTraffic},
+        rate_limiter::MessageRateLimiter,
+        size_limiter::MessageSizeLimiter,
+        PeerManagementMessage, PeerManagementMessageSerializer,
     },
 };
 
@@ -29,13 +38,17 @@ pub enum Message {
     PeerManagement(Box<PeerManagementMessage>),
 }
 
-#[derive(IntoPrimitive, Debug, Eq, PartialEq, TryFromPrimitive)]
+#[derive(IntoPrimitive, Debug, Clone, Copy, Eq, PartialEq, Hash, TryFromPrimitive)]
 #[repr(u64)]
 pub enum MessageTypeId {
     Block = 0,
     Endorsement = 1,
     Operation = 2,
     PeerManagement = 3,
+    /// zstd-compressed frame wrapping a message of one of the other types
+    Compressed = 4,
+    /// CRC32 of a frame followed by the frame, compressed or not
+    Checksummed = 5,
 }
 
 impl From<&Message> for MessageTypeId {
@@ -49,6 +62,58 @@ impl From<&Message> for MessageTypeId {
     }
 }
 
+impl From<&Message> for MessageKind {
+    fn from(value: &Message) -> Self {
+        match value {
+            Message::Block(message) => message.kind(),
+            Message::Endorsement(message) => message.kind(),
+            Message::Operation(message) => message.kind(),
+            Message::PeerManagement(message) => message.kind(),
+        }
+    }
+}
+
+impl MessageTypeId {
+    /// Category of the messages of this type, `None` for the compressed and checksummed frames that can wrap any of them
+    pub fn category(self) -> Option<MessageCategory> {
+        match self {
+            MessageTypeId::Block => Some(MessageCategory::Block),
+            MessageTypeId::Endorsement => Some(MessageCategory::Endorsement),
+            MessageTypeId::Operation => Some(MessageCategory::Operation),
+            MessageTypeId::PeerManagement => Some(MessageCategory::PeerManagement),
+            MessageTypeId::Compressed | MessageTypeId::Checksummed => None,
+        }
+    }
+}
+
+/// The format version tags are above the message type ids, so that the untagged `V1` frames are
+/// told apart from the tagged ones by their first varint
+const FORMAT_VERSION_TAG_BASE: u64 = 0x100;
+
+/// Wire format of the messages
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum MessageFormatVersion {
+    /// the message type id directly followed by the message
+    #[default]
+    V1,
+    /// a leading format version tag followed by a `V1` frame
+    V2,
+    /// a leading format version tag followed by the length of a `V1` frame and the frame,
+    /// so that the messages of types or variants unknown to the receiver are skipped rather than rejected
+    V3,
+}
+
+impl MessageFormatVersion {
+    /// Leading tag of the frames of this version, `V1` frames don't have any
+    fn tag(self) -> Option<u64> {
+        match self {
+            MessageFormatVersion::V1 => None,
+            MessageFormatVersion::V2 => Some(FORMAT_VERSION_TAG_BASE + 2),
+            MessageFormatVersion::V3 => Some(FORMAT_VERSION_TAG_BASE + 3),
+        }
+    }
+}
+
 //TODO: Macroize this
 impl From<BlockMessage> for Message {
     fn from(message: BlockMessage) -> Self {
@@ -81,6 +146,16 @@ pub struct MessagesSerializer {
     operation_message_serializer: Option<OperationMessageSerializer>,
     endorsement_message_serializer: Option<EndorsementMessageSerializer>,
     peer_management_message_serializer: Option<PeerManagementMessageSerializer>,
+    /// zstd level and minimum frame size to compress, no compression if `None`
+    compression: Option<(i32, usize)>,
+    /// counters of the peer the messages are sent to, no accounting if `None`
+    peer_traffic: Option<(PeerId, SharedPeerTraffic)>,
+    /// wire format of the messages, `V1` unless the peer advertised another one in the handshake
+    format_version: MessageFormatVersion,
+    /// wrap the frames in a checksummed frame
+    checksum: bool,
+    /// counts the messages sent by kind, no counting if `None`
+    message_counters: Option<Arc<MessageCounters>>,
 }
 
 impl Default for MessagesSerializer {
@@ -97,6 +172,11 @@ impl MessagesSerializer {
             operation_message_serializer: None,
             endorsement_message_serializer: None,
             peer_management_message_serializer: None,
+            compression: None,
+            peer_traffic: None,
+            format_version: MessageFormatVersion::default(),
+            checksum: false,
+            message_counters: None,
         }
     }
 
@@ -131,11 +211,123 @@ impl MessagesSerializer {
         self.peer_management_message_serializer = Some(peer_management_message_serializer);
         self
     }
-}
 
-impl PeerNetMessagesSerializer<Message> for MessagesSerializer {
-    /// Serialize the message
-    fn serialize(&self, message: &Message, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
+    /// Compress with zstd at `level` the frames of at least `min_size` bytes.
+    /// Only use it for peers that advertised compression support in the handshake.
+    pub fn with_compression(mut self, level: i32, min_size: usize) -> Self {
+        self.compression = Some((level, min_size));
+        self
+    }
+
+    /// Count the serialized bytes in the traffic of `peer_id`
+    pub fn with_peer_traffic(mut self, peer_id: PeerId, peer_traffic: SharedPeerTraffic) -> Self {
+        self.peer_traffic = Some((peer_id, peer_traffic));
+        self
+    }
+
+    /// Count the serialized messages by kind
+    pub fn with_message_counters(mut self, message_counters: Arc<MessageCounters>) -> Self {
+        self.message_counters = Some(message_counters);
+        self
+    }
+
+    /// Serialize the messages in the wire format `format_version`.
+    /// Only use a tagged one for peers that advertised support for it in the handshake.
+    pub fn with_format_version(mut self, format_version: MessageFormatVersion) -> Self {
+        self.format_version = format_version;
+        self
+    }
+
+    /// Checksum the frames so that the peer drops the corrupted ones.
+    /// Only use it for peers that advertised checksum support in the handshake.
+    pub fn with_checksum(mut self) -> Self {
+        self.checksum = true;
+        self
+    }
+
+    /// Serialize the message behind its format version tag, checksummed and compressed if enabled
+    fn serialize_message(&self, message: &Message, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
+        if let Some(tag) = self.format_version.tag() {
+            self.id_serializer.serialize(&tag, buffer).map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesSerializer",
+                    Some(format!("Failed to serialize format version {}", err)),
+                )
+            })?;
+        }
+        if self.format_version != MessageFormatVersion::V3 {
+            return self.serialize_checksummed(message, buffer);
+        }
+        let mut frame = Vec::new();
+        self.serialize_checksummed(message, &mut frame)?;
+        self.id_serializer
+            .serialize(&(frame.len() as u64), buffer)
+            .map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesSerializer",
+                    Some(format!("Failed to serialize envelope length {}", err)),
+                )
+            })?;
+        buffer.extend(frame);
+        Ok(())
+    }
+
+    /// Serialize the message frame, checksummed and compressed if enabled
+    fn serialize_checksummed(&self, message: &Message, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
+        if !self.checksum {
+            return self.serialize_compressed(message, buffer);
+        }
+        let mut frame = Vec::new();
+        self.serialize_compressed(message, &mut frame)?;
+        self.id_serializer
+            .serialize(&MessageTypeId::Checksummed.into(), buffer)
+            .map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesSerializer",
+                    Some(format!("Failed to serialize id {}", err)),
+                )
+            })?;
+        buffer.extend(crc32fast::hash(&frame).to_be_bytes());
+        buffer.extend(frame);
+        Ok(())
+    }
+
+    /// Serialize the message frame, compressed if enabled and worth it
+    fn serialize_compressed(&self, message: &Message, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
+        let Some((level, min_size)) = self.compression else {
+            return self.serialize_frame(message, buffer);
+        };
+        let mut frame = Vec::new();
+        self.serialize_frame(message, &mut frame)?;
+        if frame.len() < min_size {
+            buffer.extend(frame);
+            return Ok(());
+        }
+        let compressed = zstd::bulk::compress(&frame, level).map_err(|err| {
+            PeerNetError::HandlerError.error(
+                "MessagesSerializer",
+                Some(format!("Failed to compress message: {}", err)),
+            )
+        })?;
+        // not worth it if the compression doesn't save any space
+        if compressed.len() >= frame.len() {
+            buffer.extend(frame);
+            return Ok(());
+        }
+        self.id_serializer
+            .serialize(&MessageTypeId::Compressed.into(), buffer)
+            .map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesSerializer",
+                    Some(format!("Failed to serialize id {}", err)),
+                )
+            })?;
+        buffer.extend(compressed);
+        Ok(())
+    }
+
+    /// Serialize the message type id followed by the message, without compression
+    fn serialize_frame(&self, message: &Message, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
         self.id_serializer
             .serialize(
                 &MessageTypeId::from(message).try_into().map_err(|_| {
@@ -217,6 +409,25 @@ impl PeerNetMessagesSerializer<Message> for MessagesSerializer {
     }
 }
 
+impl PeerNetMessagesSerializer<Message> for MessagesSerializer {
+    /// Serialize the message
+    fn serialize(&self, message: &Message, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
+        let start = buffer.len();
+        self.serialize_message(message, buffer)?;
+        if let Some((peer_id, peer_traffic)) = &self.peer_traffic {
+            if let Some(category) = MessageTypeId::from(message).category() {
+                peer_traffic
+                    .write()
+                    .record_sent(peer_id, category, (buffer.len() - start) as u64);
+            }
+        }
+        if let Some(message_counters) = &self.message_counters {
+            message_counters.record_sent(message.into());
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct MessagesHandler {
     pub id_deserializer: U64VarIntDeserializer,
@@ -224,10 +435,190 @@ pub struct MessagesHandler {
     pub sender_endorsements: MassaSender<PeerMessageTuple>,
     pub sender_operations: MassaSender<PeerMessageTuple>,
     pub sender_peers: MassaSender<PeerMessageTuple>,
+    /// drops and bans the peers sending too many messages, no limit if `None`
+    pub rate_limiter: Option<MessageRateLimiter>,
+    /// drops and bans the peers sending messages too big for their category, no limit if `None`
+    pub size_limiter: Option<MessageSizeLimiter>,
+    /// disconnects the peers sending too many corrupted frames, no limit if `None`
+    pub corrupted_frames_limiter: Option<CorruptedFramesLimiter>,
+    /// max size of a message once decompressed
+    pub max_message_size: usize,
+    /// counts the bytes received from each peer, no accounting if `None`
+    pub peer_traffic: Option<SharedPeerTraffic>,
+    /// counts the messages received by kind and the ones dropped on full channels, no counting if `None`
+    pub message_counters: Option<Arc<MessageCounters>>,
+    /// pauses the reads of the peers with too many messages waiting for their handler, no limit if `None`
+    pub inbound_queues: Option<InboundQueues>,
 }
 
 impl PeerNetMessagesHandler<PeerId> for MessagesHandler {
     fn handle(&self, data: &[u8], peer_id: &PeerId) -> PeerNetResult<()> {
+        let (data, format_version) = self.read_format_version(data)?;
+        match format_version {
+            // the frames of both versions only differ by their tag
+            MessageFormatVersion::V1 | MessageFormatVersion::V2 => {
+                self.handle_frame(data, peer_id, None, false)
+            }
+            MessageFormatVersion::V3 => {
+                let frame = self.open_envelope(data)?;
+                self.handle_frame(frame, peer_id, None, true)
+            }
+        }
+    }
+}
+
+impl MessagesHandler {
+    /// Read the format version tag of a received message, returns the untagged frame
+    fn read_format_version<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> PeerNetResult<(&'a [u8], MessageFormatVersion)> {
+        let (rest, value) = self
+            .id_deserializer
+            .deserialize::<DeserializeError>(data)
+            .map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(format!("Failed to deserialize message header: {}", err)),
+                )
+            })?;
+        if value < FORMAT_VERSION_TAG_BASE {
+            // untagged frame starting with its message type id
+            return Ok((data, MessageFormatVersion::V1));
+        }
+        for format_version in [MessageFormatVersion::V2, MessageFormatVersion::V3] {
+            if Some(value) == format_version.tag() {
+                return Ok((rest, format_version));
+            }
+        }
+        Err(PeerNetError::HandlerError.error(
+            "MessagesHandler",
+            Some(format!(
+                "Unsupported message format version {}",
+                value - FORMAT_VERSION_TAG_BASE
+            )),
+        ))
+    }
+
+    /// Frame of a `V3` message, from the length leading it.
+    /// The bytes after the frame are left for future extensions of the envelope.
+    fn open_envelope<'a>(&self, data: &'a [u8]) -> PeerNetResult<&'a [u8]> {
+        let (rest, length) = self
+            .id_deserializer
+            .deserialize::<DeserializeError>(data)
+            .map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(format!("Failed to deserialize envelope length: {}", err)),
+                )
+            })?;
+        if length > rest.len() as u64 {
+            return Err(PeerNetError::HandlerError.error(
+                "MessagesHandler",
+                Some(String::from("Truncated message envelope")),
+            ));
+        }
+        Ok(&rest[..length as usize])
+    }
+
+    /// Count a message of `peer_id` dropped because the channel of its handler is full,
+    /// warning about it once per window so that a saturated handler doesn't go unnoticed
+    fn on_channel_full(&self, category: MessageCategory, peer_id: &PeerId) {
+        let first_in_window = self
+            .message_counters
+            .as_ref()
+            .map_or(false, |message_counters| {
+                message_counters.record_dropped(category, MassaTime::now())
+            });
+        if first_in_window {
+            warn!(
+                "The {:?} message channel is full, dropping the messages received, e.g. from peer {}",
+                category, peer_id
+            );
+        } else {
+            debug!(
+                "Dropping {:?} message from peer {}: channel full",
+                category, peer_id
+            );
+        }
+    }
+
+    /// Put a message of `peer_id` in the channel of `category` with `send`, waiting for room in the
+    /// inbound queue of the peer if there is one. `send` returns whether the message was put in `sender`.
+    fn enqueue(
+        &self,
+        peer_id: &PeerId,
+        category: MessageCategory,
+        sender: &MassaSender<PeerMessageTuple>,
+        send: impl FnOnce() -> PeerNetResult<bool>,
+    ) -> PeerNetResult<()> {
+        match &self.inbound_queues {
+            Some(inbound_queues) => inbound_queues.send(peer_id, category, sender, send),
+            None => send().map(|_| ()),
+        }
+    }
+
+    /// Whether the payload of a message of type `id` starts with a variant id unknown to this version.
+    /// A payload without any variant id is malformed rather than unknown.
+    fn is_unknown_variant(&self, id: MessageTypeId, data: &[u8]) -> bool {
+        self.id_deserializer
+            .deserialize::<DeserializeError>(data)
+            .is_ok()
+            && self.message_kind(id, data).is_none()
+    }
+
+    /// Kind of a message of type `id` from the type id leading its payload, `None` if invalid.
+    /// The payload itself is only deserialized by the handler of its type.
+    fn message_kind(&self, id: MessageTypeId, data: &[u8]) -> Option<MessageKind> {
+        let (_, raw_id) = self
+            .id_deserializer
+            .deserialize::<DeserializeError>(data)
+            .ok()?;
+        match id {
+            MessageTypeId::Block => BlockMessage::kind_of(raw_id),
+            MessageTypeId::Endorsement => EndorsementMessage::kind_of(raw_id),
+            MessageTypeId::Operation => OperationMessage::kind_of(raw_id),
+            MessageTypeId::PeerManagement => PeerManagementMessage::kind_of(raw_id),
+            MessageTypeId::Compressed | MessageTypeId::Checksummed => None,
+        }
+    }
+
+    /// Decompress a zstd frame, refusing to inflate it beyond `max_message_size`
+    fn decompress(&self, data: &[u8]) -> PeerNetResult<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        zstd::stream::read::Decoder::new(data)
+            .and_then(|decoder| {
+                decoder
+                    .take(self.max_message_size as u64 + 1)
+                    .read_to_end(&mut decompressed)
+            })
+            .map_err(|err| {
+                PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(format!("Failed to decompress message: {}", err)),
+                )
+            })?;
+        if decompressed.len() > self.max_message_size {
+            return Err(PeerNetError::HandlerError.error(
+                "MessagesHandler",
+                Some(String::from("Decompressed message too big")),
+            ));
+        }
+        Ok(decompressed)
+    }
+
+    /// Route a received frame to its handler, checking its checksum and decompressing it first if needed.
+    /// `wrapper_size` is the size of the outermost frame wrapping this one, if any:
+    /// a checksummed frame can only wrap a compressed or plain one, and a compressed frame only a plain one.
+    /// The messages of unknown types or variants are skipped if the frame came in an envelope, rejected otherwise.
+    fn handle_frame(
+        &self,
+        data: &[u8],
+        peer_id: &PeerId,
+        wrapper_size: Option<(usize, MessageTypeId)>,
+        enveloped: bool,
+    ) -> PeerNetResult<()> {
+        let received_size = wrapper_size.map_or(data.len(), |(size, _)| size);
         let (data, raw_id) = self
             .id_deserializer
             .deserialize::<DeserializeError>(data)
@@ -237,46 +628,926 @@ impl PeerNetMessagesHandler<PeerId> for MessagesHandler {
                     Some(format!("Failed to deserialize message type id: {}", err)),
                 )
             })?;
-        let id = MessageTypeId::try_from(raw_id).map_err(|_| {
-            PeerNetError::HandlerError.error(
-                "MessagesHandler",
-                Some(String::from("Invalid message type id")),
-            )
-        })?;
+        let id = match MessageTypeId::try_from(raw_id) {
+            Ok(id) => id,
+            Err(_) if enveloped => {
+                debug!(
+                    "Skipping message of unknown type {} from peer {}",
+                    raw_id, peer_id
+                );
+                return Ok(());
+            }
+            Err(_) => {
+                return Err(PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(String::from("Invalid message type id")),
+                ))
+            }
+        };
+        if id == MessageTypeId::Checksummed {
+            if wrapper_size.is_some() {
+                return Err(PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(String::from("Nested checksummed message")),
+                ));
+            }
+            if data.len() < 4 {
+                return Err(PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(String::from("Checksummed message too short")),
+                ));
+            }
+            let (checksum, frame) = data.split_at(4);
+            let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+            // corrupted on the way rather than malicious: dropped without banning the peer,
+            // which is only disconnected if it keeps on sending corrupted frames
+            if crc32fast::hash(frame) != checksum {
+                debug!("Dropping corrupted message from peer {}", peer_id);
+                if let Some(peer_traffic) = &self.peer_traffic {
+                    peer_traffic.write().record_corrupted(peer_id);
+                }
+                if let Some(corrupted_frames_limiter) = &self.corrupted_frames_limiter {
+                    corrupted_frames_limiter.record(peer_id);
+                }
+                return Ok(());
+            }
+            return self.handle_frame(frame, peer_id, Some((received_size, id)), enveloped);
+        }
+        if id == MessageTypeId::Compressed {
+            if matches!(wrapper_size, Some((_, MessageTypeId::Compressed))) {
+                return Err(PeerNetError::HandlerError.error(
+                    "MessagesHandler",
+                    Some(String::from("Nested compressed message")),
+                ));
+            }
+            let decompressed = self.decompress(data)?;
+            return self.handle_frame(&decompressed, peer_id, Some((received_size, id)), enveloped);
+        }
+        if let (Some(peer_traffic), Some(category)) = (&self.peer_traffic, id.category()) {
+            peer_traffic
+                .write()
+                .record_received(peer_id, category, received_size as u64);
+        }
+        if let Some(size_limiter) = &self.size_limiter {
+            if !size_limiter.check(peer_id, id, data.len()) {
+                return Ok(());
+            }
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if !rate_limiter.check(peer_id, id) {
+                return Ok(());
+            }
+        }
+        if enveloped && self.is_unknown_variant(id, data) {
+            // sent by a newer peer, the known but malformed messages still reach their handler to be rejected
+            debug!(
+                "Skipping message of unknown variant of type {:?} from peer {}",
+                id, peer_id
+            );
+            return Ok(());
+        }
+        if let Some(message_counters) = &self.message_counters {
+            if let Some(kind) = self.message_kind(id, data) {
+                message_counters.record_received(kind);
+            }
+        }
         match id {
             // Blocks are high-priority: we block if the channel is full.
             // This means that the sender will be blocked until the message is sent.
             MessageTypeId::Block => {
-                self.sender_blocks
-                    .send((*peer_id, data.to_vec()))
-                    .map_err(|err| {
-                        PeerNetError::HandlerError.error(
-                            "MessagesHandler",
-                            Some(format!("Failed to send block message to channel: {}", err)),
-                        )
-                    })
+                self.enqueue(peer_id, MessageCategory::Block, &self.sender_blocks, || {
+                    self.sender_blocks
+                        .send((*peer_id, data.to_vec()))
+                        .map(|()| true)
+                        .map_err(|err| {
+                            PeerNetError::HandlerError.error(
+                                "MessagesHandler",
+                                Some(format!("Failed to send block message to channel: {}", err)),
+                            )
+                        })
+                })
             }
             // Endorsements are low priority: we just drop the message if the channel is full
-            MessageTypeId::Endorsement => {
-                if let Err(err) = self.sender_endorsements.try_send((*peer_id, data.to_vec())) {
-                    debug!("Failed to send endorsement message to channel: {}", err)
-                }
-                Ok(())
-            }
+            MessageTypeId::Endorsement => self.enqueue(
+                peer_id,
+                MessageCategory::Endorsement,
+                &self.sender_endorsements,
+                || {
+                    match self.sender_endorsements.try_send((*peer_id, data.to_vec())) {
+                        Err(TrySendError::Full(_)) => {
+                            self.on_channel_full(MessageCategory::Endorsement, peer_id)
+                        }
+                        Err(err) => {
+                            debug!("Failed to send endorsement message to channel: {}", err)
+                        }
+                        Ok(()) => return Ok(true),
+                    }
+                    Ok(false)
+                },
+            ),
             // Operations are low priority: we just drop the message if the channel is full
-            MessageTypeId::Operation => {
-                if let Err(err) = self.sender_operations.try_send((*peer_id, data.to_vec())) {
-                    debug!("Failed to send operation message to channel: {}", err)
+            MessageTypeId::Operation => self.enqueue(
+                peer_id,
+                MessageCategory::Operation,
+                &self.sender_operations,
+                || {
+                    match self.sender_operations.try_send((*peer_id, data.to_vec())) {
+                        Err(TrySendError::Full(_)) => {
+                            self.on_channel_full(MessageCategory::Operation, peer_id)
+                        }
+                        Err(err) => debug!("Failed to send operation message to channel: {}", err),
+                        Ok(()) => return Ok(true),
+                    }
+                    Ok(false)
+                },
+            ),
+            // Peer management messages are low priority: we just drop the message if the channel is full
+            MessageTypeId::PeerManagement => self.enqueue(
+                peer_id,
+                MessageCategory::PeerManagement,
+                &self.sender_peers,
+                || {
+                    match self.sender_peers.try_send((*peer_id, data.to_vec())) {
+                        Err(TrySendError::Full(_)) => {
+                            self.on_channel_full(MessageCategory::PeerManagement, peer_id)
+                        }
+                        Err(err) => debug!("Failed to send peer message to channel: {}", err),
+                        Ok(()) => return Ok(true),
+                    }
+                    Ok(false)
+                },
+            ),
+            MessageTypeId::Compressed | MessageTypeId::Checksummed => {
+                unreachable!("compressed and checksummed frames are unwrapped above")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        ops::Bound::Included,
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
+
+    use massa_channel::MassaChannel;
+    use massa_hash::Hash;
+    use massa_models::{
+        address::Address,
+        amount::Amount,
+        block_id::BlockId,
+        config::MAX_MESSAGE_SIZE,
+        operation::{Operation, OperationSerializer, OperationType},
+        secure_share::SecureShareContent,
+    };
+    use massa_protocol_exports::{
+        test_exports::tools::create_block, MessageCategory, MessageCounters, MessageKind, PeerId,
+        ProtocolConfig,
+    };
+    use massa_serialization::{
+        DeserializeError, Deserializer, Serializer, U64VarIntDeserializer, U64VarIntSerializer,
+    };
+    use massa_signature::KeyPair;
+    use peernet::messages::{
+        MessagesHandler as PeerNetMessagesHandler, MessagesSerializer as PeerNetMessagesSerializer,
+    };
+
+    use super::{
+        Message, MessageFormatVersion, MessageTypeId, MessagesHandler, MessagesSerializer,
+        FORMAT_VERSION_TAG_BASE,
+    };
+    use crate::handlers::block_handler::{
+        AskForBlockInfo, BlockInfoReply, BlockMessage, BlockMessageSerializer,
+    };
+    use crate::handlers::endorsement_handler::{EndorsementMessage, EndorsementMessageSerializer};
+    use crate::handlers::operation_handler::{OperationMessage, OperationMessageSerializer};
+    use crate::handlers::peer_handler::{
+        corruption_limiter::CorruptedFramesLimiter,
+        inbound_queues::InboundQueues,
+        models::{PeerManagementCmd, SharedPeerTraffic},
+        size_limiter::MessageSizeLimiter,
+        PeerManagementMessage, PeerManagementMessageSerializer,
+    };
+
+    #[test]
+    fn test_compressed_operations_message_round_trip() {
+        let keypair = KeyPair::generate(0).unwrap();
+        let recipient_address =
+            Address::from_public_key(&KeyPair::generate(0).unwrap().get_public_key());
+        let operations = (0..200)
+            .map(|expire_period| {
+                let content = Operation {
+                    fee: Amount::zero(),
+                    op: OperationType::Transaction {
+                        recipient_address,
+                        amount: Amount::default(),
+                    },
+                    expire_period,
+                };
+                Operation::new_verifiable(content, OperationSerializer::new(), &keypair).unwrap()
+            })
+            .collect();
+        let message = Message::from(OperationMessage::Operations(operations));
+        let serializer = MessagesSerializer::new()
+            .with_operation_message_serializer(OperationMessageSerializer::new());
+        let mut uncompressed = Vec::new();
+        serializer.serialize(&message, &mut uncompressed).unwrap();
+        let mut compressed = Vec::new();
+        serializer
+            .clone()
+            .with_compression(3, 1024)
+            .serialize(&message, &mut compressed)
+            .unwrap();
+        assert!(compressed.len() < uncompressed.len());
+
+        let id_deserializer = U64VarIntDeserializer::new(Included(0), Included(u64::MAX));
+        let (_, id) = id_deserializer
+            .deserialize::<DeserializeError>(&compressed)
+            .unwrap();
+        assert_eq!(id, u64::from(MessageTypeId::Compressed));
+
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, receiver_operations) =
+            MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let handler = MessagesHandler {
+            id_deserializer: id_deserializer.clone(),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        handler.handle(&compressed, &peer_id).unwrap();
+        let (received_from, received) = receiver_operations.recv().unwrap();
+        assert_eq!(received_from, peer_id);
+        // the operation handler gets exactly the bytes of the uncompressed message
+        let (payload, id) = id_deserializer
+            .deserialize::<DeserializeError>(&uncompressed)
+            .unwrap();
+        assert_eq!(id, u64::from(MessageTypeId::Operation));
+        assert_eq!(received, payload);
+
+        // a message smaller than the threshold isn't compressed
+        let small_message = Message::from(OperationMessage::Operations(vec![]));
+        let mut small = Vec::new();
+        serializer
+            .with_compression(3, 1024)
+            .serialize(&small_message, &mut small)
+            .unwrap();
+        let (_, id) = id_deserializer
+            .deserialize::<DeserializeError>(&small)
+            .unwrap();
+        assert_eq!(id, u64::from(MessageTypeId::Operation));
+    }
+
+    #[test]
+    fn test_peer_traffic_accounting() {
+        // message type id, operation message type id and operation count
+        const FRAME_OVERHEAD: usize = 8;
+        let keypair = KeyPair::generate(0).unwrap();
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        let recipient_address =
+            Address::from_public_key(&KeyPair::generate(0).unwrap().get_public_key());
+        let operations: Vec<_> = (0..10)
+            .map(|expire_period| {
+                let content = Operation {
+                    fee: Amount::zero(),
+                    op: OperationType::Transaction {
+                        recipient_address,
+                        amount: Amount::default(),
+                    },
+                    expire_period,
+                };
+                Operation::new_verifiable(content, OperationSerializer::new(), &keypair).unwrap()
+            })
+            .collect();
+        let operations_size: usize = operations.iter().map(|op| op.serialized_size()).sum();
+        let message = Message::from(OperationMessage::Operations(operations));
+
+        let sent_traffic = SharedPeerTraffic::default();
+        let mut data = Vec::new();
+        MessagesSerializer::new()
+            .with_operation_message_serializer(OperationMessageSerializer::new())
+            .with_peer_traffic(peer_id, sent_traffic.clone())
+            .serialize(&message, &mut data)
+            .unwrap();
+        assert!(data.len() >= operations_size);
+        assert!(data.len() - operations_size <= FRAME_OVERHEAD);
+        let sent = sent_traffic.read().peers[&peer_id].clone();
+        assert_eq!(
+            sent.bytes_sent.get(&MessageCategory::Operation),
+            Some(&(data.len() as u64))
+        );
+        assert_eq!(sent.total_bytes_sent(), data.len() as u64);
+        assert_eq!(sent.total_bytes_received(), 0);
+
+        let received_traffic = SharedPeerTraffic::default();
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _receiver_operations) =
+            MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: Some(received_traffic.clone()),
+            message_counters: None,
+            inbound_queues: None,
+        };
+        handler.handle(&data, &peer_id).unwrap();
+        handler.handle(&data, &peer_id).unwrap();
+        let received = received_traffic.read().peers[&peer_id].clone();
+        assert_eq!(
+            received.bytes_received.get(&MessageCategory::Operation),
+            Some(&(2 * data.len() as u64))
+        );
+        assert_eq!(received.total_bytes_sent(), 0);
+
+        // the counters of a peer are reset when it disconnects, not the lifetime ones
+        received_traffic.write().remove_peer(&peer_id);
+        assert!(received_traffic.read().peers.is_empty());
+        assert_eq!(
+            received_traffic.read().lifetime.total_bytes_received(),
+            2 * data.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_message_format_versions_round_trip() {
+        let (sender_blocks, receiver_blocks) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, receiver_endorsements) =
+            MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, receiver_operations) =
+            MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, receiver_peers) = MassaChannel::new(String::from("test_peers"), None);
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let serializer = MessagesSerializer::new()
+            .with_block_message_serializer(BlockMessageSerializer::new())
+            .with_endorsement_message_serializer(EndorsementMessageSerializer::new())
+            .with_operation_message_serializer(OperationMessageSerializer::new())
+            .with_peer_management_message_serializer(PeerManagementMessageSerializer::new());
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let messages = || -> Vec<Message> {
+            vec![
+                BlockMessage::DataRequest {
+                    block_id: BlockId::generate_from_hash(Hash::compute_from(b"block")),
+                    block_info: AskForBlockInfo::Header,
+                }
+                .into(),
+                EndorsementMessage::AskEndorsements(vec![]).into(),
+                OperationMessage::Operations(vec![]).into(),
+                PeerManagementMessage::Disconnect {
+                    reason: "node shutting down".to_string(),
                 }
-                Ok(())
+                .into(),
+            ]
+        };
+        let id_deserializer = U64VarIntDeserializer::new(Included(0), Included(u64::MAX));
+
+        for (v1_message, v2_message) in messages().into_iter().zip(messages()) {
+            let mut v1 = Vec::new();
+            serializer
+                .clone()
+                .with_format_version(MessageFormatVersion::V1)
+                .serialize(&v1_message, &mut v1)
+                .unwrap();
+            let mut v2 = Vec::new();
+            serializer
+                .clone()
+                .with_format_version(MessageFormatVersion::V2)
+                .serialize(&v2_message, &mut v2)
+                .unwrap();
+            // a v2 message is a v1 frame behind its version tag
+            let (frame, tag) = id_deserializer
+                .deserialize::<DeserializeError>(&v2)
+                .unwrap();
+            assert_eq!(tag, MessageFormatVersion::V2.tag().unwrap());
+            assert_eq!(frame, v1.as_slice());
+
+            // both versions reach the handler of the message with the same payload
+            let (payload, id) = id_deserializer
+                .deserialize::<DeserializeError>(&v1)
+                .unwrap();
+            let receiver = match MessageTypeId::try_from(id).unwrap() {
+                MessageTypeId::Block => &receiver_blocks,
+                MessageTypeId::Endorsement => &receiver_endorsements,
+                MessageTypeId::Operation => &receiver_operations,
+                MessageTypeId::PeerManagement => &receiver_peers,
+                MessageTypeId::Compressed | MessageTypeId::Checksummed => {
+                    panic!("unexpected wrapping frame")
+                }
+            };
+            for data in [&v1, &v2] {
+                handler.handle(data, &peer_id).unwrap();
+                let (received_from, received) = receiver.try_recv().unwrap();
+                assert_eq!(received_from, peer_id);
+                assert_eq!(received, payload);
             }
-            // Peer management messages are low priority: we just drop the message if the channel is full
-            MessageTypeId::PeerManagement => {
-                if let Err(err) = self.sender_peers.try_send((*peer_id, data.to_vec())) {
-                    debug!("Failed to send peer message to channel: {}", err)
+        }
+    }
+
+    #[test]
+    fn test_unknown_message_format_version_is_rejected() {
+        let (sender_blocks, _) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, receiver_peers) = MassaChannel::new(String::from("test_peers"), None);
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        let mut v2 = Vec::new();
+        MessagesSerializer::new()
+            .with_peer_management_message_serializer(PeerManagementMessageSerializer::new())
+            .with_format_version(MessageFormatVersion::V2)
+            .serialize(
+                &PeerManagementMessage::Disconnect {
+                    reason: "node shutting down".to_string(),
                 }
-                Ok(())
+                .into(),
+                &mut v2,
+            )
+            .unwrap();
+        // a version from the future
+        let mut future_version = Vec::new();
+        U64VarIntSerializer::new()
+            .serialize(&(FORMAT_VERSION_TAG_BASE + 99), &mut future_version)
+            .unwrap();
+        future_version.extend_from_slice(&v2[2..]);
+
+        for data in [
+            future_version,
+            // truncated version tag
+            v2[..1].to_vec(),
+            vec![0xff; 12],
+            vec![],
+        ] {
+            assert!(handler.handle(&data, &peer_id).is_err());
+        }
+        assert!(receiver_peers.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_enveloped_messages_of_unknown_variants_are_skipped() {
+        let (sender_blocks, receiver_blocks) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let serializer =
+            MessagesSerializer::new().with_block_message_serializer(BlockMessageSerializer::new());
+        let message = || -> Message {
+            BlockMessage::DataRequest {
+                block_id: BlockId::generate_from_hash(Hash::compute_from(b"block")),
+                block_info: AskForBlockInfo::Header,
+            }
+            .into()
+        };
+        let mut plain = Vec::new();
+        serializer.serialize(&message(), &mut plain).unwrap();
+        let mut v3 = Vec::new();
+        serializer
+            .clone()
+            .with_format_version(MessageFormatVersion::V3)
+            .serialize(&message(), &mut v3)
+            .unwrap();
+        let envelope = |frame: &[u8]| -> Vec<u8> {
+            let mut data = Vec::new();
+            let id_serializer = U64VarIntSerializer::new();
+            id_serializer
+                .serialize(&MessageFormatVersion::V3.tag().unwrap(), &mut data)
+                .unwrap();
+            id_serializer
+                .serialize(&(frame.len() as u64), &mut data)
+                .unwrap();
+            data.extend_from_slice(frame);
+            data
+        };
+        assert_eq!(v3, envelope(&plain));
+
+        // a block message of a variant from the future, and a message of a type from the future
+        let future_variant = [u64::from(MessageTypeId::Block) as u8, 99, 1, 2, 3];
+        let future_type = [42, 1, 2, 3];
+        for frame in [&future_variant[..], &future_type[..]] {
+            assert!(handler.handle(&envelope(frame), &peer_id).is_ok());
+            assert!(receiver_blocks.try_recv().is_err());
+        }
+
+        // the connection goes on: the next messages are delivered
+        let mut with_extension = v3.clone();
+        with_extension.push(0);
+        for data in [&v3, &with_extension] {
+            handler.handle(data, &peer_id).unwrap();
+            let (received_from, received) = receiver_blocks.try_recv().unwrap();
+            assert_eq!(received_from, peer_id);
+            assert_eq!(received, plain[1..]);
+        }
+
+        // a known variant with a malformed payload reaches its handler, which bans the peer
+        let malformed = [u64::from(MessageTypeId::Block) as u8, 1, 0xff];
+        handler.handle(&envelope(&malformed), &peer_id).unwrap();
+        assert_eq!(receiver_blocks.try_recv().unwrap().1, malformed[1..]);
+
+        // without envelope, unknown types are rejected, and a truncated envelope is too
+        assert!(handler.handle(&future_type, &peer_id).is_err());
+        assert!(handler.handle(&v3[..v3.len() - 1], &peer_id).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_checksummed_frame_is_dropped() {
+        let (sender_blocks, receiver_blocks) = MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _) = MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _) = MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _) = MassaChannel::new(String::from("test_peers"), None);
+        let (peer_cmd_sender, peer_cmd_receiver) =
+            MassaChannel::new(String::from("test_peer_cmd"), None);
+        let peer_traffic = SharedPeerTraffic::default();
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: Some(MessageSizeLimiter::new(
+                &ProtocolConfig::default(),
+                peer_cmd_sender.clone(),
+            )),
+            corrupted_frames_limiter: Some(CorruptedFramesLimiter::new(
+                &ProtocolConfig {
+                    max_corrupted_frames: 1,
+                    ..Default::default()
+                },
+                peer_cmd_sender,
+            )),
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: Some(peer_traffic.clone()),
+            message_counters: None,
+            inbound_queues: None,
+        };
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let message = || -> Message {
+            BlockMessage::DataRequest {
+                block_id: BlockId::generate_from_hash(Hash::compute_from(b"block")),
+                block_info: AskForBlockInfo::Header,
             }
+            .into()
+        };
+        let serializer =
+            MessagesSerializer::new().with_block_message_serializer(BlockMessageSerializer::new());
+        let mut plain = Vec::new();
+        serializer.serialize(&message(), &mut plain).unwrap();
+        let mut data = Vec::new();
+        serializer
+            .clone()
+            .with_checksum()
+            .serialize(&message(), &mut data)
+            .unwrap();
+        let id_deserializer = U64VarIntDeserializer::new(Included(0), Included(u64::MAX));
+        let (_, id) = id_deserializer
+            .deserialize::<DeserializeError>(&data)
+            .unwrap();
+        assert_eq!(id, u64::from(MessageTypeId::Checksummed));
+
+        // an intact checksummed frame is delivered like the plain one
+        handler.handle(&data, &peer_id).unwrap();
+        let (received_from, received) = receiver_blocks.try_recv().unwrap();
+        assert_eq!(received_from, peer_id);
+        assert_eq!(received, plain[1..]);
+
+        // a flipped byte is dropped and counted, without banning the peer
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        assert!(handler.handle(&data, &peer_id).is_ok());
+        assert!(receiver_blocks.try_recv().is_err());
+        assert!(peer_cmd_receiver.try_recv().is_err());
+        assert_eq!(peer_traffic.read().peers[&peer_id].corrupted_frames, 1);
+        assert_eq!(peer_traffic.read().lifetime.corrupted_frames, 1);
+
+        // the peer is disconnected once it sends more corrupted frames than tolerated
+        assert!(handler.handle(&data, &peer_id).is_ok());
+        assert!(matches!(
+            peer_cmd_receiver.try_recv(),
+            Ok(PeerManagementCmd::Disconnect {
+                peer_id: disconnected,
+                graceful: false,
+            }) if disconnected == peer_id
+        ));
+    }
+
+    #[test]
+    fn test_message_counters_by_kind() {
+        let (sender_blocks, _receiver_blocks) =
+            MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _receiver_endorsements) =
+            MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, _receiver_operations) =
+            MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _receiver_peers) = MassaChannel::new(String::from("test_peers"), None);
+        let received_counters = Arc::new(MessageCounters::default());
+        let sent_counters = Arc::new(MessageCounters::default());
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: Some(received_counters.clone()),
+            inbound_queues: None,
+        };
+        let serializer = MessagesSerializer::new()
+            .with_block_message_serializer(BlockMessageSerializer::new())
+            .with_endorsement_message_serializer(EndorsementMessageSerializer::new())
+            .with_operation_message_serializer(OperationMessageSerializer::new())
+            .with_peer_management_message_serializer(PeerManagementMessageSerializer::new())
+            .with_message_counters(sent_counters.clone());
+        let keypair = KeyPair::generate(0).unwrap();
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        let block_id = BlockId::generate_from_hash(Hash::compute_from(b"block"));
+        let messages: Vec<(MessageKind, Message)> = vec![
+            (
+                MessageKind::BlockHeader,
+                BlockMessage::Header(create_block(&keypair).content.header).into(),
+            ),
+            (
+                MessageKind::BlockDataRequest,
+                BlockMessage::DataRequest {
+                    block_id,
+                    block_info: AskForBlockInfo::OperationIds,
+                }
+                .into(),
+            ),
+            (
+                MessageKind::BlockDataResponse,
+                BlockMessage::DataResponse {
+                    block_id,
+                    block_info: BlockInfoReply::NotFound,
+                }
+                .into(),
+            ),
+            (
+                MessageKind::CompactBlock,
+                BlockMessage::CompactBlock {
+                    header: create_block(&keypair).content.header,
+                    operation_ids: vec![],
+                }
+                .into(),
+            ),
+            (
+                MessageKind::Endorsements,
+                EndorsementMessage::Endorsements(vec![]).into(),
+            ),
+            (
+                MessageKind::EndorsementsAnnouncement,
+                EndorsementMessage::AnnounceIds(vec![]).into(),
+            ),
+            (
+                MessageKind::AskEndorsements,
+                EndorsementMessage::AskEndorsements(vec![]).into(),
+            ),
+            (
+                MessageKind::OperationsAnnouncement,
+                OperationMessage::OperationsAnnouncement(Default::default()).into(),
+            ),
+            (
+                MessageKind::AskForOperations,
+                OperationMessage::AskForOperations(Default::default()).into(),
+            ),
+            (
+                MessageKind::Operations,
+                OperationMessage::Operations(vec![]).into(),
+            ),
+            (
+                MessageKind::AskKnownOperationIds,
+                OperationMessage::AskKnownIds(0).into(),
+            ),
+            (
+                MessageKind::KnownOperationIds,
+                OperationMessage::KnownIds(vec![]).into(),
+            ),
+            (
+                MessageKind::NewPeerConnected,
+                PeerManagementMessage::NewPeerConnected((peer_id, HashMap::new())).into(),
+            ),
+            (
+                MessageKind::ListPeers,
+                PeerManagementMessage::ListPeers(vec![]).into(),
+            ),
+            (
+                MessageKind::Disconnect,
+                PeerManagementMessage::Disconnect {
+                    reason: "node shutting down".to_string(),
+                }
+                .into(),
+            ),
+            (MessageKind::Ping, PeerManagementMessage::Ping(1).into()),
+            (MessageKind::Pong, PeerManagementMessage::Pong(1).into()),
+        ];
+        assert_eq!(messages.len(), MessageKind::ALL.len());
+
+        for (kind, message) in messages {
+            assert_eq!(MessageKind::from(&message), kind);
+            assert_eq!(sent_counters.sent(kind), 0);
+            assert_eq!(received_counters.received(kind), 0);
+            let mut data = Vec::new();
+            serializer.serialize(&message, &mut data).unwrap();
+            assert_eq!(sent_counters.sent(kind), 1);
+            handler.handle(&data, &peer_id).unwrap();
+            assert_eq!(received_counters.received(kind), 1);
         }
+        // each message was counted once, on its side only
+        for kind in MessageKind::ALL {
+            assert_eq!(sent_counters.sent(kind), 1);
+            assert_eq!(sent_counters.received(kind), 0);
+            assert_eq!(received_counters.received(kind), 1);
+            assert_eq!(received_counters.sent(kind), 0);
+        }
+    }
+
+    #[test]
+    fn test_messages_dropped_on_full_channel_are_counted() {
+        let (sender_blocks, _receiver_blocks) =
+            MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _receiver_endorsements) =
+            MassaChannel::new(String::from("test_endorsements"), None);
+        // room for a single message, never read
+        let (sender_operations, receiver_operations) =
+            MassaChannel::new(String::from("test_operations"), Some(1));
+        let (sender_peers, _receiver_peers) = MassaChannel::new(String::from("test_peers"), None);
+        let message_counters = Arc::new(MessageCounters::default());
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: Some(message_counters.clone()),
+            inbound_queues: None,
+        };
+        let serializer = MessagesSerializer::new()
+            .with_operation_message_serializer(OperationMessageSerializer::new());
+        let mut data = Vec::new();
+        serializer
+            .serialize(
+                &Message::from(OperationMessage::Operations(vec![])),
+                &mut data,
+            )
+            .unwrap();
+        let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        for _ in 0..3 {
+            handler.handle(&data, &peer_id).unwrap();
+        }
+        assert_eq!(receiver_operations.len(), 1);
+        assert_eq!(message_counters.dropped(MessageCategory::Operation), 2);
+        for category in [
+            MessageCategory::Block,
+            MessageCategory::Endorsement,
+            MessageCategory::PeerManagement,
+        ] {
+            assert_eq!(message_counters.dropped(category), 0);
+        }
+    }
+
+    #[test]
+    fn test_reads_pause_for_the_peer_with_a_full_inbound_queue_only() {
+        let (sender_blocks, _receiver_blocks) =
+            MassaChannel::new(String::from("test_blocks"), None);
+        let (sender_endorsements, _receiver_endorsements) =
+            MassaChannel::new(String::from("test_endorsements"), None);
+        let (sender_operations, receiver_operations) =
+            MassaChannel::new(String::from("test_operations"), None);
+        let (sender_peers, _receiver_peers) = MassaChannel::new(String::from("test_peers"), None);
+        let message_counters = Arc::new(MessageCounters::default());
+        let handler = MessagesHandler {
+            id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+            sender_blocks,
+            sender_endorsements,
+            sender_operations,
+            sender_peers,
+            rate_limiter: None,
+            size_limiter: None,
+            corrupted_frames_limiter: None,
+            max_message_size: MAX_MESSAGE_SIZE as usize,
+            peer_traffic: None,
+            message_counters: Some(message_counters.clone()),
+            inbound_queues: Some(InboundQueues::new(
+                2,
+                Duration::from_secs(10),
+                Some(message_counters.clone()),
+            )),
+        };
+        let serializer = MessagesSerializer::new()
+            .with_operation_message_serializer(OperationMessageSerializer::new());
+        let mut data = Vec::new();
+        serializer
+            .serialize(
+                &Message::from(OperationMessage::Operations(vec![])),
+                &mut data,
+            )
+            .unwrap();
+        let flooding_peer_id =
+            PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+        let other_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+        // the messages of the flooding peer fill its queue, its next read waits
+        for _ in 0..2 {
+            handler.handle(&data, &flooding_peer_id).unwrap();
+        }
+        let (read_sender, read_receiver) = mpsc::channel();
+        let flooding_thread = std::thread::spawn({
+            let handler = handler.clone();
+            let data = data.clone();
+            move || {
+                handler.handle(&data, &flooding_peer_id).unwrap();
+                read_sender.send(()).unwrap();
+            }
+        });
+        assert!(read_receiver
+            .recv_timeout(Duration::from_millis(300))
+            .is_err());
+
+        // the other peers are still read
+        handler.handle(&data, &other_peer_id).unwrap();
+        assert_eq!(receiver_operations.len(), 3);
+
+        // the flooding peer is read again once one of its messages is processed
+        let (processed_peer_id, _) = receiver_operations.try_recv().unwrap();
+        assert_eq!(processed_peer_id, flooding_peer_id);
+        read_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the reads of the flooding peer didn't resume");
+        flooding_thread.join().unwrap();
+        assert_eq!(receiver_operations.len(), 3);
+        assert_eq!(message_counters.paused(MessageCategory::Operation), 1);
+        assert_eq!(message_counters.dropped(MessageCategory::Operation), 0);
     }
 }
@@ -0,0 +1,41 @@
+//! Randomness of the peer selections.
+//!
+//! The peers to advertise and the peers an operation is announced to are picked at random. The
+//! generators are seeded with `ProtocolConfig::rng_seed` when it is set, so that the tests can
+//! assert which peers are picked, and from the OS entropy otherwise.
+
+use parking_lot::{Mutex, MutexGuard};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// Generator of a peer selection, seeded with `seed` if any
+pub(crate) fn peer_selection_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Generator usable behind a shared reference, as the peer db is read concurrently
+pub(crate) struct SharedRng(Mutex<StdRng>);
+
+impl SharedRng {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        SharedRng(Mutex::new(peer_selection_rng(seed)))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, StdRng> {
+        self.0.lock()
+    }
+}
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        SharedRng::new(None)
+    }
+}
+
+impl Clone for SharedRng {
+    fn clone(&self) -> Self {
+        SharedRng(Mutex::new(self.lock().clone()))
+    }
+}
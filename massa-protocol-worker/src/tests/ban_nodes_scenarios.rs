@@ -1,30 +1,41 @@
 // Copyright (c) 2022 MASSA LABS <info@massa.net>
 
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
 use massa_models::{block_id::BlockId, prehash::PreHashSet, slot::Slot};
-use massa_protocol_exports::PeerId;
 use massa_protocol_exports::{test_exports::tools, ProtocolConfig};
+use massa_protocol_exports::{
+    BanContext, BanDecision, BanPolicy, BanReason, FirstOffensePolicy, PeerId,
+    ProtocolConfigUpdate, RejectionReason,
+};
 use massa_signature::KeyPair;
-use massa_test_framework::{TestUniverse, WaitPoint};
+use massa_test_framework::{TestUniverse, WaitPoint, DEFAULT_WAIT_TIMEOUT};
 use massa_time::MassaTime;
 use mockall::predicate;
 use parking_lot::{RwLock, RwLockWriteGuard};
 
-use crate::handlers::peer_handler::models::{PeerInfo, PeerState};
+use crate::handlers::peer_handler::models::{
+    BanSeverity, PeerDB, PeerInfo, PeerState, REPUTATION_DUPLICATE_HEADER,
+    REPUTATION_EARLY_ATTACK_RELAY, REPUTATION_REJECTED_HEADER, REPUTATION_STALE_HEADER,
+};
 use crate::wrap_network::{MockActiveConnectionsTrait, MockActiveConnectionsTraitWrapper};
-use crate::wrap_peer_db::MockPeerDBTrait;
+use crate::wrap_peer_db::{MockPeerDBTrait, PeerDBTrait};
 use crate::{
     handlers::{
         block_handler::{BlockInfoReply, BlockMessage},
+        endorsement_handler::EndorsementMessage,
         operation_handler::OperationMessage,
     },
     messages::Message,
 };
 
-use super::universe::{ProtocolForeignControllers, ProtocolTestUniverse};
+use super::tools::assert_no_message_to_node;
+use super::universe::{
+    ConnectedPeers, LinkProfile, ProtocolForeignControllers, ProtocolTestUniverse,
+};
 
 fn peer_db_boilerplate(mock_peer_db: &mut RwLockWriteGuard<MockPeerDBTrait>) {
     mock_peer_db
@@ -34,13 +45,29 @@ fn peer_db_boilerplate(mock_peer_db: &mut RwLockWriteGuard<MockPeerDBTrait>) {
     mock_peer_db
         .expect_get_rand_peers_to_send()
         .return_const(vec![]);
+    mock_peer_db
+        .expect_get_weighted_peers_to_send()
+        .return_const(vec![]);
+    mock_peer_db
+        .expect_unban_expired_peers()
+        .return_const(vec![]);
+    mock_peer_db.expect_tick_unban().return_const(vec![]);
+    mock_peer_db.expect_save_ban_list().return_const(());
+    mock_peer_db.expect_adjust_reputation().return_const(false);
+    mock_peer_db.expect_quarantine_peer().return_const(true);
+    mock_peer_db.expect_get_ban_details().return_const(None);
+    mock_peer_db.expect_update_ban_settings().return_const(());
+    mock_peer_db
+        .expect_get_quarantined_peers()
+        .return_const(HashSet::default());
 }
 
 #[test]
 fn test_protocol_bans_node_sending_block_header_with_invalid_signature() {
+    let unban_everyone_timer = MassaTime::from_millis(1000);
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        unban_everyone_timer: MassaTime::from_millis(1000),
+        unban_everyone_timer,
         ..Default::default()
     };
 
@@ -55,50 +82,37 @@ fn test_protocol_bans_node_sending_block_header_with_invalid_signature() {
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
 
-    let ban_waitpoint = WaitPoint::new();
-    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
-    let unban_waitpoint = WaitPoint::new();
-    let unban_waitpoint_trigger_handle = unban_waitpoint.get_trigger_handle();
+    let (ban_sender, ban_receiver) = mpsc::channel();
+    let (unban_sender, unban_receiver) = mpsc::channel();
 
     foreign_controllers
         .peer_db
         .write()
-        .expect_get_peers_mut()
-        .times(0..1)
-        .returning(move || {
-            let mut peers = HashMap::new();
-            peers.insert(
-                node_a_peer_id,
-                PeerInfo {
-                    last_announce: None,
-                    state: PeerState::Trusted,
-                },
-            );
-            peers
-        });
-    foreign_controllers
-        .peer_db
-        .write()
-        .expect_ban_peer()
-        .returning(move |peer_id| {
+        .expect_ban_peer_with_severity()
+        .returning(move |peer_id, reason, severity, _| {
             assert_eq!(peer_id, &node_a_peer_id);
-            ban_waitpoint_trigger_handle.trigger();
+            assert_eq!(reason, BanReason::InvalidBlockSignature);
+            assert_eq!(severity, BanSeverity::Major);
+            let _ = ban_sender.send(());
         });
-    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
         .peer_db
         .write()
-        .expect_unban_peer()
-        .returning(move |peer_id| {
-            assert_eq!(peer_id, &node_a_peer_id);
-            unban_waitpoint_trigger_handle.trigger();
+        .expect_tick_unban()
+        .returning(move |now| {
+            let _ = unban_sender.send(now);
+            vec![node_a_peer_id]
         });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     let mut peers = HashMap::new();
     peers.insert(
         node_a_peer_id,
         PeerInfo {
             last_announce: None,
             state: PeerState::Banned,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
         },
     );
     foreign_controllers
@@ -122,6 +136,9 @@ fn test_protocol_bans_node_sending_block_header_with_invalid_signature() {
                 peers.insert(node_a_peer_id);
                 peers
             });
+        active_connections
+            .expect_get_peers_connected()
+            .returning(HashMap::new);
         active_connections
             .expect_shutdown_connection()
             .times(1)
@@ -141,55 +158,63 @@ fn test_protocol_bans_node_sending_block_header_with_invalid_signature() {
             block_bad_public_key.content.header.clone(),
         ))),
     );
-    ban_waitpoint.wait();
+    ban_receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("node A wasn't banned");
 
-    // After `unban_everyone_timer` the node should be unbanned
-    unban_waitpoint.wait();
+    // the bans are only checked once the clock reaches `unban_everyone_timer`
+    assert!(unban_receiver.try_recv().is_err());
+    let unban_time = universe.clock.now().saturating_add(unban_everyone_timer);
+    universe.clock.advance(unban_everyone_timer);
+    assert_eq!(
+        unban_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the bans weren't checked"),
+        unban_time
+    );
 }
 
 #[test]
-fn test_protocol_bans_node_sending_operation_with_invalid_signature() {
+fn test_protocol_does_not_ban_whitelisted_node_sending_block_header_with_invalid_signature() {
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
     let protocol_config = ProtocolConfig {
         thread_count: 2,
+        ban_whitelist: HashSet::from([node_a_peer_id]),
         ..Default::default()
     };
 
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
 
-    let op_creator = KeyPair::generate(0).unwrap();
-    let mut operation = tools::create_operation_with_expire_period(&op_creator, 1);
-    operation.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
-    let node_a_keypair = KeyPair::generate(0).unwrap();
-    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
-
-    let ban_waitpoint = WaitPoint::new();
-    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let mut block_bad_public_key = block.clone();
+    block_bad_public_key.content.header.content_creator_pub_key =
+        KeyPair::generate(0).unwrap().get_public_key();
 
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
         .peer_db
         .write()
-        .expect_get_peers_mut()
-        .times(0..1)
-        .returning(move || {
-            let mut peers = HashMap::new();
-            peers.insert(
-                node_a_peer_id,
-                PeerInfo {
-                    last_announce: None,
-                    state: PeerState::Trusted,
-                },
-            );
-            peers
-        });
+        .expect_ban_peer_with_severity()
+        .times(0);
+    let mut peers = HashMap::new();
+    peers.insert(
+        node_a_peer_id,
+        PeerInfo {
+            last_announce: None,
+            state: PeerState::Trusted,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
+        },
+    );
     foreign_controllers
         .peer_db
         .write()
-        .expect_ban_peer()
-        .returning(move |peer_id| {
-            assert_eq!(peer_id, &node_a_peer_id);
-            ban_waitpoint_trigger_handle.trigger();
-        });
-    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+        .expect_get_peers()
+        .return_const(peers);
     let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
     shared_active_connections.set_expectations(|active_connections| {
         active_connections
@@ -200,10 +225,9 @@ fn test_protocol_bans_node_sending_operation_with_invalid_signature() {
                 peers
             });
         active_connections
-            .expect_shutdown_connection()
-            .times(1)
-            .with(predicate::eq(node_a_peer_id))
-            .returning(move |_| {});
+            .expect_get_peers_connected()
+            .returning(HashMap::new);
+        active_connections.expect_shutdown_connection().times(0);
     });
     foreign_controllers
         .network_controller
@@ -214,70 +238,58 @@ fn test_protocol_bans_node_sending_operation_with_invalid_signature() {
 
     universe.mock_message_receive(
         &node_a_peer_id,
-        Message::Operation(OperationMessage::Operations(vec![operation])),
+        Message::Block(Box::new(BlockMessage::Header(
+            block_bad_public_key.content.header.clone(),
+        ))),
     );
-    ban_waitpoint.wait();
+    // leave time for the ban command to reach the peer handler
+    std::thread::sleep(Duration::from_millis(1000));
+}
+
+/// Ban policy of a deployment that prefers keeping its peers whatever they send
+#[derive(Debug)]
+struct NeverBanPolicy;
+
+impl BanPolicy for NeverBanPolicy {
+    fn on_offense(&self, _peer_id: &PeerId, _ctx: &BanContext) -> BanDecision {
+        BanDecision::Ignore
+    }
 }
 
 #[test]
-fn test_protocol_bans_node_sending_header_with_invalid_signature() {
+fn test_protocol_custom_ban_policy_keeps_node_sending_block_header_with_invalid_signature() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
+        ban_policy: Arc::new(NeverBanPolicy),
         ..Default::default()
     };
-
-    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
-
-    let block_creator = KeyPair::generate(0).unwrap();
-    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
-    let block = ProtocolTestUniverse::create_block(
-        &block_creator,
-        Slot::new(1, 1),
-        vec![operation_1],
-        vec![],
-        vec![],
-    );
-    let operation_2 = ProtocolTestUniverse::create_operation(&block_creator, 1);
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
 
-    let ban_waitpoint = WaitPoint::new();
-    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
 
-    let send_message_waitpoint = WaitPoint::new();
-    let send_message_waitpoint_trigger_handle = send_message_waitpoint.get_trigger_handle();
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let mut block_bad_public_key = block.clone();
+    block_bad_public_key.content.header.content_creator_pub_key =
+        KeyPair::generate(0).unwrap().get_public_key();
 
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
         .peer_db
         .write()
-        .expect_get_peers_mut()
-        .times(0..1)
-        .returning(move || {
-            let mut peers = HashMap::new();
-            peers.insert(
-                node_a_peer_id,
-                PeerInfo {
-                    last_announce: None,
-                    state: PeerState::Trusted,
-                },
-            );
-            peers
-        });
-    foreign_controllers
-        .peer_db
-        .write()
-        .expect_ban_peer()
-        .returning(move |peer_id| {
-            assert_eq!(peer_id, &node_a_peer_id);
-            ban_waitpoint_trigger_handle.trigger();
-        });
-    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+        .expect_ban_peer_with_severity()
+        .times(0);
     let mut peers = HashMap::new();
     peers.insert(
         node_a_peer_id,
         PeerInfo {
             last_announce: None,
-            state: PeerState::Banned,
+            state: PeerState::Trusted,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
         },
     );
     foreign_controllers
@@ -285,40 +297,20 @@ fn test_protocol_bans_node_sending_header_with_invalid_signature() {
         .write()
         .expect_get_peers()
         .return_const(peers);
-    foreign_controllers
-        .consensus_controller
-        .expect_register_block_header()
-        .return_once(move |block_id, header| {
-            assert_eq!(block_id, block.id);
-            assert_eq!(header.id, block.content.header.id);
-        });
     let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
-    shared_active_connections.set_expectations(
-        |active_connections: &mut MockActiveConnectionsTrait| {
-            active_connections
-                .expect_get_peer_ids_connected()
-                .returning(move || {
-                    let mut peers = HashSet::new();
-                    peers.insert(node_a_peer_id);
-                    peers
-                });
-            active_connections
-                .expect_shutdown_connection()
-                .times(1)
-                .with(predicate::eq(node_a_peer_id))
-                .returning(move |_| {});
-
-            active_connections.expect_send_to_peer().times(1).returning(
-                move |peer_id, _, _, high_priority| {
-                    assert_eq!(peer_id, &node_a_peer_id);
-                    //TODO: Add check messages
-                    assert!(high_priority);
-                    send_message_waitpoint_trigger_handle.trigger();
-                    Ok(())
-                },
-            );
-        },
-    );
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_get_peer_ids_connected()
+            .returning(move || {
+                let mut peers = HashSet::new();
+                peers.insert(node_a_peer_id);
+                peers
+            });
+        active_connections
+            .expect_get_peers_connected()
+            .returning(HashMap::new);
+        active_connections.expect_shutdown_connection().times(0);
+    });
     foreign_controllers
         .network_controller
         .expect_get_active_connections()
@@ -328,84 +320,81 @@ fn test_protocol_bans_node_sending_header_with_invalid_signature() {
 
     universe.mock_message_receive(
         &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
-    );
-
-    universe
-        .module_controller
-        .send_wishlist_delta(
-            vec![(block.id, Some(block.content.header.clone()))]
-                .into_iter()
-                .collect(),
-            PreHashSet::<BlockId>::default(),
-        )
-        .unwrap();
-    send_message_waitpoint.wait();
-
-    universe.mock_message_receive(
-        &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::DataResponse {
-            block_id: block.id,
-            block_info: BlockInfoReply::OperationIds(vec![operation_2.id]),
-        })),
+        Message::Block(Box::new(BlockMessage::Header(
+            block_bad_public_key.content.header.clone(),
+        ))),
     );
-    ban_waitpoint.wait();
+    // leave time for the ban command to reach the peer handler
+    std::thread::sleep(Duration::from_millis(1000));
 }
 
 #[test]
-fn test_protocol_does_not_asks_for_block_from_banned_node_who_propagated_header() {
+fn test_protocol_keeps_attack_ban_past_operation_ban_duration() {
+    let mut ban_durations = HashMap::new();
+    ban_durations.insert(
+        BanReason::InvalidOperationSignature,
+        MassaTime::from_millis(500),
+    );
+    ban_durations.insert(
+        BanReason::AttackPropagation,
+        MassaTime::from_millis(60 * 60 * 1000),
+    );
     let protocol_config = ProtocolConfig {
         thread_count: 2,
+        ban_durations,
         ..Default::default()
     };
 
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
 
-    let block_creator = KeyPair::generate(0).unwrap();
-    let block =
-        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
-    let mut bad_block = block.clone();
-    bad_block.content.header.content_creator_pub_key =
-        KeyPair::generate(0).unwrap().get_public_key();
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
-    let ban_waitpoint = WaitPoint::new();
-    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+    let unban_waitpoint = WaitPoint::named("unban");
+    let unban_waitpoint_trigger_handle = unban_waitpoint.get_trigger_handle();
+    let attacker_unbanned = Arc::new(RwLock::new(false));
+    let attacker_unbanned_clone = attacker_unbanned.clone();
 
-    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // node A was banned for an operation with an invalid signature
     foreign_controllers
         .peer_db
         .write()
-        .expect_get_peers_mut()
-        .times(0..1)
-        .returning(move || {
-            let mut peers = HashMap::new();
-            peers.insert(
-                node_a_peer_id,
-                PeerInfo {
-                    last_announce: None,
-                    state: PeerState::Trusted,
-                },
-            );
-            peers
+        .expect_unban_peer()
+        .with(predicate::eq(node_a_peer_id))
+        .returning(move |_| {
+            unban_waitpoint_trigger_handle.trigger();
         });
+    // node B was banned for propagating an attack
     foreign_controllers
         .peer_db
         .write()
-        .expect_ban_peer()
-        .times(1)
-        .returning(move |peer_id| {
-            assert_eq!(peer_id, &node_a_peer_id);
-            ban_waitpoint_trigger_handle.trigger();
+        .expect_unban_peer()
+        .with(predicate::eq(node_b_peer_id))
+        .returning(move |_| {
+            *attacker_unbanned_clone.write() = true;
         });
-    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     let mut peers = HashMap::new();
     peers.insert(
         node_a_peer_id,
         PeerInfo {
             last_announce: None,
             state: PeerState::Banned,
+            ban_reason: Some((BanReason::InvalidOperationSignature, MassaTime::now())),
+            reputation: 0,
+            features: None,
+        },
+    );
+    peers.insert(
+        node_b_peer_id,
+        PeerInfo {
+            last_announce: None,
+            state: PeerState::Banned,
+            ban_reason: Some((BanReason::AttackPropagation, MassaTime::now())),
+            reputation: 0,
+            features: None,
         },
     );
     foreign_controllers
@@ -413,62 +402,92 @@ fn test_protocol_does_not_asks_for_block_from_banned_node_who_propagated_header(
         .write()
         .expect_get_peers()
         .return_const(peers);
-    foreign_controllers
-        .consensus_controller
-        .expect_register_block_header()
-        .return_once(move |block_id, header| {
-            assert_eq!(block_id, block.id);
-            assert_eq!(header.id, block.content.header.id);
-        });
-    shared_active_connections.set_expectations(
-        |active_connections: &mut MockActiveConnectionsTrait| {
-            active_connections
-                .expect_get_peer_ids_connected()
-                .returning(HashSet::new);
-            active_connections
-                .expect_shutdown_connection()
-                .times(1)
-                .with(predicate::eq(node_a_peer_id))
-                .returning(move |_| {});
-        },
-    );
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_get_peer_ids_connected()
+            .returning(HashSet::new);
+        active_connections
+            .expect_get_peers_connected()
+            .returning(HashMap::new);
+    });
     foreign_controllers
         .network_controller
         .expect_get_active_connections()
         .returning(move || Box::new(shared_active_connections.clone()));
 
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let _universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
 
-    universe.mock_message_receive(
-        &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
-    );
+    // After the operation ban duration node A should be unbanned
+    unban_waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+    // node B stays banned for longer
+    std::thread::sleep(Duration::from_millis(600));
+    assert!(!*attacker_unbanned.read());
+}
 
-    universe.mock_message_receive(
-        &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::Header(
-            bad_block.content.header.clone(),
-        ))),
-    );
+#[test]
+fn test_protocol_bans_node_sending_operation_with_invalid_signature() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
 
-    ban_waitpoint.wait();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
 
-    universe
-        .module_controller
-        .send_wishlist_delta(
-            vec![(block.id, Some(block.content.header.clone()))]
-                .into_iter()
-                .collect(),
-            PreHashSet::<BlockId>::default(),
-        )
-        .unwrap();
+    let op_creator = KeyPair::generate(0).unwrap();
+    let mut operation = tools::create_operation_with_expire_period(&op_creator, 1);
+    operation.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
 
-    //TODO: Find a way to check that no message will never be sent
-    std::thread::sleep(Duration::from_millis(1000));
+    let ban_waitpoint = WaitPoint::named("ban");
+    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .returning(move |peer_id, reason, severity, _| {
+            assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::InvalidOperationSignature);
+            assert_eq!(severity, BanSeverity::Major);
+            ban_waitpoint_trigger_handle.trigger();
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_get_peer_ids_connected()
+            .returning(move || {
+                let mut peers = HashSet::new();
+                peers.insert(node_a_peer_id);
+                peers
+            });
+        active_connections
+            .expect_get_peers_connected()
+            .returning(HashMap::new);
+        active_connections
+            .expect_shutdown_connection()
+            .times(1)
+            .with(predicate::eq(node_a_peer_id))
+            .returning(move |_| {});
+    });
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![operation])),
+    );
+    ban_waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
 }
 
 #[test]
-fn test_protocol_bans_all_nodes_propagating_an_attack_attempt() {
+fn test_protocol_bans_node_sending_endorsement_with_invalid_signature() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
         ..Default::default()
@@ -476,85 +495,215 @@ fn test_protocol_bans_all_nodes_propagating_an_attack_attempt() {
 
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
 
-    let block_creator = KeyPair::generate(0).unwrap();
-    let block =
-        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let endorsement_creator = KeyPair::generate(0).unwrap();
+    let valid_endorsement =
+        ProtocolTestUniverse::create_endorsement(&endorsement_creator, Slot::new(1, 0));
+    let mut tampered_endorsement =
+        ProtocolTestUniverse::create_endorsement(&endorsement_creator, Slot::new(1, 1));
+    tampered_endorsement.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
-    let node_b_keypair = KeyPair::generate(0).unwrap();
-    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
-    let ban_waitpoint = WaitPoint::new();
+    let ban_waitpoint = WaitPoint::named("ban");
     let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
-    let ban_waitpoint_trigger_handle_2 = ban_waitpoint.get_trigger_handle();
 
     foreign_controllers
         .peer_db
         .write()
-        .expect_get_peers_mut()
-        .times(0..1)
-        .returning(move || {
-            let mut peers = HashMap::new();
-            peers.insert(
-                node_a_peer_id,
-                PeerInfo {
-                    last_announce: None,
-                    state: PeerState::Trusted,
-                },
-            );
-            peers.insert(
-                node_b_peer_id,
-                PeerInfo {
-                    last_announce: None,
-                    state: PeerState::Trusted,
-                },
-            );
-            peers
+        .expect_ban_peer()
+        .returning(move |peer_id, reason, _| {
+            assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::InvalidEndorsementSignature);
+            ban_waitpoint_trigger_handle.trigger();
         });
-    let mut peers = HashMap::new();
-    peers.insert(
-        node_a_peer_id,
-        PeerInfo {
-            last_announce: None,
-            state: PeerState::Banned,
-        },
-    );
-    peers.insert(
-        node_b_peer_id,
-        PeerInfo {
-            last_announce: None,
-            state: PeerState::Banned,
-        },
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_get_peer_ids_connected()
+            .returning(move || {
+                let mut peers = HashSet::new();
+                peers.insert(node_a_peer_id);
+                peers
+            });
+        active_connections
+            .expect_get_peers_connected()
+            .returning(HashMap::new);
+        active_connections
+            .expect_shutdown_connection()
+            .times(1)
+            .with(predicate::eq(node_a_peer_id))
+            .returning(move |_| {});
+    });
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    // the whole batch is discarded, including the valid endorsement
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller.expect_add_endorsements().never();
+        });
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Endorsement(EndorsementMessage::Endorsements(vec![
+            valid_endorsement,
+            tampered_endorsement,
+        ])),
     );
-    let counter = Arc::new(RwLock::new(0));
-    let counter_clone = counter.clone();
+    ban_waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+}
+
+#[test]
+fn test_protocol_disconnects_first_offender_then_bans_it() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        first_offense_policy: FirstOffensePolicy::DisconnectThenBan,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+
+    let endorsement_creator = KeyPair::generate(0).unwrap();
+    let tampered_endorsement = |slot| {
+        let mut endorsement = ProtocolTestUniverse::create_endorsement(&endorsement_creator, slot);
+        endorsement.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
+        endorsement
+    };
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    // the pending offenses are kept by a real peer db
+    let peer_db = Arc::new(RwLock::new(PeerDB::new(&protocol_config, MassaTime::now())));
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_mark_first_offense()
+        .returning(move |peer_id, now| peer_db.write().mark_first_offense(peer_id, now));
+    let (ban_sender, ban_receiver) = mpsc::channel();
     foreign_controllers
         .peer_db
         .write()
         .expect_ban_peer()
-        .with(predicate::eq(node_a_peer_id))
         .times(1)
-        .returning(move |_| {
-            let mut counter = counter.write();
-            *counter += 1;
-            if *counter == 2 {
-                ban_waitpoint_trigger_handle.trigger();
-            }
+        .returning(move |peer_id, reason, _| {
+            assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::InvalidEndorsementSignature);
+            let _ = ban_sender.send(());
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let connected_peers = ConnectedPeers::new([node_a_peer_id]);
+    let (shutdown_sender, shutdown_receiver) = mpsc::channel();
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(|active_connections| {
+        let connected_peers = connected_peers.clone();
+        active_connections
+            .expect_shutdown_connection()
+            .times(2)
+            .with(predicate::eq(node_a_peer_id))
+            .returning(move |peer_id| {
+                connected_peers.drop_connection(peer_id);
+                let _ = shutdown_sender.send(());
+            });
+    });
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &connected_peers,
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller.expect_add_endorsements().never();
         });
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // the first offense only closes the connection
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Endorsement(EndorsementMessage::Endorsements(vec![
+            tampered_endorsement(Slot::new(1, 0)),
+        ])),
+    );
+    shutdown_receiver
+        .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+        .unwrap();
+    assert!(ban_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+
+    // the peer reconnects and offends again, it is banned
+    connected_peers.connect(node_a_peer_id);
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Endorsement(EndorsementMessage::Endorsements(vec![
+            tampered_endorsement(Slot::new(1, 1)),
+        ])),
+    );
+    ban_receiver.recv_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+    shutdown_receiver
+        .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+        .unwrap();
+}
+
+#[test]
+fn test_protocol_bans_node_sending_header_with_invalid_signature() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, 1),
+        vec![operation_1],
+        vec![],
+        vec![],
+    );
+    let operation_2 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let ban_waitpoint = WaitPoint::named("ban");
+    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+
+    let send_message_waitpoint = WaitPoint::new();
+    let send_message_waitpoint_trigger_handle = send_message_waitpoint.get_trigger_handle();
+
     foreign_controllers
         .peer_db
         .write()
-        .expect_ban_peer()
-        .with(predicate::eq(node_b_peer_id))
-        .times(1)
-        .returning(move |_| {
-            let mut counter = counter_clone.write();
-            *counter += 1;
-            if *counter == 2 {
-                ban_waitpoint_trigger_handle_2.trigger();
-            }
+        .expect_ban_peer_with_severity()
+        .returning(move |peer_id, reason, severity, _| {
+            assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::InvalidOperationList);
+            assert_eq!(severity, BanSeverity::Major);
+            ban_waitpoint_trigger_handle.trigger();
         });
     peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut peers = HashMap::new();
+    peers.insert(
+        node_a_peer_id,
+        PeerInfo {
+            last_announce: None,
+            state: PeerState::Banned,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
+        },
+    );
     foreign_controllers
         .peer_db
         .write()
@@ -575,19 +724,26 @@ fn test_protocol_bans_all_nodes_propagating_an_attack_attempt() {
                 .returning(move || {
                     let mut peers = HashSet::new();
                     peers.insert(node_a_peer_id);
-                    peers.insert(node_b_peer_id);
                     peers
                 });
             active_connections
-                .expect_shutdown_connection()
-                .times(1)
-                .with(predicate::eq(node_a_peer_id))
-                .returning(move |_| {});
+                .expect_get_peers_connected()
+                .returning(HashMap::new);
             active_connections
                 .expect_shutdown_connection()
                 .times(1)
-                .with(predicate::eq(node_b_peer_id))
+                .with(predicate::eq(node_a_peer_id))
                 .returning(move |_| {});
+
+            active_connections.expect_send_to_peer().times(1).returning(
+                move |peer_id, _, _, high_priority| {
+                    assert_eq!(peer_id, &node_a_peer_id);
+                    //TODO: Add check messages
+                    assert!(high_priority);
+                    send_message_waitpoint_trigger_handle.trigger();
+                    Ok(())
+                },
+            );
         },
     );
     foreign_controllers
@@ -602,18 +758,978 @@ fn test_protocol_bans_all_nodes_propagating_an_attack_attempt() {
         Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
     );
 
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    send_message_waitpoint.wait();
+
     universe.mock_message_receive(
-        &node_b_peer_id,
-        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![operation_2.id]),
+        })),
     );
+    ban_waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+}
 
-    // TODO: Find a way to wait for both previous messages to be processed because it doesn't call any mock for second block same as first
-    std::thread::sleep(Duration::from_millis(1000));
+#[test]
+fn test_protocol_bans_node_sending_oversized_operation_id_list() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_operations_per_block: 2,
+        ..Default::default()
+    };
 
-    universe
-        .module_controller
-        .notify_block_attack(block.id)
-        .unwrap();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
 
-    ban_waitpoint.wait();
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, 1),
+        vec![operation_1],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let ban_waitpoint = WaitPoint::named("ban");
+    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .returning(move |peer_id, reason, severity, _| {
+            assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::ProtocolViolation);
+            assert_eq!(severity, BanSeverity::Major);
+            ban_waitpoint_trigger_handle.trigger();
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_get_peers()
+        .return_const(HashMap::new());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(
+        |active_connections: &mut MockActiveConnectionsTrait| {
+            active_connections
+                .expect_get_peer_ids_connected()
+                .returning(move || [node_a_peer_id].into_iter().collect());
+            active_connections
+                .expect_get_peers_connected()
+                .returning(HashMap::new);
+            active_connections
+                .expect_shutdown_connection()
+                .times(1)
+                .with(predicate::eq(node_a_peer_id))
+                .returning(move |_| {});
+        },
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // more operation ids than a block can contain
+    let operation_ids = (1..=3)
+        .map(|expire_period| {
+            ProtocolTestUniverse::create_operation(&block_creator, expire_period).id
+        })
+        .collect();
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(operation_ids),
+        })),
+    );
+    ban_waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+}
+
+#[test]
+fn test_protocol_does_not_asks_for_block_from_banned_node_who_propagated_header() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let mut bad_block = block.clone();
+    bad_block.content.header.content_creator_pub_key =
+        KeyPair::generate(0).unwrap().get_public_key();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::peer_state_boilerplate(
+        &mut foreign_controllers.peer_db.write(),
+        &foreign_controllers.recorded_peers,
+    );
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    shared_active_connections.set_expectations(
+        |active_connections: &mut MockActiveConnectionsTrait| {
+            active_connections
+                .expect_get_peer_ids_connected()
+                .returning(HashSet::new);
+            active_connections
+                .expect_get_peers_connected()
+                .returning(HashMap::new);
+            active_connections
+                .expect_shutdown_connection()
+                .times(1)
+                .with(predicate::eq(node_a_peer_id))
+                .returning(move |_| {});
+        },
+    );
+    let node_a_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        LinkProfile {
+            latency: Duration::ZERO,
+            loss_rate: 0.0,
+        },
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            bad_block.content.header.clone(),
+        ))),
+    );
+
+    universe.wait_until_peer_state(&node_a_peer_id, PeerState::Banned, DEFAULT_WAIT_TIMEOUT);
+    assert_eq!(
+        universe
+            .peer_info(&node_a_peer_id)
+            .and_then(|peer| peer.ban_reason)
+            .map(|(reason, _)| reason),
+        Some(BanReason::InvalidBlockSignature)
+    );
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+
+    assert_no_message_to_node(
+        &node_a_peer_id,
+        &node_a_messages,
+        Duration::from_millis(1000),
+    );
+}
+
+#[test]
+fn test_protocol_bans_all_nodes_propagating_an_attack_attempt() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    // header sent after the others, its registration tells that they were processed
+    let last_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let ban_waitpoint = WaitPoint::named("ban");
+    let ban_waitpoint_trigger_handle = ban_waitpoint.get_trigger_handle();
+    let ban_waitpoint_trigger_handle_2 = ban_waitpoint.get_trigger_handle();
+
+    let mut peers = HashMap::new();
+    peers.insert(
+        node_a_peer_id,
+        PeerInfo {
+            last_announce: None,
+            state: PeerState::Banned,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
+        },
+    );
+    peers.insert(
+        node_b_peer_id,
+        PeerInfo {
+            last_announce: None,
+            state: PeerState::Banned,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
+        },
+    );
+    let counter = Arc::new(RwLock::new(0));
+    let counter_clone = counter.clone();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .with(
+            predicate::eq(node_a_peer_id),
+            predicate::eq(BanReason::AttackPropagation),
+            predicate::eq(BanSeverity::Critical),
+            predicate::always(),
+        )
+        .times(1)
+        .returning(move |_, _, _, _| {
+            let mut counter = counter.write();
+            *counter += 1;
+            if *counter == 2 {
+                ban_waitpoint_trigger_handle.trigger();
+            }
+        });
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .with(
+            predicate::eq(node_b_peer_id),
+            predicate::eq(BanReason::AttackPropagation),
+            predicate::eq(BanSeverity::Critical),
+            predicate::always(),
+        )
+        .times(1)
+        .returning(move |_, _, _, _| {
+            let mut counter = counter_clone.write();
+            *counter += 1;
+            if *counter == 2 {
+                ban_waitpoint_trigger_handle_2.trigger();
+            }
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (registered_sender, registered_receiver) = mpsc::channel();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_get_peers()
+        .return_const(peers);
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| {
+            registered_sender.send(block_id).unwrap();
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(
+        |active_connections: &mut MockActiveConnectionsTrait| {
+            active_connections
+                .expect_get_peer_ids_connected()
+                .returning(move || {
+                    let mut peers = HashSet::new();
+                    peers.insert(node_a_peer_id);
+                    peers.insert(node_b_peer_id);
+                    peers
+                });
+            active_connections
+                .expect_get_peers_connected()
+                .returning(HashMap::new);
+            active_connections
+                .expect_shutdown_connection()
+                .times(1)
+                .with(predicate::eq(node_a_peer_id))
+                .returning(move |_| {});
+            active_connections
+                .expect_shutdown_connection()
+                .times(1)
+                .with(predicate::eq(node_b_peer_id))
+                .returning(move |_| {});
+        },
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            last_block.content.header.clone(),
+        ))),
+    );
+    for block_id in [block.id, last_block.id] {
+        assert_eq!(
+            registered_receiver
+                .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+                .expect("the header wasn't registered"),
+            block_id
+        );
+    }
+
+    universe
+        .module_controller
+        .notify_block_attack(block.id)
+        .unwrap();
+
+    ban_waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+}
+
+#[test]
+fn test_protocol_spares_nodes_relaying_an_attack_once_before_its_detection() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        attack_relay_grace: true,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    // header sent after the others, its registration tells that they were processed
+    let last_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let (ban_sender, ban_receiver) = mpsc::channel();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .returning(move |peer_id, reason, severity, _| {
+            assert_eq!(reason, BanReason::AttackPropagation);
+            assert_eq!(severity, BanSeverity::Critical);
+            ban_sender.send(*peer_id).unwrap();
+        });
+    let (reputation_sender, reputation_receiver) = mpsc::channel();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_adjust_reputation()
+        .returning(move |peer_id, delta| {
+            reputation_sender.send((*peer_id, delta)).unwrap();
+            false
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_get_peers()
+        .return_const(HashMap::new());
+    let (registered_sender, registered_receiver) = mpsc::channel();
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| {
+            registered_sender.send(block_id).unwrap();
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id, node_b_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // node A relays the header once, node B keeps on relaying it
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    for _ in 0..2 {
+        universe.mock_message_receive(
+            &node_b_peer_id,
+            Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        );
+    }
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            last_block.content.header.clone(),
+        ))),
+    );
+    for block_id in [block.id, last_block.id] {
+        assert_eq!(
+            registered_receiver
+                .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+                .expect("the header wasn't registered"),
+            block_id
+        );
+    }
+
+    universe
+        .module_controller
+        .notify_block_attack(block.id)
+        .unwrap();
+
+    assert_eq!(
+        ban_receiver.recv_timeout(DEFAULT_WAIT_TIMEOUT).unwrap(),
+        node_b_peer_id
+    );
+    assert_eq!(
+        reputation_receiver
+            .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+            .unwrap(),
+        (node_a_peer_id, REPUTATION_EARLY_ATTACK_RELAY)
+    );
+    assert!(ban_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+
+    // relaying the block once it is known as an attack is not forgiven
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    assert_eq!(
+        ban_receiver.recv_timeout(DEFAULT_WAIT_TIMEOUT).unwrap(),
+        node_a_peer_id
+    );
+}
+
+#[test]
+fn test_protocol_applies_rate_limit_lowered_at_runtime() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_operation_messages_per_sec: 1000,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let banned = Arc::new(AtomicBool::new(false));
+    let banned_clone = banned.clone();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .returning(move |peer_id, reason, severity, _| {
+            assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::RateLimitExceeded);
+            assert_eq!(severity, BanSeverity::Major);
+            banned_clone.store(true, Ordering::SeqCst);
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let send_announcements = |count: usize| {
+        for _ in 0..count {
+            universe.mock_message_receive(
+                &node_a_peer_id,
+                Message::Operation(OperationMessage::OperationsAnnouncement(Default::default())),
+            );
+        }
+    };
+
+    // well below the configured limit
+    send_announcements(10);
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!banned.load(Ordering::SeqCst));
+
+    // the settings that can't be changed at runtime are rejected
+    let update: ProtocolConfigUpdate = serde_json::from_str(r#"{"thread_count": 16}"#).unwrap();
+    assert!(universe.module_controller.update_config(update).is_err());
+
+    universe
+        .module_controller
+        .update_config(ProtocolConfigUpdate {
+            max_operation_messages_per_sec: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+    // the same traffic now exceeds the limit once the update is applied
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !banned.load(Ordering::SeqCst) {
+        assert!(
+            Instant::now() < deadline,
+            "the lowered rate limit wasn't applied"
+        );
+        send_announcements(10);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_protocol_penalizes_node_sending_header_rejected_by_consensus() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    let block_creator = KeyPair::generate(0).unwrap();
+    let known_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let rejected_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let (reputation_sender, reputation_receiver) = mpsc::channel();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_adjust_reputation()
+        .returning(move |peer_id, delta| {
+            reputation_sender.send((*peer_id, delta)).unwrap();
+            false
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (header_sender, header_receiver) = mpsc::channel();
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| header_sender.send(block_id).unwrap());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id, node_b_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            known_block.content.header.clone(),
+        ))),
+    );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            rejected_block.content.header.clone(),
+        ))),
+    );
+    for _ in 0..2 {
+        header_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the header wasn't sent to consensus");
+    }
+
+    // nobody is at fault for a header consensus already knew
+    universe
+        .module_controller
+        .notify_header_rejected(known_block.id, RejectionReason::AlreadyKnown)
+        .unwrap();
+    universe
+        .module_controller
+        .notify_header_rejected(rejected_block.id, RejectionReason::InvalidParents)
+        .unwrap();
+
+    assert_eq!(
+        reputation_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the reputation of the node wasn't lowered"),
+        (node_b_peer_id, REPUTATION_REJECTED_HEADER)
+    );
+    assert!(reputation_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+}
+
+#[test]
+fn test_protocol_penalizes_node_announcing_blocks_it_does_not_serve() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(200),
+        withholding_min_asks: 3,
+        withholding_max_percent: 50,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let blocks: Vec<_> = [Slot::new(1, 0), Slot::new(1, 1), Slot::new(2, 0)]
+        .into_iter()
+        .map(|slot| {
+            ProtocolTestUniverse::create_block(&block_creator, slot, vec![], vec![], vec![])
+        })
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_state_boilerplate(
+        &mut foreign_controllers.peer_db.write(),
+        &foreign_controllers.recorded_peers,
+    );
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (registered_sender, registered_receiver) = mpsc::channel();
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| {
+            registered_sender.send(block_id).unwrap();
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let node_a_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        LinkProfile {
+            latency: Duration::ZERO,
+            loss_rate: 0.0,
+        },
+    );
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &ConnectedPeers::new([node_a_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    // node A announces the blocks, then we want them and it never answers the asks for their data
+    for block in &blocks {
+        universe.mock_message_receive(
+            &node_a_peer_id,
+            Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        );
+    }
+    for _ in &blocks {
+        registered_receiver
+            .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+            .expect("the header wasn't registered");
+    }
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            blocks
+                .iter()
+                .map(|block| (block.id, Some(block.content.header.clone())))
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+
+    // it is asked all the blocks before being penalized
+    let mut asked_blocks = HashSet::new();
+    while asked_blocks.len() < blocks.len() {
+        if let Message::Block(message) = node_a_messages
+            .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+            .expect("node A wasn't asked all the blocks")
+        {
+            if let BlockMessage::DataRequest { block_id, .. } = *message {
+                asked_blocks.insert(block_id);
+            }
+        }
+    }
+    assert_eq!(
+        asked_blocks,
+        blocks.iter().map(|block| block.id).collect::<HashSet<_>>()
+    );
+
+    universe.clock.advance(protocol_config.ask_block_timeout);
+    universe.wait_until_peer_state(&node_a_peer_id, PeerState::Banned, DEFAULT_WAIT_TIMEOUT);
+    assert_eq!(
+        universe
+            .peer_info(&node_a_peer_id)
+            .and_then(|peer| peer.ban_reason)
+            .map(|(reason, _)| reason),
+        Some(BanReason::WithholdingData)
+    );
+}
+
+#[test]
+fn test_protocol_drops_headers_too_far_in_the_future() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        t0: MassaTime::from_millis(16000),
+        genesis_timestamp: MassaTime::now(),
+        max_future_slots: 4,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    // a couple of slots after the current one, as produced by a node slightly ahead of us
+    let near_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let far_block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1000, 0),
+        vec![],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_state_boilerplate(
+        &mut foreign_controllers.peer_db.write(),
+        &foreign_controllers.recorded_peers,
+    );
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (header_sender, header_receiver) = mpsc::channel();
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| header_sender.send(block_id).unwrap());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            far_block.content.header.clone(),
+        ))),
+    );
+    // the far future header costs the node its reputation, as a minor offense
+    universe.wait_until_peer_state(
+        &node_a_peer_id,
+        PeerState::Quarantined,
+        DEFAULT_WAIT_TIMEOUT,
+    );
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            near_block.content.header.clone(),
+        ))),
+    );
+    assert_eq!(
+        header_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the header in the tolerated window wasn't sent to consensus"),
+        near_block.id
+    );
+    assert!(header_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+}
+
+#[test]
+fn test_protocol_drops_headers_long_finalized() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_past_slots: 10,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let stale_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    // just before the latest final slot of its thread
+    let recent_block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(99, 1),
+        vec![],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller
+                .expect_get_final_cs_periods()
+                .return_const(vec![100, 100]);
+        });
+    let (reputation_sender, reputation_receiver) = mpsc::channel();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_adjust_reputation()
+        .returning(move |peer_id, delta| {
+            reputation_sender.send((*peer_id, delta)).unwrap();
+            false
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (header_sender, header_receiver) = mpsc::channel();
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| header_sender.send(block_id).unwrap());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    for block in [&stale_block, &recent_block] {
+        universe.mock_message_receive(
+            &node_a_peer_id,
+            Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        );
+    }
+
+    assert_eq!(
+        header_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the recent header wasn't sent to consensus"),
+        recent_block.id
+    );
+    assert!(header_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+    assert_eq!(
+        reputation_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the reputation of the node wasn't lowered"),
+        (node_a_peer_id, REPUTATION_STALE_HEADER)
+    );
+    assert!(reputation_receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_protocol_drops_duplicate_headers_from_the_same_peer() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        duplicate_header_window: MassaTime::from_millis(60000),
+        max_duplicate_headers: 1,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    let (reputation_sender, reputation_receiver) = mpsc::channel();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_adjust_reputation()
+        .returning(move |peer_id, delta| {
+            reputation_sender.send((*peer_id, delta)).unwrap();
+            false
+        });
+    peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (header_sender, header_receiver) = mpsc::channel();
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |block_id, _| header_sender.send(block_id).unwrap());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id, node_b_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    // node A sends the header three times, node B once
+    for peer_id in [
+        node_a_peer_id,
+        node_a_peer_id,
+        node_a_peer_id,
+        node_b_peer_id,
+    ] {
+        universe.mock_message_receive(
+            &peer_id,
+            Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        );
+    }
+
+    assert_eq!(
+        header_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the header wasn't sent to consensus"),
+        block.id
+    );
+    assert!(header_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+    // the first copy is tolerated, the second one costs reputation
+    assert_eq!(
+        reputation_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the reputation of the node wasn't lowered"),
+        (node_a_peer_id, REPUTATION_DUPLICATE_HEADER)
+    );
+    assert!(reputation_receiver.try_recv().is_err());
 }
@@ -1,21 +1,29 @@
 // Copyright (c) 2022 MASSA LABS <info@massa.net>
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
 use crate::handlers::block_handler::{AskForBlockInfo, BlockInfoReply, BlockMessage};
 use crate::handlers::operation_handler::OperationMessage;
 use crate::messages::Message;
 use crate::wrap_network::MockActiveConnectionsTraitWrapper;
 
-use super::universe::{ProtocolForeignControllers, ProtocolTestUniverse};
+use super::tools::{assert_message_to_node_matches, assert_no_message_to_node};
+use super::universe::{
+    ConnectedPeers, LinkProfile, ProtocolForeignControllers, ProtocolTestUniverse,
+};
+use massa_consensus_exports::error::ConsensusError;
+use massa_models::block::SecureShareBlock;
 use massa_models::block_header::SecuredHeader;
 use massa_models::operation::{OperationId, OperationPrefixId};
-use massa_models::prehash::PreHashSet;
+use massa_models::prehash::{PreHashMap, PreHashSet};
 use massa_models::{block_id::BlockId, slot::Slot};
-use massa_protocol_exports::PeerId;
-use massa_protocol_exports::ProtocolConfig;
+use massa_protocol_exports::{ConsensusUnavailablePolicy, PeerEvent, PeerId};
+use massa_protocol_exports::{PeerStateCounts, ProtocolConfig, ProtocolError};
 use massa_signature::KeyPair;
-use massa_test_framework::{TestUniverse, WaitPoint};
+use massa_test_framework::{TestUniverse, WaitPoint, DEFAULT_WAIT_TIMEOUT};
 use massa_time::MassaTime;
 use mockall::Sequence;
 
@@ -215,6 +223,19 @@ fn block_retrieval_mock(
         .returning(move || Box::new(shared_active_connections.clone()));
 }
 
+/// Assert that `block_id` is being asked to exactly `peer_ids`
+fn assert_asked_to(universe: &ProtocolTestUniverse, block_id: BlockId, peer_ids: &HashSet<PeerId>) {
+    let asked_to: HashSet<PeerId> = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.block_id == block_id)
+        .map(|request| request.peer_id)
+        .collect();
+    assert_eq!(&asked_to, peer_ids);
+}
+
 #[test]
 fn test_full_ask_block_workflow() {
     let protocol_config = ProtocolConfig {
@@ -279,7 +300,7 @@ fn test_full_ask_block_workflow() {
         waitpoint.get_trigger_handle(),
     );
 
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
 
     universe.mock_message_receive(
         &node_a_peer_id,
@@ -296,6 +317,8 @@ fn test_full_ask_block_workflow() {
         )
         .unwrap();
     waitpoint.wait();
+    // node A doesn't answer in time
+    universe.clock.advance(protocol_config.ask_block_timeout);
     waitpoint.wait();
 
     universe.mock_message_receive(
@@ -318,23 +341,31 @@ fn test_full_ask_block_workflow() {
 }
 
 #[test]
-fn test_empty_block() {
+fn test_retry_ask_block_from_other_announcing_peer() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(100),
+        ask_block_timeout: MassaTime::from_millis(500),
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
-    let block =
-        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1.clone()],
+        vec![],
+        vec![],
+    );
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
     let node_b_keypair = KeyPair::generate(0).unwrap();
     let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
     let waitpoint = WaitPoint::new();
-
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
@@ -351,17 +382,30 @@ fn test_empty_block() {
                 block.id,
                 AskForBlockInfo::OperationIds,
             )),
+            // node A doesn't answer, node B announced the block too
             TestsStepMatch::AskData((
                 PeerIdMatchers::PeerId(node_b_peer_id),
                 block.id,
                 AskForBlockInfo::OperationIds,
             )),
-            TestsStepMatch::BlockManaged((block.id, false)),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                block.id,
+                AskForBlockInfo::Operations(
+                    vec![op_1.id]
+                        .into_iter()
+                        .collect::<PreHashSet<OperationId>>()
+                        .into_iter()
+                        .collect(),
+                ),
+            )),
+            TestsStepMatch::BlockManaged((block.id, true)),
         ],
         &mut foreign_controllers,
         waitpoint.get_trigger_handle(),
     );
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
 
     universe.mock_message_receive(
         &node_a_peer_id,
@@ -378,70 +422,126 @@ fn test_empty_block() {
         )
         .unwrap();
     waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe.clock.advance(protocol_config.ask_block_timeout);
+    waitpoint.wait_timeout(DEFAULT_WAIT_TIMEOUT).unwrap();
+    // the timed out ask counts as a retry
+    let requests = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].peer_id, node_b_peer_id);
+    assert_eq!(requests[0].retries, 1);
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
+        })),
+    );
     waitpoint.wait();
 
     universe.mock_message_receive(
         &node_b_peer_id,
         Message::Block(Box::new(BlockMessage::DataResponse {
             block_id: block.id,
-            block_info: BlockInfoReply::OperationIds(vec![]),
+            block_info: BlockInfoReply::Operations(vec![op_1]),
         })),
     );
     waitpoint.wait();
 }
 
 #[test]
-fn test_dont_want_it_anymore() {
+fn test_ask_block_fanout_first_response_wins() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(200),
+        block_ask_fanout: 2,
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
     let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
-    let op_2 = ProtocolTestUniverse::create_operation(&block_creator, 5);
     let op_thread = op_1
         .content_creator_address
         .get_thread(protocol_config.thread_count);
     let block = ProtocolTestUniverse::create_block(
         &block_creator,
         Slot::new(1, op_thread),
-        vec![op_1.clone(), op_2.clone()],
+        vec![op_1.clone()],
         vec![],
         vec![],
     );
+    // header sent after the others, its registration tells that they were processed
+    let last_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(2, 0), vec![], vec![], vec![]);
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
     let node_b_keypair = KeyPair::generate(0).unwrap();
     let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+    let peer_ids: HashSet<PeerId> = [node_a_peer_id, node_b_peer_id].into_iter().collect();
 
     let waitpoint = WaitPoint::new();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (registered_sender, registered_receiver) = mpsc::channel();
     foreign_controllers
         .consensus_controller
         .expect_register_block_header()
-        .return_once(move |block_id, header| {
-            assert_eq!(block_id, block.id);
-            assert_eq!(header.id, block.content.header.id);
+        .returning(move |block_id, _| {
+            registered_sender.send(block_id).unwrap();
         });
+    let operations_ask = AskForBlockInfo::Operations(
+        vec![op_1.id]
+            .into_iter()
+            .collect::<PreHashSet<OperationId>>()
+            .into_iter()
+            .collect(),
+    );
+    // both nodes that announced the block are asked each step
     block_retrieval_mock(
-        vec![TestsStepMatch::AskData((
-            PeerIdMatchers::PeerId(node_a_peer_id),
-            block.id,
-            AskForBlockInfo::OperationIds,
-        ))],
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                operations_ask.clone(),
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                operations_ask,
+            )),
+            TestsStepMatch::BlockManaged((block.id, true)),
+        ],
         &mut foreign_controllers,
         waitpoint.get_trigger_handle(),
     );
 
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
 
     universe.mock_message_receive(
         &node_a_peer_id,
         Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
     );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
 
     universe
         .module_controller
@@ -453,44 +553,78 @@ fn test_dont_want_it_anymore() {
         )
         .unwrap();
     waitpoint.wait();
+    assert_asked_to(&universe, block.id, &peer_ids);
 
-    universe
-        .module_controller
-        .send_wishlist_delta(Default::default(), vec![block.id].into_iter().collect())
-        .unwrap();
-
-    std::thread::sleep(protocol_config.ask_block_timeout.to_duration());
+    // node B is the fastest
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
+        })),
+    );
+    waitpoint.wait();
+    assert_asked_to(&universe, block.id, &peer_ids);
 
     universe.mock_message_receive(
         &node_b_peer_id,
         Message::Block(Box::new(BlockMessage::DataResponse {
             block_id: block.id,
-            block_info: BlockInfoReply::OperationIds(vec![op_1.id, op_2.id]),
+            block_info: BlockInfoReply::Operations(vec![op_1.clone()]),
         })),
     );
+    waitpoint.wait();
 
-    // TODO: Find a better way to be sure that we don't asked the ops. For now we are just sleeping
-    // and so if it's called during this timing the counts checks of mockall will panic.
-    std::thread::sleep(
-        protocol_config
-            .ask_block_timeout
-            .to_duration()
-            .checked_mul(2)
-            .unwrap(),
+    // the late answers of node A are ignored: no new ask and no ban
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
+        })),
+    );
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::Operations(vec![op_1]),
+        })),
+    );
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            last_block.content.header.clone(),
+        ))),
     );
+    while registered_receiver
+        .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+        .expect("the header wasn't registered")
+        != last_block.id
+    {}
 }
 
 #[test]
-fn test_no_one_has_it() {
+fn test_deduplicate_in_flight_block_asks() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(200),
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
-    let block =
-        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1],
+        vec![],
+        vec![],
+    );
+    // header sent after the others, its registration tells that they were processed
+    let last_block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(2, 0), vec![], vec![], vec![]);
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
     let node_b_keypair = KeyPair::generate(0).unwrap();
@@ -499,34 +633,34 @@ fn test_no_one_has_it() {
     let waitpoint = WaitPoint::new();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let (registered_sender, registered_receiver) = mpsc::channel();
     foreign_controllers
         .consensus_controller
         .expect_register_block_header()
-        .return_once(move |block_id, header| {
-            assert_eq!(block_id, block.id);
-            assert_eq!(header.id, block.content.header.id);
+        .returning(move |block_id, _| {
+            registered_sender.send(block_id).unwrap();
         });
-    let mut peer_ids: HashSet<PeerId> = HashSet::new();
-    peer_ids.insert(node_a_peer_id);
-    peer_ids.insert(node_b_peer_id);
+    // a single ask while it is in flight, even if both nodes announced the block
     block_retrieval_mock(
-        vec![
-            TestsStepMatch::AskData((
-                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
-                block.id,
-                AskForBlockInfo::OperationIds,
-            )),
-            TestsStepMatch::AskData((
-                PeerIdMatchers::AmongPeerIds(peer_ids),
-                block.id,
-                AskForBlockInfo::OperationIds,
-            )),
-        ],
+        vec![TestsStepMatch::AskData((
+            PeerIdMatchers::AmongPeerIds([node_a_peer_id, node_b_peer_id].into_iter().collect()),
+            block.id,
+            AskForBlockInfo::OperationIds,
+        ))],
         &mut foreign_controllers,
         waitpoint.get_trigger_handle(),
     );
 
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
 
     universe
         .module_controller
@@ -539,286 +673,2103 @@ fn test_no_one_has_it() {
         .unwrap();
     waitpoint.wait();
 
+    // announcing the block again does not trigger a new ask while the first one is pending
     universe.mock_message_receive(
         &node_b_peer_id,
-        Message::Block(Box::new(BlockMessage::DataResponse {
-            block_id: block.id,
-            block_info: BlockInfoReply::NotFound,
-        })),
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(
+            last_block.content.header.clone(),
+        ))),
+    );
+    while registered_receiver
+        .recv_timeout(DEFAULT_WAIT_TIMEOUT)
+        .expect("the header wasn't registered")
+        != last_block.id
+    {}
+    assert_eq!(
+        universe
+            .module_controller
+            .get_inflight_block_requests()
+            .unwrap()
+            .len(),
+        1
     );
-    waitpoint.wait();
 }
 
 #[test]
-fn test_multiple_blocks_without_a_priori() {
+fn test_ask_earliest_slot_blocks_first() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(100),
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
-    let block_1 =
-        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
-    let block_2 =
-        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let later_block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(2, op_thread),
+        vec![op_1.clone()],
+        vec![],
+        vec![],
+    );
+    let earlier_block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1],
+        vec![],
+        vec![],
+    );
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
-    let node_b_keypair = KeyPair::generate(0).unwrap();
-    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
     let waitpoint = WaitPoint::new();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
-    foreign_controllers
-        .consensus_controller
-        .expect_register_block_header()
-        .returning(move |_, _| {});
-    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
-    ProtocolTestUniverse::active_connections_boilerplate(
-        &mut shared_active_connections,
-        [node_a_peer_id, node_b_peer_id].iter().cloned().collect(),
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                earlier_block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                later_block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
     );
-    shared_active_connections.set_expectations(|active_connections| {
-        let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
-        let mut peer_ids = HashSet::new();
-        peer_ids.insert(node_a_peer_id);
-        peer_ids.insert(node_b_peer_id);
-        let mut block_ids = HashSet::new();
-        block_ids.insert(block_1.id);
-        block_ids.insert(block_2.id);
-        active_connections
-            .expect_send_to_peer()
-            .times(2..)
-            .returning(move |peer_id, _, message, high_priority| {
-                assert!(high_priority);
-                match message {
-                    Message::Block(message) => match *message {
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // the later block is wanted first but the earlier one is asked first
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![
+                (later_block.id, Some(later_block.content.header.clone())),
+                (earlier_block.id, Some(earlier_block.content.header.clone())),
+            ]
+            .into_iter()
+            .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+    // both are asked in the same round, in the order checked by the mock sequence
+    let asked: HashSet<BlockId> = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap()
+        .into_iter()
+        .map(|request| request.block_id)
+        .collect();
+    assert_eq!(
+        asked,
+        [earlier_block.id, later_block.id].into_iter().collect()
+    );
+}
+
+#[test]
+fn test_max_concurrent_block_downloads() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_concurrent_block_downloads: 2,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let blocks: Vec<_> = (1..=5)
+        .map(|period| {
+            ProtocolTestUniverse::create_block(
+                &block_creator,
+                Slot::new(period, op_thread),
+                vec![op_1.clone()],
+                vec![],
+                vec![],
+            )
+        })
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // only the two earliest blocks are asked while nothing answered
+    block_retrieval_mock(
+        blocks[..2]
+            .iter()
+            .map(|block| {
+                TestsStepMatch::AskData((
+                    PeerIdMatchers::PeerId(node_a_peer_id),
+                    block.id,
+                    AskForBlockInfo::OperationIds,
+                ))
+            })
+            .collect(),
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            blocks
+                .iter()
+                .map(|block| (block.id, Some(block.content.header.clone())))
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    // processed after the wishlist delta: the other blocks wait for a free download slot
+    let asked: HashSet<BlockId> = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap()
+        .into_iter()
+        .map(|request| request.block_id)
+        .collect();
+    assert_eq!(asked, blocks[..2].iter().map(|block| block.id).collect());
+}
+
+#[test]
+fn test_wishlist_over_max_size_is_rejected() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_wishlist_size: 2,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let blocks: Vec<_> = (1..=3)
+        .map(|period| {
+            ProtocolTestUniverse::create_block(
+                &block_creator,
+                Slot::new(period, 0),
+                vec![],
+                vec![],
+                vec![],
+            )
+        })
+        .collect();
+    let wishlist = |blocks: &[SecureShareBlock]| -> PreHashMap<BlockId, Option<SecuredHeader>> {
+        blocks
+            .iter()
+            .map(|block| (block.id, Some(block.content.header.clone())))
+            .collect()
+    };
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_get_peer_state_counts()
+        .return_const(PeerStateCounts::default());
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::new(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // a single delta over the cap
+    match universe
+        .module_controller
+        .send_wishlist_delta(wishlist(&blocks), PreHashSet::default())
+    {
+        Err(ProtocolError::WishlistFull { wanted, max }) => {
+            assert_eq!((wanted, max), (3, 2));
+        }
+        other => panic!("the wishlist over the cap was accepted: {:?}", other),
+    }
+
+    // a delta filling the wishlist, then one going over the cap
+    universe
+        .module_controller
+        .send_wishlist_delta(wishlist(&blocks[..2]), PreHashSet::default())
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while universe.module_controller.get_metrics().wishlist_size != 2 {
+        assert!(
+            Instant::now() < deadline,
+            "the wishlist wasn't updated in time"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(matches!(
+        universe
+            .module_controller
+            .send_wishlist_delta(wishlist(&blocks[2..]), PreHashSet::default()),
+        Err(ProtocolError::WishlistFull { wanted: 3, max: 2 })
+    ));
+
+    // replacing a block keeps the wishlist within the cap
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            wishlist(&blocks[2..]),
+            PreHashSet::from_iter([blocks[0].id]),
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_block_download_metrics() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1.clone()],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                AskForBlockInfo::Operations(vec![op_1.id]),
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    assert_eq!(universe.module_controller.get_metrics().block_downloads, 0);
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
+        })),
+    );
+    waitpoint.wait();
+
+    // the operation ids download is recorded, nothing timed out
+    let metrics = universe.module_controller.get_metrics();
+    assert_eq!(metrics.block_downloads, 1);
+    assert_eq!(metrics.block_download_counts.iter().sum::<u64>(), 1);
+    assert_eq!(metrics.block_ask_timeouts, 0);
+}
+
+#[test]
+fn test_empty_block() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::BlockManaged((block.id, false)),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+    // node A doesn't answer in time
+    universe.clock.advance(protocol_config.ask_block_timeout);
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![]),
+        })),
+    );
+    waitpoint.wait();
+}
+
+#[test]
+fn test_dont_want_it_anymore() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(200),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_2 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1.clone(), op_2.clone()],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    block_retrieval_mock(
+        vec![TestsStepMatch::AskData((
+            PeerIdMatchers::PeerId(node_a_peer_id),
+            block.id,
+            AskForBlockInfo::OperationIds,
+        ))],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+
+    universe
+        .module_controller
+        .send_wishlist_delta(Default::default(), vec![block.id].into_iter().collect())
+        .unwrap();
+    // processed after the wishlist delta: the block isn't asked anymore
+    assert!(universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap()
+        .is_empty());
+    universe.clock.advance(protocol_config.ask_block_timeout);
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id, op_2.id]),
+        })),
+    );
+
+    // TODO: Find a better way to be sure that we don't asked the ops. For now we are just sleeping
+    // and so if it's called during this timing the counts checks of mockall will panic.
+    std::thread::sleep(
+        protocol_config
+            .ask_block_timeout
+            .to_duration()
+            .checked_mul(2)
+            .unwrap(),
+    );
+}
+
+#[test]
+fn test_no_one_has_it() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(200),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    let mut peer_ids: HashSet<PeerId> = HashSet::new();
+    peer_ids.insert(node_a_peer_id);
+    peer_ids.insert(node_b_peer_id);
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::AmongPeerIds(peer_ids),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+
+    // the asked peer doesn't have it, the other one is asked right away
+    let requests = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap();
+    assert_eq!(requests.len(), 1);
+    universe.mock_message_receive(
+        &requests[0].peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::NotFound,
+        })),
+    );
+    waitpoint.wait();
+}
+
+#[test]
+fn test_multiple_blocks_without_a_priori() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block_1 =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let block_2 =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .returning(move |_, _| {});
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        [node_a_peer_id, node_b_peer_id].iter().cloned().collect(),
+    );
+    shared_active_connections.set_expectations(|active_connections| {
+        let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+        let mut peer_ids = HashSet::new();
+        peer_ids.insert(node_a_peer_id);
+        peer_ids.insert(node_b_peer_id);
+        let mut block_ids = HashSet::new();
+        block_ids.insert(block_1.id);
+        block_ids.insert(block_2.id);
+        active_connections
+            .expect_send_to_peer()
+            .times(2..)
+            .returning(move |peer_id, _, message, high_priority| {
+                assert!(high_priority);
+                match message {
+                    Message::Block(message) => match *message {
                         BlockMessage::DataRequest { block_id, .. } => {
                             assert!(block_ids.contains(&block_id));
                         }
                         _ => panic!("Node didn't receive the infos block message"),
                     },
-                    _ => panic!("Node didn't receive the infos block message"),
+                    _ => panic!("Node didn't receive the infos block message"),
+                }
+                assert!(peer_ids.contains(peer_id));
+                waitpoint_trigger_handle.trigger();
+                Ok(())
+            });
+    });
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![
+                (block_1.id, Some(block_1.content.header.clone())),
+                (block_2.id, Some(block_2.content.header.clone())),
+            ]
+            .into_iter()
+            .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+    // both blocks are asked at once
+    let asked: HashSet<BlockId> = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap()
+        .into_iter()
+        .map(|request| request.block_id)
+        .collect();
+    assert_eq!(asked, [block_1.id, block_2.id].into_iter().collect());
+}
+
+#[test]
+fn test_protocol_sends_blocks_when_asked_for() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(200),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    let peer_ids: HashSet<PeerId> = vec![node_a_peer_id, node_b_peer_id].into_iter().collect();
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::SendHeader((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                block.content.header.clone(),
+            )),
+            TestsStepMatch::SendHeader((
+                PeerIdMatchers::AmongPeerIds(peer_ids),
+                block.id,
+                block.content.header.clone(),
+            )),
+            TestsStepMatch::SendData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                BlockInfoReply::OperationIds(vec![]),
+            )),
+            TestsStepMatch::SendData((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                block.id,
+                BlockInfoReply::OperationIds(vec![]),
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    universe.storage.store_block(block.clone());
+    universe
+        .module_controller
+        .integrated_block(block.id, universe.storage.clone())
+        .unwrap();
+    waitpoint.wait();
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataRequest {
+            block_id: block.id,
+            block_info: AskForBlockInfo::OperationIds,
+        })),
+    );
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataRequest {
+            block_id: block.id,
+            block_info: AskForBlockInfo::OperationIds,
+        })),
+    );
+    waitpoint.wait();
+}
+
+#[test]
+fn test_repeated_operation_ids_requests_served_from_cache() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        served_op_ids_cache_ttl: MassaTime::from_millis(60000),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1.clone()],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let op_id = op_1.id;
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections.expect_send_to_peer().times(2).returning(
+            move |peer_id, _, message, _| {
+                assert_eq!(*peer_id, node_a_peer_id);
+                match message {
+                    Message::Block(message) => match *message {
+                        BlockMessage::DataResponse {
+                            block_info: BlockInfoReply::OperationIds(op_ids),
+                            ..
+                        } => assert_eq!(op_ids, vec![op_id]),
+                        _ => panic!("Node didn't receive the operation ids of the block"),
+                    },
+                    _ => panic!("Node didn't receive the operation ids of the block"),
                 }
-                assert!(peer_ids.contains(peer_id));
                 waitpoint_trigger_handle.trigger();
                 Ok(())
-            });
+            },
+        );
     });
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        [node_a_peer_id].into_iter().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.storage.store_block(block.clone());
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataRequest {
+            block_id: block.id,
+            block_info: AskForBlockInfo::OperationIds,
+        })),
+    );
+    waitpoint.wait();
+
+    // the block leaves the storage: the second answer can only come from the cache
+    universe
+        .storage
+        .drop_block_refs(&[block.id].into_iter().collect());
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataRequest {
+            block_id: block.id,
+            block_info: AskForBlockInfo::OperationIds,
+        })),
+    );
+    waitpoint.wait();
+}
+
+#[test]
+fn test_concurrent_block_info_requests_served_within_concurrency_bound() {
+    let block_serve_concurrency = 2;
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        block_serve_concurrency,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let peer_ids: Vec<PeerId> = (0..6)
+        .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+        .collect();
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let serving = Arc::new(AtomicUsize::new(0));
+    let max_serving = Arc::new(AtomicUsize::new(0));
+    let (served_sender, served_receiver) = mpsc::channel();
+    {
+        let serving = serving.clone();
+        let max_serving = max_serving.clone();
+        shared_active_connections.set_expectations(|active_connections| {
+            active_connections
+                .expect_send_to_peer()
+                .returning(move |peer_id, _, message, _| {
+                    let Message::Block(message) = message else {
+                        panic!("Node didn't receive the header of the block");
+                    };
+                    let BlockMessage::DataResponse {
+                        block_info: BlockInfoReply::Header(_),
+                        ..
+                    } = *message
+                    else {
+                        panic!("Node didn't receive the header of the block");
+                    };
+                    // the reply is sent slowly, so that the requests pile up
+                    let now_serving = serving.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_serving.fetch_max(now_serving, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    serving.fetch_sub(1, Ordering::SeqCst);
+                    served_sender.send(*peer_id).unwrap();
+                    Ok(())
+                });
+        });
+    }
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        peer_ids.iter().copied().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.storage.store_block(block.clone());
+
+    for peer_id in &peer_ids {
+        universe.mock_message_receive(
+            peer_id,
+            Message::Block(Box::new(BlockMessage::DataRequest {
+                block_id: block.id,
+                block_info: AskForBlockInfo::Header,
+            })),
+        );
+    }
+
+    let served: HashSet<PeerId> = (0..peer_ids.len())
+        .map(|_| {
+            served_receiver
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(served, peer_ids.iter().copied().collect());
+    assert!(max_serving.load(Ordering::SeqCst) <= block_serve_concurrency);
+}
+
+#[test]
+fn test_protocol_propagates_block_to_node_who_asked_for_operations_and_only_header_to_others() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(200),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+    let node_c_keypair = KeyPair::generate(0).unwrap();
+    let node_c_peer_id = PeerId::from_public_key(node_c_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waipoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+            waipoint_trigger_handle.trigger();
+        });
+    let peer_ids: HashSet<PeerId> = vec![node_b_peer_id, node_c_peer_id].into_iter().collect();
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::SendHeader((
+                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
+                block.id,
+                block.content.header.clone(),
+            )),
+            TestsStepMatch::SendHeader((
+                PeerIdMatchers::AmongPeerIds(peer_ids),
+                block.id,
+                block.content.header.clone(),
+            )),
+            TestsStepMatch::SendData((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                block.id,
+                BlockInfoReply::OperationIds(vec![]),
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    waitpoint.wait();
+
+    universe.storage.store_block(block.clone());
+    universe
+        .module_controller
+        .integrated_block(block.id, universe.storage.clone())
+        .unwrap();
+    waitpoint.wait();
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataRequest {
+            block_id: block.id,
+            block_info: AskForBlockInfo::OperationIds,
+        })),
+    );
+    waitpoint.wait();
+}
+
+#[test]
+fn test_passive_node_validates_without_relaying() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        passive_mode: true,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let operation = ProtocolTestUniverse::create_operation(&block_creator, 10);
+    let announced_operation = ProtocolTestUniverse::create_operation(&block_creator, 10);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let (header_sender, header_receiver) = mpsc::channel();
+    let (operations_sender, operations_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, _| header_sender.send(block_id).unwrap());
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller
+                .expect_add_operations()
+                .returning(move |operations| {
+                    operations_sender
+                        .send(operations.get_op_refs().clone())
+                        .unwrap();
+                });
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let node_messages: Vec<_> = [node_a_peer_id, node_b_peer_id]
+        .into_iter()
+        .map(|peer_id| {
+            ProtocolTestUniverse::create_fake_connection_with_profile(
+                &mut shared_active_connections,
+                peer_id,
+                LinkProfile {
+                    latency: Duration::ZERO,
+                    loss_rate: 0.0,
+                },
+            )
+        })
+        .collect();
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &ConnectedPeers::new([node_a_peer_id, node_b_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // the header still reaches consensus
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    assert_eq!(
+        header_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the header wasn't registered"),
+        block.id
+    );
+    universe.storage.store_block(block.clone());
+    universe
+        .module_controller
+        .integrated_block(block.id, universe.storage.clone())
+        .unwrap();
+
+    // the operations still reach the pool
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![operation.clone()])),
+    );
+    assert!(operations_receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("the operation wasn't added to the pool")
+        .contains(&operation.id));
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::OperationsAnnouncement(
+            vec![announced_operation.id.into_prefix()]
+                .into_iter()
+                .collect(),
+        )),
+    );
+
+    // nothing is announced to the peers nor asked to them, past a block propagation tick
+    for (peer_id, messages) in [node_a_peer_id, node_b_peer_id].iter().zip(&node_messages) {
+        assert_no_message_to_node(peer_id, messages, Duration::from_millis(1500));
+    }
+}
+
+#[test]
+fn test_noting_block_does_not_panic_with_one_max_node_known_blocks_size() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_known_blocks_size: 1,
+        ask_block_timeout: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1.clone()],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                block.id,
+                AskForBlockInfo::Operations(
+                    vec![op_1.id]
+                        .into_iter()
+                        .collect::<PreHashSet<OperationId>>()
+                        .into_iter()
+                        .collect(),
+                ),
+            )),
+            TestsStepMatch::BlockManaged((block.id, true)),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
+        })),
+    );
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::Operations(vec![op_1]),
+        })),
+    );
+    waitpoint.wait();
+}
+
+#[test]
+fn test_protocol_does_propagate_operations_received_in_blocks() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(200),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let op_thread = op_1
+        .content_creator_address
+        .get_thread(protocol_config.thread_count);
+    let block = ProtocolTestUniverse::create_block(
+        &block_creator,
+        Slot::new(1, op_thread),
+        vec![op_1.clone()],
+        vec![],
+        vec![],
+    );
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_once(move |block_id, header| {
+            assert_eq!(block_id, block.id);
+            assert_eq!(header.id, block.content.header.id);
+        });
+    block_retrieval_mock(
+        vec![
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                AskForBlockInfo::OperationIds,
+            )),
+            TestsStepMatch::AskData((
+                PeerIdMatchers::PeerId(node_a_peer_id),
+                block.id,
+                AskForBlockInfo::Operations(
+                    vec![op_1.id]
+                        .into_iter()
+                        .collect::<PreHashSet<OperationId>>()
+                        .into_iter()
+                        .collect(),
+                ),
+            )),
+            TestsStepMatch::BlockManaged((block.id, true)),
+            TestsStepMatch::OperationsPropagated((
+                PeerIdMatchers::PeerId(node_b_peer_id),
+                vec![op_1.clone()]
+                    .into_iter()
+                    .map(|op| op.id.prefix())
+                    .collect(),
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint.get_trigger_handle(),
+    );
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
+        })),
+    );
+    waitpoint.wait();
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::Operations(vec![op_1]),
+        })),
+    );
+    waitpoint.wait();
+    waitpoint.wait();
+}
+
+#[test]
+fn test_headers_received_in_quick_succession_are_registered_together() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        block_header_batch_window: MassaTime::from_millis(500),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    // received in reverse slot order
+    let blocks: Vec<_> = [Slot::new(2, 0), Slot::new(1, 1), Slot::new(1, 0)]
+        .into_iter()
+        .map(|slot| {
+            ProtocolTestUniverse::create_block(&block_creator, slot, vec![], vec![], vec![])
+        })
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let (batch_sender, batch_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .times(0);
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_headers()
+        .returning(move |headers| {
+            batch_sender
+                .send(
+                    headers
+                        .into_iter()
+                        .map(|(block_id, header)| {
+                            assert_eq!(block_id, header.id);
+                            header.content.slot
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap();
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        [node_a_peer_id].into_iter().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    for block in &blocks {
+        universe.mock_message_receive(
+            &node_a_peer_id,
+            Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        );
+    }
+
+    let batch = batch_receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("the headers weren't registered");
+    assert_eq!(
+        batch,
+        vec![Slot::new(1, 0), Slot::new(1, 1), Slot::new(2, 0)]
+    );
+    // all the headers came in the same batch
+    assert!(batch_receiver
+        .recv_timeout(Duration::from_millis(1000))
+        .is_err());
+}
+
+#[test]
+fn test_headers_refused_by_consensus_are_registered_again() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        consensus_unavailable_policy: ConsensusUnavailablePolicy::Retry,
+        consensus_retry_interval: MassaTime::from_millis(200),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 0), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let (registration_sender, registration_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // consensus refuses the header on arrival
+    let refusal_sender = registration_sender.clone();
+    foreign_controllers
+        .consensus_controller
+        .expect_try_register_block_header()
+        .times(1)
+        .returning(move |block_id, _| {
+            refusal_sender.send(("refused", vec![block_id])).unwrap();
+            Err(ConsensusError::Unavailable("overloaded".to_string()))
+        });
+    foreign_controllers
+        .consensus_controller
+        .expect_try_register_block_headers()
+        .returning(move |headers| {
+            registration_sender
+                .send((
+                    "retried",
+                    headers.into_iter().map(|(block_id, _)| block_id).collect(),
+                ))
+                .unwrap();
+            Ok(())
+        });
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .times(0);
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        [node_a_peer_id].into_iter().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+
+    assert_eq!(
+        registration_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the header wasn't sent to consensus"),
+        ("refused", vec![block.id])
+    );
+    assert_eq!(
+        registration_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the header wasn't registered again"),
+        ("retried", vec![block.id])
+    );
+    // the header is registered once consensus takes it
+    assert!(registration_receiver
+        .recv_timeout(Duration::from_millis(1000))
+        .is_err());
+}
+
+#[test]
+fn test_block_downloaded_through_lossy_link() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ask_block_timeout: MassaTime::from_millis(100),
+        // keep asking as long as needed
+        max_block_ask_retries: 0,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let (registered_sender, registered_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_const(());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block()
+        .times(1)
+        .returning(move |block_id, _, _, _| {
+            registered_sender.send(block_id).unwrap();
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    // half of the asks are lost
+    let delivered_receiver = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        LinkProfile {
+            latency: Duration::from_millis(10),
+            loss_rate: 0.5,
+        },
+    );
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        [node_a_peer_id].into_iter().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+
+    // the lost asks time out and are sent again, node A answers the first one that reaches it
+    let delivered = (0..100)
+        .find_map(|_| {
+            let delivered = delivered_receiver
+                .recv_timeout(Duration::from_millis(100))
+                .ok();
+            if delivered.is_none() {
+                universe.clock.advance(protocol_config.ask_block_timeout);
+            }
+            delivered
+        })
+        .expect("no ask reached node A");
+    match delivered {
+        Message::Block(message) => assert!(matches!(
+            message.as_ref(),
+            BlockMessage::DataRequest {
+                block_id,
+                block_info: AskForBlockInfo::OperationIds,
+            } if *block_id == block.id
+        )),
+        _ => panic!("node A didn't receive a block ask"),
+    }
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![]),
+        })),
+    );
+
+    assert_eq!(
+        registered_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the block wasn't registered"),
+        block.id
+    );
+}
+
+#[test]
+fn test_retry_ask_block_from_other_peer_when_asked_peer_disconnects() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        // the ask to the dropped peer could only time out at the end of the test
+        ask_block_timeout: MassaTime::from_millis(60_000),
+        try_connection_timer: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let (registered_sender, registered_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_get_peers()
+        .return_const(HashMap::default());
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_const(());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block()
+        .times(1)
+        .returning(move |block_id, _, _, _| {
+            registered_sender.send(block_id).unwrap();
+        });
+    let perfect_link = LinkProfile {
+        latency: Duration::ZERO,
+        loss_rate: 0.0,
+    };
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let node_a_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        perfect_link,
+    );
+    let node_b_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_b_peer_id,
+        perfect_link,
+    );
+    let connected_peers = ConnectedPeers::new([node_a_peer_id, node_b_peer_id]);
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &connected_peers,
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    // the connection to node A must be known before it is dropped
+    let mut peer_events = universe.module_controller.subscribe_peer_events();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match peer_events.try_recv() {
+            Ok(PeerEvent::Connected { peer_id, .. }) if peer_id == node_a_peer_id => break,
+            Ok(_) => {}
+            Err(_) => {
+                assert!(
+                    Instant::now() < deadline,
+                    "the connection to node A wasn't noticed"
+                );
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    // only node A announced the block, it is asked first
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    let is_operation_ids_request = |message: &Message| match message {
+        Message::Block(message) => matches!(
+            message.as_ref(),
+            BlockMessage::DataRequest {
+                block_id,
+                block_info: AskForBlockInfo::OperationIds,
+            } if *block_id == block.id
+        ),
+        _ => false,
+    };
+    assert_message_to_node_matches(
+        &node_a_peer_id,
+        &node_a_messages,
+        is_operation_ids_request,
+        Duration::from_secs(5),
+    );
+
+    // node A disappears without answering, the block is asked to node B long before the ask times out
+    connected_peers.drop_connection(&node_a_peer_id);
+    assert_message_to_node_matches(
+        &node_b_peer_id,
+        &node_b_messages,
+        is_operation_ids_request,
+        Duration::from_secs(5),
+    );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![]),
+        })),
+    );
+    assert_eq!(
+        registered_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the block wasn't registered"),
+        block.id
+    );
+}
+
+#[test]
+fn test_retry_ask_block_from_other_peer_on_not_found() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        // the ask to the declining peer could only time out at the end of the test
+        ask_block_timeout: MassaTime::from_millis(60_000),
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let block =
+        ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let (registered_sender, registered_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_get_peers()
+        .return_const(HashMap::default());
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block_header()
+        .return_const(());
+    foreign_controllers
+        .consensus_controller
+        .expect_register_block()
+        .times(1)
+        .returning(move |block_id, _, _, _| {
+            registered_sender.send(block_id).unwrap();
+        });
+    let perfect_link = LinkProfile {
+        latency: Duration::ZERO,
+        loss_rate: 0.0,
+    };
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let node_a_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        perfect_link,
+    );
+    let node_b_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_b_peer_id,
+        perfect_link,
+    );
+    let connected_peers = ConnectedPeers::new([node_a_peer_id, node_b_peer_id]);
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &connected_peers,
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // only node A announced the block, it is asked first
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    let is_operation_ids_request = |message: &Message| match message {
+        Message::Block(message) => matches!(
+            message.as_ref(),
+            BlockMessage::DataRequest {
+                block_id,
+                block_info: AskForBlockInfo::OperationIds,
+            } if *block_id == block.id
+        ),
+        _ => false,
+    };
+    assert_message_to_node_matches(
+        &node_a_peer_id,
+        &node_a_messages,
+        is_operation_ids_request,
+        Duration::from_secs(5),
+    );
+
+    // node A no longer has the block, it is asked to node B long before the ask times out
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::NotFound,
+        })),
+    );
+    assert_message_to_node_matches(
+        &node_b_peer_id,
+        &node_b_messages,
+        is_operation_ids_request,
+        Duration::from_secs(5),
+    );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Block(Box::new(BlockMessage::DataResponse {
+            block_id: block.id,
+            block_info: BlockInfoReply::OperationIds(vec![]),
+        })),
+    );
+    assert_eq!(
+        registered_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the block wasn't registered"),
+        block.id
+    );
+}
+
+#[test]
+fn test_overlapping_wishlist_deltas_do_not_ask_twice() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+
+    let block_creator = KeyPair::generate(0).unwrap();
+    let blocks: Vec<_> = (1..=3)
+        .map(|period| {
+            ProtocolTestUniverse::create_block(
+                &block_creator,
+                Slot::new(period, 0),
+                vec![],
+                vec![],
+                vec![],
+            )
+        })
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let node_a_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        LinkProfile {
+            latency: Duration::ZERO,
+            loss_rate: 0.0,
+        },
+    );
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &ConnectedPeers::new([node_a_peer_id]),
+    );
     foreign_controllers
         .network_controller
         .expect_get_active_connections()
         .returning(move || Box::new(shared_active_connections.clone()));
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let ask_for = |block_id: BlockId| {
+        move |message: &Message| match message {
+            Message::Block(message) => matches!(
+                message.as_ref(),
+                BlockMessage::DataRequest {
+                    block_id: asked_block_id,
+                    block_info: AskForBlockInfo::OperationIds,
+                } if *asked_block_id == block_id
+            ),
+            _ => false,
+        }
+    };
 
     universe
         .module_controller
         .send_wishlist_delta(
-            vec![
-                (block_1.id, Some(block_1.content.header.clone())),
-                (block_2.id, Some(block_2.content.header.clone())),
-            ]
-            .into_iter()
-            .collect(),
+            blocks[..2]
+                .iter()
+                .map(|block| (block.id, Some(block.content.header.clone())))
+                .collect(),
             PreHashSet::<BlockId>::default(),
         )
         .unwrap();
-    waitpoint.wait();
-    waitpoint.wait();
+    for block in &blocks[..2] {
+        assert_message_to_node_matches(
+            &node_a_peer_id,
+            &node_a_messages,
+            ask_for(block.id),
+            Duration::from_secs(5),
+        );
+    }
+
+    // the second block is sent again while its ask is pending, only the third one is asked
+    universe
+        .module_controller
+        .send_wishlist_delta(
+            blocks[1..]
+                .iter()
+                .map(|block| (block.id, Some(block.content.header.clone())))
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
+        .unwrap();
+    let message = assert_message_to_node_matches(
+        &node_a_peer_id,
+        &node_a_messages,
+        |message| matches!(message, Message::Block(message) if matches!(message.as_ref(), BlockMessage::DataRequest { .. })),
+        Duration::from_secs(5),
+    );
+    assert!(
+        ask_for(blocks[2].id)(&message),
+        "the pending ask was sent again"
+    );
+    assert_no_message_to_node(
+        &node_a_peer_id,
+        &node_a_messages,
+        Duration::from_millis(500),
+    );
 }
 
 #[test]
-fn test_protocol_sends_blocks_when_asked_for() {
+fn test_inflight_block_requests_snapshot() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(200),
+        ask_block_timeout: MassaTime::from_millis(60_000),
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
     let block =
         ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
-
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
-    let node_b_keypair = KeyPair::generate(0).unwrap();
-    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
-    let waitpoint = WaitPoint::new();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
         .consensus_controller
         .expect_register_block_header()
-        .return_once(move |block_id, header| {
-            assert_eq!(block_id, block.id);
-            assert_eq!(header.id, block.content.header.id);
-        });
-    let peer_ids: HashSet<PeerId> = vec![node_a_peer_id, node_b_peer_id].into_iter().collect();
-    block_retrieval_mock(
-        vec![
-            TestsStepMatch::SendHeader((
-                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
-                block.id,
-                block.content.header.clone(),
-            )),
-            TestsStepMatch::SendHeader((
-                PeerIdMatchers::AmongPeerIds(peer_ids),
-                block.id,
-                block.content.header.clone(),
-            )),
-            TestsStepMatch::SendData((
-                PeerIdMatchers::PeerId(node_a_peer_id),
-                block.id,
-                BlockInfoReply::OperationIds(vec![]),
-            )),
-            TestsStepMatch::SendData((
-                PeerIdMatchers::PeerId(node_b_peer_id),
-                block.id,
-                BlockInfoReply::OperationIds(vec![]),
-            )),
-        ],
-        &mut foreign_controllers,
-        waitpoint.get_trigger_handle(),
+        .return_const(());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let node_a_messages = ProtocolTestUniverse::create_fake_connection_with_profile(
+        &mut shared_active_connections,
+        node_a_peer_id,
+        LinkProfile {
+            latency: Duration::ZERO,
+            loss_rate: 0.0,
+        },
     );
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &ConnectedPeers::new([node_a_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
 
-    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    assert!(universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap()
+        .is_empty());
 
-    universe.storage.store_block(block.clone());
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+    );
     universe
         .module_controller
-        .integrated_block(block.id, universe.storage.clone())
+        .send_wishlist_delta(
+            vec![(block.id, Some(block.content.header.clone()))]
+                .into_iter()
+                .collect(),
+            PreHashSet::<BlockId>::default(),
+        )
         .unwrap();
-    waitpoint.wait();
-    waitpoint.wait();
-
-    universe.mock_message_receive(
+    assert_message_to_node_matches(
         &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::DataRequest {
-            block_id: block.id,
-            block_info: AskForBlockInfo::OperationIds,
-        })),
+        &node_a_messages,
+        |message| match message {
+            Message::Block(message) => matches!(
+                message.as_ref(),
+                BlockMessage::DataRequest { block_id, .. } if *block_id == block.id
+            ),
+            _ => false,
+        },
+        Duration::from_secs(5),
     );
-    waitpoint.wait();
 
-    universe.mock_message_receive(
-        &node_b_peer_id,
-        Message::Block(Box::new(BlockMessage::DataRequest {
-            block_id: block.id,
-            block_info: AskForBlockInfo::OperationIds,
-        })),
-    );
-    waitpoint.wait();
+    let requests = universe
+        .module_controller
+        .get_inflight_block_requests()
+        .unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].block_id, block.id);
+    assert_eq!(requests[0].peer_id, node_a_peer_id);
+    assert_eq!(requests[0].retries, 0);
+    assert_eq!(requests[0].asked_at, universe.clock.now());
 }
 
 #[test]
-fn test_protocol_propagates_block_to_node_who_asked_for_operations_and_only_header_to_others() {
+fn test_first_header_time_kept_through_duplicate_headers() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(200),
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
     let block =
         ProtocolTestUniverse::create_block(&block_creator, Slot::new(1, 1), vec![], vec![], vec![]);
-
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
     let node_b_keypair = KeyPair::generate(0).unwrap();
     let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
-    let node_c_keypair = KeyPair::generate(0).unwrap();
-    let node_c_peer_id = PeerId::from_public_key(node_c_keypair.get_public_key());
 
-    let waitpoint = WaitPoint::new();
-    let waipoint_trigger_handle = waitpoint.get_trigger_handle();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
         .consensus_controller
         .expect_register_block_header()
-        .return_once(move |block_id, header| {
-            assert_eq!(block_id, block.id);
-            assert_eq!(header.id, block.content.header.id);
-            waipoint_trigger_handle.trigger();
-        });
-    let peer_ids: HashSet<PeerId> = vec![node_b_peer_id, node_c_peer_id].into_iter().collect();
-    block_retrieval_mock(
-        vec![
-            TestsStepMatch::SendHeader((
-                PeerIdMatchers::AmongPeerIds(peer_ids.clone()),
-                block.id,
-                block.content.header.clone(),
-            )),
-            TestsStepMatch::SendHeader((
-                PeerIdMatchers::AmongPeerIds(peer_ids),
-                block.id,
-                block.content.header.clone(),
-            )),
-            TestsStepMatch::SendData((
-                PeerIdMatchers::PeerId(node_b_peer_id),
-                block.id,
-                BlockInfoReply::OperationIds(vec![]),
-            )),
-        ],
-        &mut foreign_controllers,
-        waitpoint.get_trigger_handle(),
+        .return_const(());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        [node_a_peer_id, node_b_peer_id].into_iter().collect(),
     );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
 
-    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    assert!(universe
+        .module_controller
+        .get_first_header_times()
+        .unwrap()
+        .is_empty());
 
+    let before_receipt = MassaTime::now();
     universe.mock_message_receive(
         &node_a_peer_id,
         Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
     );
-    waitpoint.wait();
-
-    universe.storage.store_block(block.clone());
-    universe
+    std::thread::sleep(Duration::from_millis(300));
+    let first_seen = *universe
         .module_controller
-        .integrated_block(block.id, universe.storage.clone())
-        .unwrap();
-    waitpoint.wait();
-    waitpoint.wait();
-
-    universe.mock_message_receive(
-        &node_b_peer_id,
-        Message::Block(Box::new(BlockMessage::DataRequest {
-            block_id: block.id,
-            block_info: AskForBlockInfo::OperationIds,
-        })),
-    );
-    waitpoint.wait();
+        .get_first_header_times()
+        .unwrap()
+        .get(&block.id)
+        .expect("the header receipt wasn't recorded");
+    assert!(first_seen >= before_receipt);
+    assert!(first_seen <= MassaTime::now());
+
+    // the same header sent again by another peer and by the first one
+    for peer_id in [node_b_peer_id, node_a_peer_id] {
+        universe.mock_message_receive(
+            &peer_id,
+            Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        );
+    }
+    std::thread::sleep(Duration::from_millis(300));
+    let times = universe.module_controller.get_first_header_times().unwrap();
+    assert_eq!(times.len(), 1);
+    assert_eq!(times.get(&block.id), Some(&first_seen));
 }
 
 #[test]
-fn test_noting_block_does_not_panic_with_one_max_node_known_blocks_size() {
+fn test_compact_block_asks_only_missing_operations() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        max_known_blocks_size: 1,
         ask_block_timeout: MassaTime::from_millis(100),
+        compact_blocks_enabled: true,
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
-    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
-    let op_thread = op_1
+    let known_op = ProtocolTestUniverse::create_operation(&block_creator, 5);
+    let missing_op = ProtocolTestUniverse::create_operation(&block_creator, 6);
+    let op_thread = known_op
         .content_creator_address
         .get_thread(protocol_config.thread_count);
     let block = ProtocolTestUniverse::create_block(
         &block_creator,
         Slot::new(1, op_thread),
-        vec![op_1.clone()],
+        vec![known_op.clone(), missing_op.clone()],
         vec![],
         vec![],
     );
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
-    let node_b_keypair = KeyPair::generate(0).unwrap();
-    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
     let waitpoint = WaitPoint::new();
+    let header_waitpoint = WaitPoint::new();
+    let header_waitpoint_trigger_handle = header_waitpoint.get_trigger_handle();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
     ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
     foreign_controllers
@@ -827,29 +2778,15 @@ fn test_noting_block_does_not_panic_with_one_max_node_known_blocks_size() {
         .return_once(move |block_id, header| {
             assert_eq!(block_id, block.id);
             assert_eq!(header.id, block.content.header.id);
+            header_waitpoint_trigger_handle.trigger();
         });
+    // the operation list comes with the header: no ask for it, only the missing operation is asked
     block_retrieval_mock(
         vec![
             TestsStepMatch::AskData((
                 PeerIdMatchers::PeerId(node_a_peer_id),
                 block.id,
-                AskForBlockInfo::OperationIds,
-            )),
-            TestsStepMatch::AskData((
-                PeerIdMatchers::PeerId(node_b_peer_id),
-                block.id,
-                AskForBlockInfo::OperationIds,
-            )),
-            TestsStepMatch::AskData((
-                PeerIdMatchers::PeerId(node_b_peer_id),
-                block.id,
-                AskForBlockInfo::Operations(
-                    vec![op_1.id]
-                        .into_iter()
-                        .collect::<PreHashSet<OperationId>>()
-                        .into_iter()
-                        .collect(),
-                ),
+                AskForBlockInfo::Operations(vec![missing_op.id]),
             )),
             TestsStepMatch::BlockManaged((block.id, true)),
         ],
@@ -857,13 +2794,19 @@ fn test_noting_block_does_not_panic_with_one_max_node_known_blocks_size() {
         waitpoint.get_trigger_handle(),
     );
 
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    // the other operation of the block is already in our mempool
+    universe.storage.store_operations(vec![known_op.clone()]);
 
     universe.mock_message_receive(
         &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
+        Message::Block(Box::new(BlockMessage::CompactBlock {
+            header: block.content.header.clone(),
+            operation_ids: vec![known_op.id, missing_op.id],
+        })),
     );
-
+    // the block is wanted once consensus got its header
+    header_waitpoint.wait();
     universe
         .module_controller
         .send_wishlist_delta(
@@ -874,51 +2817,41 @@ fn test_noting_block_does_not_panic_with_one_max_node_known_blocks_size() {
         )
         .unwrap();
     waitpoint.wait();
-    waitpoint.wait();
-
-    universe.mock_message_receive(
-        &node_b_peer_id,
-        Message::Block(Box::new(BlockMessage::DataResponse {
-            block_id: block.id,
-            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
-        })),
-    );
-    waitpoint.wait();
 
     universe.mock_message_receive(
-        &node_b_peer_id,
+        &node_a_peer_id,
         Message::Block(Box::new(BlockMessage::DataResponse {
             block_id: block.id,
-            block_info: BlockInfoReply::Operations(vec![op_1]),
+            block_info: BlockInfoReply::Operations(vec![missing_op]),
         })),
     );
     waitpoint.wait();
 }
 
 #[test]
-fn test_protocol_does_propagate_operations_received_in_blocks() {
+fn test_operation_ids_received_in_chunks_are_reassembled() {
     let protocol_config = ProtocolConfig {
         thread_count: 2,
-        ask_block_timeout: MassaTime::from_millis(200),
+        ask_block_timeout: MassaTime::from_millis(100),
         ..Default::default()
     };
 
     let block_creator = KeyPair::generate(0).unwrap();
-    let op_1 = ProtocolTestUniverse::create_operation(&block_creator, 5);
-    let op_thread = op_1
+    let ops: Vec<_> = (5..8)
+        .map(|expire_period| ProtocolTestUniverse::create_operation(&block_creator, expire_period))
+        .collect();
+    let op_thread = ops[0]
         .content_creator_address
         .get_thread(protocol_config.thread_count);
     let block = ProtocolTestUniverse::create_block(
         &block_creator,
         Slot::new(1, op_thread),
-        vec![op_1.clone()],
+        ops.clone(),
         vec![],
         vec![],
     );
     let node_a_keypair = KeyPair::generate(0).unwrap();
     let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
-    let node_b_keypair = KeyPair::generate(0).unwrap();
-    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
 
     let waitpoint = WaitPoint::new();
     let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
@@ -930,6 +2863,7 @@ fn test_protocol_does_propagate_operations_received_in_blocks() {
             assert_eq!(block_id, block.id);
             assert_eq!(header.id, block.content.header.id);
         });
+    // the reassembled list matches the header: only the operation missing from storage is asked
     block_retrieval_mock(
         vec![
             TestsStepMatch::AskData((
@@ -940,34 +2874,21 @@ fn test_protocol_does_propagate_operations_received_in_blocks() {
             TestsStepMatch::AskData((
                 PeerIdMatchers::PeerId(node_a_peer_id),
                 block.id,
-                AskForBlockInfo::Operations(
-                    vec![op_1.id]
-                        .into_iter()
-                        .collect::<PreHashSet<OperationId>>()
-                        .into_iter()
-                        .collect(),
-                ),
+                AskForBlockInfo::Operations(vec![ops[2].id]),
             )),
             TestsStepMatch::BlockManaged((block.id, true)),
-            TestsStepMatch::OperationsPropagated((
-                PeerIdMatchers::PeerId(node_b_peer_id),
-                vec![op_1.clone()]
-                    .into_iter()
-                    .map(|op| op.id.prefix())
-                    .collect(),
-            )),
         ],
         &mut foreign_controllers,
         waitpoint.get_trigger_handle(),
     );
 
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.storage.store_operations(ops[..2].to_vec());
 
     universe.mock_message_receive(
         &node_a_peer_id,
         Message::Block(Box::new(BlockMessage::Header(block.content.header.clone()))),
     );
-
     universe
         .module_controller
         .send_wishlist_delta(
@@ -979,22 +2900,28 @@ fn test_protocol_does_propagate_operations_received_in_blocks() {
         .unwrap();
     waitpoint.wait();
 
-    universe.mock_message_receive(
-        &node_a_peer_id,
-        Message::Block(Box::new(BlockMessage::DataResponse {
-            block_id: block.id,
-            block_info: BlockInfoReply::OperationIds(vec![op_1.id]),
-        })),
-    );
+    // three operation ids, in chunks of two
+    for (offset, chunk) in [(0, &ops[..2]), (2, &ops[2..])] {
+        universe.mock_message_receive(
+            &node_a_peer_id,
+            Message::Block(Box::new(BlockMessage::DataResponse {
+                block_id: block.id,
+                block_info: BlockInfoReply::OperationIdsChunk {
+                    total: 3,
+                    offset,
+                    operation_ids: chunk.iter().map(|op| op.id).collect(),
+                },
+            })),
+        );
+    }
     waitpoint.wait();
 
     universe.mock_message_receive(
         &node_a_peer_id,
         Message::Block(Box::new(BlockMessage::DataResponse {
             block_id: block.id,
-            block_info: BlockInfoReply::Operations(vec![op_1]),
+            block_info: BlockInfoReply::Operations(vec![ops[2].clone()]),
         })),
     );
     waitpoint.wait();
-    waitpoint.wait();
 }
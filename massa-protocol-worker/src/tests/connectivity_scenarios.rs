@@ -0,0 +1,286 @@
+// Copyright (c) 2023 MASSA LABS <info@massa.net>
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::handlers::peer_handler::announcement::Announcement;
+use crate::handlers::peer_handler::models::{ConnectionMetadata, PeerDB, PeerInfo, PeerState};
+use crate::wrap_network::MockActiveConnectionsTraitWrapper;
+use crate::wrap_peer_db::PeerDBTrait;
+
+use super::universe::{ConnectedPeers, ProtocolForeignControllers, ProtocolTestUniverse};
+use massa_protocol_exports::{PeerId, ProtocolConfig};
+use massa_signature::KeyPair;
+use massa_test_framework::TestUniverse;
+use massa_time::MassaTime;
+use parking_lot::Mutex;
+use peernet::transports::TransportType;
+
+#[test]
+fn test_dial_known_peers_up_to_target_out_connections() {
+    let protocol_config = ProtocolConfig {
+        target_out_connections: 3,
+        try_connection_timer: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+
+    // known peers announced on public addresses, the first one is already connected
+    let mut known_peers = HashMap::new();
+    let mut peer_ids_by_addr = HashMap::new();
+    for index in 1..=5 {
+        let keypair = KeyPair::generate(0).unwrap();
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        let listener: SocketAddr = format!("1.1.1.{}:31244", index).parse().unwrap();
+        let announcement = Announcement::new(
+            HashMap::from([(listener, TransportType::Tcp)]),
+            Some(listener.ip()),
+            &keypair,
+        )
+        .unwrap();
+        known_peers.insert(
+            peer_id,
+            PeerInfo {
+                last_announce: Some(announcement),
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        peer_ids_by_addr.insert(listener, peer_id);
+    }
+    let connected_addr: SocketAddr = "1.1.1.1:31244".parse().unwrap();
+    let connected_peers = ConnectedPeers::new([peer_ids_by_addr[&connected_addr]]);
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    {
+        let mut peer_db = foreign_controllers.peer_db.write();
+        peer_db.expect_get_peers().return_const(known_peers);
+        peer_db
+            .expect_get_connection_metadata_or_default()
+            .returning(|_| ConnectionMetadata::default());
+        peer_db
+            .expect_set_try_connect_success_or_insert()
+            .return_const(());
+        peer_db
+            .expect_set_try_connect_failure_or_insert()
+            .return_const(());
+        ProtocolTestUniverse::peer_db_boilerplate(&mut peer_db);
+    }
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &connected_peers,
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    // the dials succeed right away
+    let (dial_sender, dial_receiver) = mpsc::channel();
+    let dialed_peers = connected_peers.clone();
+    foreign_controllers
+        .network_controller
+        .expect_try_connect()
+        .returning(move |addr, _| {
+            dialed_peers.connect(peer_ids_by_addr[&addr]);
+            dial_sender.send(addr).unwrap();
+            Ok(())
+        });
+
+    let _universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // the two missing outbound connections are dialed, to other peers than the connected one
+    let mut dialed_addrs = HashSet::new();
+    for _ in 0..2 {
+        let addr = dial_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the node didn't dial enough peers");
+        assert_ne!(addr, connected_addr);
+        assert!(dialed_addrs.insert(addr), "{} dialed twice", addr);
+    }
+    // and no more once the target is met, over several connection ticks
+    assert!(
+        dial_receiver.recv_timeout(Duration::from_secs(1)).is_err(),
+        "the node dialed more peers than its target"
+    );
+}
+
+#[test]
+fn test_outbound_only_peer_is_redialed_after_disconnect() {
+    let outbound_only_keypair = KeyPair::generate(0).unwrap();
+    let outbound_only_peer_id = PeerId::from_public_key(outbound_only_keypair.get_public_key());
+    let outbound_only_addr: SocketAddr = "2.2.2.2:31244".parse().unwrap();
+    let protocol_config = ProtocolConfig {
+        target_out_connections: 1,
+        try_connection_timer: MassaTime::from_millis(100),
+        outbound_only_peers: vec![(outbound_only_peer_id, outbound_only_addr)],
+        ..Default::default()
+    };
+
+    // a single known peer, dialed for the only outbound slot
+    let keypair = KeyPair::generate(0).unwrap();
+    let peer_id = PeerId::from_public_key(keypair.get_public_key());
+    let listener: SocketAddr = "1.1.1.1:31244".parse().unwrap();
+    let announcement = Announcement::new(
+        HashMap::from([(listener, TransportType::Tcp)]),
+        Some(listener.ip()),
+        &keypair,
+    )
+    .unwrap();
+    let known_peers = HashMap::from([(
+        peer_id,
+        PeerInfo {
+            last_announce: Some(announcement),
+            state: PeerState::Trusted,
+            ban_reason: None,
+            reputation: 0,
+            features: None,
+        },
+    )]);
+    let peer_ids_by_addr = HashMap::from([
+        (listener, peer_id),
+        (outbound_only_addr, outbound_only_peer_id),
+    ]);
+    let connected_peers = ConnectedPeers::new([]);
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    {
+        let mut peer_db = foreign_controllers.peer_db.write();
+        peer_db.expect_get_peers().return_const(known_peers);
+        peer_db
+            .expect_get_connection_metadata_or_default()
+            .returning(|_| ConnectionMetadata::default());
+        peer_db
+            .expect_set_try_connect_success_or_insert()
+            .return_const(());
+        peer_db
+            .expect_set_try_connect_failure_or_insert()
+            .return_const(());
+        ProtocolTestUniverse::peer_db_boilerplate(&mut peer_db);
+    }
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &connected_peers,
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    let (dial_sender, dial_receiver) = mpsc::channel();
+    let dialed_peers = connected_peers.clone();
+    foreign_controllers
+        .network_controller
+        .expect_try_connect()
+        .returning(move |addr, _| {
+            dialed_peers.connect(peer_ids_by_addr[&addr]);
+            dial_sender.send(addr).unwrap();
+            Ok(())
+        });
+
+    let _universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // the outbound-only peer doesn't take the outbound slot of the known peer
+    let mut dialed_addrs = HashSet::new();
+    for _ in 0..2 {
+        let addr = dial_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the node didn't dial both peers");
+        dialed_addrs.insert(addr);
+    }
+    assert_eq!(dialed_addrs, HashSet::from([listener, outbound_only_addr]));
+
+    // once its connection drops, the outbound-only peer is dialed again
+    connected_peers.drop_connection(&outbound_only_peer_id);
+    assert_eq!(
+        dial_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the outbound-only peer wasn't redialed"),
+        outbound_only_addr
+    );
+}
+
+#[test]
+fn test_peers_not_heard_of_are_forgotten_after_expiry() {
+    let peer_expiry = MassaTime::from_millis(60 * 60 * 1000);
+    let protocol_config = ProtocolConfig {
+        peer_expiry,
+        ..Default::default()
+    };
+
+    // two peers that announced themselves once, only one of them stays connected
+    let mut peer_db = PeerDB::new(&protocol_config, MassaTime::now());
+    let [stale_peer_id, connected_peer_id] = [1, 2].map(|index| {
+        let keypair = KeyPair::generate(0).unwrap();
+        let peer_id = PeerId::from_public_key(keypair.get_public_key());
+        let listener: SocketAddr = format!("1.1.1.{}:31244", index).parse().unwrap();
+        let announcement = Announcement::new(
+            HashMap::from([(listener, TransportType::Tcp)]),
+            Some(listener.ip()),
+            &keypair,
+        )
+        .unwrap();
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: Some(announcement),
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        peer_id
+    });
+    let peer_db = Arc::new(Mutex::new(peer_db));
+    let connected_peers = ConnectedPeers::new([connected_peer_id]);
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    let (prune_sender, prune_receiver) = mpsc::channel();
+    {
+        let mut mock_peer_db = foreign_controllers.peer_db.write();
+        let peer_db = peer_db.clone();
+        mock_peer_db
+            .expect_prune_expired_peers()
+            .returning(move |now, connected| {
+                let pruned = peer_db.lock().prune_expired_peers(now, connected);
+                prune_sender.send(pruned.clone()).unwrap();
+                pruned
+            });
+        ProtocolTestUniverse::peer_db_boilerplate(&mut mock_peer_db);
+    }
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::connected_peers_boilerplate(
+        &mut shared_active_connections,
+        &connected_peers,
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    // nobody expired at the first check
+    let check_interval = MassaTime::from_millis(peer_expiry.as_millis() / 10);
+    universe.clock.advance(check_interval);
+    assert!(prune_receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("the expired peers weren't looked for")
+        .is_empty());
+
+    // once the expiry elapsed, the disconnected peer is forgotten
+    universe.clock.advance(peer_expiry);
+    assert_eq!(
+        prune_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the expired peers weren't looked for"),
+        vec![stale_peer_id]
+    );
+    let peer_db = peer_db.lock();
+    assert!(!peer_db.peers.contains_key(&stale_peer_id));
+    assert!(peer_db.peers.contains_key(&connected_peer_id));
+}
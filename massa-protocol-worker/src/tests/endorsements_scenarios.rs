@@ -1,7 +1,10 @@
+use std::time::Duration;
+
+use massa_models::endorsement::EndorsementId;
 use massa_models::slot::Slot;
 use massa_pos_exports::Selection;
-use massa_protocol_exports::PeerId;
 use massa_protocol_exports::ProtocolConfig;
+use massa_protocol_exports::{BanReason, PeerId};
 use massa_signature::KeyPair;
 use massa_test_framework::{TestUniverse, WaitPoint};
 
@@ -105,8 +108,9 @@ fn test_protocol_does_not_send_invalid_endorsements_it_receives_to_pool() {
         .peer_db
         .write()
         .expect_ban_peer()
-        .returning(move |peer_id| {
+        .returning(move |peer_id, reason, _| {
             assert_eq!(peer_id, &node_a_peer_id);
+            assert_eq!(reason, BanReason::InvalidEndorsementSignature);
             waitpoint_trigger_handle.trigger();
         });
     foreign_controllers
@@ -218,6 +222,92 @@ fn test_protocol_propagates_endorsements_to_active_nodes() {
     waitpoint.wait();
 }
 
+#[test]
+fn test_protocol_drops_endorsements_it_already_saw() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+    let peer_ids = [node_a_peer_id, node_b_peer_id];
+    let endorsement_creator = KeyPair::generate(0).unwrap();
+    let endorsement =
+        ProtocolTestUniverse::create_endorsement(&endorsement_creator, Slot::new(1, 1));
+    let endorsement_clone = endorsement.clone();
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        peer_ids.into_iter().collect(),
+    );
+    // relayed at most once (the second sender may already know it), never back to the first sender
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_send_to_peer()
+            .times(0..=1)
+            .returning(
+                move |peer_id, _message_serializer, message, _high_priority| {
+                    assert_eq!(peer_id, &node_b_peer_id);
+                    match message {
+                        Message::Endorsement(EndorsementMessage::Endorsements(endorsements)) => {
+                            assert_eq!(endorsements, vec![endorsement_clone.clone()]);
+                        }
+                        _ => panic!("Unexpected message type"),
+                    }
+                    Ok(())
+                },
+            );
+    });
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    // inserted once in the pool
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller
+                .expect_add_endorsements()
+                .times(1)
+                .returning(move |endorsements_storage| {
+                    assert_eq!(endorsements_storage.get_endorsement_refs().len(), 1);
+                    waitpoint_trigger_handle.trigger();
+                });
+        });
+    foreign_controllers
+        .selector_controller
+        .set_expectations(|selector_controller| {
+            selector_controller
+                .expect_get_selection()
+                .return_once(move |_| {
+                    Ok(Selection {
+                        endorsements: vec![endorsement.content_creator_address; 1],
+                        producer: endorsement.content_creator_address,
+                    })
+                });
+        });
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Endorsement(EndorsementMessage::Endorsements(vec![endorsement.clone()])),
+    );
+    waitpoint.wait();
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Endorsement(EndorsementMessage::Endorsements(vec![endorsement])),
+    );
+    // leave time for an unexpected second insertion or relay
+    std::thread::sleep(Duration::from_millis(300));
+}
+
 #[test]
 fn test_protocol_propagates_endorsements_only_to_nodes_that_dont_know_about_it_block_integration() {
     let protocol_config = ProtocolConfig {
@@ -307,3 +397,98 @@ fn test_protocol_propagates_endorsements_only_to_nodes_that_dont_know_about_it_b
     );
     waitpoint.wait();
 }
+
+#[test]
+fn test_protocol_asks_announced_endorsements_and_sends_them_to_pool() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        endorsement_announce_enabled: true,
+        ..Default::default()
+    };
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let peer_ids = [node_a_peer_id];
+    let endorsement_creator = KeyPair::generate(0).unwrap();
+    let endorsements = vec![
+        ProtocolTestUniverse::create_endorsement(&endorsement_creator, Slot::new(1, 0)),
+        ProtocolTestUniverse::create_endorsement(&endorsement_creator, Slot::new(1, 1)),
+    ];
+    let endorsement_ids: Vec<EndorsementId> = endorsements
+        .iter()
+        .map(|endorsement| endorsement.id)
+        .collect();
+    let creator_address = endorsements[0].content_creator_address;
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let asked_ids = endorsement_ids.clone();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections.expect_send_to_peer().times(1).returning(
+            move |peer_id, _, message, _| {
+                assert_eq!(peer_id, &node_a_peer_id);
+                match message {
+                    Message::Endorsement(EndorsementMessage::AskEndorsements(ids)) => {
+                        assert_eq!(ids.len(), 2);
+                        for id in asked_ids.iter() {
+                            assert!(ids.contains(id));
+                        }
+                    }
+                    _ => panic!("Unexpected message type."),
+                }
+                waitpoint_trigger_handle.trigger();
+                Ok(())
+            },
+        );
+    });
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        peer_ids.into_iter().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let pooled_ids = endorsement_ids.clone();
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller
+                .expect_add_endorsements()
+                .return_once(move |endorsements_storage| {
+                    let stored_endorsements = endorsements_storage.get_endorsement_refs();
+                    assert_eq!(stored_endorsements.len(), 2);
+                    for id in pooled_ids.iter() {
+                        assert!(stored_endorsements.contains(id));
+                    }
+                    waitpoint_trigger_handle.trigger();
+                });
+        });
+    foreign_controllers
+        .selector_controller
+        .set_expectations(|selector_controller| {
+            selector_controller
+                .expect_get_selection()
+                .returning(move |_| {
+                    Ok(Selection {
+                        endorsements: vec![creator_address; 1],
+                        producer: creator_address,
+                    })
+                });
+        });
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Endorsement(EndorsementMessage::AnnounceIds(endorsement_ids)),
+    );
+    waitpoint.wait();
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Endorsement(EndorsementMessage::Endorsements(endorsements)),
+    );
+    waitpoint.wait();
+}
@@ -1,25 +1,45 @@
 use num::rational::Ratio;
-use std::{collections::HashMap, fs::read_to_string, time::Duration};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    time::{Duration, Instant},
+};
 
 use massa_consensus_exports::MockConsensusController;
+use massa_hash::Hash;
 use massa_metrics::MassaMetrics;
-use massa_models::config::MIP_STORE_STATS_BLOCK_CONSIDERED;
+use massa_models::{
+    block_id::BlockId,
+    config::MIP_STORE_STATS_BLOCK_CONSIDERED,
+    prehash::{PreHashMap, PreHashSet},
+};
 use massa_pool_exports::MockPoolController;
 use massa_pos_exports::MockSelectorController;
-use massa_protocol_exports::{PeerCategoryInfo, PeerData, PeerId, ProtocolConfig};
+use massa_protocol_exports::{
+    AsyncProtocolController, BanReason, ConfigError, PeerCategoryInfo, PeerData, PeerEvent, PeerId,
+    ProtocolConfig, ProtocolError,
+};
 use massa_signature::KeyPair;
 use massa_storage::Storage;
+use massa_time::MassaTime;
 use massa_versioning::versioning::{MipStatsConfig, MipStore};
 use peernet::transports::TransportType;
 use tempfile::NamedTempFile;
 
-use crate::{create_protocol_controller, start_protocol_controller};
+use crate::{
+    create_protocol_controller,
+    handlers::peer_handler::models::{PeerInfo, PeerState},
+    start_protocol_controller,
+    worker::load_initial_peers,
+};
 
 mod ban_nodes_scenarios;
 mod block_scenarios;
+mod connectivity_scenarios;
 mod endorsements_scenarios;
 mod operations_scenarios;
 mod peer_priorization;
+mod tools;
 mod universe;
 
 #[test]
@@ -107,7 +127,7 @@ fn basic() {
     );
     serde_json::to_writer_pretty(initial_peers_file_2.as_file(), &initial_peers2)
         .expect("unable to write ledger file");
-    config1.initial_peers = initial_peers_file.path().to_path_buf();
+    config1.initial_peers = vec![initial_peers_file.path().to_path_buf()];
     let mut categories = HashMap::default();
     categories.insert(
         "Bootstrap".to_string(),
@@ -119,7 +139,7 @@ fn basic() {
         },
     );
     config1.peers_categories = categories;
-    config2.initial_peers = initial_peers_file_2.path().to_path_buf();
+    config2.initial_peers = vec![initial_peers_file_2.path().to_path_buf()];
     let mut categories2 = HashMap::default();
     categories2.insert(
         "Bootstrap".to_string(),
@@ -270,7 +290,7 @@ fn stop_with_controller_still_exists() {
     );
     serde_json::to_writer_pretty(initial_peers_file_2.as_file(), &initial_peers2)
         .expect("unable to write ledger file");
-    config1.initial_peers = initial_peers_file.path().to_path_buf();
+    config1.initial_peers = vec![initial_peers_file.path().to_path_buf()];
     let mut categories = HashMap::default();
     categories.insert(
         "Bootstrap".to_string(),
@@ -282,7 +302,7 @@ fn stop_with_controller_still_exists() {
         },
     );
     config1.peers_categories = categories;
-    config2.initial_peers = initial_peers_file_2.path().to_path_buf();
+    config2.initial_peers = vec![initial_peers_file_2.path().to_path_buf()];
     let mut categories2 = HashMap::default();
     categories2.insert(
         "Bootstrap".to_string(),
@@ -349,3 +369,446 @@ fn stop_with_controller_still_exists() {
     manager1.stop();
     manager2.stop();
 }
+
+#[test]
+fn disconnected_peer_stays_trusted_and_reconnects() {
+    let default_panic = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_panic(info);
+        std::process::exit(1);
+    }));
+
+    let mut pool_controller1 = Box::new(MockPoolController::new());
+    pool_controller1
+        .expect_clone_box()
+        .returning(|| Box::new(MockPoolController::new()));
+    let mut pool_controller2 = Box::new(MockPoolController::new());
+    pool_controller2
+        .expect_clone_box()
+        .returning(|| Box::new(MockPoolController::new()));
+
+    let consensus_controller1 = Box::new(MockConsensusController::new());
+    let consensus_controller2 = Box::new(MockConsensusController::new());
+
+    let mut selector_controller1 = Box::new(MockSelectorController::new());
+    selector_controller1
+        .expect_clone_box()
+        .returning(|| Box::new(MockSelectorController::new()));
+    let mut selector_controller2 = Box::new(MockSelectorController::new());
+    selector_controller2
+        .expect_clone_box()
+        .returning(|| Box::new(MockSelectorController::new()));
+    // Setup the configs, the nodes try to connect often so that they reconnect quickly
+    let mut config1 = ProtocolConfig {
+        try_connection_timer: MassaTime::from_millis(100),
+        try_connection_timer_same_peer: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+    config1
+        .listeners
+        .insert("127.0.0.1:8087".parse().unwrap(), TransportType::Tcp);
+    config1.keypair_file = "./src/tests/test_keypair1.json".to_string().into();
+    let mut config2 = ProtocolConfig {
+        try_connection_timer: MassaTime::from_millis(100),
+        try_connection_timer_same_peer: MassaTime::from_millis(100),
+        ..Default::default()
+    };
+    config2
+        .listeners
+        .insert("127.0.0.1:8088".parse().unwrap(), TransportType::Tcp);
+    config2.keypair_file = "./src/tests/test_keypair2.json".to_string().into();
+    let keypair_bs58_check_encoded = read_to_string(&config2.keypair_file)
+        .map_err(|err| {
+            std::io::Error::new(err.kind(), format!("could not load node key file: {}", err))
+        })
+        .unwrap();
+    let keypair2 =
+        serde_json::from_slice::<KeyPair>(keypair_bs58_check_encoded.as_bytes()).unwrap();
+    let peer_id2 = PeerId::from_public_key(keypair2.get_public_key());
+
+    // Only the first node knows the second one, it initiates the connections
+    let initial_peers_file = NamedTempFile::new().expect("cannot create temp file");
+    let mut initial_peers1: HashMap<PeerId, PeerData> = HashMap::new();
+    let mut peers_1 = HashMap::new();
+    peers_1.insert("127.0.0.1:8088".parse().unwrap(), TransportType::Tcp);
+    initial_peers1.insert(
+        peer_id2,
+        PeerData {
+            listeners: peers_1,
+            category: "Bootstrap".to_string(),
+        },
+    );
+    serde_json::to_writer_pretty(initial_peers_file.as_file(), &initial_peers1)
+        .expect("unable to write ledger file");
+    let initial_peers_file_2 = NamedTempFile::new().expect("cannot create temp file");
+    serde_json::to_writer_pretty(
+        initial_peers_file_2.as_file(),
+        &HashMap::<PeerId, PeerData>::new(),
+    )
+    .expect("unable to write ledger file");
+    config1.initial_peers = vec![initial_peers_file.path().to_path_buf()];
+    let mut categories = HashMap::default();
+    categories.insert(
+        "Bootstrap".to_string(),
+        PeerCategoryInfo {
+            allow_local_peers: true,
+            max_in_connections: 1,
+            target_out_connections: 1,
+            max_in_connections_per_ip: 1,
+        },
+    );
+    config1.peers_categories = categories;
+    config2.initial_peers = vec![initial_peers_file_2.path().to_path_buf()];
+    config2.debug = false;
+
+    let mip_stats_config = MipStatsConfig {
+        block_count_considered: MIP_STORE_STATS_BLOCK_CONSIDERED,
+        warn_announced_version_ratio: Ratio::new_raw(30, 100),
+    };
+    let mip_store = MipStore::try_from(([], mip_stats_config)).unwrap();
+    let metrics = MassaMetrics::new(
+        false,
+        "0.0.0.0:9898".parse().unwrap(),
+        32,
+        std::time::Duration::from_secs(5),
+    )
+    .0;
+
+    let (mut sender_manager1, channels1) = create_protocol_controller(config1.clone());
+    let (mut sender_manager2, channels2) = create_protocol_controller(config2.clone());
+    let peer_db1 = channels1.peer_db.clone();
+    let mut peer_events1 = sender_manager1.subscribe_peer_events();
+    let (mut manager1, _, _) = start_protocol_controller(
+        config1,
+        selector_controller1,
+        consensus_controller1,
+        None,
+        pool_controller1,
+        Storage::create_root(),
+        channels1,
+        mip_store.clone(),
+        metrics.clone(),
+    )
+    .expect("Failed to start protocol 1");
+    let (mut manager2, _, _) = start_protocol_controller(
+        config2,
+        selector_controller2,
+        consensus_controller2,
+        None,
+        pool_controller2,
+        Storage::create_root(),
+        channels2,
+        mip_store,
+        metrics,
+    )
+    .expect("Failed to start protocol 2");
+
+    let mut wait_for_event = |is_expected: &dyn Fn(&PeerEvent) -> bool, what: &str| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match peer_events1.try_recv() {
+                Ok(event) if is_expected(&event) => return,
+                Ok(_) => {}
+                Err(_) => {
+                    assert!(Instant::now() < deadline, "the {} wasn't noticed", what);
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+    };
+    wait_for_event(
+        &|event| matches!(event, PeerEvent::Connected { peer_id, .. } if *peer_id == peer_id2),
+        "connection",
+    );
+
+    sender_manager1.disconnect_peer(&peer_id2, true).unwrap();
+    wait_for_event(
+        &|event| matches!(event, PeerEvent::Disconnected { peer_id, .. } if *peer_id == peer_id2),
+        "disconnection",
+    );
+    // the peer isn't banned, it is connected to again
+    {
+        let peer_db1 = peer_db1.read();
+        assert_eq!(
+            peer_db1
+                .get_peers()
+                .get(&peer_id2)
+                .map(|peer| peer.state.clone()),
+            Some(PeerState::Trusted)
+        );
+        assert!(peer_db1.get_ban_reason(&peer_id2).is_none());
+    }
+    wait_for_event(
+        &|event| matches!(event, PeerEvent::Connected { peer_id, .. } if *peer_id == peer_id2),
+        "reconnection",
+    );
+
+    // Stop the protocols
+    sender_manager1.stop();
+    sender_manager2.stop();
+    manager1.stop();
+    manager2.stop();
+}
+
+#[test]
+fn list_and_clear_bans_from_controller() {
+    let (controller, channels) = create_protocol_controller(ProtocolConfig {
+        ban_durations: HashMap::from([(BanReason::Manual, MassaTime::from_millis(60 * 1000))]),
+        ..Default::default()
+    });
+    let mut peer_events = controller.subscribe_peer_events();
+    let peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+    {
+        let mut peer_db = channels.peer_db.write();
+        peer_db.insert_peer(
+            peer_id,
+            PeerInfo {
+                last_announce: None,
+                state: PeerState::Trusted,
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            },
+        );
+        peer_db.ban_peer(&peer_id, BanReason::Manual, MassaTime::now());
+    }
+
+    let banned_peers = controller.get_banned_peers();
+    assert_eq!(banned_peers.len(), 1);
+    assert_eq!(banned_peers[0].peer_id, peer_id);
+    assert_eq!(banned_peers[0].reason, BanReason::Manual);
+    let remaining = banned_peers[0].remaining.unwrap();
+    assert!(remaining > MassaTime::from_millis(0));
+    assert!(remaining <= MassaTime::from_millis(60 * 1000));
+
+    controller.unban_peer(&peer_id).unwrap();
+    assert!(controller.get_banned_peers().is_empty());
+    assert!(matches!(
+        peer_events.try_recv(),
+        Ok(PeerEvent::Unbanned { peer_id: unbanned, .. }) if unbanned == peer_id
+    ));
+    // the peer isn't banned anymore
+    assert!(controller.unban_peer(&peer_id).is_err());
+}
+
+#[tokio::test]
+async fn async_controller_times_out_when_the_worker_is_unresponsive() {
+    // the protocol threads aren't started, nothing reads the commands of the block handler
+    let (controller, channels) = create_protocol_controller(ProtocolConfig {
+        max_size_channel_commands_retrieval_blocks: 1,
+        max_size_channel_commands_propagation_blocks: 1,
+        ..Default::default()
+    });
+    let timeout = Duration::from_millis(200);
+    let controller = AsyncProtocolController::new(controller, timeout);
+    let block_id = BlockId::generate_from_hash(Hash::compute_from(b"attack"));
+
+    // the first commands fill the queues
+    controller
+        .send_wishlist_delta(PreHashMap::default(), PreHashSet::default())
+        .await
+        .unwrap();
+    controller.notify_block_attack(block_id).await.unwrap();
+
+    let start = Instant::now();
+    assert!(matches!(
+        controller
+            .send_wishlist_delta(PreHashMap::default(), PreHashSet::default())
+            .await,
+        Err(ProtocolError::Timeout(_))
+    ));
+    assert!(start.elapsed() >= timeout);
+    assert!(matches!(
+        controller.notify_block_attack(block_id).await,
+        Err(ProtocolError::Timeout(_))
+    ));
+
+    // the calls go through again once the worker catches up
+    channels.block_handler_retrieval.1.try_recv().unwrap();
+    controller
+        .send_wishlist_delta(PreHashMap::default(), PreHashSet::default())
+        .await
+        .unwrap();
+    assert!(channels.block_handler_retrieval.1.try_recv().is_ok());
+    assert!(channels.block_handler_retrieval.1.try_recv().is_err());
+}
+
+/// Default config reading its initial peers from `initial_peers_file`
+fn config_with_initial_peers(initial_peers_file: &NamedTempFile) -> ProtocolConfig {
+    ProtocolConfig {
+        initial_peers: vec![initial_peers_file.path().to_path_buf()],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn validate_accepts_consistent_config() {
+    let initial_peers_file = NamedTempFile::new().expect("cannot create temp file");
+    config_with_initial_peers(&initial_peers_file)
+        .validate()
+        .unwrap();
+}
+
+#[test]
+fn validate_rejects_zero_thread_count() {
+    let initial_peers_file = NamedTempFile::new().expect("cannot create temp file");
+    let config = ProtocolConfig {
+        thread_count: 0,
+        ..config_with_initial_peers(&initial_peers_file)
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::ZeroThreadCount)
+    ));
+}
+
+#[test]
+fn validate_rejects_missing_initial_peers() {
+    let initial_peers_file = NamedTempFile::new().expect("cannot create temp file");
+    let config = config_with_initial_peers(&initial_peers_file);
+    drop(initial_peers_file);
+    let error = config.validate().unwrap_err();
+    assert!(
+        matches!(&error, ConfigError::UnreadableInitialPeers { path, .. } if *path == config.initial_peers[0])
+    );
+    assert!(error
+        .to_string()
+        .contains(&config.initial_peers[0].display().to_string()));
+}
+
+#[test]
+fn initial_peers_are_merged_from_all_sources() {
+    let peer_1 = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+    let peer_2 = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+    let peer_3 = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+    let write_peers_file = |peers: &[(PeerId, &str)]| {
+        let peers_file = NamedTempFile::new().expect("cannot create temp file");
+        let peers: HashMap<PeerId, PeerData> = peers
+            .iter()
+            .map(|(peer_id, addr)| {
+                (
+                    *peer_id,
+                    PeerData {
+                        listeners: HashMap::from([(addr.parse().unwrap(), TransportType::Tcp)]),
+                        category: "Bootstrap".to_string(),
+                    },
+                )
+            })
+            .collect();
+        serde_json::to_writer_pretty(peers_file.as_file(), &peers)
+            .expect("unable to write initial peers file");
+        peers_file
+    };
+    let peers_file_1 = write_peers_file(&[(peer_1, "127.0.0.1:8081"), (peer_2, "127.0.0.1:8082")]);
+    let peers_file_2 = write_peers_file(&[(peer_2, "127.0.0.2:8082"), (peer_3, "127.0.0.1:8083")]);
+    let missing_file = NamedTempFile::new().expect("cannot create temp file");
+    let missing_path = missing_file.path().to_path_buf();
+    drop(missing_file);
+    let peer_4 = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+    let config = ProtocolConfig {
+        initial_peers: vec![
+            peers_file_1.path().to_path_buf(),
+            missing_path.clone(),
+            peers_file_2.path().to_path_buf(),
+        ],
+        inline_initial_peers: vec![
+            (peer_1, "127.0.0.3:8081".parse().unwrap()),
+            (peer_4, "127.0.0.1:8084".parse().unwrap()),
+        ],
+        ..Default::default()
+    };
+    // a missing file is skipped as long as another one can be read
+    config.validate().unwrap();
+
+    let initial_peers = load_initial_peers(&config).unwrap();
+    assert_eq!(initial_peers.len(), 4);
+    let listeners = |peer_id: &PeerId| {
+        let mut listeners: Vec<String> = initial_peers[peer_id]
+            .listeners
+            .keys()
+            .map(|addr| addr.to_string())
+            .collect();
+        listeners.sort();
+        listeners
+    };
+    assert_eq!(listeners(&peer_1), ["127.0.0.1:8081", "127.0.0.3:8081"]);
+    assert_eq!(listeners(&peer_2), ["127.0.0.1:8082", "127.0.0.2:8082"]);
+    assert_eq!(listeners(&peer_3), ["127.0.0.1:8083"]);
+    assert_eq!(listeners(&peer_4), ["127.0.0.1:8084"]);
+    assert_eq!(initial_peers[&peer_1].category, "Bootstrap");
+    assert_eq!(initial_peers[&peer_4].category, "");
+
+    // the loading fails when none of the files can be read
+    let config = ProtocolConfig {
+        initial_peers: vec![missing_path],
+        ..config
+    };
+    assert!(config.validate().is_err());
+    assert!(matches!(
+        load_initial_peers(&config),
+        Err(ProtocolError::IOError(_))
+    ));
+}
+
+#[test]
+fn validate_rejects_zero_timers() {
+    let initial_peers_file = NamedTempFile::new().expect("cannot create temp file");
+    let config = ProtocolConfig {
+        try_connection_timer: MassaTime::from_millis(0),
+        ..config_with_initial_peers(&initial_peers_file)
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::ZeroTimer("try_connection_timer"))
+    ));
+
+    // the ping timeout only matters when the keepalive is enabled
+    let config = ProtocolConfig {
+        ping_timeout: MassaTime::from_millis(0),
+        ..config_with_initial_peers(&initial_peers_file)
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::ZeroTimer("ping_timeout"))
+    ));
+    let config = ProtocolConfig {
+        idle_ping_interval: MassaTime::from_millis(0),
+        ..config
+    };
+    config.validate().unwrap();
+}
+
+#[test]
+fn validate_rejects_contradictory_timers() {
+    let initial_peers_file = NamedTempFile::new().expect("cannot create temp file");
+    let config = ProtocolConfig {
+        connect_backoff_base: MassaTime::from_millis(60000),
+        connect_backoff_max: MassaTime::from_millis(1000),
+        ..config_with_initial_peers(&initial_peers_file)
+    };
+    let error = config.validate().unwrap_err();
+    assert!(matches!(
+        error,
+        ConfigError::TimerOrder {
+            shorter: "connect_backoff_base",
+            longer: "connect_backoff_max",
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "connect_backoff_base must not be longer than connect_backoff_max"
+    );
+
+    let config = ProtocolConfig {
+        block_propagation_tick: MassaTime::from_millis(50000),
+        max_block_propagation_time: MassaTime::from_millis(40000),
+        ..config_with_initial_peers(&initial_peers_file)
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::TimerOrder {
+            shorter: "block_propagation_tick",
+            ..
+        })
+    ));
+}
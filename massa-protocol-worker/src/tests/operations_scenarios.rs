@@ -1,18 +1,28 @@
 // Copyright (c) 2022 MASSA LABS <info@massa.net>
 
 use std::collections::HashSet;
-use std::time::Duration;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use massa_models::operation::{OperationPrefixId, SecureShareOperation};
+use massa_models::address::Address;
+use massa_models::amount::Amount;
+use massa_models::operation::{
+    Operation, OperationId, OperationPrefixId, OperationSerializer, OperationType,
+    SecureShareOperation,
+};
+use massa_models::secure_share::SecureShareContent;
 use massa_models::{block_id::BlockId, prehash::PreHashSet, slot::Slot};
-use massa_protocol_exports::PeerId;
 use massa_protocol_exports::ProtocolConfig;
+use massa_protocol_exports::{BanReason, PeerId};
 use massa_signature::KeyPair;
 use massa_test_framework::{TestUniverse, WaitPoint};
 use massa_time::MassaTime;
 use mockall::{predicate, Sequence};
 
 use crate::handlers::block_handler::AskForBlockInfo;
+use crate::handlers::peer_handler::models::BanSeverity;
 use crate::wrap_network::MockActiveConnectionsTraitWrapper;
 use crate::{
     handlers::{
@@ -22,6 +32,7 @@ use crate::{
     messages::Message,
 };
 
+use super::tools::{assert_message_to_node_matches, assert_no_message_to_node};
 use super::universe::{ProtocolForeignControllers, ProtocolTestUniverse};
 
 enum TestsStepMatch {
@@ -216,6 +227,63 @@ fn test_protocol_sends_valid_operations_it_receives_to_pool() {
     waitpoint.wait();
 }
 
+#[test]
+fn test_protocol_does_not_reprocess_operations_it_already_checked() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operations: Vec<_> = (0..1000)
+        .map(|_| ProtocolTestUniverse::create_operation(&block_creator, 1))
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // the operations are verified and sent to the pool only once
+    operation_workflow_mock(
+        vec![TestsStepMatch::OperationsInPool(operations.clone())],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(operations.clone())),
+    );
+    waitpoint.wait();
+
+    let first_pass = universe.module_controller.get_metrics();
+    assert_eq!(first_pass.operations_received, 1000);
+    assert_eq!(first_pass.operations_duplicates, 0);
+
+    // the checked operations cache filters the duplicates before their signatures are verified
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(operations)),
+    );
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let second_pass = loop {
+        let metrics = universe.module_controller.get_metrics();
+        if metrics.operations_received == 2000 {
+            break metrics;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "the second batch wasn't processed in time"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // none of the operations of the second batch was verified nor sent to the pool again
+    assert_eq!(second_pass.operations_duplicates, 1000);
+    assert_eq!(second_pass.operations_invalid, 0);
+}
+
 #[test]
 fn test_protocol_does_not_send_invalid_operations_it_receives_to_pool() {
     let protocol_config = ProtocolConfig {
@@ -237,10 +305,12 @@ fn test_protocol_does_not_send_invalid_operations_it_receives_to_pool() {
     foreign_controllers
         .peer_db
         .write()
-        .expect_ban_peer()
+        .expect_ban_peer_with_severity()
         .times(1)
-        .returning(move |peer_id| {
+        .returning(move |peer_id, reason, severity, _| {
             assert_eq!(*peer_id, node_a_peer_id);
+            assert_eq!(reason, BanReason::InvalidOperationSignature);
+            assert_eq!(severity, BanSeverity::Major);
             waitpoint_trigger_handle2.trigger();
         });
     operation_workflow_mock(vec![], &mut foreign_controllers, waitpoint_trigger_handle);
@@ -257,6 +327,58 @@ fn test_protocol_does_not_send_invalid_operations_it_receives_to_pool() {
     waitpoint.wait();
 }
 
+#[test]
+fn test_protocol_only_bans_the_sender_of_the_invalid_operation_of_a_batch() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let valid_operations: Vec<_> = (0..3)
+        .map(|_| ProtocolTestUniverse::create_operation(&block_creator, 1))
+        .collect();
+    let mut invalid_operation = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    invalid_operation.content_creator_pub_key = KeyPair::generate(0).unwrap().get_public_key();
+    let honest_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+    let bad_peer_id = PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let ban_waitpoint = WaitPoint::new();
+    let ban_trigger_handle = ban_waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .peer_db
+        .write()
+        .expect_ban_peer_with_severity()
+        .times(1)
+        .returning(move |peer_id, reason, severity, _| {
+            assert_eq!(*peer_id, bad_peer_id);
+            assert_eq!(reason, BanReason::InvalidOperationSignature);
+            assert_eq!(severity, BanSeverity::Major);
+            ban_trigger_handle.trigger();
+        });
+    // the operations of the honest peer, verified in the same batch as the invalid one, reach the pool
+    operation_workflow_mock(
+        vec![TestsStepMatch::OperationsInPool(valid_operations.clone())],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &bad_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![invalid_operation])),
+    );
+    universe.mock_message_receive(
+        &honest_peer_id,
+        Message::Operation(OperationMessage::Operations(valid_operations)),
+    );
+    ban_waitpoint.wait();
+    waitpoint.wait();
+}
+
 #[test]
 fn test_protocol_propagates_operations_to_active_nodes() {
     let protocol_config = ProtocolConfig {
@@ -303,6 +425,186 @@ fn test_protocol_propagates_operations_to_active_nodes() {
     waitpoint.wait();
 }
 
+fn create_operation_with_fee(keypair: &KeyPair, fee: &str) -> SecureShareOperation {
+    let recv_keypair = KeyPair::generate(0).unwrap();
+    let content = Operation {
+        fee: Amount::from_str(fee).unwrap(),
+        op: OperationType::Transaction {
+            recipient_address: Address::from_public_key(&recv_keypair.get_public_key()),
+            amount: Amount::default(),
+        },
+        expire_period: 1,
+    };
+    Operation::new_verifiable(content, OperationSerializer::new(), keypair).unwrap()
+}
+
+#[test]
+fn test_protocol_does_not_propagate_operations_below_min_fee() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        min_propagation_fee: Amount::from_str("1").unwrap(),
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let zero_fee_operation = create_operation_with_fee(&block_creator, "0");
+    let high_fee_operation = create_operation_with_fee(&block_creator, "10");
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // both operations reach the pool, only the high fee one is relayed
+    operation_workflow_mock(
+        vec![
+            TestsStepMatch::OperationsInPool(vec![
+                zero_fee_operation.clone(),
+                high_fee_operation.clone(),
+            ]),
+            TestsStepMatch::OperationsPropagated((
+                node_b_peer_id,
+                vec![high_fee_operation.id.into_prefix()],
+                true,
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![
+            zero_fee_operation,
+            high_fee_operation,
+        ])),
+    );
+    waitpoint.wait();
+    waitpoint.wait();
+    std::thread::sleep(Duration::from_millis(300));
+}
+
+#[test]
+fn test_protocol_drops_expired_operations_it_receives() {
+    let t0 = MassaTime::from_millis(16000);
+    // the current period is 20
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        t0,
+        genesis_timestamp: MassaTime::now().saturating_sub(t0.saturating_mul(20)),
+        expired_operations_tolerance: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let expired_operation = ProtocolTestUniverse::create_operation(&block_creator, 10);
+    let valid_operation = ProtocolTestUniverse::create_operation(&block_creator, 30);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // the expired operation neither reaches the pool nor is relayed
+    operation_workflow_mock(
+        vec![
+            TestsStepMatch::OperationsInPool(vec![valid_operation.clone()]),
+            TestsStepMatch::OperationsPropagated((
+                node_b_peer_id,
+                vec![valid_operation.id.into_prefix()],
+                true,
+            )),
+        ],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![
+            expired_operation,
+            valid_operation,
+        ])),
+    );
+    waitpoint.wait();
+    waitpoint.wait();
+    std::thread::sleep(Duration::from_millis(300));
+}
+
+#[test]
+fn test_protocol_propagates_operations_to_fanout_peers_only() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        operation_propagation_fanout: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let peer_ids: Vec<PeerId> = (0..4)
+        .map(|_| PeerId::from_public_key(KeyPair::generate(0).unwrap().get_public_key()))
+        .collect();
+    let origin_peer_id = peer_ids[0];
+
+    let waitpoint = WaitPoint::new();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller
+                .expect_add_operations()
+                .times(1)
+                .returning(move |_| waitpoint_trigger_handle.trigger());
+        });
+    // the operation is announced to exactly `operation_propagation_fanout` peers
+    let announced_to = Arc::new(Mutex::new(Vec::new()));
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    let announced_to_clone = announced_to.clone();
+    let operation_prefix = operation.id.into_prefix();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections.expect_send_to_peer().times(2).returning(
+            move |peer_id, _, message, _| {
+                match message {
+                    Message::Operation(OperationMessage::OperationsAnnouncement(operations)) => {
+                        assert_eq!(operations.len(), 1);
+                        assert!(operations.contains(&operation_prefix));
+                    }
+                    _ => panic!("Unexpected message type."),
+                }
+                announced_to_clone.lock().unwrap().push(*peer_id);
+                Ok(())
+            },
+        );
+    });
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        peer_ids.iter().copied().collect(),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &origin_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![operation])),
+    );
+    waitpoint.wait();
+    // leave time for the announcements to be sent
+    std::thread::sleep(Duration::from_millis(500));
+    let announced_to: HashSet<PeerId> = announced_to.lock().unwrap().iter().copied().collect();
+    assert_eq!(announced_to.len(), 2);
+    assert!(!announced_to.contains(&origin_peer_id));
+}
+
 #[test]
 fn test_protocol_batches_propagation_of_operations_received_over_the_network_and_from_the_api() {
     let protocol_config = ProtocolConfig {
@@ -535,7 +837,7 @@ fn test_protocol_re_ask_operations_to_another_node_on_batch_received_after_delay
         &mut foreign_controllers,
         waitpoint_trigger_handle,
     );
-    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config.clone());
 
     universe.mock_message_receive(
         &node_a_peer_id,
@@ -545,6 +847,12 @@ fn test_protocol_re_ask_operations_to_another_node_on_batch_received_after_delay
     );
     waitpoint.wait();
 
+    // node B announces the operations once node A had the time to send them
+    universe.clock.advance(
+        protocol_config
+            .operation_batch_proc_period
+            .saturating_add(MassaTime::from_millis(1)),
+    );
     universe.mock_message_receive(
         &node_b_peer_id,
         Message::Operation(OperationMessage::OperationsAnnouncement(
@@ -611,6 +919,50 @@ fn test_protocol_does_not_re_ask_operations_to_another_node_if_received() {
     std::thread::sleep(Duration::from_millis(200));
 }
 
+#[test]
+fn test_protocol_only_asks_announced_operations_it_does_not_have() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let operation_2 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    operation_workflow_mock(
+        vec![
+            TestsStepMatch::OperationsInPool(vec![operation_1.clone()]),
+            TestsStepMatch::AskForOperations((node_a_peer_id, vec![operation_2.id.into_prefix()])),
+        ],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![operation_1.clone()])),
+    );
+    waitpoint.wait();
+
+    // we already hold the first operation: only the second one is asked
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::OperationsAnnouncement(
+            vec![operation_1.id.into_prefix(), operation_2.id.into_prefix()]
+                .into_iter()
+                .collect(),
+        )),
+    );
+    waitpoint.wait();
+}
+
 #[test]
 fn test_protocol_on_ask_operations() {
     let protocol_config = ProtocolConfig {
@@ -660,3 +1012,401 @@ fn test_protocol_on_ask_operations() {
     );
     waitpoint.wait();
 }
+
+#[test]
+fn test_protocol_does_not_send_operations_to_the_peer_that_announced_them() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let operation_2 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+    let node_b_keypair = KeyPair::generate(0).unwrap();
+    let node_b_peer_id = PeerId::from_public_key(node_b_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // only the operation node B didn't announce is sent to it
+    operation_workflow_mock(
+        vec![
+            TestsStepMatch::OperationsInPool(vec![operation_1.clone(), operation_2.clone()]),
+            TestsStepMatch::OperationsSent((node_b_peer_id, vec![operation_2.clone()])),
+        ],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![
+            operation_1.clone(),
+            operation_2.clone(),
+        ])),
+    );
+    waitpoint.wait();
+
+    // the operation is already known, it isn't asked back
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Operation(OperationMessage::OperationsAnnouncement(
+            vec![operation_1.id.into_prefix()].into_iter().collect(),
+        )),
+    );
+    universe.mock_message_receive(
+        &node_b_peer_id,
+        Message::Operation(OperationMessage::AskForOperations(
+            vec![operation_1.id.into_prefix(), operation_2.id.into_prefix()]
+                .into_iter()
+                .collect(),
+        )),
+    );
+    waitpoint.wait();
+    // leave time for an unexpected message with the announced operation to be sent
+    std::thread::sleep(Duration::from_millis(500));
+}
+
+#[test]
+fn test_protocol_batches_operations_asked_within_the_batch_window() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        operation_batch_window: MassaTime::from_millis(300),
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operations: Vec<SecureShareOperation> = (1..=3)
+        .map(|expire_period| ProtocolTestUniverse::create_operation(&block_creator, expire_period))
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // the three asked operations are sent back in a single message
+    operation_workflow_mock(
+        vec![TestsStepMatch::OperationsSent((
+            node_a_peer_id,
+            operations.clone(),
+        ))],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.storage.store_operations(operations.clone());
+
+    for operation in operations {
+        universe.mock_message_receive(
+            &node_a_peer_id,
+            Message::Operation(OperationMessage::AskForOperations(
+                vec![operation.id.into_prefix()].into_iter().collect(),
+            )),
+        );
+    }
+    waitpoint.wait();
+    // leave time for an unexpected second message to be sent
+    std::thread::sleep(Duration::from_millis(500));
+}
+
+#[test]
+fn test_protocol_counts_ingested_operations_in_metrics() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operations: Vec<SecureShareOperation> = (1..=3)
+        .map(|expire_period| ProtocolTestUniverse::create_operation(&block_creator, expire_period))
+        .collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let waitpoint = WaitPoint::new();
+    let waitpoint_trigger_handle = waitpoint.get_trigger_handle();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    // the duplicates don't reach the pool
+    operation_workflow_mock(
+        vec![TestsStepMatch::OperationsInPool(operations.clone())],
+        &mut foreign_controllers,
+        waitpoint_trigger_handle,
+    );
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(operations.clone())),
+    );
+    waitpoint.wait();
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(operations)),
+    );
+    std::thread::sleep(Duration::from_millis(300));
+
+    let metrics = universe.module_controller.get_metrics();
+    assert_eq!(metrics.operations_received, 6);
+    assert_eq!(metrics.operations_duplicates, 3);
+    assert_eq!(metrics.operations_invalid, 0);
+    assert_eq!(metrics.operations_below_fee, 0);
+    assert_eq!(metrics.operations_sent_to_pool, 3);
+    assert!(metrics.get_operation_ingestion_rate(MassaTime::now()) > 0.0);
+}
+
+#[test]
+fn test_protocol_stops_forwarding_operations_while_pool_is_full() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        pool_backpressure_enabled: true,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let operation_2 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let operation_3 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let pool_full = Arc::new(AtomicBool::new(false));
+    let (pool_sender, pool_receiver) = mpsc::channel();
+    let (message_sender, message_receiver) = mpsc::channel();
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            let pool_full = pool_full.clone();
+            pool_controller
+                .expect_is_operation_pool_full()
+                .returning(move || pool_full.load(Ordering::SeqCst));
+            pool_controller
+                .expect_add_operations()
+                .returning(move |op_storage| {
+                    pool_sender.send(op_storage.get_op_refs().clone()).unwrap();
+                });
+        });
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_send_to_peer()
+            .returning(move |_, _, message, _| {
+                message_sender.send(message).unwrap();
+                Ok(())
+            });
+    });
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([node_a_peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![operation_1.clone()])),
+    );
+    let in_pool = pool_receiver
+        .recv_timeout(Duration::from_secs(10))
+        .expect("the operation wasn't sent to the pool");
+    assert!(in_pool.contains(&operation_1.id));
+
+    pool_full.store(true, Ordering::SeqCst);
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::Operations(vec![operation_2.clone()])),
+    );
+    // the announced operations aren't asked for either
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::OperationsAnnouncement(
+            vec![operation_3.id.prefix()].into_iter().collect(),
+        )),
+    );
+    assert!(pool_receiver
+        .recv_timeout(Duration::from_millis(500))
+        .is_err());
+    while let Ok(message) = message_receiver.try_recv() {
+        assert!(
+            !matches!(
+                message,
+                Message::Operation(OperationMessage::AskForOperations(_))
+                    | Message::Operation(OperationMessage::OperationsAnnouncement(_))
+            ),
+            "operations were asked for or relayed while the pool is full"
+        );
+    }
+}
+
+/// Mocks of a connection to `peer_id` whose sent messages are forwarded to the returned receiver
+fn forward_sent_messages(
+    foreign_controllers: &mut ProtocolForeignControllers,
+    peer_id: PeerId,
+) -> mpsc::Receiver<Message> {
+    let (message_sender, message_receiver) = mpsc::channel();
+    let mut shared_active_connections = MockActiveConnectionsTraitWrapper::new();
+    shared_active_connections.set_expectations(|active_connections| {
+        active_connections
+            .expect_send_to_peer()
+            .returning(move |_, _, message, _| {
+                message_sender.send(message).unwrap();
+                Ok(())
+            });
+    });
+    ProtocolTestUniverse::active_connections_boilerplate(
+        &mut shared_active_connections,
+        HashSet::from([peer_id]),
+    );
+    foreign_controllers
+        .network_controller
+        .expect_get_active_connections()
+        .returning(move || Box::new(shared_active_connections.clone()));
+    message_receiver
+}
+
+#[test]
+fn test_protocol_serves_known_operation_ids_by_page() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_operations_per_message: 2,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operations: Vec<SecureShareOperation> = (1..=3)
+        .map(|expire_period| ProtocolTestUniverse::create_operation(&block_creator, expire_period))
+        .collect();
+    let mut pool_ids: Vec<OperationId> = operations.iter().map(|op| op.id).collect();
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    foreign_controllers
+        .pool_controller
+        .set_expectations(|pool_controller| {
+            pool_controller
+                .expect_get_operation_ids()
+                .return_const(pool_ids.clone());
+        });
+    let messages = forward_sent_messages(&mut foreign_controllers, node_a_peer_id);
+    let mut universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+    universe.storage.store_operations(operations.clone());
+    pool_ids.sort_unstable();
+
+    // the fresh peer gets the ids of the pool in two pages
+    let mut known_ids = Vec::new();
+    for offset in [0, 2] {
+        universe.mock_message_receive(
+            &node_a_peer_id,
+            Message::Operation(OperationMessage::AskKnownIds(offset)),
+        );
+        match messages.recv_timeout(Duration::from_secs(5)) {
+            Ok(Message::Operation(OperationMessage::KnownIds(page))) => known_ids.extend(page),
+            other => panic!("unexpected answer to AskKnownIds: {:?}", other),
+        }
+    }
+    assert_eq!(known_ids, pool_ids);
+
+    // then fetches the operations it misses
+    let wanted: Vec<OperationId> = vec![known_ids[0], known_ids[2]];
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::AskForOperations(
+            wanted.iter().map(|id| id.into_prefix()).collect(),
+        )),
+    );
+    match messages.recv_timeout(Duration::from_secs(5)) {
+        Ok(Message::Operation(OperationMessage::Operations(sent))) => {
+            let mut sent_ids: Vec<OperationId> = sent.iter().map(|op| op.id).collect();
+            sent_ids.sort_unstable();
+            assert_eq!(sent_ids, wanted);
+        }
+        other => panic!("unexpected answer to AskForOperations: {:?}", other),
+    }
+}
+
+#[test]
+fn test_protocol_syncs_mempool_from_connected_peer() {
+    let protocol_config = ProtocolConfig {
+        thread_count: 2,
+        max_operations_per_message: 2,
+        mempool_sync_peers: 1,
+        ..Default::default()
+    };
+    let block_creator = KeyPair::generate(0).unwrap();
+    let operation_1 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let operation_2 = ProtocolTestUniverse::create_operation(&block_creator, 1);
+    let node_a_keypair = KeyPair::generate(0).unwrap();
+    let node_a_peer_id = PeerId::from_public_key(node_a_keypair.get_public_key());
+
+    let mut foreign_controllers = ProtocolForeignControllers::new_with_mocks();
+    ProtocolTestUniverse::peer_db_boilerplate(&mut foreign_controllers.peer_db.write());
+    let messages = forward_sent_messages(&mut foreign_controllers, node_a_peer_id);
+    let universe = ProtocolTestUniverse::new(foreign_controllers, protocol_config);
+
+    assert_message_to_node_matches(
+        &node_a_peer_id,
+        &messages,
+        |message| {
+            matches!(
+                message,
+                Message::Operation(OperationMessage::AskKnownIds(0))
+            )
+        },
+        Duration::from_secs(5),
+    );
+    // a full page: the missing operations and the next page are asked
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::KnownIds(vec![
+            operation_1.id,
+            operation_2.id,
+        ])),
+    );
+    assert_message_to_node_matches(
+        &node_a_peer_id,
+        &messages,
+        |message| {
+            matches!(
+                message,
+                Message::Operation(OperationMessage::AskKnownIds(2))
+            )
+        },
+        Duration::from_secs(5),
+    );
+    let asked = assert_message_to_node_matches(
+        &node_a_peer_id,
+        &messages,
+        |message| {
+            matches!(
+                message,
+                Message::Operation(OperationMessage::AskForOperations(_))
+            )
+        },
+        Duration::from_secs(5),
+    );
+    let Message::Operation(OperationMessage::AskForOperations(asked)) = asked else {
+        unreachable!()
+    };
+    assert_eq!(
+        asked,
+        vec![operation_1.id.into_prefix(), operation_2.id.into_prefix()]
+            .into_iter()
+            .collect()
+    );
+
+    // the last page ends the sync with this peer
+    universe.mock_message_receive(
+        &node_a_peer_id,
+        Message::Operation(OperationMessage::KnownIds(vec![])),
+    );
+    assert_no_message_to_node(&node_a_peer_id, &messages, Duration::from_millis(500));
+}
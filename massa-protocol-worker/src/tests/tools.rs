@@ -0,0 +1,48 @@
+//! Assertions on the messages sent to the simulated nodes,
+//! see `ProtocolTestUniverse::create_fake_connection_with_profile`
+
+use std::{
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use massa_protocol_exports::PeerId;
+
+use crate::messages::Message;
+
+/// Fail if a message reaches `node` within `timeout`
+pub fn assert_no_message_to_node(node: &PeerId, messages: &Receiver<Message>, timeout: Duration) {
+    if let Ok(message) = messages.recv_timeout(timeout) {
+        panic!("unexpected message sent to node {}: {:?}", node, message);
+    }
+}
+
+/// Wait for a message matching `predicate` to reach `node` within `timeout` and return it,
+/// the other messages received in the meantime are skipped
+pub fn assert_message_to_node_matches<F>(
+    node: &PeerId,
+    messages: &Receiver<Message>,
+    predicate: F,
+    timeout: Duration,
+) -> Message
+where
+    F: Fn(&Message) -> bool,
+{
+    let deadline = Instant::now() + timeout;
+    let mut skipped = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match messages.recv_timeout(remaining) {
+            Ok(message) if predicate(&message) => return message,
+            Ok(message) => skipped.push(message),
+            Err(RecvTimeoutError::Timeout) => panic!(
+                "no matching message sent to node {} within {:?}, it only received {:?}",
+                node, timeout, skipped
+            ),
+            Err(RecvTimeoutError::Disconnected) => panic!(
+                "the connection to node {} was closed before a matching message, it only received {:?}",
+                node, skipped
+            ),
+        }
+    }
+}
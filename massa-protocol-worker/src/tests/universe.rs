@@ -4,29 +4,39 @@ use massa_models::config::MIP_STORE_STATS_BLOCK_CONSIDERED;
 use massa_pool_exports::{MockPoolControllerWrapper, PoolController};
 use massa_pos_exports::{MockSelectorControllerWrapper, SelectorController};
 use massa_protocol_exports::{
-    PeerCategoryInfo, PeerConnectionType, PeerId, ProtocolConfig, ProtocolController,
+    BanReason, PeerCategoryInfo, PeerConnectionType, PeerId, ProtocolConfig, ProtocolController,
     ProtocolError, ProtocolManager,
 };
 use massa_serialization::U64VarIntDeserializer;
 use massa_signature::KeyPair;
 use massa_storage::Storage;
 use massa_test_framework::TestUniverse;
+use massa_time::MassaTime;
 use parking_lot::{RwLock, RwLockWriteGuard};
 use peernet::messages::{MessagesHandler as _, MessagesSerializer as _};
+use rand::{thread_rng, Rng};
 use std::{
     collections::{HashMap, HashSet},
     fs::read_to_string,
-    sync::Arc,
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
 };
 
 use crate::{
+    clock::{ManualClock, ProtocolClock},
     connectivity::start_connectivity_thread,
     create_protocol_controller,
     handlers::{
         block_handler::BlockMessageSerializer,
         endorsement_handler::EndorsementMessageSerializer,
         operation_handler::OperationMessageSerializer,
-        peer_handler::{models::SharedPeerDB, PeerManagementMessageSerializer},
+        peer_handler::{
+            corruption_limiter::CorruptedFramesLimiter,
+            models::{PeerInfo, PeerState, SharedPeerDB},
+            rate_limiter::MessageRateLimiter,
+            size_limiter::MessageSizeLimiter,
+            PeerManagementMessageSerializer,
+        },
     },
     manager::ProtocolManagerImpl,
     messages::{Message, MessagesHandler, MessagesSerializer},
@@ -46,14 +56,51 @@ pub struct ProtocolTestUniverse {
     message_serializer: MessagesSerializer,
     pub storage: Storage,
     pub peer_db: SharedPeerDB,
+    /// time of the protocol timers, only moved by the test
+    pub clock: ManualClock,
+    recorded_peers: RecordedPeers,
+}
+
+/// Quality of a simulated link to a peer, see `create_fake_connection_with_profile`
+#[derive(Clone, Copy, Debug)]
+pub struct LinkProfile {
+    /// time taken by the messages to reach the peer
+    pub latency: Duration,
+    /// fraction of the messages lost on the way, from 0 to 1
+    pub loss_rate: f64,
 }
 
+/// Peers connected in the mocked network, see `connected_peers_boilerplate`
+#[derive(Clone, Default)]
+pub struct ConnectedPeers(Arc<RwLock<HashSet<PeerId>>>);
+
+impl ConnectedPeers {
+    pub fn new(peer_ids: impl IntoIterator<Item = PeerId>) -> Self {
+        ConnectedPeers(Arc::new(RwLock::new(peer_ids.into_iter().collect())))
+    }
+
+    /// Simulate a new connection to `peer_id`, e.g. after a successful dial
+    pub fn connect(&self, peer_id: PeerId) {
+        self.0.write().insert(peer_id);
+    }
+
+    /// Simulate an abrupt close of the connection to `peer_id`, without any disconnect message:
+    /// the protocol only notices that the peer isn't connected anymore
+    pub fn drop_connection(&self, peer_id: &PeerId) {
+        self.0.write().remove(peer_id);
+    }
+}
+
+/// Peers whose state was changed through the mocked peer db, see `peer_state_boilerplate`
+pub type RecordedPeers = Arc<RwLock<HashMap<PeerId, PeerInfo>>>;
+
 pub struct ProtocolForeignControllers {
     pub consensus_controller: Box<MockConsensusController>,
     pub pool_controller: Box<MockPoolControllerWrapper>,
     pub selector_controller: Box<MockSelectorControllerWrapper>,
     pub network_controller: Box<MockNetworkController>,
     pub peer_db: Arc<RwLock<MockPeerDBTrait>>,
+    pub recorded_peers: RecordedPeers,
 }
 
 impl ProtocolForeignControllers {
@@ -64,6 +111,7 @@ impl ProtocolForeignControllers {
             selector_controller: Box::new(MockSelectorControllerWrapper::new()),
             network_controller: Box::new(MockNetworkController::new()),
             peer_db: Arc::new(RwLock::new(MockPeerDBTrait::new())),
+            recorded_peers: RecordedPeers::default(),
         }
     }
 }
@@ -74,6 +122,7 @@ impl TestUniverse for ProtocolTestUniverse {
 
     fn new(controllers: Self::ForeignControllers, config: Self::Config) -> Self {
         let storage = Storage::create_root();
+        let clock = ManualClock::new(MassaTime::now());
         let (messages_handler, protocol_controller, protocol_manager) =
             start_protocol_controller_with_mock_network(
                 config,
@@ -83,6 +132,7 @@ impl TestUniverse for ProtocolTestUniverse {
                 controllers.network_controller,
                 storage.clone(),
                 controllers.peer_db.clone(),
+                ProtocolClock::Manual(clock.clone()),
             )
             .unwrap();
         let universe = Self {
@@ -96,6 +146,8 @@ impl TestUniverse for ProtocolTestUniverse {
                 .with_peer_management_message_serializer(PeerManagementMessageSerializer::new()),
             storage,
             module_manager: protocol_manager,
+            clock,
+            recorded_peers: controllers.recorded_peers,
         };
         universe.initialize();
         universe
@@ -129,6 +181,183 @@ impl ProtocolTestUniverse {
         mock_peer_db
             .expect_get_rand_peers_to_send()
             .return_const(vec![]);
+        mock_peer_db
+            .expect_get_weighted_peers_to_send()
+            .return_const(vec![]);
+        mock_peer_db
+            .expect_unban_expired_peers()
+            .return_const(vec![]);
+        mock_peer_db.expect_tick_unban().return_const(vec![]);
+        mock_peer_db.expect_save_ban_list().return_const(());
+        mock_peer_db.expect_adjust_reputation().return_const(false);
+        mock_peer_db.expect_quarantine_peer().return_const(true);
+        mock_peer_db.expect_get_ban_details().return_const(None);
+        mock_peer_db.expect_update_ban_settings().return_const(());
+        mock_peer_db
+            .expect_get_quarantined_peers()
+            .return_const(HashSet::default());
+    }
+
+    /// Record the state changes made through the mocked peer db, for `assert_peer_state`.
+    /// Must be called before `peer_db_boilerplate`, the known peers returned by the mock stay empty.
+    pub fn peer_state_boilerplate(
+        mock_peer_db: &mut RwLockWriteGuard<MockPeerDBTrait>,
+        recorded_peers: &RecordedPeers,
+    ) {
+        fn record(
+            recorded_peers: &RecordedPeers,
+            peer_id: &PeerId,
+            state: PeerState,
+            ban_reason: Option<(BanReason, MassaTime)>,
+        ) {
+            let mut recorded_peers = recorded_peers.write();
+            let peer = recorded_peers.entry(*peer_id).or_insert(PeerInfo {
+                last_announce: None,
+                state: state.clone(),
+                ban_reason: None,
+                reputation: 0,
+                features: None,
+            });
+            peer.state = state;
+            peer.ban_reason = ban_reason;
+        }
+
+        let peers = recorded_peers.clone();
+        mock_peer_db
+            .expect_ban_peer()
+            .returning(move |peer_id, reason, now| {
+                record(&peers, peer_id, PeerState::Banned, Some((reason, now)));
+            });
+        let peers = recorded_peers.clone();
+        mock_peer_db
+            .expect_ban_peer_with_severity()
+            .returning(move |peer_id, reason, _, now| {
+                record(&peers, peer_id, PeerState::Banned, Some((reason, now)));
+            });
+        let peers = recorded_peers.clone();
+        mock_peer_db.expect_unban_peer().returning(move |peer_id| {
+            record(&peers, peer_id, PeerState::HandshakeFailed, None);
+        });
+        let peers = recorded_peers.clone();
+        mock_peer_db
+            .expect_quarantine_peer()
+            .returning(move |peer_id, _| {
+                record(&peers, peer_id, PeerState::Quarantined, None);
+                true
+            });
+        let peers = recorded_peers.clone();
+        mock_peer_db
+            .expect_set_peer_state()
+            .returning(move |peer_id, state| {
+                record(&peers, peer_id, state, None);
+                true
+            });
+        mock_peer_db
+            .expect_get_peers()
+            .return_const(HashMap::default());
+    }
+
+    /// Last state recorded for a peer by `peer_state_boilerplate`
+    pub fn peer_info(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        self.recorded_peers.read().get(peer_id).cloned()
+    }
+
+    /// Fail if the recorded state of a peer isn't `state`
+    pub fn assert_peer_state(&self, peer_id: &PeerId, state: PeerState) {
+        let current = self.peer_info(peer_id).map(|peer| peer.state);
+        assert_eq!(
+            current,
+            Some(state.clone()),
+            "peer {} is in state {:?} instead of {:?}",
+            peer_id,
+            current,
+            state
+        );
+    }
+
+    /// Fail if the recorded state of a peer doesn't become `state` within `timeout`
+    pub fn wait_until_peer_state(&self, peer_id: &PeerId, state: PeerState, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let current = self.peer_info(peer_id).map(|peer| peer.state);
+            if current.as_ref() == Some(&state) {
+                return;
+            }
+            if Instant::now() >= deadline {
+                panic!(
+                    "peer {} didn't reach state {:?} within {:?}, it is in state {:?}",
+                    peer_id, state, timeout, current
+                );
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Simulate the link to `peer_id`: the messages sent to the peer are randomly lost
+    /// or delivered after the latency of the link to the returned receiver,
+    /// for the test to answer them as the peer would
+    pub fn create_fake_connection_with_profile(
+        mock_active_connections: &mut MockActiveConnectionsTraitWrapper,
+        peer_id: PeerId,
+        profile: LinkProfile,
+    ) -> mpsc::Receiver<Message> {
+        let (delivered_sender, delivered_receiver) = mpsc::channel();
+        mock_active_connections.set_expectations(|mock_active_connections| {
+            mock_active_connections
+                .expect_send_to_peer()
+                .withf(move |to_peer_id, _, _, _| *to_peer_id == peer_id)
+                .returning(move |_, _, message, _| {
+                    if thread_rng().gen_bool(profile.loss_rate) {
+                        return Ok(());
+                    }
+                    let delivered_sender = delivered_sender.clone();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(profile.latency);
+                        let _ = delivered_sender.send(message);
+                    });
+                    Ok(())
+                });
+        });
+        delivered_receiver
+    }
+
+    /// Same as `active_connections_boilerplate`, with connections that can be dropped during the test
+    pub fn connected_peers_boilerplate(
+        mock_active_connections: &mut MockActiveConnectionsTraitWrapper,
+        connected_peers: &ConnectedPeers,
+    ) {
+        let peer_ids = connected_peers.clone();
+        let peers = connected_peers.clone();
+        mock_active_connections.set_expectations(|mock_active_connections| {
+            mock_active_connections
+                .expect_get_peer_ids_connected()
+                .returning(move || peer_ids.0.read().clone());
+            mock_active_connections
+                .expect_shutdown_connection()
+                .returning(move |_| ());
+            mock_active_connections
+                .expect_get_peer_ids_out_connection_queue()
+                .returning(HashSet::new);
+            mock_active_connections
+                .expect_get_peers_connected()
+                .returning(move || {
+                    peers
+                        .0
+                        .read()
+                        .iter()
+                        .map(|peer_id| {
+                            (
+                                *peer_id,
+                                (
+                                    "127.0.0.1:8080".parse().unwrap(),
+                                    PeerConnectionType::OUT,
+                                    None,
+                                ),
+                            )
+                        })
+                        .collect()
+                });
+        });
     }
 
     pub fn active_connections_boilerplate(
@@ -173,6 +402,7 @@ pub fn start_protocol_controller_with_mock_network(
     network_controller: Box<dyn NetworkController>,
     storage: Storage,
     peer_db: SharedPeerDB,
+    clock: ProtocolClock,
 ) -> Result<
     (
         MessagesHandler,
@@ -215,6 +445,9 @@ pub fn start_protocol_controller_with_mock_network(
         Some(config.max_size_channel_network_to_peer_handler),
     );
 
+    let (controller, mut channels) = create_protocol_controller(config.clone());
+    channels.clock = clock;
+
     // Register channels for handlers
     let message_handlers: MessagesHandler = MessagesHandler {
         sender_blocks: sender_blocks.clone(),
@@ -222,10 +455,24 @@ pub fn start_protocol_controller_with_mock_network(
         sender_operations: sender_operations.clone(),
         sender_peers: sender_peers.clone(),
         id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+        rate_limiter: Some(MessageRateLimiter::new(
+            &config,
+            channels.peer_management_handler.0.clone(),
+        )),
+        size_limiter: Some(MessageSizeLimiter::new(
+            &config,
+            channels.peer_management_handler.0.clone(),
+        )),
+        corrupted_frames_limiter: Some(CorruptedFramesLimiter::new(
+            &config,
+            channels.peer_management_handler.0.clone(),
+        )),
+        max_message_size: config.max_message_size,
+        peer_traffic: Some(channels.peer_traffic.clone()),
+        message_counters: Some(channels.protocol_metrics.read().message_counters.clone()),
+        inbound_queues: None,
     };
 
-    let (controller, channels) = create_protocol_controller(config.clone());
-
     let mip_stats_config = MipStatsConfig {
         block_count_considered: MIP_STORE_STATS_BLOCK_CONSIDERED,
         warn_announced_version_ratio: Ratio::new_raw(30, 100),
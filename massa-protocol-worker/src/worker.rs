@@ -5,8 +5,8 @@ use massa_models::node::NodeId;
 use massa_pool_exports::PoolController;
 use massa_pos_exports::SelectorController;
 use massa_protocol_exports::{
-    BootstrapPeers, PeerData, PeerId, ProtocolConfig, ProtocolController, ProtocolError,
-    ProtocolManager,
+    BootstrapPeers, PeerData, PeerEventBroadcast, PeerId, ProtocolConfig, ProtocolController,
+    ProtocolError, ProtocolManager, ProtocolMetrics, TransportType,
 };
 use massa_serialization::U64VarIntDeserializer;
 use massa_signature::KeyPair;
@@ -22,17 +22,24 @@ use peernet::{
     config::{PeerNetCategoryInfo, PeerNetConfiguration},
     network_manager::PeerNetManager,
 };
-use std::{collections::HashMap, fs::read_to_string, ops::Bound::Included, sync::Arc};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::read_to_string,
+    ops::Bound::Included,
+    sync::Arc,
+};
 use tracing::{debug, log::warn};
 
 use crate::{
+    bandwidth_limiter::BandwidthLimiter,
+    clock::ProtocolClock,
     connectivity::{start_connectivity_thread, ConnectivityCommand},
     context::Context,
     controller::ProtocolControllerImpl,
     handlers::{
         block_handler::{
             commands_propagation::BlockHandlerPropagationCommand,
-            commands_retrieval::BlockHandlerRetrievalCommand,
+            commands_retrieval::BlockHandlerRetrievalCommand, SharedProtocolMetrics,
         },
         endorsement_handler::{
             commands_propagation::EndorsementHandlerPropagationCommand,
@@ -43,14 +50,18 @@ use crate::{
             commands_retrieval::OperationHandlerRetrievalCommand,
         },
         peer_handler::{
-            models::{PeerDB, PeerManagementCmd},
+            corruption_limiter::CorruptedFramesLimiter,
+            inbound_queues::InboundQueues,
+            models::{PeerDB, PeerManagementCmd, SharedPeerDB, SharedPeerTraffic},
+            rate_limiter::MessageRateLimiter,
+            size_limiter::MessageSizeLimiter,
             MassaHandshake,
         },
     },
     ip::to_canonical,
     manager::ProtocolManagerImpl,
     messages::MessagesHandler,
-    wrap_network::NetworkControllerImpl,
+    wrap_network::{NetworkController, NetworkControllerImpl},
 };
 
 pub struct ProtocolChannels {
@@ -86,6 +97,63 @@ pub struct ProtocolChannels {
         MassaSender<PeerManagementCmd>,
         MassaReceiver<PeerManagementCmd>,
     ),
+    pub peer_events: PeerEventBroadcast,
+    pub peer_db: SharedPeerDB,
+    pub protocol_metrics: SharedProtocolMetrics,
+    pub peer_traffic: SharedPeerTraffic,
+    /// time followed by the protocol timers
+    pub clock: ProtocolClock,
+}
+
+/// Merge the peers of the initial peers files and the inline ones, the listeners of a peer found
+/// several times are merged too. The missing files are skipped with a warning, the loading fails
+/// only if none of them can be read.
+pub(crate) fn load_initial_peers(
+    config: &ProtocolConfig,
+) -> Result<HashMap<PeerId, PeerData>, ProtocolError> {
+    let mut initial_peers: HashMap<PeerId, PeerData> = HashMap::new();
+    let mut read_error = None;
+    let mut read_files = 0;
+    for path in &config.initial_peers {
+        let content = match read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                warn!(
+                    "could not read initial peers file {}: {}",
+                    path.display(),
+                    err
+                );
+                read_error = Some(err);
+                continue;
+            }
+        };
+        read_files += 1;
+        for (peer_id, data) in serde_json::from_str::<HashMap<PeerId, PeerData>>(&content)? {
+            match initial_peers.entry(peer_id) {
+                Entry::Occupied(mut entry) => entry.get_mut().listeners.extend(data.listeners),
+                Entry::Vacant(entry) => {
+                    entry.insert(data);
+                }
+            }
+        }
+    }
+    if read_files == 0 {
+        if let Some(err) = read_error {
+            return Err(err.into());
+        }
+    }
+    for (peer_id, addr) in &config.inline_initial_peers {
+        initial_peers
+            .entry(*peer_id)
+            .or_insert_with(|| PeerData {
+                listeners: HashMap::new(),
+                // in no category, the peer falls in the default one
+                category: String::new(),
+            })
+            .listeners
+            .insert(*addr, TransportType::Tcp);
+    }
+    Ok(initial_peers)
 }
 
 /// This function exists because consensus need the protocol controller and we need consensus controller.
@@ -131,6 +199,13 @@ pub fn create_protocol_controller(
         "peer_management_ext".to_string(),
         Some(config.max_size_channel_commands_peers),
     );
+    let peer_events = PeerEventBroadcast::new(config.peer_events_channel_capacity);
+    let clock = ProtocolClock::default();
+    let peer_db: SharedPeerDB = Arc::new(RwLock::new(PeerDB::new(&config, clock.now())));
+    let protocol_metrics: SharedProtocolMetrics = Arc::new(RwLock::new(ProtocolMetrics::new(
+        config.block_download_latency_buckets.clone(),
+    )));
+    let peer_traffic = SharedPeerTraffic::default();
     (
         Box::new(ProtocolControllerImpl::new(
             sender_blocks_retrieval_ext.clone(),
@@ -139,6 +214,11 @@ pub fn create_protocol_controller(
             sender_endorsements_propagation_ext.clone(),
             sender_connectivity_ext.clone(),
             sender_peer_management_ext.clone(),
+            peer_events.clone(),
+            peer_db.clone(),
+            protocol_metrics.clone(),
+            peer_traffic.clone(),
+            config.max_wishlist_size,
         )),
         ProtocolChannels {
             operation_handler_retrieval: (
@@ -164,6 +244,11 @@ pub fn create_protocol_controller(
             ),
             connectivity_thread: (sender_connectivity_ext, receiver_connectivity_ext),
             peer_management_handler: (sender_peer_management_ext, receiver_peer_management_ext),
+            peer_events,
+            peer_db,
+            protocol_metrics,
+            peer_traffic,
+            clock,
         },
     )
 }
@@ -188,7 +273,8 @@ pub fn start_protocol_controller(
     massa_metrics: MassaMetrics,
 ) -> Result<(Box<dyn ProtocolManager>, KeyPair, NodeId), ProtocolError> {
     debug!("starting protocol controller");
-    let peer_db = Arc::new(RwLock::new(PeerDB::default()));
+    config.validate()?;
+    let peer_db = protocol_channels.peer_db.clone();
 
     let (sender_operations, receiver_operations) = MassaChannel::new(
         "sender_operations".to_string(),
@@ -207,6 +293,11 @@ pub fn start_protocol_controller(
         Some(config.max_size_channel_network_to_peer_handler),
     );
 
+    let message_counters = protocol_channels
+        .protocol_metrics
+        .read()
+        .message_counters
+        .clone();
     // Register channels for handlers
     let message_handlers: MessagesHandler = MessagesHandler {
         sender_blocks: sender_blocks.clone(),
@@ -214,6 +305,28 @@ pub fn start_protocol_controller(
         sender_operations: sender_operations.clone(),
         sender_peers: sender_peers.clone(),
         id_deserializer: U64VarIntDeserializer::new(Included(0), Included(u64::MAX)),
+        rate_limiter: Some(MessageRateLimiter::new(
+            &config,
+            protocol_channels.peer_management_handler.0.clone(),
+        )),
+        size_limiter: Some(MessageSizeLimiter::new(
+            &config,
+            protocol_channels.peer_management_handler.0.clone(),
+        )),
+        corrupted_frames_limiter: Some(CorruptedFramesLimiter::new(
+            &config,
+            protocol_channels.peer_management_handler.0.clone(),
+        )),
+        max_message_size: config.max_message_size,
+        peer_traffic: Some(protocol_channels.peer_traffic.clone()),
+        inbound_queues: (config.per_peer_inbound_queue_depth > 0).then(|| {
+            InboundQueues::new(
+                config.per_peer_inbound_queue_depth,
+                config.message_timeout.to_duration(),
+                Some(message_counters.clone()),
+            )
+        }),
+        message_counters: Some(message_counters.clone()),
     };
 
     // try to read node keypair from file, otherwise generate it & write to file. Then derive nodeId
@@ -237,8 +350,14 @@ pub fn start_protocol_controller(
         keypair
     };
 
+    let mut handshake = MassaHandshake::new(peer_db.clone(), config.clone());
+    handshake.clock = protocol_channels.clock.clone();
+    let compression_peers = handshake.compression_peers.clone();
+    let checksum_peers = handshake.checksum_peers.clone();
+    let format_versions = handshake.format_versions.clone();
+    let handshake_active_connections = handshake.active_connections.clone();
     let mut peernet_config = PeerNetConfiguration::default(
-        MassaHandshake::new(peer_db.clone(), config.clone()),
+        handshake,
         message_handlers.clone(),
         Context {
             our_keypair: keypair.clone(),
@@ -250,9 +369,7 @@ pub fn start_protocol_controller(
     peernet_config.rate_limit = config.rate_limit;
     peernet_config.rate_bucket_size = config.rate_limit.saturating_mul(2);
 
-    let initial_peers_infos = serde_json::from_str::<HashMap<PeerId, PeerData>>(
-        &std::fs::read_to_string(&config.initial_peers)?,
-    )?;
+    let initial_peers_infos = load_initial_peers(&config)?;
 
     let initial_peers = if let Some(bootstrap_peers) = bootstrap_peers {
         //TODO: Remove when we will be able to test the bootstrap peer even if someone else found them full
@@ -312,11 +429,40 @@ pub fn start_protocol_controller(
         max_in_connections_per_ip: config.default_category_info.max_in_connections_per_ip,
         max_out_connections: config.default_category_info.target_out_connections,
     };
+    // the outbound-only peers are dialed on top of the targets, make room for them in their category
+    for (_, addr) in &config.outbound_only_peers {
+        let ip = to_canonical(addr.ip());
+        match peernet_config
+            .peers_categories
+            .values_mut()
+            .find(|(ips, _)| ips.contains(&ip))
+        {
+            Some((_, infos)) => infos.max_out_connections += 1,
+            None => peernet_config.default_category_info.max_out_connections += 1,
+        }
+    }
     peernet_config.max_in_connections = config.max_in_connections;
 
-    let network_controller = Box::new(NetworkControllerImpl::new(PeerNetManager::new(
-        peernet_config,
-    )));
+    let network_controller = Box::new(NetworkControllerImpl::new(
+        PeerNetManager::new(peernet_config),
+        config.compression,
+        compression_peers,
+        checksum_peers,
+        format_versions,
+        protocol_channels.peer_traffic.clone(),
+        message_counters,
+        config
+            .per_peer_bandwidth_limit
+            .filter(|limit| *limit > 0)
+            .map(|limit| {
+                BandwidthLimiter::new(
+                    limit,
+                    config.per_peer_bandwidth_burst,
+                    protocol_channels.protocol_metrics.clone(),
+                )
+            }),
+    ));
+    *handshake_active_connections.write() = Some(network_controller.get_active_connections());
 
     let connectivity_thread_handle = start_connectivity_thread(
         PeerId::from_public_key(keypair.get_public_key()),
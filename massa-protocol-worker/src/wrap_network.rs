@@ -1,23 +1,33 @@
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
+    sync::Arc,
 };
 
-use massa_protocol_exports::{PeerId, ProtocolError};
+use massa_protocol_exports::{CompressionMode, MessageCounters, PeerId, ProtocolError};
 use peernet::{
+    error::PeerNetResult,
+    messages::MessagesSerializer as PeerNetMessagesSerializer,
     network_manager::{PeerNetManager, SharedActiveConnections},
     peer::PeerConnectionType,
     transports::TransportType,
 };
+use tracing::debug;
 
 use crate::{
+    bandwidth_limiter::{BandwidthLimiter, PacedSender, MAX_WAITING_MESSAGES_PER_PEER},
     context::Context,
-    handlers::peer_handler::MassaHandshake,
+    handlers::peer_handler::{
+        models::{
+            SharedChecksumPeers, SharedCompressionPeers, SharedFormatVersions, SharedPeerTraffic,
+        },
+        MassaHandshake,
+    },
     messages::{Message, MessagesHandler, MessagesSerializer},
 };
 
 #[cfg(test)]
-use std::sync::{Arc, RwLock};
+use std::sync::RwLock;
 
 #[cfg_attr(test, mockall_wrap::wrap, mockall::automock)]
 pub trait ActiveConnectionsTrait: Send + Sync {
@@ -46,7 +56,50 @@ impl Clone for Box<dyn ActiveConnectionsTrait> {
     }
 }
 
-impl ActiveConnectionsTrait for SharedActiveConnections<PeerId> {
+/// Active connections of the peernet manager, compressing, checksumming and tagging with their format
+/// version the messages sent to the peers with which it was negotiated in the handshake, and pacing the messages sent
+/// to each peer under the bandwidth limit
+#[derive(Clone)]
+pub struct PeerNetActiveConnections {
+    connections: SharedActiveConnections<PeerId>,
+    compression: CompressionMode,
+    compression_peers: SharedCompressionPeers,
+    checksum_peers: SharedChecksumPeers,
+    format_versions: SharedFormatVersions,
+    bandwidth_limiter: Option<(BandwidthLimiter, PacedSender)>,
+    peer_traffic: SharedPeerTraffic,
+    message_counters: Arc<MessageCounters>,
+}
+
+/// Passes through the messages already serialized
+#[derive(Clone)]
+struct SerializedMessageSerializer;
+
+impl PeerNetMessagesSerializer<Vec<u8>> for SerializedMessageSerializer {
+    fn serialize(&self, message: &Vec<u8>, buffer: &mut Vec<u8>) -> PeerNetResult<()> {
+        buffer.extend_from_slice(message);
+        Ok(())
+    }
+}
+
+/// Send a message already serialized to `peer_id`
+fn send_serialized(
+    connections: &SharedActiveConnections<PeerId>,
+    peer_id: &PeerId,
+    data: Vec<u8>,
+    high_priority: bool,
+) -> Result<(), ProtocolError> {
+    if let Some(connection) = connections.read().connections.get(peer_id) {
+        connection
+            .send_channels
+            .try_send(&SerializedMessageSerializer, data, high_priority)
+            .map_err(|err| ProtocolError::SendError(err.to_string()))
+    } else {
+        Err(ProtocolError::PeerDisconnected(peer_id.to_string()))
+    }
+}
+
+impl ActiveConnectionsTrait for PeerNetActiveConnections {
     fn send_to_peer(
         &self,
         peer_id: &PeerId,
@@ -54,10 +107,58 @@ impl ActiveConnectionsTrait for SharedActiveConnections<PeerId> {
         message: Message,
         high_priority: bool,
     ) -> Result<(), ProtocolError> {
-        if let Some(connection) = self.read().connections.get(peer_id) {
+        let compressed_serializer;
+        let message_serializer = match self.compression {
+            CompressionMode::Zstd { level, min_size }
+                if self.compression_peers.read().contains(peer_id) =>
+            {
+                compressed_serializer =
+                    message_serializer.clone().with_compression(level, min_size);
+                &compressed_serializer
+            }
+            _ => message_serializer,
+        };
+        let checksummed_serializer;
+        let message_serializer = if self.checksum_peers.read().contains(peer_id) {
+            checksummed_serializer = message_serializer.clone().with_checksum();
+            &checksummed_serializer
+        } else {
+            message_serializer
+        };
+        let versioned_serializer;
+        let message_serializer = match self.format_versions.read().get(peer_id) {
+            Some(format_version) => {
+                versioned_serializer = message_serializer
+                    .clone()
+                    .with_format_version(*format_version);
+                &versioned_serializer
+            }
+            None => message_serializer,
+        };
+        let message_serializer = message_serializer
+            .clone()
+            .with_peer_traffic(*peer_id, self.peer_traffic.clone())
+            .with_message_counters(self.message_counters.clone());
+        if let Some((bandwidth_limiter, paced_sender)) = &self.bandwidth_limiter {
+            if !self.connections.read().connections.contains_key(peer_id) {
+                return Err(ProtocolError::PeerDisconnected(peer_id.to_string()));
+            }
+            // the size on the wire is only known once serialized, the serialized bytes are then sent as is
+            let mut data = Vec::new();
+            message_serializer
+                .serialize(&message, &mut data)
+                .map_err(|err| ProtocolError::SendError(err.to_string()))?;
+            let delay = bandwidth_limiter.reserve(peer_id, data.len());
+            // the delayed messages are sent later by the paced sender
+            return match paced_sender.delay(peer_id, data, high_priority, delay)? {
+                Some(data) => send_serialized(&self.connections, peer_id, data, high_priority),
+                None => Ok(()),
+            };
+        }
+        if let Some(connection) = self.connections.read().connections.get(peer_id) {
             connection
                 .send_channels
-                .try_send(message_serializer, message, high_priority)
+                .try_send(&message_serializer, message, high_priority)
                 .map_err(|err| ProtocolError::SendError(err.to_string()))
         } else {
             Err(ProtocolError::PeerDisconnected(peer_id.to_string()))
@@ -69,13 +170,19 @@ impl ActiveConnectionsTrait for SharedActiveConnections<PeerId> {
     }
 
     fn get_peer_ids_connected(&self) -> HashSet<PeerId> {
-        self.read().connections.keys().cloned().collect()
+        self.connections
+            .read()
+            .connections
+            .keys()
+            .cloned()
+            .collect()
     }
 
     fn get_peers_connected(
         &self,
     ) -> HashMap<PeerId, (SocketAddr, PeerConnectionType, Option<String>)> {
-        self.read()
+        self.connections
+            .read()
             .connections
             .iter()
             .map(|(peer_id, connection)| {
@@ -92,29 +199,33 @@ impl ActiveConnectionsTrait for SharedActiveConnections<PeerId> {
     }
 
     fn get_nb_out_connections(&self) -> usize {
-        self.read().nb_out_connections
+        self.connections.read().nb_out_connections
     }
 
     fn get_nb_in_connections(&self) -> usize {
-        self.read().nb_in_connections
+        self.connections.read().nb_in_connections
     }
 
     fn shutdown_connection(&mut self, peer_id: &PeerId) {
-        if let Some(connection) = self.write().connections.get_mut(peer_id) {
+        if let Some(connection) = self.connections.write().connections.get_mut(peer_id) {
             connection.shutdown();
         }
+        if let Some((bandwidth_limiter, paced_sender)) = &self.bandwidth_limiter {
+            bandwidth_limiter.remove_peer(peer_id);
+            paced_sender.remove_peer(peer_id);
+        }
     }
 
     fn get_peers_connections_bandwidth(&self) -> HashMap<String, (u64, u64)> {
         let mut map = HashMap::new();
-        for (peerid, conn) in self.read().connections.iter() {
+        for (peerid, conn) in self.connections.read().connections.iter() {
             map.insert(peerid.to_string(), conn.endpoint.get_bandwidth());
         }
         map
     }
 
     fn get_peer_ids_out_connection_queue(&self) -> HashSet<SocketAddr> {
-        self.read().out_connection_queue.clone()
+        self.connections.read().out_connection_queue.clone()
     }
 }
 
@@ -142,19 +253,66 @@ pub trait NetworkController: Send + Sync {
 
 pub struct NetworkControllerImpl {
     peernet_manager: PeerNetManager<PeerId, Context, MassaHandshake, MessagesHandler>,
+    compression: CompressionMode,
+    compression_peers: SharedCompressionPeers,
+    checksum_peers: SharedChecksumPeers,
+    format_versions: SharedFormatVersions,
+    bandwidth_limiter: Option<(BandwidthLimiter, PacedSender)>,
+    peer_traffic: SharedPeerTraffic,
+    message_counters: Arc<MessageCounters>,
 }
 
 impl NetworkControllerImpl {
     pub fn new(
         peernet_manager: PeerNetManager<PeerId, Context, MassaHandshake, MessagesHandler>,
+        compression: CompressionMode,
+        compression_peers: SharedCompressionPeers,
+        checksum_peers: SharedChecksumPeers,
+        format_versions: SharedFormatVersions,
+        peer_traffic: SharedPeerTraffic,
+        message_counters: Arc<MessageCounters>,
+        bandwidth_limiter: Option<BandwidthLimiter>,
     ) -> Self {
-        Self { peernet_manager }
+        let bandwidth_limiter = bandwidth_limiter.map(|bandwidth_limiter| {
+            let connections = peernet_manager.active_connections.clone();
+            let paced_sender = PacedSender::new(
+                MAX_WAITING_MESSAGES_PER_PEER,
+                move |peer_id, data, high_priority| {
+                    if let Err(err) = send_serialized(&connections, peer_id, data, high_priority) {
+                        debug!(
+                            "Failed to send delayed message to peer {}: {}",
+                            peer_id, err
+                        );
+                    }
+                },
+            );
+            (bandwidth_limiter, paced_sender)
+        });
+        Self {
+            peernet_manager,
+            compression,
+            compression_peers,
+            checksum_peers,
+            format_versions,
+            bandwidth_limiter,
+            peer_traffic,
+            message_counters,
+        }
     }
 }
 
 impl NetworkController for NetworkControllerImpl {
     fn get_active_connections(&self) -> Box<dyn ActiveConnectionsTrait> {
-        Box::new(self.peernet_manager.active_connections.clone())
+        Box::new(PeerNetActiveConnections {
+            connections: self.peernet_manager.active_connections.clone(),
+            compression: self.compression,
+            compression_peers: self.compression_peers.clone(),
+            checksum_peers: self.checksum_peers.clone(),
+            format_versions: self.format_versions.clone(),
+            bandwidth_limiter: self.bandwidth_limiter.clone(),
+            peer_traffic: self.peer_traffic.clone(),
+            message_counters: self.message_counters.clone(),
+        })
     }
 
     fn start_listener(
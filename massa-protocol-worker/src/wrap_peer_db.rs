@@ -1,16 +1,62 @@
-use crate::handlers::peer_handler::models::{ConnectionMetadata, PeerInfo};
+use crate::handlers::peer_handler::{
+    announcement::Announcement,
+    models::{
+        BanSeverity, ConnectionMetadata, NegotiatedFeatures, PeerInfo, PeerState, WeightingPolicy,
+    },
+};
+use ipnet::IpNet;
 use std::{
     collections::{HashMap, HashSet},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     time::Duration,
 };
 
-use massa_protocol_exports::{PeerId, TransportType};
+use massa_protocol_exports::{BanReason, BannedPeerInfo, PeerId, PeerStateCounts, TransportType};
 
 #[cfg_attr(test, mockall::automock)]
 pub trait PeerDBTrait: Send + Sync {
-    fn ban_peer(&mut self, peer_id: &PeerId);
+    /// Ban a peer at `now`
+    fn ban_peer(&mut self, peer_id: &PeerId, reason: BanReason, now: massa_time::MassaTime);
+    /// Ban a peer at `now` for a duration escalating with its offenses
+    fn ban_peer_with_severity(
+        &mut self,
+        peer_id: &PeerId,
+        reason: BanReason,
+        severity: BanSeverity,
+        now: massa_time::MassaTime,
+    );
     fn unban_peer(&mut self, peer_id: &PeerId);
+    /// Unban the escalated bans and quarantines that are over at `now`, returns the unbanned peers
+    fn unban_expired_peers(&mut self, now: massa_time::MassaTime) -> Vec<PeerId>;
+    /// Restore the peers that stayed banned for the duration of their ban reason, returns the unbanned peers
+    fn tick_unban(&mut self, now: massa_time::MassaTime) -> Vec<PeerId>;
+    fn save_ban_list(&self);
+    /// Apply the ban settings of `config`, changed while the node runs
+    fn update_ban_settings(&mut self, config: &massa_protocol_exports::ProtocolConfig);
+    /// Change the reputation of a peer, returns true if it dropped below the ban threshold
+    fn adjust_reputation(&mut self, peer_id: &PeerId, delta: i32) -> bool;
+    /// Put a peer in quarantine at `now`, returns false if it was already quarantined and must be banned
+    fn quarantine_peer(&mut self, peer_id: &PeerId, now: massa_time::MassaTime) -> bool;
+    /// Record a first offense of a peer at `now`, returns false if it already offended within the first offense window and must be banned
+    fn mark_first_offense(&mut self, peer_id: &PeerId, now: massa_time::MassaTime) -> bool;
+    /// Forget the peers and addresses we didn't hear of for `peer_expiry` at `now`, except the
+    /// `connected` peers and the banned or quarantined ones. Returns the forgotten peers
+    fn prune_expired_peers(
+        &mut self,
+        now: massa_time::MassaTime,
+        connected: &HashSet<PeerId>,
+    ) -> Vec<PeerId>;
+    fn get_quarantined_peers(&self) -> HashSet<PeerId>;
+    /// Ban an IP address at `now`
+    fn ban_ip(&mut self, ip: IpAddr, now: massa_time::MassaTime);
+    /// Ban a subnet at `now`
+    fn ban_subnet(&mut self, subnet: IpNet, now: massa_time::MassaTime);
+    fn is_ip_banned(&self, ip: &IpAddr) -> bool;
+    /// Offense count of a banned peer and end of its ban, `None` if the peer isn't banned
+    fn get_ban_details(&self, peer_id: &PeerId) -> Option<(u32, Option<massa_time::MassaTime>)>;
+    fn get_ban_reason(&self, peer_id: &PeerId) -> Option<(BanReason, massa_time::MassaTime)>;
+    /// Get the peers banned at `now` with the remaining time of their ban
+    fn get_banned_peers(&self, now: massa_time::MassaTime) -> Vec<BannedPeerInfo>;
     fn clone_box(&self) -> Box<dyn PeerDBTrait>;
     fn get_oldest_peer(
         &self,
@@ -21,15 +67,49 @@ pub trait PeerDBTrait: Send + Sync {
         &self,
         nb_peers: usize,
     ) -> Vec<(PeerId, HashMap<SocketAddr, TransportType>)>;
+    /// Same as `get_rand_peers_to_send`, favoring the peers with a high reputation and a recent announcement
+    fn get_weighted_peers_to_send(
+        &self,
+        nb_peers: usize,
+        policy: WeightingPolicy,
+    ) -> Vec<(PeerId, HashMap<SocketAddr, TransportType>)>;
     fn get_banned_peer_count(&self) -> u64;
     fn get_known_peer_count(&self) -> u64;
     fn get_peers(&self) -> &HashMap<PeerId, PeerInfo>;
-    fn get_peers_mut(&mut self) -> &mut HashMap<PeerId, PeerInfo>;
+    /// Last announcement received from a peer, `None` if the peer is unknown or didn't announce itself
+    fn get_peer_announce(&self, peer_id: &PeerId) -> Option<Announcement>;
+    /// What was negotiated during the last successful handshake with a peer, `None` if the peer is unknown or never completed one
+    fn get_peer_features(&self, peer_id: &PeerId) -> Option<NegotiatedFeatures>;
+    /// Last announcement received from each peer that announced itself
+    fn get_all_announces(&self) -> HashMap<PeerId, Announcement>;
+    /// Record the last announcement of a known peer, returns false if the peer is unknown
+    fn set_peer_announcement(&mut self, peer_id: &PeerId, announcement: Announcement) -> bool;
+    /// Record what was negotiated during the last handshake with a known peer, returns false if the peer is unknown
+    fn set_peer_features(&mut self, peer_id: &PeerId, features: Option<NegotiatedFeatures>)
+        -> bool;
+    /// Change the state of a known peer, returns false if the peer is unknown
+    fn set_peer_state(&mut self, peer_id: &PeerId, state: PeerState) -> bool;
+    /// Add a peer or replace a known one
+    fn insert_peer(&mut self, peer_id: PeerId, peer: PeerInfo);
+    /// Number of known peers in each state
+    fn get_peer_state_counts(&self) -> PeerStateCounts;
     fn get_connection_metadata_or_default(&self, addr: &SocketAddr) -> ConnectionMetadata;
-    fn set_try_connect_success_or_insert(&mut self, addr: &SocketAddr);
-    fn set_try_connect_failure_or_insert(&mut self, addr: &SocketAddr);
-    fn set_try_connect_test_success_or_insert(&mut self, addr: &SocketAddr);
-    fn set_try_connect_test_failure_or_insert(&mut self, addr: &SocketAddr);
+    /// Record a connection attempt to an address at `now`
+    fn set_try_connect_success_or_insert(&mut self, addr: &SocketAddr, now: massa_time::MassaTime);
+    /// Record a connection to an address that failed at `now`, backing off the next attempts
+    fn set_try_connect_failure_or_insert(&mut self, addr: &SocketAddr, now: massa_time::MassaTime);
+    /// Record a successful handshake with an address at `now`, ending its connection backoff
+    fn set_handshake_success_or_insert(&mut self, addr: &SocketAddr, now: massa_time::MassaTime);
+    fn set_try_connect_test_success_or_insert(
+        &mut self,
+        addr: &SocketAddr,
+        now: massa_time::MassaTime,
+    );
+    fn set_try_connect_test_failure_or_insert(
+        &mut self,
+        addr: &SocketAddr,
+        now: massa_time::MassaTime,
+    );
     fn insert_peer_in_test(&mut self, addr: &SocketAddr) -> bool;
     fn remove_peer_in_test(&mut self, addr: &SocketAddr) -> bool;
     fn get_peers_in_test(&self) -> &HashSet<SocketAddr>;
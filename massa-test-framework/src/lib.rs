@@ -1,4 +1,8 @@
-use std::sync::{Arc, Condvar, Mutex};
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
 
 use massa_hash::Hash;
 use massa_models::{
@@ -103,13 +107,36 @@ pub trait TestUniverse {
     }
 }
 
+/// Generous time to wait for a `WaitPoint` in the tests before considering that it never triggers
+pub const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct WaitPoint(Arc<WaitPointInner>);
 
 struct WaitPointInner {
+    name: String,
     mutex: Mutex<bool>,
     condvar: Condvar,
 }
 
+/// A `WaitPoint` wasn't triggered in time
+#[derive(Debug)]
+pub struct WaitTimeoutError {
+    pub name: String,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for WaitTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wait point `{}` wasn't triggered within {:?}",
+            self.name, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for WaitTimeoutError {}
+
 impl Default for WaitPoint {
     fn default() -> Self {
         Self::new()
@@ -118,7 +145,13 @@ impl Default for WaitPoint {
 
 impl WaitPoint {
     pub fn new() -> Self {
+        Self::named("unnamed")
+    }
+
+    /// Wait point whose name is given in the timeout errors
+    pub fn named(name: &str) -> Self {
         Self(Arc::new(WaitPointInner {
+            name: name.to_string(),
             mutex: Mutex::new(false),
             condvar: Condvar::new(),
         }))
@@ -136,6 +169,24 @@ impl WaitPoint {
         }
     }
 
+    /// Same as `wait`, but gives up after `timeout`
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), WaitTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut started = self.0.mutex.lock().unwrap();
+        *started = false;
+        while !*started {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(WaitTimeoutError {
+                    name: self.0.name.clone(),
+                    timeout,
+                });
+            }
+            started = self.0.condvar.wait_timeout(started, remaining).unwrap().0;
+        }
+        Ok(())
+    }
+
     pub fn trigger(&self) {
         let mut started = self.0.mutex.lock().unwrap();
         *started = true;